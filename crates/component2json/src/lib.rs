@@ -192,6 +192,170 @@ pub fn json_to_vals(value: &Value, types: &[(String, Type)]) -> Result<Vec<Val>,
     }
 }
 
+/// A place where a JSON value doesn't match a schema generated by this crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// JSON pointer to the value that doesn't match, `""` for the value itself
+    pub path: String,
+    /// What is wrong with the value
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Checks `value` against `schema`, such as the `inputSchema` of a tool, and returns every place
+/// where it doesn't match. Understands the keywords of the schemas generated from WIT types:
+/// `type`, `properties`, `required`, `items`, `prefixItems`, `minItems`, `maxItems`, `enum`,
+/// `const`, `oneOf` and `anyOf`. Properties a schema doesn't list are allowed, and a value matches
+/// a `oneOf` if it matches any of its schemas.
+pub fn validate_json(value: &Value, schema: &Value) -> Vec<SchemaViolation> {
+    let mut violations = Vec::new();
+    validate_at(value, schema, "", &mut violations);
+    violations
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str, violations: &mut Vec<SchemaViolation>) {
+    let mut violation = |message: String| {
+        violations.push(SchemaViolation {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    if let Some(cases) = schema
+        .get("oneOf")
+        .or_else(|| schema.get("anyOf"))
+        .and_then(Value::as_array)
+    {
+        if cases
+            .iter()
+            .any(|case| validate_json(value, case).is_empty())
+        {
+            return;
+        }
+        // A variant case with the value's tag explains best what is wrong with the value
+        let tagged = value.get("tag").and_then(|tag| {
+            cases
+                .iter()
+                .find(|case| case.pointer("/properties/tag/const") == Some(tag))
+        });
+        match tagged {
+            Some(case) => validate_at(value, case, path, violations),
+            None => violation(format!(
+                "does not match any of the {} allowed shapes",
+                cases.len()
+            )),
+        }
+        return;
+    }
+
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if json_type_name(value) != expected {
+            violation(format!(
+                "expected {expected}, found {}",
+                json_type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            violation(format!("expected {expected}, found {value}"));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            let allowed = allowed
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            violation(format!("{value} is not one of {allowed}"));
+            return;
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !fields.contains_key(name) {
+                    violation(format!("missing required property '{name}'"));
+                }
+            }
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (name, field) in fields {
+                    if let Some(field_schema) = properties.get(name) {
+                        let path = format!("{path}/{}", name.replace('~', "~0").replace('/', "~1"));
+                        validate_at(field, field_schema, &path, violations);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            let count = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+            if let Some(min) = count("minItems").filter(|&min| (items.len() as u64) < min) {
+                violation(format!(
+                    "expected at least {min} items, found {}",
+                    items.len()
+                ));
+            }
+            if let Some(max) = count("maxItems").filter(|&max| (items.len() as u64) > max) {
+                violation(format!(
+                    "expected at most {max} items, found {}",
+                    items.len()
+                ));
+            }
+            let prefix = schema
+                .get("prefixItems")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for (i, item) in items.iter().enumerate() {
+                let item_schema = match prefix.get(i) {
+                    Some(item_schema) => item_schema,
+                    None => match schema.get("items") {
+                        // An array of schemas is the older form of `prefixItems`
+                        Some(Value::Array(schemas)) => match schemas.get(i) {
+                            Some(item_schema) => item_schema,
+                            None => continue,
+                        },
+                        Some(item_schema) => item_schema,
+                        None => continue,
+                    },
+                };
+                validate_at(item, item_schema, &format!("{path}/{i}"), violations);
+            }
+        }
+        _ => {}
+    }
+}
+
 /// Prepares a placeholder `Vec<Val>` to receive the results of a component function call.
 /// The vector will have the correct length and correctly-typed (but empty/zeroed) values.
 pub fn create_placeholder_results(results: &[Type]) -> Vec<Val> {
@@ -1651,6 +1815,79 @@ mod tests {
         assert_eq!(original_list, roundtrip_list);
     }
 
+    #[test]
+    fn test_validate_json() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "method": { "type": "string", "enum": ["get", "post"] },
+                "headers": {
+                    "type": "array",
+                    "items": {
+                        "type": "array",
+                        "prefixItems": [{ "type": "string" }, { "type": "string" }],
+                        "minItems": 2,
+                        "maxItems": 2
+                    }
+                },
+                "timeout": { "anyOf": [{ "type": "null" }, { "type": "number" }] },
+                "body": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "properties": { "tag": { "const": "text" }, "val": { "type": "string" } },
+                            "required": ["tag", "val"]
+                        },
+                        {
+                            "type": "object",
+                            "properties": { "tag": { "const": "empty" } },
+                            "required": ["tag"]
+                        }
+                    ]
+                }
+            },
+            "required": ["url", "method"]
+        });
+
+        let valid = json!({
+            "url": "https://example.com",
+            "method": "get",
+            "headers": [["accept", "text/html"]],
+            "timeout": null,
+            "body": { "tag": "text", "val": "hello" },
+            "max_tokens": 100
+        });
+        assert_eq!(validate_json(&valid, &schema), vec![]);
+
+        let invalid = json!({
+            "method": "put",
+            "headers": [["accept"], ["x-count", 1]],
+            "timeout": "soon",
+            "body": { "tag": "text", "val": 1 }
+        });
+        let messages = validate_json(&invalid, &schema)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "missing required property 'url'",
+                "/body/val: expected string, found number",
+                "/headers/0: expected at least 2 items, found 1",
+                "/headers/1/1: expected string, found number",
+                "/method: \"put\" is not one of \"get\", \"post\"",
+                "/timeout: does not match any of the 2 allowed shapes",
+            ]
+        );
+
+        assert_eq!(
+            validate_json(&json!([]), &schema)[0].message,
+            "expected object, found array"
+        );
+    }
+
     #[test]
     fn test_tool_name_validation() {
         // Valid tool names
@@ -0,0 +1,256 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Several tool calls in one request
+//!
+//! An agent that needs the results of N independent calls, such as N fetches, pays a round trip
+//! through the model for each of them. The `call-tools-batch` tool takes the calls as a list of
+//! `{tool, arguments}` entries, runs up to `max_parallel` of them at a time through the usual
+//! tool call pipeline and returns the result or error of every entry in the order of the list.
+
+use anyhow::{anyhow, bail, Result};
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use rmcp::model::{CallToolRequestParam, CallToolResult, Content};
+use rmcp::{Peer, RoleServer};
+use serde_json::{json, Map, Value};
+use tracing::info;
+use wassette::LifecycleManager;
+
+use crate::components::extract_args_from_request;
+use crate::errors::ToolError;
+use crate::tools::{execute_tool_call, ToolCallOptions};
+
+/// Name of the built-in tool that runs a batch of tool calls
+pub const BATCH_TOOL: &str = "call-tools-batch";
+
+/// Most entries a batch may have
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// Calls of a batch that run at the same time unless `max_parallel` says otherwise
+pub const DEFAULT_BATCH_PARALLELISM: usize = 4;
+
+/// Most calls of a batch that may run at the same time
+pub const MAX_BATCH_PARALLELISM: usize = 16;
+
+/// The calls of a `call-tools-batch` request, in order. Fails if the batch is empty, too large,
+/// has an entry without a tool, or calls `call-tools-batch` itself.
+pub fn batch_entries(req: &CallToolRequestParam) -> Result<Vec<CallToolRequestParam>> {
+    let args = extract_args_from_request(req)?;
+    let calls = args
+        .get("calls")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("Missing required argument: 'calls'"))?;
+    if calls.is_empty() {
+        bail!("'calls' must have at least one entry");
+    }
+    if calls.len() > MAX_BATCH_SIZE {
+        bail!(
+            "A batch may have at most {MAX_BATCH_SIZE} calls, got {}",
+            calls.len()
+        );
+    }
+    calls
+        .iter()
+        .enumerate()
+        .map(|(index, call)| {
+            let tool = call
+                .get("tool")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("Call {index} has no 'tool'"))?;
+            if tool == BATCH_TOOL {
+                bail!("Call {index} can't be another {BATCH_TOOL}");
+            }
+            let arguments = match call.get("arguments") {
+                None | Some(Value::Null) => None,
+                Some(Value::Object(arguments)) => Some(arguments.clone()),
+                Some(_) => bail!("'arguments' of call {index} must be an object"),
+            };
+            Ok(CallToolRequestParam {
+                name: tool.to_string().into(),
+                arguments,
+            })
+        })
+        .collect()
+}
+
+/// Runs the calls of a `call-tools-batch` request, `max_parallel` at a time. The batch succeeds
+/// even if some of its calls fail; each entry of the result has either the `result` or the
+/// `error` of its call.
+///
+/// The batch is itself a tool call, so its future is boxed to make tool calls of its own.
+pub(crate) fn handle_call_tools_batch<'a>(
+    req: &'a CallToolRequestParam,
+    lifecycle_manager: &'a LifecycleManager,
+    server_peer: Option<&'a Peer<RoleServer>>,
+    options: &'a ToolCallOptions,
+) -> BoxFuture<'a, Result<CallToolResult>> {
+    Box::pin(async move {
+        let entries = batch_entries(req)?;
+        let max_parallel = match extract_args_from_request(req)?.get("max_parallel") {
+            None | Some(Value::Null) => DEFAULT_BATCH_PARALLELISM,
+            Some(value) => value
+                .as_u64()
+                .filter(|n| (1..=MAX_BATCH_PARALLELISM as u64).contains(n))
+                .ok_or_else(|| {
+                    anyhow!("'max_parallel' must be an integer from 1 to {MAX_BATCH_PARALLELISM}")
+                })? as usize,
+        };
+        info!(
+            calls = entries.len(),
+            max_parallel, "Running tool call batch"
+        );
+
+        let results = stream::iter(entries)
+            .map(|entry| async move {
+                let tool = entry.name.to_string();
+                let result =
+                    execute_tool_call(entry, lifecycle_manager, server_peer.cloned(), options)
+                        .await;
+                batch_entry(tool, result)
+            })
+            .buffered(max_parallel)
+            .collect::<Vec<_>>()
+            .await;
+
+        let failed = results
+            .iter()
+            .filter(|result| result.contains_key("error"))
+            .count();
+        let status_text = serde_json::to_string(&json!({
+            "succeeded": results.len() - failed,
+            "failed": failed,
+            "results": results,
+        }))?;
+
+        Ok(CallToolResult {
+            content: Some(vec![Content::text(status_text)]),
+            structured_content: None,
+            is_error: None,
+        })
+    })
+}
+
+/// The entry of the batch result for a call of `tool` that returned `result`
+fn batch_entry(tool: String, result: Result<Value>) -> Map<String, Value> {
+    let mut entry = Map::new();
+    entry.insert("tool".to_string(), Value::String(tool));
+    match result {
+        Ok(value) => {
+            let text = value["content"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|content| content["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if value["isError"].as_bool() == Some(true) {
+                match value["structuredContent"]["error"].as_object() {
+                    Some(error) => insert_error(&mut entry, error),
+                    None => {
+                        let text = text.strip_prefix("Error: ").unwrap_or(&text);
+                        entry.insert("error".to_string(), Value::String(text.to_string()));
+                    }
+                }
+            } else {
+                // Results that are JSON are nested as JSON rather than as a string of it
+                let result = serde_json::from_str(&text).unwrap_or(Value::String(text));
+                entry.insert("result".to_string(), result);
+            }
+        }
+        Err(e) => {
+            if let Value::Object(error) = ToolError::from_error(&e).to_value() {
+                insert_error(&mut entry, &error);
+            }
+        }
+    }
+    entry
+}
+
+/// Adds the [`ToolError`] of a failed call to its entry: the message as `error`, next to the
+/// code, hint and remediation
+fn insert_error(entry: &mut Map<String, Value>, error: &Map<String, Value>) {
+    for (key, value) in error {
+        match key.as_str() {
+            "message" => entry.insert("error".to_string(), value.clone()),
+            "details" => None,
+            key => entry.insert(key.to_string(), value.clone()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::RateLimiter;
+
+    fn batch(calls: Value) -> CallToolRequestParam {
+        CallToolRequestParam {
+            name: BATCH_TOOL.into(),
+            arguments: json!({ "calls": calls }).as_object().cloned(),
+        }
+    }
+
+    #[test]
+    fn test_batch_entries() {
+        let entries = batch_entries(&batch(json!([
+            {"tool": "list-components"},
+            {"tool": "get-policy", "arguments": {"component_id": "fetch-rs"}},
+        ])))
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "list-components");
+        assert_eq!(entries[0].arguments, None);
+        assert_eq!(
+            entries[1].arguments.as_ref().unwrap()["component_id"],
+            "fetch-rs"
+        );
+
+        assert!(batch_entries(&batch(json!([]))).is_err());
+        assert!(batch_entries(&batch(json!([{"arguments": {}}]))).is_err());
+        assert!(batch_entries(&batch(json!([{"tool": "get-policy", "arguments": 1}]))).is_err());
+        assert!(batch_entries(&batch(json!([{"tool": BATCH_TOOL}]))).is_err());
+        let too_many = vec![json!({"tool": "list-components"}); MAX_BATCH_SIZE + 1];
+        assert!(batch_entries(&batch(Value::Array(too_many))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_tools_batch() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions {
+            rate_limits: RateLimiter::new(["list-components=1/1h".parse()?]),
+            ..Default::default()
+        };
+
+        let result = execute_tool_call(
+            batch(json!([
+                {"tool": "list-components"},
+                {"tool": "get-policy", "arguments": {"component_id": "missing"}},
+                {"tool": "list-components"},
+            ])),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        let text = result["content"][0]["text"].as_str().unwrap();
+        let report: Value = serde_json::from_str(text)?;
+        assert_eq!(report["succeeded"], 1);
+        assert_eq!(report["failed"], 2);
+        let results = report["results"].as_array().unwrap();
+        assert_eq!(results[0]["tool"], "list-components");
+        assert_eq!(results[0]["result"]["total"], 0);
+        assert_eq!(results[1]["tool"], "get-policy");
+        assert!(results[1]["error"].is_string());
+        assert_eq!(results[1]["code"], "component_not_found");
+        // Every call of the batch counts against the rate limits of its tool
+        assert!(results[2]["error"]
+            .as_str()
+            .unwrap()
+            .contains("list-components"));
+        assert_eq!(results[2]["code"], "resource_exhausted");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Argument completion for `completion/complete`
+//!
+//! MCP references prompts and resources in completion requests. Besides the arguments of the
+//! usage prompts and the component resource templates, a `ref/prompt` request may name a tool,
+//! and then completes the arguments of that tool: component IDs for the built-in tools, and the
+//! values of arguments whose schema is an `enum` or a boolean.
+
+use anyhow::Result;
+use rmcp::model::{CompleteRequestParam, CompleteResult, CompletionInfo, Reference};
+use serde_json::Value;
+use wassette::LifecycleManager;
+
+use crate::components::get_component_tools;
+use crate::prompts::USAGE_PROMPT_PREFIX;
+use crate::resources::{COMPONENT_URI_PREFIX, COMPONENT_URI_TEMPLATE_ARGUMENT};
+use crate::secrets::get_secret_tools;
+use crate::tools::get_builtin_tools;
+
+/// Most values a completion returns, as the MCP specification allows
+const MAX_COMPLETIONS: usize = 100;
+
+/// Built-in tools whose `id` argument names a component in the trash instead of a loaded one
+const TRASHED_ID_TOOLS: [&str; 2] = ["restore-component", "purge-component"];
+
+/// Completes the argument of a prompt, resource template or tool that the client asks for
+pub async fn handle_completion(
+    req: CompleteRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<Value> {
+    let argument = req.argument.name.as_str();
+    let values = match &req.r#ref {
+        Reference::Resource(resource) => {
+            if resource.uri.starts_with(COMPONENT_URI_PREFIX)
+                && argument == COMPONENT_URI_TEMPLATE_ARGUMENT
+            {
+                lifecycle_manager.list_components().await
+            } else {
+                Vec::new()
+            }
+        }
+        // The `task` of a usage prompt is free text
+        Reference::Prompt(prompt) if prompt.name.starts_with(USAGE_PROMPT_PREFIX) => Vec::new(),
+        Reference::Prompt(tool) => {
+            tool_argument_values(&tool.name, argument, lifecycle_manager).await?
+        }
+    };
+    Ok(serde_json::to_value(CompleteResult {
+        completion: completion_info(values, &req.argument.value),
+    })?)
+}
+
+/// The values `argument` of `tool` can take, before they are matched with what the user typed
+async fn tool_argument_values(
+    tool: &str,
+    argument: &str,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<Vec<String>> {
+    if argument == "id" && TRASHED_ID_TOOLS.contains(&tool) {
+        return Ok(lifecycle_manager
+            .list_trashed_components()
+            .await?
+            .into_iter()
+            .map(|trashed| trashed.component_id)
+            .collect());
+    }
+    let mut builtin = get_builtin_tools();
+    builtin.extend(get_secret_tools());
+    let names_component =
+        argument == "component_id" || (argument == "id" && tool == "unload-component");
+    if names_component && builtin.iter().any(|builtin| builtin.name == tool) {
+        return Ok(lifecycle_manager.list_components().await);
+    }
+
+    let schema = match builtin.into_iter().find(|builtin| builtin.name == tool) {
+        Some(builtin) => Value::Object(builtin.input_schema.as_ref().clone()),
+        None => get_component_tools(lifecycle_manager)
+            .await?
+            .into_iter()
+            .find(|component_tool| component_tool.name == tool)
+            .map(|component_tool| Value::Object(component_tool.input_schema.as_ref().clone()))
+            .unwrap_or_default(),
+    };
+    let property = &schema["properties"][argument];
+    Ok(match (property.get("enum"), property.get("type")) {
+        (Some(Value::Array(values)), _) => values
+            .iter()
+            .map(|value| match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+            .collect(),
+        (None, Some(Value::String(kind))) if kind == "boolean" => {
+            vec!["false".to_string(), "true".to_string()]
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// The `values` that start with `prefix`, sorted and limited to what a completion may return
+pub fn completion_info(values: Vec<String>, prefix: &str) -> CompletionInfo {
+    let mut values = values
+        .into_iter()
+        .filter(|value| value.starts_with(prefix))
+        .collect::<Vec<_>>();
+    values.sort();
+    values.dedup();
+    let total = values.len();
+    values.truncate(MAX_COMPLETIONS);
+    CompletionInfo {
+        has_more: Some(total > values.len()),
+        total: u32::try_from(total).ok(),
+        values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rmcp::model::{ArgumentInfo, PromptReference, ResourceReference};
+
+    use super::*;
+
+    #[test]
+    fn test_completion_info() {
+        let info = completion_info(
+            vec![
+                "fetch".into(),
+                "filesystem".into(),
+                "eval".into(),
+                "fetch".into(),
+            ],
+            "f",
+        );
+        assert_eq!(info.values, vec!["fetch", "filesystem"]);
+        assert_eq!(info.total, Some(2));
+        assert_eq!(info.has_more, Some(false));
+
+        let info = completion_info((0..150).map(|i| format!("c{i:03}")).collect(), "");
+        assert_eq!(info.values.len(), MAX_COMPLETIONS);
+        assert_eq!(info.total, Some(150));
+        assert_eq!(info.has_more, Some(true));
+    }
+
+    async fn complete(
+        reference: Reference,
+        name: &str,
+        value: &str,
+        manager: &LifecycleManager,
+    ) -> Vec<String> {
+        let req = CompleteRequestParam {
+            r#ref: reference,
+            argument: ArgumentInfo {
+                name: name.to_string(),
+                value: value.to_string(),
+            },
+        };
+        let result: CompleteResult =
+            serde_json::from_value(handle_completion(req, manager).await.unwrap()).unwrap();
+        result.completion.values
+    }
+
+    fn tool(name: &str) -> Reference {
+        Reference::Prompt(PromptReference {
+            name: name.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_handle_completion() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        for id in ["fetch", "filesystem"] {
+            std::fs::write(
+                tempdir.path().join(format!("{id}.wasm")),
+                r#"(component
+                  (core module $m (func (export "run") (result i32) i32.const 0))
+                  (core instance $i (instantiate $m))
+                  (func $run (result u32) (canon lift (core func $i "run")))
+                  (export "run" (func $run)))"#,
+            )?;
+        }
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        assert_eq!(
+            complete(tool("get-policy"), "component_id", "fi", &manager).await,
+            vec!["filesystem"]
+        );
+        assert_eq!(
+            complete(tool("unload-component"), "id", "", &manager).await,
+            vec!["fetch", "filesystem"]
+        );
+        assert_eq!(
+            complete(tool("search-history"), "kind", "", &manager).await,
+            vec!["doc", "log", "result"]
+        );
+        assert_eq!(
+            complete(tool("grant-root-access"), "confirm", "t", &manager).await,
+            vec!["true"]
+        );
+        assert!(complete(tool("use-fetch"), "task", "", &manager)
+            .await
+            .is_empty());
+
+        let resource = Reference::Resource(ResourceReference {
+            uri: format!("{COMPONENT_URI_PREFIX}{{component_id}}/wit"),
+        });
+        assert_eq!(
+            complete(resource, "component_id", "fe", &manager).await,
+            vec!["fetch"]
+        );
+
+        manager.unload_component("fetch").await?;
+        assert_eq!(
+            complete(tool("restore-component"), "id", "", &manager).await,
+            vec!["fetch"]
+        );
+        Ok(())
+    }
+}
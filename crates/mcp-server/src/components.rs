@@ -10,7 +10,13 @@ use rmcp::model::{CallToolRequestParam, CallToolResult, Content, Tool};
 use rmcp::{Peer, RoleServer};
 use serde_json::{json, Value};
 use tracing::{debug, error, info, instrument};
-use wassette::LifecycleManager;
+use wassette::{CapturedOutput, HistoryQuery, LifecycleManager, RecallQuery};
+
+use crate::list_changes::ListChanges;
+use crate::sampling::ClientSampler;
+use crate::summarize::fit_to_budget;
+use crate::tokens::MAX_TOKENS_ARGUMENT;
+use crate::tools::ToolCallOptions;
 
 #[instrument(skip(lifecycle_manager))]
 pub(crate) async fn get_component_tools(lifecycle_manager: &LifecycleManager) -> Result<Vec<Tool>> {
@@ -27,7 +33,7 @@ pub(crate) async fn get_component_tools(lifecycle_manager: &LifecycleManager) ->
                 let tool_count = arr.len();
                 debug!(component_id = %id, tool_count, "Found tools in component");
                 for tool_json in arr {
-                    if let Some(tool) = parse_tool_schema(tool_json) {
+                    if let Some(tool) = parse_tool_schema(&with_max_tokens_argument(tool_json)) {
                         tools.push(tool);
                     }
                 }
@@ -43,6 +49,7 @@ pub(crate) async fn handle_load_component(
     req: &CallToolRequestParam,
     lifecycle_manager: &LifecycleManager,
     server_peer: Peer<RoleServer>,
+    list_changes: &ListChanges,
 ) -> Result<CallToolResult> {
     let args = extract_args_from_request(req)?;
     let path = args
@@ -54,7 +61,7 @@ pub(crate) async fn handle_load_component(
 
     match lifecycle_manager.load_component(path).await {
         Ok((id, _load_result)) => {
-            handle_tool_list_notification(Some(server_peer), &id, "load").await;
+            handle_tool_list_notification(Some((server_peer, list_changes)), &id, "load").await;
             create_component_success_result("load", &id)
         }
         Err(e) => {
@@ -73,6 +80,7 @@ pub(crate) async fn handle_unload_component(
     req: &CallToolRequestParam,
     lifecycle_manager: &LifecycleManager,
     server_peer: Peer<RoleServer>,
+    list_changes: &ListChanges,
 ) -> Result<CallToolResult> {
     let args = extract_args_from_request(req)?;
     let id = args
@@ -80,12 +88,19 @@ pub(crate) async fn handle_unload_component(
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing 'id' in arguments"))?;
 
-    info!(component_id = %id, "Unloading component");
+    let purge = args.get("purge").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    info!(component_id = %id, purge, "Unloading component");
 
-    match lifecycle_manager.unload_component(id).await {
+    let result = if purge {
+        lifecycle_manager.unload_and_purge_component(id).await
+    } else {
+        lifecycle_manager.unload_component(id).await
+    };
+    match result {
         Ok(()) => {
-            handle_tool_list_notification(Some(server_peer), id, "unload").await;
-            create_component_success_result("unload", id)
+            handle_tool_list_notification(Some((server_peer, list_changes)), id, "unload").await;
+            create_component_success_result(if purge { "purge" } else { "unload" }, id)
         }
         Err(e) => {
             error!(error = %e, "Failed to unload component");
@@ -95,11 +110,63 @@ pub(crate) async fn handle_unload_component(
 }
 
 #[instrument(skip(lifecycle_manager))]
-pub(crate) async fn handle_component_call(
+pub(crate) async fn handle_restore_component(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    server_peer: Peer<RoleServer>,
+    list_changes: &ListChanges,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let id = args
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing 'id' in arguments"))?;
+
+    info!(component_id = %id, "Restoring component");
+
+    match lifecycle_manager.restore_component(id).await {
+        Ok(()) => {
+            handle_tool_list_notification(Some((server_peer, list_changes)), id, "restore").await;
+            create_component_success_result("restore", id)
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to restore component");
+            Ok(create_component_error_result("restore", id, &e))
+        }
+    }
+}
+
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_purge_component(
     req: &CallToolRequestParam,
     lifecycle_manager: &LifecycleManager,
 ) -> Result<CallToolResult> {
     let args = extract_args_from_request(req)?;
+    let id = args
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing 'id' in arguments"))?;
+
+    info!(component_id = %id, "Purging component from trash");
+
+    match lifecycle_manager.purge_component(id).await {
+        Ok(()) => create_component_success_result("purge", id),
+        Err(e) => {
+            error!(error = %e, "Failed to purge component");
+            Ok(create_component_error_result("purge", id, &e))
+        }
+    }
+}
+
+#[instrument(skip(lifecycle_manager, options, server_peer))]
+pub(crate) async fn handle_component_call(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+    server_peer: Option<&Peer<RoleServer>>,
+) -> Result<CallToolResult> {
+    let tokens = &options.tokens;
+    let mut args = extract_args_from_request(req)?;
 
     let method_name = req.name.to_string();
     info!(function_name = %method_name, "Calling function");
@@ -108,17 +175,82 @@ pub(crate) async fn handle_component_call(
         .get_component_id_for_tool(&method_name)
         .await
         .map_err(|e| {
-            anyhow::anyhow!("Failed to find component for tool '{}': {}", method_name, e)
+            let message = format!("Failed to find component for tool '{method_name}': {e}");
+            e.context(message)
         })?;
 
-    let result = lifecycle_manager
-        .execute_component_call(&component_id, &method_name, &serde_json::to_string(&args)?)
-        .await;
+    // `max_tokens` is ours unless the tool itself takes an argument by that name
+    let max_tokens = if args.contains_key(MAX_TOKENS_ARGUMENT)
+        && !tool_declares_argument(
+            lifecycle_manager,
+            &component_id,
+            &method_name,
+            MAX_TOKENS_ARGUMENT,
+        )
+        .await
+    {
+        let value = args.remove(MAX_TOKENS_ARGUMENT).unwrap_or_default();
+        Some(value.as_u64().ok_or_else(|| {
+            anyhow::anyhow!("'{MAX_TOKENS_ARGUMENT}' must be a non-negative integer")
+        })? as usize)
+    } else {
+        None
+    };
+
+    let parameters = serde_json::to_string(&args)?;
+    // Components the policy lets sample ask the client that called the tool
+    let (result, output) = match server_peer {
+        Some(peer) => {
+            lifecycle_manager
+                .execute_component_call_with_sampler(
+                    &component_id,
+                    &method_name,
+                    &parameters,
+                    Arc::new(ClientSampler::new(peer.clone())),
+                )
+                .await
+        }
+        None => {
+            lifecycle_manager
+                .execute_component_call_with_output(&component_id, &method_name, &parameters)
+                .await
+        }
+    };
+    let output_text = output.as_ref().and_then(captured_output_text);
 
     match result {
         Ok(result_str) => {
             debug!("Component call successful");
-            let contents = vec![Content::text(result_str)];
+            let estimated_tokens = tokens.heuristic.estimate(&result_str);
+            // An explicit `max_tokens` wins over the budget configured for the tool
+            let mut contents = match (max_tokens, options.summarize.budget(&method_name)) {
+                (Some(max_tokens), _) => vec![Content::text(
+                    tokens
+                        .heuristic
+                        .truncate(&result_str, max_tokens)
+                        .unwrap_or(result_str),
+                )],
+                (None, Some(budget)) if estimated_tokens > budget => {
+                    fit_to_budget(
+                        &method_name,
+                        result_str,
+                        budget,
+                        &tokens.heuristic,
+                        &options.results,
+                        server_peer,
+                    )
+                    .await
+                }
+                (None, _) => vec![Content::text(result_str)],
+            };
+            if tokens.annotate {
+                contents.push(Content::text(format!(
+                    "[Estimated tokens: {estimated_tokens}]"
+                )));
+            }
+            if let Some(output_text) = output_text {
+                contents.push(Content::text(output_text));
+            }
 
             Ok(CallToolResult {
                 content: Some(contents),
@@ -128,9 +260,82 @@ pub(crate) async fn handle_component_call(
         }
         Err(e) => {
             error!(error = %e, "Component call failed");
-            Err(anyhow::anyhow!(e.to_string()))
+            // The error keeps its cause, such as a trap, for the error code of the call
+            match output_text {
+                Some(output_text) => {
+                    let message = format!("{e}\n\n{output_text}");
+                    Err(e.context(message))
+                }
+                None => Err(e),
+            }
+        }
+    }
+}
+
+/// Adds the optional `max_tokens` argument to a component tool's input schema, unless the tool
+/// already has an argument by that name
+fn with_max_tokens_argument(tool_json: &Value) -> Value {
+    let mut tool_json = tool_json.clone();
+    if let Some(properties) = tool_json
+        .pointer_mut("/inputSchema/properties")
+        .and_then(|properties| properties.as_object_mut())
+    {
+        properties.entry(MAX_TOKENS_ARGUMENT).or_insert_with(|| {
+            json!({
+                "type": "integer",
+                "minimum": 0,
+                "description": "Approximate maximum number of tokens to return. Longer results are cut at a paragraph boundary."
+            })
+        });
+    }
+    tool_json
+}
+
+/// The input schema of the tool `tool_name` of `component_id`
+pub(crate) async fn tool_input_schema(
+    lifecycle_manager: &LifecycleManager,
+    component_id: &str,
+    tool_name: &str,
+) -> Option<Value> {
+    let schema = lifecycle_manager.get_component_schema(component_id).await?;
+    schema
+        .get("tools")?
+        .as_array()?
+        .iter()
+        .find(|tool| tool.get("name").and_then(|name| name.as_str()) == Some(tool_name))?
+        .get("inputSchema")
+        .cloned()
+}
+
+/// Whether the input schema of `tool_name` has a property called `argument`
+async fn tool_declares_argument(
+    lifecycle_manager: &LifecycleManager,
+    component_id: &str,
+    tool_name: &str,
+    argument: &str,
+) -> bool {
+    tool_input_schema(lifecycle_manager, component_id, tool_name)
+        .await
+        .and_then(|schema| schema.get("properties")?.get(argument).cloned())
+        .is_some()
+}
+
+/// Formats the captured stdout and stderr of a call as a text block, or `None` if the component
+/// wrote nothing
+fn captured_output_text(output: &CapturedOutput) -> Option<String> {
+    if output.is_empty() {
+        return None;
+    }
+    let mut text = String::new();
+    for (name, stream) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        if !stream.is_empty() {
+            text.push_str(&format!("[{name}]\n{}\n", stream.trim_end()));
         }
     }
+    if output.truncated {
+        text.push_str("[output truncated]\n");
+    }
+    Some(text.trim_end().to_string())
 }
 
 #[instrument(skip(lifecycle_manager))]
@@ -182,6 +387,147 @@ pub async fn handle_list_components(
     })
 }
 
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_search_history(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let mut query = HistoryQuery::new(
+        args.get("query")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'query' in arguments"))?,
+    );
+    if let Some(limit) = args.get("limit").and_then(|v| v.as_u64()) {
+        query.limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    }
+    if let Some(kind) = args.get("kind").and_then(|v| v.as_str()) {
+        query.kind = Some(kind.parse()?);
+    }
+    query.component_id = args
+        .get("component_id")
+        .and_then(|v| v.as_str())
+        .map(String::from);
+    query.include_text = args
+        .get("include_text")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!(query = %query.query, "Searching history");
+
+    let hits = lifecycle_manager.search_history(query).await?;
+    let result_text = serde_json::to_string(&json!({
+        "hits": hits,
+        "total": hits.len()
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(result_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_get_network_activity(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let component_id = args.get("component_id").and_then(|v| v.as_str());
+    let limit = args
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .map(|limit| usize::try_from(limit).unwrap_or(usize::MAX))
+        .unwrap_or(50);
+
+    let requests = lifecycle_manager.network_activity(component_id, limit);
+    let result_text = serde_json::to_string(&json!({
+        "requests": requests,
+        "total": requests.len()
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(result_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_semantic_recall(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let mut query = RecallQuery::new(
+        args.get("query")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'query' in arguments"))?,
+    );
+    if let Some(limit) = args.get("limit").and_then(|v| v.as_u64()) {
+        query.limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    }
+    query.component_id = args
+        .get("component_id")
+        .and_then(|v| v.as_str())
+        .map(String::from);
+    query.include_text = args
+        .get("include_text")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    info!("Recalling similar tool results");
+
+    let memories = lifecycle_manager.semantic_recall(query).await?;
+    let result_text = serde_json::to_string(&json!({
+        "memories": memories,
+        "total": memories.len()
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(result_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+/// Most components `search-components` returns unless `limit` says otherwise
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// Most components `search-components` returns
+const MAX_SEARCH_LIMIT: usize = 50;
+
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_search_components(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let query = args
+        .get("query")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing 'query' in arguments"))?;
+    let limit = args
+        .get("limit")
+        .and_then(|v| v.as_u64())
+        .map_or(DEFAULT_SEARCH_LIMIT, |limit| {
+            usize::try_from(limit).unwrap_or(MAX_SEARCH_LIMIT)
+        })
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    info!("Searching component indexes");
+
+    let search = lifecycle_manager.search_components(query, limit).await?;
+    let result_text = serde_json::to_string(&search)?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(result_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
 pub(crate) fn extract_args_from_request(
     req: &CallToolRequestParam,
 ) -> Result<serde_json::Map<String, Value>> {
@@ -199,13 +545,22 @@ pub(crate) fn extract_args_from_request(
     }
 }
 
+/// Past tense of a component operation name, used in status messages
+fn operation_past_tense(operation_name: &str) -> String {
+    if operation_name.ends_with('e') {
+        format!("{operation_name}d")
+    } else {
+        format!("{operation_name}ed")
+    }
+}
+
 /// Create successful result for component operations
 fn create_component_success_result(
     operation_name: &str,
     component_id: &str,
 ) -> Result<CallToolResult> {
     let status_text = serde_json::to_string(&json!({
-        "status": format!("component {}ed successfully", operation_name),
+        "status": format!("component {} successfully", operation_past_tense(operation_name)),
         "id": component_id
     }))?;
 
@@ -244,21 +599,34 @@ fn create_component_error_result(
 
 /// Handle tool list change notification
 async fn handle_tool_list_notification(
-    server_peer: Option<Peer<RoleServer>>,
+    server_peer: Option<(Peer<RoleServer>, &ListChanges)>,
     component_id: &str,
     operation_name: &str,
 ) {
-    if let Some(peer) = server_peer {
-        if let Err(e) = peer.notify_tool_list_changed().await {
+    if let Some((peer, list_changes)) = server_peer {
+        // The notification follows the lifecycle event of the operation. Without a forwarder
+        // for the session, e.g. before the client is initialized, it's sent here instead.
+        if list_changes.flush().await {
+            info!(
+                component_id = %component_id,
+                operation = %operation_name,
+                "Sent tool list changed notification after component operation"
+            );
+        } else if let Err(e) = peer.notify_tool_list_changed().await {
             error!(error = %e, "Failed to send tool list change notification");
         } else {
             info!(
                 component_id = %component_id,
-                "Sent tool list changed notification after {}ing component", operation_name
+                operation = %operation_name,
+                "Sent tool list changed notification after component operation"
             );
         }
     } else {
-        info!(component_id = %component_id, "Component {}ed successfully in CLI mode", operation_name);
+        info!(
+            component_id = %component_id,
+            "Component {} successfully in CLI mode",
+            operation_past_tense(operation_name)
+        );
     }
 }
 
@@ -304,12 +672,19 @@ pub async fn handle_unload_component_cli(
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing 'id' in arguments"))?;
 
-    info!(component_id = %id, "Unloading component (CLI mode)");
+    let purge = args.get("purge").and_then(|v| v.as_bool()).unwrap_or(false);
 
-    match lifecycle_manager.unload_component(id).await {
+    info!(component_id = %id, purge, "Unloading component (CLI mode)");
+
+    let result = if purge {
+        lifecycle_manager.unload_and_purge_component(id).await
+    } else {
+        lifecycle_manager.unload_component(id).await
+    };
+    match result {
         Ok(()) => {
             handle_tool_list_notification(None, id, "unload").await;
-            create_component_success_result("unload", id)
+            create_component_success_result(if purge { "purge" } else { "unload" }, id)
         }
         Err(e) => {
             error!(error = %e, "Failed to unload component");
@@ -318,6 +693,32 @@ pub async fn handle_unload_component_cli(
     }
 }
 
+/// CLI-specific version of handle_restore_component that doesn't require server peer notifications
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_restore_component_cli(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let id = args
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing 'id' in arguments"))?;
+
+    info!(component_id = %id, "Restoring component (CLI mode)");
+
+    match lifecycle_manager.restore_component(id).await {
+        Ok(()) => {
+            handle_tool_list_notification(None, id, "restore").await;
+            create_component_success_result("restore", id)
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to restore component");
+            Ok(create_component_error_result("restore", id, &e))
+        }
+    }
+}
+
 #[instrument]
 pub(crate) fn parse_tool_schema(tool_json: &Value) -> Option<Tool> {
     let name = tool_json
@@ -390,6 +791,47 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_with_max_tokens_argument() {
+        let tool_json = json!({
+            "name": "fetch",
+            "inputSchema": {"type": "object", "properties": {"url": {"type": "string"}}}
+        });
+        let tool_json = with_max_tokens_argument(&tool_json);
+        assert_eq!(
+            tool_json["inputSchema"]["properties"][MAX_TOKENS_ARGUMENT]["type"],
+            "integer"
+        );
+        assert_eq!(
+            tool_json["inputSchema"]["properties"]["url"]["type"],
+            "string"
+        );
+
+        // A tool's own max_tokens argument is left alone
+        let tool_json = json!({
+            "name": "complete",
+            "inputSchema": {"type": "object", "properties": {"max_tokens": {"type": "string"}}}
+        });
+        assert_eq!(
+            with_max_tokens_argument(&tool_json)["inputSchema"]["properties"]["max_tokens"]["type"],
+            "string"
+        );
+    }
+
+    #[test]
+    fn test_captured_output_text() {
+        assert_eq!(captured_output_text(&CapturedOutput::default()), None);
+        assert_eq!(
+            captured_output_text(&CapturedOutput {
+                stdout: "fetched 3 items\n".to_string(),
+                stderr: "retrying\n".to_string(),
+                truncated: true,
+            })
+            .as_deref(),
+            Some("[stdout]\nfetched 3 items\n[stderr]\nretrying\n[output truncated]")
+        );
+    }
+
     #[test]
     fn test_parse_tool_schema() {
         let tool_json = json!({
@@ -613,4 +1055,42 @@ mod tests {
         });
         assert_eq!(input_schema_json, expected_input);
     }
+
+    #[test]
+    fn test_component_success_result_status() {
+        for (operation, expected) in [
+            ("load", "component loaded successfully"),
+            ("unload", "component unloaded successfully"),
+            ("restore", "component restored successfully"),
+            ("purge", "component purged successfully"),
+        ] {
+            let result = create_component_success_result(operation, "test-id").unwrap();
+            let text = match &result.content.unwrap()[0].raw {
+                rmcp::model::RawContent::Text(text) => text.text.clone(),
+                _ => panic!("expected text content"),
+            };
+            let value: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(value["status"], expected);
+            assert_eq!(value["id"], "test-id");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purge_component_not_in_trash() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+
+        let req = CallToolRequestParam {
+            name: "purge-component".into(),
+            arguments: Some(serde_json::Map::from_iter([(
+                "id".to_string(),
+                json!("missing-component"),
+            )])),
+        };
+
+        let result = handle_purge_component(&req, &lifecycle_manager).await?;
+        assert_eq!(result.is_error, Some(true));
+
+        Ok(())
+    }
 }
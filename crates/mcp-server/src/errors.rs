@@ -0,0 +1,331 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Error codes of failed tool calls
+//!
+//! A tool call that fails once it runs returns an `isError` result whose `structuredContent` is
+//! `{"error": {"code": ..., "message": ..., "hint": ..., "remediation": [...]}}`. The `code` is
+//! one of the [`ToolErrorCode`]s, so clients can branch on it instead of matching messages such as
+//! `HttpRequestDenied`, and `remediation` lists the tool calls that fix the failure, such as the
+//! exact `grant-network-permission` call for a host the network policy blocked. Calls rejected
+//! before they run fail with a JSON-RPC error whose `data` has the same `code`.
+
+use std::fmt;
+
+use rmcp::model::{CallToolResult, Content};
+use serde_json::{json, Value};
+use wassette::{ComponentNotFound, NetworkRequest};
+
+use crate::security::{
+    retry_after_secs, InvalidArguments, InvalidToolCall, RateLimitExceeded, ToolAccessDenied,
+};
+
+/// Why a tool call failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolErrorCode {
+    /// The policy of the component, or the caller's API key, doesn't allow what the call needed
+    PolicyDenied,
+    /// The component trapped
+    Trap,
+    /// The call didn't finish in time
+    Timeout,
+    /// The tool name or arguments are malformed, over the limits or don't match the tool's schema
+    ValidationFailed,
+    /// The component, or the component with the tool, isn't loaded
+    ComponentNotFound,
+    /// The call ran into a rate limit or a resource limit of the component
+    ResourceExhausted,
+    /// Anything without a code of its own, such as an error the tool returned
+    ToolFailed,
+}
+
+impl ToolErrorCode {
+    /// The code as it appears in error data
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ToolErrorCode::PolicyDenied => "policy_denied",
+            ToolErrorCode::Trap => "trap",
+            ToolErrorCode::Timeout => "timeout",
+            ToolErrorCode::ValidationFailed => "validation_failed",
+            ToolErrorCode::ComponentNotFound => "component_not_found",
+            ToolErrorCode::ResourceExhausted => "resource_exhausted",
+            ToolErrorCode::ToolFailed => "tool_failed",
+        }
+    }
+}
+
+impl fmt::Display for ToolErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A failed tool call as it is reported to the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolError {
+    /// Why the call failed
+    pub code: ToolErrorCode,
+    /// What went wrong
+    pub message: String,
+    /// What the caller can do about it
+    pub hint: Option<String>,
+    /// Tool calls that fix the failure, as `{"tool": ..., "arguments": ...}`
+    pub remediation: Vec<Value>,
+    /// Structured information about the failure, such as the requests the policy blocked
+    pub details: Option<Value>,
+}
+
+impl ToolError {
+    /// Creates an error without a hint
+    pub fn new(code: ToolErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            hint: None,
+            remediation: Vec::new(),
+            details: None,
+        }
+    }
+
+    /// Adds a hint for the caller
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Adds structured details
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// The error of a call that failed with `error`. Errors of the types this crate and wassette
+    /// define get their own code, and so do traps; any other error is [`ToolErrorCode::ToolFailed`].
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        let message = error.to_string();
+        error
+            .chain()
+            .find_map(|cause| error_of_cause(cause, &message))
+            .unwrap_or_else(|| Self::new(ToolErrorCode::ToolFailed, message))
+    }
+
+    /// The error of a call of `component_id` that failed, most likely because its network policy
+    /// blocked `denied_requests`. The remediation grants each of their hosts.
+    pub fn policy_denied(
+        component_id: &str,
+        denied_requests: &[NetworkRequest],
+        message: impl Into<String>,
+    ) -> Self {
+        let mut hosts = denied_requests
+            .iter()
+            .map(|request| request.host.as_str())
+            .collect::<Vec<_>>();
+        hosts.sort_unstable();
+        hosts.dedup();
+        let remediation = hosts
+            .iter()
+            .map(|host| {
+                json!({
+                    "tool": "grant-network-permission",
+                    "arguments": { "component_id": component_id, "details": { "host": host } },
+                })
+            })
+            .collect();
+        Self {
+            remediation,
+            ..Self::new(ToolErrorCode::PolicyDenied, message)
+                .with_hint(format!(
+                    "The network policy of component {component_id} blocked requests to {}. Grant access with grant-network-permission and retry the call.",
+                    hosts.join(", ")
+                ))
+                .with_details(json!({ "denied_requests": denied_requests }))
+        }
+    }
+
+    /// The error as it appears in error data
+    pub fn to_value(&self) -> Value {
+        let mut value = json!({ "code": self.code.as_str(), "message": self.message });
+        if let Some(hint) = &self.hint {
+            value["hint"] = json!(hint);
+        }
+        if !self.remediation.is_empty() {
+            value["remediation"] = json!(self.remediation);
+        }
+        if let Some(details) = &self.details {
+            value["details"] = details.clone();
+        }
+        value
+    }
+
+    /// The `isError` result of the call, with the message and hint as text for people and the
+    /// error as `structuredContent` for programs
+    pub fn into_result(self) -> CallToolResult {
+        let text = match &self.hint {
+            Some(hint) => format!("Error: {}\n\nHint: {hint}", self.message),
+            None => format!("Error: {}", self.message),
+        };
+        CallToolResult {
+            content: Some(vec![Content::text(text)]),
+            structured_content: Some(json!({ "error": self.to_value() })),
+            is_error: Some(true),
+        }
+    }
+}
+
+/// The error with `message` of a call that failed because of `cause`, if it has a code of its own
+fn error_of_cause(cause: &(dyn std::error::Error + 'static), message: &str) -> Option<ToolError> {
+    if let Some(not_found) = cause.downcast_ref::<ComponentNotFound>() {
+        let hint = match not_found {
+            ComponentNotFound::Component(_) => {
+                "Call list-components to see the loaded components, or load-component to load this one"
+            }
+            ComponentNotFound::Tool(_) => {
+                "List the tools to see the available ones, or call load-component to load the component with this tool"
+            }
+        };
+        return Some(ToolError::new(ToolErrorCode::ComponentNotFound, message).with_hint(hint));
+    }
+    if let Some(invalid) = cause.downcast_ref::<InvalidToolCall>() {
+        return Some(
+            ToolError::new(ToolErrorCode::ValidationFailed, message)
+                .with_hint("Fix the tool name, or make the arguments smaller or less deeply nested")
+                .with_details(json!({ "reason": invalid.reason })),
+        );
+    }
+    if let Some(invalid) = cause.downcast_ref::<InvalidArguments>() {
+        return Some(
+            ToolError::new(ToolErrorCode::ValidationFailed, message)
+                .with_hint("Fix the arguments to match the inputSchema of the tool")
+                .with_details(json!({ "errors": invalid.data()["errors"] })),
+        );
+    }
+    if let Some(exceeded) = cause.downcast_ref::<RateLimitExceeded>() {
+        let secs = retry_after_secs(exceeded.retry_after);
+        return Some(
+            ToolError::new(ToolErrorCode::ResourceExhausted, message)
+                .with_hint(format!("Retry the call after {secs} seconds"))
+                .with_details(json!({ "retry_after_secs": secs })),
+        );
+    }
+    if cause.downcast_ref::<ToolAccessDenied>().is_some() {
+        return Some(
+            ToolError::new(ToolErrorCode::PolicyDenied, message)
+                .with_hint("Use an API key that is allowed to call this tool"),
+        );
+    }
+    if cause
+        .downcast_ref::<tokio::time::error::Elapsed>()
+        .is_some()
+    {
+        return Some(ToolError::new(ToolErrorCode::Timeout, message));
+    }
+    cause
+        .downcast_ref::<wasmtime::Trap>()
+        .map(|trap| trap_error(*trap, message))
+}
+
+/// The error of a call in which the component trapped. Running out of time or stack gets a code
+/// of its own.
+fn trap_error(trap: wasmtime::Trap, message: &str) -> ToolError {
+    match trap {
+        wasmtime::Trap::Interrupt | wasmtime::Trap::OutOfFuel => {
+            ToolError::new(ToolErrorCode::Timeout, message)
+        }
+        wasmtime::Trap::StackOverflow => ToolError::new(ToolErrorCode::ResourceExhausted, message)
+            .with_hint("The component overflowed its stack; retrying with smaller input may help"),
+        _ => ToolError::new(ToolErrorCode::Trap, message)
+            .with_hint("The component crashed; retrying the same call will most likely fail again"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn denied_request(host: &str) -> NetworkRequest {
+        NetworkRequest {
+            component_id: "fetch".to_string(),
+            method: "GET".to_string(),
+            host: host.to_string(),
+            path: "/".to_string(),
+            status: None,
+            bytes: 0,
+            duration_ms: 0,
+            cached: false,
+            error: Some("denied by network policy".to_string()),
+            time: "2025-01-01T00:00:00.000Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_error() {
+        let error = anyhow::Error::new(ComponentNotFound::Component("fetch".to_string()));
+        let tool_error = ToolError::from_error(&error);
+        assert_eq!(tool_error.code, ToolErrorCode::ComponentNotFound);
+        assert_eq!(tool_error.message, "Component not found: fetch");
+        assert!(tool_error.hint.unwrap().contains("load-component"));
+
+        // Context added on the way up doesn't hide the code
+        let error =
+            anyhow::Error::new(wasmtime::Trap::UnreachableCodeReached).context("Tool fetch failed");
+        assert_eq!(ToolError::from_error(&error).code, ToolErrorCode::Trap);
+        let error = anyhow::Error::new(wasmtime::Trap::Interrupt);
+        assert_eq!(ToolError::from_error(&error).code, ToolErrorCode::Timeout);
+
+        let error = anyhow::Error::new(RateLimitExceeded {
+            tool: "fetch".to_string(),
+            limit: 2,
+            retry_after: Duration::from_millis(1500),
+        });
+        let tool_error = ToolError::from_error(&error);
+        assert_eq!(tool_error.code, ToolErrorCode::ResourceExhausted);
+        assert_eq!(tool_error.details, Some(json!({ "retry_after_secs": 2 })));
+
+        let error = anyhow::anyhow!("the tool failed");
+        assert_eq!(
+            ToolError::from_error(&error),
+            ToolError::new(ToolErrorCode::ToolFailed, "the tool failed")
+        );
+    }
+
+    #[test]
+    fn test_policy_denied() {
+        let denied = [
+            denied_request("api.example.com"),
+            denied_request("api.example.com"),
+            denied_request("cdn.example.com"),
+        ];
+        let error = ToolError::policy_denied("fetch", &denied, "HttpRequestDenied");
+        assert_eq!(error.code, ToolErrorCode::PolicyDenied);
+        assert_eq!(
+            error.remediation,
+            vec![
+                json!({
+                    "tool": "grant-network-permission",
+                    "arguments": {"component_id": "fetch", "details": {"host": "api.example.com"}},
+                }),
+                json!({
+                    "tool": "grant-network-permission",
+                    "arguments": {"component_id": "fetch", "details": {"host": "cdn.example.com"}},
+                }),
+            ]
+        );
+
+        let result = error.into_result();
+        assert_eq!(result.is_error, Some(true));
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["error"]["code"], "policy_denied");
+        assert_eq!(structured["error"]["message"], "HttpRequestDenied");
+        assert_eq!(
+            structured["error"]["details"]["denied_requests"][0]["host"],
+            "api.example.com"
+        );
+        let text = serde_json::to_value(&result.content.unwrap()[0]).unwrap();
+        assert!(text["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error: HttpRequestDenied\n\nHint: The network policy"));
+    }
+}
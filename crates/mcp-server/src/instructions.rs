@@ -0,0 +1,139 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! The `instructions` the server returns to clients at initialization
+//!
+//! The instructions are rendered from a template when a client connects, so they describe the
+//! components that are loaded at that time. The template can use these placeholders:
+//!
+//! - `{components}`: a list of the loaded components with their tools and whether they have a
+//!   policy
+//! - `{component_count}`: the number of loaded components
+//! - `{tool_count}`: the number of tools of the loaded components
+
+use serde_json::Value;
+use wassette::LifecycleManager;
+
+/// The instructions template used unless the server is configured with another one
+pub const DEFAULT_INSTRUCTIONS_TEMPLATE: &str = r#"This server runs tools in sandboxed WebAssembly environments with no default access to host resources.
+
+Key points:
+- Tools must be loaded before use: "Load component from oci://registry/tool:version" or "file:///path/to/tool.wasm"
+- When the server starts, it will load all tools present in the plugin directory.
+- You can list loaded tools with 'list-components' tool.
+- Each tool only accesses resources explicitly granted by a policy file (filesystem paths, network domains, etc.)
+- You MUST never modify the policy file directly, use tools to grant permissions instead.
+- Tools needs permission for that resource
+- If access is denied, suggest alternatives within allowed permissions or propose to grant permission
+- Each component has a `use-<component-id>` prompt that explains its tools and permissions
+
+Loaded components ({component_count} components, {tool_count} tools):
+{components}"#;
+
+/// How many components and tools are loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadedTools {
+    /// Number of loaded components
+    pub components: usize,
+    /// Number of tools of the loaded components
+    pub tools: usize,
+}
+
+/// Counts the loaded components and their tools
+pub async fn loaded_tools(lifecycle_manager: &LifecycleManager) -> LoadedTools {
+    LoadedTools {
+        components: lifecycle_manager.list_components().await.len(),
+        tools: lifecycle_manager.list_tools().await.len(),
+    }
+}
+
+/// Renders `template` for the components `lifecycle_manager` has loaded
+pub async fn render_instructions(lifecycle_manager: &LifecycleManager, template: &str) -> String {
+    let mut components = lifecycle_manager.list_components().await;
+    components.sort();
+
+    let mut lines = Vec::new();
+    let mut tool_count = 0;
+    for id in &components {
+        let tools = lifecycle_manager
+            .get_component_schema(id)
+            .await
+            .and_then(|schema| schema.get("tools").and_then(Value::as_array).cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|tool| tool.get("name").and_then(Value::as_str))
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>();
+        tool_count += tools.len();
+
+        let mut line = format!("- `{id}`");
+        if let Some(description) = lifecycle_manager
+            .get_component_docs(id)
+            .await
+            .ok()
+            .and_then(|docs| docs.description)
+            .and_then(|description| description.lines().next().map(str::trim).map(String::from))
+            .filter(|description| !description.is_empty())
+        {
+            line.push_str(&format!(" ({description})"));
+        }
+        line.push_str(&format!(": {}.", tools.join(", ")));
+        if lifecycle_manager.get_policy_info(id).await.is_some() {
+            line.push_str(" Its policy grants permissions, see `get-policy`.");
+        } else {
+            line.push_str(" No permissions are granted to it.");
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push("None yet. Load one with the `load-component` tool.".to_string());
+    }
+
+    template
+        .replace("{components}", &lines.join("\n"))
+        .replace("{component_count}", &components.len().to_string())
+        .replace("{tool_count}", &tool_count.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render_instructions() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        let empty = render_instructions(&manager, DEFAULT_INSTRUCTIONS_TEMPLATE).await;
+        assert!(
+            empty.contains("(0 components, 0 tools):\nNone yet."),
+            "{empty}"
+        );
+
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func $run (result u32) (canon lift (core func $i "run")))
+              (export "run" (func $run))
+              (@custom "package-docs" "\00{\"worlds\":{\"answer\":{\"docs\":\"Answers questions.\\nAll of them.\"}}}"))"#,
+        )?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        assert_eq!(
+            loaded_tools(&manager).await,
+            LoadedTools {
+                components: 1,
+                tools: 1
+            }
+        );
+        let instructions =
+            render_instructions(&manager, "{component_count}/{tool_count}\n{components}").await;
+        assert_eq!(
+            instructions,
+            "1/1\n- `answer` (Answers questions.): `run`. No permissions are granted to it."
+        );
+        Ok(())
+    }
+}
@@ -3,11 +3,51 @@
 
 pub use wassette::LifecycleManager;
 
+pub mod batch;
+pub mod completion;
 pub mod components;
+pub mod errors;
+pub mod instructions;
+pub mod list_changes;
+pub mod logging;
 pub mod prompts;
+pub mod proxy;
 pub mod resources;
+pub mod roots;
+pub mod sampling;
+pub mod scheduler;
+pub mod secrets;
+pub mod security;
+pub mod summarize;
+pub mod tokens;
 pub mod tools;
 
-pub use prompts::handle_prompts_list;
-pub use resources::handle_resources_list;
-pub use tools::{handle_tools_call, handle_tools_list};
+pub use batch::{batch_entries, BATCH_TOOL};
+pub use completion::{completion_info, handle_completion};
+pub use errors::{ToolError, ToolErrorCode};
+pub use instructions::{
+    loaded_tools, render_instructions, LoadedTools, DEFAULT_INSTRUCTIONS_TEMPLATE,
+};
+pub use list_changes::{changes_tool_list, forward_list_changes, ListChanges};
+pub use logging::{
+    component_log_notification, forward_component_logs, forward_security_events,
+    policy_mismatch_notification, requested_loggers, secret_change_notification, LogFilter,
+    SECURITY_LOGGER,
+};
+pub use prompts::{handle_prompts_get, handle_prompts_list, USAGE_PROMPT_PREFIX};
+pub use proxy::{DownstreamServer, DownstreamTransport, Downstreams, PROXY_TOOL_SEPARATOR};
+pub use resources::{
+    forward_resource_updates, handle_resource_templates_list, handle_resources_list,
+    handle_resources_read, updated_resource_uris, ResourceSubscriptions, ResultStore,
+};
+pub use roots::{list_roots, storage_uri, supports_roots, RootChanges, RootGrants};
+pub use sampling::ClientSampler;
+pub use scheduler::{ComponentWeight, Priority, Scheduler, ToolPriority};
+pub use secrets::{is_secret_tool, SECRET_TOOLS};
+pub use security::{
+    ApiKeyAccess, ApiKeys, CallLimitOverrides, CallLimitSettings, CallLimits, InvalidArguments,
+    InvalidToolCall, RateLimitExceeded, RateLimitRule, RateLimiter, ToolAccessDenied,
+};
+pub use summarize::{SummarizeSettings, SummaryBudget};
+pub use tokens::{TokenHeuristic, TokenSettings};
+pub use tools::{execute_tool_call, handle_tools_call, handle_tools_list, ToolCallOptions};
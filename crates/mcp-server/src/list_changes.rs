@@ -0,0 +1,153 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! `notifications/tools/list_changed` for every change of the loaded components and their
+//! permissions
+//!
+//! The notifications follow the lifecycle events, so clients also refresh their tool list when a
+//! component is loaded in the background or by another session. Bursts of events, such as a
+//! restore that loads a component and attaches its policy, are sent as one notification.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rmcp::{Peer, RoleServer};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use wassette::{Event, LifecycleManager};
+
+/// How long events are collected into one notification
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Whether clients should list the tools again after `event`
+pub fn changes_tool_list(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::ComponentLoaded { .. }
+            | Event::ComponentUnloaded { .. }
+            | Event::ComponentRestored { .. }
+            | Event::PolicyAttached { .. }
+            | Event::PolicyDetached { .. }
+            | Event::PermissionGranted { .. }
+            | Event::PermissionRevoked { .. }
+            | Event::PermissionsReset { .. }
+    )
+}
+
+/// The tool list change notifications of one client session
+#[derive(Debug, Clone, Default)]
+pub struct ListChanges {
+    flushes: Arc<Mutex<Option<mpsc::UnboundedSender<oneshot::Sender<()>>>>>,
+}
+
+impl ListChanges {
+    /// Sends the notification for the events so far right away, so that it reaches the client
+    /// before the response of the tool call that caused them. Returns `false` if nothing
+    /// forwards the notifications of the session.
+    pub async fn flush(&self) -> bool {
+        let Some(flushes) = self.flushes.lock().unwrap().clone() else {
+            return false;
+        };
+        let (reply, flushed) = oneshot::channel();
+        flushes.send(reply).is_ok() && flushed.await.is_ok()
+    }
+}
+
+/// Sends `notifications/tools/list_changed` to `server_peer` after every change of the tool
+/// list until the client goes away
+pub async fn forward_list_changes(
+    lifecycle_manager: LifecycleManager,
+    server_peer: Peer<RoleServer>,
+    list_changes: ListChanges,
+) {
+    let mut events = lifecycle_manager.subscribe_events();
+    // A session view kept by the task would keep the session's components loaded
+    drop(lifecycle_manager);
+    let (sender, mut flushes) = mpsc::unbounded_channel();
+    *list_changes.flushes.lock().unwrap() = Some(sender);
+
+    let mut deadline: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if changes_tool_list(&event) => {
+                    deadline.get_or_insert_with(|| Instant::now() + DEBOUNCE);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Dropped lifecycle events for a slow client");
+                    deadline.get_or_insert_with(|| Instant::now() + DEBOUNCE);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Some(reply) = flushes.recv() => {
+                if drain(&mut events) || deadline.is_some() {
+                    deadline = None;
+                    if !notify(&server_peer).await {
+                        break;
+                    }
+                }
+                let _ = reply.send(());
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                deadline = None;
+                if !notify(&server_peer).await {
+                    break;
+                }
+            }
+        }
+    }
+    list_changes.flushes.lock().unwrap().take();
+}
+
+/// Takes the events that are already waiting and tells whether any of them changes the tool list
+fn drain(events: &mut Receiver<Event>) -> bool {
+    let mut changed = false;
+    loop {
+        match events.try_recv() {
+            Ok(event) => changed |= changes_tool_list(&event),
+            Err(TryRecvError::Lagged(_)) => changed = true,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return changed,
+        }
+    }
+}
+
+async fn notify(server_peer: &Peer<RoleServer>) -> bool {
+    match server_peer.notify_tool_list_changed().await {
+        Ok(()) => {
+            debug!("Sent tool list changed notification");
+            true
+        }
+        Err(e) => {
+            debug!(error = %e, "Stopped sending tool list changes");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_tool_list() {
+        assert!(changes_tool_list(&Event::ComponentLoaded {
+            component_id: "fetch".to_string(),
+            replaced: false,
+        }));
+        assert!(changes_tool_list(&Event::PermissionsReset {
+            component_id: "fetch".to_string(),
+        }));
+        assert!(!changes_tool_list(&Event::ComponentPurged {
+            component_id: "fetch".to_string(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_flush_without_forwarder() {
+        assert!(!ListChanges::default().flush().await);
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! MCP `notifications/message` of the server and of components
+//!
+//! Every client session receives the log messages of components, with the component ID as the
+//! logger, and the permission changes made through tools, changes of the secrets of components
+//! and warnings about components that import capabilities their policy grants nothing for, with
+//! [`SECURITY_LOGGER`] as the logger.
+//! A client picks what it receives with `logging/setLevel`: messages below the level it sets are
+//! dropped, and if the request's `_meta` has a list of `loggers`, so are the messages of any
+//! other logger. A logger name also covers the loggers below it, so `wassette` covers
+//! `wassette.security`.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Result};
+use rmcp::model::{LoggingLevel, LoggingMessageNotificationParam, Meta};
+use rmcp::{Peer, RoleServer};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+use wassette::{ComponentLog, Event, LifecycleManager, LogLevel};
+
+/// Logger of the notifications about permissions, secrets and policy mismatches
+pub const SECURITY_LOGGER: &str = "wassette.security";
+
+/// Key of the `_meta` of a `logging/setLevel` request with the loggers the client wants
+pub const LOGGERS_META_KEY: &str = "loggers";
+
+/// The `notifications/message` that a client session receives. Until the client sets a level,
+/// it receives all of them.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    settings: Arc<RwLock<LogFilterSettings>>,
+}
+
+#[derive(Debug, Default)]
+struct LogFilterSettings {
+    level: Option<LoggingLevel>,
+    loggers: Option<Vec<String>>,
+}
+
+impl LogFilter {
+    /// Only lets through messages at `level` or above, and if `loggers` is given, only those of
+    /// the loggers in it and the loggers below them
+    pub fn set(&self, level: LoggingLevel, loggers: Option<Vec<String>>) {
+        if let Ok(mut settings) = self.settings.write() {
+            *settings = LogFilterSettings {
+                level: Some(level),
+                loggers,
+            };
+        }
+    }
+
+    /// Whether the client should receive `notification`
+    pub fn allows(&self, notification: &LoggingMessageNotificationParam) -> bool {
+        let Ok(settings) = self.settings.read() else {
+            return true;
+        };
+        if let Some(level) = settings.level {
+            if severity(notification.level) < severity(level) {
+                return false;
+            }
+        }
+        match (&settings.loggers, &notification.logger) {
+            (None, _) => true,
+            (Some(loggers), Some(logger)) => loggers.iter().any(|name| logger_covers(name, logger)),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// The loggers a `logging/setLevel` request asks for in `_meta.loggers`, or `None` for all of
+/// them
+pub fn requested_loggers(meta: &Meta) -> Result<Option<Vec<String>>> {
+    match meta.get(LOGGERS_META_KEY) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(loggers)) => loggers
+            .iter()
+            .map(|logger| match logger.as_str() {
+                Some(logger) if !logger.is_empty() => Ok(logger.to_string()),
+                _ => bail!("'{LOGGERS_META_KEY}' must be a list of logger names"),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some),
+        Some(_) => bail!("'{LOGGERS_META_KEY}' must be a list of logger names"),
+    }
+}
+
+/// Rank of `level`, from debug, the lowest, to emergency
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+/// Whether the logger called `name` is `logger` or one of the loggers above it
+fn logger_covers(name: &str, logger: &str) -> bool {
+    logger
+        .strip_prefix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Builds the `notifications/message` for a component log message. The logger name is the
+/// component id.
+pub fn component_log_notification(log: &ComponentLog) -> LoggingMessageNotificationParam {
+    let level = match log.level {
+        LogLevel::Trace | LogLevel::Debug => LoggingLevel::Debug,
+        LogLevel::Info => LoggingLevel::Info,
+        LogLevel::Warn => LoggingLevel::Warning,
+        LogLevel::Error => LoggingLevel::Error,
+        LogLevel::Critical => LoggingLevel::Critical,
+    };
+
+    LoggingMessageNotificationParam {
+        level,
+        logger: Some(log.component_id.clone()),
+        data: json!({
+            "context": log.context,
+            "message": log.message,
+        }),
+    }
+}
+
+/// Forwards the component log messages that `filter` lets through to `server_peer` until the
+/// client goes away
+pub async fn forward_component_logs(
+    lifecycle_manager: LifecycleManager,
+    server_peer: Peer<RoleServer>,
+    filter: LogFilter,
+) {
+    let mut logs = lifecycle_manager.subscribe_component_logs();
+    // A session view kept by the task would keep the session's components loaded
+    drop(lifecycle_manager);
+    loop {
+        match logs.recv().await {
+            Ok(log) => {
+                let notification = component_log_notification(&log);
+                if !filter.allows(&notification) {
+                    continue;
+                }
+                if let Err(e) = server_peer.notify_logging_message(notification).await {
+                    debug!(error = %e, "Stopped forwarding component logs");
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Dropped component log messages for a slow client");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Builds the `notifications/message` for a change of the secrets of a component, however it
+/// was made. Only the name of the secret is sent, never its value.
+pub fn secret_change_notification(event: &Event) -> Option<LoggingMessageNotificationParam> {
+    let Event::SecretChanged {
+        component_id,
+        key,
+        removed,
+    } = event
+    else {
+        return None;
+    };
+    Some(LoggingMessageNotificationParam {
+        level: LoggingLevel::Info,
+        logger: Some(SECURITY_LOGGER.to_string()),
+        data: json!({
+            "event": "secret_changed",
+            "operation": if *removed { "delete" } else { "set" },
+            "component_id": component_id,
+            "key": key,
+        }),
+    })
+}
+
+/// Builds the `notifications/message` for a component that imports capabilities its policy
+/// grants nothing for, such as `imports wasi:sockets but policy grants no network`
+pub fn policy_mismatch_notification(event: &Event) -> Option<LoggingMessageNotificationParam> {
+    let Event::PolicyMismatch {
+        component_id,
+        warnings,
+    } = event
+    else {
+        return None;
+    };
+    Some(LoggingMessageNotificationParam {
+        level: LoggingLevel::Warning,
+        logger: Some(SECURITY_LOGGER.to_string()),
+        data: json!({
+            "event": "policy_mismatch",
+            "component_id": component_id,
+            "warnings": warnings,
+        }),
+    })
+}
+
+/// Forwards the changes of the secrets of components and the policy mismatches of loaded
+/// components that `filter` lets through to `server_peer` until the client goes away
+pub async fn forward_security_events(
+    lifecycle_manager: LifecycleManager,
+    server_peer: Peer<RoleServer>,
+    filter: LogFilter,
+) {
+    let mut events = lifecycle_manager.subscribe_events();
+    // A session view kept by the task would keep the session's components loaded
+    drop(lifecycle_manager);
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                let Some(notification) = secret_change_notification(&event)
+                    .or_else(|| policy_mismatch_notification(&event))
+                else {
+                    continue;
+                };
+                if !filter.allows(&notification) {
+                    continue;
+                }
+                if let Err(e) = server_peer.notify_logging_message(notification).await {
+                    debug!(error = %e, "Stopped forwarding security events");
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Dropped security notifications for a slow client");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wassette::{Capability, PolicyWarning};
+
+    use super::*;
+
+    #[test]
+    fn test_component_log_notification() {
+        let notification = component_log_notification(&ComponentLog {
+            component_id: "fetch-rs".to_string(),
+            level: LogLevel::Warn,
+            context: "http".to_string(),
+            message: "retrying".to_string(),
+        });
+
+        assert_eq!(notification.level, LoggingLevel::Warning);
+        assert_eq!(notification.logger.as_deref(), Some("fetch-rs"));
+        assert_eq!(
+            notification.data,
+            json!({"context": "http", "message": "retrying"})
+        );
+    }
+
+    fn notification(level: LoggingLevel, logger: Option<&str>) -> LoggingMessageNotificationParam {
+        LoggingMessageNotificationParam {
+            level,
+            logger: logger.map(str::to_string),
+            data: json!({}),
+        }
+    }
+
+    #[test]
+    fn test_secret_change_notification() {
+        let notification = secret_change_notification(&Event::SecretChanged {
+            component_id: "fetch-rs".to_string(),
+            key: "API_TOKEN".to_string(),
+            removed: true,
+        })
+        .unwrap();
+
+        assert_eq!(notification.level, LoggingLevel::Info);
+        assert_eq!(notification.logger.as_deref(), Some(SECURITY_LOGGER));
+        assert_eq!(
+            notification.data,
+            json!({
+                "event": "secret_changed",
+                "operation": "delete",
+                "component_id": "fetch-rs",
+                "key": "API_TOKEN",
+            })
+        );
+        assert!(secret_change_notification(&Event::ComponentUnloaded {
+            component_id: "fetch-rs".to_string(),
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn test_policy_mismatch_notification() {
+        let notification = policy_mismatch_notification(&Event::PolicyMismatch {
+            component_id: "fetch-rs".to_string(),
+            warnings: vec![PolicyWarning {
+                capability: Capability::Sockets,
+                import: "wasi:sockets/tcp@0.2.0".to_string(),
+                message: "imports wasi:sockets but policy grants no network".to_string(),
+            }],
+        })
+        .unwrap();
+
+        assert_eq!(notification.level, LoggingLevel::Warning);
+        assert_eq!(notification.logger.as_deref(), Some(SECURITY_LOGGER));
+        assert_eq!(
+            notification.data,
+            json!({
+                "event": "policy_mismatch",
+                "component_id": "fetch-rs",
+                "warnings": [{
+                    "capability": "sockets",
+                    "import": "wasi:sockets/tcp@0.2.0",
+                    "message": "imports wasi:sockets but policy grants no network",
+                }],
+            })
+        );
+        assert!(policy_mismatch_notification(&Event::ComponentUnloaded {
+            component_id: "fetch-rs".to_string(),
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let filter = LogFilter::default();
+        assert!(filter.allows(&notification(LoggingLevel::Debug, None)));
+
+        filter.set(LoggingLevel::Warning, None);
+        assert!(!filter.allows(&notification(LoggingLevel::Info, Some("fetch-rs"))));
+        assert!(filter.allows(&notification(LoggingLevel::Warning, Some("fetch-rs"))));
+        assert!(filter.allows(&notification(LoggingLevel::Critical, None)));
+
+        filter.set(LoggingLevel::Info, Some(vec!["wassette".to_string()]));
+        assert!(filter.allows(&notification(LoggingLevel::Info, Some(SECURITY_LOGGER))));
+        assert!(filter.allows(&notification(LoggingLevel::Info, Some("wassette"))));
+        assert!(!filter.allows(&notification(LoggingLevel::Info, Some("wassettes"))));
+        assert!(!filter.allows(&notification(LoggingLevel::Error, Some("fetch-rs"))));
+        assert!(!filter.allows(&notification(LoggingLevel::Error, None)));
+    }
+
+    #[test]
+    fn test_requested_loggers() {
+        let meta = |value: Value| Meta(value.as_object().cloned().unwrap());
+        assert_eq!(requested_loggers(&meta(json!({}))).unwrap(), None);
+        assert_eq!(
+            requested_loggers(&meta(json!({"loggers": [SECURITY_LOGGER]}))).unwrap(),
+            Some(vec![SECURITY_LOGGER.to_string()])
+        );
+        assert!(requested_loggers(&meta(json!({"loggers": "wassette"}))).is_err());
+        assert!(requested_loggers(&meta(json!({"loggers": [""]}))).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Tools of downstream MCP servers, re-exported by Wassette
+//!
+//! Wassette can act as a gateway for other MCP servers: it connects to each configured server
+//! at startup, over stdio to a child process or over streamable HTTP, and lists its tools next to
+//! the component tools as `<server>__<tool>`. Calls of these tools go through the same pipeline
+//! as component calls, with rate limits, API key allowlists and the call queue keyed by the name
+//! of the server, and every call is recorded as a `proxy.tool.invoked` event.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rmcp::model::{CallToolRequestParam, CallToolResult, RawContent, Tool};
+use rmcp::service::RunningService;
+use rmcp::transport::streamable_http_client::StreamableHttpClientTransportConfig;
+use rmcp::transport::StreamableHttpClientTransport;
+use rmcp::{RoleClient, ServiceExt as _};
+use tokio::process::{Child, Command};
+use tracing::{info, warn};
+use wassette::LifecycleManager;
+
+/// Separates the name of a downstream server from the name of its tool in proxied tool names
+pub const PROXY_TOOL_SEPARATOR: &str = "__";
+
+/// How long connecting to a downstream server and listing its tools may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How Wassette reaches a downstream server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownstreamTransport {
+    /// A child process that speaks MCP over its stdin and stdout
+    Command {
+        command: String,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+    },
+    /// A server with a streamable HTTP endpoint
+    StreamableHttp {
+        url: String,
+        headers: HashMap<String, String>,
+    },
+}
+
+/// A downstream MCP server whose tools Wassette re-exports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownstreamServer {
+    /// Namespace of the server's tools
+    pub name: String,
+    pub transport: DownstreamTransport,
+    /// Tools to import, by their name on the server. Every tool if `None`.
+    pub tools: Option<Vec<String>>,
+}
+
+struct Connection {
+    service: RunningService<RoleClient, ()>,
+    tools: Vec<Tool>,
+    /// The process of a stdio server, killed when the connection is dropped
+    _child: Option<Child>,
+}
+
+/// The connected downstream servers and the tools imported from them
+#[derive(Clone, Default)]
+pub struct Downstreams {
+    connections: Arc<HashMap<String, Connection>>,
+}
+
+impl std::fmt::Debug for Downstreams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Downstreams")
+            .field("servers", &self.connections.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Downstreams {
+    /// Connects to `servers` and lists their tools. Servers that can't be reached are logged and
+    /// left out, so one broken server doesn't keep Wassette from starting.
+    pub async fn connect(servers: &[DownstreamServer]) -> Result<Self> {
+        let mut connections = HashMap::new();
+        let mut names = std::collections::HashSet::new();
+        for server in servers {
+            if server.name.is_empty() || server.name.contains(PROXY_TOOL_SEPARATOR) {
+                bail!(
+                    "MCP server names must be non-empty and not contain '{PROXY_TOOL_SEPARATOR}': '{}'",
+                    server.name
+                );
+            }
+            if !names.insert(server.name.as_str()) {
+                bail!("MCP server name '{}' is used more than once", server.name);
+            }
+            match tokio::time::timeout(CONNECT_TIMEOUT, connect(server)).await {
+                Ok(Ok(connection)) => {
+                    info!(
+                        server = server.name,
+                        tools = connection.tools.len(),
+                        "Connected to MCP server"
+                    );
+                    connections.insert(server.name.clone(), connection);
+                }
+                Ok(Err(e)) => {
+                    warn!(server = server.name, error = %e, "Failed to connect to MCP server")
+                }
+                Err(_) => warn!(server = server.name, "Timed out connecting to MCP server"),
+            }
+        }
+        Ok(Self {
+            connections: Arc::new(connections),
+        })
+    }
+
+    /// Whether no downstream server is connected
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// The imported tools of every server, under their proxied names
+    pub fn tools(&self) -> Vec<Tool> {
+        let mut tools = self
+            .connections
+            .iter()
+            .flat_map(|(server, connection)| {
+                connection.tools.iter().map(move |tool| Tool {
+                    name: Cow::Owned(format!("{server}{PROXY_TOOL_SEPARATOR}{}", tool.name)),
+                    ..tool.clone()
+                })
+            })
+            .collect::<Vec<_>>();
+        tools.sort_by(|a, b| a.name.cmp(&b.name));
+        tools
+    }
+
+    /// The server and the name on that server of the proxied tool `name`, if it is one
+    fn resolve<'n>(&self, name: &'n str) -> Option<(&str, &Connection, &'n str)> {
+        let (server, tool) = name.split_once(PROXY_TOOL_SEPARATOR)?;
+        let (server, connection) = self.connections.get_key_value(server)?;
+        connection
+            .tools
+            .iter()
+            .any(|imported| imported.name == tool)
+            .then_some((server.as_str(), connection, tool))
+    }
+
+    /// The name of the server of the proxied tool `name`, if it is one
+    pub fn server_of(&self, name: &str) -> Option<&str> {
+        self.resolve(name).map(|(server, _, _)| server)
+    }
+
+    /// Calls the proxied tool of `req` on its server and records the call with the lifecycle
+    /// manager's events
+    pub async fn call(
+        &self,
+        req: &CallToolRequestParam,
+        lifecycle_manager: &LifecycleManager,
+    ) -> Result<CallToolResult> {
+        let (server, connection, tool) = self
+            .resolve(&req.name)
+            .ok_or_else(|| anyhow!("Unknown proxied tool: {}", req.name))?;
+        let start = Instant::now();
+        let result = connection
+            .service
+            .call_tool(CallToolRequestParam {
+                name: Cow::Owned(tool.to_string()),
+                arguments: req.arguments.clone(),
+            })
+            .await
+            .with_context(|| format!("MCP server '{server}' failed to call {tool}"));
+        let error = match &result {
+            Ok(result) if result.is_error == Some(true) => Some(error_text(result)),
+            Ok(_) => None,
+            Err(e) => Some(format!("{e:#}")),
+        };
+        lifecycle_manager.record_proxied_call(
+            server,
+            tool,
+            start.elapsed().as_millis() as u64,
+            error,
+        );
+        result
+    }
+}
+
+/// The text of a failed call's result, for its audit event
+fn error_text(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .flatten()
+        .filter_map(|content| match &content.raw {
+            RawContent::Text(text) => Some(text.text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn connect(server: &DownstreamServer) -> Result<Connection> {
+    let (service, child) = match &server.transport {
+        DownstreamTransport::Command { command, args, env } => {
+            let mut child = Command::new(command)
+                .args(args)
+                .envs(env)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start {command}"))?;
+            let stdout = child.stdout.take().context("No stdout")?;
+            let stdin = child.stdin.take().context("No stdin")?;
+            (().serve((stdout, stdin)).await?, Some(child))
+        }
+        DownstreamTransport::StreamableHttp { url, headers } => {
+            let mut default_headers = reqwest::header::HeaderMap::new();
+            for (name, value) in headers {
+                default_headers.insert(
+                    reqwest::header::HeaderName::try_from(name.as_str())
+                        .with_context(|| format!("Invalid header name {name}"))?,
+                    reqwest::header::HeaderValue::try_from(value.as_str())
+                        .with_context(|| format!("Invalid value of header {name}"))?,
+                );
+            }
+            let client = reqwest::Client::builder()
+                .default_headers(default_headers)
+                .build()?;
+            let transport = StreamableHttpClientTransport::with_client(
+                client,
+                StreamableHttpClientTransportConfig::with_uri(url.as_str()),
+            );
+            (().serve(transport).await?, None)
+        }
+    };
+    import_tools(service, child, server.tools.as_deref()).await
+}
+
+/// The connection of `service` with the tools of `allowed` it lists, or every tool
+async fn import_tools(
+    service: RunningService<RoleClient, ()>,
+    child: Option<Child>,
+    allowed: Option<&[String]>,
+) -> Result<Connection> {
+    let mut tools = service.list_all_tools().await?;
+    if let Some(allowed) = allowed {
+        tools.retain(|tool| allowed.iter().any(|name| name == &tool.name));
+    }
+    Ok(Connection {
+        service,
+        tools,
+        _child: child,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rmcp::model::{CallToolResult, Content, ErrorData, ListToolsResult, PaginatedRequestParam};
+    use rmcp::service::RequestContext;
+    use rmcp::{RoleServer, ServerHandler};
+    use serde_json::json;
+    use wassette::Event;
+
+    use super::*;
+
+    struct Echo;
+
+    impl ServerHandler for Echo {
+        async fn list_tools(
+            &self,
+            _request: Option<PaginatedRequestParam>,
+            _context: RequestContext<RoleServer>,
+        ) -> Result<ListToolsResult, ErrorData> {
+            let schema = Arc::new(json!({ "type": "object" }).as_object().unwrap().clone());
+            Ok(ListToolsResult {
+                tools: vec![
+                    Tool::new("echo", "Echoes its arguments", schema.clone()),
+                    Tool::new("fail", "Always fails", schema),
+                ],
+                next_cursor: None,
+            })
+        }
+
+        async fn call_tool(
+            &self,
+            request: CallToolRequestParam,
+            _context: RequestContext<RoleServer>,
+        ) -> Result<CallToolResult, ErrorData> {
+            Ok(match request.name.as_ref() {
+                "echo" => CallToolResult::success(vec![Content::text(
+                    serde_json::to_string(&request.arguments).unwrap(),
+                )]),
+                _ => CallToolResult::error(vec![Content::text("broken")]),
+            })
+        }
+    }
+
+    async fn echo_downstreams(tools: Option<&[String]>) -> Result<Downstreams> {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            if let Ok(service) = Echo.serve(tokio::io::split(server)).await {
+                let _ = service.waiting().await;
+            }
+        });
+        let service = ().serve(tokio::io::split(client)).await?;
+        Ok(Downstreams {
+            connections: Arc::new(HashMap::from([(
+                "echoer".to_string(),
+                import_tools(service, None, tools).await?,
+            )])),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_proxied_calls() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        let mut events = manager.subscribe_events();
+        let downstreams = echo_downstreams(None).await?;
+
+        let names = downstreams
+            .tools()
+            .into_iter()
+            .map(|tool| tool.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["echoer__echo", "echoer__fail"]);
+        assert_eq!(downstreams.server_of("echoer__echo"), Some("echoer"));
+        assert_eq!(downstreams.server_of("echoer__missing"), None);
+        assert_eq!(downstreams.server_of("other__echo"), None);
+
+        let result = downstreams
+            .call(
+                &CallToolRequestParam {
+                    name: "echoer__echo".into(),
+                    arguments: json!({ "text": "hi" }).as_object().cloned(),
+                },
+                &manager,
+            )
+            .await?;
+        assert_eq!(
+            result.content.as_ref().unwrap()[0].as_text().unwrap().text,
+            r#"{"text":"hi"}"#
+        );
+        match events.recv().await? {
+            Event::ProxiedToolInvoked {
+                server,
+                tool,
+                success,
+                ..
+            } => {
+                assert_eq!((server.as_str(), tool.as_str()), ("echoer", "echo"));
+                assert!(success);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let result = downstreams
+            .call(
+                &CallToolRequestParam {
+                    name: "echoer__fail".into(),
+                    arguments: None,
+                },
+                &manager,
+            )
+            .await?;
+        assert_eq!(result.is_error, Some(true));
+        match events.recv().await? {
+            Event::ProxiedToolInvoked { success, error, .. } => {
+                assert!(!success);
+                assert_eq!(error.as_deref(), Some("broken"));
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let filtered = echo_downstreams(Some(&["echo".to_string()])).await?;
+        assert_eq!(filtered.tools().len(), 1);
+        assert!(filtered.server_of("echoer__fail").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_validates_names() {
+        let server = |name: &str| DownstreamServer {
+            name: name.to_string(),
+            transport: DownstreamTransport::StreamableHttp {
+                url: "http://127.0.0.1:1/mcp".to_string(),
+                headers: HashMap::new(),
+            },
+            tools: None,
+        };
+        assert!(Downstreams::connect(&[server("a__b")]).await.is_err());
+        assert!(Downstreams::connect(&[server("")]).await.is_err());
+        // Unreachable servers are left out
+        let downstreams = Downstreams::connect(&[server("offline")]).await.unwrap();
+        assert!(downstreams.is_empty());
+    }
+}
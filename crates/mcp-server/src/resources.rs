@@ -1,14 +1,652 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use anyhow::Result;
-use rmcp::model::{ListResourcesRequest, ListResourcesResult};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 
-pub async fn handle_resources_list(req: serde_json::Value) -> Result<serde_json::Value> {
-    let _parsed_req: ListResourcesRequest = serde_json::from_value(req)?;
+use anyhow::{anyhow, bail, Context, Result};
+use rmcp::model::{
+    AnnotateAble, ListResourceTemplatesResult, ListResourcesResult, RawResource,
+    RawResourceTemplate, ReadResourceRequestParam, ReadResourceResult, Resource, ResourceContents,
+    ResourceTemplate, ResourceUpdatedNotificationParam,
+};
+use rmcp::{Peer, RoleServer};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+use wassette::{ComponentNotFound, Event, LifecycleManager};
+
+/// Prefix of the URIs under which full tool results are kept
+pub const RESULT_URI_PREFIX: &str = "wassette://results/";
+
+/// Prefix of the URIs of the WIT, schema, policy, audit log and profiles of each loaded
+/// component, e.g. `wassette://components/fetch-rs/wit`
+pub const COMPONENT_URI_PREFIX: &str = "wassette://components/";
+
+/// Prefix of the URIs of the guest profiles of calls, e.g.
+/// `wassette://profiles/fetch-rs/1749000000123-fetch.json`
+pub const PROFILE_URI_PREFIX: &str = "wassette://profiles/";
+
+/// The variable that stands for the component ID in the component resource templates
+pub const COMPONENT_URI_TEMPLATE_ARGUMENT: &str = "component_id";
+
+/// How many of the most recent network requests the audit log resource of a component shows
+const AUDIT_EXCERPT_LIMIT: usize = 100;
+
+/// How many full results are kept before the oldest is dropped
+const RESULT_STORE_CAPACITY: usize = 64;
+
+struct StoredResult {
+    uri: String,
+    tool: String,
+    text: String,
+}
+
+#[derive(Default)]
+struct ResultStoreInner {
+    next_id: u64,
+    results: VecDeque<StoredResult>,
+}
+
+/// Full tool results that were summarized or truncated, readable as MCP resources
+#[derive(Clone, Default)]
+pub struct ResultStore {
+    inner: Arc<Mutex<ResultStoreInner>>,
+}
+
+impl std::fmt::Debug for ResultStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().expect("result store lock poisoned");
+        f.debug_struct("ResultStore")
+            .field("results", &inner.results.len())
+            .finish()
+    }
+}
+
+impl ResultStore {
+    /// Keeps the full result of a `tool` call and returns its URI
+    pub fn insert(&self, tool: &str, text: String) -> String {
+        let mut inner = self.inner.lock().expect("result store lock poisoned");
+        inner.next_id += 1;
+        let uri = format!("{RESULT_URI_PREFIX}{}", inner.next_id);
+        if inner.results.len() == RESULT_STORE_CAPACITY {
+            inner.results.pop_front();
+        }
+        inner.results.push_back(StoredResult {
+            uri: uri.clone(),
+            tool: tool.to_string(),
+            text,
+        });
+        uri
+    }
+
+    /// Returns the full result stored under `uri`, if it hasn't been dropped yet
+    pub fn get(&self, uri: &str) -> Option<String> {
+        let inner = self.inner.lock().expect("result store lock poisoned");
+        inner
+            .results
+            .iter()
+            .find(|result| result.uri == uri)
+            .map(|result| result.text.clone())
+    }
+}
+
+/// The resources one client subscribed to with `resources/subscribe`
+#[derive(Clone, Debug, Default)]
+pub struct ResourceSubscriptions {
+    uris: Arc<Mutex<HashSet<String>>>,
+}
+
+impl ResourceSubscriptions {
+    /// Subscribes to the resource with the given URI. Components don't have to be loaded yet,
+    /// but the URI must name a resource this server can serve.
+    pub fn subscribe(&self, uri: &str) -> Result<()> {
+        if ComponentResource::parse(uri).is_none() && !uri.starts_with(RESULT_URI_PREFIX) {
+            bail!("Unknown resource: {uri}");
+        }
+        self.lock().insert(uri.to_string());
+        Ok(())
+    }
+
+    /// Removes a subscription. Unsubscribing from a resource that wasn't subscribed does nothing.
+    pub fn unsubscribe(&self, uri: &str) {
+        self.lock().remove(uri);
+    }
+
+    /// Whether the client subscribed to the resource with the given URI
+    pub fn contains(&self, uri: &str) -> bool {
+        self.lock().contains(uri)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.uris.lock().expect("subscriptions lock poisoned")
+    }
+}
+
+/// The URIs of the resources whose content changes with `event`
+pub fn updated_resource_uris(event: &Event) -> Vec<String> {
+    let (component_id, resources): (&str, &[ComponentResource]) = match event {
+        Event::ComponentLoaded { component_id, .. } | Event::ComponentRestored { component_id } => {
+            (
+                component_id,
+                &[
+                    ComponentResource::Wit,
+                    ComponentResource::Schema,
+                    ComponentResource::Policy,
+                ],
+            )
+        }
+        Event::PolicyAttached { component_id, .. }
+        | Event::PolicyDetached { component_id }
+        | Event::PermissionGranted { component_id, .. }
+        | Event::PermissionRevoked { component_id, .. }
+        | Event::PermissionsReset { component_id } => (component_id, &[ComponentResource::Policy]),
+        Event::NetworkRequest(request) => (&request.component_id, &[ComponentResource::Audit]),
+        _ => return Vec::new(),
+    };
+    resources
+        .iter()
+        .map(|resource| resource.uri(component_id))
+        .collect()
+}
+
+/// Sends `notifications/resources/updated` to `server_peer` whenever a resource in
+/// `subscriptions` changes, until the client goes away
+pub async fn forward_resource_updates(
+    lifecycle_manager: LifecycleManager,
+    server_peer: Peer<RoleServer>,
+    subscriptions: ResourceSubscriptions,
+) {
+    let mut events = lifecycle_manager.subscribe_events();
+    // A session view kept by the task would keep the session's components loaded
+    drop(lifecycle_manager);
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                for uri in updated_resource_uris(&event) {
+                    if !subscriptions.contains(&uri) {
+                        continue;
+                    }
+                    let notification = ResourceUpdatedNotificationParam { uri };
+                    if let Err(e) = server_peer.notify_resource_updated(notification).await {
+                        debug!(error = %e, "Stopped sending resource updates");
+                        return;
+                    }
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Dropped resource updates for a slow client");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// What a component resource shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComponentResource {
+    Wit,
+    Schema,
+    Policy,
+    Audit,
+    Profiles,
+}
+
+impl ComponentResource {
+    const ALL: [ComponentResource; 5] = [
+        ComponentResource::Wit,
+        ComponentResource::Schema,
+        ComponentResource::Policy,
+        ComponentResource::Audit,
+        ComponentResource::Profiles,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ComponentResource::Wit => "wit",
+            ComponentResource::Schema => "schema",
+            ComponentResource::Policy => "policy",
+            ComponentResource::Audit => "audit",
+            ComponentResource::Profiles => "profiles",
+        }
+    }
+
+    fn mime_type(self) -> &'static str {
+        match self {
+            ComponentResource::Wit => "text/plain",
+            ComponentResource::Schema | ComponentResource::Audit | ComponentResource::Profiles => {
+                "application/json"
+            }
+            ComponentResource::Policy => "application/yaml",
+        }
+    }
+
+    fn uri(self, component_id: &str) -> String {
+        format!("{COMPONENT_URI_PREFIX}{component_id}/{}", self.name())
+    }
+
+    fn parse(uri: &str) -> Option<(&str, ComponentResource)> {
+        let (component_id, name) = uri.strip_prefix(COMPONENT_URI_PREFIX)?.rsplit_once('/')?;
+        let resource = Self::ALL
+            .into_iter()
+            .find(|resource| resource.name() == name)?;
+        Some((component_id, resource))
+    }
+
+    fn resource(self, component_id: &str) -> Resource {
+        let (name, description) = match self {
+            ComponentResource::Wit => (
+                format!("WIT of {component_id}"),
+                "The WIT world the component imports and exports",
+            ),
+            ComponentResource::Schema => (
+                format!("Tool schema of {component_id}"),
+                "The JSON schemas of the tools the component exports",
+            ),
+            ComponentResource::Policy => (
+                format!("Policy of {component_id}"),
+                "The policy attached to the component, with the permissions granted at runtime",
+            ),
+            ComponentResource::Audit => (
+                format!("Audit log of {component_id}"),
+                "The most recent outgoing network requests of the component, allowed or denied",
+            ),
+            ComponentResource::Profiles => (
+                format!("Profiles of {component_id}"),
+                "The guest profiles of the most recent calls, when the component is profiled",
+            ),
+        };
+        let mut resource = RawResource::new(self.uri(component_id), name);
+        resource.description = Some(description.to_string());
+        resource.mime_type = Some(self.mime_type().to_string());
+        resource.no_annotation()
+    }
+
+    fn template(self) -> ResourceTemplate {
+        RawResourceTemplate {
+            uri_template: self.uri(&format!("{{{COMPONENT_URI_TEMPLATE_ARGUMENT}}}")),
+            name: format!("Component {}", self.name()),
+            description: Some(
+                self.resource("{component_id}")
+                    .raw
+                    .description
+                    .unwrap_or_default(),
+            ),
+            mime_type: Some(self.mime_type().to_string()),
+        }
+        .no_annotation()
+    }
+
+    async fn read(
+        self,
+        component_id: &str,
+        lifecycle_manager: &LifecycleManager,
+    ) -> Result<String> {
+        if lifecycle_manager
+            .get_component(component_id)
+            .await
+            .is_none()
+        {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        match self {
+            ComponentResource::Wit => lifecycle_manager.get_component_wit(component_id).await,
+            ComponentResource::Schema => {
+                let schema = lifecycle_manager
+                    .get_component_schema(component_id)
+                    .await
+                    .ok_or_else(|| ComponentNotFound::Component(component_id.to_string()))?;
+                Ok(serde_json::to_string_pretty(&schema)?)
+            }
+            ComponentResource::Policy => {
+                let info = lifecycle_manager
+                    .get_policy_info(component_id)
+                    .await
+                    .ok_or_else(|| anyhow!("Component {} has no policy", component_id))?;
+                tokio::fs::read_to_string(&info.local_path)
+                    .await
+                    .with_context(|| format!("Failed to read policy of {component_id}"))
+            }
+            ComponentResource::Audit => Ok(serde_json::to_string_pretty(
+                &lifecycle_manager.network_activity(Some(component_id), AUDIT_EXCERPT_LIMIT),
+            )?),
+            ComponentResource::Profiles => {
+                let profiles = lifecycle_manager
+                    .profiles(component_id)
+                    .await?
+                    .into_iter()
+                    .map(|profile| {
+                        let mut value = serde_json::to_value(&profile)?;
+                        value["uri"] = profile_uri(component_id, &profile.name).into();
+                        Ok(value)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(serde_json::to_string_pretty(&profiles)?)
+            }
+        }
+    }
+}
+
+/// The URI of a guest profile of a component
+fn profile_uri(component_id: &str, name: &str) -> String {
+    format!("{PROFILE_URI_PREFIX}{component_id}/{name}")
+}
+
+/// Reads the guest profile a `wassette://profiles/` URI names, or returns `None` for any other URI
+async fn read_profile(uri: &str, lifecycle_manager: &LifecycleManager) -> Option<Result<String>> {
+    let (component_id, name) = uri.strip_prefix(PROFILE_URI_PREFIX)?.split_once('/')?;
+    Some(lifecycle_manager.read_profile(component_id, name).await)
+}
+
+pub async fn handle_resources_list(
+    results: &ResultStore,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<serde_json::Value> {
+    let mut component_ids = lifecycle_manager.list_components().await;
+    component_ids.sort();
+    let mut resources = Vec::new();
+    for component_id in &component_ids {
+        let has_policy = lifecycle_manager
+            .get_policy_info(component_id)
+            .await
+            .is_some();
+        resources.extend(
+            ComponentResource::ALL
+                .into_iter()
+                .filter(|resource| *resource != ComponentResource::Policy || has_policy)
+                .filter(|resource| {
+                    *resource != ComponentResource::Profiles
+                        || lifecycle_manager.is_profiling(component_id)
+                })
+                .map(|resource| resource.resource(component_id)),
+        );
+    }
+    {
+        let inner = results.inner.lock().expect("result store lock poisoned");
+        resources.extend(inner.results.iter().map(|result| {
+            let mut resource = RawResource::new(
+                result.uri.clone(),
+                format!("Full result of {}", result.tool),
+            );
+            resource.mime_type = Some("text/plain".to_string());
+            resource.size = u32::try_from(result.text.len()).ok();
+            resource.no_annotation()
+        }));
+    }
     let response = ListResourcesResult {
-        resources: vec![],
+        resources,
         next_cursor: None,
     };
     Ok(serde_json::to_value(response)?)
 }
+
+/// The templates of the component resources, so clients can complete the component ID
+pub fn handle_resource_templates_list() -> Result<serde_json::Value> {
+    let response = ListResourceTemplatesResult {
+        resource_templates: ComponentResource::ALL
+            .into_iter()
+            .map(ComponentResource::template)
+            .collect(),
+        next_cursor: None,
+    };
+    Ok(serde_json::to_value(response)?)
+}
+
+pub async fn handle_resources_read(
+    req: ReadResourceRequestParam,
+    results: &ResultStore,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<serde_json::Value> {
+    let (mime_type, text) = match ComponentResource::parse(&req.uri) {
+        Some((component_id, resource)) => (
+            resource.mime_type(),
+            resource.read(component_id, lifecycle_manager).await?,
+        ),
+        None => match read_profile(&req.uri, lifecycle_manager).await {
+            Some(profile) => ("application/json", profile?),
+            None => (
+                "text/plain",
+                results
+                    .get(&req.uri)
+                    .ok_or_else(|| anyhow!("Resource not found: {}", req.uri))?,
+            ),
+        },
+    };
+    let response = ReadResourceResult {
+        contents: vec![ResourceContents::TextResourceContents {
+            uri: req.uri,
+            mime_type: Some(mime_type.to_string()),
+            text,
+        }],
+    };
+    Ok(serde_json::to_value(response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = r#"(component
+      (core module $m (func (export "run") (result i32) i32.const 42))
+      (core instance $i (instantiate $m))
+      (func $run (result u32) (canon lift (core func $i "run")))
+      (export "run" (func $run)))"#;
+
+    #[tokio::test]
+    async fn test_result_store() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let manager = LifecycleManager::new(&tempdir).await.unwrap();
+        let results = ResultStore::default();
+        let first = results.insert("fetch", "full output".to_string());
+        assert_eq!(first, "wassette://results/1");
+        assert_eq!(results.get(&first).as_deref(), Some("full output"));
+
+        let list = handle_resources_list(&results, &manager).await.unwrap();
+        assert_eq!(list["resources"][0]["uri"], "wassette://results/1");
+        assert_eq!(list["resources"][0]["name"], "Full result of fetch");
+
+        let read = handle_resources_read(
+            ReadResourceRequestParam { uri: first.clone() },
+            &results,
+            &manager,
+        )
+        .await
+        .unwrap();
+        assert_eq!(read["contents"][0]["text"], "full output");
+
+        // The oldest results are dropped once the store is full
+        for _ in 0..RESULT_STORE_CAPACITY {
+            results.insert("fetch", String::new());
+        }
+        assert_eq!(results.get(&first), None);
+        assert!(
+            handle_resources_read(ReadResourceRequestParam { uri: first }, &results, &manager)
+                .await
+                .is_err()
+        );
+    }
+
+    async fn read(uri: &str, manager: &LifecycleManager) -> Result<serde_json::Value> {
+        handle_resources_read(
+            ReadResourceRequestParam {
+                uri: uri.to_string(),
+            },
+            &ResultStore::default(),
+            manager,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_component_resources() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("answer.wasm"), ANSWER)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        let list = handle_resources_list(&ResultStore::default(), &manager).await?;
+        let uris = list["resources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|resource| resource["uri"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uris,
+            vec![
+                "wassette://components/answer/wit",
+                "wassette://components/answer/schema",
+                "wassette://components/answer/audit",
+            ]
+        );
+
+        let wit = read("wassette://components/answer/wit", &manager).await?;
+        assert!(wit["contents"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("export run: func() -> u32;"));
+        let schema = read("wassette://components/answer/schema", &manager).await?;
+        assert_eq!(schema["contents"][0]["mimeType"], "application/json");
+        assert!(schema["contents"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("\"run\""));
+        let audit = read("wassette://components/answer/audit", &manager).await?;
+        assert_eq!(audit["contents"][0]["text"], "[]");
+
+        // The policy resource appears once permissions are granted
+        manager
+            .grant_permission(
+                "answer",
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+        let list = handle_resources_list(&ResultStore::default(), &manager).await?;
+        assert_eq!(
+            list["resources"][2]["uri"],
+            "wassette://components/answer/policy"
+        );
+        let policy = read("wassette://components/answer/policy", &manager).await?;
+        assert!(policy["contents"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("api.example.com"));
+
+        assert!(read("wassette://components/missing/wit", &manager)
+            .await
+            .is_err());
+        assert!(read("wassette://components/answer/other", &manager)
+            .await
+            .is_err());
+
+        let templates = handle_resource_templates_list()?;
+        assert_eq!(
+            templates["resourceTemplates"][2]["uriTemplate"],
+            "wassette://components/{component_id}/policy"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profile_resources() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("answer.wasm"), ANSWER)?;
+        let manager = LifecycleManager::new(&tempdir)
+            .await?
+            .with_profiling(wassette::ProfilingConfig::new(["answer"]));
+        manager
+            .execute_component_call("answer", "run", "{}")
+            .await?;
+
+        let list = handle_resources_list(&ResultStore::default(), &manager).await?;
+        assert_eq!(
+            list["resources"][3]["uri"],
+            "wassette://components/answer/profiles"
+        );
+        let profiles = read("wassette://components/answer/profiles", &manager).await?;
+        let profiles: serde_json::Value =
+            serde_json::from_str(profiles["contents"][0]["text"].as_str().unwrap())?;
+        assert_eq!(profiles[0]["tool"], "run");
+        let uri = profiles[0]["uri"].as_str().unwrap();
+        assert!(uri.starts_with("wassette://profiles/answer/"));
+
+        let profile = read(uri, &manager).await?;
+        assert_eq!(profile["contents"][0]["mimeType"], "application/json");
+        assert!(profile["contents"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("\"meta\""));
+        assert!(read("wassette://profiles/answer/missing.json", &manager)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resource_subscriptions() {
+        let subscriptions = ResourceSubscriptions::default();
+        subscriptions
+            .subscribe("wassette://components/fetch/policy")
+            .unwrap();
+        subscriptions.subscribe("wassette://results/3").unwrap();
+        assert!(subscriptions
+            .subscribe("wassette://components/fetch/other")
+            .is_err());
+        assert!(subscriptions.subscribe("file:///etc/passwd").is_err());
+        assert!(subscriptions.contains("wassette://components/fetch/policy"));
+
+        // Sessions don't share subscriptions
+        assert!(!ResourceSubscriptions::default().contains("wassette://results/3"));
+
+        subscriptions.unsubscribe("wassette://components/fetch/policy");
+        subscriptions.unsubscribe("wassette://components/fetch/wit");
+        assert!(!subscriptions.contains("wassette://components/fetch/policy"));
+    }
+
+    #[test]
+    fn test_updated_resource_uris() {
+        assert_eq!(
+            updated_resource_uris(&Event::ComponentLoaded {
+                component_id: "fetch".to_string(),
+                replaced: true,
+            }),
+            vec![
+                "wassette://components/fetch/wit",
+                "wassette://components/fetch/schema",
+                "wassette://components/fetch/policy",
+            ]
+        );
+        assert_eq!(
+            updated_resource_uris(&Event::PermissionGranted {
+                component_id: "fetch".to_string(),
+                permission_type: "network".to_string(),
+                details: serde_json::json!({"host": "example.com"}),
+            }),
+            vec!["wassette://components/fetch/policy"]
+        );
+        let request = wassette::NetworkRequest {
+            component_id: "fetch".to_string(),
+            method: "GET".to_string(),
+            host: "example.com".to_string(),
+            path: "/".to_string(),
+            status: Some(200),
+            bytes: 0,
+            duration_ms: 1,
+            cached: false,
+            error: None,
+            time: "2025-01-01T00:00:00Z".to_string(),
+        };
+        assert_eq!(
+            updated_resource_uris(&Event::NetworkRequest(request)),
+            vec!["wassette://components/fetch/audit"]
+        );
+        assert!(updated_resource_uris(&Event::ToolInvoked {
+            component_id: "fetch".to_string(),
+            function_name: "fetch".to_string(),
+            duration_ms: 1,
+            success: true,
+            error: None,
+            callers: Vec::new(),
+        })
+        .is_empty());
+    }
+}
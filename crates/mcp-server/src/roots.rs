@@ -0,0 +1,483 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Storage grants for the roots of MCP clients
+//!
+//! Clients can tell the server which directories the user works in as roots. With the
+//! `grant-root-access` tool, the user can let a component use them instead of granting each path
+//! by hand. The tool first shows what it would grant, and only grants it when it is called again
+//! with `confirm`, so the model has to ask the user in between. The grants follow the roots: when
+//! the client sends `notifications/roots/list_changed`, the component gets the new roots and loses
+//! the ones that are gone.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rmcp::model::{CallToolRequestParam, CallToolResult, Content, Root};
+use rmcp::{Peer, RoleServer};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::{info, instrument, warn};
+use wassette::{ComponentNotFound, LifecycleManager};
+
+use crate::components::extract_args_from_request;
+
+/// Whether the client behind `peer` can list its roots
+pub fn supports_roots(peer: &Peer<RoleServer>) -> bool {
+    peer.peer_info()
+        .is_some_and(|info| info.capabilities.roots.is_some())
+}
+
+/// Asks the client behind `peer` for its roots. Clients without the `roots` capability have
+/// none.
+pub async fn list_roots(peer: &Peer<RoleServer>) -> Result<Vec<Root>> {
+    if !supports_roots(peer) {
+        return Ok(Vec::new());
+    }
+    Ok(peer
+        .list_roots()
+        .await
+        .context("Failed to list the roots of the client")?
+        .roots)
+}
+
+/// The `fs://` storage URI of a `file://` root, e.g. `fs:///home/me/project` for
+/// `file:///home/me/project`. Roots with other schemes have none.
+pub fn storage_uri(root_uri: &str) -> Option<String> {
+    let rest = root_uri.strip_prefix("file://")?;
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+    let path = percent_decode(path)?;
+    let path = match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    };
+    Some(format!("fs://{path}"))
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The roots a component may use and what was granted to it for them
+#[derive(Debug)]
+struct ComponentRoots {
+    /// Access to every root, e.g. `["read", "write"]`
+    access: Vec<String>,
+    /// The root URIs the user picked, or `None` for all roots, including future ones
+    selected: Option<BTreeSet<String>>,
+    /// The storage URIs granted for roots. Roots the policy already granted are not included, so
+    /// they are never revoked here.
+    granted: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct RootState {
+    /// The roots of the client, once they were listed
+    roots: Option<Vec<Root>>,
+    components: BTreeMap<String, ComponentRoots>,
+}
+
+/// The storage URIs a sync granted and revoked
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RootChanges {
+    /// Newly granted storage URIs
+    pub granted: Vec<String>,
+    /// Revoked storage URIs
+    pub revoked: Vec<String>,
+}
+
+/// The roots of one client session and the components that may use them
+#[derive(Debug, Clone, Default)]
+pub struct RootGrants {
+    state: Arc<Mutex<RootState>>,
+}
+
+impl RootGrants {
+    /// The roots of the client, asking it the first time
+    pub async fn roots(&self, server_peer: Option<&Peer<RoleServer>>) -> Result<Vec<Root>> {
+        let mut state = self.state.lock().await;
+        if let Some(roots) = &state.roots {
+            return Ok(roots.clone());
+        }
+        let server_peer =
+            server_peer.ok_or_else(|| anyhow!("Roots are only available to MCP clients"))?;
+        let roots = list_roots(server_peer).await?;
+        state.roots = Some(roots.clone());
+        Ok(roots)
+    }
+
+    /// Lets `component_id` use the roots in `selected`, or all roots, with `access`, and
+    /// grants them right away
+    pub async fn grant(
+        &self,
+        lifecycle_manager: &LifecycleManager,
+        component_id: &str,
+        access: Vec<String>,
+        selected: Option<BTreeSet<String>>,
+    ) -> Result<RootChanges> {
+        let mut state = self.state.lock().await;
+        let roots = state.roots.clone().unwrap_or_default();
+        let mut entry = state
+            .components
+            .remove(component_id)
+            .unwrap_or(ComponentRoots {
+                access: Vec::new(),
+                selected: None,
+                granted: BTreeSet::new(),
+            });
+        let access_changed = entry.access != access;
+        entry.access = access;
+        entry.selected = selected;
+        if access_changed {
+            // Grants merge access, so fewer access types take a new grant
+            for uri in std::mem::take(&mut entry.granted) {
+                lifecycle_manager
+                    .revoke_storage_permission_by_uri(component_id, &uri)
+                    .await?;
+            }
+        }
+        let changes = apply(lifecycle_manager, component_id, &mut entry, &roots).await;
+        state.components.insert(component_id.to_string(), entry);
+        changes
+    }
+
+    /// Revokes everything that was granted to `component_id` for roots, and stops following
+    /// the roots for it
+    pub async fn revoke(
+        &self,
+        lifecycle_manager: &LifecycleManager,
+        component_id: &str,
+    ) -> Result<Vec<String>> {
+        let entry = self
+            .state
+            .lock()
+            .await
+            .components
+            .remove(component_id)
+            .ok_or_else(|| anyhow!("Component {} has no root access", component_id))?;
+        for uri in &entry.granted {
+            lifecycle_manager
+                .revoke_storage_permission_by_uri(component_id, uri)
+                .await?;
+        }
+        Ok(entry.granted.into_iter().collect())
+    }
+
+    /// Updates the grants of every component with root access to the new `roots` of the client
+    pub async fn sync(&self, lifecycle_manager: &LifecycleManager, roots: Vec<Root>) {
+        let mut state = self.state.lock().await;
+        let loaded = lifecycle_manager
+            .list_components()
+            .await
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let mut components = std::mem::take(&mut state.components);
+        // Unloaded components lose their root access
+        components.retain(|component_id, _| loaded.contains(component_id));
+        for (component_id, entry) in components.iter_mut() {
+            match apply(lifecycle_manager, component_id, entry, &roots).await {
+                Ok(changes) if changes != RootChanges::default() => info!(
+                    component_id,
+                    granted = ?changes.granted,
+                    revoked = ?changes.revoked,
+                    "Updated root access"
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(component_id, error = %e, "Failed to update root access"),
+            }
+        }
+        state.components = components;
+        state.roots = Some(roots);
+    }
+}
+
+/// Grants the storage URIs of the roots `entry` selects and revokes the ones granted for roots
+/// that are gone
+async fn apply(
+    lifecycle_manager: &LifecycleManager,
+    component_id: &str,
+    entry: &mut ComponentRoots,
+    roots: &[Root],
+) -> Result<RootChanges> {
+    let wanted = roots
+        .iter()
+        .filter(|root| {
+            entry
+                .selected
+                .as_ref()
+                .is_none_or(|selected| selected.contains(&root.uri))
+        })
+        .filter_map(|root| storage_uri(&root.uri))
+        .collect::<BTreeSet<_>>();
+    let existing = lifecycle_manager
+        .get_storage_grants(component_id)
+        .await?
+        .into_iter()
+        .map(|grant| grant.uri)
+        .collect::<BTreeSet<_>>();
+
+    let mut changes = RootChanges::default();
+    for uri in entry.granted.clone() {
+        if wanted.contains(&uri) {
+            continue;
+        }
+        if existing.contains(&uri) {
+            lifecycle_manager
+                .revoke_storage_permission_by_uri(component_id, &uri)
+                .await?;
+        }
+        entry.granted.remove(&uri);
+        changes.revoked.push(uri);
+    }
+    for uri in wanted {
+        if entry.granted.contains(&uri) || existing.contains(&uri) {
+            continue;
+        }
+        lifecycle_manager
+            .grant_permission(
+                component_id,
+                "storage",
+                &json!({ "uri": uri, "access": entry.access }),
+            )
+            .await?;
+        entry.granted.insert(uri.clone());
+        changes.granted.push(uri);
+    }
+    Ok(changes)
+}
+
+fn text_result(value: Value) -> Result<CallToolResult> {
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(serde_json::to_string(&value)?)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+#[instrument(skip(lifecycle_manager, server_peer, root_grants))]
+pub async fn handle_grant_root_access(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    server_peer: Option<&Peer<RoleServer>>,
+    root_grants: &RootGrants,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+
+    let component_id = args
+        .get("component_id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing required argument: 'component_id'"))?;
+    if lifecycle_manager
+        .get_component(component_id)
+        .await
+        .is_none()
+    {
+        return Err(ComponentNotFound::Component(component_id.to_string()).into());
+    }
+
+    let access = match args.get("access") {
+        None | Some(Value::Null) => vec!["read".to_string()],
+        Some(Value::Array(access)) => access
+            .iter()
+            .map(|access| match access.as_str() {
+                Some(access @ ("read" | "write")) => Ok(access.to_string()),
+                _ => Err(anyhow!("'access' must only contain \"read\" and \"write\"")),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(_) => bail!("'access' must be an array"),
+    };
+    if access.is_empty() {
+        bail!("'access' must not be empty");
+    }
+
+    let roots = root_grants.roots(server_peer).await?;
+    let selected = match args.get("roots") {
+        None | Some(Value::Null) => None,
+        Some(Value::Array(selected)) => Some(
+            selected
+                .iter()
+                .map(|uri| {
+                    let uri = uri
+                        .as_str()
+                        .ok_or_else(|| anyhow!("'roots' must be an array of root URIs"))?;
+                    if !roots.iter().any(|root| root.uri == uri) {
+                        bail!("The client has no root {}", uri);
+                    }
+                    Ok(uri.to_string())
+                })
+                .collect::<Result<BTreeSet<_>>>()?,
+        ),
+        Some(_) => bail!("'roots' must be an array of root URIs"),
+    };
+
+    let grants = roots
+        .iter()
+        .filter(|root| {
+            selected
+                .as_ref()
+                .is_none_or(|selected| selected.contains(&root.uri))
+        })
+        .filter_map(|root| {
+            storage_uri(&root.uri).map(|uri| {
+                json!({
+                    "root": root.uri,
+                    "name": root.name,
+                    "uri": uri,
+                    "access": access,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    if grants.is_empty() {
+        bail!("The client has not shared any file:// roots");
+    }
+
+    if !args
+        .get("confirm")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+    {
+        return text_result(json!({
+            "status": "confirmation required",
+            "component_id": component_id,
+            "grants": grants,
+            "message": format!(
+                "Ask the user whether {component_id} may access these roots. Only if they agree, \
+                 call grant-root-access again with the same arguments and confirm set to true."
+            ),
+        }));
+    }
+
+    info!(component_id, ?access, "Granting root access");
+    let changes = root_grants
+        .grant(lifecycle_manager, component_id, access, selected)
+        .await
+        .with_context(|| format!("Failed to grant root access to component {component_id}"))?;
+    text_result(json!({
+        "status": "root access granted",
+        "component_id": component_id,
+        "grants": grants,
+        "granted": changes.granted,
+        "message": "The grants follow the roots of the client until revoke-root-access is called",
+    }))
+}
+
+#[instrument(skip(lifecycle_manager, root_grants))]
+pub async fn handle_revoke_root_access(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    root_grants: &RootGrants,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+
+    let component_id = args
+        .get("component_id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Missing required argument: 'component_id'"))?;
+
+    info!(component_id, "Revoking root access");
+    let revoked = root_grants.revoke(lifecycle_manager, component_id).await?;
+    text_result(json!({
+        "status": "root access revoked",
+        "component_id": component_id,
+        "revoked": revoked,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_uri() {
+        assert_eq!(
+            storage_uri("file:///home/me/project/").as_deref(),
+            Some("fs:///home/me/project")
+        );
+        assert_eq!(
+            storage_uri("file://localhost/My%20Files").as_deref(),
+            Some("fs:///My Files")
+        );
+        assert_eq!(storage_uri("file:///").as_deref(), Some("fs:///"));
+        assert_eq!(storage_uri("https://example.com/repo"), None);
+        assert_eq!(storage_uri("file://server/share"), None);
+        assert_eq!(storage_uri("file:///bad%2"), None);
+    }
+
+    fn root(uri: &str) -> Root {
+        Root {
+            uri: uri.to_string(),
+            name: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_root_grants_follow_roots() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("files.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 0))
+              (core instance $i (instantiate $m))
+              (func $run (result u32) (canon lift (core func $i "run")))
+              (export "run" (func $run)))"#,
+        )?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        let grants = RootGrants::default();
+        let uris = |manager: LifecycleManager| async move {
+            manager
+                .get_storage_grants("files")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|grant| grant.uri)
+                .collect::<Vec<_>>()
+        };
+
+        // Without an MCP client there are no roots
+        assert!(grants.roots(None).await.is_err());
+
+        // A root the policy already grants is never revoked here
+        manager
+            .grant_permission(
+                "files",
+                "storage",
+                &json!({"uri": "fs:///b", "access": ["read"]}),
+            )
+            .await?;
+        grants
+            .sync(&manager, vec![root("file:///a"), root("file:///b")])
+            .await;
+        let changes = grants
+            .grant(&manager, "files", vec!["read".to_string()], None)
+            .await?;
+        assert_eq!(changes.granted, vec!["fs:///a"]);
+        assert_eq!(uris(manager.clone()).await, vec!["fs:///b", "fs:///a"]);
+
+        grants
+            .sync(&manager, vec![root("file:///b"), root("file:///c")])
+            .await;
+        assert_eq!(uris(manager.clone()).await, vec!["fs:///b", "fs:///c"]);
+
+        assert_eq!(grants.revoke(&manager, "files").await?, vec!["fs:///c"]);
+        assert_eq!(uris(manager.clone()).await, vec!["fs:///b"]);
+        assert!(grants.revoke(&manager, "files").await.is_err());
+        Ok(())
+    }
+}
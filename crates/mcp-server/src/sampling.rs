@@ -0,0 +1,122 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Completions for components through the MCP client
+//!
+//! Components with `permissions.sampling` in their policy can import `wassette:ai/sampling`.
+//! Their requests are sent to the client that called the tool as `sampling/createMessage`, so
+//! the client's model answers them and the client stays in control of what is sent to it.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rmcp::model::{Content, CreateMessageRequestParam, Role, SamplingMessage};
+use rmcp::{Peer, RoleServer};
+use serde_json::json;
+use wassette::{Sampler, SamplingRequest, SamplingResponse, SamplingRole};
+
+use crate::summarize::supports_sampling;
+
+/// Sends the sampling requests of components to an MCP client
+#[derive(Debug, Clone)]
+pub struct ClientSampler {
+    peer: Peer<RoleServer>,
+}
+
+impl ClientSampler {
+    /// A sampler that asks the client behind `peer`
+    pub fn new(peer: Peer<RoleServer>) -> Self {
+        Self { peer }
+    }
+}
+
+/// The `sampling/createMessage` parameters for a component's request
+pub fn create_message_param(request: SamplingRequest) -> CreateMessageRequestParam {
+    CreateMessageRequestParam {
+        messages: request
+            .messages
+            .into_iter()
+            .map(|message| SamplingMessage {
+                role: match message.role {
+                    SamplingRole::User => Role::User,
+                    SamplingRole::Assistant => Role::Assistant,
+                },
+                content: Content::text(message.content),
+            })
+            .collect(),
+        model_preferences: None,
+        system_prompt: request.system_prompt,
+        include_context: None,
+        temperature: request.temperature,
+        max_tokens: request.max_tokens,
+        stop_sequences: None,
+        // Lets the client tell the user which component is asking
+        metadata: Some(json!({ "component_id": request.component_id })),
+    }
+}
+
+impl Sampler for ClientSampler {
+    fn create_message(
+        &self,
+        request: SamplingRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<SamplingResponse>> + Send + '_>> {
+        Box::pin(async move {
+            if !supports_sampling(&self.peer) {
+                bail!("The MCP client does not support sampling");
+            }
+            let result = self
+                .peer
+                .create_message(create_message_param(request))
+                .await
+                .context("Sampling request failed")?;
+            let content = result
+                .message
+                .content
+                .as_text()
+                .map(|content| content.text.clone())
+                .ok_or_else(|| anyhow!("Client returned a completion that is not text"))?;
+            Ok(SamplingResponse {
+                content,
+                model: result.model,
+                stop_reason: result.stop_reason,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wassette::SamplingMessage as ComponentMessage;
+
+    use super::*;
+
+    #[test]
+    fn test_create_message_param() {
+        let param = create_message_param(SamplingRequest {
+            component_id: "fetch".to_string(),
+            messages: vec![
+                ComponentMessage {
+                    role: SamplingRole::User,
+                    content: "Summarize this page".to_string(),
+                },
+                ComponentMessage {
+                    role: SamplingRole::Assistant,
+                    content: "Which page?".to_string(),
+                },
+            ],
+            system_prompt: Some("Be brief".to_string()),
+            max_tokens: 100,
+            temperature: Some(0.5),
+        });
+        assert_eq!(param.messages.len(), 2);
+        assert_eq!(param.messages[1].role, Role::Assistant);
+        assert_eq!(
+            param.messages[0].content.as_text().unwrap().text,
+            "Summarize this page"
+        );
+        assert_eq!(param.system_prompt.as_deref(), Some("Be brief"));
+        assert_eq!(param.max_tokens, 100);
+        assert_eq!(param.metadata, Some(json!({ "component_id": "fetch" })));
+    }
+}
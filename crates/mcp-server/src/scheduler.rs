@@ -0,0 +1,604 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Priority and fair scheduling of tool calls under a concurrency limit
+//!
+//! With a limit on concurrent calls, calls that find every slot taken wait in a queue. When a
+//! slot frees up it goes to a waiting interactive call, so calls made by users in an MCP client
+//! don't wait behind background jobs. A background call that has waited longer than
+//! [`STARVATION_TIMEOUT`] goes first, so a steady stream of interactive calls can't hold
+//! background work back forever.
+//!
+//! Within a priority class, calls are served by start-time fair queuing across components
+//! rather than in arrival order. Each waiting call is tagged with the virtual time at which its
+//! component's previous call finishes, and the call with the lowest tag goes next. One call of a
+//! component with weight `w` takes `1/w` of virtual time, so under load each component gets a
+//! share of the slots proportional to its weight, and a burst of calls to one component can't
+//! starve the others.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::sync::oneshot;
+
+use crate::summarize::ALL_TOOLS;
+
+/// How long a background call waits before it is served ahead of interactive calls
+pub const STARVATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Queue of the calls of built-in tools, which belong to no component
+pub const BUILTIN_QUEUE: &str = "wassette";
+
+/// Virtual time one call of a component with weight 1 takes
+const VIRTUAL_CALL_TIME: u64 = 1 << 20;
+
+/// Priority class of a tool call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    /// Calls a user is waiting on, such as those from an MCP client
+    #[default]
+    Interactive,
+    /// Calls that can wait, such as scheduled jobs from the message bus
+    Background,
+}
+
+impl FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "interactive" => Ok(Self::Interactive),
+            "background" => Ok(Self::Background),
+            _ => bail!("Unknown priority class '{s}', expected interactive or background"),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Interactive => "interactive",
+            Self::Background => "background",
+        })
+    }
+}
+
+/// A `<tool>=<class>` priority, such as `report=background`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolPriority {
+    /// Tool the class applies to, or `*` for every tool
+    pub tool: String,
+    /// Priority class of the tool's calls
+    pub priority: Priority,
+}
+
+impl FromStr for ToolPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tool, priority) = s
+            .rsplit_once('=')
+            .with_context(|| format!("Tool priority must be <tool>=<class>: {s}"))?;
+        if tool.is_empty() {
+            return Err(anyhow!("Tool priority has no tool name: {s}"));
+        }
+        Ok(Self {
+            tool: tool.to_string(),
+            priority: priority.parse()?,
+        })
+    }
+}
+
+/// A `<component>=<weight>` share of the slots, such as `fetch-rs=3`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentWeight {
+    /// Component the weight applies to, or `wassette` for the built-in tools
+    pub component: String,
+    /// Share of the slots relative to the other components, at least 1
+    pub weight: u32,
+}
+
+impl FromStr for ComponentWeight {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (component, weight) = s
+            .rsplit_once('=')
+            .with_context(|| format!("Component weight must be <component>=<weight>: {s}"))?;
+        if component.is_empty() {
+            return Err(anyhow!("Component weight has no component: {s}"));
+        }
+        let weight = weight
+            .parse()
+            .ok()
+            .filter(|weight| *weight > 0)
+            .with_context(|| format!("Component weight must be a positive integer: {s}"))?;
+        Ok(Self {
+            component: component.to_string(),
+            weight,
+        })
+    }
+}
+
+/// The queue of one component
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueStats {
+    /// Component the calls are for, or `wassette` for the built-in tools
+    pub component: String,
+    /// Share of the slots of the component
+    pub weight: u32,
+    /// Calls of the component that are running
+    pub running: usize,
+    /// Calls of the component that wait for a slot
+    pub waiting: usize,
+    /// Calls of the component that got a slot so far
+    pub served: u64,
+    /// How long the oldest waiting call of the component has waited
+    pub longest_wait: Duration,
+}
+
+struct Waiter {
+    queue: String,
+    priority: Priority,
+    enqueued: Instant,
+    tag: u64,
+    slot: oneshot::Sender<Slot>,
+}
+
+#[derive(Default)]
+struct Flow {
+    running: usize,
+    served: u64,
+    finish: u64,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: usize,
+    waiting: VecDeque<Waiter>,
+    flows: HashMap<String, Flow>,
+    virtual_time: u64,
+}
+
+impl SchedulerState {
+    /// Queues a call for the component `queue`, tagging it after the component's previous call
+    fn enqueue(
+        &mut self,
+        queue: &str,
+        weight: u32,
+        priority: Priority,
+        now: Instant,
+    ) -> oneshot::Receiver<Slot> {
+        let flow = self.flows.entry(queue.to_string()).or_default();
+        let tag = flow.finish.max(self.virtual_time);
+        flow.finish = tag + VIRTUAL_CALL_TIME / u64::from(weight);
+        let (sender, receiver) = oneshot::channel();
+        self.waiting.push_back(Waiter {
+            queue: queue.to_string(),
+            priority,
+            enqueued: now,
+            tag,
+            slot: sender,
+        });
+        receiver
+    }
+
+    /// Removes the waiter that gets the next free slot
+    fn next_waiter(&mut self, now: Instant) -> Option<Waiter> {
+        let starved = self.waiting.iter().position(|waiter| {
+            waiter.priority == Priority::Background
+                && now.duration_since(waiter.enqueued) >= STARVATION_TIMEOUT
+        });
+        let fairest = |priority: Option<Priority>| {
+            self.waiting
+                .iter()
+                .enumerate()
+                .filter(|(_, waiter)| priority.is_none_or(|priority| waiter.priority == priority))
+                .min_by_key(|(_, waiter)| waiter.tag)
+                .map(|(index, _)| index)
+        };
+        let index = starved
+            .or_else(|| fairest(Some(Priority::Interactive)))
+            .or_else(|| fairest(None))?;
+        let waiter = self.waiting.remove(index)?;
+        self.virtual_time = self.virtual_time.max(waiter.tag);
+        Some(waiter)
+    }
+
+    /// Counts a call of `queue` that got a slot
+    fn start(&mut self, queue: &str) {
+        let flow = self.flows.entry(queue.to_string()).or_default();
+        flow.running += 1;
+        flow.served += 1;
+    }
+}
+
+struct SchedulerInner {
+    max_concurrent: usize,
+    state: Mutex<SchedulerState>,
+}
+
+impl SchedulerInner {
+    /// Hands the slot freed by a call of `queue` to the next waiter, or frees it if nobody waits
+    fn release(self: &Arc<Self>, queue: &str) {
+        let mut state = self.state.lock().expect("scheduler lock poisoned");
+        if let Some(flow) = state.flows.get_mut(queue) {
+            flow.running -= 1;
+        }
+        while let Some(waiter) = state.next_waiter(Instant::now()) {
+            let slot = Slot {
+                inner: Some(self.clone()),
+                queue: waiter.queue.clone(),
+            };
+            match waiter.slot.send(slot) {
+                Ok(()) => {
+                    state.start(&waiter.queue);
+                    return;
+                }
+                // The call was cancelled while it waited
+                Err(mut slot) => slot.inner = None,
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+/// A slot to run a tool call in, freed when dropped
+pub struct Slot {
+    inner: Option<Arc<SchedulerInner>>,
+    queue: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.release(&self.queue);
+        }
+    }
+}
+
+/// Limits how many tool calls run at once, serving waiting calls by priority and fairly across
+/// components
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    inner: Option<Arc<SchedulerInner>>,
+    priorities: Arc<HashMap<String, Priority>>,
+    weights: Arc<HashMap<String, u32>>,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field(
+                "max_concurrent",
+                &self.inner.as_ref().map(|inner| inner.max_concurrent),
+            )
+            .field("priorities", &self.priorities)
+            .field("weights", &self.weights)
+            .finish()
+    }
+}
+
+impl Scheduler {
+    /// Creates a scheduler that runs at most `max_concurrent` calls at once, or any number of
+    /// calls if `None`. Components without a weight have weight 1. Later priorities and weights
+    /// for the same tool or component win.
+    pub fn new(
+        max_concurrent: Option<usize>,
+        priorities: impl IntoIterator<Item = ToolPriority>,
+        weights: impl IntoIterator<Item = ComponentWeight>,
+    ) -> Result<Self> {
+        if max_concurrent == Some(0) {
+            bail!("The concurrency limit must be at least 1");
+        }
+        Ok(Self {
+            inner: max_concurrent.map(|max_concurrent| {
+                Arc::new(SchedulerInner {
+                    max_concurrent,
+                    state: Default::default(),
+                })
+            }),
+            priorities: Arc::new(
+                priorities
+                    .into_iter()
+                    .map(|priority| (priority.tool, priority.priority))
+                    .collect(),
+            ),
+            weights: Arc::new(
+                weights
+                    .into_iter()
+                    .map(|weight| (weight.component, weight.weight))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Whether calls are limited and may wait
+    pub fn is_limited(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    fn weight(&self, queue: &str) -> u32 {
+        self.weights.get(queue).copied().unwrap_or(1)
+    }
+
+    /// The priority class of a call of `tool`: the class configured for the tool, if any,
+    /// otherwise `default`
+    pub fn priority(&self, tool: &str, default: Priority) -> Priority {
+        self.priorities
+            .get(tool)
+            .or_else(|| self.priorities.get(ALL_TOOLS))
+            .copied()
+            .unwrap_or(default)
+    }
+
+    /// Waits for a slot to run a call of the component `queue` with `priority` in. Without a
+    /// concurrency limit a slot is always free.
+    pub async fn acquire(&self, queue: &str, priority: Priority) -> Slot {
+        let Some(inner) = &self.inner else {
+            return Slot {
+                inner: None,
+                queue: queue.to_string(),
+            };
+        };
+        let receiver = {
+            let mut state = inner.state.lock().expect("scheduler lock poisoned");
+            if state.running < inner.max_concurrent && state.waiting.is_empty() {
+                state.running += 1;
+                state.start(queue);
+                return Slot {
+                    inner: Some(inner.clone()),
+                    queue: queue.to_string(),
+                };
+            }
+            state.enqueue(queue, self.weight(queue), priority, Instant::now())
+        };
+        receiver
+            .await
+            .expect("scheduler dropped a waiting call without a slot")
+    }
+
+    /// How many calls run and how many wait, if there is a concurrency limit
+    pub fn load(&self) -> Option<(usize, usize)> {
+        let inner = self.inner.as_ref()?;
+        let state = inner.state.lock().expect("scheduler lock poisoned");
+        Some((state.running, state.waiting.len()))
+    }
+
+    /// The concurrency limit and the queue of every component that had calls, sorted by
+    /// component, if there is a limit
+    pub fn queue_stats(&self) -> Option<(usize, Vec<QueueStats>)> {
+        let inner = self.inner.as_ref()?;
+        let state = inner.state.lock().expect("scheduler lock poisoned");
+        let now = Instant::now();
+        let mut stats = state
+            .flows
+            .iter()
+            .map(|(queue, flow)| {
+                let waiting = state.waiting.iter().filter(|waiter| &waiter.queue == queue);
+                QueueStats {
+                    component: queue.clone(),
+                    weight: self.weight(queue),
+                    running: flow.running,
+                    waiting: waiting.clone().count(),
+                    served: flow.served,
+                    longest_wait: waiting
+                        .map(|waiter| now.duration_since(waiter.enqueued))
+                        .max()
+                        .unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| a.component.cmp(&b.component));
+        Some((inner.max_concurrent, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tool_priority() {
+        assert_eq!(
+            "report=background".parse::<ToolPriority>().unwrap(),
+            ToolPriority {
+                tool: "report".to_string(),
+                priority: Priority::Background,
+            }
+        );
+        assert!("report".parse::<ToolPriority>().is_err());
+        assert!("=background".parse::<ToolPriority>().is_err());
+        assert!("report=urgent".parse::<ToolPriority>().is_err());
+
+        let scheduler = Scheduler::new(
+            None,
+            ["report=background", "*=interactive"].map(|s| s.parse().unwrap()),
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            scheduler.priority("report", Priority::Interactive),
+            Priority::Background
+        );
+        assert_eq!(
+            scheduler.priority("fetch", Priority::Background),
+            Priority::Interactive
+        );
+        assert_eq!(
+            Scheduler::default().priority("fetch", Priority::Background),
+            Priority::Background
+        );
+        assert!(Scheduler::new(Some(0), [], []).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_interactive_calls_go_first() {
+        let scheduler = Scheduler::new(Some(1), [], []).unwrap();
+        let running = scheduler.acquire("fetch-rs", Priority::Background).await;
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for (name, priority) in [
+            ("background", Priority::Background),
+            ("interactive", Priority::Interactive),
+        ] {
+            let task_scheduler = scheduler.clone();
+            let order_tx = order_tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let _slot = task_scheduler.acquire("fetch-rs", priority).await;
+                order_tx.send(name).unwrap();
+            }));
+            // Queue the calls in a known order
+            while scheduler.load().unwrap().1 < tasks.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(scheduler.load(), Some((1, 2)));
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(order_rx.recv().await, Some("interactive"));
+        assert_eq!(order_rx.recv().await, Some("background"));
+        assert_eq!(scheduler.load(), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_starved_background_calls_go_first() {
+        let now = Instant::now();
+        let mut state = SchedulerState::default();
+        for (priority, waited) in [
+            (Priority::Background, STARVATION_TIMEOUT),
+            (Priority::Interactive, Duration::ZERO),
+            (Priority::Background, Duration::ZERO),
+        ] {
+            drop(state.enqueue("fetch-rs", 1, priority, now - waited));
+        }
+
+        let order = std::iter::from_fn(|| state.next_waiter(now))
+            .map(|waiter| (waiter.priority, now.duration_since(waiter.enqueued)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                (Priority::Background, STARVATION_TIMEOUT),
+                (Priority::Interactive, Duration::ZERO),
+                (Priority::Background, Duration::ZERO),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_frees_its_slot() {
+        let scheduler = Scheduler::new(Some(1), [], []).unwrap();
+        let running = scheduler.acquire("fetch-rs", Priority::Interactive).await;
+
+        let task_scheduler = scheduler.clone();
+        let waiting = tokio::spawn(async move {
+            task_scheduler
+                .acquire("fetch-rs", Priority::Interactive)
+                .await
+        });
+        while scheduler.load().unwrap().1 == 0 {
+            tokio::task::yield_now().await;
+        }
+        waiting.abort();
+        let _ = waiting.await;
+
+        drop(running);
+        assert_eq!(scheduler.load(), Some((0, 0)));
+        let _slot = scheduler.acquire("fetch-rs", Priority::Background).await;
+        assert_eq!(scheduler.load(), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_fair_queuing_across_components() {
+        let now = Instant::now();
+        let order = |state: &mut SchedulerState| {
+            std::iter::from_fn(|| state.next_waiter(now))
+                .map(|waiter| waiter.queue)
+                .collect::<Vec<_>>()
+        };
+
+        // A burst of calls to one component doesn't hold back a component queued after it
+        let mut state = SchedulerState::default();
+        for queue in ["burst", "burst", "burst", "burst", "other", "other"] {
+            drop(state.enqueue(queue, 1, Priority::Interactive, now));
+        }
+        assert_eq!(
+            order(&mut state),
+            ["burst", "other", "burst", "other", "burst", "burst"]
+        );
+
+        // A component with weight 2 gets twice the slots
+        let mut state = SchedulerState::default();
+        for (queue, weight) in [("heavy", 2), ("light", 1)] {
+            for _ in 0..4 {
+                drop(state.enqueue(queue, weight, Priority::Interactive, now));
+            }
+        }
+        assert_eq!(
+            order(&mut state),
+            ["heavy", "light", "heavy", "heavy", "light", "heavy", "light", "light"]
+        );
+
+        assert_eq!(
+            "fetch-rs=3".parse::<ComponentWeight>().unwrap(),
+            ComponentWeight {
+                component: "fetch-rs".to_string(),
+                weight: 3,
+            }
+        );
+        assert!("fetch-rs=0".parse::<ComponentWeight>().is_err());
+        assert!("fetch-rs".parse::<ComponentWeight>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_queue_stats() {
+        let scheduler = Scheduler::new(Some(1), [], ["fetch-rs=2".parse().unwrap()]).unwrap();
+        assert_eq!(Scheduler::default().queue_stats(), None);
+
+        let running = scheduler.acquire("fetch-rs", Priority::Interactive).await;
+        let task_scheduler = scheduler.clone();
+        let waiting = tokio::spawn(async move {
+            task_scheduler
+                .acquire(BUILTIN_QUEUE, Priority::Interactive)
+                .await
+        });
+        while scheduler.load().unwrap().1 == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let (limit, stats) = scheduler.queue_stats().unwrap();
+        assert_eq!(limit, 1);
+        assert_eq!(
+            stats
+                .iter()
+                .map(|stats| (
+                    stats.component.as_str(),
+                    stats.weight,
+                    stats.running,
+                    stats.waiting,
+                    stats.served
+                ))
+                .collect::<Vec<_>>(),
+            [("fetch-rs", 2, 1, 0, 1), ("wassette", 1, 0, 1, 0)]
+        );
+
+        drop(running);
+        let slot = waiting.await.unwrap();
+        let (_, stats) = scheduler.queue_stats().unwrap();
+        assert_eq!(
+            (stats[0].running, stats[1].running, stats[1].served),
+            (0, 1, 1)
+        );
+        drop(slot);
+        assert_eq!(scheduler.load(), Some((0, 0)));
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Built-in tools that manage the secrets of components
+//!
+//! `set-secret`, `list-secret-keys` and `delete-secret` are only listed and callable when the
+//! server is started with `secret_tools` turned on, since they let any client change what a
+//! component authenticates with. None of them ever returns the value of a secret.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rmcp::model::{CallToolRequestParam, CallToolResult, Content, Tool};
+use serde_json::{json, Value};
+use tracing::info;
+use wassette::LifecycleManager;
+
+use crate::components::extract_args_from_request;
+
+/// Names of the secret management tools
+pub const SECRET_TOOLS: [&str; 3] = ["set-secret", "list-secret-keys", "delete-secret"];
+
+/// Whether `tool` is one of the secret management tools
+pub fn is_secret_tool(tool: &str) -> bool {
+    SECRET_TOOLS.contains(&tool)
+}
+
+/// The definitions of the secret management tools
+pub(crate) fn get_secret_tools() -> Vec<Tool> {
+    let component_id = json!({
+        "type": "string",
+        "description": "ID of the component the secret belongs to"
+    });
+    let tool = |name: &'static str, description: &'static str, schema: Value| Tool {
+        name: Cow::Borrowed(name),
+        description: Some(Cow::Borrowed(description)),
+        input_schema: Arc::new(serde_json::from_value(schema).unwrap_or_default()),
+        output_schema: None,
+        annotations: None,
+    };
+    vec![
+        tool(
+            "set-secret",
+            "Sets a secret of a loaded component, replacing its value if it has one. The component reads it as a wasi:config variable on its next call. Secrets are stored on the server and kept when the component is unloaded.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "component_id": component_id,
+                    "key": {"type": "string", "description": "Name of the secret"},
+                    "value": {"type": "string", "description": "Value of the secret"},
+                    "functions": {
+                        "type": ["array", "null"],
+                        "items": {"type": "string"},
+                        "description": "Exported functions of the component that get the secret. Calls of other functions run without it. null gives it to every function again; leave it out to keep the functions the secret had."
+                    }
+                },
+                "required": ["component_id", "key", "value"]
+            }),
+        ),
+        tool(
+            "list-secret-keys",
+            "Lists the names of the secrets of a loaded component. Their values are never returned.",
+            json!({
+                "type": "object",
+                "properties": {"component_id": component_id},
+                "required": ["component_id"]
+            }),
+        ),
+        tool(
+            "delete-secret",
+            "Deletes a secret of a loaded component",
+            json!({
+                "type": "object",
+                "properties": {
+                    "component_id": component_id,
+                    "key": {"type": "string", "description": "Name of the secret"}
+                },
+                "required": ["component_id", "key"]
+            }),
+        ),
+    ]
+}
+
+/// Handles a call of one of the [`SECRET_TOOLS`]
+pub(crate) async fn handle_secret_tool(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+    let argument = |name: &str| {
+        args.get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Missing required argument: '{name}'"))
+    };
+    let component_id = argument("component_id")?;
+
+    let status = match req.name.as_ref() {
+        "set-secret" => {
+            let key = argument("key")?;
+            info!(component_id, key, "Setting secret");
+            lifecycle_manager
+                .set_secret(component_id, key, argument("value")?)
+                .await?;
+            match args.get("functions") {
+                None => {}
+                Some(Value::Null) => {
+                    lifecycle_manager
+                        .set_secret_scope(component_id, key, None)
+                        .await?
+                }
+                Some(functions) => {
+                    let functions = serde_json::from_value::<Vec<String>>(functions.clone())
+                        .map_err(|_| anyhow!("'functions' must be a list of function names"))?;
+                    lifecycle_manager
+                        .set_secret_scope(component_id, key, Some(functions))
+                        .await?
+                }
+            }
+            "secret set"
+        }
+        "delete-secret" => {
+            let key = argument("key")?;
+            info!(component_id, key, "Deleting secret");
+            if lifecycle_manager.delete_secret(component_id, key).await? {
+                "secret deleted"
+            } else {
+                "no such secret"
+            }
+        }
+        _ => "ok",
+    };
+
+    let status_text = serde_json::to_string(&json!({
+        "status": status,
+        "component_id": component_id,
+        "keys": lifecycle_manager.list_secret_keys(component_id).await?,
+        "scopes": lifecycle_manager.secret_scopes(component_id).await?,
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(status_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{execute_tool_call, handle_tools_list, ToolCallOptions};
+
+    #[tokio::test]
+    async fn test_secret_tools() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("fetch.wasm"),
+            r#"(component
+              (core module $m (func (export "run")))
+              (core instance $i (instantiate $m))
+              (func $run (canon lift (core func $i "run")))
+              (export "run" (func $run)))"#,
+        )?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+        let call = |name: &str, arguments: Value| CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        };
+
+        // Without the opt-in, the tools are neither listed nor callable
+        let options = ToolCallOptions::default();
+        let tools = handle_tools_list(&lifecycle_manager, &options).await?;
+        assert!(!tools.to_string().contains("set-secret"));
+        let result = execute_tool_call(
+            call("list-secret-keys", json!({"component_id": "fetch"})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        assert_eq!(result["isError"], true);
+
+        let options = ToolCallOptions {
+            secret_tools: true,
+            ..Default::default()
+        };
+        let tools = handle_tools_list(&lifecycle_manager, &options).await?;
+        assert!(tools.to_string().contains("set-secret"));
+        let result = execute_tool_call(
+            call(
+                "set-secret",
+                json!({"component_id": "fetch", "key": "API_TOKEN", "value": "s3cr3t"}),
+            ),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        let text = result["content"][0]["text"].as_str().unwrap();
+        assert!(!text.contains("s3cr3t"));
+        let status: Value = serde_json::from_str(text)?;
+        assert_eq!(status["keys"], json!(["API_TOKEN"]));
+        assert_eq!(status["scopes"], json!({}));
+
+        let result = execute_tool_call(
+            call(
+                "set-secret",
+                json!({"component_id": "fetch", "key": "API_TOKEN", "value": "s3cr3t", "functions": ["run"]}),
+            ),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        let status: Value = serde_json::from_str(result["content"][0]["text"].as_str().unwrap())?;
+        assert_eq!(status["scopes"], json!({"API_TOKEN": ["run"]}));
+
+        let result = execute_tool_call(
+            call(
+                "delete-secret",
+                json!({"component_id": "fetch", "key": "API_TOKEN"}),
+            ),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        let status: Value = serde_json::from_str(result["content"][0]["text"].as_str().unwrap())?;
+        assert_eq!(status["status"], "secret deleted");
+        assert_eq!(status["keys"], json!([]));
+
+        Ok(())
+    }
+}
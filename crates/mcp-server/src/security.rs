@@ -0,0 +1,934 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Limits applied to tool calls before they run
+//!
+//! A tool can be given a rate limit of some number of calls per period. Calls are counted in
+//! fixed windows that start with the first call after the previous window ended. A call over the
+//! limit fails with [`RateLimitExceeded`], which tells the caller how long to wait before the
+//! window resets.
+//!
+//! Callers that authenticate with an API key are further held to what their key allows: the tools
+//! and components it lists, and rate limits of its own, counted separately for every key.
+//!
+//! Every call is also checked against [`CallLimits`]: its tool name must be well formed and its
+//! arguments must not be larger or more deeply nested than the limits allow, or it fails with
+//! [`InvalidToolCall`] before it reaches a component. The arguments of a component tool must
+//! also match the tool's input schema, or the call fails with [`InvalidArguments`] that lists
+//! everything wrong with them, without instantiating the component. The text that components and downstream
+//! servers return is stripped of terminal escape sequences, control characters and bidirectional
+//! overrides, which could hide text from the user reading it. The limits can be set for every
+//! component, or for one of them.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use component2json::SchemaViolation;
+use serde_json::{json, Value};
+
+use crate::errors::ToolErrorCode;
+use crate::summarize::ALL_TOOLS;
+
+/// JSON-RPC error code of calls rejected by a rate limit
+pub const RATE_LIMITED_ERROR_CODE: i32 = -32029;
+
+/// JSON-RPC error code of calls of tools that the caller's API key doesn't allow
+pub const ACCESS_DENIED_ERROR_CODE: i32 = -32030;
+
+/// JSON-RPC error code of calls with a malformed tool name or arguments over the limits, the
+/// standard "Invalid params" code
+pub const INVALID_CALL_ERROR_CODE: i32 = -32602;
+
+/// Longest tool name that may be called
+pub const MAX_TOOL_NAME_LENGTH: usize = 128;
+
+/// A `<tool>=<calls>/<period>` rate limit, such as `fetch=10/1m`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitRule {
+    /// Tool the limit applies to, or `*` for every tool
+    pub tool: String,
+    /// How many calls are allowed per period
+    pub calls: u32,
+    /// Length of a window
+    pub period: Duration,
+}
+
+/// Parses a period such as `30s`, `1m`, `2h` or `1d`. A bare number is in seconds.
+fn parse_period(s: &str) -> Result<Duration> {
+    let (count, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    // "/m" reads as one minute
+    let count: u64 = if count.is_empty() { 1 } else { count.parse()? };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(anyhow!("Unknown period unit '{unit}'")),
+    };
+    if count == 0 {
+        return Err(anyhow!("Period must be longer than zero"));
+    }
+    Ok(Duration::from_secs(count * seconds))
+}
+
+impl FromStr for RateLimitRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tool, limit) = s
+            .rsplit_once('=')
+            .with_context(|| format!("Rate limit must be <tool>=<calls>/<period>: {s}"))?;
+        if tool.is_empty() {
+            return Err(anyhow!("Rate limit has no tool name: {s}"));
+        }
+        let (calls, period) = limit
+            .split_once('/')
+            .with_context(|| format!("Rate limit must be <tool>=<calls>/<period>: {s}"))?;
+        let calls = calls
+            .parse()
+            .with_context(|| format!("Invalid call count in rate limit: {s}"))?;
+        let period =
+            parse_period(period).with_context(|| format!("Invalid period in rate limit: {s}"))?;
+        Ok(Self {
+            tool: tool.to_string(),
+            calls,
+            period,
+        })
+    }
+}
+
+/// Where a tool stands in its current window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitStatus {
+    /// Calls allowed per window
+    pub limit: u32,
+    /// Calls left in the current window
+    pub remaining: u32,
+    /// Time until the current window ends
+    pub reset_after: Duration,
+}
+
+/// Error of a call rejected by a rate limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitExceeded {
+    /// The tool that was called
+    pub tool: String,
+    /// Calls allowed per window
+    pub limit: u32,
+    /// Time until the window resets and calls are allowed again
+    pub retry_after: Duration,
+}
+
+impl RateLimitExceeded {
+    /// The structured data of the error, sent as the `data` of the JSON-RPC error
+    pub fn data(&self) -> Value {
+        json!({
+            "code": ToolErrorCode::ResourceExhausted.as_str(),
+            "tool": self.tool,
+            "limit": self.limit,
+            "remaining": 0,
+            "retry_after_secs": retry_after_secs(self.retry_after),
+        })
+    }
+}
+
+impl fmt::Display for RateLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rate limit of {} calls exceeded for tool '{}', retry after {} seconds",
+            self.limit,
+            self.tool,
+            retry_after_secs(self.retry_after)
+        )
+    }
+}
+
+impl std::error::Error for RateLimitExceeded {}
+
+/// Whole seconds to wait, rounded up so that waiting that long is always enough
+pub fn retry_after_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+struct Window {
+    started: Instant,
+    calls: u32,
+}
+
+/// Counts tool calls against per-tool rate limits
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    rules: Arc<HashMap<String, RateLimitRule>>,
+    windows: Arc<Mutex<HashMap<String, Window>>>,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("rules", &self.rules.values().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter from a list of rules. Later rules for the same tool win.
+    pub fn new(rules: impl IntoIterator<Item = RateLimitRule>) -> Self {
+        Self {
+            rules: Arc::new(
+                rules
+                    .into_iter()
+                    .map(|rule| (rule.tool.clone(), rule))
+                    .collect(),
+            ),
+            windows: Default::default(),
+        }
+    }
+
+    fn rule(&self, tool: &str) -> Option<&RateLimitRule> {
+        self.rules.get(tool).or_else(|| self.rules.get(ALL_TOOLS))
+    }
+
+    /// Counts a call of `tool`, failing with [`RateLimitExceeded`] if it is over the limit.
+    /// Returns `None` for tools without a limit.
+    pub fn check(&self, tool: &str) -> Result<Option<RateLimitStatus>, RateLimitExceeded> {
+        self.check_at(tool, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        tool: &str,
+        now: Instant,
+    ) -> Result<Option<RateLimitStatus>, RateLimitExceeded> {
+        let Some(rule) = self.rule(tool) else {
+            return Ok(None);
+        };
+        let mut windows = self.windows.lock().expect("rate limiter lock poisoned");
+        let window = windows.entry(tool.to_string()).or_insert(Window {
+            started: now,
+            calls: 0,
+        });
+        if now.duration_since(window.started) >= rule.period {
+            *window = Window {
+                started: now,
+                calls: 0,
+            };
+        }
+        let reset_after = rule.period - now.duration_since(window.started);
+        if window.calls >= rule.calls {
+            return Err(RateLimitExceeded {
+                tool: tool.to_string(),
+                limit: rule.calls,
+                retry_after: reset_after,
+            });
+        }
+        window.calls += 1;
+        Ok(Some(RateLimitStatus {
+            limit: rule.calls,
+            remaining: rule.calls - window.calls,
+            reset_after,
+        }))
+    }
+
+    /// Where `tool` stands in its current window, without counting a call. Returns `None` for
+    /// tools without a limit.
+    pub fn status(&self, tool: &str) -> Option<RateLimitStatus> {
+        self.status_at(tool, Instant::now())
+    }
+
+    fn status_at(&self, tool: &str, now: Instant) -> Option<RateLimitStatus> {
+        let rule = self.rule(tool)?;
+        let windows = self.windows.lock().expect("rate limiter lock poisoned");
+        let status = match windows.get(tool) {
+            Some(window) if now.duration_since(window.started) < rule.period => RateLimitStatus {
+                limit: rule.calls,
+                remaining: rule.calls.saturating_sub(window.calls),
+                reset_after: rule.period - now.duration_since(window.started),
+            },
+            _ => RateLimitStatus {
+                limit: rule.calls,
+                remaining: rule.calls,
+                reset_after: rule.period,
+            },
+        };
+        Some(status)
+    }
+}
+
+/// What the holder of an API key may call
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeyAccess {
+    /// Name of the key, used in errors and logs instead of the key itself
+    pub name: String,
+    /// Rate limits of the key's calls, counted apart from those of other keys
+    pub rate_limits: RateLimiter,
+    /// Tools the key may call. `None` doesn't restrict the tools.
+    pub tools: Option<Vec<String>>,
+    /// Components whose tools the key may call. `None` doesn't restrict the components.
+    pub components: Option<Vec<String>>,
+}
+
+impl ApiKeyAccess {
+    /// Whether the key restricts what may be called. Keys that don't only have rate limits, if
+    /// any.
+    pub fn is_restricted(&self) -> bool {
+        self.tools.is_some() || self.components.is_some()
+    }
+
+    /// Whether the key may call `tool`, which belongs to `component` unless it's a built-in tool.
+    /// With both allowlists, a tool on either of them may be called.
+    pub fn allows(&self, tool: &str, component: Option<&str>) -> bool {
+        if !self.is_restricted() {
+            return true;
+        }
+        let listed = |allowlist: &Option<Vec<String>>, name: &str| {
+            allowlist
+                .iter()
+                .flatten()
+                .any(|allowed| allowed == ALL_TOOLS || allowed == name)
+        };
+        listed(&self.tools, tool) || component.is_some_and(|id| listed(&self.components, id))
+    }
+}
+
+/// Error of a call of a tool that the caller's API key doesn't allow
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolAccessDenied {
+    /// Name of the API key
+    pub key: String,
+    /// The tool that was called
+    pub tool: String,
+}
+
+impl ToolAccessDenied {
+    /// The structured data of the error, sent as the `data` of the JSON-RPC error
+    pub fn data(&self) -> Value {
+        json!({
+            "code": ToolErrorCode::PolicyDenied.as_str(),
+            "key": self.key,
+            "tool": self.tool,
+        })
+    }
+}
+
+impl fmt::Display for ToolAccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "API key '{}' is not allowed to call tool '{}'",
+            self.key, self.tool
+        )
+    }
+}
+
+impl std::error::Error for ToolAccessDenied {}
+
+/// The API keys callers may authenticate with, by name
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: Arc<HashMap<String, ApiKeyAccess>>,
+}
+
+impl ApiKeys {
+    /// Creates the set of keys. Later keys with the same name win.
+    pub fn new(keys: impl IntoIterator<Item = ApiKeyAccess>) -> Self {
+        Self {
+            keys: Arc::new(
+                keys.into_iter()
+                    .map(|key| (key.name.clone(), key))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// What the key called `name` may call
+    pub fn get(&self, name: &str) -> Option<&ApiKeyAccess> {
+        self.keys.get(name)
+    }
+
+    /// Checks a call of `tool` of `component` by the holder of the key called `name`, and counts
+    /// it against the key's rate limits. Fails with [`ToolAccessDenied`] or
+    /// [`RateLimitExceeded`].
+    pub fn check(&self, name: &str, tool: &str, component: Option<&str>) -> Result<()> {
+        let access = self
+            .get(name)
+            .with_context(|| format!("Unknown API key '{name}'"))?;
+        if !access.allows(tool, component) {
+            return Err(ToolAccessDenied {
+                key: name.to_string(),
+                tool: tool.to_string(),
+            }
+            .into());
+        }
+        access.rate_limits.check(tool)?;
+        Ok(())
+    }
+}
+
+/// Limits on the arguments and results of the calls of a component's tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallLimits {
+    /// Most bytes the arguments of a call may take as JSON
+    pub max_argument_size: usize,
+    /// Deepest the arguments of a call may nest arrays and objects
+    pub max_argument_depth: usize,
+    /// Whether escape sequences and control characters are stripped from results
+    pub sanitize_output: bool,
+}
+
+impl Default for CallLimits {
+    fn default() -> Self {
+        Self {
+            max_argument_size: 1024 * 1024,
+            max_argument_depth: 32,
+            sanitize_output: true,
+        }
+    }
+}
+
+/// The limits of a component that differ from those of every component
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallLimitOverrides {
+    /// Replaces [`CallLimits::max_argument_size`]
+    pub max_argument_size: Option<usize>,
+    /// Replaces [`CallLimits::max_argument_depth`]
+    pub max_argument_depth: Option<usize>,
+    /// Replaces [`CallLimits::sanitize_output`]
+    pub sanitize_output: Option<bool>,
+}
+
+/// The limits of every component and the overrides of single components
+#[derive(Debug, Clone, Default)]
+pub struct CallLimitSettings {
+    limits: CallLimits,
+    overrides: Arc<HashMap<String, CallLimitOverrides>>,
+}
+
+impl CallLimitSettings {
+    /// Creates the settings from the limits of every component and the overrides of components,
+    /// by component ID. The overrides of downstream servers are keyed by server name.
+    pub fn new(
+        limits: CallLimits,
+        overrides: impl IntoIterator<Item = (String, CallLimitOverrides)>,
+    ) -> Self {
+        Self {
+            limits,
+            overrides: Arc::new(overrides.into_iter().collect()),
+        }
+    }
+
+    /// The limits of the calls of `component`, or of built-in tools for `None`
+    pub fn limits(&self, component: Option<&str>) -> CallLimits {
+        let Some(overrides) = component.and_then(|id| self.overrides.get(id)) else {
+            return self.limits;
+        };
+        CallLimits {
+            max_argument_size: overrides
+                .max_argument_size
+                .unwrap_or(self.limits.max_argument_size),
+            max_argument_depth: overrides
+                .max_argument_depth
+                .unwrap_or(self.limits.max_argument_depth),
+            sanitize_output: overrides
+                .sanitize_output
+                .unwrap_or(self.limits.sanitize_output),
+        }
+    }
+}
+
+/// Error of a call with a malformed tool name or arguments over the limits
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidToolCall {
+    /// The tool that was called
+    pub tool: String,
+    /// What is wrong with the call
+    pub reason: String,
+}
+
+impl InvalidToolCall {
+    /// The structured data of the error, sent as the `data` of the JSON-RPC error
+    pub fn data(&self) -> Value {
+        json!({
+            "code": ToolErrorCode::ValidationFailed.as_str(),
+            "tool": self.tool,
+            "reason": self.reason,
+        })
+    }
+}
+
+impl fmt::Display for InvalidToolCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid call of tool '{}': {}", self.tool, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidToolCall {}
+
+/// Error of a call of a component tool with arguments that don't match the tool's input schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidArguments {
+    /// The tool that was called
+    pub tool: String,
+    /// Everything wrong with the arguments
+    pub violations: Vec<SchemaViolation>,
+}
+
+impl InvalidArguments {
+    /// The structured data of the error, sent as the `data` of the JSON-RPC error
+    pub fn data(&self) -> Value {
+        let errors = self
+            .violations
+            .iter()
+            .map(|violation| json!({ "path": violation.path, "message": violation.message }))
+            .collect::<Vec<_>>();
+        json!({
+            "code": ToolErrorCode::ValidationFailed.as_str(),
+            "tool": self.tool,
+            "errors": errors,
+        })
+    }
+}
+
+impl fmt::Display for InvalidArguments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let violations = self
+            .violations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+        write!(
+            f,
+            "Invalid arguments for tool '{}': {violations}",
+            self.tool
+        )
+    }
+}
+
+impl std::error::Error for InvalidArguments {}
+
+/// Fails with [`InvalidArguments`] if `arguments` don't match `input_schema`, the input schema
+/// of the component tool `tool`
+pub fn validate_against_schema(
+    tool: &str,
+    arguments: Option<&serde_json::Map<String, Value>>,
+    input_schema: &Value,
+) -> Result<(), InvalidArguments> {
+    let arguments = Value::Object(arguments.cloned().unwrap_or_default());
+    let violations = component2json::validate_json(&arguments, input_schema);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(InvalidArguments {
+        tool: tool.to_string(),
+        violations,
+    })
+}
+
+/// Fails with [`InvalidToolCall`] unless `tool` is 1 to [`MAX_TOOL_NAME_LENGTH`] letters,
+/// digits, `_`, `-`, `.` or `/`
+pub fn validate_tool_name(tool: &str) -> Result<(), InvalidToolCall> {
+    let reason = if tool.is_empty() {
+        "the tool name is empty".to_string()
+    } else if tool.len() > MAX_TOOL_NAME_LENGTH {
+        format!("tool names may be at most {MAX_TOOL_NAME_LENGTH} characters long")
+    } else if let Some(c) = tool
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/')))
+    {
+        format!("tool names may not contain {c:?}")
+    } else {
+        return Ok(());
+    };
+    Err(InvalidToolCall {
+        // The name itself may be what's wrong, so it's shortened and escaped for the error
+        tool: tool
+            .chars()
+            .take(MAX_TOOL_NAME_LENGTH)
+            .flat_map(char::escape_default)
+            .collect(),
+        reason,
+    })
+}
+
+/// Fails with [`InvalidToolCall`] if the `arguments` of a call of `tool` are larger or nest
+/// deeper than `limits` allow
+pub fn validate_arguments(
+    tool: &str,
+    arguments: Option<&serde_json::Map<String, Value>>,
+    limits: &CallLimits,
+) -> Result<(), InvalidToolCall> {
+    let Some(arguments) = arguments else {
+        return Ok(());
+    };
+    let invalid = |reason: String| InvalidToolCall {
+        tool: tool.to_string(),
+        reason,
+    };
+    let size = serde_json::to_vec(arguments).map_or(0, |json| json.len());
+    if size > limits.max_argument_size {
+        return Err(invalid(format!(
+            "the arguments take {size} bytes, more than the limit of {}",
+            limits.max_argument_size
+        )));
+    }
+    if arguments
+        .values()
+        .any(|value| exceeds_depth(value, limits.max_argument_depth.saturating_sub(1)))
+    {
+        return Err(invalid(format!(
+            "the arguments nest deeper than {} levels",
+            limits.max_argument_depth
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `value` nests arrays and objects more than `depth` levels deep
+fn exceeds_depth(value: &Value, depth: usize) -> bool {
+    let children: Box<dyn Iterator<Item = &Value>> = match value {
+        Value::Array(items) => Box::new(items.iter()),
+        Value::Object(fields) => Box::new(fields.values()),
+        _ => return false,
+    };
+    match depth.checked_sub(1) {
+        Some(depth) => children
+            .into_iter()
+            .any(|child| exceeds_depth(child, depth)),
+        None => true,
+    }
+}
+
+/// `text` without terminal escape sequences, control characters other than newlines and tabs,
+/// and bidirectional overrides and isolates. Text that is JSON has the strings in it sanitized,
+/// since their escape sequences would otherwise only be decoded by the reader.
+pub fn sanitize_output(text: &str) -> Cow<'_, str> {
+    if let Ok(mut value) = serde_json::from_str::<Value>(text) {
+        if sanitize_json(&mut value) {
+            return Cow::Owned(value.to_string());
+        }
+        return Cow::Borrowed(text);
+    }
+    sanitize_text(text)
+}
+
+/// Sanitizes the strings in `value`. Returns whether any of them changed.
+fn sanitize_json(value: &mut Value) -> bool {
+    match value {
+        Value::String(s) => match sanitize_text(s) {
+            Cow::Owned(sanitized) => {
+                *s = sanitized;
+                true
+            }
+            Cow::Borrowed(_) => false,
+        },
+        Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |changed, item| sanitize_json(item) | changed),
+        Value::Object(fields) => fields
+            .values_mut()
+            .fold(false, |changed, field| sanitize_json(field) | changed),
+        _ => false,
+    }
+}
+
+fn is_unsafe_char(c: char) -> bool {
+    (c.is_control() && !matches!(c, '\n' | '\t' | '\r'))
+        || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+fn sanitize_text(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_unsafe_char) {
+        return Cow::Borrowed(text);
+    }
+    let mut sanitized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            match chars.peek() {
+                // CSI sequences such as colors end with a byte from '@' to '~'
+                Some('[') => {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC sequences such as hyperlinks and window titles end with BEL or ESC \
+                Some(']') => {
+                    chars.next();
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        } else if !is_unsafe_char(c) {
+            sanitized.push(c);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit_rule() {
+        assert_eq!(
+            "fetch=10/1m".parse::<RateLimitRule>().unwrap(),
+            RateLimitRule {
+                tool: "fetch".to_string(),
+                calls: 10,
+                period: Duration::from_secs(60),
+            }
+        );
+        assert_eq!(
+            "*=100/h".parse::<RateLimitRule>().unwrap().period,
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            "fetch=1/30".parse::<RateLimitRule>().unwrap().period,
+            Duration::from_secs(30)
+        );
+        assert!("fetch".parse::<RateLimitRule>().is_err());
+        assert!("=10/1m".parse::<RateLimitRule>().is_err());
+        assert!("fetch=10".parse::<RateLimitRule>().is_err());
+        assert!("fetch=ten/1m".parse::<RateLimitRule>().is_err());
+        assert!("fetch=10/0s".parse::<RateLimitRule>().is_err());
+        assert!("fetch=10/1w".parse::<RateLimitRule>().is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_windows() {
+        let limiter = RateLimiter::new(["fetch=2/1m", "*=5/1h"].map(|s| s.parse().unwrap()));
+        let start = Instant::now();
+
+        let first = limiter.check_at("fetch", start).unwrap().unwrap();
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset_after, Duration::from_secs(60));
+        limiter.check_at("fetch", start).unwrap();
+
+        let later = start + Duration::from_millis(10_500);
+        let exceeded = limiter.check_at("fetch", later).unwrap_err();
+        assert_eq!(exceeded.limit, 2);
+        assert_eq!(exceeded.retry_after, Duration::from_millis(49_500));
+        assert_eq!(
+            exceeded.data(),
+            json!({
+                "code": "resource_exhausted",
+                "tool": "fetch",
+                "limit": 2,
+                "remaining": 0,
+                "retry_after_secs": 50,
+            })
+        );
+        assert_eq!(limiter.status_at("fetch", later).unwrap().remaining, 0);
+
+        // The next window starts once the period is over
+        let next = start + Duration::from_secs(60);
+        assert_eq!(limiter.status_at("fetch", next).unwrap().remaining, 2);
+        assert_eq!(
+            limiter.check_at("fetch", next).unwrap().unwrap().remaining,
+            1
+        );
+
+        // Other tools fall back to the limit for every tool, each with its own window
+        assert_eq!(
+            limiter
+                .check_at("read-file", start)
+                .unwrap()
+                .unwrap()
+                .remaining,
+            4
+        );
+        assert_eq!(
+            limiter
+                .status_at("list-components", start)
+                .unwrap()
+                .remaining,
+            5
+        );
+        assert_eq!(RateLimiter::default().check("fetch").unwrap(), None);
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let keys = ApiKeys::new([
+            ApiKeyAccess {
+                name: "triage-bot".to_string(),
+                rate_limits: RateLimiter::new(["*=2/1h".parse().unwrap()]),
+                tools: Some(vec!["list-components".to_string()]),
+                components: Some(vec!["fetch".to_string()]),
+            },
+            ApiKeyAccess {
+                name: "admin".to_string(),
+                ..Default::default()
+            },
+        ]);
+
+        keys.check("triage-bot", "list-components", None).unwrap();
+        keys.check("triage-bot", "fetch", Some("fetch")).unwrap();
+        let err = keys
+            .check("triage-bot", "load-component", None)
+            .unwrap_err();
+        let denied = err.downcast_ref::<ToolAccessDenied>().unwrap();
+        assert_eq!(
+            denied.data(),
+            json!({"code": "policy_denied", "key": "triage-bot", "tool": "load-component"})
+        );
+        assert!(keys
+            .check("triage-bot", "read-file", Some("filesystem"))
+            .unwrap_err()
+            .is::<ToolAccessDenied>());
+
+        // Denied calls don't count against the key's limit
+        keys.check("triage-bot", "list-components", None).unwrap();
+        let err = keys
+            .check("triage-bot", "list-components", None)
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<RateLimitExceeded>().unwrap().limit, 2);
+
+        // Keys without allowlists or limits may call everything
+        for _ in 0..5 {
+            keys.check("admin", "load-component", None).unwrap();
+        }
+        assert!(!keys.get("admin").unwrap().is_restricted());
+        assert!(keys.check("unknown", "list-components", None).is_err());
+    }
+
+    #[test]
+    fn test_validate_tool_name() {
+        validate_tool_name("fetch").unwrap();
+        validate_tool_name("fetch-rs__get_weather").unwrap();
+        validate_tool_name("github/search.issues").unwrap();
+        assert!(validate_tool_name("").is_err());
+        assert!(validate_tool_name(&"a".repeat(MAX_TOOL_NAME_LENGTH + 1)).is_err());
+        let err = validate_tool_name("fetch\u{1b}[2J").unwrap_err();
+        assert_eq!(err.tool, "fetch\\u{1b}[2J");
+        assert_eq!(err.reason, "tool names may not contain '\\u{1b}'");
+        assert!(validate_tool_name("fetch all").is_err());
+    }
+
+    #[test]
+    fn test_validate_arguments() {
+        let limits = CallLimits {
+            max_argument_size: 64,
+            max_argument_depth: 3,
+            sanitize_output: true,
+        };
+        let args = |value: Value| value.as_object().cloned().unwrap();
+
+        validate_arguments("fetch", None, &limits).unwrap();
+        validate_arguments("fetch", Some(&args(json!({"a": {"b": [1]}}))), &limits).unwrap();
+        let err = validate_arguments("fetch", Some(&args(json!({"a": {"b": [[1]]}}))), &limits)
+            .unwrap_err();
+        assert_eq!(err.reason, "the arguments nest deeper than 3 levels");
+        let err = validate_arguments(
+            "fetch",
+            Some(&args(json!({"url": "x".repeat(64)}))),
+            &limits,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.data(),
+            json!({"code": "validation_failed", "tool": "fetch", "reason": "the arguments take 74 bytes, more than the limit of 64"})
+        );
+    }
+
+    #[test]
+    fn test_validate_against_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "method": { "type": "string", "enum": ["get", "post"] }
+            },
+            "required": ["url", "method"]
+        });
+        let args = |value: Value| value.as_object().cloned();
+
+        validate_against_schema(
+            "fetch",
+            args(json!({"url": "https://example.com", "method": "get"})).as_ref(),
+            &schema,
+        )
+        .unwrap();
+        let err = validate_against_schema("fetch", args(json!({"url": 1})).as_ref(), &schema)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid arguments for tool 'fetch': missing required property 'method'; /url: expected string, found number"
+        );
+        assert_eq!(
+            err.data(),
+            json!({"code": "validation_failed", "tool": "fetch", "errors": [
+                {"path": "", "message": "missing required property 'method'"},
+                {"path": "/url", "message": "expected string, found number"}
+            ]})
+        );
+        assert_eq!(
+            validate_against_schema("fetch", None, &schema)
+                .unwrap_err()
+                .violations
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_call_limit_overrides() {
+        let settings = CallLimitSettings::new(
+            CallLimits::default(),
+            [(
+                "fetch-rs".to_string(),
+                CallLimitOverrides {
+                    max_argument_size: Some(4096),
+                    sanitize_output: Some(false),
+                    ..Default::default()
+                },
+            )],
+        );
+        let fetch = settings.limits(Some("fetch-rs"));
+        assert_eq!(fetch.max_argument_size, 4096);
+        assert_eq!(fetch.max_argument_depth, 32);
+        assert!(!fetch.sanitize_output);
+        assert_eq!(settings.limits(Some("time")), CallLimits::default());
+        assert_eq!(settings.limits(None), CallLimits::default());
+    }
+
+    #[test]
+    fn test_sanitize_output() {
+        assert!(matches!(sanitize_output("plain\ntext"), Cow::Borrowed(_)));
+        assert_eq!(
+            sanitize_output("\u{1b}[31mred\u{1b}[0m and \u{7}bell"),
+            "red and bell"
+        );
+        assert_eq!(
+            sanitize_output("\u{1b}]8;;https://evil.example\u{1b}\\link\u{1b}]8;;\u{7}"),
+            "link"
+        );
+        assert_eq!(sanitize_output("abc\u{202E}fed"), "abcfed");
+        // JSON results have their strings sanitized and stay JSON
+        assert_eq!(
+            sanitize_output(r#"{"title":"\u001b[2Jhi","n":1}"#),
+            r#"{"n":1,"title":"hi"}"#
+        );
+        assert!(matches!(
+            sanitize_output(r#"{"title":"hi"}"#),
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Per-tool token budgets with summarization through MCP sampling
+//!
+//! A tool can be given a token budget. When one of its results is over budget and the client
+//! supports sampling, the client's model is asked for a summary, which is returned instead of the
+//! result. Clients without sampling get the result truncated to the budget. Either way the full
+//! result is kept as a `wassette://results/...` resource and linked from the tool result.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use rmcp::model::{Content, CreateMessageRequestParam, Role, SamplingMessage};
+use rmcp::{Peer, RoleServer};
+use tracing::warn;
+
+use crate::resources::ResultStore;
+use crate::tokens::TokenHeuristic;
+
+/// Tool name of a budget that applies to every component tool without its own
+pub const ALL_TOOLS: &str = "*";
+
+const SUMMARY_SYSTEM_PROMPT: &str = "You summarize the output of a tool for an AI agent that \
+called it. Keep the facts, numbers, identifiers and errors the agent is likely to need, drop \
+repetition and boilerplate, and reply with the summary only.";
+
+/// A `<tool>=<tokens>` budget
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryBudget {
+    /// Tool the budget applies to, or `*` for every tool
+    pub tool: String,
+    /// Largest result, in estimated tokens, that is returned as it is
+    pub max_tokens: usize,
+}
+
+impl FromStr for SummaryBudget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tool, max_tokens) = s
+            .rsplit_once('=')
+            .with_context(|| format!("Summary budget must be <tool>=<tokens>: {s}"))?;
+        if tool.is_empty() {
+            return Err(anyhow!("Summary budget has no tool name: {s}"));
+        }
+        let max_tokens = max_tokens
+            .parse()
+            .with_context(|| format!("Invalid token count in summary budget: {s}"))?;
+        Ok(Self {
+            tool: tool.to_string(),
+            max_tokens,
+        })
+    }
+}
+
+/// The token budgets of component tools
+#[derive(Debug, Clone, Default)]
+pub struct SummarizeSettings {
+    budgets: HashMap<String, usize>,
+}
+
+impl SummarizeSettings {
+    /// Creates settings from a list of budgets. Later budgets for the same tool win.
+    pub fn new(budgets: impl IntoIterator<Item = SummaryBudget>) -> Self {
+        Self {
+            budgets: budgets
+                .into_iter()
+                .map(|budget| (budget.tool, budget.max_tokens))
+                .collect(),
+        }
+    }
+
+    /// The budget of `tool`, falling back to the budget for every tool
+    pub fn budget(&self, tool: &str) -> Option<usize> {
+        self.budgets
+            .get(tool)
+            .or_else(|| self.budgets.get(ALL_TOOLS))
+            .copied()
+    }
+}
+
+/// Whether the client announced the sampling capability
+pub fn supports_sampling(peer: &Peer<RoleServer>) -> bool {
+    peer.peer_info()
+        .is_some_and(|info| info.capabilities.sampling.is_some())
+}
+
+/// Asks the client's model to summarize a result of `tool` in at most `max_tokens` tokens
+pub async fn request_summary(
+    peer: &Peer<RoleServer>,
+    tool: &str,
+    text: &str,
+    max_tokens: usize,
+) -> Result<String> {
+    let result = peer
+        .create_message(CreateMessageRequestParam {
+            messages: vec![SamplingMessage {
+                role: Role::User,
+                content: Content::text(format!(
+                    "Summarize this output of the `{tool}` tool in at most {max_tokens} tokens:\n\n{text}"
+                )),
+            }],
+            model_preferences: None,
+            system_prompt: Some(SUMMARY_SYSTEM_PROMPT.to_string()),
+            include_context: None,
+            temperature: None,
+            max_tokens: u32::try_from(max_tokens).unwrap_or(u32::MAX),
+            stop_sequences: None,
+            metadata: None,
+        })
+        .await
+        .context("Sampling request failed")?;
+    result
+        .message
+        .content
+        .as_text()
+        .map(|content| content.text.clone())
+        .ok_or_else(|| anyhow!("Client returned a summary that is not text"))
+}
+
+/// Shortens a result of `tool` that is over `budget`, keeping the full text in `results`.
+/// Returns the summary or truncated result followed by a link to the full result.
+pub(crate) async fn fit_to_budget(
+    tool: &str,
+    text: String,
+    budget: usize,
+    heuristic: &TokenHeuristic,
+    results: &ResultStore,
+    peer: Option<&Peer<RoleServer>>,
+) -> Vec<Content> {
+    let total = heuristic.estimate(&text);
+    let summary = match peer.filter(|peer| supports_sampling(peer)) {
+        Some(peer) => match request_summary(peer, tool, &text, budget).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                warn!(tool, error = %e, "Failed to summarize tool result, truncating it instead");
+                None
+            }
+        },
+        None => None,
+    };
+    let shortened = match summary {
+        Some(summary) => {
+            format!("[Summary of a result of about {total} estimated tokens]\n\n{summary}")
+        }
+        None => heuristic
+            .truncate(&text, budget)
+            .unwrap_or_else(|| text.clone()),
+    };
+
+    let uri = results.insert(tool, text);
+    vec![
+        Content::text(shortened),
+        Content::text(format!("[Full result: {uri}]")),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_settings() {
+        assert_eq!(
+            "fetch=2000".parse::<SummaryBudget>().unwrap(),
+            SummaryBudget {
+                tool: "fetch".to_string(),
+                max_tokens: 2000,
+            }
+        );
+        assert!("fetch".parse::<SummaryBudget>().is_err());
+        assert!("=2000".parse::<SummaryBudget>().is_err());
+        assert!("fetch=lots".parse::<SummaryBudget>().is_err());
+
+        let settings = SummarizeSettings::new(["*=8000", "fetch=2000"].map(|s| s.parse().unwrap()));
+        assert_eq!(settings.budget("fetch"), Some(2000));
+        assert_eq!(settings.budget("read-file"), Some(8000));
+        assert_eq!(SummarizeSettings::default().budget("fetch"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fit_to_budget_without_sampling() {
+        let results = ResultStore::default();
+        let text = "first paragraph\n\nsecond paragraph".to_string();
+
+        let contents = fit_to_budget(
+            "fetch",
+            text.clone(),
+            20,
+            &TokenHeuristic::Chars(1.0),
+            &results,
+            None,
+        )
+        .await;
+
+        assert_eq!(
+            contents[0].as_text().unwrap().text,
+            "first paragraph\n\n[Truncated to about 15 of 33 estimated tokens]"
+        );
+        assert_eq!(
+            contents[1].as_text().unwrap().text,
+            "[Full result: wassette://results/1]"
+        );
+        assert_eq!(results.get("wassette://results/1"), Some(text));
+    }
+}
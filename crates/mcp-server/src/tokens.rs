@@ -0,0 +1,159 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Approximate token counting for tool results
+//!
+//! Agents have to fit tool results into a context window, but the exact count depends on the
+//! client's tokenizer, which the server doesn't know. The estimates here use a simple heuristic
+//! that is close enough to budget with.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+/// Name of the call argument that caps the size of a component tool's result
+pub const MAX_TOKENS_ARGUMENT: &str = "max_tokens";
+
+/// How to turn text into an approximate token count
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenHeuristic {
+    /// One token per this many characters
+    Chars(f64),
+    /// This many tokens per whitespace-separated word
+    Words(f64),
+}
+
+impl Default for TokenHeuristic {
+    /// About four characters per token, which is typical for English text and code
+    fn default() -> Self {
+        TokenHeuristic::Chars(4.0)
+    }
+}
+
+impl FromStr for TokenHeuristic {
+    type Err = anyhow::Error;
+
+    /// Parses `chars:<characters per token>` or `words:<tokens per word>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, ratio) = s
+            .split_once(':')
+            .with_context(|| format!("Token heuristic must be chars:<n> or words:<n>: {s}"))?;
+        let ratio: f64 = ratio
+            .parse()
+            .with_context(|| format!("Invalid token heuristic ratio: {ratio}"))?;
+        if !ratio.is_finite() || ratio <= 0.0 {
+            bail!("Token heuristic ratio must be positive: {s}");
+        }
+        match kind {
+            "chars" => Ok(TokenHeuristic::Chars(ratio)),
+            "words" => Ok(TokenHeuristic::Words(ratio)),
+            _ => bail!("Unknown token heuristic '{kind}', expected chars or words"),
+        }
+    }
+}
+
+impl TokenHeuristic {
+    /// Estimates the number of tokens in `text`
+    pub fn estimate(&self, text: &str) -> usize {
+        let tokens = match self {
+            TokenHeuristic::Chars(chars_per_token) => text.chars().count() as f64 / chars_per_token,
+            TokenHeuristic::Words(tokens_per_word) => {
+                text.split_whitespace().count() as f64 * tokens_per_word
+            }
+        };
+        tokens.ceil() as usize
+    }
+
+    /// Shortens `text` to about `max_tokens` tokens. The cut is made at the last paragraph break
+    /// that fits, falling back to a line break, then a word break. Returns `None` if the text
+    /// already fits.
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> Option<String> {
+        let total = self.estimate(text);
+        if total <= max_tokens {
+            return None;
+        }
+
+        let fits = |end: usize| self.estimate(&text[..end]) <= max_tokens;
+        let paragraphs: Vec<usize> = text.match_indices("\n\n").map(|(i, _)| i).collect();
+        let lines: Vec<usize> = text.match_indices('\n').map(|(i, _)| i).collect();
+        let words: Vec<usize> = text
+            .char_indices()
+            .filter(|(_, c)| c.is_whitespace())
+            .map(|(i, _)| i)
+            .collect();
+        let chars: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+
+        let end = [paragraphs, lines, words, chars]
+            .iter()
+            .find_map(|boundaries| last_fitting(boundaries, fits))
+            .unwrap_or(0);
+        let kept = text[..end].trim_end();
+        Some(format!(
+            "{kept}\n\n[Truncated to about {} of {total} estimated tokens]",
+            self.estimate(kept)
+        ))
+    }
+}
+
+/// Finds the last boundary whose prefix fits, or `None` if none does. The estimate only grows
+/// with the prefix, so a binary search is enough.
+fn last_fitting(boundaries: &[usize], fits: impl Fn(usize) -> bool) -> Option<usize> {
+    let count = boundaries.partition_point(|&end| fits(end));
+    match count {
+        0 => None,
+        n => Some(boundaries[n - 1]).filter(|&end| end > 0),
+    }
+}
+
+/// Token settings for component tool results
+#[derive(Debug, Clone, Default)]
+pub struct TokenSettings {
+    /// Heuristic used for estimates and for `max_tokens`
+    pub heuristic: TokenHeuristic,
+    /// Whether to add the estimated token count to every component tool result
+    pub annotate: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_heuristic() {
+        assert_eq!(
+            "chars:3.5".parse::<TokenHeuristic>().unwrap(),
+            TokenHeuristic::Chars(3.5)
+        );
+        assert_eq!(
+            "words:1.3".parse::<TokenHeuristic>().unwrap(),
+            TokenHeuristic::Words(1.3)
+        );
+        assert!("chars".parse::<TokenHeuristic>().is_err());
+        assert!("chars:0".parse::<TokenHeuristic>().is_err());
+        assert!("bytes:4".parse::<TokenHeuristic>().is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(TokenHeuristic::Chars(4.0).estimate("abcdefghi"), 3);
+        assert_eq!(TokenHeuristic::Words(1.5).estimate("three small words"), 5);
+        assert_eq!(TokenHeuristic::default().estimate(""), 0);
+    }
+
+    #[test]
+    fn test_truncate_at_paragraph() {
+        let heuristic = TokenHeuristic::Chars(1.0);
+        let text = "first paragraph\n\nsecond paragraph\n\nthird paragraph";
+
+        assert_eq!(heuristic.truncate(text, 1000), None);
+        assert_eq!(
+            heuristic.truncate(text, 40).unwrap(),
+            "first paragraph\n\nsecond paragraph\n\n[Truncated to about 33 of 50 estimated tokens]"
+        );
+        // Without a paragraph break that fits, cut between words
+        assert_eq!(
+            heuristic.truncate(text, 10).unwrap(),
+            "first\n\n[Truncated to about 5 of 50 estimated tokens]"
+        );
+    }
+}
@@ -3,26 +3,58 @@
 
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::Result;
-use rmcp::model::{CallToolRequestParam, CallToolResult, Content, Tool};
+use rmcp::model::{
+    CallToolRequestParam, CallToolResult, Content, LoggingLevel, LoggingMessageNotificationParam,
+    RawContent, Tool,
+};
 use rmcp::{Peer, RoleServer};
 use serde_json::{json, Value};
 use tracing::{debug, error, info, instrument};
-use wassette::LifecycleManager;
+use wassette::{ComponentNotFound, LifecycleManager};
 
+use crate::batch::{batch_entries, handle_call_tools_batch, BATCH_TOOL};
 use crate::components::{
-    extract_args_from_request, get_component_tools, handle_component_call, handle_list_components,
-    handle_load_component, handle_unload_component,
+    extract_args_from_request, get_component_tools, handle_component_call,
+    handle_get_network_activity, handle_list_components, handle_load_component,
+    handle_load_component_cli, handle_purge_component, handle_restore_component,
+    handle_restore_component_cli, handle_search_components, handle_search_history,
+    handle_semantic_recall, handle_unload_component, handle_unload_component_cli,
+    tool_input_schema,
 };
-
-/// Handles a request to list available tools.
-#[instrument(skip(lifecycle_manager))]
-pub async fn handle_tools_list(lifecycle_manager: &LifecycleManager) -> Result<Value> {
+use crate::errors::ToolError;
+use crate::list_changes::ListChanges;
+use crate::logging::{LogFilter, SECURITY_LOGGER};
+use crate::proxy::Downstreams;
+use crate::resources::ResultStore;
+use crate::roots::{handle_grant_root_access, handle_revoke_root_access, RootGrants};
+use crate::scheduler::{Priority, Scheduler, BUILTIN_QUEUE};
+use crate::secrets::{get_secret_tools, handle_secret_tool, is_secret_tool};
+use crate::security::{
+    sanitize_output, validate_against_schema, validate_arguments, validate_tool_name, ApiKeys,
+    CallLimitSettings, RateLimiter,
+};
+use crate::summarize::SummarizeSettings;
+use crate::tokens::TokenSettings;
+
+/// Handles a request to list available tools: the component tools, the built-in ones, including
+/// the secret management tools if `options` turns them on, and those proxied from downstream
+/// servers.
+#[instrument(skip_all)]
+pub async fn handle_tools_list(
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+) -> Result<Value> {
     debug!("Handling tools list request");
 
     let mut tools = get_component_tools(lifecycle_manager).await?;
     tools.extend(get_builtin_tools());
+    if options.secret_tools {
+        tools.extend(get_secret_tools());
+    }
+    tools.extend(options.downstreams.tools());
     debug!(num_tools = %tools.len(), "Retrieved tools");
 
     let response = rmcp::model::ListToolsResult {
@@ -33,63 +65,309 @@ pub async fn handle_tools_list(lifecycle_manager: &LifecycleManager) -> Result<V
     Ok(serde_json::to_value(response)?)
 }
 
-/// Handles a tool call request.
-#[instrument(skip_all, fields(method_name = %req.name))]
+/// Server-wide settings that apply to every tool call
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallOptions {
+    /// Token estimation and `max_tokens` handling for component tool results
+    pub tokens: TokenSettings,
+    /// Per-tool token budgets above which results are summarized
+    pub summarize: SummarizeSettings,
+    /// Full results of summarized or truncated calls, served as resources
+    pub results: ResultStore,
+    /// Per-tool rate limits. Calls over a limit fail with
+    /// [`crate::security::RateLimitExceeded`].
+    pub rate_limits: RateLimiter,
+    /// Concurrency limit and per-tool priority classes
+    pub scheduler: Scheduler,
+    /// Priority class of calls of tools without a class of their own
+    pub priority: Priority,
+    /// Tool list change notifications of the session the calls come from
+    pub list_changes: ListChanges,
+    /// Roots of the session's client and the components that may use them
+    pub roots: RootGrants,
+    /// API keys that HTTP clients authenticate with, and what each of them may call
+    pub api_keys: ApiKeys,
+    /// Downstream MCP servers whose tools are proxied
+    pub downstreams: Downstreams,
+    /// Limits on the arguments and results of calls, with the overrides of components. Calls
+    /// over a limit fail with [`crate::security::InvalidToolCall`].
+    pub call_limits: CallLimitSettings,
+    /// The log notifications the session's client wants, set with `logging/setLevel`
+    pub log_filter: LogFilter,
+    /// Whether clients may manage the secrets of components with the
+    /// [`crate::secrets::SECRET_TOOLS`]
+    pub secret_tools: bool,
+}
+
+/// Handles a tool call request. The call is checked against the tools and rate limits of the
+/// API key the client authenticated with, if any, and so is every call of a batch.
 pub async fn handle_tools_call(
     req: CallToolRequestParam,
     lifecycle_manager: &LifecycleManager,
     server_peer: Peer<RoleServer>,
+    options: &ToolCallOptions,
+    api_key: Option<&str>,
+) -> Result<Value> {
+    if let Some(api_key) = api_key {
+        check_api_key(&req, lifecycle_manager, options, api_key).await?;
+        if req.name == BATCH_TOOL {
+            for entry in batch_entries(&req)? {
+                check_api_key(&entry, lifecycle_manager, options, api_key).await?;
+            }
+        }
+    }
+    execute_tool_call(req, lifecycle_manager, Some(server_peer), options).await
+}
+
+/// Fails with [`crate::security::ToolAccessDenied`] or [`crate::security::RateLimitExceeded`]
+/// if the key called `api_key` may not make the call now
+async fn check_api_key(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+    api_key: &str,
+) -> Result<()> {
+    let component = match options.downstreams.server_of(&req.name) {
+        Some(server) => Some(server.to_string()),
+        None => lifecycle_manager
+            .get_component_id_for_tool(&req.name)
+            .await
+            .ok(),
+    };
+    let result = options
+        .api_keys
+        .check(api_key, &req.name, component.as_deref());
+    if let Err(e) = &result {
+        info!(api_key, error = %e, "Rejected tool call");
+    }
+    result
+}
+
+/// Runs a tool call through the same pipeline as MCP `tools/call` requests. Frontends without an
+/// MCP client to notify, such as the message bus, pass `None` for `server_peer`.
+#[instrument(skip_all, fields(tool_name = %req.name))]
+pub async fn execute_tool_call(
+    req: CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+    server_peer: Option<Peer<RoleServer>>,
+    options: &ToolCallOptions,
 ) -> Result<Value> {
     info!("Handling tool call");
 
-    let result = match req.name.as_ref() {
-        "load-component" => handle_load_component(&req, lifecycle_manager, server_peer).await,
-        "unload-component" => handle_unload_component(&req, lifecycle_manager, server_peer).await,
-        "list-components" => handle_list_components(lifecycle_manager).await,
-        "get-policy" => handle_get_policy(&req, lifecycle_manager).await,
-        "grant-storage-permission" => {
+    validate_tool_name(&req.name)?;
+    // The component or downstream server of the tool, or `None` for built-in tools
+    let component = match options.downstreams.server_of(&req.name) {
+        Some(server) => Some(server.to_string()),
+        None => lifecycle_manager
+            .get_component_id_for_tool(&req.name)
+            .await
+            .ok(),
+    };
+    let limits = options.call_limits.limits(component.as_deref());
+    validate_arguments(&req.name, req.arguments.as_ref(), &limits)?;
+    // Arguments that don't fit a component tool's schema would only fail inside the guest
+    if let (Some(component_id), None) = (&component, options.downstreams.server_of(&req.name)) {
+        if let Some(schema) = tool_input_schema(lifecycle_manager, component_id, &req.name).await {
+            validate_against_schema(&req.name, req.arguments.as_ref(), &schema)?;
+        }
+    }
+    options.rate_limits.check(&req.name)?;
+    // Looking at the queue must not wait in it, and a batch would hold a slot its calls wait for
+    let _slot =
+        if options.scheduler.is_limited() && req.name != "get-call-queue" && req.name != BATCH_TOOL
+        {
+            let queue = component.as_deref().unwrap_or(BUILTIN_QUEUE);
+            let priority = options.scheduler.priority(&req.name, options.priority);
+            Some(options.scheduler.acquire(queue, priority).await)
+        } else {
+            None
+        };
+
+    // Requests the network policy blocks from now on are the ones of this call
+    let started = SystemTime::now();
+    let result = match (req.name.as_ref(), server_peer.clone()) {
+        ("load-component", Some(peer)) => {
+            handle_load_component(&req, lifecycle_manager, peer, &options.list_changes).await
+        }
+        ("load-component", None) => handle_load_component_cli(&req, lifecycle_manager).await,
+        ("unload-component", Some(peer)) => {
+            handle_unload_component(&req, lifecycle_manager, peer, &options.list_changes).await
+        }
+        ("unload-component", None) => handle_unload_component_cli(&req, lifecycle_manager).await,
+        ("restore-component", Some(peer)) => {
+            handle_restore_component(&req, lifecycle_manager, peer, &options.list_changes).await
+        }
+        ("restore-component", None) => handle_restore_component_cli(&req, lifecycle_manager).await,
+        ("purge-component", _) => handle_purge_component(&req, lifecycle_manager).await,
+        ("list-components", _) => handle_list_components(lifecycle_manager).await,
+        ("search-history", _) => handle_search_history(&req, lifecycle_manager).await,
+        ("semantic-recall", _) => handle_semantic_recall(&req, lifecycle_manager).await,
+        ("search-components", _) => handle_search_components(&req, lifecycle_manager).await,
+        ("get-policy", _) => handle_get_policy(&req, lifecycle_manager).await,
+        ("grant-storage-permission", _) => {
             handle_grant_storage_permission(&req, lifecycle_manager).await
         }
-        "grant-network-permission" => {
+        ("grant-network-permission", _) => {
             handle_grant_network_permission(&req, lifecycle_manager).await
         }
-        "grant-environment-variable-permission" => {
+        ("grant-environment-variable-permission", _) => {
             handle_grant_environment_variable_permission(&req, lifecycle_manager).await
         }
-        "revoke-storage-permission" => {
+        ("revoke-storage-permission", _) => {
             handle_revoke_storage_permission(&req, lifecycle_manager).await
         }
-        "revoke-network-permission" => {
+        ("revoke-network-permission", _) => {
             handle_revoke_network_permission(&req, lifecycle_manager).await
         }
-        "revoke-environment-variable-permission" => {
+        ("revoke-environment-variable-permission", _) => {
             handle_revoke_environment_variable_permission(&req, lifecycle_manager).await
         }
-        "reset-permission" => handle_reset_permission(&req, lifecycle_manager).await,
-        _ => handle_component_call(&req, lifecycle_manager).await,
+        ("reset-permission", _) => handle_reset_permission(&req, lifecycle_manager).await,
+        ("grant-root-access", _) => {
+            handle_grant_root_access(
+                &req,
+                lifecycle_manager,
+                server_peer.as_ref(),
+                &options.roots,
+            )
+            .await
+        }
+        ("revoke-root-access", _) => {
+            handle_revoke_root_access(&req, lifecycle_manager, &options.roots).await
+        }
+        ("set-config", _) => handle_set_config(&req, lifecycle_manager).await,
+        (name, _) if is_secret_tool(name) => {
+            if options.secret_tools {
+                handle_secret_tool(&req, lifecycle_manager).await
+            } else {
+                Err(anyhow::anyhow!(
+                    "Tool {name} is disabled; start the server with secret_tools turned on to manage secrets over MCP"
+                ))
+            }
+        }
+        ("get-call-queue", _) => handle_get_call_queue(&options.scheduler),
+        ("get-network-activity", _) => handle_get_network_activity(&req, lifecycle_manager).await,
+        (BATCH_TOOL, _) => {
+            handle_call_tools_batch(&req, lifecycle_manager, server_peer.as_ref(), options).await
+        }
+        (name, _) if options.downstreams.server_of(name).is_some() => {
+            options.downstreams.call(&req, lifecycle_manager).await
+        }
+        _ => handle_component_call(&req, lifecycle_manager, options, server_peer.as_ref()).await,
     };
 
     if let Err(ref e) = result {
         error!(error = ?e, "Tool call failed");
+    } else if let (Some(server_peer), Some(notification)) =
+        (&server_peer, permission_change_notification(&req))
+    {
+        if options.log_filter.allows(&notification) {
+            if let Err(e) = server_peer.notify_logging_message(notification).await {
+                error!(error = %e, "Failed to send permission change notification");
+            }
+        }
+        // Clients get the tool list change of the permission change before the response
+        options.list_changes.flush().await;
     }
 
-    match result {
-        Ok(result) => Ok(serde_json::to_value(result)?),
-        Err(e) => {
-            let error_text = format!("Error: {e}");
-            let contents = vec![Content::text(error_text)];
+    // Built-in tools only return what the server wrote itself
+    let sanitize = component.is_some() && limits.sanitize_output;
+    // A component call that fails after its policy blocked a request most likely failed because
+    // of it, whether it trapped or returned an error
+    let denied_requests = match &component {
+        Some(component_id) if options.downstreams.server_of(&req.name).is_none() => {
+            lifecycle_manager.denied_network_requests(component_id, started)
+        }
+        _ => Vec::new(),
+    };
+    let mut error = match result {
+        Ok(mut result) => match (returned_error(&result), &component) {
+            (Some(err), Some(component_id)) if !denied_requests.is_empty() => {
+                ToolError::policy_denied(component_id, &denied_requests, err)
+            }
+            _ => {
+                if sanitize {
+                    sanitize_contents(&mut result);
+                }
+                return Ok(serde_json::to_value(result)?);
+            }
+        },
+        Err(e) => match &component {
+            Some(component_id) if !denied_requests.is_empty() => {
+                ToolError::policy_denied(component_id, &denied_requests, e.to_string())
+            }
+            _ => ToolError::from_error(&e),
+        },
+    };
+    if sanitize {
+        error.message = sanitize_output(&error.message).into_owned();
+    }
+    Ok(serde_json::to_value(error.into_result())?)
+}
 
-            let error_result = CallToolResult {
-                content: Some(contents),
-                structured_content: None,
-                is_error: Some(true),
-            };
-            Ok(serde_json::to_value(error_result)?)
+/// The error a `result<_, E>` tool returned as `{"err": ...}`, if it returned one
+fn returned_error(result: &CallToolResult) -> Option<String> {
+    let text = match &result.content.as_ref()?.first()?.raw {
+        RawContent::Text(text) => &text.text,
+        _ => return None,
+    };
+    let value = serde_json::from_str::<Value>(text).ok()?;
+    match value.as_object()? {
+        object if object.len() == 1 => match object.get("err")? {
+            Value::String(err) => Some(err.clone()),
+            err => Some(err.to_string()),
+        },
+        _ => None,
+    }
+}
+
+/// Sanitizes the text contents of `result` with [`sanitize_output`]
+fn sanitize_contents(result: &mut CallToolResult) {
+    for content in result.content.iter_mut().flatten() {
+        if let RawContent::Text(text) = &mut content.raw {
+            if let Cow::Owned(sanitized) = sanitize_output(&text.text) {
+                text.text = sanitized;
+            }
         }
     }
 }
 
-fn get_builtin_tools() -> Vec<Tool> {
+/// Builds the logging notification announcing a successful permission change,
+/// or `None` if the tool call does not alter a component's capabilities.
+fn permission_change_notification(
+    req: &CallToolRequestParam,
+) -> Option<LoggingMessageNotificationParam> {
+    let args = extract_args_from_request(req).ok()?;
+    let operation = match req.name.as_ref() {
+        "grant-storage-permission"
+        | "grant-network-permission"
+        | "grant-environment-variable-permission" => "grant",
+        // Without `confirm` the call only shows what it would grant
+        "grant-root-access" if args.get("confirm").and_then(Value::as_bool) == Some(true) => {
+            "grant"
+        }
+        "revoke-storage-permission"
+        | "revoke-network-permission"
+        | "revoke-environment-variable-permission"
+        | "revoke-root-access" => "revoke",
+        "reset-permission" => "reset",
+        _ => return None,
+    };
+
+    Some(LoggingMessageNotificationParam {
+        level: LoggingLevel::Info,
+        logger: Some(SECURITY_LOGGER.to_string()),
+        data: json!({
+            "event": "permissions_changed",
+            "operation": operation,
+            "tool": req.name,
+            "component_id": args.get("component_id"),
+            "details": args.get("details"),
+        }),
+    })
+}
+
+pub(crate) fn get_builtin_tools() -> Vec<Tool> {
     debug!("Getting builtin tools");
     vec![
         Tool {
@@ -113,7 +391,47 @@ fn get_builtin_tools() -> Vec<Tool> {
         Tool {
             name: Cow::Borrowed("unload-component"),
             description: Some(Cow::Borrowed(
-                "Unloads a tool or component.",
+                "Unloads a tool or component. The component is moved to the trash and can be restored with restore-component, unless purge is set.",
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "purge": {
+                            "type": "boolean",
+                            "description": "Delete the component and everything derived from it right away instead of moving it to the trash (default: false)"
+                        }
+                    },
+                    "required": ["id"]
+                }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("restore-component"),
+            description: Some(Cow::Borrowed(
+                "Restores a previously unloaded component, together with its policy, from the trash.",
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"}
+                    },
+                    "required": ["id"]
+                }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("purge-component"),
+            description: Some(Cow::Borrowed(
+                "Permanently deletes a previously unloaded component from the trash. This cannot be undone.",
             )),
             input_schema: Arc::new(
                 serde_json::from_value(json!({
@@ -208,7 +526,7 @@ fn get_builtin_tools() -> Vec<Tool> {
         Tool {
             name: Cow::Borrowed("grant-network-permission"),
             description: Some(Cow::Borrowed(
-                "Grants network access permission to a component, allowing it to make network requests to specific hosts."
+                "Grants network access permission to a component, allowing it to make network requests to specific hosts. Pass 'udp' (and optionally 'port') instead of 'host' to allow UDP datagrams to a specific host, IP address or CIDR range, or 'tcp' to allow raw TCP connections to one (e.g. tcp://db.internal:5432)."
             )),
             input_schema: Arc::new(
                 serde_json::from_value(json!({
@@ -224,9 +542,27 @@ fn get_builtin_tools() -> Vec<Tool> {
                           "host": { 
                             "type": "string",
                             "description": "Host to grant network access to"
+                          },
+                          "udp": {
+                            "type": "string",
+                            "description": "Host, IP address or CIDR range to allow UDP datagrams to (e.g. pool.ntp.org)"
+                          },
+                          "tcp": {
+                            "type": "string",
+                            "description": "Host, IP address, CIDR range or tcp://host:port endpoint to allow raw TCP connections to (e.g. tcp://db.internal:5432)"
+                          },
+                          "port": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 65535,
+                            "description": "Remote UDP or TCP port (e.g. 123). Every port is allowed when omitted"
                           }
                         },
-                        "required": ["host"],
+                        "anyOf": [
+                          { "required": ["host"] },
+                          { "required": ["udp"] },
+                          { "required": ["tcp"] }
+                        ],
                         "additionalProperties": false
                       }
                     },
@@ -320,9 +656,27 @@ fn get_builtin_tools() -> Vec<Tool> {
                           "host": { 
                             "type": "string",
                             "description": "Host to revoke network access from"
+                          },
+                          "udp": {
+                            "type": "string",
+                            "description": "Host, IP address or CIDR range of the UDP grant to revoke"
+                          },
+                          "tcp": {
+                            "type": "string",
+                            "description": "Target of the TCP grant to revoke, as it was granted"
+                          },
+                          "port": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 65535,
+                            "description": "Remote port of the UDP or TCP grant to revoke"
                           }
                         },
-                        "required": ["host"],
+                        "anyOf": [
+                          { "required": ["host"] },
+                          { "required": ["udp"] },
+                          { "required": ["tcp"] }
+                        ],
                         "additionalProperties": false
                       }
                     },
@@ -368,7 +722,7 @@ fn get_builtin_tools() -> Vec<Tool> {
         Tool {
             name: Cow::Borrowed("reset-permission"),
             description: Some(Cow::Borrowed(
-                "Resets all permissions for a component, removing all granted permissions and returning it to the default state."
+                "Resets all permissions for a component, dropping every permission granted or revoked at runtime and reverting to the component's attached policy, or to no permissions if it has none."
             )),
             input_schema: Arc::new(
                 serde_json::from_value(json!({
@@ -386,6 +740,274 @@ fn get_builtin_tools() -> Vec<Tool> {
             output_schema: None,
             annotations: None,
         },
+        Tool {
+            name: Cow::Borrowed("set-config"),
+            description: Some(Cow::Borrowed(
+                "Sets a wasi:config variable of a loaded component, or removes it when value is null. The next call of the component sees the change, without reloading it. Values last until the component is unloaded or the server restarts, and should not be secrets."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "component_id": {
+                        "type": "string",
+                        "description": "ID of the component to configure"
+                      },
+                      "key": {
+                        "type": "string",
+                        "description": "Name of the config variable"
+                      },
+                      "value": {
+                        "type": ["string", "null"],
+                        "description": "New value, or null to remove the value set earlier"
+                      }
+                    },
+                    "required": ["component_id", "key"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("search-history"),
+            description: Some(Cow::Borrowed(
+                "Searches earlier tool results, component tool docs and component logs, so a result can be found again without calling the tool again. Only available when the server indexes history."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "query": {
+                        "type": "string",
+                        "description": "Full-text query, e.g. weather AND berlin, or \"an exact phrase\""
+                      },
+                      "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Most hits to return (default: 10)"
+                      },
+                      "kind": {
+                        "type": "string",
+                        "enum": ["result", "doc", "log"],
+                        "description": "Only return tool results, tool docs or log messages"
+                      },
+                      "component_id": {
+                        "type": "string",
+                        "description": "Only return entries of this component"
+                      },
+                      "include_text": {
+                        "type": "boolean",
+                        "description": "Return the full indexed text of every hit, not just a snippet (default: false)"
+                      }
+                    },
+                    "required": ["query"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("semantic-recall"),
+            description: Some(Cow::Borrowed(
+                "Finds earlier tool results that are similar to a text, with the component, tool, arguments and policy digest that produced each. Only available when the server runs with semantic recall."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "query": {
+                        "type": "string",
+                        "description": "Text to find similar results for"
+                      },
+                      "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Most results to return (default: 5)"
+                      },
+                      "component_id": {
+                        "type": "string",
+                        "description": "Only return results of this component"
+                      },
+                      "include_text": {
+                        "type": "boolean",
+                        "description": "Return the full stored text of every result, not just an excerpt (default: false)"
+                      }
+                    },
+                    "required": ["query"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("search-components"),
+            description: Some(Cow::Borrowed(
+                "Searches the component indexes the server is configured with for components that match keywords, and returns their names, descriptions and the refs to load them from with load-component. Use it to find a component for a task that no loaded tool can do, and propose loading it to the user."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "query": {
+                        "type": "string",
+                        "description": "Keywords to search for, e.g. 'weather forecast'. Components that match more of them come first; an empty query lists every component."
+                      },
+                      "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": 50,
+                        "description": "Most components to return (default: 10)"
+                      }
+                    },
+                    "required": ["query"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("grant-root-access"),
+            description: Some(Cow::Borrowed(
+                "Grants a component storage access to the roots the MCP client shared, and keeps the grants in sync when the roots change. Without confirm, it only returns the directories it would grant: show them to the user and call again with confirm set to true only if the user agrees."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "component_id": {
+                        "type": "string",
+                        "description": "ID of the component that may access the roots"
+                      },
+                      "roots": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "URIs of the roots to grant (default: all roots, including ones the client adds later)"
+                      },
+                      "access": {
+                        "type": "array",
+                        "items": { "type": "string", "enum": ["read", "write"] },
+                        "description": "Access to the roots (default: [\"read\"])"
+                      },
+                      "confirm": {
+                        "type": "boolean",
+                        "description": "Whether the user agreed to the grants (default: false)"
+                      }
+                    },
+                    "required": ["component_id"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("revoke-root-access"),
+            description: Some(Cow::Borrowed(
+                "Revokes the storage access to roots that grant-root-access gave a component, and stops following the roots for it."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "component_id": {
+                        "type": "string",
+                        "description": "ID of the component"
+                      }
+                    },
+                    "required": ["component_id"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("get-call-queue"),
+            description: Some(Cow::Borrowed(
+                "Shows how many tool calls of each component run and wait for a slot, with each component's share of the slots. Only available when the server limits concurrent calls."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {}
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed("get-network-activity"),
+            description: Some(Cow::Borrowed(
+                "Lists the most recent HTTP requests of components, newest first, with method, host, path template, status, response size and duration. Identifiers and tokens in paths are replaced by {id} and {redacted}. Requests blocked by a network policy are included with the reason."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "component_id": {
+                        "type": "string",
+                        "description": "Only return requests of this component"
+                      },
+                      "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Most requests to return (default: 50)"
+                      }
+                    }
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
+        Tool {
+            name: Cow::Borrowed(BATCH_TOOL),
+            description: Some(Cow::Borrowed(
+                "Calls several tools in one request and returns the result or error of each call, in order. Use it for independent calls, such as several fetches, instead of calling the tools one by one. A failed call doesn't stop the others."
+            )),
+            input_schema: Arc::new(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "properties": {
+                      "calls": {
+                        "type": "array",
+                        "minItems": 1,
+                        "maxItems": crate::batch::MAX_BATCH_SIZE,
+                        "description": "The tool calls to make",
+                        "items": {
+                          "type": "object",
+                          "properties": {
+                            "tool": {
+                              "type": "string",
+                              "description": "Name of the tool to call"
+                            },
+                            "arguments": {
+                              "type": "object",
+                              "description": "Arguments of the call"
+                            }
+                          },
+                          "required": ["tool"]
+                        }
+                      },
+                      "max_parallel": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": crate::batch::MAX_BATCH_PARALLELISM,
+                        "description": "Most calls that run at the same time (default: 4)"
+                      }
+                    },
+                    "required": ["calls"]
+                  }))
+                .unwrap_or_default(),
+            ),
+            output_schema: None,
+            annotations: None,
+        },
     ]
 }
 
@@ -409,7 +1031,7 @@ pub async fn handle_get_policy(
         .await
         .is_some();
     if !component_exists {
-        return Err(anyhow::anyhow!("Component not found: {}", component_id));
+        return Err(ComponentNotFound::Component(component_id.to_string()).into());
     }
 
     let policy_info = lifecycle_manager.get_policy_info(component_id).await;
@@ -823,7 +1445,7 @@ pub async fn handle_reset_permission(
 
     info!("Resetting all permissions for component {}", component_id);
 
-    let result = lifecycle_manager.reset_permission(component_id).await;
+    let result = lifecycle_manager.reset_permissions(component_id).await;
 
     match result {
         Ok(()) => {
@@ -851,6 +1473,83 @@ pub async fn handle_reset_permission(
     }
 }
 
+#[instrument(skip(lifecycle_manager))]
+pub async fn handle_set_config(
+    req: &CallToolRequestParam,
+    lifecycle_manager: &LifecycleManager,
+) -> Result<CallToolResult> {
+    let args = extract_args_from_request(req)?;
+
+    let component_id = args
+        .get("component_id")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing required argument: 'component_id'"))?;
+    let key = args
+        .get("key")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing required argument: 'key'"))?;
+    let value = match args.get("value") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(value)) => Some(value.as_str()),
+        Some(_) => return Err(anyhow::anyhow!("'value' must be a string or null")),
+    };
+
+    info!(component_id, key, "Setting config variable");
+
+    lifecycle_manager
+        .set_component_config(component_id, key, value)
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!("Failed to set config for component {}: {}", component_id, e)
+        })?;
+
+    let status_text = serde_json::to_string(&json!({
+        "status": if value.is_some() { "config variable set" } else { "config variable removed" },
+        "component_id": component_id,
+        "key": key,
+        "config": lifecycle_manager.component_config(component_id).await
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(status_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
+pub fn handle_get_call_queue(scheduler: &Scheduler) -> Result<CallToolResult> {
+    let Some((limit, queues)) = scheduler.queue_stats() else {
+        return Err(anyhow::anyhow!(
+            "Tool calls are not queued, the server runs without a concurrency limit"
+        ));
+    };
+    let components = queues
+        .iter()
+        .map(|queue| {
+            json!({
+                "component": queue.component,
+                "weight": queue.weight,
+                "running": queue.running,
+                "waiting": queue.waiting,
+                "served": queue.served,
+                "longest_wait_ms": queue.longest_wait.as_millis() as u64,
+            })
+        })
+        .collect::<Vec<_>>();
+    let status_text = serde_json::to_string(&json!({
+        "max_concurrent_calls": limit,
+        "running": queues.iter().map(|queue| queue.running).sum::<usize>(),
+        "waiting": queues.iter().map(|queue| queue.waiting).sum::<usize>(),
+        "components": components,
+    }))?;
+
+    Ok(CallToolResult {
+        content: Some(vec![Content::text(status_text)]),
+        structured_content: None,
+        is_error: None,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -858,9 +1557,11 @@ mod tests {
     #[test]
     fn test_get_builtin_tools() {
         let tools = get_builtin_tools();
-        assert_eq!(tools.len(), 11);
+        assert_eq!(tools.len(), 22);
         assert!(tools.iter().any(|t| t.name == "load-component"));
         assert!(tools.iter().any(|t| t.name == "unload-component"));
+        assert!(tools.iter().any(|t| t.name == "restore-component"));
+        assert!(tools.iter().any(|t| t.name == "purge-component"));
         assert!(tools.iter().any(|t| t.name == "list-components"));
         assert!(tools.iter().any(|t| t.name == "get-policy"));
         assert!(tools.iter().any(|t| t.name == "grant-storage-permission"));
@@ -874,6 +1575,59 @@ mod tests {
             .iter()
             .any(|t| t.name == "revoke-environment-variable-permission"));
         assert!(tools.iter().any(|t| t.name == "reset-permission"));
+        assert!(tools.iter().any(|t| t.name == "set-config"));
+        assert!(tools.iter().any(|t| t.name == "search-history"));
+        assert!(tools.iter().any(|t| t.name == "semantic-recall"));
+        assert!(tools.iter().any(|t| t.name == "search-components"));
+        assert!(tools.iter().any(|t| t.name == "get-call-queue"));
+        assert!(tools.iter().any(|t| t.name == "get-network-activity"));
+        assert!(tools.iter().any(|t| t.name == "grant-root-access"));
+        assert!(tools.iter().any(|t| t.name == "revoke-root-access"));
+        assert!(tools.iter().any(|t| t.name == "call-tools-batch"));
+    }
+
+    #[test]
+    fn test_permission_change_notification() {
+        let mut args = serde_json::Map::new();
+        args.insert("component_id".to_string(), json!("test-component"));
+        args.insert("details".to_string(), json!({"host": "api.example.com"}));
+
+        let req = CallToolRequestParam {
+            name: "grant-network-permission".into(),
+            arguments: Some(args.clone()),
+        };
+        let notification = permission_change_notification(&req).unwrap();
+        assert_eq!(notification.level, LoggingLevel::Info);
+        assert_eq!(notification.data["event"], "permissions_changed");
+        assert_eq!(notification.data["operation"], "grant");
+        assert_eq!(notification.data["component_id"], "test-component");
+        assert_eq!(notification.data["details"]["host"], "api.example.com");
+
+        let req = CallToolRequestParam {
+            name: "reset-permission".into(),
+            arguments: Some(args.clone()),
+        };
+        let notification = permission_change_notification(&req).unwrap();
+        assert_eq!(notification.data["operation"], "reset");
+
+        let req = CallToolRequestParam {
+            name: "grant-root-access".into(),
+            arguments: Some(args.clone()),
+        };
+        assert!(permission_change_notification(&req).is_none());
+        args.insert("confirm".to_string(), json!(true));
+        let req = CallToolRequestParam {
+            name: "grant-root-access".into(),
+            arguments: Some(args.clone()),
+        };
+        let notification = permission_change_notification(&req).unwrap();
+        assert_eq!(notification.data["operation"], "grant");
+
+        let req = CallToolRequestParam {
+            name: "list-components".into(),
+            arguments: Some(args),
+        };
+        assert!(permission_change_notification(&req).is_none());
     }
 
     #[tokio::test]
@@ -1128,6 +1882,273 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_set_config_arguments() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+
+        let mut args = serde_json::Map::new();
+        args.insert("component_id".to_string(), json!("test-component"));
+        let req = CallToolRequestParam {
+            name: "set-config".into(),
+            arguments: Some(args.clone()),
+        };
+        let result = handle_set_config(&req, &lifecycle_manager).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Missing required argument: 'key'"));
+
+        args.insert("key".to_string(), json!("region"));
+        args.insert("value".to_string(), json!(42));
+        let req = CallToolRequestParam {
+            name: "set-config".into(),
+            arguments: Some(args.clone()),
+        };
+        let result = handle_set_config(&req, &lifecycle_manager).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("must be a string or null"));
+
+        // The component has to be loaded
+        args.insert("value".to_string(), json!("eu-west"));
+        let req = CallToolRequestParam {
+            name: "set-config".into(),
+            arguments: Some(args),
+        };
+        let result = handle_set_config(&req, &lifecycle_manager).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Component not found"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_tool_call() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions {
+            rate_limits: RateLimiter::new(["list-components=1/1h".parse()?]),
+            ..Default::default()
+        };
+        let req = CallToolRequestParam {
+            name: "list-components".into(),
+            arguments: None,
+        };
+
+        execute_tool_call(req.clone(), &lifecycle_manager, None, &options).await?;
+        let err = execute_tool_call(req, &lifecycle_manager, None, &options)
+            .await
+            .unwrap_err();
+        let exceeded = err
+            .downcast_ref::<crate::security::RateLimitExceeded>()
+            .expect("call should be rate limited");
+        assert_eq!(exceeded.tool, "list-components");
+        assert_eq!(exceeded.data()["remaining"], 0);
+        assert!(exceeded.data()["retry_after_secs"].as_u64().unwrap() > 3500);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tool_call() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions {
+            call_limits: CallLimitSettings::new(
+                crate::security::CallLimits {
+                    max_argument_size: 32,
+                    ..Default::default()
+                },
+                [],
+            ),
+            ..Default::default()
+        };
+        let call = |name: &str, arguments: Value| CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        };
+
+        let err = execute_tool_call(
+            call("list components", json!({})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<crate::security::InvalidToolCall>());
+        let err = execute_tool_call(
+            call("get-policy", json!({"component_id": "x".repeat(32)})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await
+        .unwrap_err();
+        let invalid = err
+            .downcast_ref::<crate::security::InvalidToolCall>()
+            .expect("arguments should be too large");
+        assert_eq!(invalid.tool, "get-policy");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_schema_validated_tool_call() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("echo.wasm"),
+            r#"(component
+              (core module $m (func (export "echo") (param i32) (result i32) local.get 0))
+              (core instance $i (instantiate $m))
+              (func $echo (param "n" u32) (result u32) (canon lift (core func $i "echo")))
+              (export "echo" (func $echo)))"#,
+        )?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions::default();
+        let call = |arguments: Value| CallToolRequestParam {
+            name: "echo".into(),
+            arguments: arguments.as_object().cloned(),
+        };
+
+        let err = execute_tool_call(
+            call(json!({"n": "seven"})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await
+        .unwrap_err();
+        let invalid = err
+            .downcast_ref::<crate::security::InvalidArguments>()
+            .expect("arguments should not match the schema");
+        assert_eq!(
+            invalid.data(),
+            json!({
+                "code": "validation_failed",
+                "tool": "echo",
+                "errors": [{"path": "/n", "message": "expected number, found string"}],
+            })
+        );
+        let err = execute_tool_call(call(json!({})), &lifecycle_manager, None, &options)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("missing required property 'n'"));
+
+        let result =
+            execute_tool_call(call(json!({"n": 7})), &lifecycle_manager, None, &options).await?;
+        assert_eq!(result["content"][0]["text"], "7");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coded_tool_errors() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("crash.wasm"),
+            r#"(component
+              (core module $m (func (export "crash") unreachable))
+              (core instance $i (instantiate $m))
+              (func $crash (canon lift (core func $i "crash")))
+              (export "crash" (func $crash)))"#,
+        )?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions::default();
+        let call = |name: &str, arguments: Value| CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        };
+
+        let result =
+            execute_tool_call(call("crash", json!({})), &lifecycle_manager, None, &options).await?;
+        assert_eq!(result["isError"], true);
+        assert_eq!(result["structuredContent"]["error"]["code"], "trap");
+
+        let result = execute_tool_call(
+            call("get-policy", json!({"component_id": "missing"})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        let error = &result["structuredContent"]["error"];
+        assert_eq!(error["code"], "component_not_found");
+        assert_eq!(error["message"], "Component not found: missing");
+        assert!(result["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("Hint: Call list-components"));
+
+        let result = execute_tool_call(
+            call("missing", json!({})),
+            &lifecycle_manager,
+            None,
+            &options,
+        )
+        .await?;
+        assert_eq!(
+            result["structuredContent"]["error"]["code"],
+            "component_not_found"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_key_tool_call() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = wassette::LifecycleManager::new(&tempdir).await?;
+        let options = ToolCallOptions {
+            api_keys: ApiKeys::new([crate::security::ApiKeyAccess {
+                name: "triage-bot".to_string(),
+                rate_limits: RateLimiter::new(["list-components=1/1h".parse()?]),
+                tools: Some(vec!["list-components".to_string()]),
+                components: None,
+            }]),
+            ..Default::default()
+        };
+        let call = |name: &str| CallToolRequestParam {
+            name: name.to_string().into(),
+            arguments: None,
+        };
+
+        check_api_key(
+            &call("list-components"),
+            &lifecycle_manager,
+            &options,
+            "triage-bot",
+        )
+        .await?;
+        let err = check_api_key(
+            &call("list-components"),
+            &lifecycle_manager,
+            &options,
+            "triage-bot",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<crate::security::RateLimitExceeded>());
+        let err = check_api_key(
+            &call("purge-component"),
+            &lifecycle_manager,
+            &options,
+            "triage-bot",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<crate::security::ToolAccessDenied>());
+
+        // The server-wide limits are not affected by the key's calls
+        execute_tool_call(call("list-components"), &lifecycle_manager, None, &options).await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_revoke_permission_missing_arguments() -> Result<()> {
         let tempdir = tempfile::tempdir()?;
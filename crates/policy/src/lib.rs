@@ -41,6 +41,38 @@ impl PolicyDocument {
         Ok(())
     }
 
+    /// The legacy constructs the policy uses, e.g. `permissions.resources.memory`. They are
+    /// still accepted, but rejected by [`PolicyParser::parse_str_strict`].
+    pub fn legacy_fields(&self) -> Vec<&'static str> {
+        let mut legacy = Vec::new();
+        let Some(resources) = &self.permissions.resources else {
+            return legacy;
+        };
+        if resources.cpu.is_some() {
+            legacy.push("permissions.resources.cpu");
+        }
+        if resources.memory.is_some() {
+            legacy.push("permissions.resources.memory");
+        }
+        if resources.io.is_some() {
+            legacy.push("permissions.resources.io");
+        }
+        if let Some(limits) = &resources.limits {
+            if matches!(limits.cpu, Some(CpuLimit::Number(_))) {
+                legacy.push("permissions.resources.limits.cpu (numeric)");
+            }
+            if matches!(limits.memory, Some(MemoryLimit::Number(_))) {
+                legacy.push("permissions.resources.limits.memory (numeric)");
+            }
+        }
+        if let Some(http) = &resources.http {
+            if matches!(http.max_response_size, Some(MemoryLimit::Number(_))) {
+                legacy.push("permissions.resources.http.max_response_size (numeric)");
+            }
+        }
+        legacy
+    }
+
     /// Create a new policy document with default permissions
     pub fn new(version: impl Into<String>, description: Option<String>) -> Self {
         Self {
@@ -162,6 +194,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_strict_parsing() {
+        // The testdata policies use neither unknown fields nor legacy constructs
+        PolicyParser::parse_file_strict("testdata/docker.yaml").unwrap();
+
+        let unknown = r#"
+version: "1.0"
+permissions:
+  netwrok:
+    allow:
+      - host: "api.example.com"
+  storage:
+    allow:
+      - uri: "fs://work/**"
+        access: ["read"]
+        acess: ["write"]
+"#;
+        assert!(PolicyParser::parse_str(unknown).is_ok());
+        let error = PolicyParser::parse_str_strict(unknown)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("permissions.netwrok"), "{error}");
+        assert!(
+            error.contains("permissions.storage.allow[0].acess"),
+            "{error}"
+        );
+
+        let legacy = r#"
+version: "1.0"
+permissions:
+  resources:
+    memory: 512
+    limits:
+      cpu: 1.5
+"#;
+        let policy = PolicyParser::parse_str(legacy).unwrap();
+        assert_eq!(
+            policy.legacy_fields(),
+            [
+                "permissions.resources.memory",
+                "permissions.resources.limits.cpu (numeric)"
+            ]
+        );
+        assert!(PolicyParser::parse_str_strict(legacy).is_err());
+
+        let current = r#"
+version: "1.0"
+description: null
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+      - udp: "pool.ntp.org"
+        port: 123
+  resources:
+    limits:
+      cpu: "500m"
+      memory: "512Mi"
+    http:
+      read_timeout: "30s"
+"#;
+        assert_eq!(
+            PolicyParser::parse_str_strict(current).unwrap(),
+            PolicyParser::parse_str(current).unwrap()
+        );
+    }
+
     #[test]
     fn test_round_trip_docker_yaml() {
         let original_policy = PolicyParser::parse_file("testdata/docker.yaml").unwrap();
@@ -4,7 +4,8 @@
 use std::fs;
 use std::path::Path;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use serde_yaml::Value;
 
 use crate::{PolicyDocument, PolicyResult};
 
@@ -67,6 +68,55 @@ impl PolicyParser {
         Self::parse_str(content)
     }
 
+    /// Parse a policy document from a YAML string in strict mode
+    ///
+    /// On top of the checks of [`PolicyParser::parse_str`], this rejects fields the parser
+    /// doesn't know, which are otherwise ignored, and the legacy constructs listed by
+    /// [`PolicyDocument::legacy_fields`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use policy::PolicyParser;
+    ///
+    /// let yaml_content = r#"
+    /// version: "1.0"
+    /// permissions:
+    ///   netwrok:
+    ///     allow:
+    ///     - host: "api.example.com"
+    /// "#;
+    ///
+    /// assert!(PolicyParser::parse_str(yaml_content).is_ok());
+    /// assert!(PolicyParser::parse_str_strict(yaml_content).is_err());
+    /// ```
+    pub fn parse_str_strict(content: impl AsRef<str>) -> PolicyResult<PolicyDocument> {
+        let raw: Value = serde_yaml::from_str(content.as_ref())?;
+        let document: PolicyDocument = serde_yaml::from_value(raw.clone())?;
+        document.validate()?;
+
+        // Whatever doesn't survive a round trip through the policy types is unknown to them
+        let known = serde_yaml::to_value(&document)?;
+        let mut unknown = Vec::new();
+        find_unknown_fields(&raw, &known, "", &mut unknown);
+        if !unknown.is_empty() {
+            bail!("Unknown policy fields: {}", unknown.join(", "));
+        }
+
+        let legacy = document.legacy_fields();
+        if !legacy.is_empty() {
+            bail!("Legacy policy fields: {}", legacy.join(", "));
+        }
+        Ok(document)
+    }
+
+    /// Parse a policy document from a file path in strict mode, see
+    /// [`PolicyParser::parse_str_strict`]
+    pub fn parse_file_strict<P: AsRef<Path>>(path: P) -> PolicyResult<PolicyDocument> {
+        let content = fs::read_to_string(path)?;
+        Self::parse_str_strict(&content)
+    }
+
     /// Serialize a policy document to YAML string
     ///
     /// # Example
@@ -106,6 +156,40 @@ impl PolicyParser {
     }
 }
 
+/// Collects the paths of the fields of `raw` that are missing from `known`. Fields set to null
+/// are skipped, they mean the same as leaving the field out.
+fn find_unknown_fields(raw: &Value, known: &Value, path: &str, unknown: &mut Vec<String>) {
+    match (raw, known) {
+        (Value::Mapping(raw), Value::Mapping(known)) => {
+            for (key, value) in raw {
+                let name = match key {
+                    Value::String(name) => name.clone(),
+                    key => serde_yaml::to_string(key)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let field = if path.is_empty() {
+                    name
+                } else {
+                    format!("{path}.{name}")
+                };
+                match known.get(key) {
+                    Some(known) => find_unknown_fields(value, known, &field, unknown),
+                    None if value.is_null() => {}
+                    None => unknown.push(field),
+                }
+            }
+        }
+        (Value::Sequence(raw), Value::Sequence(known)) => {
+            for (index, (raw, known)) in raw.iter().zip(known).enumerate() {
+                find_unknown_fields(raw, known, &format!("{path}[{index}]"), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
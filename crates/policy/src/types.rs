@@ -49,18 +49,159 @@ pub struct NetworkCidrPermission {
     pub cidr: String,
 }
 
-/// Network permission entry - can be either host or CIDR
+/// Network UDP permission
+///
+/// udp: Hostname, IP address or CIDR range the component may exchange datagrams with
+/// port: Remote port (e.g. 123 for NTP); every port is allowed when omitted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkUdpPermission {
+    /// Hostname, IP address or CIDR range
+    pub udp: String,
+    /// Remote port, or every port when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// Network TCP permission
+///
+/// tcp: Hostname, IP address or CIDR range the component may open raw TCP connections to,
+///      optionally written as an endpoint (e.g. tcp://db.internal:5432)
+/// port: Remote port (e.g. 5432 for PostgreSQL); every port is allowed when omitted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkTcpPermission {
+    /// Hostname, IP address, CIDR range or `tcp://host:port` endpoint
+    pub tcp: String,
+    /// Remote port, or every port when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// Network permission entry - can be a host, a CIDR range, a UDP grant or a TCP grant
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum NetworkPermission {
     Host(NetworkHostPermission),
     Cidr(NetworkCidrPermission),
+    Udp(NetworkUdpPermission),
+    Tcp(NetworkTcpPermission),
 }
 
+/// Prefix of the `from` of an environment variable whose value is a secret of the component
+pub const SECRET_REFERENCE_PREFIX: &str = "secret://";
+
 /// Environment variable permission
+///
+/// key: Name of the variable the component sees
+/// from: Where the value comes from, `secret://<name>` for the secret `<name>` of the component
+/// instead of the variable of the same name in the server's environment
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EnvironmentPermission {
     pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
+
+impl EnvironmentPermission {
+    /// The name of the secret that holds the value of the variable, if it comes from one
+    pub fn secret(&self) -> Option<&str> {
+        self.from.as_deref()?.strip_prefix(SECRET_REFERENCE_PREFIX)
+    }
+}
+
+/// Key-value bucket permission
+///
+/// bucket: Name of a wasi:keyvalue bucket the component may open (e.g. cache)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyValuePermission {
+    /// Name of the bucket
+    pub bucket: String,
+}
+
+/// Blobstore container permission
+///
+/// container: Name of a wasi:blobstore container the component may use (e.g. artifacts)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobstorePermission {
+    /// Name of the container
+    pub container: String,
+}
+
+/// Database permission
+///
+/// name: Name the component opens the database by (e.g. analytics)
+/// path: SQLite file inside one of the component's storage grants (e.g. fs:///data/analytics.db)
+/// dsn: Connection string of a database set up by the operator, used instead of a path
+///      (e.g. sqlite:///var/lib/wassette/shared.db or sqlite::memory:)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabasePermission {
+    /// Name of the database
+    pub name: String,
+    /// `fs://` URI of the SQLite file, which has to be inside a storage grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Connection string of the database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dsn: Option<String>,
+}
+
+/// Where the wasi:blobstore containers of a component are stored
+///
+/// type: filesystem (the default) or s3
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BlobstoreBackend {
+    /// A directory on the host
+    Filesystem {
+        /// Directory the containers are stored in, `<plugin dir>/blobstore` if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    /// An S3-compatible object store. Credentials are read from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` of the server.
+    S3 {
+        /// URL of the service, e.g. `https://s3.us-east-1.amazonaws.com`
+        endpoint: String,
+        /// The bucket the containers are stored in
+        bucket: String,
+        /// Region used to sign requests, `us-east-1` if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+        /// Key prefix the containers are stored under
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+    },
+}
+
+impl Default for BlobstoreBackend {
+    fn default() -> Self {
+        BlobstoreBackend::Filesystem { path: None }
+    }
+}
+
+/// Host extension permission
+///
+/// name: Name of a host extension registered by the embedder (e.g. gpu)
+/// config: Extension-specific settings, handed to the extension as they are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionPermission {
+    /// Name of the extension
+    pub name: String,
+    /// Settings for the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<serde_yaml::Value>,
+}
+
+/// Component call permission
+///
+/// id: ID of a loaded component whose tools the component may call (e.g. html-to-markdown)
+/// tools: The tools it may call, all of them if omitted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentPermission {
+    /// ID of the component
+    pub id: String,
+    /// Names of the tools that may be called
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
 }
 
 /// Docker capability action
@@ -140,6 +281,32 @@ pub enum MemoryLimit {
     Number(u64),
 }
 
+/// Timeout that supports "500ms", "30s" and "2m" values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TimeoutLimit {
+    /// String format with an ms, s or m suffix
+    String(String),
+    /// Numeric format in seconds
+    Number(u64),
+}
+
+/// Limits on the outgoing HTTP requests of a component
+///
+/// Each limit overrides the server-wide default for the component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct HttpLimits {
+    /// Largest response body accepted, in k8s format ("10Mi", "512Ki")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_response_size: Option<MemoryLimit>,
+    /// Time allowed to establish a connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<TimeoutLimit>,
+    /// Time allowed to wait for the response headers and between chunks of the body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<TimeoutLimit>,
+}
+
 /// Resource limit values under the limits section
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct ResourceLimitValues {
@@ -147,6 +314,18 @@ pub struct ResourceLimitValues {
     pub cpu: Option<CpuLimit>,
     /// Memory limit in k8s format ("512Mi", "1Gi", "256Ki")
     pub memory: Option<MemoryLimit>,
+    /// Most elements a single table may grow to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_elements: Option<u64>,
+    /// Most core instances, counting the ones a component is made of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instances: Option<u64>,
+    /// Most tables across all instances
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tables: Option<u64>,
+    /// Most linear memories across all instances
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memories: Option<u64>,
     /// Cached parsed CPU value in cores (not serialized)
     #[serde(skip)]
     cpu_cores_cache: OnceLock<f64>,
@@ -167,6 +346,9 @@ pub struct ResourceLimits {
     pub memory: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub io: Option<u64>,
+    /// Limits on outgoing HTTP requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpLimits>,
 }
 
 /// IPC permission configuration (future/TODO)
@@ -175,6 +357,35 @@ pub struct IpcPermission {
     pub uri: String,
 }
 
+/// How the virtual clocks of a deterministic runtime advance
+///
+/// fixed: every reading returns the start time
+/// logical: every reading advances the clock by one millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VirtualClock {
+    Fixed,
+    #[default]
+    Logical,
+}
+
+/// Deterministic execution: virtual `wasi:clocks` and seeded `wasi:random`
+///
+/// Every call starts from the same clock and random state, so the same inputs produce the same
+/// outputs. The random numbers are predictable and must not be used for anything secret.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct DeterministicRuntime {
+    /// How the clocks advance
+    #[serde(default)]
+    pub clock: VirtualClock,
+    /// Wall clock time at the start of every call, in seconds since the Unix epoch
+    #[serde(default)]
+    pub start_time: u64,
+    /// Seed of the random number generators
+    #[serde(default)]
+    pub seed: u64,
+}
+
 /// Runtime configuration
 ///
 /// TODO: add more sandboxing runtimes
@@ -182,6 +393,7 @@ pub struct IpcPermission {
 pub struct Runtime {
     pub docker: Option<DockerRuntime>,
     pub hyperlight: Option<HyperlightRuntime>,
+    pub deterministic: Option<DeterministicRuntime>,
 }
 
 /// Permission list with allow/deny rules
@@ -206,6 +418,48 @@ pub struct EnvironmentPermissions {
     pub allow: Option<Vec<EnvironmentPermission>>,
 }
 
+/// Key-value permissions (allow-only, every bucket is private to its component)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct KeyValuePermissions {
+    pub allow: Option<Vec<KeyValuePermission>>,
+}
+
+/// Blobstore permissions (allow-only, every container is private to its component)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct BlobstorePermissions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<BlobstoreBackend>,
+    pub allow: Option<Vec<BlobstorePermission>>,
+}
+
+/// Database permissions (allow-only, components can't open databases that aren't listed)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct DatabasePermissions {
+    pub allow: Option<Vec<DatabasePermission>>,
+}
+
+/// Component call permissions (allow-only, components can't call other components unless granted)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ComponentPermissions {
+    pub allow: Option<Vec<ComponentPermission>>,
+}
+
+/// Host extension permissions (allow-only, extensions are off unless granted)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ExtensionPermissions {
+    pub allow: Option<Vec<ExtensionPermission>>,
+}
+
+/// Sampling permission (off unless present): lets the component ask the model of the connected
+/// MCP client for completions through `wassette:ai/sampling`
+///
+/// max_tokens: Most tokens a single completion may have. Requests for more are lowered to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct SamplingPermissions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
 /// Complete permissions structure
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Permissions {
@@ -215,6 +469,12 @@ pub struct Permissions {
     pub runtime: Option<Runtime>,
     pub resources: Option<ResourceLimits>,
     pub ipc: Option<PermissionList<IpcPermission>>,
+    pub keyvalue: Option<KeyValuePermissions>,
+    pub blobstore: Option<BlobstorePermissions>,
+    pub database: Option<DatabasePermissions>,
+    pub components: Option<ComponentPermissions>,
+    pub extensions: Option<ExtensionPermissions>,
+    pub sampling: Option<SamplingPermissions>,
 }
 
 impl CpuLimit {
@@ -309,12 +569,66 @@ impl MemoryLimit {
     }
 }
 
+impl TimeoutLimit {
+    /// Validate and convert the timeout to a duration
+    pub fn to_duration(&self) -> PolicyResult<std::time::Duration> {
+        let millis = match self {
+            TimeoutLimit::String(s) => {
+                let (value_str, multiplier) = if let Some(value) = s.strip_suffix("ms") {
+                    (value, 1u64)
+                } else if let Some(value) = s.strip_suffix('s') {
+                    (value, 1000)
+                } else if let Some(value) = s.strip_suffix('m') {
+                    (value, 60 * 1000)
+                } else {
+                    bail!("Timeout needs an ms, s or m suffix: {}", s);
+                };
+
+                let value: u64 = value_str
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid timeout value: {}", s))?;
+                value
+                    .checked_mul(multiplier)
+                    .ok_or_else(|| anyhow::anyhow!("Timeout too large: {}", s))?
+            }
+            TimeoutLimit::Number(n) => n
+                .checked_mul(1000)
+                .ok_or_else(|| anyhow::anyhow!("Timeout too large: {}", n))?,
+        };
+
+        if millis == 0 {
+            bail!("Timeout cannot be zero");
+        }
+        Ok(std::time::Duration::from_millis(millis))
+    }
+}
+
+impl HttpLimits {
+    /// Validate the HTTP limits
+    pub fn validate(&self) -> PolicyResult<()> {
+        if let Some(size) = &self.max_response_size {
+            size.to_bytes()?;
+        }
+        if let Some(timeout) = &self.connect_timeout {
+            timeout.to_duration()?;
+        }
+        if let Some(timeout) = &self.read_timeout {
+            timeout.to_duration()?;
+        }
+        Ok(())
+    }
+}
+
 impl ResourceLimitValues {
     /// Create a new ResourceLimitValues instance
     pub fn new(cpu: Option<CpuLimit>, memory: Option<MemoryLimit>) -> Self {
         Self {
             cpu,
             memory,
+            table_elements: None,
+            instances: None,
+            tables: None,
+            memories: None,
             cpu_cores_cache: OnceLock::new(),
             memory_bytes_cache: OnceLock::new(),
         }
@@ -359,6 +673,16 @@ impl ResourceLimitValues {
         // Validation now uses the cached getters, which will parse and cache the values
         self.cpu_cores()?;
         self.memory_bytes()?;
+        // Every component is made of at least one core instance with a memory and a table
+        for (name, limit) in [
+            ("instances", self.instances),
+            ("tables", self.tables),
+            ("memories", self.memories),
+        ] {
+            if limit == Some(0) {
+                bail!("Resource limit {name} must be at least 1");
+            }
+        }
         Ok(())
     }
 }
@@ -385,6 +709,10 @@ impl ResourceLimits {
             // IO values are fine as u64 is naturally non-negative
         }
 
+        if let Some(http) = &self.http {
+            http.validate()?;
+        }
+
         Ok(())
     }
 }
@@ -440,6 +768,34 @@ impl Permissions {
         Ok(())
     }
 
+    fn validate_udp_permission(perm: &NetworkUdpPermission) -> PolicyResult<()> {
+        if perm.udp.is_empty() {
+            bail!("UDP target can't be empty");
+        }
+        if perm.udp.contains('*') {
+            bail!("Wildcards aren't supported for UDP targets: {}", perm.udp);
+        }
+        if perm.port == Some(0) {
+            bail!("UDP port can't be 0 for: {}", perm.udp);
+        }
+
+        Ok(())
+    }
+
+    fn validate_tcp_permission(perm: &NetworkTcpPermission) -> PolicyResult<()> {
+        if perm.tcp.is_empty() {
+            bail!("TCP target can't be empty");
+        }
+        if perm.tcp.contains('*') {
+            bail!("Wildcards aren't supported for TCP targets: {}", perm.tcp);
+        }
+        if perm.port == Some(0) {
+            bail!("TCP port can't be 0 for: {}", perm.tcp);
+        }
+
+        Ok(())
+    }
+
     fn validate_environment_key(key: &str) -> PolicyResult<()> {
         if key.is_empty() {
             bail!("Environment key can't be empty");
@@ -453,6 +809,113 @@ impl Permissions {
         Ok(())
     }
 
+    fn validate_environment_source(from: &str) -> PolicyResult<()> {
+        let Some(secret) = from.strip_prefix(SECRET_REFERENCE_PREFIX) else {
+            bail!("Environment source must be {SECRET_REFERENCE_PREFIX}<name>: {from}");
+        };
+        if secret.is_empty() || secret.contains('*') {
+            bail!("Invalid secret name in environment source: {from}");
+        }
+
+        Ok(())
+    }
+
+    fn validate_extension_name(name: &str) -> PolicyResult<()> {
+        if name.is_empty() {
+            bail!("Extension name can't be empty");
+        }
+        if name.contains('*') || name.chars().any(char::is_whitespace) {
+            bail!(
+                "Extension names can't contain wildcards or whitespace: {}",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    fn validate_component_call(perm: &ComponentPermission) -> PolicyResult<()> {
+        if perm.id.is_empty() || perm.id.contains('*') || perm.id.chars().any(char::is_whitespace) {
+            bail!(
+                "Component IDs in components.allow can't be empty or contain wildcards or whitespace: {:?}",
+                perm.id
+            );
+        }
+        if let Some(tools) = &perm.tools {
+            if tools.iter().any(|tool| tool.is_empty()) {
+                bail!("Tool names of component {} can't be empty", perm.id);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_blobstore_container(container: &str) -> PolicyResult<()> {
+        // Container names end up in directory names and object keys
+        if container.is_empty()
+            || container.starts_with('.')
+            || !container
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!(
+                "Blobstore container names may only contain letters, digits, '-', '_' and '.', and can't start with '.': {:?}",
+                container
+            );
+        }
+        Ok(())
+    }
+
+    fn validate_database(perm: &DatabasePermission) -> PolicyResult<()> {
+        if perm.name.is_empty()
+            || !perm
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!(
+                "Database names may only contain letters, digits, '-', '_' and '.': {:?}",
+                perm.name
+            );
+        }
+        match (&perm.path, &perm.dsn) {
+            (Some(path), None) => {
+                if !path.starts_with("fs://") {
+                    bail!("Database path must be an fs:// URI: {path}");
+                }
+            }
+            (None, Some(dsn)) => {
+                // Only SQLite is supported for now
+                if !dsn.starts_with("sqlite:") {
+                    bail!("Unsupported database connection string, expected sqlite:<path> or sqlite::memory: {dsn}");
+                }
+            }
+            _ => bail!(
+                "Database {} needs either a path or a dsn, but not both",
+                perm.name
+            ),
+        }
+        Ok(())
+    }
+
+    fn validate_keyvalue_bucket(bucket: &str) -> PolicyResult<()> {
+        if bucket.is_empty() {
+            bail!("Key-value bucket name can't be empty");
+        }
+
+        // Bucket names end up in storage table names, so keep them boring
+        if !bucket
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!(
+                "Key-value bucket names may only contain letters, digits, '-', '_' and '.': {}",
+                bucket
+            );
+        }
+
+        Ok(())
+    }
+
     /// Validate the permissions structure
     pub fn validate(&self) -> PolicyResult<()> {
         if let Some(storage) = &self.storage {
@@ -489,6 +952,12 @@ impl Permissions {
                                 bail!("CIDR needs a slash: {}", cidr_perm.cidr);
                             }
                         }
+                        NetworkPermission::Udp(udp_perm) => {
+                            Self::validate_udp_permission(udp_perm)?;
+                        }
+                        NetworkPermission::Tcp(tcp_perm) => {
+                            Self::validate_tcp_permission(tcp_perm)?;
+                        }
                     }
                 }
             }
@@ -506,6 +975,12 @@ impl Permissions {
                                 bail!("CIDR needs a slash: {}", cidr_perm.cidr);
                             }
                         }
+                        NetworkPermission::Udp(udp_perm) => {
+                            Self::validate_udp_permission(udp_perm)?;
+                        }
+                        NetworkPermission::Tcp(tcp_perm) => {
+                            Self::validate_tcp_permission(tcp_perm)?;
+                        }
                     }
                 }
             }
@@ -515,10 +990,71 @@ impl Permissions {
             if let Some(allow_list) = &env.allow {
                 for perm in allow_list {
                     Self::validate_environment_key(&perm.key)?;
+                    if let Some(from) = &perm.from {
+                        Self::validate_environment_source(from)?;
+                    }
                 }
             }
         }
 
+        if let Some(keyvalue) = &self.keyvalue {
+            if let Some(allow_list) = &keyvalue.allow {
+                for perm in allow_list {
+                    Self::validate_keyvalue_bucket(&perm.bucket)?;
+                }
+            }
+        }
+
+        if let Some(blobstore) = &self.blobstore {
+            if let Some(allow_list) = &blobstore.allow {
+                for perm in allow_list {
+                    Self::validate_blobstore_container(&perm.container)?;
+                }
+            }
+            if let Some(BlobstoreBackend::S3 {
+                endpoint, bucket, ..
+            }) = &blobstore.backend
+            {
+                if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                    bail!("Blobstore endpoint must be an http:// or https:// URL: {endpoint}");
+                }
+                if bucket.is_empty() || bucket.contains('/') {
+                    bail!("Invalid blobstore bucket: {bucket:?}");
+                }
+            }
+        }
+
+        if let Some(database) = &self.database {
+            if let Some(allow_list) = &database.allow {
+                for perm in allow_list {
+                    Self::validate_database(perm)?;
+                }
+            }
+        }
+
+        if let Some(components) = &self.components {
+            if let Some(allow_list) = &components.allow {
+                for perm in allow_list {
+                    Self::validate_component_call(perm)?;
+                }
+            }
+        }
+
+        if let Some(extensions) = &self.extensions {
+            if let Some(allow_list) = &extensions.allow {
+                for perm in allow_list {
+                    Self::validate_extension_name(&perm.name)?;
+                }
+            }
+        }
+
+        if let Some(SamplingPermissions {
+            max_tokens: Some(0),
+        }) = &self.sampling
+        {
+            bail!("sampling.max_tokens must be greater than 0");
+        }
+
         if let Some(resources) = &self.resources {
             resources.validate()?;
         }
@@ -529,6 +1065,8 @@ impl Permissions {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
 
     #[test]
@@ -562,6 +1100,327 @@ mod tests {
         assert!(permissions.validate().is_err());
     }
 
+    #[test]
+    fn test_network_udp_validation() {
+        let udp_permissions = |udp: &str, port: Option<u16>| Permissions {
+            network: Some(PermissionList {
+                allow: Some(vec![NetworkPermission::Udp(NetworkUdpPermission {
+                    udp: udp.to_string(),
+                    port,
+                })]),
+                deny: None,
+            }),
+            ..Default::default()
+        };
+
+        assert!(udp_permissions("pool.ntp.org", Some(123))
+            .validate()
+            .is_ok());
+        assert!(udp_permissions("10.0.0.0/8", None).validate().is_ok());
+
+        assert!(udp_permissions("", Some(53)).validate().is_err());
+        assert!(udp_permissions("*.ntp.org", Some(123)).validate().is_err());
+        assert!(udp_permissions("1.1.1.1", Some(0)).validate().is_err());
+    }
+
+    #[test]
+    fn test_network_udp_deserialization() {
+        let network: Vec<NetworkPermission> = serde_yaml::from_str(
+            r#"
+- host: "api.example.com"
+- udp: "pool.ntp.org"
+  port: 123
+- udp: "10.0.0.0/8"
+"#,
+        )
+        .unwrap();
+
+        assert!(matches!(network[0], NetworkPermission::Host(_)));
+        assert_eq!(
+            network[1],
+            NetworkPermission::Udp(NetworkUdpPermission {
+                udp: "pool.ntp.org".to_string(),
+                port: Some(123),
+            })
+        );
+        assert_eq!(
+            network[2],
+            NetworkPermission::Udp(NetworkUdpPermission {
+                udp: "10.0.0.0/8".to_string(),
+                port: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_network_tcp_permission() {
+        let network: Vec<NetworkPermission> = serde_yaml::from_str(
+            r#"
+- tcp: "db.internal"
+  port: 5432
+- tcp: "tcp://smtp.example.com:587"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            network[0],
+            NetworkPermission::Tcp(NetworkTcpPermission {
+                tcp: "db.internal".to_string(),
+                port: Some(5432),
+            })
+        );
+        assert!(matches!(&network[1], NetworkPermission::Tcp(tcp) if tcp.port.is_none()));
+
+        let tcp_permissions = |tcp: &str, port: Option<u16>| Permissions {
+            network: Some(PermissionList {
+                allow: Some(vec![NetworkPermission::Tcp(NetworkTcpPermission {
+                    tcp: tcp.to_string(),
+                    port,
+                })]),
+                deny: None,
+            }),
+            ..Default::default()
+        };
+        assert!(tcp_permissions("db.internal", Some(5432))
+            .validate()
+            .is_ok());
+        assert!(tcp_permissions("", Some(5432)).validate().is_err());
+        assert!(tcp_permissions("*.internal", None).validate().is_err());
+        assert!(tcp_permissions("db.internal", Some(0)).validate().is_err());
+    }
+
+    #[test]
+    fn test_extension_permissions() {
+        let permissions: Permissions = serde_yaml::from_str(
+            r#"
+extensions:
+  allow:
+    - name: "gpu"
+      config:
+        devices: 1
+    - name: "acme:clock"
+"#,
+        )
+        .unwrap();
+        assert!(permissions.validate().is_ok());
+
+        let allow = permissions.extensions.unwrap().allow.unwrap();
+        assert_eq!(allow[0].name, "gpu");
+        assert_eq!(allow[0].config.as_ref().unwrap()["devices"], 1);
+        assert_eq!(allow[1].config, None);
+
+        for name in ["", "*", "my extension"] {
+            let permissions = Permissions {
+                extensions: Some(ExtensionPermissions {
+                    allow: Some(vec![ExtensionPermission {
+                        name: name.to_string(),
+                        config: None,
+                    }]),
+                }),
+                ..Default::default()
+            };
+            assert!(
+                permissions.validate().is_err(),
+                "{name:?} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_keyvalue_bucket_validation() {
+        let keyvalue_permissions = |bucket: &str| Permissions {
+            keyvalue: Some(KeyValuePermissions {
+                allow: Some(vec![KeyValuePermission {
+                    bucket: bucket.to_string(),
+                }]),
+            }),
+            ..Default::default()
+        };
+
+        assert!(keyvalue_permissions("cache").validate().is_ok());
+        assert!(keyvalue_permissions("session-state_v2.1")
+            .validate()
+            .is_ok());
+
+        assert!(keyvalue_permissions("").validate().is_err());
+        assert!(keyvalue_permissions("../other").validate().is_err());
+        assert!(keyvalue_permissions("other/cache").validate().is_err());
+    }
+
+    #[test]
+    fn test_blobstore_permissions() {
+        let yaml = r#"
+backend:
+  type: s3
+  endpoint: https://s3.us-east-1.amazonaws.com
+  bucket: agent-artifacts
+allow:
+  - container: reports
+"#;
+        let blobstore: BlobstorePermissions = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            blobstore.backend,
+            Some(BlobstoreBackend::S3 {
+                endpoint: "https://s3.us-east-1.amazonaws.com".to_string(),
+                bucket: "agent-artifacts".to_string(),
+                region: None,
+                prefix: None,
+            })
+        );
+        let permissions = |blobstore: BlobstorePermissions| Permissions {
+            blobstore: Some(blobstore),
+            ..Default::default()
+        };
+        assert!(permissions(blobstore.clone()).validate().is_ok());
+
+        let blobstore: BlobstorePermissions =
+            serde_yaml::from_str("allow:\n  - container: cache\n").unwrap();
+        assert_eq!(blobstore.backend, None);
+        assert_eq!(
+            blobstore.backend.unwrap_or_default(),
+            BlobstoreBackend::default()
+        );
+
+        for container in ["", "..", ".hidden", "a/b"] {
+            let blobstore = BlobstorePermissions {
+                allow: Some(vec![BlobstorePermission {
+                    container: container.to_string(),
+                }]),
+                ..Default::default()
+            };
+            assert!(permissions(blobstore).validate().is_err(), "{container:?}");
+        }
+        let blobstore = BlobstorePermissions {
+            backend: Some(BlobstoreBackend::S3 {
+                endpoint: "s3.amazonaws.com".to_string(),
+                bucket: "artifacts".to_string(),
+                region: None,
+                prefix: None,
+            }),
+            allow: None,
+        };
+        assert!(permissions(blobstore).validate().is_err());
+    }
+
+    #[test]
+    fn test_database_permissions() {
+        let yaml = r#"
+allow:
+  - name: analytics
+    path: fs:///data/analytics.db
+  - name: shared
+    dsn: sqlite:///var/lib/wassette/shared.db?mode=ro
+"#;
+        let database: DatabasePermissions = serde_yaml::from_str(yaml).unwrap();
+        let allow = database.allow.clone().unwrap();
+        assert_eq!(allow[0].path.as_deref(), Some("fs:///data/analytics.db"));
+        assert_eq!(
+            allow[1].dsn.as_deref(),
+            Some("sqlite:///var/lib/wassette/shared.db?mode=ro")
+        );
+
+        let permissions = |perm: DatabasePermission| Permissions {
+            database: Some(DatabasePermissions {
+                allow: Some(vec![perm]),
+            }),
+            ..Default::default()
+        };
+        for perm in allow {
+            assert!(permissions(perm).validate().is_ok());
+        }
+
+        let invalid = [
+            ("../db", Some("fs:///data/a.db"), None),
+            ("both", Some("fs:///data/a.db"), Some("sqlite::memory:")),
+            ("neither", None, None),
+            ("relative", Some("data/a.db"), None),
+            ("postgres", None, Some("postgres://localhost/db")),
+        ];
+        for (name, path, dsn) in invalid {
+            let perm = DatabasePermission {
+                name: name.to_string(),
+                path: path.map(str::to_string),
+                dsn: dsn.map(str::to_string),
+            };
+            assert!(permissions(perm).validate().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_sampling_permissions() {
+        let permissions: Permissions =
+            serde_yaml::from_str("sampling:\n  max_tokens: 500\n").unwrap();
+        assert_eq!(
+            permissions.sampling,
+            Some(SamplingPermissions {
+                max_tokens: Some(500)
+            })
+        );
+        assert!(permissions.validate().is_ok());
+
+        let permissions: Permissions = serde_yaml::from_str("sampling: {}\n").unwrap();
+        assert_eq!(permissions.sampling, Some(SamplingPermissions::default()));
+
+        let permissions = Permissions {
+            sampling: Some(SamplingPermissions {
+                max_tokens: Some(0),
+            }),
+            ..Default::default()
+        };
+        assert!(permissions.validate().is_err());
+    }
+
+    #[test]
+    fn test_component_permissions() {
+        let yaml = "allow:\n  - id: html-to-markdown\n    tools: [convert]\n  - id: fetch\n";
+        let components: ComponentPermissions = serde_yaml::from_str(yaml).unwrap();
+        let allow = components.allow.clone().unwrap();
+        assert_eq!(allow[0].tools, Some(vec!["convert".to_string()]));
+        assert_eq!(allow[1].tools, None);
+
+        let permissions = |perm: ComponentPermission| Permissions {
+            components: Some(ComponentPermissions {
+                allow: Some(vec![perm]),
+            }),
+            ..Default::default()
+        };
+        for perm in allow {
+            assert!(permissions(perm).validate().is_ok());
+        }
+        for (id, tools) in [
+            ("", None),
+            ("*", None),
+            ("fetch", Some(vec![String::new()])),
+        ] {
+            let perm = ComponentPermission {
+                id: id.to_string(),
+                tools,
+            };
+            assert!(permissions(perm).validate().is_err(), "{id:?}");
+        }
+    }
+
+    #[test]
+    fn test_deterministic_runtime_defaults() {
+        let runtime: Runtime = serde_yaml::from_str("deterministic:\n  seed: 7\n").unwrap();
+        assert_eq!(
+            runtime.deterministic,
+            Some(DeterministicRuntime {
+                clock: VirtualClock::Logical,
+                start_time: 0,
+                seed: 7,
+            })
+        );
+
+        let runtime: Runtime =
+            serde_yaml::from_str("deterministic:\n  clock: fixed\n  start_time: 1700000000\n")
+                .unwrap();
+        let deterministic = runtime.deterministic.unwrap();
+        assert_eq!(deterministic.clock, VirtualClock::Fixed);
+        assert_eq!(deterministic.start_time, 1_700_000_000);
+    }
+
     #[test]
     fn test_valid_permissions() {
         let permissions = Permissions {
@@ -624,6 +1483,22 @@ mod tests {
         assert!(Permissions::validate_environment_key("PATH**").is_err());
     }
 
+    #[test]
+    fn test_environment_source_validation() {
+        assert!(Permissions::validate_environment_source("secret://API_KEY").is_ok());
+
+        assert!(Permissions::validate_environment_source("secret://").is_err());
+        assert!(Permissions::validate_environment_source("secret://API_*").is_err());
+        assert!(Permissions::validate_environment_source("env://API_KEY").is_err());
+        assert!(Permissions::validate_environment_source("API_KEY").is_err());
+
+        let permission = EnvironmentPermission {
+            key: "API_KEY".to_string(),
+            from: Some("secret://TOKEN".to_string()),
+        };
+        assert_eq!(permission.secret(), Some("TOKEN"));
+    }
+
     #[test]
     fn test_comprehensive_wildcard_validation() {
         let permissions = Permissions {
@@ -661,12 +1536,15 @@ mod tests {
                 allow: Some(vec![
                     EnvironmentPermission {
                         key: "PATH".to_string(),
+                        from: None,
                     },
                     EnvironmentPermission {
                         key: "HOME".to_string(),
+                        from: None,
                     },
                     EnvironmentPermission {
                         key: "MY_DEBUG_VAR".to_string(),
+                        from: None,
                     },
                 ]),
             }),
@@ -753,6 +1631,66 @@ mod tests {
         assert!(invalid_number.to_bytes().is_err());
     }
 
+    #[test]
+    fn test_timeout_limit_parsing() {
+        let millis = TimeoutLimit::String("500ms".to_string());
+        assert_eq!(millis.to_duration().unwrap(), Duration::from_millis(500));
+
+        let seconds = TimeoutLimit::String("30s".to_string());
+        assert_eq!(seconds.to_duration().unwrap(), Duration::from_secs(30));
+
+        let minutes = TimeoutLimit::String("2m".to_string());
+        assert_eq!(minutes.to_duration().unwrap(), Duration::from_secs(120));
+
+        // Numbers are seconds
+        assert_eq!(
+            TimeoutLimit::Number(10).to_duration().unwrap(),
+            Duration::from_secs(10)
+        );
+
+        assert!(TimeoutLimit::String("30".to_string())
+            .to_duration()
+            .is_err());
+        assert!(TimeoutLimit::String("fasts".to_string())
+            .to_duration()
+            .is_err());
+        assert!(TimeoutLimit::String("0s".to_string())
+            .to_duration()
+            .is_err());
+        assert!(TimeoutLimit::Number(0).to_duration().is_err());
+    }
+
+    #[test]
+    fn test_http_limits_validation() {
+        let valid = ResourceLimits {
+            http: Some(HttpLimits {
+                max_response_size: Some(MemoryLimit::String("10Mi".to_string())),
+                connect_timeout: Some(TimeoutLimit::String("5s".to_string())),
+                read_timeout: Some(TimeoutLimit::Number(30)),
+            }),
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+
+        let invalid_size = ResourceLimits {
+            http: Some(HttpLimits {
+                max_response_size: Some(MemoryLimit::String("lotsMi".to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(invalid_size.validate().is_err());
+
+        let invalid_timeout = ResourceLimits {
+            http: Some(HttpLimits {
+                read_timeout: Some(TimeoutLimit::String("forever".to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(invalid_timeout.validate().is_err());
+    }
+
     #[test]
     fn test_resource_limit_values_validation() {
         // Valid resource limits
@@ -808,6 +1746,24 @@ mod tests {
         assert_eq!(empty_limits.memory_bytes().unwrap(), None);
     }
 
+    #[test]
+    fn test_resource_count_limits() {
+        let yaml = "memory: 64Mi\ntable_elements: 10000\ninstances: 20\ntables: 20\nmemories: 2\n";
+        let limits: ResourceLimitValues = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(limits.table_elements, Some(10000));
+        assert_eq!(limits.instances, Some(20));
+        assert_eq!(limits.tables, Some(20));
+        assert_eq!(limits.memories, Some(2));
+        assert!(limits.validate().is_ok());
+
+        let mut limits = ResourceLimitValues::new(None, None);
+        limits.memories = Some(0);
+        assert!(limits.validate().is_err());
+        limits.memories = None;
+        limits.table_elements = Some(0);
+        assert!(limits.validate().is_ok());
+    }
+
     #[test]
     fn test_resource_limits_validation() {
         // Valid new format
@@ -819,6 +1775,7 @@ mod tests {
             cpu: None,
             memory: None,
             io: None,
+            http: None,
         };
         assert!(valid_new.validate().is_ok());
 
@@ -828,6 +1785,7 @@ mod tests {
             cpu: Some(1.5),
             memory: Some(512),
             io: Some(1000),
+            http: None,
         };
         assert!(valid_legacy.validate().is_ok());
 
@@ -840,6 +1798,7 @@ mod tests {
             cpu: None,
             memory: None,
             io: None,
+            http: None,
         };
         assert!(invalid_new.validate().is_err());
 
@@ -849,6 +1808,7 @@ mod tests {
             cpu: Some(-1.0),
             memory: None,
             io: None,
+            http: None,
         };
         assert!(invalid_legacy.validate().is_err());
     }
@@ -874,8 +1834,15 @@ mod tests {
                 cpu: None,
                 memory: None,
                 io: None,
+                http: None,
             }),
             ipc: None,
+            keyvalue: None,
+            blobstore: None,
+            database: None,
+            components: None,
+            extensions: None,
+            sampling: None,
         };
 
         assert!(permissions.validate().is_ok());
@@ -909,6 +1876,7 @@ mod tests {
         permissions.environment = Some(EnvironmentPermissions {
             allow: Some(vec![EnvironmentPermission {
                 key: "PATH_WITH_WILDCARD_*".to_string(),
+                from: None,
             }]),
         });
         assert!(permissions.validate().is_err());
@@ -0,0 +1,299 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Disk artifacts of components
+//!
+//! Everything Wassette keeps on disk for a component is tied to its ID: the component and policy
+//! files in the plugin directory, its trash entry, its key-value buckets, and its entries in the
+//! history index and the recall store. Purging a component removes all of them. Artifacts whose
+//! component is neither loaded nor in the trash are orphans, left behind by a crash, a manual
+//! deletion or an older version of Wassette, and can be found and removed with
+//! [`crate::LifecycleManager::find_orphaned_artifacts`].
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::blobstore::BLOBSTORE_DIR;
+use crate::history::HISTORY_DIR;
+use crate::recall::RECALL_DB_FILE;
+use crate::trash::TRASH_DIR;
+use crate::DOWNLOADS_DIR;
+
+/// File name suffixes of the policy files a component can have in the plugin directory
+const POLICY_FILE_SUFFIXES: [&str; 3] = [".policy.yaml", ".policy.meta.json", ".policy.base.yaml"];
+
+/// What kind of artifact an orphan is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// A policy, policy metadata or base policy file without its component
+    PolicyFile,
+    /// A trash entry that can't be restored
+    TrashEntry,
+    /// A file left behind in the downloads staging directory
+    Download,
+    /// Key-value buckets
+    KeyValueBuckets,
+    /// Blobstore containers in the default blobstore directory
+    BlobstoreContainers,
+    /// Entries in the history index
+    HistoryEntries,
+    /// Stored tool results for semantic recall
+    RecallResults,
+}
+
+/// An artifact whose component is neither loaded nor in the trash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrphanedArtifact {
+    /// What kind of artifact it is
+    pub kind: ArtifactKind,
+    /// Component the artifact belongs to, if it can be told
+    pub component_id: Option<String>,
+    /// The file or directory, or the store the artifact lives in
+    pub path: PathBuf,
+}
+
+impl crate::LifecycleManager {
+    /// IDs of the components that are loaded, in any session, or can be restored from the trash
+    async fn known_component_ids(&self) -> Result<BTreeSet<String>> {
+        let mut known: BTreeSet<String> = self.components.read().await.keys().cloned().collect();
+        for trashed in self.list_trashed_components().await? {
+            if tokio::fs::try_exists(
+                self.trash_path(&trashed.component_id)
+                    .join(format!("{}.wasm", trashed.component_id)),
+            )
+            .await
+            .unwrap_or(false)
+            {
+                known.insert(trashed.component_id);
+            }
+        }
+        Ok(known)
+    }
+
+    /// Finds the artifacts of components that are neither loaded nor in the trash. The history
+    /// index and the recall store are only checked if they are enabled.
+    #[instrument(skip(self))]
+    pub async fn find_orphaned_artifacts(&self) -> Result<Vec<OrphanedArtifact>> {
+        let known = self.known_component_ids().await?;
+        let mut orphans = Vec::new();
+
+        let mut entries = tokio::fs::read_dir(&self.plugin_dir)
+            .await
+            .context("Failed to read plugin directory")?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let component_id = POLICY_FILE_SUFFIXES
+                .iter()
+                .find_map(|suffix| file_name.strip_suffix(suffix));
+            if let Some(component_id) = component_id {
+                if !known.contains(component_id)
+                    && !tokio::fs::try_exists(self.component_path(component_id))
+                        .await
+                        .unwrap_or(false)
+                {
+                    orphans.push(OrphanedArtifact {
+                        kind: ArtifactKind::PolicyFile,
+                        component_id: Some(component_id.to_string()),
+                        path: entry.path(),
+                    });
+                }
+            }
+        }
+
+        if let Ok(mut entries) = tokio::fs::read_dir(self.plugin_dir.join(TRASH_DIR)).await {
+            while let Some(entry) = entries.next_entry().await? {
+                let component_id = entry.file_name().to_string_lossy().to_string();
+                if !known.contains(&component_id) {
+                    orphans.push(OrphanedArtifact {
+                        kind: ArtifactKind::TrashEntry,
+                        component_id: Some(component_id),
+                        path: entry.path(),
+                    });
+                }
+            }
+        }
+
+        // Downloads are staged in temporary directories, so anything here is a leftover
+        if let Ok(mut entries) = tokio::fs::read_dir(self.plugin_dir.join(DOWNLOADS_DIR)).await {
+            while let Some(entry) = entries.next_entry().await? {
+                orphans.push(OrphanedArtifact {
+                    kind: ArtifactKind::Download,
+                    component_id: None,
+                    path: entry.path(),
+                });
+            }
+        }
+
+        if let Ok(mut entries) = tokio::fs::read_dir(self.plugin_dir.join(BLOBSTORE_DIR)).await {
+            while let Some(entry) = entries.next_entry().await? {
+                let component_id = entry.file_name().to_string_lossy().to_string();
+                if !known.contains(&component_id) {
+                    orphans.push(OrphanedArtifact {
+                        kind: ArtifactKind::BlobstoreContainers,
+                        component_id: Some(component_id),
+                        path: entry.path(),
+                    });
+                }
+            }
+        }
+
+        match self.keyvalue.component_ids() {
+            Ok(ids) => orphans.extend(ids.into_iter().filter(|id| !known.contains(id)).map(
+                |component_id| OrphanedArtifact {
+                    kind: ArtifactKind::KeyValueBuckets,
+                    component_id: Some(component_id),
+                    path: self.keyvalue.path().to_path_buf(),
+                },
+            )),
+            Err(e) => warn!(error = %e, "Failed to list key-value buckets, skipping them"),
+        }
+
+        match self.history_component_ids().await {
+            Some(Ok(ids)) => orphans.extend(ids.into_iter().filter(|id| !known.contains(id)).map(
+                |component_id| OrphanedArtifact {
+                    kind: ArtifactKind::HistoryEntries,
+                    component_id: Some(component_id),
+                    path: self.plugin_dir.join(HISTORY_DIR),
+                },
+            )),
+            Some(Err(e)) => warn!(error = %e, "Failed to list history entries, skipping them"),
+            None => {}
+        }
+
+        if let Some(ids) = self.recall_component_ids() {
+            orphans.extend(
+                ids.into_iter()
+                    .filter(|id| !known.contains(id))
+                    .map(|component_id| OrphanedArtifact {
+                        kind: ArtifactKind::RecallResults,
+                        component_id: Some(component_id),
+                        path: self.plugin_dir.join(RECALL_DB_FILE),
+                    }),
+            );
+        }
+
+        Ok(orphans)
+    }
+
+    /// Removes the artifacts found by [`crate::LifecycleManager::find_orphaned_artifacts`].
+    /// Returns the removed artifacts.
+    #[instrument(skip(self))]
+    pub async fn remove_orphaned_artifacts(&self) -> Result<Vec<OrphanedArtifact>> {
+        let orphans = self.find_orphaned_artifacts().await?;
+        for orphan in &orphans {
+            let component_id = orphan.component_id.as_deref().unwrap_or_default();
+            match orphan.kind {
+                ArtifactKind::PolicyFile
+                | ArtifactKind::TrashEntry
+                | ArtifactKind::Download
+                | ArtifactKind::BlobstoreContainers => {
+                    let result = if tokio::fs::metadata(&orphan.path).await?.is_dir() {
+                        tokio::fs::remove_dir_all(&orphan.path).await
+                    } else {
+                        tokio::fs::remove_file(&orphan.path).await
+                    };
+                    result
+                        .with_context(|| format!("Failed to remove {}", orphan.path.display()))?;
+                }
+                ArtifactKind::KeyValueBuckets => {
+                    self.keyvalue.delete_component(component_id)?;
+                }
+                ArtifactKind::HistoryEntries => self.forget_history(component_id).await,
+                ArtifactKind::RecallResults => self.forget_memories(component_id).await,
+            }
+            info!(kind = ?orphan.kind, component_id, path = %orphan.path.display(), "Removed orphaned artifact");
+        }
+        Ok(orphans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::tests::{create_test_manager, TEST_COMPONENT_ID};
+
+    #[test(tokio::test)]
+    async fn test_orphaned_artifacts() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+        assert_eq!(manager.find_orphaned_artifacts().await?, []);
+
+        // A policy without its component, a broken trash entry, a stale download and the
+        // containers of a component that is gone
+        let stray_policy = manager.plugin_dir.join("gone.policy.yaml");
+        tokio::fs::write(&stray_policy, "version: \"1.0\"\n").await?;
+        let broken_trash = manager.plugin_dir.join(TRASH_DIR).join("broken");
+        tokio::fs::create_dir_all(&broken_trash).await?;
+        let download = manager.plugin_dir.join(DOWNLOADS_DIR).join("partial.wasm");
+        tokio::fs::write(&download, b"\0asm").await?;
+        let blobs = manager.plugin_dir.join(BLOBSTORE_DIR).join("gone");
+        tokio::fs::create_dir_all(blobs.join("reports")).await?;
+
+        // A trashed component is not an orphan
+        manager.unload_component(TEST_COMPONENT_ID).await?;
+
+        let orphans = manager.find_orphaned_artifacts().await?;
+        assert_eq!(
+            orphans
+                .iter()
+                .map(|orphan| (orphan.kind, orphan.component_id.as_deref()))
+                .collect::<Vec<_>>(),
+            [
+                (ArtifactKind::PolicyFile, Some("gone")),
+                (ArtifactKind::TrashEntry, Some("broken")),
+                (ArtifactKind::Download, None),
+                (ArtifactKind::BlobstoreContainers, Some("gone")),
+            ]
+        );
+
+        assert_eq!(manager.remove_orphaned_artifacts().await?, orphans);
+        assert!(!stray_policy.exists());
+        assert!(!broken_trash.exists());
+        assert!(!download.exists());
+        assert!(!blobs.exists());
+        assert_eq!(manager.find_orphaned_artifacts().await?, []);
+        assert!(manager
+            .trash_path(TEST_COMPONENT_ID)
+            .join(format!("{TEST_COMPONENT_ID}.wasm"))
+            .exists());
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_unload_and_purge_component() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+
+        manager
+            .unload_and_purge_component(TEST_COMPONENT_ID)
+            .await?;
+        assert!(manager.list_components().await.is_empty());
+        assert!(manager.list_trashed_components().await?.is_empty());
+        for (path, _) in manager.component_files(TEST_COMPONENT_ID) {
+            assert!(!path.exists(), "{} was left behind", path.display());
+        }
+        assert_eq!(manager.find_orphaned_artifacts().await?, []);
+        Ok(())
+    }
+}
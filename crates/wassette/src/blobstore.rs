@@ -0,0 +1,1217 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wasi:blobstore`
+//!
+//! Containers are stored by the backend configured under `permissions.blobstore.backend` in the
+//! policy of a component: a directory on the host, `<plugin_dir>/blobstore` by default, or an
+//! S3-compatible object store. Every component gets its own namespace in the backend, so two
+//! components using a container called `reports` never see each other's objects, and a component
+//! can only use the containers listed under `permissions.blobstore.allow`.
+//!
+//! Values are buffered in memory. A value written through the `output-stream` of an
+//! `outgoing-value` is stored when `write-data` is called, so the guest has to finish writing
+//! before it calls it.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use policy::BlobstoreBackend;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use wasmtime::component::{Resource, ResourceTable};
+use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::p2::{DynInputStream, DynOutputStream};
+
+use self::generated::wasi::blobstore::{blobstore, container, types};
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/blobstore",
+        world: "wasi:blobstore/imports",
+        require_store_data_send: true,
+        additional_derives: [PartialEq],
+        with: {
+            "wasi:io": wasmtime_wasi::p2::bindings::io,
+            "wasi:blobstore/types/outgoing-value": crate::blobstore::OutgoingValue,
+            "wasi:blobstore/types/incoming-value": crate::blobstore::IncomingValue,
+            "wasi:blobstore/container/container": crate::blobstore::Container,
+            "wasi:blobstore/container/stream-object-names": crate::blobstore::StreamObjectNames,
+        },
+    });
+}
+
+/// Directory in the plugin directory that holds the containers of the default backend
+pub(crate) const BLOBSTORE_DIR: &str = "blobstore";
+
+/// Largest value a component can write in one `outgoing-value`
+const MAX_VALUE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Characters of object names that are kept in file names. Everything else, including `/` and
+/// `.`, is percent-encoded so a name can't leave its container directory.
+const FILE_NAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+/// Characters of S3 keys that are kept in request paths, as SigV4 requires
+const S3_PATH_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Characters of S3 query parameters that are kept, as SigV4 requires
+const S3_QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+type Error = String;
+
+fn other(err: impl std::fmt::Display) -> Error {
+    err.to_string()
+}
+
+/// An object as the backend reports it
+struct ObjectInfo {
+    size: u64,
+    created_at: u64,
+}
+
+/// Storage of the containers of one component
+trait Backend: Send + Sync {
+    fn create_container(&self, container: &str) -> Result<(), Error>;
+    /// When the container was created, in seconds since the Unix epoch, or `None` if it doesn't
+    /// exist
+    fn container_created_at(&self, container: &str) -> Result<Option<u64>, Error>;
+    fn delete_container(&self, container: &str) -> Result<(), Error>;
+    fn list_objects(&self, container: &str) -> Result<Vec<String>, Error>;
+    /// Reads the bytes from `start` to `end` of an object, both inclusive
+    fn read_object(
+        &self,
+        container: &str,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, Error>;
+    fn write_object(&self, container: &str, name: &str, data: Bytes) -> Result<(), Error>;
+    fn delete_object(&self, container: &str, name: &str) -> Result<(), Error>;
+    fn object_info(&self, container: &str, name: &str) -> Result<Option<ObjectInfo>, Error>;
+
+    fn copy_object(&self, src: &types::ObjectId, dest: &types::ObjectId) -> Result<(), Error> {
+        let data = self.read_object(&src.container, &src.object, 0, u64::MAX)?;
+        self.write_object(&dest.container, &dest.object, data.into())
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Containers in directories under `<root>/<component_id>`
+struct FilesystemBackend {
+    root: PathBuf,
+}
+
+impl FilesystemBackend {
+    fn container_path(&self, container: &str) -> PathBuf {
+        self.root.join(container)
+    }
+
+    fn object_path(&self, container: &str, name: &str) -> PathBuf {
+        self.container_path(container)
+            .join(utf8_percent_encode(name, FILE_NAME_ENCODE_SET).to_string())
+    }
+}
+
+impl Backend for FilesystemBackend {
+    fn create_container(&self, container: &str) -> Result<(), Error> {
+        std::fs::create_dir_all(self.container_path(container)).map_err(other)
+    }
+
+    fn container_created_at(&self, container: &str) -> Result<Option<u64>, Error> {
+        match std::fs::metadata(self.container_path(container)) {
+            Ok(metadata) => Ok(Some(unix_secs(
+                metadata
+                    .created()
+                    .or_else(|_| metadata.modified())
+                    .map_err(other)?,
+            ))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(other(e)),
+        }
+    }
+
+    fn delete_container(&self, container: &str) -> Result<(), Error> {
+        match std::fs::remove_dir_all(self.container_path(container)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(other(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn list_objects(&self, container: &str) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.container_path(container)).map_err(other)? {
+            let file_name = entry.map_err(other)?.file_name();
+            let name = percent_encoding::percent_decode_str(&file_name.to_string_lossy())
+                .decode_utf8()
+                .map_err(other)?
+                .to_string();
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    fn read_object(
+        &self,
+        container: &str,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, Error> {
+        use std::io::{Read, Seek};
+
+        let mut file = std::fs::File::open(self.object_path(container, name)).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                format!("Object {name} does not exist")
+            } else {
+                other(e)
+            }
+        })?;
+        file.seek(std::io::SeekFrom::Start(start)).map_err(other)?;
+        let mut data = Vec::new();
+        file.take(end.saturating_sub(start).saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(other)?;
+        Ok(data)
+    }
+
+    fn write_object(&self, container: &str, name: &str, data: Bytes) -> Result<(), Error> {
+        // Write to a temporary file first so readers never see a partial object
+        let path = self.object_path(container, name);
+        let mut file =
+            tempfile::NamedTempFile::new_in(self.container_path(container)).map_err(other)?;
+        std::io::Write::write_all(&mut file, &data).map_err(other)?;
+        file.persist(path).map_err(other)?;
+        Ok(())
+    }
+
+    fn delete_object(&self, container: &str, name: &str) -> Result<(), Error> {
+        match std::fs::remove_file(self.object_path(container, name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(other(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn object_info(&self, container: &str, name: &str) -> Result<Option<ObjectInfo>, Error> {
+        match std::fs::metadata(self.object_path(container, name)) {
+            Ok(metadata) => Ok(Some(ObjectInfo {
+                size: metadata.len(),
+                created_at: unix_secs(metadata.modified().map_err(other)?),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(other(e)),
+        }
+    }
+
+    fn copy_object(&self, src: &types::ObjectId, dest: &types::ObjectId) -> Result<(), Error> {
+        let data = std::fs::read(self.object_path(&src.container, &src.object)).map_err(other)?;
+        self.write_object(&dest.container, &dest.object, data.into())
+    }
+}
+
+/// Credentials used to sign S3 requests
+struct S3Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Credentials {
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID")
+                .context("AWS_ACCESS_KEY_ID is not set for the S3 blobstore backend")?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                .context("AWS_SECRET_ACCESS_KEY is not set for the S3 blobstore backend")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// Containers stored under `<prefix><component_id>/<container>/` in an S3 bucket. A container
+/// exists if its marker object, the empty key `<container>/`, exists.
+struct S3Backend {
+    client: reqwest::Client,
+    endpoint: url::Url,
+    bucket: String,
+    region: String,
+    root: String,
+    credentials: S3Credentials,
+}
+
+/// The mutable parts of an S3 request
+struct S3Request<'a> {
+    method: reqwest::Method,
+    key: &'a str,
+    query: Vec<(&'a str, String)>,
+    headers: Vec<(&'static str, String)>,
+    body: Bytes,
+}
+
+impl<'a> S3Request<'a> {
+    fn new(method: reqwest::Method, key: &'a str) -> Self {
+        Self {
+            method,
+            key,
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derives the SigV4 key of a secret for one day, region and service
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    [date, region, service, "aws4_request"].iter().fold(
+        format!("AWS4{secret_access_key}").into_bytes(),
+        |key, part| hmac_sha256(&key, part),
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Runs `future` to completion on a runtime of its own. Host functions are synchronous and run
+/// on a worker of the server's runtime, which can't block on a future itself.
+fn block_on<F>(future: F) -> Result<F::Output, Error>
+where
+    F: std::future::Future + Send,
+    F::Output: Send,
+{
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map(|runtime| runtime.block_on(future))
+                    .map_err(other)
+            })
+            .join()
+            .map_err(|_| "S3 request panicked".to_string())?
+    })
+}
+
+/// Text of the `<tag>` elements in `xml`, unescaped
+fn xml_elements(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| {
+            let text = rest.split_once(close.as_str())?.0;
+            Some(
+                text.replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            )
+        })
+        .collect()
+}
+
+impl S3Backend {
+    fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: Option<&str>,
+        prefix: Option<&str>,
+        component_id: &str,
+    ) -> anyhow::Result<Self> {
+        let prefix = prefix.unwrap_or_default().trim_matches('/');
+        Ok(Self {
+            // Connections are not pooled, because every request runs on a runtime of its own
+            client: reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()?,
+            endpoint: url::Url::parse(endpoint)
+                .with_context(|| format!("Invalid blobstore endpoint: {endpoint}"))?,
+            bucket: bucket.to_string(),
+            region: region.unwrap_or("us-east-1").to_string(),
+            root: if prefix.is_empty() {
+                format!("{component_id}/")
+            } else {
+                format!("{prefix}/{component_id}/")
+            },
+            credentials: S3Credentials::from_env()?,
+        })
+    }
+
+    fn container_key(&self, container: &str) -> String {
+        format!("{}{container}/", self.root)
+    }
+
+    fn object_key(&self, container: &str, name: &str) -> String {
+        format!("{}{container}/{name}", self.root)
+    }
+
+    /// Signs `request` with AWS Signature Version 4 and sends it
+    fn send(&self, request: S3Request<'_>) -> Result<reqwest::Response, Error> {
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(&request.body));
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{port}", self.endpoint.host_str().unwrap_or_default()),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let path = format!(
+            "/{}/{}",
+            self.bucket,
+            utf8_percent_encode(request.key, S3_PATH_ENCODE_SET)
+        );
+
+        let mut query: Vec<(String, String)> = request
+            .query
+            .iter()
+            .map(|(name, value)| {
+                (
+                    utf8_percent_encode(name, S3_QUERY_ENCODE_SET).to_string(),
+                    utf8_percent_encode(value, S3_QUERY_ENCODE_SET).to_string(),
+                )
+            })
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.extend(request.headers);
+        headers.sort();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            request.method
+        );
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(
+            &self.credentials.secret_access_key,
+            &date,
+            &self.region,
+            "s3",
+        );
+        let signature = hex(&hmac_sha256(&key, &string_to_sign));
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query((!query.is_empty()).then_some(query.as_str()));
+        let mut builder = self
+            .client
+            .request(request.method, url.as_str())
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.credentials.access_key_id
+                ),
+            )
+            .body(request.body);
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            builder = builder.header(name, value);
+        }
+        block_on(builder.send())?.map_err(other)
+    }
+
+    /// Sends `request` and fails unless the response is a success
+    fn send_ok(&self, request: S3Request<'_>) -> Result<reqwest::Response, Error> {
+        let response = self.send(request)?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            let status = response.status();
+            let body = block_on(response.text())?.unwrap_or_default();
+            let message = xml_elements(&body, "Message").pop().unwrap_or_default();
+            Err(format!("S3 request failed with {status}: {message}"))
+        }
+    }
+
+    /// Keys under `prefix`, following continuation tokens
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut request = S3Request::new(reqwest::Method::GET, "");
+            request.query = vec![
+                ("list-type", "2".to_string()),
+                ("prefix", prefix.to_string()),
+            ];
+            if let Some(token) = token.take() {
+                request.query.push(("continuation-token", token));
+            }
+            let response = self.send_ok(request)?;
+            let body = block_on(response.text())?.map_err(other)?;
+            keys.extend(xml_elements(&body, "Key"));
+            let truncated = xml_elements(&body, "IsTruncated")
+                .iter()
+                .any(|t| t == "true");
+            match xml_elements(&body, "NextContinuationToken").pop() {
+                Some(next) if truncated => token = Some(next),
+                _ => return Ok(keys),
+            }
+        }
+    }
+
+    /// Size and last modification of `key`, or `None` if it doesn't exist
+    fn head(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        let response = self.send(S3Request::new(reqwest::Method::HEAD, key))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("S3 request failed with {}", response.status()));
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(Some(ObjectInfo {
+            size: header("content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or_default(),
+            created_at: header("last-modified")
+                .and_then(|date| chrono::DateTime::parse_from_rfc2822(&date).ok())
+                .map(|date| date.timestamp().max(0) as u64)
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+impl Backend for S3Backend {
+    fn create_container(&self, container: &str) -> Result<(), Error> {
+        let key = self.container_key(container);
+        self.send_ok(S3Request::new(reqwest::Method::PUT, &key))?;
+        Ok(())
+    }
+
+    fn container_created_at(&self, container: &str) -> Result<Option<u64>, Error> {
+        Ok(self
+            .head(&self.container_key(container))?
+            .map(|info| info.created_at))
+    }
+
+    fn delete_container(&self, container: &str) -> Result<(), Error> {
+        for key in self.list_keys(&self.container_key(container))? {
+            self.send_ok(S3Request::new(reqwest::Method::DELETE, &key))?;
+        }
+        Ok(())
+    }
+
+    fn list_objects(&self, container: &str) -> Result<Vec<String>, Error> {
+        let prefix = self.container_key(container);
+        Ok(self
+            .list_keys(&prefix)?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
+            .filter(|name| !name.is_empty())
+            .collect())
+    }
+
+    fn read_object(
+        &self,
+        container: &str,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, Error> {
+        let key = self.object_key(container, name);
+        let mut request = S3Request::new(reqwest::Method::GET, &key);
+        let range = if end == u64::MAX {
+            format!("bytes={start}-")
+        } else {
+            format!("bytes={start}-{end}")
+        };
+        request.headers.push(("range", range));
+        let response = self.send(request)?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Err(format!("Object {name} does not exist")),
+            // The range starts past the end of the object
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE => Ok(Vec::new()),
+            status if status.is_success() => {
+                Ok(block_on(response.bytes())?.map_err(other)?.to_vec())
+            }
+            status => Err(format!("S3 request failed with {status}")),
+        }
+    }
+
+    fn write_object(&self, container: &str, name: &str, data: Bytes) -> Result<(), Error> {
+        let key = self.object_key(container, name);
+        let mut request = S3Request::new(reqwest::Method::PUT, &key);
+        request.body = data;
+        self.send_ok(request)?;
+        Ok(())
+    }
+
+    fn delete_object(&self, container: &str, name: &str) -> Result<(), Error> {
+        let key = self.object_key(container, name);
+        self.send_ok(S3Request::new(reqwest::Method::DELETE, &key))?;
+        Ok(())
+    }
+
+    fn object_info(&self, container: &str, name: &str) -> Result<Option<ObjectInfo>, Error> {
+        self.head(&self.object_key(container, name))
+    }
+
+    fn copy_object(&self, src: &types::ObjectId, dest: &types::ObjectId) -> Result<(), Error> {
+        let key = self.object_key(&dest.container, &dest.object);
+        let mut request = S3Request::new(reqwest::Method::PUT, &key);
+        let source = format!(
+            "/{}/{}",
+            self.bucket,
+            self.object_key(&src.container, &src.object)
+        );
+        request.headers.push((
+            "x-amz-copy-source",
+            utf8_percent_encode(&source, S3_PATH_ENCODE_SET).to_string(),
+        ));
+        self.send_ok(request)?;
+        Ok(())
+    }
+}
+
+/// An open container. It only holds the name, the backend is in [`BlobstoreCtx`].
+pub struct Container {
+    name: String,
+}
+
+/// The names of the objects of a container that were not read yet
+pub struct StreamObjectNames {
+    names: VecDeque<String>,
+}
+
+/// A value that is being written. Its stream writes into `body`.
+pub struct OutgoingValue {
+    body: MemoryOutputPipe,
+    stream_taken: bool,
+}
+
+/// A value that was read from a container
+pub struct IncomingValue {
+    data: Bytes,
+}
+
+/// The `wasi:blobstore` state of a single component instance
+#[derive(Default)]
+pub(crate) struct BlobstoreCtx {
+    backend_config: BlobstoreBackend,
+    containers: HashSet<String>,
+    backend: Option<Arc<dyn Backend>>,
+    component_id: String,
+}
+
+impl BlobstoreCtx {
+    /// Creates a context that may use the given containers once it is bound to a component
+    pub(crate) fn new(containers: HashSet<String>, backend_config: BlobstoreBackend) -> Self {
+        Self {
+            backend_config,
+            containers,
+            ..Default::default()
+        }
+    }
+
+    /// Connects the context to its backend, in the namespace of `component_id`. Relative
+    /// filesystem paths are resolved against `plugin_dir`. Components without containers never
+    /// touch the backend.
+    pub(crate) fn bind(&mut self, plugin_dir: &Path, component_id: &str) -> anyhow::Result<()> {
+        self.component_id = component_id.to_string();
+        if self.containers.is_empty() {
+            return Ok(());
+        }
+        let backend: Arc<dyn Backend> = match &self.backend_config {
+            BlobstoreBackend::Filesystem { path } => Arc::new(FilesystemBackend {
+                root: plugin_dir
+                    .join(path.as_deref().unwrap_or(BLOBSTORE_DIR))
+                    .join(component_id),
+            }),
+            BlobstoreBackend::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+            } => Arc::new(S3Backend::new(
+                endpoint,
+                bucket,
+                region.as_deref(),
+                prefix.as_deref(),
+                component_id,
+            )?),
+        };
+        self.backend = Some(backend);
+        Ok(())
+    }
+
+    fn backend(&self) -> Result<&dyn Backend, Error> {
+        self.backend
+            .as_deref()
+            .ok_or_else(|| "No blobstore is available".to_string())
+    }
+
+    /// The backend, if the policy allows `container`
+    fn allowed(&self, container: &str) -> Result<&dyn Backend, Error> {
+        if !self.containers.contains(container) {
+            debug!(
+                component_id = %self.component_id,
+                container,
+                "Blobstore container denied by policy"
+            );
+            return Err(format!("Access to container {container} denied by policy"));
+        }
+        self.backend()
+    }
+}
+
+/// A view of a component's `wasi:blobstore` state used by the host bindings
+pub(crate) struct BlobstoreHost<'a> {
+    ctx: &'a BlobstoreCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> BlobstoreHost<'a> {
+    pub(crate) fn new(ctx: &'a BlobstoreCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    fn container_name(&self, container: &Resource<Container>) -> Result<String, Error> {
+        Ok(self.table.get(container).map_err(other)?.name.clone())
+    }
+
+    /// Opens `name` if it exists
+    fn open(&mut self, name: String) -> Result<Resource<Container>, Error> {
+        if self
+            .ctx
+            .allowed(&name)?
+            .container_created_at(&name)?
+            .is_none()
+        {
+            return Err(format!("Container {name} does not exist"));
+        }
+        self.table.push(Container { name }).map_err(other)
+    }
+
+    /// The backend, if both containers are allowed and the destination exists
+    fn transfer(
+        &self,
+        src: &types::ObjectId,
+        dest: &types::ObjectId,
+    ) -> Result<&dyn Backend, Error> {
+        self.ctx.allowed(&src.container)?;
+        let backend = self.ctx.allowed(&dest.container)?;
+        if backend.container_created_at(&dest.container)?.is_none() {
+            return Err(format!("Container {} does not exist", dest.container));
+        }
+        Ok(backend)
+    }
+}
+
+impl blobstore::Host for BlobstoreHost<'_> {
+    fn create_container(&mut self, name: String) -> Result<Resource<Container>, Error> {
+        let backend = self.ctx.allowed(&name)?;
+        if backend.container_created_at(&name)?.is_some() {
+            return Err(format!("Container {name} already exists"));
+        }
+        backend.create_container(&name)?;
+        self.table.push(Container { name }).map_err(other)
+    }
+
+    fn get_container(&mut self, name: String) -> Result<Resource<Container>, Error> {
+        self.open(name)
+    }
+
+    fn delete_container(&mut self, name: String) -> Result<(), Error> {
+        self.ctx.allowed(&name)?.delete_container(&name)
+    }
+
+    fn container_exists(&mut self, name: String) -> Result<bool, Error> {
+        Ok(self
+            .ctx
+            .allowed(&name)?
+            .container_created_at(&name)?
+            .is_some())
+    }
+
+    fn copy_object(&mut self, src: types::ObjectId, dest: types::ObjectId) -> Result<(), Error> {
+        self.transfer(&src, &dest)?.copy_object(&src, &dest)
+    }
+
+    fn move_object(&mut self, src: types::ObjectId, dest: types::ObjectId) -> Result<(), Error> {
+        let backend = self.transfer(&src, &dest)?;
+        if src == dest {
+            return Ok(());
+        }
+        backend.copy_object(&src, &dest)?;
+        backend.delete_object(&src.container, &src.object)
+    }
+}
+
+impl container::Host for BlobstoreHost<'_> {}
+
+impl container::HostContainer for BlobstoreHost<'_> {
+    fn name(&mut self, container: Resource<Container>) -> Result<String, Error> {
+        self.container_name(&container)
+    }
+
+    fn info(&mut self, container: Resource<Container>) -> Result<types::ContainerMetadata, Error> {
+        let name = self.container_name(&container)?;
+        let created_at = self
+            .ctx
+            .backend()?
+            .container_created_at(&name)?
+            .ok_or_else(|| format!("Container {name} does not exist"))?;
+        Ok(types::ContainerMetadata { name, created_at })
+    }
+
+    fn get_data(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+        start: u64,
+        end: u64,
+    ) -> Result<Resource<IncomingValue>, Error> {
+        if end < start {
+            return Err(format!("Invalid range {start}-{end}"));
+        }
+        let container = self.container_name(&container)?;
+        let data = self
+            .ctx
+            .backend()?
+            .read_object(&container, &name, start, end)?;
+        self.table
+            .push(IncomingValue { data: data.into() })
+            .map_err(other)
+    }
+
+    fn write_data(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+        data: Resource<OutgoingValue>,
+    ) -> Result<(), Error> {
+        let container = self.container_name(&container)?;
+        let data = self.table.get(&data).map_err(other)?.body.contents();
+        self.ctx.backend()?.write_object(&container, &name, data)
+    }
+
+    fn list_objects(
+        &mut self,
+        container: Resource<Container>,
+    ) -> Result<Resource<StreamObjectNames>, Error> {
+        let container = self.container_name(&container)?;
+        let mut names = self.ctx.backend()?.list_objects(&container)?;
+        names.sort();
+        self.table
+            .push(StreamObjectNames {
+                names: names.into(),
+            })
+            .map_err(other)
+    }
+
+    fn delete_object(&mut self, container: Resource<Container>, name: String) -> Result<(), Error> {
+        let container = self.container_name(&container)?;
+        self.ctx.backend()?.delete_object(&container, &name)
+    }
+
+    fn delete_objects(
+        &mut self,
+        container: Resource<Container>,
+        names: Vec<String>,
+    ) -> Result<(), Error> {
+        let container = self.container_name(&container)?;
+        let backend = self.ctx.backend()?;
+        for name in &names {
+            backend.delete_object(&container, name)?;
+        }
+        Ok(())
+    }
+
+    fn has_object(&mut self, container: Resource<Container>, name: String) -> Result<bool, Error> {
+        let container = self.container_name(&container)?;
+        Ok(self
+            .ctx
+            .backend()?
+            .object_info(&container, &name)?
+            .is_some())
+    }
+
+    fn object_info(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+    ) -> Result<types::ObjectMetadata, Error> {
+        let container = self.container_name(&container)?;
+        let info = self
+            .ctx
+            .backend()?
+            .object_info(&container, &name)?
+            .ok_or_else(|| format!("Object {name} does not exist"))?;
+        Ok(types::ObjectMetadata {
+            name,
+            container,
+            created_at: info.created_at,
+            size: info.size,
+        })
+    }
+
+    fn clear(&mut self, container: Resource<Container>) -> Result<(), Error> {
+        let container = self.container_name(&container)?;
+        let backend = self.ctx.backend()?;
+        for name in backend.list_objects(&container)? {
+            backend.delete_object(&container, &name)?;
+        }
+        Ok(())
+    }
+
+    fn drop(&mut self, container: Resource<Container>) -> wasmtime::Result<()> {
+        self.table.delete(container)?;
+        Ok(())
+    }
+}
+
+impl container::HostStreamObjectNames for BlobstoreHost<'_> {
+    fn read_stream_object_names(
+        &mut self,
+        stream: Resource<StreamObjectNames>,
+        len: u64,
+    ) -> Result<(Vec<String>, bool), Error> {
+        let stream = self.table.get_mut(&stream).map_err(other)?;
+        let len = (len as usize).min(stream.names.len());
+        let names = stream.names.drain(..len).collect();
+        Ok((names, stream.names.is_empty()))
+    }
+
+    fn skip_stream_object_names(
+        &mut self,
+        stream: Resource<StreamObjectNames>,
+        num: u64,
+    ) -> Result<(u64, bool), Error> {
+        let stream = self.table.get_mut(&stream).map_err(other)?;
+        let num = (num as usize).min(stream.names.len());
+        stream.names.drain(..num);
+        Ok((num as u64, stream.names.is_empty()))
+    }
+
+    fn drop(&mut self, stream: Resource<StreamObjectNames>) -> wasmtime::Result<()> {
+        self.table.delete(stream)?;
+        Ok(())
+    }
+}
+
+impl types::Host for BlobstoreHost<'_> {}
+
+impl types::HostOutgoingValue for BlobstoreHost<'_> {
+    fn new_outgoing_value(&mut self) -> Resource<OutgoingValue> {
+        self.table
+            .push(OutgoingValue {
+                body: MemoryOutputPipe::new(MAX_VALUE_SIZE),
+                stream_taken: false,
+            })
+            .expect("the resource table is not full")
+    }
+
+    fn outgoing_value_write_body(
+        &mut self,
+        value: Resource<OutgoingValue>,
+    ) -> Result<Resource<DynOutputStream>, ()> {
+        let value = self.table.get_mut(&value).map_err(|_| ())?;
+        if value.stream_taken {
+            return Err(());
+        }
+        value.stream_taken = true;
+        let stream: DynOutputStream = Box::new(value.body.clone());
+        self.table.push(stream).map_err(|_| ())
+    }
+
+    fn finish(&mut self, value: Resource<OutgoingValue>) -> Result<(), Error> {
+        self.table.delete(value).map_err(other)?;
+        Ok(())
+    }
+
+    fn drop(&mut self, value: Resource<OutgoingValue>) -> wasmtime::Result<()> {
+        self.table.delete(value)?;
+        Ok(())
+    }
+}
+
+impl types::HostIncomingValue for BlobstoreHost<'_> {
+    fn incoming_value_consume_sync(
+        &mut self,
+        value: Resource<IncomingValue>,
+    ) -> Result<Vec<u8>, Error> {
+        Ok(self.table.delete(value).map_err(other)?.data.to_vec())
+    }
+
+    fn incoming_value_consume_async(
+        &mut self,
+        value: Resource<IncomingValue>,
+    ) -> Result<Resource<DynInputStream>, Error> {
+        let data = self.table.delete(value).map_err(other)?.data;
+        let stream: DynInputStream = Box::new(MemoryInputPipe::new(data));
+        self.table.push(stream).map_err(other)
+    }
+
+    fn size(&mut self, value: Resource<IncomingValue>) -> u64 {
+        self.table
+            .get(&value)
+            .map(|value| value.data.len() as u64)
+            .unwrap_or_default()
+    }
+
+    fn drop(&mut self, value: Resource<IncomingValue>) -> wasmtime::Result<()> {
+        self.table.delete(value)?;
+        Ok(())
+    }
+}
+
+/// Adds the `wasi:blobstore` interfaces to the linker
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> BlobstoreHost<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    blobstore::add_to_linker_get_host(linker, f)?;
+    container::add_to_linker_get_host(linker, f)?;
+    types::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
+
+impl crate::LifecycleManager {
+    /// Deletes the containers of a component in the default blobstore directory
+    pub(crate) async fn delete_blobstore(&self, component_id: &str) -> anyhow::Result<()> {
+        let path = self.plugin_dir.join(BLOBSTORE_DIR).join(component_id);
+        match tokio::fs::remove_dir_all(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blobstore::Host as _;
+    use container::{HostContainer as _, HostStreamObjectNames as _};
+    use types::{HostIncomingValue as _, HostOutgoingValue as _};
+
+    use super::*;
+
+    fn ctx(plugin_dir: &Path, component_id: &str, containers: &[&str]) -> BlobstoreCtx {
+        let mut ctx = BlobstoreCtx::new(
+            containers.iter().map(|c| c.to_string()).collect(),
+            BlobstoreBackend::default(),
+        );
+        ctx.bind(plugin_dir, component_id).unwrap();
+        ctx
+    }
+
+    fn borrow<T: 'static>(resource: &Resource<T>) -> Resource<T> {
+        Resource::new_borrow(resource.rep())
+    }
+
+    fn write(
+        host: &mut BlobstoreHost<'_>,
+        container: &Resource<Container>,
+        name: &str,
+        data: &[u8],
+    ) {
+        let value = host.new_outgoing_value();
+        let stream = host.outgoing_value_write_body(borrow(&value)).unwrap();
+        let mut body = host.table.delete(stream).unwrap();
+        body.write(Bytes::copy_from_slice(data)).unwrap();
+        host.write_data(borrow(container), name.to_string(), borrow(&value))
+            .unwrap();
+        host.finish(value).unwrap();
+    }
+
+    fn read(host: &mut BlobstoreHost<'_>, container: &Resource<Container>, name: &str) -> Vec<u8> {
+        let value = host
+            .get_data(borrow(container), name.to_string(), 0, u64::MAX)
+            .unwrap();
+        host.incoming_value_consume_sync(value).unwrap()
+    }
+
+    #[test]
+    fn test_container_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ctx(dir.path(), "reports-component", &["reports", "archive"]);
+        let mut table = ResourceTable::new();
+        let mut host = BlobstoreHost::new(&ctx, &mut table);
+
+        assert_eq!(host.container_exists("reports".to_string()), Ok(false));
+        assert!(host.get_container("reports".to_string()).is_err());
+        let reports = host.create_container("reports".to_string()).unwrap();
+        assert!(host.create_container("reports".to_string()).is_err());
+        assert_eq!(host.container_exists("reports".to_string()), Ok(true));
+        assert_eq!(host.name(borrow(&reports)), Ok("reports".to_string()));
+
+        write(&mut host, &reports, "2025/summary.md", b"# Summary");
+        write(&mut host, &reports, "../escape", b"stays inside");
+        assert_eq!(read(&mut host, &reports, "2025/summary.md"), b"# Summary");
+        assert!(dir
+            .path()
+            .join("blobstore/reports-component/reports")
+            .read_dir()
+            .unwrap()
+            .all(|entry| !entry.unwrap().file_name().to_string_lossy().contains('/')));
+
+        let partial = host
+            .get_data(borrow(&reports), "2025/summary.md".to_string(), 2, 4)
+            .unwrap();
+        assert_eq!(host.size(borrow(&partial)), 3);
+        assert_eq!(
+            host.incoming_value_consume_sync(partial),
+            Ok(b"Sum".to_vec())
+        );
+
+        let info = host
+            .object_info(borrow(&reports), "2025/summary.md".to_string())
+            .unwrap();
+        assert_eq!((info.size, info.container.as_str()), (9, "reports"));
+        assert_eq!(
+            host.has_object(borrow(&reports), "missing".to_string()),
+            Ok(false)
+        );
+
+        let names = host.list_objects(borrow(&reports)).unwrap();
+        assert_eq!(
+            host.read_stream_object_names(borrow(&names), 1),
+            Ok((vec!["../escape".to_string()], false))
+        );
+        assert_eq!(
+            host.read_stream_object_names(borrow(&names), 10),
+            Ok((vec!["2025/summary.md".to_string()], true))
+        );
+
+        host.create_container("archive".to_string()).unwrap();
+        let id = |container: &str, object: &str| types::ObjectId {
+            container: container.to_string(),
+            object: object.to_string(),
+        };
+        host.move_object(
+            id("reports", "2025/summary.md"),
+            id("archive", "summary.md"),
+        )
+        .unwrap();
+        assert_eq!(
+            host.has_object(borrow(&reports), "2025/summary.md".to_string()),
+            Ok(false)
+        );
+        let archive = host.get_container("archive".to_string()).unwrap();
+        assert_eq!(read(&mut host, &archive, "summary.md"), b"# Summary");
+
+        host.clear(borrow(&reports)).unwrap();
+        let names = host.list_objects(borrow(&reports)).unwrap();
+        assert_eq!(host.read_stream_object_names(names, 10), Ok((vec![], true)));
+        host.delete_container("archive".to_string()).unwrap();
+        assert_eq!(host.container_exists("archive".to_string()), Ok(false));
+    }
+
+    #[test]
+    fn test_containers_are_gated_and_namespaced() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let first_ctx = ctx(dir.path(), "first", &["shared"]);
+        let mut first_table = ResourceTable::new();
+        let mut first = BlobstoreHost::new(&first_ctx, &mut first_table);
+        assert!(first.create_container("secrets".to_string()).is_err());
+        let shared = first.create_container("shared".to_string()).unwrap();
+        write(&mut first, &shared, "note", b"from first");
+        assert!(first
+            .copy_object(
+                types::ObjectId {
+                    container: "shared".to_string(),
+                    object: "note".to_string(),
+                },
+                types::ObjectId {
+                    container: "secrets".to_string(),
+                    object: "note".to_string(),
+                },
+            )
+            .is_err());
+
+        let second_ctx = ctx(dir.path(), "second", &["shared"]);
+        let mut second_table = ResourceTable::new();
+        let mut second = BlobstoreHost::new(&second_ctx, &mut second_table);
+        assert_eq!(second.container_exists("shared".to_string()), Ok(false));
+
+        // A component without containers has no backend at all
+        let none = ctx(dir.path(), "third", &[]);
+        let mut none_table = ResourceTable::new();
+        let mut none = BlobstoreHost::new(&none, &mut none_table);
+        assert!(none.container_exists("shared".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // The example from the AWS Signature Version 4 documentation
+        assert_eq!(
+            hex(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam",
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_xml_elements() {
+        let xml = "<ListBucketResult><IsTruncated>true</IsTruncated>\
+            <Contents><Key>a/b&amp;c</Key></Contents><Contents><Key>a/d</Key></Contents>\
+            <NextContinuationToken>token</NextContinuationToken></ListBucketResult>";
+        assert_eq!(xml_elements(xml, "Key"), vec!["a/b&c", "a/d"]);
+        assert_eq!(xml_elements(xml, "IsTruncated"), vec!["true"]);
+        assert!(xml_elements(xml, "Missing").is_empty());
+    }
+}
@@ -0,0 +1,790 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Export and import of component bundles
+//!
+//! A bundle is a gzipped tarball holding a set of components together with their policies,
+//! policy metadata, state and secrets, so a plugin directory can be moved to another machine or
+//! shared with a team. The archive layout is:
+//!
+//! ```text
+//! bundle.json                          # BundleManifest
+//! components/<id>.wasm
+//! components/<id>.policy.yaml          # optional
+//! components/<id>.policy.meta.json     # optional
+//! components/<id>.keyvalue.json        # optional, key-value buckets
+//! components/<id>.blobstore/           # optional, containers of the default blobstore directory
+//! components/<id>.secrets.json         # optional, secrets and their scopes
+//! ```
+//!
+//! Secrets are stored in plain text unless the bundle is exported with a passphrase. They are
+//! then encrypted with XSalsa20-Poly1305 under a key derived from the passphrase with scrypt, and
+//! only the names of the secrets and their scopes can be read without it.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::XSalsa20Poly1305;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand_core::RngCore as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, instrument};
+
+use crate::blobstore::BLOBSTORE_DIR;
+use crate::keyvalue::Buckets;
+use crate::ComponentNotFound;
+
+/// Version of the bundle format written by [`crate::LifecycleManager::export_bundle`]
+pub const BUNDLE_FORMAT_VERSION: u32 = 2;
+
+const BUNDLE_MANIFEST_FILE: &str = "bundle.json";
+const BUNDLE_COMPONENTS_DIR: &str = "components";
+
+/// scrypt cost of the key that encrypts the secrets of a bundle, as the base-2 logarithm of N.
+/// This is what cosign uses for its keys.
+const SECRETS_SCRYPT_LOG_N: u8 = 15;
+
+/// Highest scrypt cost accepted from a bundle, so a crafted bundle can't exhaust the memory
+const MAX_SECRETS_SCRYPT_LOG_N: u8 = 20;
+
+/// Describes the contents of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    /// Version of the bundle format
+    pub format_version: u32,
+    /// When the bundle was created, in seconds since the Unix epoch
+    pub created_at: u64,
+    /// The components in the bundle
+    pub components: Vec<BundledComponent>,
+    /// How the secrets in the bundle are encrypted, if they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets_encryption: Option<SecretsEncryption>,
+}
+
+/// The key derivation of the passphrase that the secrets in a bundle are encrypted with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SecretsEncryption {
+    /// scrypt cost, as the base-2 logarithm of N
+    pub log_n: u8,
+    /// Base64 encoded scrypt salt
+    pub salt: String,
+}
+
+impl SecretsEncryption {
+    fn key(&self, passphrase: &str) -> Result<[u8; 32]> {
+        if self.log_n > MAX_SECRETS_SCRYPT_LOG_N {
+            bail!("Invalid scrypt cost in bundle: {}", self.log_n);
+        }
+        let params = scrypt::Params::new(self.log_n, 8, 1, 32)
+            .map_err(|e| anyhow::anyhow!("Invalid scrypt parameters in bundle: {e}"))?;
+        let salt = BASE64
+            .decode(&self.salt)
+            .context("Invalid scrypt salt in bundle")?;
+        let mut key = [0u8; 32];
+        scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive key: {e}"))?;
+        Ok(key)
+    }
+}
+
+/// A component stored in a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledComponent {
+    /// ID of the component
+    pub component_id: String,
+    /// Hex encoded SHA-256 digest of the component's wasm file
+    pub sha256: String,
+    /// Whether the bundle contains a policy for the component
+    pub has_policy: bool,
+    /// Whether the bundle contains key-value buckets or blobstore containers of the component
+    #[serde(default)]
+    pub has_state: bool,
+    /// Whether the bundle contains secrets of the component
+    #[serde(default)]
+    pub has_secrets: bool,
+}
+
+/// The secrets of a component in a bundle
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BundledSecrets {
+    /// The secrets by name, unless they are encrypted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    values: BTreeMap<String, String>,
+    /// The secrets by name as encrypted JSON, if the bundle has a passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted: Option<EncryptedSecrets>,
+    /// The exported functions that secrets are limited to, by the name of the secret
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scopes: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedSecrets {
+    /// Base64 encoded nonce
+    nonce: String,
+    /// Base64 encoded ciphertext
+    ciphertext: String,
+}
+
+impl BundledSecrets {
+    fn new(
+        values: BTreeMap<String, String>,
+        scopes: BTreeMap<String, Vec<String>>,
+        key: Option<&[u8; 32]>,
+    ) -> Result<Self> {
+        let Some(key) = key else {
+            return Ok(Self {
+                values,
+                encrypted: None,
+                scopes,
+            });
+        };
+        let mut nonce = [0u8; 24];
+        rand_core::OsRng.fill_bytes(&mut nonce);
+        let ciphertext = XSalsa20Poly1305::new(key.into())
+            .encrypt(&nonce.into(), serde_json::to_vec(&values)?.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secrets"))?;
+        Ok(Self {
+            values: BTreeMap::new(),
+            encrypted: Some(EncryptedSecrets {
+                nonce: BASE64.encode(nonce),
+                ciphertext: BASE64.encode(ciphertext),
+            }),
+            scopes,
+        })
+    }
+
+    /// The secrets by name, decrypted with `key` if they are encrypted
+    fn values(self, key: Option<&[u8; 32]>) -> Result<BTreeMap<String, String>> {
+        let Some(encrypted) = self.encrypted else {
+            return Ok(self.values);
+        };
+        let key = key.context("Bundle has encrypted secrets but no secrets encryption")?;
+        let nonce = BASE64.decode(&encrypted.nonce)?;
+        if nonce.len() != 24 {
+            bail!("Invalid nonce for secrets in bundle");
+        }
+        let plaintext = XSalsa20Poly1305::new(key.into())
+            .decrypt(
+                nonce.as_slice().into(),
+                BASE64.decode(&encrypted.ciphertext)?.as_slice(),
+            )
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase for the secrets in the bundle"))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+/// The state and secrets of a bundled component, read before the import changes anything
+struct ImportedData {
+    wasm: Vec<u8>,
+    buckets: Buckets,
+    secrets: BTreeMap<String, String>,
+    scopes: BTreeMap<String, Vec<String>>,
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Rejects IDs that would escape the components directory when used as a file name
+pub(crate) fn validate_component_id(component_id: &str) -> Result<()> {
+    if component_id.is_empty()
+        || component_id.contains(['/', '\\'])
+        || component_id.starts_with('.')
+    {
+        bail!("Invalid component ID: {:?}", component_id);
+    }
+    Ok(())
+}
+
+impl crate::LifecycleManager {
+    /// Writes the given loaded components, with their policies, state and secrets, to a bundle
+    /// at `output`. Every loaded component is exported when `component_ids` is empty. The
+    /// secrets are encrypted with `passphrase` if one is given, and stored in plain text
+    /// otherwise.
+    #[instrument(skip(self, passphrase))]
+    pub async fn export_bundle(
+        &self,
+        component_ids: &[String],
+        output: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<BundleManifest> {
+        let mut component_ids = if component_ids.is_empty() {
+            self.list_components().await
+        } else {
+            component_ids.to_vec()
+        };
+        component_ids.sort();
+        component_ids.dedup();
+
+        let secrets_encryption = passphrase.map(|_| {
+            let mut salt = [0u8; 32];
+            rand_core::OsRng.fill_bytes(&mut salt);
+            SecretsEncryption {
+                log_n: SECRETS_SCRYPT_LOG_N,
+                salt: BASE64.encode(salt),
+            }
+        });
+        let key = match (&secrets_encryption, passphrase) {
+            (Some(encryption), Some(passphrase)) => Some(encryption.key(passphrase)?),
+            _ => None,
+        };
+
+        let mut components = Vec::new();
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        let mut data = Vec::new();
+        for component_id in &component_ids {
+            if self.get_component(component_id).await.is_none() {
+                return Err(ComponentNotFound::Component(component_id.to_string()).into());
+            }
+
+            let wasm = tokio::fs::read(self.component_path(component_id))
+                .await
+                .with_context(|| format!("Failed to read component {component_id}"))?;
+            let mut has_policy = false;
+            for (path, file_name) in self.component_files(component_id) {
+                if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                    continue;
+                }
+                if path == self.get_component_policy_path(component_id) {
+                    has_policy = true;
+                }
+                files.push((path, file_name));
+            }
+
+            let buckets = self.keyvalue.export_component(component_id)?;
+            let blobstore = self.plugin_dir.join(BLOBSTORE_DIR).join(component_id);
+            let has_blobstore = tokio::fs::try_exists(&blobstore).await.unwrap_or(false);
+            let has_state = has_blobstore || !buckets.is_empty();
+            if !buckets.is_empty() {
+                let buckets: BTreeMap<_, BTreeMap<_, _>> = buckets
+                    .into_iter()
+                    .map(|(bucket, entries)| {
+                        let entries = entries
+                            .into_iter()
+                            .map(|(key, value)| (key, BASE64.encode(value)))
+                            .collect();
+                        (bucket, entries)
+                    })
+                    .collect();
+                data.push((
+                    format!("{component_id}.keyvalue.json"),
+                    serde_json::to_vec_pretty(&buckets)?,
+                ));
+            }
+            if has_blobstore {
+                dirs.push((blobstore, format!("{component_id}.blobstore")));
+            }
+
+            let secrets = self.secrets.load(component_id).await?;
+            let has_secrets = !secrets.is_empty();
+            if has_secrets {
+                let scopes = self.secret_scopes(component_id).await?;
+                data.push((
+                    format!("{component_id}.secrets.json"),
+                    serde_json::to_vec_pretty(&BundledSecrets::new(
+                        secrets,
+                        scopes,
+                        key.as_ref(),
+                    )?)?,
+                ));
+            }
+
+            components.push(BundledComponent {
+                component_id: component_id.clone(),
+                sha256: sha256_hex(&wasm),
+                has_policy,
+                has_state,
+                has_secrets,
+            });
+        }
+
+        let manifest = BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            created_at: crate::trash::now_secs(),
+            components,
+            secrets_encryption,
+        };
+        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+        let output = output.to_path_buf();
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let file = File::create(&output)
+                .with_context(|| format!("Failed to create bundle {}", output.display()))?;
+            let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+            let mut append_data = |name: &Path, contents: &[u8]| {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                archive.append_data(&mut header, name, contents)
+            };
+            append_data(Path::new(BUNDLE_MANIFEST_FILE), &manifest_json)?;
+            for (file_name, contents) in data {
+                append_data(&Path::new(BUNDLE_COMPONENTS_DIR).join(file_name), &contents)?;
+            }
+
+            for (path, file_name) in files {
+                archive
+                    .append_path_with_name(&path, Path::new(BUNDLE_COMPONENTS_DIR).join(file_name))
+                    .with_context(|| format!("Failed to add {} to bundle", path.display()))?;
+            }
+            for (path, dir_name) in dirs {
+                archive
+                    .append_dir_all(Path::new(BUNDLE_COMPONENTS_DIR).join(dir_name), &path)
+                    .with_context(|| format!("Failed to add {} to bundle", path.display()))?;
+            }
+
+            archive.into_inner()?.finish()?;
+            Ok(())
+        })
+        .await??;
+
+        info!(
+            components = manifest.components.len(),
+            "Exported component bundle"
+        );
+        Ok(manifest)
+    }
+
+    /// Loads every component of the bundle at `bundle` into this plugin directory, together
+    /// with its policy, state and secrets. The state in the bundle replaces the state of the
+    /// component, while its secrets are set on top of the ones it has. Components that are
+    /// already loaded are only replaced when `overwrite` is set. `passphrase` decrypts the
+    /// secrets of a bundle that was exported with one. Returns the bundle's manifest.
+    #[instrument(skip(self, passphrase))]
+    pub async fn import_bundle(
+        &self,
+        bundle: &Path,
+        overwrite: bool,
+        passphrase: Option<&str>,
+    ) -> Result<BundleManifest> {
+        let extract_dir = tempfile::tempdir()?;
+        let bundle_path = bundle.to_path_buf();
+        let extract_path = extract_dir.path().to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let file = File::open(&bundle_path)
+                .with_context(|| format!("Failed to open bundle {}", bundle_path.display()))?;
+            let mut archive = tar::Archive::new(GzDecoder::new(file));
+            let entries = archive
+                .entries()
+                .with_context(|| format!("Failed to unpack bundle {}", bundle_path.display()))?;
+            for entry in entries {
+                let mut entry = entry.with_context(|| {
+                    format!("Failed to unpack bundle {}", bundle_path.display())
+                })?;
+                // Links could make the import copy files from outside the bundle
+                let entry_type = entry.header().entry_type();
+                if !entry_type.is_file() && !entry_type.is_dir() {
+                    bail!(
+                        "Bundle entry {} is not a regular file or directory",
+                        entry.path()?.display()
+                    );
+                }
+                entry.unpack_in(&extract_path).with_context(|| {
+                    format!("Failed to unpack bundle {}", bundle_path.display())
+                })?;
+            }
+            Ok(())
+        })
+        .await??;
+
+        let manifest: BundleManifest = serde_json::from_slice(
+            &tokio::fs::read(extract_dir.path().join(BUNDLE_MANIFEST_FILE))
+                .await
+                .context("Bundle is missing its manifest")?,
+        )
+        .context("Invalid bundle manifest")?;
+        if manifest.format_version > BUNDLE_FORMAT_VERSION {
+            bail!(
+                "Unsupported bundle format version {} (this version of wassette supports up to {})",
+                manifest.format_version,
+                BUNDLE_FORMAT_VERSION
+            );
+        }
+        let key = match (&manifest.secrets_encryption, passphrase) {
+            (Some(encryption), Some(passphrase)) => Some(encryption.key(passphrase)?),
+            (Some(_), None) => {
+                bail!("The secrets in the bundle are encrypted, so a passphrase is needed")
+            }
+            (None, _) => None,
+        };
+
+        // Check the whole bundle before touching the plugin directory
+        let components_dir = extract_dir.path().join(BUNDLE_COMPONENTS_DIR);
+        let mut imported = Vec::new();
+        for bundled in &manifest.components {
+            validate_component_id(&bundled.component_id)?;
+            let wasm = tokio::fs::read(bundled_wasm_path(&components_dir, bundled))
+                .await
+                .with_context(|| format!("Bundle is missing component {}", bundled.component_id))?;
+            if sha256_hex(&wasm) != bundled.sha256 {
+                bail!(
+                    "Digest mismatch for component {} in bundle",
+                    bundled.component_id
+                );
+            }
+            if !overwrite && self.get_component(&bundled.component_id).await.is_some() {
+                bail!(
+                    "Component is already loaded: {} (use overwrite to replace it)",
+                    bundled.component_id
+                );
+            }
+            let mut data = read_bundled_data(&components_dir, bundled, key.as_ref()).await?;
+            data.wasm = wasm;
+            imported.push(data);
+        }
+
+        for (bundled, data) in manifest.components.iter().zip(imported) {
+            let component_id = &bundled.component_id;
+            for (path, file_name) in self.component_files(component_id) {
+                let bundled_file = components_dir.join(&file_name);
+                if path == self.component_path(component_id) {
+                    continue;
+                }
+                if is_regular_file(&bundled_file).await? {
+                    tokio::fs::copy(&bundled_file, &path)
+                        .await
+                        .with_context(|| format!("Failed to import {}", path.display()))?;
+                } else {
+                    // Don't let a stale policy from the plugin directory apply to the import
+                    self.remove_file_if_exists(&path, "stale policy file", component_id)
+                        .await?;
+                }
+            }
+
+            // The digest in the manifest was checked above, so the component is loaded from
+            // its bytes rather than through the verification policy for file sources
+            self.load_component_bytes(component_id, &data.wasm, None)
+                .await?;
+
+            let policy_path = self.get_component_policy_path(component_id);
+            if tokio::fs::try_exists(&policy_path).await.unwrap_or(false) {
+                let policy = self.parse_policy_file(&policy_path)?;
+                self.update_policy_registry(component_id, &policy).await?;
+            } else {
+                self.cleanup_policy_registry(component_id).await;
+            }
+
+            if bundled.has_state {
+                self.keyvalue
+                    .import_component(component_id, &data.buckets)?;
+                self.delete_blobstore(component_id).await?;
+                let from = components_dir.join(format!("{component_id}.blobstore"));
+                if tokio::fs::try_exists(&from).await.unwrap_or(false) {
+                    let to = self.plugin_dir.join(BLOBSTORE_DIR).join(component_id);
+                    tokio::task::spawn_blocking(move || copy_dir(&from, &to))
+                        .await?
+                        .context("Failed to import blobstore containers")?;
+                }
+            }
+            for (key, value) in &data.secrets {
+                self.set_secret(component_id, key, value).await?;
+            }
+            for (key, functions) in data.scopes {
+                self.set_secret_scope(component_id, &key, Some(functions))
+                    .await?;
+            }
+        }
+
+        info!(
+            components = manifest.components.len(),
+            "Imported component bundle"
+        );
+        Ok(manifest)
+    }
+}
+
+/// Reads the state and secrets of a bundled component
+async fn read_bundled_data(
+    components_dir: &Path,
+    bundled: &BundledComponent,
+    key: Option<&[u8; 32]>,
+) -> Result<ImportedData> {
+    let component_id = &bundled.component_id;
+    let mut data = ImportedData {
+        wasm: Vec::new(),
+        buckets: Buckets::new(),
+        secrets: BTreeMap::new(),
+        scopes: BTreeMap::new(),
+    };
+
+    let keyvalue_path = components_dir.join(format!("{component_id}.keyvalue.json"));
+    if bundled.has_state && tokio::fs::try_exists(&keyvalue_path).await.unwrap_or(false) {
+        let buckets: BTreeMap<String, BTreeMap<String, String>> =
+            serde_json::from_slice(&tokio::fs::read(&keyvalue_path).await?).with_context(|| {
+                format!("Invalid key-value buckets for {component_id} in bundle")
+            })?;
+        for (bucket, entries) in buckets {
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| Ok((key, BASE64.decode(value)?)))
+                .collect::<Result<_>>()
+                .with_context(|| {
+                    format!("Invalid key-value buckets for {component_id} in bundle")
+                })?;
+            data.buckets.insert(bucket, entries);
+        }
+    }
+
+    if bundled.has_secrets {
+        let mut secrets: BundledSecrets = serde_json::from_slice(
+            &tokio::fs::read(components_dir.join(format!("{component_id}.secrets.json")))
+                .await
+                .with_context(|| format!("Bundle is missing the secrets of {component_id}"))?,
+        )
+        .with_context(|| format!("Invalid secrets for {component_id} in bundle"))?;
+        data.scopes = std::mem::take(&mut secrets.scopes);
+        data.secrets = secrets.values(key)?;
+    }
+    Ok(data)
+}
+
+/// Whether there is a regular file at `path`. Fails for anything else, such as a symlink.
+async fn is_regular_file(path: &Path) -> Result<bool> {
+    match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_file() => Ok(true),
+        Ok(_) => bail!("{} in the bundle is not a regular file", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Copies the directory `from` with the files and directories in it to `to`. Fails for
+/// anything else, such as a symlink.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), target)?;
+        } else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a regular file", entry.path().display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn bundled_wasm_path(components_dir: &Path, bundled: &BundledComponent) -> PathBuf {
+    components_dir.join(format!("{}.wasm", bundled.component_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::tests::{create_test_manager, COMPONENT, TEST_COMPONENT_ID};
+    use crate::{LifecycleManager, VerificationPolicy, VerificationRules};
+
+    /// Exports `run`, which does nothing
+    #[test(tokio::test)]
+    async fn test_export_import_bundle() -> Result<()> {
+        let source = create_test_manager().await?;
+        source.load_test_component().await?;
+        source
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+
+        let bundle_dir = tempfile::tempdir()?;
+        let bundle_path = bundle_dir.path().join("team.tar.gz");
+        let manifest = source.export_bundle(&[], &bundle_path, None).await?;
+
+        assert_eq!(manifest.format_version, BUNDLE_FORMAT_VERSION);
+        assert_eq!(manifest.components.len(), 1);
+        assert_eq!(manifest.components[0].component_id, TEST_COMPONENT_ID);
+        assert!(manifest.components[0].has_policy);
+
+        let target = create_test_manager().await?;
+        let imported = target.import_bundle(&bundle_path, false, None).await?;
+
+        assert_eq!(imported, manifest);
+        assert_eq!(target.list_components().await, vec![TEST_COMPONENT_ID]);
+        assert!(target.get_component_policy_path(TEST_COMPONENT_ID).exists());
+        let templates = target.policy_registry.read().await;
+        let template = templates
+            .component_policies
+            .get(TEST_COMPONENT_ID)
+            .expect("policy should be imported");
+        assert!(template.allowed_hosts.contains("api.example.com"));
+        drop(templates);
+
+        // Importing on top of a loaded component needs an explicit overwrite
+        let err = target
+            .import_bundle(&bundle_path, false, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already loaded"));
+        target.import_bundle(&bundle_path, true, None).await?;
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_bundle_keeps_state_and_secrets() -> Result<()> {
+        let source_dir = tempfile::tempdir()?;
+        tokio::fs::write(source_dir.path().join("notes.wasm"), COMPONENT).await?;
+        let source = LifecycleManager::new(source_dir.path()).await?;
+        source.set_secret("notes", "API_KEY", "s3cret").await?;
+        source.set_secret("notes", "TOKEN", "t0ken").await?;
+        source
+            .set_secret_scope("notes", "TOKEN", Some(vec!["run".to_string()]))
+            .await?;
+        let buckets = Buckets::from([(
+            "cache".to_string(),
+            [("k".to_string(), b"v".to_vec())].into(),
+        )]);
+        source.keyvalue.import_component("notes", &buckets)?;
+        let container = source_dir.path().join(BLOBSTORE_DIR).join("notes/files");
+        tokio::fs::create_dir_all(&container).await?;
+        tokio::fs::write(container.join("todo"), "buy milk").await?;
+
+        let bundle_dir = tempfile::tempdir()?;
+        let plain = bundle_dir.path().join("plain.tar.gz");
+        let manifest = source.export_bundle(&[], &plain, None).await?;
+        assert!(manifest.components[0].has_state);
+        assert!(manifest.components[0].has_secrets);
+        assert!(manifest.secrets_encryption.is_none());
+        let encrypted = bundle_dir.path().join("encrypted.tar.gz");
+        let manifest = source
+            .export_bundle(&[], &encrypted, Some("correct horse"))
+            .await?;
+        assert!(manifest.secrets_encryption.is_some());
+
+        // The digests in the bundle are checked instead of the policy for file sources
+        let deny_all: VerificationRules =
+            serde_yaml::from_str("registries:\n  - match: \"*\"\n    deny: true\n")?;
+        for (bundle, passphrase) in [(&plain, None), (&encrypted, Some("correct horse"))] {
+            let target_dir = tempfile::tempdir()?;
+            let target = LifecycleManager::new(target_dir.path())
+                .await?
+                .with_verification_policy(VerificationPolicy::new(deny_all.clone()).await?);
+            target.import_bundle(bundle, false, passphrase).await?;
+
+            assert_eq!(
+                target.secrets.load("notes").await?,
+                BTreeMap::from([
+                    ("API_KEY".to_string(), "s3cret".to_string()),
+                    ("TOKEN".to_string(), "t0ken".to_string()),
+                ])
+            );
+            assert_eq!(
+                target.secret_scopes("notes").await?,
+                BTreeMap::from([("TOKEN".to_string(), vec!["run".to_string()])])
+            );
+            assert_eq!(target.keyvalue.export_component("notes")?, buckets);
+            assert_eq!(
+                tokio::fs::read_to_string(
+                    target_dir
+                        .path()
+                        .join(BLOBSTORE_DIR)
+                        .join("notes/files/todo")
+                )
+                .await?,
+                "buy milk"
+            );
+        }
+
+        // Encrypted secrets can't be imported without their passphrase
+        let target_dir = tempfile::tempdir()?;
+        let target = LifecycleManager::new(target_dir.path()).await?;
+        let err = target
+            .import_bundle(&encrypted, false, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("passphrase is needed"));
+        let err = target
+            .import_bundle(&encrypted, false, Some("wrong"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"));
+        assert!(target.list_components().await.is_empty());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_import_bundle_rejects_links() -> Result<()> {
+        let bundle_dir = tempfile::tempdir()?;
+        let bundle_path = bundle_dir.path().join("links.tar.gz");
+        let mut archive = tar::Builder::new(GzEncoder::new(
+            File::create(&bundle_path)?,
+            Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        archive.append_link(
+            &mut header,
+            "components/notes.blobstore/files/key",
+            "/etc/passwd",
+        )?;
+        archive.into_inner()?.finish()?;
+
+        let manager = create_test_manager().await?;
+        let err = manager
+            .import_bundle(&bundle_path, false, None)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("not a regular file or directory"),
+            "{err}"
+        );
+
+        #[cfg(unix)]
+        {
+            let from = bundle_dir.path().join("from");
+            std::fs::create_dir_all(&from)?;
+            std::os::unix::fs::symlink("/etc/passwd", from.join("key"))?;
+            assert!(copy_dir(&from, &bundle_dir.path().join("to")).is_err());
+        }
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_export_bundle_unknown_component() -> Result<()> {
+        let manager = create_test_manager().await?;
+        let bundle_dir = tempfile::tempdir()?;
+
+        let err = manager
+            .export_bundle(
+                &["missing".to_string()],
+                &bundle_dir.path().join("b.tar.gz"),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Component not found"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_component_id() {
+        assert!(validate_component_id("fetch-rs").is_ok());
+        assert!(validate_component_id("").is_err());
+        assert!(validate_component_id("../evil").is_err());
+        assert!(validate_component_id("nested/evil").is_err());
+    }
+}
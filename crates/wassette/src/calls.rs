@@ -0,0 +1,246 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wassette:components/call`
+//!
+//! A component can call the tools of other loaded components, for example to hand what `fetch`
+//! returned to `html-to-markdown`, without a round trip through the MCP client. It can only call
+//! the components and tools listed under `permissions.components.allow` in its policy. Each call
+//! carries a delegation token with the permissions of the caller, see [`crate::delegation`], and
+//! the called component runs with the permissions that both the token and its own policy grant.
+//! A call neither lends the permissions of one component to the other nor lets a caller reach
+//! more through the component it calls.
+//!
+//! A chain of calls can't come back to a component that is already part of it, and is at most
+//! [`MAX_CALL_DEPTH`] components long.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use policy::PolicyDocument;
+use tracing::debug;
+
+use self::generated::wassette::components::call;
+use crate::delegation::DelegationScope;
+use crate::sampling::Sampler;
+use crate::LifecycleManager;
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/components",
+        world: "wassette:components/imports",
+        async: true,
+    });
+}
+
+/// Most components in a chain of calls, counting the one the MCP client called
+pub(crate) const MAX_CALL_DEPTH: usize = 8;
+
+/// The components a component may call, with the tools it may call or `None` for all of them
+pub(crate) type CallGrants = HashMap<String, Option<HashSet<String>>>;
+
+/// Extract the components and tools the policy allows the component to call
+pub(crate) fn extract_call_grants(policy: &PolicyDocument) -> CallGrants {
+    let mut grants = CallGrants::new();
+    for perm in policy
+        .permissions
+        .components
+        .iter()
+        .flat_map(|components| components.allow.iter().flatten())
+    {
+        let entry = grants
+            .entry(perm.id.clone())
+            .or_insert(Some(HashSet::new()));
+        match (&perm.tools, entry.as_mut()) {
+            (Some(tools), Some(granted)) => granted.extend(tools.iter().cloned()),
+            // A grant without tools allows every tool of the component
+            _ => *entry = None,
+        }
+    }
+    grants
+}
+
+/// The `wassette:components` state of a single component instance
+#[derive(Default)]
+pub(crate) struct CallCtx {
+    grants: CallGrants,
+    manager: Option<LifecycleManager>,
+    /// The components of the chain of calls that led to this instance, this one last
+    chain: Vec<String>,
+    /// The permissions of this instance, which it delegates to the components it calls
+    scope: DelegationScope,
+    /// The sampler of the outermost call, which the components it calls sample with too
+    sampler: Option<Arc<dyn Sampler>>,
+}
+
+impl CallCtx {
+    /// Creates a context that may call the given components once it is bound to a manager, and
+    /// delegates `scope` to them
+    pub(crate) fn new(grants: CallGrants, scope: DelegationScope) -> Self {
+        Self {
+            grants,
+            scope,
+            ..Default::default()
+        }
+    }
+
+    /// Binds the context to the manager that runs the calls, to the chain of callers of
+    /// `component_id` and to the sampler of the call
+    pub(crate) fn bind(
+        &mut self,
+        manager: LifecycleManager,
+        callers: &[String],
+        component_id: &str,
+        sampler: Option<Arc<dyn Sampler>>,
+    ) {
+        self.manager = Some(manager);
+        self.sampler = sampler;
+        self.chain = callers.to_vec();
+        self.chain.push(component_id.to_string());
+    }
+
+    /// Checks that the component of this instance may call `tool` of `component` and returns
+    /// the manager to call it with
+    fn check(&self, component: &str, tool: &str) -> Result<&LifecycleManager, String> {
+        let caller = self.chain.last().map(String::as_str).unwrap_or_default();
+        let allowed = match self.grants.get(component) {
+            Some(Some(tools)) => tools.contains(tool),
+            Some(None) => true,
+            None => false,
+        };
+        if !allowed {
+            debug!(
+                component_id = caller,
+                callee = component,
+                tool,
+                "Component call denied by policy"
+            );
+            return Err(format!(
+                "Component {caller} is not allowed to call tool {tool} of component {component}"
+            ));
+        }
+        if self.chain.iter().any(|id| id == component) {
+            return Err(format!(
+                "Calling component {component} again would create a cycle: {} -> {component}",
+                self.chain.join(" -> ")
+            ));
+        }
+        if self.chain.len() >= MAX_CALL_DEPTH {
+            return Err(format!(
+                "Component calls can't be nested more than {MAX_CALL_DEPTH} deep: {} -> {component}",
+                self.chain.join(" -> ")
+            ));
+        }
+        self.manager
+            .as_ref()
+            .ok_or_else(|| "Component calls are not available".to_string())
+    }
+}
+
+/// A view of a component's `wassette:components` state used by the host bindings
+pub(crate) struct CallHost<'a> {
+    ctx: &'a CallCtx,
+}
+
+impl<'a> CallHost<'a> {
+    pub(crate) fn new(ctx: &'a CallCtx) -> Self {
+        Self { ctx }
+    }
+}
+
+impl call::Host for CallHost<'_> {
+    async fn call_tool(
+        &mut self,
+        component: String,
+        tool: String,
+        arguments: String,
+    ) -> Result<String, String> {
+        let manager = self.ctx.check(&component, &tool)?;
+        match manager.get_component_id_for_tool(&tool).await {
+            Ok(id) if id == component => {}
+            _ => return Err(format!("Component {component} has no tool {tool}")),
+        }
+        let token = manager
+            .issue_delegation(&self.ctx.chain, &component, &tool, self.ctx.scope.clone())
+            .map_err(|e| format!("{e:#}"))?;
+        manager
+            .execute_nested_call(
+                &token,
+                self.ctx.sampler.as_ref(),
+                &component,
+                &tool,
+                &arguments,
+            )
+            .await
+            .map_err(|e| format!("{e:#}"))
+    }
+}
+
+/// Adds the `wassette:components` interfaces to the linker
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> CallHost<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    call::add_to_linker_get_host(linker, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use policy::PolicyParser;
+
+    use super::*;
+
+    #[test]
+    fn test_extract_call_grants() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+permissions:
+  components:
+    allow:
+      - id: "html-to-markdown"
+        tools: ["convert"]
+      - id: "fetch"
+      - id: "fetch"
+        tools: ["fetch"]
+"#,
+        )
+        .unwrap();
+        let grants = extract_call_grants(&policy);
+        assert_eq!(
+            grants["html-to-markdown"],
+            Some(HashSet::from(["convert".to_string()]))
+        );
+        assert_eq!(grants["fetch"], None);
+    }
+
+    fn check_err(ctx: &CallCtx, component: &str, tool: &str) -> String {
+        ctx.check(component, tool).map(|_| ()).unwrap_err()
+    }
+
+    #[test]
+    fn test_check() {
+        let grants = CallGrants::from([
+            (
+                "html-to-markdown".to_string(),
+                Some(HashSet::from(["convert".to_string()])),
+            ),
+            ("fetch".to_string(), None),
+        ]);
+        let mut ctx = CallCtx::new(grants, DelegationScope::default());
+        ctx.chain = vec!["fetch".to_string(), "pipeline".to_string()];
+
+        let err = check_err(&ctx, "html-to-markdown", "convert");
+        assert!(err.contains("not available"), "{err}");
+        let err = check_err(&ctx, "html-to-markdown", "summarize");
+        assert!(err.contains("not allowed"), "{err}");
+        let err = check_err(&ctx, "filesystem", "read-file");
+        assert!(err.contains("not allowed"), "{err}");
+        let err = check_err(&ctx, "fetch", "fetch");
+        assert!(err.contains("fetch -> pipeline -> fetch"), "{err}");
+
+        ctx.chain = (0..MAX_CALL_DEPTH).map(|i| format!("step-{i}")).collect();
+        let err = check_err(&ctx, "html-to-markdown", "convert");
+        assert!(err.contains("nested"), "{err}");
+    }
+}
@@ -0,0 +1,621 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Release channel subscriptions of manifest components
+//!
+//! A manifest component with a `channel` follows a moving tag of its `oci://` repository, such as
+//! `stable` or `nightly`, or the newest version that matches a semver range such as `^1.2`:
+//!
+//! ```yaml
+//! components:
+//!   - id: fetch_rs
+//!     source: oci://ghcr.io/microsoft/fetch-rs
+//!     channel: ^1.2
+//!     public_key: file://./cosign.pub
+//! ```
+//!
+//! The version a component runs is pinned by digest in the [`LOCKFILE`] of the plugin directory,
+//! so the component doesn't change when the channel moves. The first reconcile resolves the
+//! channel and pins what it finds. [`crate::LifecycleManager::check_updates`] resolves every
+//! channel again and stages each newer version for approval, after checking its cosign signature
+//! when the component has a `public_key`. [`crate::LifecycleManager::approve_update`] pins the
+//! staged version, which the next reconcile loads.
+//!
+//! A semver range can also take the place of the tag of a reference, as in
+//! `oci://ghcr.io/microsoft/fetch-rs:^1.2`. Loading such a reference loads the highest matching
+//! version and pins its digest in the lockfile; in a manifest it is the same as a `channel`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use oci_client::secrets::RegistryAuth;
+use oci_client::Reference;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::bundle::sha256_hex;
+use crate::manifest::{DesiredComponent, DesiredStateManifest, ReconcileFailure};
+use crate::signature::SignatureVerifier;
+use crate::LoadResult;
+
+/// File of the plugin directory that pins the versions of subscribed components
+pub const LOCKFILE: &str = "wassette.lock";
+
+const LOCKFILE_VERSION: &str = "1.0";
+
+/// Most pages of tags fetched from a registry when resolving a range
+const MAX_TAG_PAGES: usize = 100;
+
+/// Most versions listed in the error for a range that matches none
+const MAX_LISTED_VERSIONS: usize = 10;
+
+/// What a component follows: a tag that moves between versions, or a semver range
+#[derive(Debug, Clone, PartialEq)]
+pub enum Channel {
+    /// A tag such as `stable` or `nightly`
+    Tag(String),
+    /// The highest version tag that matches the range, e.g. `^1.2`. Tags may start with `v`.
+    Range(VersionReq),
+}
+
+impl FromStr for Channel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(range) = VersionReq::parse(s) {
+            return Ok(Channel::Range(range));
+        }
+        if !is_valid_tag(s) {
+            bail!("Channel must be a tag such as stable or a semver range such as ^1.2: {s}");
+        }
+        Ok(Channel::Tag(s.to_string()))
+    }
+}
+
+/// Whether `s` is a valid OCI tag
+fn is_valid_tag(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 128
+        && s.chars().enumerate().all(|(i, c)| {
+            c.is_ascii_alphanumeric() || c == '_' || (i > 0 && (c == '.' || c == '-'))
+        })
+}
+
+/// Splits an `oci://` URI whose tag is a semver range, such as
+/// `oci://ghcr.io/microsoft/fetch-rs:^1.2`, into the untagged reference and the range. A literal
+/// tag such as `1.2.0` is not a range, even though it would parse as one.
+pub(crate) fn split_constraint(uri: &str) -> Option<(&str, VersionReq)> {
+    let reference = uri.strip_prefix("oci://")?;
+    let name_end = reference.rfind('/').unwrap_or(0);
+    let (repository, constraint) = reference.rsplit_once(':')?;
+    if repository.len() < name_end || is_valid_tag(constraint) {
+        return None;
+    }
+    let range = VersionReq::parse(constraint).ok()?;
+    Some((repository, range))
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Tag(tag) => f.write_str(tag),
+            Channel::Range(range) => write!(f, "{range}"),
+        }
+    }
+}
+
+/// Parses a tag as a version, allowing a `v` prefix
+fn tag_version(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// The tag with the highest version that matches `range`
+pub(crate) fn newest_matching<'a>(tags: &'a [String], range: &VersionReq) -> Option<&'a str> {
+    tags.iter()
+        .filter_map(|tag| tag_version(tag).map(|version| (version, tag)))
+        .filter(|(version, _)| range.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag.as_str())
+}
+
+/// The error for a range that no tag of `repository` matches, listing the highest versions there
+/// are so the range can be fixed
+fn no_match_error(repository: &str, range: &VersionReq, tags: &[String]) -> anyhow::Error {
+    let mut versions: Vec<Version> = tags.iter().filter_map(|tag| tag_version(tag)).collect();
+    if versions.is_empty() {
+        return anyhow::anyhow!(
+            "No version of {repository} matches {range}: none of its {} tags is a semver version",
+            tags.len()
+        );
+    }
+    versions.sort();
+    versions.dedup();
+    let highest: Vec<String> = versions
+        .iter()
+        .rev()
+        .take(MAX_LISTED_VERSIONS)
+        .map(Version::to_string)
+        .collect();
+    let more = versions.len().saturating_sub(MAX_LISTED_VERSIONS);
+    anyhow::anyhow!(
+        "No version of {repository} matches {range}. Available versions: {}{}",
+        highest.join(", "),
+        if more > 0 {
+            format!(" and {more} older")
+        } else {
+            String::new()
+        }
+    )
+}
+
+/// A version of a component in its registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolvedVersion {
+    /// Tagged reference of the version, e.g. `ghcr.io/microsoft/fetch-rs:1.4.0`
+    pub reference: String,
+    /// Digest of the OCI manifest of the version
+    pub digest: String,
+    /// Whether the signature of the version was verified with the component's public key
+    pub verified: bool,
+    /// When the version was resolved, in RFC 3339 format
+    pub resolved_at: String,
+}
+
+impl ResolvedVersion {
+    /// The `oci://` source of exactly this version
+    pub fn source(&self) -> Result<String> {
+        let reference: Reference = self
+            .reference
+            .parse()
+            .with_context(|| format!("Invalid reference in lockfile: {}", self.reference))?;
+        Ok(format!(
+            "oci://{}",
+            reference.clone_with_digest(self.digest.clone()).whole()
+        ))
+    }
+}
+
+/// The pinned version of a component that follows a channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedComponent {
+    /// The channel the version was resolved from
+    pub channel: String,
+    /// The version the component runs
+    pub pinned: ResolvedVersion,
+    /// SHA-256 digest of the wasm of the pinned version, as `sha256:<hex>`, once it is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm_digest: Option<String>,
+    /// A newer version of the channel waiting for approval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staged: Option<ResolvedVersion>,
+}
+
+/// The contents of the [`LOCKFILE`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// Lockfile format version
+    pub version: String,
+    /// Pinned versions by component ID
+    #[serde(default)]
+    pub components: BTreeMap<String, LockedComponent>,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self {
+            version: LOCKFILE_VERSION.to_string(),
+            components: BTreeMap::new(),
+        }
+    }
+}
+
+impl Lockfile {
+    /// The pin of a manifest component, unless it was resolved from another channel
+    pub(crate) fn locked(&self, desired: &DesiredComponent) -> Option<&LockedComponent> {
+        self.components
+            .get(&desired.id)
+            .filter(|locked| Some(&locked.channel) == desired.channel.as_ref())
+    }
+}
+
+/// A newer version that was staged for approval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AvailableUpdate {
+    /// ID of the component
+    pub component_id: String,
+    /// Reference of the pinned version
+    pub current: String,
+    /// The staged version
+    pub update: ResolvedVersion,
+}
+
+/// The outcome of a [`crate::LifecycleManager::check_updates`] pass
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UpdateReport {
+    /// Components with a newer version staged for approval
+    pub staged: Vec<AvailableUpdate>,
+    /// Components that run the newest version of their channel
+    pub up_to_date: Vec<String>,
+    /// Components whose channel was never resolved; reconciling the manifest installs them
+    pub not_installed: Vec<String>,
+    /// Components whose channel could not be resolved or whose update failed verification
+    pub failed: Vec<ReconcileFailure>,
+}
+
+/// The OCI repository of a manifest component, from its `oci://` source
+fn source_reference(desired: &DesiredComponent) -> Result<Reference> {
+    let Some(reference) = desired.source.strip_prefix("oci://") else {
+        bail!(
+            "Component {} follows a channel but its source is not an oci:// reference",
+            desired.id
+        );
+    };
+    reference
+        .parse()
+        .with_context(|| format!("Invalid OCI reference for component {}", desired.id))
+}
+
+impl crate::LifecycleManager {
+    fn lockfile_path(&self) -> std::path::PathBuf {
+        self.plugin_dir.join(LOCKFILE)
+    }
+
+    /// Returns the pinned versions of the components that follow a channel
+    pub async fn read_lockfile(&self) -> Result<Lockfile> {
+        match tokio::fs::read(self.lockfile_path()).await {
+            Ok(content) => serde_json::from_slice(&content).context("Invalid lockfile"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Lockfile::default()),
+            Err(e) => Err(e).context("Failed to read lockfile"),
+        }
+    }
+
+    pub(crate) async fn write_lockfile(&self, lockfile: &Lockfile) -> Result<()> {
+        let path = self.lockfile_path();
+        let temp_path = path.with_extension("lock.tmp");
+        tokio::fs::write(&temp_path, serde_json::to_vec_pretty(lockfile)?).await?;
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .context("Failed to write lockfile")
+    }
+
+    /// Resolves the channel of a manifest component to the version it currently points at, and
+    /// checks the signature of that version when the component must be signed
+    pub(crate) async fn resolve_channel(
+        &self,
+        desired: &DesiredComponent,
+    ) -> Result<ResolvedVersion> {
+        let channel: Channel = desired
+            .channel
+            .as_deref()
+            .context("Component doesn't follow a channel")?
+            .parse()?;
+        let repository = source_reference(desired)?;
+        let verifier = desired.verifier().await?;
+        self.resolve_version(&repository, &channel, verifier.as_ref())
+            .await
+    }
+
+    /// Lists every tag of a repository, following the registry's pagination
+    async fn list_all_tags(&self, repository: &Reference) -> Result<Vec<String>> {
+        let mut tags: Vec<String> = Vec::new();
+        for _ in 0..MAX_TAG_PAGES {
+            let page = self
+                .oci_client
+                .list_tags(
+                    repository,
+                    &RegistryAuth::Anonymous,
+                    None,
+                    tags.last().map(String::as_str),
+                )
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to list the tags of {}/{}",
+                        repository.registry(),
+                        repository.repository()
+                    )
+                })?
+                .tags;
+            // Registries that don't paginate return every tag again, or nothing, for the next page
+            if page.is_empty() || page.last() == tags.last() {
+                break;
+            }
+            tags.extend(page);
+        }
+        tags.dedup();
+        Ok(tags)
+    }
+
+    /// Resolves a channel of `repository` to the version it currently points at, and checks the
+    /// signature of that version when a verifier is given
+    async fn resolve_version(
+        &self,
+        repository: &Reference,
+        channel: &Channel,
+        verifier: Option<&SignatureVerifier>,
+    ) -> Result<ResolvedVersion> {
+        let auth = RegistryAuth::Anonymous;
+        let tag = match channel {
+            Channel::Tag(tag) => tag.clone(),
+            Channel::Range(range) => {
+                let tags = self.list_all_tags(repository).await?;
+                match newest_matching(&tags, range) {
+                    Some(tag) => tag.to_string(),
+                    None => {
+                        let name = format!("{}/{}", repository.registry(), repository.repository());
+                        return Err(no_match_error(&name, range, &tags));
+                    }
+                }
+            }
+        };
+        let reference = Reference::with_tag(
+            repository.registry().to_string(),
+            repository.repository().to_string(),
+            tag,
+        );
+        let digest = self
+            .oci_client
+            .fetch_manifest_digest(&reference, &auth)
+            .await
+            .with_context(|| format!("Failed to resolve {}", reference.whole()))?;
+
+        let verified = match verifier {
+            Some(verifier) => {
+                self.verify_artifact(verifier, &reference, &digest).await?;
+                true
+            }
+            None => false,
+        };
+        Ok(ResolvedVersion {
+            reference: reference.whole(),
+            digest,
+            verified,
+            resolved_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        })
+    }
+
+    /// Loads the highest version of `repository` that matches `range` and pins its digest in the
+    /// lockfile
+    pub(crate) async fn load_constrained_component(
+        &self,
+        repository: &str,
+        range: VersionReq,
+    ) -> Result<(String, LoadResult)> {
+        let reference: Reference = repository
+            .parse()
+            .with_context(|| format!("Invalid OCI reference {repository}"))?;
+        let channel = Channel::Range(range);
+        let resolved = self.resolve_version(&reference, &channel, None).await?;
+        info!(
+            repository,
+            range = %channel,
+            reference = resolved.reference,
+            "Resolved version range"
+        );
+
+        let downloaded = self.download_component(&resolved.source()?).await?;
+        let wasm = tokio::fs::read(downloaded.as_ref()).await?;
+        let wasm_digest = format!("sha256:{}", sha256_hex(&wasm));
+        let (id, result) = self.load_downloaded_component(downloaded).await?;
+
+        let mut lockfile = self.read_lockfile().await?;
+        lockfile.components.insert(
+            id.clone(),
+            LockedComponent {
+                channel: channel.to_string(),
+                pinned: resolved,
+                wasm_digest: Some(wasm_digest),
+                staged: None,
+            },
+        );
+        self.write_lockfile(&lockfile).await?;
+        Ok((id, result))
+    }
+
+    /// Resolves the channel of every subscribed component of `manifest` and stages the versions
+    /// that are newer than the pinned ones. Versions whose signature doesn't verify are reported
+    /// as failed and not staged. Nothing is loaded.
+    #[instrument(skip_all)]
+    pub async fn check_updates(&self, manifest: &DesiredStateManifest) -> Result<UpdateReport> {
+        let mut lockfile = self.read_lockfile().await?;
+        let mut report = UpdateReport::default();
+
+        for desired in manifest.components.iter().filter(|c| c.channel.is_some()) {
+            let component_id = desired.id.clone();
+            if lockfile.locked(desired).is_none() {
+                report.not_installed.push(component_id);
+                continue;
+            }
+            let resolved = match self.resolve_channel(desired).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    warn!(component_id, error = %e, "Failed to check for updates");
+                    report.failed.push(ReconcileFailure {
+                        component_id,
+                        error: format!("{e:#}"),
+                    });
+                    continue;
+                }
+            };
+
+            let Some(locked) = lockfile.components.get_mut(&component_id) else {
+                continue;
+            };
+            if locked.pinned.digest == resolved.digest {
+                locked.staged = None;
+                report.up_to_date.push(component_id);
+                continue;
+            }
+            report.staged.push(AvailableUpdate {
+                component_id,
+                current: locked.pinned.reference.clone(),
+                update: resolved.clone(),
+            });
+            locked.staged = Some(resolved);
+        }
+
+        self.write_lockfile(&lockfile).await?;
+        info!(
+            staged = report.staged.len(),
+            up_to_date = report.up_to_date.len(),
+            failed = report.failed.len(),
+            "Checked subscribed components for updates"
+        );
+        Ok(report)
+    }
+
+    /// Pins the staged update of a component. The new version is loaded by the next reconcile.
+    pub async fn approve_update(&self, component_id: &str) -> Result<ResolvedVersion> {
+        let mut lockfile = self.read_lockfile().await?;
+        let locked = lockfile
+            .components
+            .get_mut(component_id)
+            .with_context(|| format!("Component {component_id} doesn't follow a channel"))?;
+        let staged = locked
+            .staged
+            .take()
+            .with_context(|| format!("No update is staged for component {component_id}"))?;
+        locked.pinned = staged.clone();
+        locked.wasm_digest = None;
+        self.write_lockfile(&lockfile).await?;
+        info!(
+            component_id,
+            reference = staged.reference,
+            "Approved component update"
+        );
+        Ok(staged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_manager;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(
+            "stable".parse::<Channel>().unwrap(),
+            Channel::Tag("stable".to_string())
+        );
+        assert_eq!(
+            "^1.2".parse::<Channel>().unwrap(),
+            Channel::Range(VersionReq::parse("^1.2").unwrap())
+        );
+        assert!(matches!(
+            ">=1.0, <2".parse::<Channel>().unwrap(),
+            Channel::Range(_)
+        ));
+        assert!("".parse::<Channel>().is_err());
+        assert!("-nightly".parse::<Channel>().is_err());
+        assert!("stable/2".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn test_newest_matching() {
+        let tags = tags(&[
+            "latest",
+            "v1.2.0",
+            "1.3.1",
+            "1.10.0",
+            "2.0.0",
+            "1.11.0-rc.1",
+            "sha256-abc.sig",
+        ]);
+        let range = |range: &str| VersionReq::parse(range).unwrap();
+        assert_eq!(newest_matching(&tags, &range("^1.2")), Some("1.10.0"));
+        assert_eq!(newest_matching(&tags, &range("~1.2")), Some("v1.2.0"));
+        assert_eq!(newest_matching(&tags, &range("*")), Some("2.0.0"));
+        assert_eq!(newest_matching(&tags, &range("^3")), None);
+    }
+
+    #[test]
+    fn test_split_constraint() {
+        let (repository, range) =
+            split_constraint("oci://ghcr.io/microsoft/fetch-rs:^1.2").unwrap();
+        assert_eq!(repository, "ghcr.io/microsoft/fetch-rs");
+        assert_eq!(range, VersionReq::parse("^1.2").unwrap());
+        let (repository, _) = split_constraint("oci://localhost:5000/fetch:~2.0").unwrap();
+        assert_eq!(repository, "localhost:5000/fetch");
+
+        assert!(split_constraint("oci://ghcr.io/microsoft/fetch-rs:1.2.0").is_none());
+        assert!(split_constraint("oci://ghcr.io/microsoft/fetch-rs:latest").is_none());
+        assert!(split_constraint("oci://ghcr.io/microsoft/fetch-rs").is_none());
+        assert!(split_constraint("oci://localhost:5000/fetch").is_none());
+        assert!(split_constraint(&format!(
+            "oci://ghcr.io/microsoft/fetch-rs@sha256:{}",
+            "a".repeat(64)
+        ))
+        .is_none());
+        assert!(split_constraint("file:///tmp/fetch:^1.wasm").is_none());
+    }
+
+    #[test]
+    fn test_no_match_error() {
+        let range = VersionReq::parse("^3").unwrap();
+        let versions: Vec<String> = (0..12).map(|minor| format!("1.{minor}.0")).collect();
+        let err = no_match_error("ghcr.io/microsoft/fetch-rs", &range, &versions).to_string();
+        assert_eq!(
+            err,
+            "No version of ghcr.io/microsoft/fetch-rs matches ^3. Available versions: 1.11.0, \
+             1.10.0, 1.9.0, 1.8.0, 1.7.0, 1.6.0, 1.5.0, 1.4.0, 1.3.0, 1.2.0 and 2 older"
+        );
+        let err = no_match_error("ghcr.io/microsoft/fetch-rs", &range, &tags(&["latest"]));
+        assert!(err.to_string().contains("none of its 1 tags"), "{err}");
+    }
+
+    #[test]
+    fn test_resolved_version_source() {
+        let version = ResolvedVersion {
+            reference: "ghcr.io/microsoft/fetch-rs:1.4.0".to_string(),
+            digest: format!("sha256:{}", "a".repeat(64)),
+            verified: true,
+            resolved_at: "2025-01-01T00:00:00.000Z".to_string(),
+        };
+        assert_eq!(
+            version.source().unwrap(),
+            format!("oci://ghcr.io/microsoft/fetch-rs@sha256:{}", "a".repeat(64))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_approve_update() -> Result<()> {
+        let manager = create_test_manager().await?;
+        assert_eq!(manager.read_lockfile().await?, Lockfile::default());
+        assert!(manager.approve_update("fetch_rs").await.is_err());
+
+        let version = |tag: &str, digest: char| ResolvedVersion {
+            reference: format!("ghcr.io/microsoft/fetch-rs:{tag}"),
+            digest: format!("sha256:{}", digest.to_string().repeat(64)),
+            verified: false,
+            resolved_at: "2025-01-01T00:00:00.000Z".to_string(),
+        };
+        let mut lockfile = Lockfile::default();
+        lockfile.components.insert(
+            "fetch_rs".to_string(),
+            LockedComponent {
+                channel: "^1".to_string(),
+                pinned: version("1.0.0", 'a'),
+                wasm_digest: Some(format!("sha256:{}", "c".repeat(64))),
+                staged: Some(version("1.1.0", 'b')),
+            },
+        );
+        manager.write_lockfile(&lockfile).await?;
+
+        let approved = manager.approve_update("fetch_rs").await?;
+        assert_eq!(approved, version("1.1.0", 'b'));
+        let locked = &manager.read_lockfile().await?.components["fetch_rs"];
+        assert_eq!(locked.pinned, version("1.1.0", 'b'));
+        assert_eq!(locked.wasm_digest, None);
+        assert_eq!(locked.staged, None);
+        assert!(manager.approve_update("fetch_rs").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Indexes of components to discover
+//!
+//! An index lists components that can be loaded, with a description and keywords for each, so
+//! that agents can find a component for a task and propose to load it. An index is either
+//!
+//! - a JSON document at an `http(s)://` URL, a `file://` URL or a path, holding an array of
+//!   `{"name", "ref", "description", "keywords", "version"}` entries or an object with such an
+//!   array as `components`, or
+//! - the referrers of an OCI artifact, given as `oci://<registry>/<repository>:<tag>`. Each
+//!   referrer is a component, described by its `org.opencontainers.image.title`,
+//!   `org.opencontainers.image.description` and `org.opencontainers.image.version` annotations
+//!   and [`KEYWORDS_ANNOTATION`]. Its reference is [`REF_ANNOTATION`] if it has one, and its
+//!   digest in the repository of the index otherwise.
+//!
+//! Indexes are fetched on the first search and again once they are five minutes old.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use oci_client::secrets::RegistryAuth;
+use oci_client::Reference;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Annotation of an OCI referrer with the comma separated keywords of its component
+pub const KEYWORDS_ANNOTATION: &str = "dev.wassette.keywords";
+
+/// Annotation of an OCI referrer with the reference to load its component from
+pub const REF_ANNOTATION: &str = "dev.wassette.ref";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+const DESCRIPTION_ANNOTATION: &str = "org.opencontainers.image.description";
+const VERSION_ANNOTATION: &str = "org.opencontainers.image.version";
+
+/// How long a fetched index is searched before it is fetched again
+const INDEX_TTL: Duration = Duration::from_secs(300);
+
+/// Where an index of components is published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentIndex {
+    /// A JSON document at an `http(s)://` URL
+    Url(String),
+    /// A JSON document in a local file
+    File(PathBuf),
+    /// The referrers of an OCI artifact
+    Oci(Reference),
+}
+
+impl fmt::Display for ComponentIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComponentIndex::Url(url) => f.write_str(url),
+            ComponentIndex::File(path) => write!(f, "file://{}", path.display()),
+            ComponentIndex::Oci(reference) => write!(f, "oci://{}", reference.whole()),
+        }
+    }
+}
+
+impl FromStr for ComponentIndex {
+    type Err = anyhow::Error;
+
+    /// Parses an `http(s)://`, `file://` or `oci://` URL, or a path
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(ComponentIndex::Url(s.to_string()))
+        } else if let Some(reference) = s.strip_prefix("oci://") {
+            Ok(ComponentIndex::Oci(reference.parse().with_context(
+                || format!("Invalid OCI reference of component index: {s}"),
+            )?))
+        } else if s.is_empty() || s.contains("://") && !s.starts_with("file://") {
+            bail!("Component index must be an http(s)://, file:// or oci:// URL or a path: {s}")
+        } else {
+            Ok(ComponentIndex::File(PathBuf::from(
+                s.strip_prefix("file://").unwrap_or(s),
+            )))
+        }
+    }
+}
+
+/// A component listed in an index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexedComponent {
+    /// Name of the component
+    pub name: String,
+    /// Reference to load the component from, such as `oci://ghcr.io/org/fetch:1.0`
+    #[serde(rename = "ref")]
+    pub reference: String,
+    /// What the component does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Words the component is found by besides its name and description
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Version of the component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The index the component is listed in
+    #[serde(default, skip_deserializing)]
+    pub index: String,
+}
+
+impl IndexedComponent {
+    /// How well the component matches the lowercase `terms`: the number of terms it matches,
+    /// then how many points the matches are worth. A term in the name is worth more than one
+    /// in a keyword, which is worth more than one in the description or reference.
+    fn score(&self, terms: &[String]) -> (usize, usize) {
+        let name = self.name.to_lowercase();
+        let description = self.description.as_deref().unwrap_or("").to_lowercase();
+        let reference = self.reference.to_lowercase();
+        let keywords = self
+            .keywords
+            .iter()
+            .map(|keyword| keyword.to_lowercase())
+            .collect::<Vec<_>>();
+        terms.iter().fold((0, 0), |(matched, points), term| {
+            let term_points = [
+                (name.contains(term.as_str()), 5),
+                (keywords.iter().any(|keyword| keyword == term), 3),
+                (
+                    keywords
+                        .iter()
+                        .any(|keyword| keyword.contains(term.as_str())),
+                    1,
+                ),
+                (description.contains(term.as_str()), 2),
+                (reference.contains(term.as_str()), 1),
+            ]
+            .into_iter()
+            .filter_map(|(hit, points)| hit.then_some(points))
+            .sum::<usize>();
+            (matched + usize::from(term_points > 0), points + term_points)
+        })
+    }
+}
+
+/// The entries of a JSON index
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonIndex {
+    List(Vec<IndexedComponent>),
+    Object { components: Vec<IndexedComponent> },
+}
+
+/// The result of a search of the component indexes
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ComponentSearch {
+    /// The best matching components, best first
+    pub results: Vec<IndexedComponent>,
+    /// How many components match, including the ones beyond the limit
+    pub total: usize,
+    /// Errors of the indexes that couldn't be fetched, by index
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub errors: HashMap<String, String>,
+}
+
+/// The components of an index and when they were fetched
+type FetchedIndex = (Instant, Arc<Vec<IndexedComponent>>);
+
+/// The configured indexes and what was last fetched from each of them
+#[derive(Default)]
+pub(crate) struct ComponentIndexes {
+    indexes: Vec<ComponentIndex>,
+    fetched: Mutex<HashMap<String, FetchedIndex>>,
+}
+
+impl ComponentIndexes {
+    async fn entries(
+        &self,
+        index: &ComponentIndex,
+        oci_client: &oci_client::Client,
+        http_client: &reqwest::Client,
+    ) -> Result<Arc<Vec<IndexedComponent>>> {
+        let key = index.to_string();
+        if let Some((fetched_at, entries)) = self.fetched.lock().await.get(&key) {
+            if fetched_at.elapsed() < INDEX_TTL {
+                return Ok(entries.clone());
+            }
+        }
+        debug!(index = %key, "Fetching component index");
+        let mut entries = match index {
+            ComponentIndex::Url(url) => {
+                let body = http_client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .with_context(|| format!("Failed to fetch component index {url}"))?
+                    .bytes()
+                    .await?;
+                parse_json_index(&body)?
+            }
+            ComponentIndex::File(path) => {
+                parse_json_index(&tokio::fs::read(path).await.with_context(|| {
+                    format!("Failed to read component index {}", path.display())
+                })?)?
+            }
+            ComponentIndex::Oci(reference) => oci_index(reference, oci_client).await?,
+        };
+        for entry in &mut entries {
+            entry.index.clone_from(&key);
+        }
+        let entries = Arc::new(entries);
+        self.fetched
+            .lock()
+            .await
+            .insert(key, (Instant::now(), entries.clone()));
+        Ok(entries)
+    }
+}
+
+fn parse_json_index(body: &[u8]) -> Result<Vec<IndexedComponent>> {
+    let index: JsonIndex = serde_json::from_slice(body).context("Invalid component index")?;
+    Ok(match index {
+        JsonIndex::List(components) | JsonIndex::Object { components } => components,
+    })
+}
+
+/// The components that refer to the OCI artifact `reference`
+async fn oci_index(
+    reference: &Reference,
+    oci_client: &oci_client::Client,
+) -> Result<Vec<IndexedComponent>> {
+    oci_client
+        .store_auth_if_needed(reference.resolve_registry(), &RegistryAuth::Anonymous)
+        .await;
+    let referrers = oci_client
+        .pull_referrers(reference, None)
+        .await
+        .with_context(|| format!("Failed to fetch the referrers of {}", reference.whole()))?;
+    Ok(referrers
+        .manifests
+        .into_iter()
+        .map(|manifest| {
+            let annotations = manifest.annotations.unwrap_or_default();
+            let annotation = |key: &str| annotations.get(key).cloned();
+            IndexedComponent {
+                name: annotation(TITLE_ANNOTATION).unwrap_or_else(|| manifest.digest.clone()),
+                reference: annotation(REF_ANNOTATION).unwrap_or_else(|| {
+                    format!(
+                        "oci://{}/{}@{}",
+                        reference.registry(),
+                        reference.repository(),
+                        manifest.digest
+                    )
+                }),
+                description: annotation(DESCRIPTION_ANNOTATION),
+                keywords: annotation(KEYWORDS_ANNOTATION)
+                    .map(|keywords| {
+                        keywords
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|keyword| !keyword.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                version: annotation(VERSION_ANNOTATION),
+                index: String::new(),
+            }
+        })
+        .collect())
+}
+
+impl crate::LifecycleManager {
+    /// Searches `indexes` with [`crate::LifecycleManager::search_components`]
+    pub fn with_component_indexes(mut self, indexes: Vec<ComponentIndex>) -> Self {
+        self.component_indexes = Arc::new(ComponentIndexes {
+            indexes,
+            fetched: Mutex::default(),
+        });
+        self
+    }
+
+    /// Returns the components of the configured indexes that match the words of `query`, at
+    /// most `limit` of them, the ones matching the most words first. An empty query matches
+    /// every component. Indexes that can't be fetched are reported in
+    /// [`ComponentSearch::errors`]; the search only fails if none of them can.
+    pub async fn search_components(&self, query: &str, limit: usize) -> Result<ComponentSearch> {
+        let indexes = &self.component_indexes;
+        if indexes.indexes.is_empty() {
+            bail!("No component index is configured, start the server with --component-index");
+        }
+        let terms = query
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        let mut search = ComponentSearch::default();
+        let mut matches = Vec::new();
+        for index in &indexes.indexes {
+            match indexes
+                .entries(index, &self.oci_client, &self.http_client)
+                .await
+            {
+                Ok(entries) => matches.extend(entries.iter().filter_map(|entry| {
+                    let score = entry.score(&terms);
+                    (terms.is_empty() || score.0 > 0).then(|| (score, entry.clone()))
+                })),
+                Err(e) => {
+                    warn!(%index, error = %e, "Failed to fetch component index");
+                    search.errors.insert(index.to_string(), format!("{e:#}"));
+                }
+            }
+        }
+        if search.errors.len() == indexes.indexes.len() {
+            bail!(
+                "No component index could be fetched: {}",
+                search
+                    .errors
+                    .values()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
+        matches.sort_by(|(a, a_entry), (b, b_entry)| {
+            b.cmp(a).then_with(|| a_entry.name.cmp(&b_entry.name))
+        });
+        search.total = matches.len();
+        search.results = matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, entry)| entry)
+            .collect();
+        Ok(search)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::LifecycleManager;
+
+    #[test]
+    fn test_parse_component_index() {
+        assert_eq!(
+            "https://example.com/index.json"
+                .parse::<ComponentIndex>()
+                .unwrap(),
+            ComponentIndex::Url("https://example.com/index.json".to_string())
+        );
+        assert_eq!(
+            "file:///srv/index.json".parse::<ComponentIndex>().unwrap(),
+            ComponentIndex::File(PathBuf::from("/srv/index.json"))
+        );
+        assert_eq!(
+            "index.json".parse::<ComponentIndex>().unwrap(),
+            ComponentIndex::File(PathBuf::from("index.json"))
+        );
+        let index = "oci://ghcr.io/example/catalog:latest"
+            .parse::<ComponentIndex>()
+            .unwrap();
+        assert_eq!(index.to_string(), "oci://ghcr.io/example/catalog:latest");
+        assert!("ftp://example.com/index.json"
+            .parse::<ComponentIndex>()
+            .is_err());
+        assert!("".parse::<ComponentIndex>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_components() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let index_path = tempdir.path().join("index.json");
+        std::fs::write(
+            &index_path,
+            json!({"components": [
+                {
+                    "name": "weather",
+                    "ref": "oci://ghcr.io/example/weather:1.0",
+                    "description": "Current weather and forecasts for a city",
+                    "keywords": ["forecast", "climate"]
+                },
+                {
+                    "name": "fetch",
+                    "ref": "oci://ghcr.io/example/fetch:2.1",
+                    "description": "Fetches a web page and returns it as markdown"
+                },
+                {
+                    "name": "time",
+                    "ref": "oci://ghcr.io/example/time:1.0",
+                    "description": "Current time in a time zone, for weather reports too"
+                }
+            ]})
+            .to_string(),
+        )?;
+
+        let manager = LifecycleManager::new(tempdir.path().join("components")).await?;
+        assert!(manager.search_components("weather", 10).await.is_err());
+
+        let index = index_path.to_str().unwrap().parse::<ComponentIndex>()?;
+        let manager = manager.with_component_indexes(vec![
+            index.clone(),
+            "file:///nonexistent/index.json".parse()?,
+        ]);
+        let search = manager.search_components("Weather forecast", 10).await?;
+        assert_eq!(search.total, 2);
+        assert_eq!(search.results[0].name, "weather");
+        assert_eq!(search.results[0].index, index.to_string());
+        assert_eq!(search.results[1].name, "time");
+        assert!(search.errors.contains_key("file:///nonexistent/index.json"));
+
+        let search = manager.search_components("", 2).await?;
+        assert_eq!(search.total, 3);
+        assert_eq!(search.results.len(), 2);
+        assert!(manager
+            .search_components("database", 10)
+            .await?
+            .results
+            .is_empty());
+
+        let manager = manager.with_component_indexes(vec!["/nonexistent.json".parse()?]);
+        assert!(manager.search_components("weather", 10).await.is_err());
+        Ok(())
+    }
+}
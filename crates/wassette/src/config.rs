@@ -0,0 +1,240 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Live updates of `wasi:config` variables
+//!
+//! A component's `wasi:config` store normally holds the environment variables its policy
+//! allows. [`crate::LifecycleManager::set_component_config`] layers values on top of that, so
+//! the next call sees them without editing the policy or reloading the component. The values
+//! are kept in memory until the component is unloaded or the server restarts, and aren't meant
+//! for secrets.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use tracing::{info, instrument};
+
+use crate::{ComponentNotFound, Event};
+
+impl crate::LifecycleManager {
+    /// Sets the `wasi:config` variable `key` of a loaded component, or removes the value set
+    /// earlier when `value` is `None`. The next call of the component sees the change.
+    #[instrument(skip(self, value))]
+    pub async fn set_component_config(
+        &self,
+        component_id: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<()> {
+        if !self.components.read().await.contains_key(component_id) {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        self.ensure_modifiable(component_id).await?;
+        if key.is_empty() {
+            bail!("Config key cannot be empty");
+        }
+
+        {
+            let mut registry = self.policy_registry.write().await;
+            match value {
+                Some(value) => {
+                    registry
+                        .config_overrides
+                        .entry(component_id.to_string())
+                        .or_default()
+                        .insert(key.to_string(), value.to_string());
+                }
+                None => {
+                    if let Some(values) = registry.config_overrides.get_mut(component_id) {
+                        values.remove(key);
+                        if values.is_empty() {
+                            registry.config_overrides.remove(component_id);
+                        }
+                    }
+                }
+            }
+        }
+
+        info!(
+            component_id,
+            key,
+            removed = value.is_none(),
+            "Config variable updated"
+        );
+        self.events.emit(Event::ConfigChanged {
+            component_id: component_id.to_string(),
+            key: key.to_string(),
+            removed: value.is_none(),
+        });
+        Ok(())
+    }
+
+    /// Returns the `wasi:config` variables set with
+    /// [`crate::LifecycleManager::set_component_config`] for a component. Values the component
+    /// gets from environment variables are not included.
+    pub async fn component_config(&self, component_id: &str) -> HashMap<String, String> {
+        self.policy_registry
+            .read()
+            .await
+            .config_overrides
+            .get(component_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LifecycleManager;
+
+    /// Exports `run`, which returns the `greeting` config variable
+    const CONFIG_COMPONENT: &str = r#"
+(component
+  (type $config (instance
+    (type $error (variant (case "upstream" string) (case "io" string)))
+    (export "error" (type $error' (eq $error)))
+    (export "get" (func (param "key" string) (result (result (option string) (error $error')))))))
+  (import "wasi:config/store@0.2.0-draft" (instance $store (type $config)))
+  (core module $mem (memory (export "memory") 1)
+    (global $next (mut i32) (i32.const 1024))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $next))
+      (global.set $next (i32.add (global.get $next) (local.get 3)))
+      (local.get $ptr)))
+  (core instance $mem (instantiate $mem))
+  (core func $get (canon lower (func $store "get") (memory $mem "memory") (realloc (func $mem "realloc"))))
+  (core module $m
+    (import "mem" "memory" (memory 1))
+    (import "store" "get" (func $get (param i32 i32 i32)))
+    (data (i32.const 0) "greeting")
+    (func (export "run") (result i32)
+      (call $get (i32.const 0) (i32.const 8) (i32.const 16))
+      ;; Return a pointer to the option<string> payload, or to an empty string
+      (if (result i32)
+        (i32.and
+          (i32.eqz (i32.load8_u (i32.const 16)))
+          (i32.load8_u (i32.const 20)))
+        (then (i32.const 24))
+        (else (i32.const 64)))))
+  (core instance $i (instantiate $m
+    (with "mem" (instance $mem))
+    (with "store" (instance (export "get" (func $get))))))
+  (func (export "run") (result string)
+    (canon lift (core func $i "run") (memory $mem "memory") (realloc (func $mem "realloc"))))
+)
+"#;
+
+    #[tokio::test]
+    async fn test_set_component_config() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("greeter.wasm"), CONFIG_COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            ""
+        );
+        manager
+            .set_component_config("greeter", "greeting", Some("hello"))
+            .await?;
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            "hello"
+        );
+        assert_eq!(
+            manager.component_config("greeter").await,
+            HashMap::from([("greeting".to_string(), "hello".to_string())])
+        );
+
+        manager
+            .set_component_config("greeter", "greeting", None)
+            .await?;
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            ""
+        );
+        assert!(manager.component_config("greeter").await.is_empty());
+
+        assert!(manager
+            .set_component_config("greeter", "", Some("x"))
+            .await
+            .is_err());
+        assert!(manager
+            .set_component_config("missing", "greeting", Some("x"))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_config_variables() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("greeter.wasm"), CONFIG_COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        manager.set_secret("greeter", "greeting", "secret").await?;
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            "secret"
+        );
+        // Values set with set-config win over secrets
+        manager
+            .set_component_config("greeter", "greeting", Some("hello"))
+            .await?;
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            "hello"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secret_references() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("greeter.wasm"), CONFIG_COMPONENT)?;
+        std::fs::write(
+            tempdir.path().join("greeter.policy.yaml"),
+            r#"
+version: "1.0"
+permissions:
+  environment:
+    allow:
+      - key: greeting
+        from: secret://GREETING
+"#,
+        )?;
+        let manager = LifecycleManager::new_with_env(
+            &tempdir,
+            HashMap::from([("greeting".to_string(), "from the host".to_string())]),
+        )
+        .await?;
+
+        // The server's environment is never used for a variable that comes from a secret
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            ""
+        );
+        manager.set_secret("greeter", "GREETING", "hi").await?;
+        assert_eq!(
+            manager
+                .execute_component_call("greeter", "run", "{}")
+                .await?,
+            "hi"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Capability delegation tokens for calls between components
+//!
+//! When a component calls a tool of another component, the host issues a short-lived token that
+//! names the chain of callers, the called component and tool, and the [`DelegationScope`]: the
+//! permissions the callers hold between them. The token is signed with a key that only exists in
+//! the memory of the server, and the host validates it before it runs the call, which then gets
+//! only what both the token and the policy of the called component allow. Every issued token is
+//! published as an [`crate::Event::DelegationIssued`] event, so each nested call can be traced
+//! back to what was delegated to it.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::sockets::SocketGrant;
+
+/// How long a delegation token is valid after it was issued
+pub const DELEGATION_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// A directory a delegation token grants access to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DelegatedDir {
+    /// Path of the directory on the host
+    pub host_path: PathBuf,
+    /// Path the component sees the directory at
+    pub guest_path: String,
+    /// Whether files and directories may be read
+    pub read: bool,
+    /// Whether files and directories may be written, created and removed
+    pub write: bool,
+}
+
+/// What the component called with a delegation token may access, at most
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DelegationScope {
+    /// Whether the component may write to stdout
+    pub stdout: bool,
+    /// Whether the component may write to stderr
+    pub stderr: bool,
+    /// Whether the component may read its command line arguments
+    pub args: bool,
+    /// Whether the component may open TCP sockets
+    pub tcp: bool,
+    /// Whether the component may open UDP sockets
+    pub udp: bool,
+    /// Whether the component may resolve names through wasi:sockets/ip-name-lookup
+    pub ip_name_lookup: bool,
+    /// Hosts the component may send HTTP requests to
+    pub hosts: BTreeSet<String>,
+    /// Names the component may resolve
+    pub dns_names: BTreeSet<String>,
+    /// Names of the environment variables the component may see
+    pub environment: BTreeSet<String>,
+    /// Directories the component may access
+    pub storage: Vec<DelegatedDir>,
+    /// Endpoints the component may exchange UDP datagrams with
+    pub(crate) udp_grants: Vec<SocketGrant>,
+    /// Endpoints the component may open raw TCP connections to
+    pub(crate) tcp_grants: Vec<SocketGrant>,
+    /// wasi:keyvalue buckets the component may open
+    pub keyvalue_buckets: BTreeSet<String>,
+    /// wasi:blobstore containers the component may use
+    pub blobstore_containers: BTreeSet<String>,
+    /// wasi:sql databases the component may open
+    pub databases: BTreeSet<String>,
+    /// Host extensions the component may use
+    pub extensions: BTreeSet<String>,
+    /// Whether the component may request completions through wassette:ai
+    pub sampling: bool,
+}
+
+/// The contents of a delegation token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DelegationClaims {
+    /// Unique ID of the token
+    pub id: String,
+    /// The components whose calls led to the delegated call, the outermost first
+    pub callers: Vec<String>,
+    /// ID of the component the token may call
+    pub component_id: String,
+    /// The tool of the component the token may call
+    pub tool: String,
+    /// What the call may access, at most
+    pub scope: DelegationScope,
+    /// When the token was issued, in seconds since the Unix epoch
+    pub issued_at: u64,
+    /// When the token expires, in seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// Issues and validates the delegation tokens of one lifecycle manager
+#[derive(Clone)]
+pub(crate) struct DelegationAuthority {
+    key: Arc<[u8; 32]>,
+}
+
+impl Default for DelegationAuthority {
+    fn default() -> Self {
+        let mut key = [0u8; 32];
+        rand_core::OsRng.fill_bytes(&mut key);
+        Self { key: Arc::new(key) }
+    }
+}
+
+impl DelegationAuthority {
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.key.as_ref()).expect("HMAC accepts keys of any length")
+    }
+
+    /// Issues a token that lets `callers` call `tool` of `component_id` with at most `scope`
+    pub(crate) fn issue(
+        &self,
+        callers: &[String],
+        component_id: &str,
+        tool: &str,
+        scope: DelegationScope,
+    ) -> Result<(String, DelegationClaims)> {
+        let issued_at = unix_time();
+        let claims = DelegationClaims {
+            id: uuid::Uuid::new_v4().to_string(),
+            callers: callers.to_vec(),
+            component_id: component_id.to_string(),
+            tool: tool.to_string(),
+            scope,
+            issued_at,
+            expires_at: issued_at + DELEGATION_TOKEN_TTL.as_secs(),
+        };
+        let payload = BASE64.encode(serde_json::to_vec(&claims)?);
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = BASE64.encode(mac.finalize().into_bytes());
+        Ok((format!("{payload}.{signature}"), claims))
+    }
+
+    /// Checks that `token` was issued by this authority for calling `tool` of `component_id`
+    /// and hasn't expired, and returns its claims
+    pub(crate) fn validate(
+        &self,
+        token: &str,
+        component_id: &str,
+        tool: &str,
+    ) -> Result<DelegationClaims> {
+        let (payload, signature) = token
+            .split_once('.')
+            .context("Malformed delegation token")?;
+        let signature = BASE64
+            .decode(signature)
+            .context("Malformed delegation token")?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| anyhow::anyhow!("Delegation token has an invalid signature"))?;
+        let claims: DelegationClaims = serde_json::from_slice(
+            &BASE64
+                .decode(payload)
+                .context("Malformed delegation token")?,
+        )
+        .context("Malformed delegation token")?;
+        if claims.expires_at < unix_time() {
+            bail!("Delegation token {} has expired", claims.id);
+        }
+        if claims.component_id != component_id || claims.tool != tool {
+            bail!(
+                "Delegation token {} is for tool {} of component {}, not {tool} of {component_id}",
+                claims.id,
+                claims.tool,
+                claims.component_id
+            );
+        }
+        Ok(claims)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_validate() -> Result<()> {
+        let authority = DelegationAuthority::default();
+        let scope = DelegationScope {
+            hosts: BTreeSet::from(["api.example.com".to_string()]),
+            ..Default::default()
+        };
+        let (token, claims) =
+            authority.issue(&["pipeline".to_string()], "fetch", "fetch", scope.clone())?;
+        assert_eq!(claims.scope, scope);
+
+        let validated = authority.validate(&token, "fetch", "fetch")?;
+        assert_eq!(validated, claims);
+
+        let err = authority.validate(&token, "fetch", "other").unwrap_err();
+        assert!(err.to_string().contains("not other of fetch"), "{err}");
+        let err = DelegationAuthority::default()
+            .validate(&token, "fetch", "fetch")
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid signature"), "{err}");
+
+        // A token whose scope was widened no longer matches its signature
+        let (_, signature) = token.split_once('.').unwrap();
+        let widened = DelegationClaims {
+            scope: DelegationScope {
+                hosts: BTreeSet::from(["*".to_string()]),
+                ..Default::default()
+            },
+            ..claims.clone()
+        };
+        let forged = format!(
+            "{}.{signature}",
+            BASE64.encode(serde_json::to_vec(&widened)?)
+        );
+        assert!(authority.validate(&forged, "fetch", "fetch").is_err());
+
+        let expired = DelegationClaims {
+            expires_at: claims.issued_at - 1,
+            ..claims
+        };
+        let payload = BASE64.encode(serde_json::to_vec(&expired)?);
+        let mut mac = authority.mac();
+        mac.update(payload.as_bytes());
+        let token = format!("{payload}.{}", BASE64.encode(mac.finalize().into_bytes()));
+        let err = authority.validate(&token, "fetch", "fetch").unwrap_err();
+        assert!(err.to_string().contains("expired"), "{err}");
+        Ok(())
+    }
+}
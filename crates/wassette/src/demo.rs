@@ -0,0 +1,290 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Offline demo mode
+//!
+//! A [`DemoWorkspace`] is a temporary plugin directory with a few example components whose
+//! bytes are embedded in the binary: `fetch`, `html-to-markdown` and `filesystem`, and a handful
+//! of sample files the filesystem component may read. With
+//! [`crate::LifecycleManager::with_demo_fixtures`], outgoing HTTP requests are answered from
+//! canned responses instead of the network, so a demo needs neither a registry nor internet
+//! access.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use wasmtime_wasi_http::types::IncomingResponse;
+
+/// A component bundled for the demo
+struct DemoComponent {
+    id: &'static str,
+    wasm: &'static [u8],
+    /// Policy of the component. `{files}` is replaced with the directory of the sample files.
+    policy: &'static str,
+}
+
+const DEMO_COMPONENTS: [DemoComponent; 3] = [
+    DemoComponent {
+        id: "fetch",
+        wasm: include_bytes!("../demo/fetch.wasm"),
+        policy: r#"version: "1.0"
+description: "Demo policy for the fetch example"
+permissions:
+  network:
+    allow:
+      - host: "example.com"
+      - host: "api.github.com"
+      - host: "rss.nytimes.com"
+"#,
+    },
+    DemoComponent {
+        id: "html-to-markdown",
+        wasm: include_bytes!("../demo/html-to-markdown.wasm"),
+        policy: r#"version: "1.0"
+description: "Demo policy for the html-to-markdown example"
+permissions: {}
+"#,
+    },
+    DemoComponent {
+        id: "filesystem",
+        wasm: include_bytes!("../demo/filesystem.wasm"),
+        policy: r#"version: "1.0"
+description: "Demo policy for the filesystem example"
+permissions:
+  storage:
+    allow:
+      - uri: "fs://{files}"
+        access: ["read"]
+"#,
+    },
+];
+
+/// Sample files the filesystem component can read
+const DEMO_FILES: [(&str, &str); 2] = [
+    (
+        "README.md",
+        "# Wassette demo\n\nThese files live in a temporary directory that is deleted when the \
+         demo stops. The filesystem component may read them, and nothing else.\n",
+    ),
+    (
+        "notes/ideas.txt",
+        "- Fetch https://example.com/ and convert it to Markdown\n\
+         - Ask for the open source license of https://api.github.com/repos/microsoft/wassette\n\
+         - Summarize https://rss.nytimes.com/services/xml/rss/nyt/Technology.xml\n",
+    ),
+];
+
+/// A canned response to requests for a URL
+struct Fixture {
+    url: &'static str,
+    content_type: &'static str,
+    body: &'static str,
+}
+
+const FIXTURES: [Fixture; 4] = [
+    Fixture {
+        url: "https://example.com/",
+        content_type: "text/html; charset=utf-8",
+        body: include_str!("../demo/fixtures/example.com.html"),
+    },
+    Fixture {
+        url: "https://example.com/wassette",
+        content_type: "text/html; charset=utf-8",
+        body: include_str!("../demo/fixtures/page.html"),
+    },
+    Fixture {
+        url: "https://api.github.com/repos/microsoft/wassette",
+        content_type: "application/json; charset=utf-8",
+        body: include_str!("../demo/fixtures/wassette.json"),
+    },
+    Fixture {
+        url: "https://rss.nytimes.com/services/xml/rss/nyt/Technology.xml",
+        content_type: "text/xml; charset=utf-8",
+        body: include_str!("../demo/fixtures/technology.xml"),
+    },
+];
+
+/// URLs the demo has canned responses for
+pub fn demo_fixture_urls() -> impl Iterator<Item = &'static str> {
+    FIXTURES.iter().map(|fixture| fixture.url)
+}
+
+/// Answers a request from the fixtures. URLs without a fixture get a `404 Not Found` that lists
+/// the URLs that have one. The query is ignored.
+pub(crate) fn fixture_response(
+    uri: &hyper::Uri,
+    between_bytes_timeout: Duration,
+) -> IncomingResponse {
+    let url = format!(
+        "{}://{}{}",
+        uri.scheme_str().unwrap_or("http"),
+        uri.authority().map(|a| a.as_str()).unwrap_or_default(),
+        uri.path()
+    );
+    let fixture = FIXTURES
+        .iter()
+        .find(|fixture| fixture.url.trim_end_matches('/') == url.trim_end_matches('/'));
+    let (status, content_type, body) = match fixture {
+        Some(fixture) => (
+            hyper::StatusCode::OK,
+            fixture.content_type,
+            Bytes::from_static(fixture.body.as_bytes()),
+        ),
+        None => (
+            hyper::StatusCode::NOT_FOUND,
+            "text/plain; charset=utf-8",
+            Bytes::from(format!(
+                "The wassette demo has no response for {url}. Try one of:\n{}\n",
+                demo_fixture_urls().collect::<Vec<_>>().join("\n")
+            )),
+        ),
+    };
+    let mut resp = hyper::Response::new(
+        http_body_util::Full::new(body)
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static(content_type),
+    );
+    IncomingResponse {
+        resp,
+        worker: None,
+        between_bytes_timeout,
+    }
+}
+
+/// A temporary plugin directory with the demo components and sample files, deleted when dropped
+pub struct DemoWorkspace {
+    dir: tempfile::TempDir,
+}
+
+impl DemoWorkspace {
+    /// Writes the demo components, their policies and the sample files to a new temporary
+    /// directory
+    pub async fn create() -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("wassette-demo-")
+            .tempdir()
+            .context("Failed to create the demo directory")?;
+        let workspace = Self { dir };
+
+        let files_dir = workspace.files_dir();
+        for (name, contents) in DEMO_FILES {
+            let path = files_dir.join(name);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&path, contents).await?;
+        }
+
+        let plugin_dir = workspace.plugin_dir();
+        tokio::fs::create_dir_all(&plugin_dir).await?;
+        for component in &DEMO_COMPONENTS {
+            tokio::fs::write(
+                plugin_dir.join(format!("{}.wasm", component.id)),
+                component.wasm,
+            )
+            .await?;
+            tokio::fs::write(
+                plugin_dir.join(format!("{}.policy.yaml", component.id)),
+                component
+                    .policy
+                    .replace("{files}", &files_dir.to_string_lossy()),
+            )
+            .await?;
+        }
+        Ok(workspace)
+    }
+
+    /// The plugin directory with the demo components
+    pub fn plugin_dir(&self) -> PathBuf {
+        self.dir.path().join("components")
+    }
+
+    /// The directory with the sample files
+    pub fn files_dir(&self) -> PathBuf {
+        self.dir.path().join("files")
+    }
+
+    /// IDs of the demo components
+    pub fn component_ids() -> impl Iterator<Item = &'static str> {
+        DEMO_COMPONENTS.iter().map(|component| component.id)
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Answers the HTTP requests of components from the demo's canned responses instead of
+    /// sending them. Requests are still checked against the network policy first.
+    pub fn with_demo_fixtures(mut self) -> Self {
+        self.demo_fixtures = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn test_fixture_response() {
+        let timeout = Duration::from_secs(1);
+        let uri = "https://example.com/?utm=demo".parse().unwrap();
+        let response = fixture_response(&uri, timeout);
+        assert_eq!(response.resp.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.resp.headers()[hyper::header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+
+        let uri = "https://example.com/missing".parse().unwrap();
+        let response = fixture_response(&uri, timeout);
+        assert_eq!(response.resp.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[test(tokio::test)]
+    async fn test_demo_components() -> Result<()> {
+        let workspace = DemoWorkspace::create().await?;
+        let manager = crate::LifecycleManager::new(workspace.plugin_dir())
+            .await?
+            .with_demo_fixtures();
+
+        let mut components = manager.list_components().await;
+        components.sort();
+        assert_eq!(components, ["fetch", "filesystem", "html-to-markdown"]);
+
+        let result = manager
+            .execute_component_call("fetch", "fetch", r#"{"url": "https://example.com/"}"#)
+            .await?;
+        assert!(result.contains("# Example Domain"), "{result}");
+
+        let result = manager
+            .execute_component_call(
+                "html-to-markdown",
+                "convert",
+                r#"{"html": "<h2>Hi</h2><ul><li><a href=\"/a\">A</a></li></ul><script>x()</script>"}"#,
+            )
+            .await?;
+        assert!(result.contains("## Hi"), "{result}");
+        assert!(result.contains("- [A](/a)"), "{result}");
+        assert!(!result.contains("x()"), "{result}");
+
+        let files = workspace.files_dir();
+        let result = manager
+            .execute_component_call(
+                "filesystem",
+                "read-file",
+                &serde_json::json!({"path": files.join("README.md")}).to_string(),
+            )
+            .await?;
+        assert!(result.contains("Wassette demo"), "{result}");
+        Ok(())
+    }
+}
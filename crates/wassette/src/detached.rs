@@ -0,0 +1,339 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Detached signatures of component files
+//!
+//! Components loaded from `file://` and `https://` sources have no registry to keep their
+//! signatures in, so they are signed like any other file with `cosign sign-blob`. A
+//! [`DetachedSignature`] reads what that writes: the base64 signature from `--output-signature`,
+//! the cosign bundle from `--bundle`, or a Sigstore bundle (`.sigstore.json`) from
+//! `--new-bundle-format`. Keyless signatures need one of the bundles, which hold the signing
+//! certificate and the Rekor entry.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::signature::SIGNATURE_ANNOTATION;
+use crate::signing::{BUNDLE_ANNOTATION, CERTIFICATE_ANNOTATION};
+
+/// Scheme of a base64 standard encoding
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+/// Prefix of the media types of Sigstore bundles
+const SIGSTORE_BUNDLE_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.bundle";
+
+/// A bundle written by `cosign sign-blob --bundle`
+#[derive(Deserialize)]
+struct CosignBundle {
+    #[serde(rename = "base64Signature")]
+    signature: String,
+    /// The base64 PEM certificate or public key
+    #[serde(default)]
+    cert: Option<String>,
+    #[serde(rename = "rekorBundle", default)]
+    rekor_bundle: Option<serde_json::Value>,
+}
+
+/// A Sigstore bundle of a message signature, in its JSON encoding
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SigstoreBundle {
+    media_type: String,
+    verification_material: VerificationMaterial,
+    message_signature: Option<MessageSignature>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMaterial {
+    /// The signing certificate, in bundles since v0.3
+    certificate: Option<RawBytes>,
+    /// The signing certificate and its chain, in older bundles
+    x509_certificate_chain: Option<CertificateChain>,
+    #[serde(default)]
+    tlog_entries: Vec<TlogEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+#[derive(Deserialize)]
+struct CertificateChain {
+    certificates: Vec<RawBytes>,
+}
+
+/// A Rekor entry; its 64-bit integers are strings, as in all protobuf JSON
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TlogEntry {
+    log_index: String,
+    log_id: LogId,
+    integrated_time: String,
+    inclusion_promise: Option<InclusionPromise>,
+    canonicalized_body: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogId {
+    key_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionPromise {
+    signed_entry_timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct MessageSignature {
+    signature: String,
+}
+
+/// A detached signature of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetachedSignature {
+    /// The base64 signature
+    signature: String,
+    /// The PEM signing certificate of a keyless signature
+    certificate: Option<String>,
+    /// The cosign bundle of the signature's Rekor entry, as JSON
+    rekor_bundle: Option<String>,
+}
+
+impl DetachedSignature {
+    /// Parses a base64 signature, a cosign bundle or a Sigstore bundle
+    pub fn parse(contents: &str) -> Result<Self> {
+        let contents = contents.trim();
+        if !contents.starts_with('{') {
+            BASE64
+                .decode(contents)
+                .context("Detached signature is neither base64 nor a bundle")?;
+            return Ok(Self {
+                signature: contents.to_string(),
+                certificate: None,
+                rekor_bundle: None,
+            });
+        }
+
+        let value: serde_json::Value =
+            serde_json::from_str(contents).context("Invalid signature bundle")?;
+        if value.get("mediaType").is_some() {
+            Self::from_sigstore_bundle(serde_json::from_value(value)?)
+        } else {
+            let bundle: CosignBundle =
+                serde_json::from_value(value).context("Invalid cosign bundle")?;
+            let certificate = bundle
+                .cert
+                .map(|cert| BASE64.decode(cert.trim()))
+                .transpose()
+                .context("Certificate of cosign bundle is not valid base64")?
+                .map(String::from_utf8)
+                .transpose()?
+                // Bundles of key-based signatures hold the public key instead
+                .filter(|pem| pem.contains("BEGIN CERTIFICATE"));
+            Ok(Self {
+                signature: bundle.signature,
+                certificate,
+                rekor_bundle: bundle.rekor_bundle.map(|bundle| bundle.to_string()),
+            })
+        }
+    }
+
+    fn from_sigstore_bundle(bundle: SigstoreBundle) -> Result<Self> {
+        if !bundle.media_type.starts_with(SIGSTORE_BUNDLE_MEDIA_TYPE) {
+            bail!("Unsupported bundle type {}", bundle.media_type);
+        }
+        let signature = bundle
+            .message_signature
+            .context("Sigstore bundle has no message signature")?
+            .signature;
+        let material = bundle.verification_material;
+        let certificate = material.certificate.or_else(|| {
+            material
+                .x509_certificate_chain
+                .and_then(|chain| chain.certificates.into_iter().next())
+        });
+        let certificate = certificate
+            .map(|certificate| -> Result<String> {
+                BASE64
+                    .decode(&certificate.raw_bytes)
+                    .context("Certificate of Sigstore bundle is not valid base64")?;
+                Ok(pem("CERTIFICATE", &certificate.raw_bytes))
+            })
+            .transpose()?;
+        let rekor_bundle = material
+            .tlog_entries
+            .into_iter()
+            .next()
+            .map(|entry| -> Result<String> {
+                let timestamp = entry
+                    .inclusion_promise
+                    .context("Rekor entry of Sigstore bundle has no inclusion promise")?
+                    .signed_entry_timestamp;
+                let log_id = BASE64
+                    .decode(&entry.log_id.key_id)
+                    .context("Rekor log ID is not valid base64")?;
+                Ok(json!({
+                    "SignedEntryTimestamp": timestamp,
+                    "Payload": {
+                        "body": entry.canonicalized_body,
+                        "integratedTime": entry.integrated_time.parse::<u64>()?,
+                        "logIndex": entry.log_index.parse::<u64>()?,
+                        "logID": log_id.iter().map(|b| format!("{b:02x}")).collect::<String>(),
+                    },
+                })
+                .to_string())
+            })
+            .transpose()?;
+        Ok(Self {
+            signature,
+            certificate,
+            rekor_bundle,
+        })
+    }
+
+    /// Reads a detached signature from a `file://` or `https://` URI, or a path
+    pub async fn from_uri(uri: &str, http_client: &reqwest::Client) -> Result<Self> {
+        let contents = if uri.starts_with("https://") {
+            let response = http_client
+                .get(uri)
+                .send()
+                .await
+                .with_context(|| format!("Failed to download signature {uri}"))?
+                .error_for_status()
+                .with_context(|| format!("Failed to download signature {uri}"))?;
+            response.text().await?
+        } else {
+            let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+            tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Failed to read signature {}", path.display()))?
+        };
+        Self::parse(&contents).with_context(|| format!("Invalid signature {uri}"))
+    }
+
+    /// The signature as the annotations of a signature layer
+    pub(crate) fn annotations(&self) -> BTreeMap<String, String> {
+        let mut annotations =
+            BTreeMap::from([(SIGNATURE_ANNOTATION.to_string(), self.signature.clone())]);
+        if let Some(certificate) = &self.certificate {
+            annotations.insert(CERTIFICATE_ANNOTATION.to_string(), certificate.clone());
+        }
+        if let Some(bundle) = &self.rekor_bundle {
+            annotations.insert(BUNDLE_ANNOTATION.to_string(), bundle.clone());
+        }
+        annotations
+    }
+}
+
+/// A PEM block of base64 contents
+fn pem(label: &str, base64: &str) -> String {
+    let lines: Vec<&str> = base64
+        .as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    format!(
+        "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+        lines.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::signature::Signer as _;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::{EncodePublicKey as _, LineEnding};
+
+    use super::*;
+    use crate::SignatureVerifier;
+
+    const WASM: &[u8] = b"\0asm\x0d\0\x01\0";
+
+    fn sign(key: &SigningKey, data: &[u8]) -> String {
+        let signature: Signature = key.sign(data);
+        BASE64.encode(signature.to_der())
+    }
+
+    #[test]
+    fn test_verify_detached_signature() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let public_key = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        let verifier = SignatureVerifier::from_pem(&public_key).unwrap();
+
+        let signature = DetachedSignature::parse(&format!("{}\n", sign(&key, WASM))).unwrap();
+        verifier.verify_blob(WASM, &signature).unwrap();
+        assert!(verifier.verify_blob(b"\0asm", &signature).is_err());
+
+        // A cosign bundle of a key-based signature holds the public key, not a certificate
+        let bundle = DetachedSignature::parse(
+            &json!({
+                "base64Signature": sign(&key, WASM),
+                "cert": BASE64.encode(&public_key),
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(bundle, signature);
+
+        let other = SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let signature = DetachedSignature::parse(&sign(&other, WASM)).unwrap();
+        assert!(verifier.verify_blob(WASM, &signature).is_err());
+
+        assert!(DetachedSignature::parse("not base64!").is_err());
+        assert!(DetachedSignature::parse("{\"mediaType\": \"text/plain\"}").is_err());
+    }
+
+    #[test]
+    fn test_parse_sigstore_bundle() {
+        let certificate = BASE64.encode([0x30u8; 100]);
+        let bundle = json!({
+            "mediaType": "application/vnd.dev.sigstore.bundle.v0.3+json",
+            "verificationMaterial": {
+                "certificate": {"rawBytes": certificate},
+                "tlogEntries": [{
+                    "logIndex": "42",
+                    "logId": {"keyId": BASE64.encode([0xc0, 0xd2])},
+                    "kindVersion": {"kind": "hashedrekord", "version": "0.0.1"},
+                    "integratedTime": "1704110400",
+                    "inclusionPromise": {"signedEntryTimestamp": "MEUCIQ=="},
+                    "canonicalizedBody": "eyJraW5kIjoiaGFzaGVkcmVrb3JkIn0="
+                }]
+            },
+            "messageSignature": {
+                "messageDigest": {"algorithm": "SHA2_256", "digest": "AAAA"},
+                "signature": "MEQCIA=="
+            }
+        });
+        let signature = DetachedSignature::parse(&bundle.to_string()).unwrap();
+        let annotations = signature.annotations();
+        assert_eq!(annotations[SIGNATURE_ANNOTATION], "MEQCIA==");
+        assert!(annotations[CERTIFICATE_ANNOTATION].starts_with("-----BEGIN CERTIFICATE-----\n"));
+        let rekor: serde_json::Value =
+            serde_json::from_str(&annotations[BUNDLE_ANNOTATION]).unwrap();
+        assert_eq!(
+            rekor,
+            json!({
+                "SignedEntryTimestamp": "MEUCIQ==",
+                "Payload": {
+                    "body": "eyJraW5kIjoiaGFzaGVkcmVrb3JkIn0=",
+                    "integratedTime": 1704110400,
+                    "logIndex": 42,
+                    "logID": "c0d2",
+                },
+            })
+        );
+    }
+}
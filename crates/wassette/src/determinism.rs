@@ -0,0 +1,160 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Deterministic execution mode
+//!
+//! A policy with `permissions.runtime.deterministic` replaces the host clocks with virtual ones
+//! and the host entropy with a seeded generator. Every call gets a fresh clock and generator, so
+//! calling a tool twice with the same input observes the same time and the same random numbers.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use policy::{DeterministicRuntime, VirtualClock};
+use wasmtime_wasi::p2::WasiCtxBuilder;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, RngCore};
+
+/// How far a logical clock moves on every reading
+const LOGICAL_TICK: Duration = Duration::from_millis(1);
+
+/// Virtual time elapsed since the start of a call
+struct VirtualTime {
+    mode: VirtualClock,
+    elapsed: AtomicU64,
+}
+
+impl VirtualTime {
+    fn new(mode: VirtualClock) -> Self {
+        Self {
+            mode,
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the nanoseconds since the start of the call, advancing a logical clock
+    fn read(&self) -> u64 {
+        match self.mode {
+            VirtualClock::Fixed => 0,
+            VirtualClock::Logical => self
+                .elapsed
+                .fetch_add(LOGICAL_TICK.as_nanos() as u64, Ordering::Relaxed),
+        }
+    }
+}
+
+struct VirtualWallClock {
+    start: Duration,
+    time: VirtualTime,
+}
+
+impl HostWallClock for VirtualWallClock {
+    fn resolution(&self) -> Duration {
+        LOGICAL_TICK
+    }
+
+    fn now(&self) -> Duration {
+        self.start + Duration::from_nanos(self.time.read())
+    }
+}
+
+struct VirtualMonotonicClock {
+    time: VirtualTime,
+}
+
+impl HostMonotonicClock for VirtualMonotonicClock {
+    fn resolution(&self) -> u64 {
+        LOGICAL_TICK.as_nanos() as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.time.read()
+    }
+}
+
+/// SplitMix64, which is small, fast and good enough for reproducible test data
+struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Installs the virtual clocks and seeded generators of `settings` on `builder`
+pub(crate) fn apply(builder: &mut WasiCtxBuilder, settings: &DeterministicRuntime) {
+    builder
+        .wall_clock(VirtualWallClock {
+            start: Duration::from_secs(settings.start_time),
+            time: VirtualTime::new(settings.clock),
+        })
+        .monotonic_clock(VirtualMonotonicClock {
+            time: VirtualTime::new(settings.clock),
+        })
+        .secure_random(SeededRng::new(settings.seed))
+        // Use a different stream for the insecure generator so it doesn't mirror the secure one
+        .insecure_random(SeededRng::new(!settings.seed))
+        .insecure_random_seed(u128::from(settings.seed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clocks() {
+        let fixed = VirtualWallClock {
+            start: Duration::from_secs(1_700_000_000),
+            time: VirtualTime::new(VirtualClock::Fixed),
+        };
+        assert_eq!(fixed.now(), Duration::from_secs(1_700_000_000));
+        assert_eq!(fixed.now(), Duration::from_secs(1_700_000_000));
+
+        let logical = VirtualMonotonicClock {
+            time: VirtualTime::new(VirtualClock::Logical),
+        };
+        assert_eq!(logical.now(), 0);
+        assert_eq!(logical.now(), 1_000_000);
+        assert_eq!(logical.now(), 2_000_000);
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut first = [0u8; 20];
+        let mut second = [0u8; 20];
+        SeededRng::new(42).fill_bytes(&mut first);
+        SeededRng::new(42).fill_bytes(&mut second);
+        assert_eq!(first, second);
+
+        let mut other = [0u8; 20];
+        SeededRng::new(43).fill_bytes(&mut other);
+        assert_ne!(first, other);
+    }
+}
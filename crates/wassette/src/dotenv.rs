@@ -0,0 +1,218 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Parsing of dotenv files
+//!
+//! A dotenv file has one `KEY=VALUE` per line, optionally prefixed with `export`. Lines that
+//! start with `#` are comments, as is the rest of a line after a `#` that follows an unquoted
+//! value and whitespace. Unquoted values are trimmed. Values in single quotes are taken as they
+//! are, and values in double quotes understand `\n`, `\r`, `\t`, `\"`, `\\` and `\$`. Both kinds
+//! of quoted values may span several lines.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::{bail, Result};
+
+/// Parses the variables of a dotenv file, in the order they appear. A variable that appears
+/// more than once is listed each time.
+pub fn parse_dotenv(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut parser = Parser {
+        chars: contents.chars().peekable(),
+        line: 1,
+    };
+    let mut vars = Vec::new();
+    while let Some(var) = parser.variable()? {
+        vars.push(var);
+    }
+    Ok(vars)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while self.chars.next_if(|c| predicate(*c)).is_some() {}
+    }
+
+    fn skip_line(&mut self) {
+        while self.next().is_some_and(|c| c != '\n') {}
+    }
+
+    /// Parses the next variable, skipping blank lines and comments
+    fn variable(&mut self) -> Result<Option<(String, String)>> {
+        loop {
+            while let Some(c) = self.chars.peek() {
+                if !c.is_whitespace() {
+                    break;
+                }
+                self.next();
+            }
+            match self.chars.peek() {
+                None => return Ok(None),
+                Some('#') => self.skip_line(),
+                Some(_) => break,
+            }
+        }
+
+        let line = self.line;
+        let mut key = String::new();
+        while let Some(c) = self.chars.next_if(|c| *c != '=' && *c != '\n') {
+            key.push(c);
+        }
+        if self.chars.next_if_eq(&'=').is_none() {
+            bail!("Line {line}: expected KEY=VALUE");
+        }
+        let key = key.trim();
+        let key = key.strip_prefix("export ").map_or(key, str::trim_start);
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        {
+            bail!("Line {line}: invalid variable name '{key}'");
+        }
+
+        self.skip_while(|c| c == ' ' || c == '\t');
+        let value = match self.chars.peek() {
+            Some('\'') => self.single_quoted(line)?,
+            Some('"') => self.double_quoted(line)?,
+            _ => return Ok(Some((key.to_string(), self.unquoted()))),
+        };
+        // Only a comment may follow a quoted value
+        self.skip_while(|c| c == ' ' || c == '\t' || c == '\r');
+        match self.chars.peek() {
+            None | Some('\n') => {}
+            Some('#') => self.skip_line(),
+            Some(_) => bail!(
+                "Line {}: unexpected characters after the value of {key}",
+                self.line
+            ),
+        }
+        Ok(Some((key.to_string(), value)))
+    }
+
+    fn unquoted(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.chars.next_if(|c| *c != '\n') {
+            if c == '#' && value.ends_with([' ', '\t']) {
+                self.skip_line();
+                break;
+            }
+            value.push(c);
+        }
+        value.trim_end().to_string()
+    }
+
+    fn single_quoted(&mut self, line: usize) -> Result<String> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(value),
+                Some(c) => value.push(c),
+                None => bail!("Line {line}: missing closing quote"),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, line: usize) -> Result<String> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                    // Other escapes are kept as they are
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => bail!("Line {line}: missing closing quote"),
+                },
+                Some(c) => value.push(c),
+                None => bail!("Line {line}: missing closing quote"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(contents: &str) -> Vec<(String, String)> {
+        parse_dotenv(contents).unwrap()
+    }
+
+    fn var(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        assert_eq!(
+            vars(
+                "# Weather API\n\
+                 API_KEY=abc123\n\
+                 \n\
+                 export REGION = eu-west  # the closest\n\
+                 URL=https://example.com/#anchor\r\n\
+                 EMPTY=\n"
+            ),
+            [
+                var("API_KEY", "abc123"),
+                var("REGION", "eu-west"),
+                var("URL", "https://example.com/#anchor"),
+                var("EMPTY", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_quotes() {
+        assert_eq!(
+            vars(r#"SINGLE='a "b" \n $c'  # comment"#),
+            [var("SINGLE", r#"a "b" \n $c"#)]
+        );
+        assert_eq!(
+            vars(r#"DOUBLE="line\tone\n\"two\" \$HOME \\ \x""#),
+            [var("DOUBLE", "line\tone\n\"two\" $HOME \\ \\x")]
+        );
+        assert_eq!(
+            vars("KEY=\"-----BEGIN KEY-----\nabc\n-----END KEY-----\"\nNEXT='x\ny'\n"),
+            [
+                var("KEY", "-----BEGIN KEY-----\nabc\n-----END KEY-----"),
+                var("NEXT", "x\ny"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv_errors() {
+        let error = |contents| parse_dotenv(contents).unwrap_err().to_string();
+        assert_eq!(error("A=1\nNOT A VARIABLE\n"), "Line 2: expected KEY=VALUE");
+        assert_eq!(error("A=1\n=2"), "Line 2: invalid variable name ''");
+        assert_eq!(error("A B=1"), "Line 1: invalid variable name 'A B'");
+        assert_eq!(error("A=1\nB=\"open\n\n"), "Line 2: missing closing quote");
+        assert_eq!(
+            error("A='x' y"),
+            "Line 1: unexpected characters after the value of A"
+        );
+    }
+}
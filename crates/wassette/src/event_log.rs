@@ -0,0 +1,405 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Persistent log of events
+//!
+//! With [`crate::LifecycleManager::with_event_log`], every [`Event`] is appended to
+//! `<plugin_dir>/events.jsonl` as a [`LoggedEvent`], one JSON object per line, so what happened
+//! in the runtime can still be looked into after the in-memory feeds have moved on or the server
+//! was restarted. Entries older than the retention period are dropped when the log is opened and
+//! once an hour after that. [`crate::LifecycleManager::query_events`] reads the log back, filtered
+//! by time, component, event name and outcome.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+
+use crate::Event;
+
+/// File of the event log in the plugin directory
+pub const EVENT_LOG_FILE: &str = "events.jsonl";
+
+/// How long events are kept unless configured otherwise
+pub const DEFAULT_EVENT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often entries past the retention period are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Events returned by a query without a limit
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// Most events a single query returns
+const MAX_QUERY_LIMIT: usize = 1000;
+
+/// An event in the event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    /// When the event happened
+    pub time: DateTime<Utc>,
+    /// The event
+    pub event: Event,
+}
+
+/// Which events of the event log to return. Every filter that is set must match.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventQuery {
+    /// Only events that happened at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only events that happened before this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    /// Only events about this component, or this downstream server for proxied calls
+    #[serde(default)]
+    pub component_id: Option<String>,
+    /// Only events with this name, e.g. `tool.invoked`, or a name under it, so `permission`
+    /// matches `permission.granted` as well as `permission.revoked`
+    #[serde(default, rename = "type")]
+    pub event_type: Option<String>,
+    /// Only tool calls and network requests that succeeded or failed. Events without an outcome
+    /// never match.
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// Most events to return, 100 by default and at most 1000
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    fn matches(&self, entry: &LoggedEvent) -> bool {
+        let name = entry.event.name();
+        self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+            && self
+                .component_id
+                .as_deref()
+                .is_none_or(|component_id| entry.event.component_id() == component_id)
+            && self.event_type.as_deref().is_none_or(|event_type| {
+                name == event_type
+                    || name
+                        .strip_prefix(event_type)
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+            && self
+                .success
+                .is_none_or(|success| entry.event.success() == Some(success))
+    }
+}
+
+/// The event log file, appended to by one task and pruned in place
+#[derive(Debug)]
+pub(crate) struct EventLog {
+    path: PathBuf,
+    retention: Duration,
+    /// The file opened for appending. Held while the log is written or pruned, and reset by
+    /// pruning, which replaces the file.
+    file: Mutex<Option<tokio::fs::File>>,
+}
+
+impl EventLog {
+    pub(crate) async fn open(plugin_dir: &Path, retention: Duration) -> Result<Self> {
+        let log = Self {
+            path: plugin_dir.join(EVENT_LOG_FILE),
+            retention,
+            file: Mutex::new(None),
+        };
+        log.prune().await?;
+        Ok(log)
+    }
+
+    async fn append(&self, entry: &LoggedEvent) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        let file = match &mut *file {
+            Some(file) => file,
+            None => file.insert(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await
+                    .with_context(|| format!("Failed to open {}", self.path.display()))?,
+            ),
+        };
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// All entries of the log, oldest first. Lines that can't be parsed, such as one cut short
+    /// by a crash, are skipped.
+    async fn read(&self) -> Result<Vec<LoggedEvent>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Drops the entries older than the retention period
+    async fn prune(&self) -> Result<()> {
+        let mut file = self.file.lock().await;
+        let entries = self.read().await?;
+        let cutoff = Utc::now() - self.retention;
+        let kept = entries
+            .iter()
+            .filter(|entry| entry.time >= cutoff)
+            .collect::<Vec<_>>();
+        if kept.len() == entries.len() {
+            return Ok(());
+        }
+
+        let mut contents = Vec::new();
+        for entry in &kept {
+            serde_json::to_writer(&mut contents, entry)?;
+            contents.push(b'\n');
+        }
+        let temp_path = self.path.with_extension("jsonl.tmp");
+        tokio::fs::write(&temp_path, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        tokio::fs::rename(&temp_path, &self.path)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        *file = None;
+        info!(
+            dropped = entries.len() - kept.len(),
+            "Dropped events past the retention period from the event log"
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn query(&self, query: &EventQuery) -> Result<Vec<LoggedEvent>> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT);
+        let entries = {
+            let _file = self.file.lock().await;
+            self.read().await?
+        };
+        Ok(entries
+            .into_iter()
+            .rev()
+            .filter(|entry| query.matches(entry))
+            .take(limit)
+            .collect())
+    }
+
+    /// Appends every event of `receiver` to the log and prunes it periodically, until the
+    /// event channel closes
+    async fn run(self: Arc<Self>, mut receiver: broadcast::Receiver<Event>) {
+        let mut prune_interval = tokio::time::interval(PRUNE_INTERVAL);
+        // The log was pruned when it was opened
+        prune_interval.tick().await;
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!(skipped, "Event log fell behind, events were not logged");
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let entry = LoggedEvent { time: Utc::now(), event };
+                    if let Err(e) = self.append(&entry).await {
+                        warn!(error = %e, "Failed to write to the event log");
+                    }
+                }
+                _ = prune_interval.tick() => {
+                    if let Err(e) = self.prune().await {
+                        warn!(error = %e, "Failed to prune the event log");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Appends every event from now on to `<plugin_dir>/events.jsonl`, keeping each one for
+    /// `retention`, so that [`crate::LifecycleManager::query_events`] can find it
+    pub async fn with_event_log(mut self, retention: Duration) -> Result<Self> {
+        let log = Arc::new(EventLog::open(&self.plugin_dir, retention).await?);
+        tokio::spawn(log.clone().run(self.subscribe_events()));
+        self.event_log = Some(log);
+        Ok(self)
+    }
+
+    /// Returns the logged events that match `query`, newest first. Fails unless the event log
+    /// was enabled with [`crate::LifecycleManager::with_event_log`].
+    pub async fn query_events(&self, query: &EventQuery) -> Result<Vec<LoggedEvent>> {
+        let Some(log) = &self.event_log else {
+            bail!("The event log is not enabled");
+        };
+        log.query(query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_invoked(component_id: &str, success: bool) -> Event {
+        Event::ToolInvoked {
+            component_id: component_id.to_string(),
+            function_name: "run".to_string(),
+            duration_ms: 5,
+            success,
+            error: (!success).then(|| "trapped".to_string()),
+            callers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let log = EventLog::open(tempdir.path(), DEFAULT_EVENT_RETENTION).await?;
+        let start = Utc::now();
+        let events = [
+            Event::ComponentLoaded {
+                component_id: "fetch".to_string(),
+                replaced: false,
+            },
+            Event::PermissionGranted {
+                component_id: "fetch".to_string(),
+                permission_type: "network".to_string(),
+                details: serde_json::json!({"host": "api.example.com"}),
+            },
+            tool_invoked("fetch", true),
+            tool_invoked("fetch", false),
+            tool_invoked("weather", true),
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let time = start + chrono::Duration::seconds(i as i64);
+            log.append(&LoggedEvent {
+                time,
+                event: event.clone(),
+            })
+            .await?;
+        }
+
+        let all = log.query(&EventQuery::default()).await?;
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[0].event, events[4]);
+
+        let query = |query: EventQuery| {
+            let log = &log;
+            async move {
+                log.query(&query)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|entry| entry.event)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            query(EventQuery {
+                component_id: Some("fetch".to_string()),
+                success: Some(false),
+                ..Default::default()
+            })
+            .await,
+            [events[3].clone()]
+        );
+        assert_eq!(
+            query(EventQuery {
+                event_type: Some("permission".to_string()),
+                ..Default::default()
+            })
+            .await,
+            [events[1].clone()]
+        );
+        assert!(query(EventQuery {
+            event_type: Some("perm".to_string()),
+            ..Default::default()
+        })
+        .await
+        .is_empty());
+        assert_eq!(
+            query(EventQuery {
+                since: Some(start + chrono::Duration::seconds(1)),
+                until: Some(start + chrono::Duration::seconds(3)),
+                ..Default::default()
+            })
+            .await,
+            [events[2].clone(), events[1].clone()]
+        );
+        assert_eq!(
+            query(EventQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retention() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let log = EventLog::open(tempdir.path(), DEFAULT_EVENT_RETENTION).await?;
+        let old = LoggedEvent {
+            time: Utc::now() - chrono::Duration::days(40),
+            event: tool_invoked("fetch", true),
+        };
+        let recent = LoggedEvent {
+            time: Utc::now(),
+            event: tool_invoked("fetch", false),
+        };
+        log.append(&old).await?;
+        log.append(&recent).await?;
+        drop(log);
+
+        let log = EventLog::open(tempdir.path(), DEFAULT_EVENT_RETENTION).await?;
+        assert_eq!(
+            log.query(&EventQuery::default()).await?,
+            vec![recent.clone()]
+        );
+        // Appending after pruning goes to the new file
+        log.append(&recent).await?;
+        assert_eq!(log.query(&EventQuery::default()).await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_manager_event_log() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let manager = crate::LifecycleManager::new(&tempdir).await?;
+        assert!(manager.query_events(&EventQuery::default()).await.is_err());
+
+        let manager = manager.with_event_log(DEFAULT_EVENT_RETENTION).await?;
+        manager.record_proxied_call("github", "search", 12, None);
+        let mut logged = Vec::new();
+        for _ in 0..100 {
+            logged = manager.query_events(&EventQuery::default()).await?;
+            if !logged.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].event.name(), "proxy.tool.invoked");
+        assert!(tempdir.path().join(EVENT_LOG_FILE).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,641 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Lifecycle and invocation events
+//!
+//! The [`crate::LifecycleManager`] publishes an [`Event`] whenever a component is loaded,
+//! unloaded or invoked, its permissions change, or it delegates permissions to another component. Listeners inside the process subscribe with
+//! [`crate::LifecycleManager::subscribe_events`]. External systems receive the same events as
+//! [CloudEvents 1.0](https://cloudevents.io) structured JSON through an [`EventSink`], which is
+//! either an HTTP(S) endpoint or a NATS subject.
+//!
+//! Every event type carries a schema version suffix (e.g. `dev.wassette.component.loaded.v1`).
+//! Fields may be added to a payload without a version bump; removing or changing a field
+//! requires a new version.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::delegation::DelegationClaims;
+use crate::inspection::{Capability, InspectionFinding};
+use crate::nats::{NatsClient, DEFAULT_NATS_PORT};
+use crate::network_log::NetworkRequest;
+use crate::policy_check::PolicyWarning;
+
+/// The CloudEvents specification version of the envelopes produced by wassette
+pub const CLOUDEVENTS_SPEC_VERSION: &str = "1.0";
+
+/// Version of the event payload schemas, appended to every event type
+pub const EVENT_SCHEMA_VERSION: &str = "v1";
+
+/// The `source` attribute of every event
+pub const EVENT_SOURCE: &str = "/wassette";
+
+const EVENT_TYPE_PREFIX: &str = "dev.wassette";
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+const SINK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened in the runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// Loading a component from a file path, OCI reference or URL reached a new stage. The load
+    /// ends with [`Event::ComponentLoaded`] or [`Event::ComponentLoadFailed`].
+    ComponentLoading {
+        /// The file path, OCI reference or URL the component is loaded from
+        source: String,
+        /// What the load is doing now
+        stage: LoadStage,
+    },
+    /// Loading a component from a file path, OCI reference or URL failed
+    ComponentLoadFailed {
+        /// The file path, OCI reference or URL the component was loaded from
+        source: String,
+        /// Why the load failed
+        error: String,
+    },
+    /// A component was loaded, or replaced by a new version
+    ComponentLoaded {
+        /// ID of the component
+        component_id: String,
+        /// Whether an already loaded component with the same ID was replaced
+        replaced: bool,
+    },
+    /// A component was inspected before loading, see [`crate::inspection`]
+    ComponentInspected {
+        /// ID of the component
+        component_id: String,
+        /// The capabilities its imports request
+        capabilities: Vec<Capability>,
+        /// What the inspectors found
+        findings: Vec<InspectionFinding>,
+        /// Whether a finding kept the component from loading
+        blocked: bool,
+    },
+    /// A loaded component imports capabilities that its policy grants nothing for, see
+    /// [`crate::PolicyWarning`]
+    PolicyMismatch {
+        /// ID of the component
+        component_id: String,
+        /// One warning per capability
+        warnings: Vec<PolicyWarning>,
+    },
+    /// A component was unloaded and moved to the trash
+    ComponentUnloaded {
+        /// ID of the component
+        component_id: String,
+    },
+    /// A component was restored from the trash
+    ComponentRestored {
+        /// ID of the component
+        component_id: String,
+    },
+    /// A component was permanently deleted from the trash
+    ComponentPurged {
+        /// ID of the component
+        component_id: String,
+    },
+    /// A policy was attached to a component
+    PolicyAttached {
+        /// ID of the component
+        component_id: String,
+        /// Where the policy was loaded from
+        policy_uri: String,
+    },
+    /// The policy of a component was detached
+    PolicyDetached {
+        /// ID of the component
+        component_id: String,
+    },
+    /// A permission was granted to a component
+    PermissionGranted {
+        /// ID of the component
+        component_id: String,
+        /// The permission type, e.g. `network` or `storage`
+        permission_type: String,
+        /// The permission details as passed to the grant
+        details: Value,
+    },
+    /// A permission was revoked from a component
+    PermissionRevoked {
+        /// ID of the component
+        component_id: String,
+        /// The permission type, e.g. `network` or `storage`
+        permission_type: String,
+        /// The permission details as passed to the revoke
+        details: Value,
+    },
+    /// A component's permissions were reset to its on-disk policy
+    PermissionsReset {
+        /// ID of the component
+        component_id: String,
+    },
+    /// A `wasi:config` variable of a component was set or removed at runtime
+    ConfigChanged {
+        /// ID of the component
+        component_id: String,
+        /// The variable that changed
+        key: String,
+        /// Whether the value set earlier was removed
+        removed: bool,
+    },
+    /// A secret of a component was set or deleted. The value is never part of the event.
+    SecretChanged {
+        /// ID of the component
+        component_id: String,
+        /// The name of the secret
+        key: String,
+        /// Whether the secret was deleted
+        removed: bool,
+    },
+    /// A component function finished running
+    ToolInvoked {
+        /// ID of the component
+        component_id: String,
+        /// Name of the invoked function
+        function_name: String,
+        /// How long the call took, in milliseconds
+        duration_ms: u64,
+        /// Whether the call returned successfully
+        success: bool,
+        /// The error of a failed call
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// The components whose calls led to this one, the outermost first. Empty when an MCP
+        /// client called the tool.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        callers: Vec<String>,
+    },
+    /// A tool of a downstream MCP server that Wassette proxies finished running
+    ProxiedToolInvoked {
+        /// Name of the downstream server
+        server: String,
+        /// Name of the tool on the downstream server
+        tool: String,
+        /// How long the call took, in milliseconds
+        duration_ms: u64,
+        /// Whether the call returned successfully
+        success: bool,
+        /// The error of a failed call
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A component sent an HTTP request, or tried to
+    NetworkRequest(NetworkRequest),
+    /// The host issued a delegation token for a call of one component to another
+    DelegationIssued(DelegationClaims),
+}
+
+/// A stage of loading a component, see [`Event::ComponentLoading`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadStage {
+    /// The component is downloaded and its signature checked
+    Downloading,
+    /// The component is compiled, inspected and registered
+    Compiling,
+}
+
+impl Event {
+    /// The name of the event without prefix or schema version, e.g. `component.loaded`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ComponentLoading { .. } => "component.loading",
+            Event::ComponentLoadFailed { .. } => "component.load.failed",
+            Event::ComponentLoaded { .. } => "component.loaded",
+            Event::ComponentInspected { .. } => "component.inspected",
+            Event::PolicyMismatch { .. } => "policy.mismatch",
+            Event::ComponentUnloaded { .. } => "component.unloaded",
+            Event::ComponentRestored { .. } => "component.restored",
+            Event::ComponentPurged { .. } => "component.purged",
+            Event::PolicyAttached { .. } => "policy.attached",
+            Event::PolicyDetached { .. } => "policy.detached",
+            Event::PermissionGranted { .. } => "permission.granted",
+            Event::PermissionRevoked { .. } => "permission.revoked",
+            Event::PermissionsReset { .. } => "permission.reset",
+            Event::ConfigChanged { .. } => "config.changed",
+            Event::SecretChanged { .. } => "secret.changed",
+            Event::ToolInvoked { .. } => "tool.invoked",
+            Event::ProxiedToolInvoked { .. } => "proxy.tool.invoked",
+            Event::NetworkRequest(_) => "network.request",
+            Event::DelegationIssued(_) => "delegation.issued",
+        }
+    }
+
+    /// The ID of the component the event is about, the source of a component that is still
+    /// loading, or the name of the downstream server of a proxied call
+    pub fn component_id(&self) -> &str {
+        match self {
+            Event::ComponentLoading { source, .. } | Event::ComponentLoadFailed { source, .. } => {
+                source
+            }
+            Event::ComponentLoaded { component_id, .. }
+            | Event::ComponentInspected { component_id, .. }
+            | Event::PolicyMismatch { component_id, .. }
+            | Event::ComponentUnloaded { component_id }
+            | Event::ComponentRestored { component_id }
+            | Event::ComponentPurged { component_id }
+            | Event::PolicyAttached { component_id, .. }
+            | Event::PolicyDetached { component_id }
+            | Event::PermissionGranted { component_id, .. }
+            | Event::PermissionRevoked { component_id, .. }
+            | Event::PermissionsReset { component_id }
+            | Event::ConfigChanged { component_id, .. }
+            | Event::SecretChanged { component_id, .. }
+            | Event::ToolInvoked { component_id, .. } => component_id,
+            Event::ProxiedToolInvoked { server, .. } => server,
+            Event::NetworkRequest(request) => &request.component_id,
+            Event::DelegationIssued(claims) => &claims.component_id,
+        }
+    }
+
+    /// Whether the tool call or network request of the event succeeded, or `None` for events
+    /// without an outcome
+    pub fn success(&self) -> Option<bool> {
+        match self {
+            Event::ToolInvoked { success, .. } | Event::ProxiedToolInvoked { success, .. } => {
+                Some(*success)
+            }
+            Event::NetworkRequest(request) => Some(request.error.is_none()),
+            _ => None,
+        }
+    }
+
+    /// Wraps the event in a CloudEvents envelope
+    pub fn to_cloud_event(&self) -> CloudEvent {
+        let name = self.name();
+        CloudEvent {
+            specversion: CLOUDEVENTS_SPEC_VERSION.to_string(),
+            id: uuid::Uuid::new_v4().to_string(),
+            source: EVENT_SOURCE.to_string(),
+            event_type: format!("{EVENT_TYPE_PREFIX}.{name}.{EVENT_SCHEMA_VERSION}"),
+            subject: self.component_id().to_string(),
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            datacontenttype: "application/json".to_string(),
+            dataschema: format!("urn:wassette:events:{name}:{EVENT_SCHEMA_VERSION}"),
+            data: serde_json::to_value(self).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// A CloudEvents 1.0 envelope in structured JSON mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloudEvent {
+    /// The CloudEvents specification version
+    pub specversion: String,
+    /// Unique ID of the event
+    pub id: String,
+    /// The producer of the event
+    pub source: String,
+    /// Versioned event type, e.g. `dev.wassette.component.loaded.v1`
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// The component the event is about
+    pub subject: String,
+    /// When the event happened, in RFC 3339 format
+    pub time: String,
+    /// Content type of `data`
+    pub datacontenttype: String,
+    /// Identifies the schema of `data`
+    pub dataschema: String,
+    /// The event payload
+    pub data: Value,
+}
+
+/// The sending side of the event channel. Sending never blocks and events are dropped when
+/// nobody is subscribed.
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    pub(crate) fn emit(&self, event: Event) {
+        debug!(
+            event = event.name(),
+            component_id = event.component_id(),
+            "Emitting event"
+        );
+        // An error only means that there are no subscribers right now
+        let _ = self.sender.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// An external destination for CloudEvents
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventSink {
+    /// POSTs each event to the URL with content type `application/cloudevents+json`
+    Http(String),
+    /// Publishes each event to a subject on a NATS server
+    Nats {
+        /// `host:port` of the NATS server
+        address: String,
+        /// The subject events are published to
+        subject: String,
+    },
+}
+
+impl std::str::FromStr for EventSink {
+    type Err = anyhow::Error;
+
+    /// Parses `http(s)://...` URLs and `nats://host[:port]/subject` URIs
+    fn from_str(s: &str) -> Result<Self> {
+        let url = url::Url::parse(s).with_context(|| format!("Invalid event sink: {s}"))?;
+        match url.scheme() {
+            "http" | "https" => Ok(EventSink::Http(s.to_string())),
+            "nats" => {
+                let host = url
+                    .host_str()
+                    .with_context(|| format!("NATS event sink is missing a host: {s}"))?;
+                let subject = url.path().trim_start_matches('/').replace('/', ".");
+                if subject.is_empty() {
+                    bail!("NATS event sink is missing a subject: {s}");
+                }
+                Ok(EventSink::Nats {
+                    address: format!("{host}:{}", url.port().unwrap_or(DEFAULT_NATS_PORT)),
+                    subject,
+                })
+            }
+            scheme => bail!("Unsupported event sink scheme '{scheme}': {s}"),
+        }
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Subscribes to the events of this lifecycle manager. A receiver that falls too far behind
+    /// skips the oldest events.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Records a call of `tool` of the downstream MCP server `server` that took `duration_ms`,
+    /// with the error of a failed call
+    pub fn record_proxied_call(
+        &self,
+        server: &str,
+        tool: &str,
+        duration_ms: u64,
+        error: Option<String>,
+    ) {
+        self.events.emit(Event::ProxiedToolInvoked {
+            server: server.to_string(),
+            tool: tool.to_string(),
+            duration_ms,
+            success: error.is_none(),
+            error,
+        });
+    }
+
+    /// Forwards every event to the given sinks as CloudEvents until the lifecycle manager is
+    /// dropped. Delivery is best effort: failures are logged and the event is skipped.
+    pub fn spawn_event_forwarder(&self, sinks: Vec<EventSink>) -> JoinHandle<()> {
+        let mut receiver = self.subscribe_events();
+        let http_client = self.http_client.clone();
+        tokio::spawn(async move {
+            let mut nats = Vec::new();
+            for sink in &sinks {
+                if let EventSink::Nats { address, .. } = sink {
+                    nats.push(NatsPublisher::new(address.clone()));
+                }
+            }
+
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Event forwarder fell behind, events were dropped");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let cloud_event = event.to_cloud_event();
+                let body = match serde_json::to_vec(&cloud_event) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        warn!(error = %e, "Failed to serialize event");
+                        continue;
+                    }
+                };
+
+                let mut nats = nats.iter();
+                for sink in &sinks {
+                    let result = match sink {
+                        EventSink::Http(url) => post_event(&http_client, url, &body).await,
+                        EventSink::Nats { subject, .. } => match nats.next() {
+                            Some(publisher) => publisher.publish(subject, &body).await,
+                            None => continue,
+                        },
+                    };
+                    if let Err(e) = result {
+                        warn!(sink = ?sink, event_id = %cloud_event.id, error = %e, "Failed to deliver event");
+                    }
+                }
+            }
+        })
+    }
+}
+
+async fn post_event(client: &reqwest::Client, url: &str, body: &[u8]) -> Result<()> {
+    client
+        .post(url)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/cloudevents+json",
+        )
+        .timeout(SINK_TIMEOUT)
+        .body(body.to_vec())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Publishes events to a NATS server. The connection is opened on first use and re-opened after
+/// it drops.
+struct NatsPublisher {
+    address: String,
+    client: Mutex<Option<NatsClient>>,
+}
+
+impl NatsPublisher {
+    fn new(address: String) -> Self {
+        Self {
+            address,
+            client: Mutex::new(None),
+        }
+    }
+
+    async fn publish(&self, subject: &str, payload: &[u8]) -> Result<()> {
+        let mut client = self.client.lock().await;
+        if client.as_ref().is_none_or(NatsClient::is_closed) {
+            *client = Some(NatsClient::connect(&self.address).await?);
+        }
+        client
+            .as_ref()
+            .expect("connected above")
+            .publish(subject, payload)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::tests::{create_test_manager, TEST_COMPONENT_ID};
+
+    #[test]
+    fn test_cloud_event_envelope() {
+        let event = Event::ComponentLoaded {
+            component_id: "fetch".to_string(),
+            replaced: false,
+        };
+        let cloud_event = event.to_cloud_event();
+
+        assert_eq!(cloud_event.specversion, "1.0");
+        assert_eq!(cloud_event.event_type, "dev.wassette.component.loaded.v1");
+        assert_eq!(cloud_event.subject, "fetch");
+        assert_eq!(
+            cloud_event.dataschema,
+            "urn:wassette:events:component.loaded:v1"
+        );
+        assert_eq!(
+            cloud_event.data,
+            serde_json::json!({"kind": "component_loaded", "component_id": "fetch", "replaced": false})
+        );
+
+        let json = serde_json::to_value(&cloud_event).unwrap();
+        assert_eq!(json["type"], "dev.wassette.component.loaded.v1");
+    }
+
+    #[test]
+    fn test_event_sink_parse() {
+        assert_eq!(
+            "https://events.example.com/hook"
+                .parse::<EventSink>()
+                .unwrap(),
+            EventSink::Http("https://events.example.com/hook".to_string())
+        );
+        assert_eq!(
+            "nats://localhost/wassette/events"
+                .parse::<EventSink>()
+                .unwrap(),
+            EventSink::Nats {
+                address: "localhost:4222".to_string(),
+                subject: "wassette.events".to_string(),
+            }
+        );
+        assert!("nats://localhost:4222".parse::<EventSink>().is_err());
+        assert!("ftp://example.com".parse::<EventSink>().is_err());
+        assert!("not a url".parse::<EventSink>().is_err());
+    }
+
+    #[test(tokio::test)]
+    async fn test_lifecycle_events() -> Result<()> {
+        let manager = create_test_manager().await?;
+        let mut events = manager.subscribe_events();
+
+        manager.load_test_component().await?;
+        let _ = manager.load_component("file:///missing.wasm").await;
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+        manager.unload_component(TEST_COMPONENT_ID).await?;
+
+        let event = events.recv().await?;
+        assert!(
+            matches!(
+                event,
+                Event::ComponentLoading {
+                    stage: LoadStage::Downloading,
+                    ..
+                }
+            ),
+            "{event:?}"
+        );
+        let event = events.recv().await?;
+        assert!(
+            matches!(
+                event,
+                Event::ComponentLoading {
+                    stage: LoadStage::Compiling,
+                    ..
+                }
+            ),
+            "{event:?}"
+        );
+        assert_eq!(
+            events.recv().await?,
+            Event::ComponentLoaded {
+                component_id: TEST_COMPONENT_ID.to_string(),
+                replaced: false,
+            }
+        );
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        let event = events.recv().await?;
+        assert_eq!(event.name(), "component.load.failed");
+        assert_eq!(event.component_id(), "file:///missing.wasm");
+        assert_eq!(events.recv().await?.name(), "permission.granted");
+        assert_eq!(
+            events.recv().await?,
+            Event::ComponentUnloaded {
+                component_id: TEST_COMPONENT_ID.to_string(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_nats_publisher() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"INFO {}\r\n").await.unwrap();
+            let mut reader = BufReader::new(reader);
+            let mut lines = Vec::new();
+            for _ in 0..3 {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                lines.push(line);
+            }
+            lines
+        });
+
+        let publisher = NatsPublisher::new(address);
+        publisher.publish("wassette.events", b"{}").await?;
+
+        let lines = server.await?;
+        assert!(lines[0].starts_with("CONNECT "));
+        assert_eq!(lines[1], "PUB wassette.events 2\r\n");
+        assert_eq!(lines[2], "{}\r\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host extensions
+//!
+//! Out of the box, components can import wasi, wasi-http, wasi-config, wasi-keyvalue and
+//! wasi-logging. Embedders can add their own host functions by passing [`HostExtension`]s to
+//! [`crate::LifecycleManager::new_with_extensions`]. The imports of an extension are linked for
+//! every component, so components using them load normally, but their host functions should
+//! call [`WassetteWasiState::extension_grant`] first. It fails unless the component's policy
+//! lists the extension under `permissions.extensions`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use policy::PolicyDocument;
+use serde_json::Value;
+use wasmtime::component::Linker;
+
+use crate::wasistate::WasiState;
+use crate::WassetteWasiState;
+
+/// The linker host extensions add their imports to
+pub type ExtensionLinker = Linker<WassetteWasiState<WasiState>>;
+
+/// A set of extra host functions that components can import
+pub trait HostExtension: Send + Sync + 'static {
+    /// Name of the extension, which is how policies grant it
+    fn name(&self) -> &str;
+
+    /// Adds the imports of the extension to `linker`
+    fn add_to_linker(&self, linker: &mut ExtensionLinker) -> Result<()>;
+}
+
+/// An extension granted to the component that is calling a host function
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionGrant {
+    /// The calling component
+    pub component_id: String,
+    /// The `config` of the grant in the component's policy, or `null` if it has none
+    pub config: Value,
+}
+
+/// The extensions granted to one component instance
+#[derive(Default)]
+pub(crate) struct ExtensionCtx {
+    grants: HashMap<String, ExtensionGrant>,
+}
+
+impl ExtensionCtx {
+    pub(crate) fn new(configs: &HashMap<String, Value>) -> Self {
+        Self {
+            grants: configs
+                .iter()
+                .map(|(name, config)| {
+                    let grant = ExtensionGrant {
+                        component_id: String::new(),
+                        config: config.clone(),
+                    };
+                    (name.clone(), grant)
+                })
+                .collect(),
+        }
+    }
+
+    /// Attributes the grants of this instance to `component_id`
+    pub(crate) fn bind(&mut self, component_id: &str) {
+        for grant in self.grants.values_mut() {
+            grant.component_id = component_id.to_string();
+        }
+    }
+}
+
+impl WassetteWasiState<WasiState> {
+    /// Returns the grant of extension `name` for the calling component, or an error if its
+    /// policy doesn't grant the extension. Host functions of an extension should return this
+    /// error, which traps the call.
+    pub fn extension_grant(&self, name: &str) -> Result<&ExtensionGrant> {
+        match self.inner.extensions.grants.get(name) {
+            Some(grant) => Ok(grant),
+            None => bail!("Component is not allowed to use host extension '{name}'"),
+        }
+    }
+}
+
+/// Extract the extensions the policy grants, with their settings
+pub(crate) fn extract_extension_grants(
+    policy: &PolicyDocument,
+) -> anyhow::Result<HashMap<String, Value>> {
+    policy
+        .permissions
+        .extensions
+        .iter()
+        .flat_map(|extensions| extensions.allow.iter().flatten())
+        .map(|perm| {
+            let config = match &perm.config {
+                Some(config) => serde_json::to_value(config)?,
+                None => Value::Null,
+            };
+            Ok((perm.name.clone(), config))
+        })
+        .collect()
+}
+
+/// Links every extension, refusing two extensions with the same name
+pub(crate) fn add_to_linker(
+    linker: &mut ExtensionLinker,
+    extensions: &[Arc<dyn HostExtension>],
+) -> Result<()> {
+    let mut names = HashSet::new();
+    for extension in extensions {
+        if !names.insert(extension.name()) {
+            bail!("Host extension '{}' is registered twice", extension.name());
+        }
+        extension.add_to_linker(linker)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use policy::PolicyParser;
+    use wasmtime::StoreContextMut;
+
+    use super::*;
+    use crate::LifecycleManager;
+
+    /// Imports `acme:demo/host.answer` and exports it as the `run` tool
+    const DEMO_COMPONENT: &str = r#"
+(component
+  (import "acme:demo/host" (instance $host (export "answer" (func (result u32)))))
+  (core func $answer (canon lower (func $host "answer")))
+  (core module $m
+    (import "host" "answer" (func $answer (result i32)))
+    (func (export "run") (result i32) call $answer))
+  (core instance $i (instantiate $m (with "host" (instance (export "answer" (func $answer))))))
+  (func (export "run") (result u32) (canon lift (core func $i "run")))
+)
+"#;
+
+    struct DemoExtension;
+
+    impl HostExtension for DemoExtension {
+        fn name(&self) -> &str {
+            "demo"
+        }
+
+        fn add_to_linker(&self, linker: &mut ExtensionLinker) -> Result<()> {
+            linker.instance("acme:demo/host")?.func_wrap(
+                "answer",
+                |store: StoreContextMut<'_, WassetteWasiState<WasiState>>, (): ()| {
+                    let grant = store.data().extension_grant("demo")?;
+                    Ok((grant.config["answer"].as_u64().unwrap_or_default() as u32,))
+                },
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extension_is_gated_by_policy() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("granted.wasm"), DEMO_COMPONENT)?;
+        std::fs::write(tempdir.path().join("denied.wasm"), DEMO_COMPONENT)?;
+        std::fs::write(
+            tempdir.path().join("granted.policy.yaml"),
+            r#"
+version: "1.0"
+description: "Grants the demo extension"
+permissions:
+  extensions:
+    allow:
+      - name: "demo"
+        config:
+          answer: 42
+"#,
+        )?;
+
+        let manager = LifecycleManager::new_with_extensions(
+            &tempdir,
+            HashMap::new(),
+            vec![Arc::new(DemoExtension)],
+        )
+        .await?;
+
+        // Both components export `run`, and the one that sorts last gets a prefix
+        assert_eq!(
+            manager
+                .execute_component_call("granted", "granted__run", "{}")
+                .await?,
+            "42"
+        );
+        let denied = manager
+            .execute_component_call("denied", "run", "{}")
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{denied:?}").contains("not allowed to use host extension 'demo'"),
+            "{denied:?}"
+        );
+
+        // Two extensions can't claim the same name
+        let duplicate = LifecycleManager::new_with_extensions(
+            tempfile::tempdir()?.path(),
+            HashMap::new(),
+            vec![Arc::new(DemoExtension), Arc::new(DemoExtension)],
+        )
+        .await;
+        assert!(duplicate.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_extension_grants() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Extension grants"
+permissions:
+  extensions:
+    allow:
+      - name: "gpu"
+        config:
+          devices: 2
+      - name: "acme:clock"
+"#,
+        )
+        .unwrap();
+
+        let grants = extract_extension_grants(&policy).unwrap();
+        assert_eq!(grants["gpu"], serde_json::json!({"devices": 2}));
+        assert_eq!(grants["acme:clock"], Value::Null);
+
+        let mut ctx = ExtensionCtx::new(&grants);
+        ctx.bind("renderer");
+        assert_eq!(
+            ctx.grants["gpu"],
+            ExtensionGrant {
+                component_id: "renderer".to_string(),
+                config: serde_json::json!({"devices": 2}),
+            }
+        );
+        assert!(!ctx.grants.contains_key("filesystem"));
+    }
+}
@@ -0,0 +1,717 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Full-text index over past tool results, component docs and component logs
+//!
+//! When enabled with [`crate::LifecycleManager::with_history_index`], every successful tool
+//! result, the tool docs of every loaded component and every component log message are indexed
+//! with tantivy under `<plugin_dir>/history`. Agents can then find an earlier result with
+//! [`crate::LifecycleManager::search_history`] instead of calling the tool again.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, NumericOptions, Schema, Value, STORED, STRING, TEXT,
+};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+use tracing::{info, warn};
+
+use crate::logging::ComponentLog;
+
+/// Directory of the index, relative to the plugin directory
+pub(crate) const HISTORY_DIR: &str = "history";
+
+/// Indexed text beyond this many bytes is dropped
+const MAX_INDEXED_TEXT: usize = 64 * 1024;
+
+/// Memory the index writer may use before flushing a segment, which is tantivy's minimum
+const WRITER_MEMORY_BUDGET: usize = 15_000_000;
+
+/// Longest snippet returned with a hit, in characters
+const SNIPPET_MAX_CHARS: usize = 200;
+
+/// What an indexed entry was recorded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryKind {
+    /// The result of a successful tool call
+    Result,
+    /// The name, description and input schema of a component tool
+    Doc,
+    /// A message a component logged or wrote to stdout or stderr
+    Log,
+}
+
+impl HistoryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HistoryKind::Result => "result",
+            HistoryKind::Doc => "doc",
+            HistoryKind::Log => "log",
+        }
+    }
+}
+
+impl fmt::Display for HistoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HistoryKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "result" => Ok(HistoryKind::Result),
+            "doc" => Ok(HistoryKind::Doc),
+            "log" => Ok(HistoryKind::Log),
+            _ => bail!("Unknown history kind '{s}', expected result, doc or log"),
+        }
+    }
+}
+
+/// A search over the history index
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryQuery {
+    /// Query in tantivy syntax, e.g. `weather AND berlin` or `"exact phrase"`
+    pub query: String,
+    /// Most hits to return
+    pub limit: usize,
+    /// Only return entries of this kind
+    pub kind: Option<HistoryKind>,
+    /// Only return entries of this component
+    pub component_id: Option<String>,
+    /// Whether to return the full indexed text of every hit along with the snippet
+    pub include_text: bool,
+}
+
+impl HistoryQuery {
+    /// Number of hits returned unless a limit is given
+    pub const DEFAULT_LIMIT: usize = 10;
+
+    /// Creates a query for the best [`HistoryQuery::DEFAULT_LIMIT`] hits of every kind
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            limit: Self::DEFAULT_LIMIT,
+            kind: None,
+            component_id: None,
+            include_text: false,
+        }
+    }
+}
+
+/// An entry of the history index that matched a search
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryHit {
+    /// What the entry was recorded from
+    pub kind: HistoryKind,
+    /// The component the entry belongs to
+    pub component_id: String,
+    /// The tool of a result or doc, or the context of a log message
+    pub name: String,
+    /// The arguments of the call that produced a result
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub arguments: String,
+    /// The part of the text that best matches the query
+    pub snippet: String,
+    /// The full indexed text, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// When the entry was recorded, in RFC 3339 format
+    pub timestamp: String,
+    /// Relevance of the hit, higher is better
+    pub score: f32,
+}
+
+/// An entry to add to the index
+pub(crate) struct HistoryEntry {
+    pub(crate) kind: HistoryKind,
+    pub(crate) component_id: String,
+    pub(crate) name: String,
+    pub(crate) arguments: String,
+    pub(crate) text: String,
+}
+
+impl HistoryEntry {
+    pub(crate) fn result(component_id: &str, tool: &str, arguments: &str, text: &str) -> Self {
+        Self {
+            kind: HistoryKind::Result,
+            component_id: component_id.to_string(),
+            name: tool.to_string(),
+            arguments: arguments.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Creates the doc entry of a tool from its MCP schema
+    pub(crate) fn doc(component_id: &str, schema: &serde_json::Value) -> Self {
+        let name = schema["name"].as_str().unwrap_or_default();
+        let description = schema["description"].as_str().unwrap_or_default();
+        Self {
+            kind: HistoryKind::Doc,
+            component_id: component_id.to_string(),
+            name: name.to_string(),
+            arguments: String::new(),
+            text: format!("{name}\n{description}\n{}", schema["inputSchema"]),
+        }
+    }
+
+    pub(crate) fn log(log: &ComponentLog) -> Self {
+        Self {
+            kind: HistoryKind::Log,
+            component_id: log.component_id.clone(),
+            name: log.context.clone(),
+            arguments: String::new(),
+            text: log.message.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    kind: Field,
+    component_id: Field,
+    name: Field,
+    arguments: Field,
+    text: Field,
+    timestamp: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            kind: builder.add_text_field("kind", STRING | STORED),
+            component_id: builder.add_text_field("component_id", STRING | STORED),
+            name: builder.add_text_field("name", STRING | STORED),
+            arguments: builder.add_text_field("arguments", TEXT | STORED),
+            text: builder.add_text_field("text", TEXT | STORED),
+            timestamp: builder.add_i64_field(
+                "timestamp",
+                NumericOptions::default().set_indexed().set_stored(),
+            ),
+        };
+        (builder.build(), fields)
+    }
+}
+
+/// The tantivy index behind the history. Writes commit right away, so they are blocking.
+pub(crate) struct HistoryIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Option<Mutex<IndexWriter>>,
+    fields: Fields,
+}
+
+impl HistoryIndex {
+    /// Opens or creates the index in `dir`. A read-only index can be searched while another
+    /// process, e.g. a running server, holds the writer.
+    pub(crate) fn open(dir: &Path, writable: bool) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(dir).context("Failed to create history directory")?;
+        let (schema, fields) = Fields::schema();
+        let directory = tantivy::directory::MmapDirectory::open(dir)
+            .context("Failed to open history directory")?;
+        let index =
+            Index::open_or_create(directory, schema).context("Failed to open history index")?;
+        let writer = if writable {
+            let writer = index
+                .writer_with_num_threads(1, WRITER_MEMORY_BUDGET)
+                .context("Failed to open history index for writing, is another server using it?")?;
+            Some(Mutex::new(writer))
+        } else {
+            None
+        };
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to open history index for reading")?;
+        info!(dir = %dir.display(), writable, "Opened history index");
+        Ok(Arc::new(Self {
+            index,
+            reader,
+            writer,
+            fields,
+        }))
+    }
+
+    fn component_docs(&self, component_id: &str) -> Box<dyn Query> {
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Must, self.term_query(self.fields.kind, "doc")),
+            (
+                Occur::Must,
+                self.term_query(self.fields.component_id, component_id),
+            ),
+        ]))
+    }
+
+    fn term_query(&self, field: Field, value: &str) -> Box<dyn Query> {
+        Box::new(TermQuery::new(
+            Term::from_field_text(field, value),
+            IndexRecordOption::Basic,
+        ))
+    }
+
+    /// Adds `entries` and commits them. Doc entries replace the earlier docs of their
+    /// component.
+    pub(crate) fn add(&self, entries: Vec<HistoryEntry>) -> Result<()> {
+        let Some(writer) = &self.writer else {
+            bail!("History index is read-only");
+        };
+        let mut writer = writer.lock().expect("history writer lock poisoned");
+        let timestamp = chrono::Utc::now().timestamp();
+        let mut replaced = Vec::new();
+        for entry in entries {
+            if entry.kind == HistoryKind::Doc && !replaced.contains(&entry.component_id) {
+                writer.delete_query(self.component_docs(&entry.component_id))?;
+                replaced.push(entry.component_id.clone());
+            }
+            let mut text = entry.text;
+            if text.len() > MAX_INDEXED_TEXT {
+                let mut end = MAX_INDEXED_TEXT;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text.truncate(end);
+            }
+            writer.add_document(doc!(
+                self.fields.kind => entry.kind.as_str(),
+                self.fields.component_id => entry.component_id,
+                self.fields.name => entry.name,
+                self.fields.arguments => entry.arguments,
+                self.fields.text => text,
+                self.fields.timestamp => timestamp,
+            ))?;
+        }
+        writer
+            .commit()
+            .context("Failed to commit to history index")?;
+        Ok(())
+    }
+
+    /// Removes the docs of a component that was unloaded. Its results and logs stay searchable.
+    pub(crate) fn remove_docs(&self, component_id: &str) -> Result<()> {
+        let Some(writer) = &self.writer else {
+            bail!("History index is read-only");
+        };
+        let mut writer = writer.lock().expect("history writer lock poisoned");
+        writer.delete_query(self.component_docs(component_id))?;
+        writer
+            .commit()
+            .context("Failed to commit to history index")?;
+        Ok(())
+    }
+
+    /// Removes every entry of a purged component
+    pub(crate) fn remove_component(&self, component_id: &str) -> Result<()> {
+        let Some(writer) = &self.writer else {
+            bail!("History index is read-only");
+        };
+        let mut writer = writer.lock().expect("history writer lock poisoned");
+        writer.delete_term(Term::from_field_text(
+            self.fields.component_id,
+            component_id,
+        ));
+        writer
+            .commit()
+            .context("Failed to commit to history index")?;
+        Ok(())
+    }
+
+    /// The IDs of the components that have entries in the index
+    pub(crate) fn component_ids(&self) -> Result<BTreeSet<String>> {
+        self.reader
+            .reload()
+            .context("Failed to reload history index")?;
+        let searcher = self.reader.searcher();
+        let mut ids = BTreeSet::new();
+        for segment in searcher.segment_readers() {
+            let terms = segment.inverted_index(self.fields.component_id)?;
+            let mut stream = terms.terms().stream()?;
+            while stream.advance() {
+                ids.insert(String::from_utf8_lossy(stream.key()).to_string());
+            }
+        }
+        // Terms of deleted entries linger until segments are merged
+        let mut live = BTreeSet::new();
+        for id in ids {
+            if searcher.search(&*self.term_query(self.fields.component_id, &id), &Count)? > 0 {
+                live.insert(id);
+            }
+        }
+        Ok(live)
+    }
+
+    /// Returns the best matches of `query`, best first
+    pub(crate) fn search(&self, query: &HistoryQuery) -> Result<Vec<HistoryHit>> {
+        if query.query.trim().is_empty() {
+            bail!("Search query cannot be empty");
+        }
+        self.reader
+            .reload()
+            .context("Failed to reload history index")?;
+        let searcher = self.reader.searcher();
+
+        let parser = QueryParser::for_index(
+            &self.index,
+            vec![self.fields.text, self.fields.arguments, self.fields.name],
+        );
+        let text_query = parser
+            .parse_query(&query.query)
+            .with_context(|| format!("Invalid search query: {}", query.query))?;
+        let mut clauses = vec![(Occur::Must, text_query.box_clone())];
+        if let Some(kind) = query.kind {
+            clauses.push((
+                Occur::Must,
+                self.term_query(self.fields.kind, kind.as_str()),
+            ));
+        }
+        if let Some(component_id) = &query.component_id {
+            clauses.push((
+                Occur::Must,
+                self.term_query(self.fields.component_id, component_id),
+            ));
+        }
+        let full_query = BooleanQuery::new(clauses);
+
+        let mut snippets = SnippetGenerator::create(&searcher, &*text_query, self.fields.text)?;
+        snippets.set_max_num_chars(SNIPPET_MAX_CHARS);
+
+        let top_docs = searcher.search(&full_query, &TopDocs::with_limit(query.limit.max(1)))?;
+        let mut hits = Vec::with_capacity(top_docs.len());
+        for (score, address) in top_docs {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let get = |field| {
+                doc.get_first(field)
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let text = get(self.fields.text);
+            let snippet = snippets.snippet(&text);
+            let snippet = if snippet.is_empty() {
+                text.chars().take(SNIPPET_MAX_CHARS).collect()
+            } else {
+                snippet.fragment().to_string()
+            };
+            let timestamp = doc
+                .get_first(self.fields.timestamp)
+                .and_then(|value| value.as_i64())
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default();
+            let kind = match get(self.fields.kind).parse() {
+                Ok(kind) => kind,
+                Err(e) => {
+                    warn!(error = %e, "Skipping history entry with an unknown kind");
+                    continue;
+                }
+            };
+            hits.push(HistoryHit {
+                kind,
+                component_id: get(self.fields.component_id),
+                name: get(self.fields.name),
+                arguments: get(self.fields.arguments),
+                snippet,
+                text: query.include_text.then_some(text),
+                timestamp,
+                score,
+            });
+        }
+        Ok(hits)
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Indexes successful tool results, the docs of loaded components and component logs so
+    /// that [`crate::LifecycleManager::search_history`] can find them. The index lives under
+    /// `<plugin_dir>/history` and only one lifecycle manager at a time can write to it.
+    pub async fn with_history_index(mut self) -> Result<Self> {
+        let dir = self.plugin_dir.join(HISTORY_DIR);
+        let history = tokio::task::spawn_blocking(move || HistoryIndex::open(&dir, true)).await??;
+        self.history = Some(history.clone());
+
+        let mut docs = Vec::new();
+        for component_id in self.list_components().await {
+            docs.extend(self.component_docs(&component_id).await);
+        }
+        self.record_history(docs).await;
+
+        let mut logs = self.subscribe_component_logs();
+        tokio::spawn(async move {
+            loop {
+                let log = match logs.recv().await {
+                    Ok(log) => log,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(
+                            skipped,
+                            "History indexer fell behind, log messages were not indexed"
+                        );
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let history = history.clone();
+                let entry = HistoryEntry::log(&log);
+                let result = tokio::task::spawn_blocking(move || history.add(vec![entry])).await;
+                if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+                    warn!(error = %e, "Failed to index component log message");
+                }
+            }
+        });
+        Ok(self)
+    }
+
+    /// Opens the history index written by another lifecycle manager, e.g. a running server,
+    /// for [`crate::LifecycleManager::search_history`] without indexing anything
+    pub fn with_history_reader(mut self) -> Result<Self> {
+        self.history = Some(HistoryIndex::open(
+            &self.plugin_dir.join(HISTORY_DIR),
+            false,
+        )?);
+        Ok(self)
+    }
+
+    /// Searches the indexed results, docs and logs. Fails unless the index was enabled with
+    /// [`crate::LifecycleManager::with_history_index`] or
+    /// [`crate::LifecycleManager::with_history_reader`].
+    pub async fn search_history(&self, query: HistoryQuery) -> Result<Vec<HistoryHit>> {
+        let Some(history) = self.history.clone() else {
+            bail!("History indexing is not enabled, start the server with --index-history");
+        };
+        tokio::task::spawn_blocking(move || history.search(&query)).await?
+    }
+
+    /// The doc entries of the tools of `component_id`
+    pub(crate) async fn component_docs(&self, component_id: &str) -> Vec<HistoryEntry> {
+        self.registry
+            .read()
+            .await
+            .component_tool_schemas(component_id)
+            .map(|schema| HistoryEntry::doc(component_id, schema))
+            .collect()
+    }
+
+    /// Adds `entries` to the history index, if it is enabled. Failures are only logged.
+    pub(crate) async fn record_history(&self, entries: Vec<HistoryEntry>) {
+        let Some(history) = self.history.clone() else {
+            return;
+        };
+        if entries.is_empty() {
+            return;
+        }
+        let result = tokio::task::spawn_blocking(move || history.add(entries)).await;
+        if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+            warn!(error = %e, "Failed to update history index");
+        }
+    }
+
+    /// Drops every entry of a purged component from the history index, if it is enabled
+    pub(crate) async fn forget_history(&self, component_id: &str) {
+        let Some(history) = self.history.clone() else {
+            return;
+        };
+        let component_id = component_id.to_string();
+        let result =
+            tokio::task::spawn_blocking(move || history.remove_component(&component_id)).await;
+        if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+            warn!(error = %e, "Failed to update history index");
+        }
+    }
+
+    /// The IDs of the components with entries in the history index, if it is enabled
+    pub(crate) async fn history_component_ids(&self) -> Option<Result<BTreeSet<String>>> {
+        let history = self.history.clone()?;
+        Some(
+            tokio::task::spawn_blocking(move || history.component_ids())
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|r| r),
+        )
+    }
+
+    /// Drops the docs of an unloaded component from the history index, if it is enabled
+    pub(crate) async fn forget_history_docs(&self, component_id: &str) {
+        let Some(history) = self.history.clone() else {
+            return;
+        };
+        let component_id = component_id.to_string();
+        let result = tokio::task::spawn_blocking(move || history.remove_docs(&component_id)).await;
+        if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+            warn!(error = %e, "Failed to update history index");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::LogLevel;
+
+    fn hit_names(hits: &[HistoryHit]) -> Vec<(HistoryKind, &str)> {
+        hits.iter()
+            .map(|hit| (hit.kind, hit.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_index() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let history = HistoryIndex::open(tempdir.path(), true)?;
+
+        history.add(vec![
+            HistoryEntry::result(
+                "fetch-rs",
+                "fetch",
+                r#"{"url":"https://example.com/weather"}"#,
+                "Berlin: sunny, 21 degrees",
+            ),
+            HistoryEntry::doc(
+                "fetch-rs",
+                &serde_json::json!({
+                    "name": "fetch",
+                    "description": "Fetch a web page",
+                    "inputSchema": {"type": "object"},
+                }),
+            ),
+            HistoryEntry::log(&ComponentLog {
+                component_id: "time-server".to_string(),
+                level: LogLevel::Info,
+                context: "stdout".to_string(),
+                message: "sunny day in Berlin".to_string(),
+            }),
+        ])?;
+
+        let hits = history.search(&HistoryQuery::new("berlin"))?;
+        assert_eq!(hits.len(), 2);
+        let result = hits
+            .iter()
+            .find(|hit| hit.kind == HistoryKind::Result)
+            .unwrap();
+        assert_eq!(result.component_id, "fetch-rs");
+        assert_eq!(result.arguments, r#"{"url":"https://example.com/weather"}"#);
+        assert!(result.snippet.contains("Berlin"), "{}", result.snippet);
+        assert_eq!(result.text, None);
+
+        // Arguments are searchable too
+        let hits = history.search(&HistoryQuery::new("example.com"))?;
+        assert_eq!(hit_names(&hits), vec![(HistoryKind::Result, "fetch")]);
+
+        let hits = history.search(&HistoryQuery {
+            kind: Some(HistoryKind::Log),
+            include_text: true,
+            ..HistoryQuery::new("sunny")
+        })?;
+        assert_eq!(hit_names(&hits), vec![(HistoryKind::Log, "stdout")]);
+        assert_eq!(hits[0].text.as_deref(), Some("sunny day in Berlin"));
+
+        let hits = history.search(&HistoryQuery {
+            component_id: Some("fetch-rs".to_string()),
+            ..HistoryQuery::new("fetch")
+        })?;
+        assert_eq!(hits.len(), 2);
+
+        // Reloading a component replaces its docs, unloading it drops them
+        history.add(vec![HistoryEntry::doc(
+            "fetch-rs",
+            &serde_json::json!({"name": "fetch", "description": "Download a page"}),
+        )])?;
+        let docs = HistoryQuery {
+            kind: Some(HistoryKind::Doc),
+            ..HistoryQuery::new("fetch")
+        };
+        let hits = history.search(&docs)?;
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].snippet.contains("Download"), "{}", hits[0].snippet);
+        history.remove_docs("fetch-rs")?;
+        assert!(history.search(&docs)?.is_empty());
+
+        assert!(history.component_ids()?.contains("fetch-rs"));
+        history.remove_component("fetch-rs")?;
+        assert!(!history.component_ids()?.contains("fetch-rs"));
+        assert!(history
+            .search(&HistoryQuery {
+                component_id: Some("fetch-rs".to_string()),
+                ..HistoryQuery::new("fetch")
+            })?
+            .is_empty());
+
+        assert!(history.search(&HistoryQuery::new(" ")).is_err());
+        assert!(history.search(&HistoryQuery::new("text:(")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_manager_indexes_history() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func (export "run") (result u32) (canon lift (core func $i "run"))))"#,
+        )?;
+        let manager = crate::LifecycleManager::new(&tempdir)
+            .await?
+            .with_history_index()
+            .await?;
+
+        manager
+            .execute_component_call("answer", "run", "{}")
+            .await?;
+        let hits = manager
+            .search_history(HistoryQuery {
+                kind: Some(HistoryKind::Result),
+                ..HistoryQuery::new("42")
+            })
+            .await?;
+        assert_eq!(hit_names(&hits), vec![(HistoryKind::Result, "run")]);
+        assert_eq!(hits[0].component_id, "answer");
+
+        let docs = HistoryQuery {
+            kind: Some(HistoryKind::Doc),
+            ..HistoryQuery::new("run")
+        };
+        assert_eq!(manager.search_history(docs.clone()).await?.len(), 1);
+        manager.unload_component("answer").await?;
+        assert!(manager.search_history(docs).await?.is_empty());
+
+        // Without the index, searching fails
+        let manager = crate::LifecycleManager::new(tempfile::tempdir()?.path()).await?;
+        assert!(manager
+            .search_history(HistoryQuery::new("42"))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_index_read_only() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let writer = HistoryIndex::open(tempdir.path(), true)?;
+        writer.add(vec![HistoryEntry::result(
+            "c",
+            "tool",
+            "{}",
+            "indexed text",
+        )])?;
+
+        // A second writer is refused, but the index can still be searched
+        assert!(HistoryIndex::open(tempdir.path(), true).is_err());
+        let reader = HistoryIndex::open(tempdir.path(), false)?;
+        assert_eq!(reader.search(&HistoryQuery::new("indexed"))?.len(), 1);
+        assert!(reader.add(Vec::new()).is_err());
+        Ok(())
+    }
+}
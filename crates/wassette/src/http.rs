@@ -2,16 +2,35 @@
 // Licensed under the MIT license.
 
 use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::Result;
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use policy::HttpLimits;
 use tracing::{debug, warn};
 use url::Url;
-use wasmtime::component::Resource;
-use wasmtime_wasi::p2::{IoView, WasiView};
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::p2::bindings::sockets::ip_name_lookup::{
+    self, HostResolveAddressStream, ResolveAddressStream,
+};
+use wasmtime_wasi::p2::bindings::sockets::network::{self, ErrorCode, Network};
+use wasmtime_wasi::p2::{DynPollable, IoImpl, IoView, SocketError, WasiImpl, WasiView};
 use wasmtime_wasi_http::bindings::http::types;
-use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::body::HyperIncomingBody;
+use wasmtime_wasi_http::types::{
+    HostFutureIncomingResponse, IncomingResponse, OutgoingRequestConfig,
+};
 use wasmtime_wasi_http::{HttpResult, WasiHttpView};
 
+use crate::http_cache::{CacheLookup, HttpCache};
+use crate::network_log::{NetworkLog, RequestRecorder, NETWORK_POLICY_DENIAL};
+use crate::proxy::{self, ProxyConfig};
+use crate::tls::{self, TlsConfig};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct AllowedHost {
     scheme: Option<String>,
@@ -23,12 +42,12 @@ impl AllowedHost {
         if let Ok(url) = Url::parse(host_str) {
             Ok(AllowedHost {
                 scheme: Some(url.scheme().to_string()),
-                host: url.host_str().unwrap_or("").to_string(),
+                host: url.host_str().and_then(canonical_host).unwrap_or_default(),
             })
         } else if let Ok(url) = Url::parse(&format!("http://{host_str}")) {
             Ok(AllowedHost {
                 scheme: None,
-                host: url.host_str().unwrap_or("").to_string(),
+                host: url.host_str().and_then(canonical_host).unwrap_or_default(),
             })
         } else {
             Err(anyhow::anyhow!("Invalid host format: {}", host_str))
@@ -47,14 +66,221 @@ impl AllowedHost {
     }
 }
 
+/// Limits applied to every outgoing HTTP request of a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RequestLimits {
+    /// Largest response body accepted, in bytes
+    pub(crate) max_response_size: u64,
+    /// Time allowed to establish a connection
+    pub(crate) connect_timeout: Duration,
+    /// Time allowed to wait for the response headers and between chunks of the body
+    pub(crate) read_timeout: Duration,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_response_size: 64 * 1024 * 1024,
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Returns these limits with the ones set in `limits` replacing them
+    pub(crate) fn with_overrides(mut self, limits: &HttpLimits) -> Result<Self> {
+        if let Some(size) = &limits.max_response_size {
+            self.max_response_size = size.to_bytes()?;
+        }
+        if let Some(timeout) = &limits.connect_timeout {
+            self.connect_timeout = timeout.to_duration()?;
+        }
+        if let Some(timeout) = &limits.read_timeout {
+            self.read_timeout = timeout.to_duration()?;
+        }
+        Ok(self)
+    }
+
+    /// Lowers the timeouts the guest asked for to the limits
+    fn clamp(&self, config: &mut OutgoingRequestConfig) {
+        config.connect_timeout = config.connect_timeout.min(self.connect_timeout);
+        config.first_byte_timeout = config.first_byte_timeout.min(self.read_timeout);
+        config.between_bytes_timeout = config.between_bytes_timeout.min(self.read_timeout);
+    }
+
+    /// Makes the body of `response` fail once it grows past the size limit, and records the
+    /// request with `recorder` once the body was read or dropped
+    fn limit_response(
+        &self,
+        response: HostFutureIncomingResponse,
+        recorder: Option<RequestRecorder>,
+    ) -> HostFutureIncomingResponse {
+        let max = self.max_response_size;
+        match response {
+            HostFutureIncomingResponse::Ready(Ok(response)) => {
+                HostFutureIncomingResponse::ready(Ok(limit_body(response, max, recorder)))
+            }
+            HostFutureIncomingResponse::Pending(handle) => {
+                HostFutureIncomingResponse::pending(wasmtime_wasi::runtime::spawn(async move {
+                    handle
+                        .await
+                        .map(|response| limit_body(response, max, recorder))
+                }))
+            }
+            response => response,
+        }
+    }
+}
+
+/// `code` as it is recorded in the network log, e.g. `ConnectionRefused`
+fn error_text(code: &types::ErrorCode) -> String {
+    let text = format!("{code:?}");
+    text.strip_prefix("ErrorCode::")
+        .map(str::to_string)
+        .unwrap_or(text)
+}
+
+/// Rejects responses that announce a body over `max` bytes and wraps the body of the others
+fn limit_body(
+    response: Result<IncomingResponse, types::ErrorCode>,
+    max: u64,
+    recorder: Option<RequestRecorder>,
+) -> Result<IncomingResponse, types::ErrorCode> {
+    let response = match response {
+        Ok(response) => response,
+        Err(code) => {
+            if let Some(recorder) = recorder {
+                recorder.finish(None, 0, Some(error_text(&code)));
+            }
+            return Err(code);
+        }
+    };
+    let status = response.resp.status().as_u16();
+
+    let content_length = response
+        .resp
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if let Some(length) = content_length.filter(|length| *length > max) {
+        warn!(
+            content_length = length,
+            max_response_size = max,
+            "HTTP response too large"
+        );
+        let code = types::ErrorCode::HttpResponseBodySize(Some(length));
+        if let Some(recorder) = recorder {
+            recorder.finish(Some(status), 0, Some(error_text(&code)));
+        }
+        return Err(code);
+    }
+
+    Ok(IncomingResponse {
+        resp: response.resp.map(|body| {
+            HyperIncomingBody::new(LimitedBody {
+                inner: body,
+                max,
+                received: 0,
+                status,
+                error: None,
+                recorder,
+            })
+        }),
+        ..response
+    })
+}
+
+/// A response body that fails once more than `max` bytes were received. The request is recorded
+/// when the body is dropped.
+struct LimitedBody {
+    inner: HyperIncomingBody,
+    max: u64,
+    received: u64,
+    status: u16,
+    error: Option<String>,
+    recorder: Option<RequestRecorder>,
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = types::ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    self.received += data.len() as u64;
+                    if self.received > self.max {
+                        warn!(
+                            received = self.received,
+                            max_response_size = self.max,
+                            "HTTP response too large"
+                        );
+                        let code = types::ErrorCode::HttpResponseBodySize(Some(self.received));
+                        self.error = Some(error_text(&code));
+                        return Poll::Ready(Some(Err(code)));
+                    }
+                }
+            }
+            Some(Err(code)) => self.error = Some(error_text(code)),
+            None => {}
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LimitedBody {
+    fn drop(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish(Some(self.status), self.received, self.error.take());
+        }
+    }
+}
+
 /// WassetteWasiState is a wrapper around a WASI state that enforces network policies by filtering
-/// outgoing HTTP requests based on a list of allowed hosts from the component's policy document.
+/// outgoing HTTP requests based on a list of allowed hosts from the component's policy document,
+/// and by limiting `wasi:sockets/ip-name-lookup` to an allowlist of DNS names.
 pub struct WassetteWasiState<T> {
     /// The underlying WASI state
     pub inner: T,
 
     /// Set of allowed hosts for network requests (extracted from policy document)
     allowed_hosts: HashSet<AllowedHost>,
+
+    /// Set of names the component may resolve through `wasi:sockets/ip-name-lookup`
+    allowed_dns_names: HashSet<String>,
+
+    /// Proxies that allowed HTTP requests are sent through
+    proxy: Option<Arc<ProxyConfig>>,
+
+    /// Per-host TLS settings of allowed HTTPS requests
+    tls: Option<Arc<TlsConfig>>,
+
+    /// Size and time limits of allowed HTTP requests
+    limits: RequestLimits,
+
+    /// Cache of responses to allowed HTTP requests, shared with other components
+    cache: Option<HttpCache>,
+
+    /// Where HTTP requests are recorded, with the ID of the component they are attributed to
+    network_log: Option<(NetworkLog, String)>,
+
+    /// Whether allowed HTTP requests are answered from the offline demo's canned responses
+    demo_fixtures: bool,
 }
 
 impl<T> WassetteWasiState<T> {
@@ -77,22 +303,82 @@ impl<T> WassetteWasiState<T> {
         Ok(Self {
             inner,
             allowed_hosts: parsed_hosts,
+            allowed_dns_names: HashSet::new(),
+            proxy: None,
+            tls: None,
+            limits: RequestLimits::default(),
+            cache: None,
+            network_log: None,
+            demo_fixtures: false,
         })
     }
 
+    /// Set the names the component is allowed to resolve. Names are matched case-insensitively
+    /// and any trailing dot is ignored. An empty set denies every lookup.
+    pub fn with_allowed_dns_names(mut self, allowed_dns_names: HashSet<String>) -> Self {
+        self.allowed_dns_names = allowed_dns_names
+            .iter()
+            .map(|name| normalize_dns_name(name))
+            .collect();
+        self
+    }
+
+    /// Send allowed HTTP requests through the proxies in `proxy`, if given
+    pub fn with_proxy(mut self, proxy: Option<Arc<ProxyConfig>>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Negotiate TLS for allowed HTTPS requests with the per-host settings in `tls`, if given
+    pub fn with_tls(mut self, tls: Option<Arc<TlsConfig>>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Apply `limits` to allowed HTTP requests
+    pub(crate) fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Serve allowed HTTP requests from `cache` when possible, if given
+    pub(crate) fn with_cache(mut self, cache: Option<HttpCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Answer allowed HTTP requests from the offline demo's canned responses instead of sending
+    /// them
+    pub(crate) fn with_demo_fixtures(mut self, demo_fixtures: bool) -> Self {
+        self.demo_fixtures = demo_fixtures;
+        self
+    }
+
+    /// Record HTTP requests in `log`, attributed to `component_id`
+    pub(crate) fn with_network_log(mut self, log: NetworkLog, component_id: &str) -> Self {
+        self.network_log = Some((log, component_id.to_string()));
+        self
+    }
+
+    /// Check if a name lookup is allowed by the policy. IP literals never reach a resolver,
+    /// so they are always allowed.
+    fn is_dns_name_allowed(&self, name: &str) -> bool {
+        if name.parse::<std::net::IpAddr>().is_ok() {
+            return true;
+        }
+        self.allowed_dns_names.contains(&normalize_dns_name(name))
+    }
+
     /// Check if a host is allowed by the policy
     fn is_host_allowed(&self, uri: &hyper::Uri) -> bool {
-        let request_host = if let Some(host) = uri.host() {
-            host.to_string()
-        } else {
+        let Some(request_host) = uri.host().and_then(canonical_host) else {
             return false;
         };
 
         let request_scheme = uri.scheme().map(|s| s.as_str());
 
-        let req = request_host.to_ascii_lowercase();
         for allowed_host in &self.allowed_hosts {
-            if allowed_host.matches(&req, request_scheme) {
+            if allowed_host.matches(&request_host, request_scheme) {
                 return true;
             }
         }
@@ -101,6 +387,135 @@ impl<T> WassetteWasiState<T> {
     }
 }
 
+/// The hosts of `hosts` that `other` allows as well. A host allowed for any scheme in one set
+/// and for a single scheme in the other is allowed for that scheme only.
+pub(crate) fn intersect_allowed_hosts(
+    hosts: &HashSet<String>,
+    other: &HashSet<String>,
+) -> HashSet<String> {
+    let other: Vec<(&String, AllowedHost)> = other
+        .iter()
+        .filter_map(|host| Some((host, AllowedHost::from_str(host).ok()?)))
+        .collect();
+    let mut shared = HashSet::new();
+    for host in hosts {
+        let Ok(allowed) = AllowedHost::from_str(host) else {
+            continue;
+        };
+        for (other_host, other_allowed) in &other {
+            if allowed.host != other_allowed.host {
+                continue;
+            }
+            match (&allowed.scheme, &other_allowed.scheme) {
+                (Some(scheme), Some(other_scheme)) if scheme != other_scheme => {}
+                (None, Some(_)) => {
+                    shared.insert((*other_host).clone());
+                }
+                _ => {
+                    shared.insert(host.clone());
+                }
+            }
+        }
+    }
+    shared
+}
+
+fn normalize_dns_name(name: &str) -> String {
+    canonical_host(name).unwrap_or_else(|| name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// The form hosts are matched against the network policy in: lowercase, with internationalized
+/// names in their `xn--` punycode form, without a trailing dot, and with IP addresses written the
+/// shortest way, IPv6 ones in brackets. Returns `None` if `host` is not a valid host.
+pub(crate) fn canonical_host(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    match url::Host::parse(host).ok()? {
+        url::Host::Domain(domain) => Some(domain),
+        url::Host::Ipv4(ip) => Some(ip.to_string()),
+        url::Host::Ipv6(ip) => Some(format!("[{ip}]")),
+    }
+}
+
+/// Replaces the default `wasi:sockets/ip-name-lookup` implementation with one that only
+/// resolves names in the component's DNS allowlist.
+pub(crate) fn add_ip_name_lookup_to_linker<T: WasiView + 'static>(
+    linker: &mut Linker<WassetteWasiState<T>>,
+) -> Result<()> {
+    fn get_host<T>(state: &mut WassetteWasiState<T>) -> &mut WassetteWasiState<T> {
+        state
+    }
+
+    linker.allow_shadowing(true);
+    ip_name_lookup::add_to_linker_get_host(linker, get_host)?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+impl<T: WasiView> ip_name_lookup::Host for WassetteWasiState<T> {
+    fn resolve_addresses(
+        &mut self,
+        network: Resource<Network>,
+        name: String,
+    ) -> Result<Resource<ResolveAddressStream>, SocketError> {
+        if !self.is_dns_name_allowed(&name) {
+            warn!(
+                name = %name,
+                allowed_dns_names = ?self.allowed_dns_names,
+                "DNS lookup blocked by network policy"
+            );
+            return Err(ErrorCode::PermanentResolverFailure.into());
+        }
+
+        debug!(name = %name, "DNS lookup allowed by network policy");
+
+        ip_name_lookup::Host::resolve_addresses(
+            &mut WasiImpl(IoImpl(&mut self.inner)),
+            network,
+            name,
+        )
+    }
+}
+
+impl<T: WasiView> HostResolveAddressStream for WassetteWasiState<T> {
+    fn resolve_next_address(
+        &mut self,
+        resource: Resource<ResolveAddressStream>,
+    ) -> Result<Option<ip_name_lookup::IpAddress>, SocketError> {
+        WasiImpl(IoImpl(&mut self.inner)).resolve_next_address(resource)
+    }
+
+    fn subscribe(
+        &mut self,
+        resource: Resource<ResolveAddressStream>,
+    ) -> wasmtime::Result<Resource<DynPollable>> {
+        HostResolveAddressStream::subscribe(&mut WasiImpl(IoImpl(&mut self.inner)), resource)
+    }
+
+    fn drop(&mut self, resource: Resource<ResolveAddressStream>) -> wasmtime::Result<()> {
+        HostResolveAddressStream::drop(&mut WasiImpl(IoImpl(&mut self.inner)), resource)
+    }
+}
+
+// The ip-name-lookup host traits require the network traits, which are forwarded unchanged.
+impl<T: WasiView> network::Host for WassetteWasiState<T> {
+    fn convert_error_code(&mut self, error: SocketError) -> wasmtime::Result<ErrorCode> {
+        network::Host::convert_error_code(&mut WasiImpl(IoImpl(&mut self.inner)), error)
+    }
+
+    fn network_error_code(
+        &mut self,
+        err: Resource<network::Error>,
+    ) -> wasmtime::Result<Option<ErrorCode>> {
+        network::Host::network_error_code(&mut WasiImpl(IoImpl(&mut self.inner)), err)
+    }
+}
+
+impl<T: WasiView> network::HostNetwork for WassetteWasiState<T> {
+    fn drop(&mut self, this: Resource<Network>) -> wasmtime::Result<()> {
+        network::HostNetwork::drop(&mut WasiImpl(IoImpl(&mut self.inner)), this)
+    }
+}
+
 impl<T: IoView> IoView for WassetteWasiState<T> {
     fn table(&mut self) -> &mut wasmtime_wasi::ResourceTable {
         self.inner.table()
@@ -129,13 +544,20 @@ impl<T: WasiHttpView> WasiHttpView for WassetteWasiState<T> {
 
     fn send_request(
         &mut self,
-        request: hyper::Request<wasmtime_wasi_http::body::HyperOutgoingBody>,
+        mut request: hyper::Request<wasmtime_wasi_http::body::HyperOutgoingBody>,
         config: OutgoingRequestConfig,
     ) -> HttpResult<HostFutureIncomingResponse> {
+        let mut recorder = self
+            .network_log
+            .as_ref()
+            .map(|(log, component_id)| RequestRecorder::start(log.clone(), component_id, &request));
         let uri = request.uri();
 
         if uri.host().is_none() {
             warn!("HTTP request missing host, blocking request");
+            if let Some(recorder) = recorder {
+                recorder.finish(None, 0, Some("missing host".to_string()));
+            }
             return Err(types::ErrorCode::HttpRequestUriInvalid.into());
         }
 
@@ -145,12 +567,74 @@ impl<T: WasiHttpView> WasiHttpView for WassetteWasiState<T> {
                 allowed_hosts = ?self.allowed_hosts,
                 "HTTP request blocked by network policy"
             );
+            if let Some(recorder) = recorder {
+                recorder.finish(None, 0, Some(NETWORK_POLICY_DENIAL.to_string()));
+            }
             return Err(types::ErrorCode::HttpRequestDenied.into());
         }
 
         debug!(uri = %uri, "HTTP request allowed by network policy");
 
-        self.inner.send_request(request, config)
+        let mut config = config;
+        self.limits.clamp(&mut config);
+        if self.demo_fixtures {
+            let response = crate::demo::fixture_response(uri, config.between_bytes_timeout);
+            return Ok(self.limits.limit_response(
+                HostFutureIncomingResponse::ready(Ok(Ok(response))),
+                recorder,
+            ));
+        }
+        let pending = match self
+            .cache
+            .as_ref()
+            .map(|cache| cache.lookup(&mut request, config.between_bytes_timeout))
+        {
+            Some(CacheLookup::Hit(response)) => {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.cached();
+                }
+                return Ok(self.limits.limit_response(
+                    HostFutureIncomingResponse::ready(Ok(Ok(response))),
+                    recorder,
+                ));
+            }
+            Some(CacheLookup::Miss(pending)) => Some(pending),
+            Some(CacheLookup::Bypass) | None => None,
+        };
+        let uri = request.uri();
+        let proxy = self
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.proxy_for(uri, config.use_tls))
+            .cloned();
+        let tls = self.tls.clone();
+        let response = if let Some(proxy) = proxy {
+            HostFutureIncomingResponse::pending(wasmtime_wasi::runtime::spawn(async move {
+                Ok(proxy::send_request(request, config, proxy, tls.unwrap_or_default()).await)
+            }))
+        } else if let Some(tls) =
+            tls.filter(|tls| config.use_tls && uri.host().is_some_and(|host| tls.has_host(host)))
+        {
+            HostFutureIncomingResponse::pending(wasmtime_wasi::runtime::spawn(async move {
+                Ok(tls::send_request(request, config, tls).await)
+            }))
+        } else {
+            match self.inner.send_request(request, config) {
+                Ok(response) => response,
+                Err(e) => {
+                    if let Some(recorder) = recorder {
+                        recorder.finish(None, 0, Some(e.to_string()));
+                    }
+                    return Err(e);
+                }
+            }
+        };
+        let response = match pending {
+            Some(pending) => pending.complete(response),
+            None => response,
+        };
+
+        Ok(self.limits.limit_response(response, recorder))
     }
 
     fn is_forbidden_header(&mut self, name: &hyper::header::HeaderName) -> bool {
@@ -210,6 +694,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_intersect_allowed_hosts() {
+        let hosts = HashSet::from([
+            "api.example.com".to_string(),
+            "https://cdn.example.com".to_string(),
+            "http://files.example.com".to_string(),
+        ]);
+        let other = HashSet::from([
+            "https://api.example.com".to_string(),
+            "CDN.example.com".to_string(),
+            "https://files.example.com".to_string(),
+        ]);
+        assert_eq!(
+            intersect_allowed_hosts(&hosts, &other),
+            HashSet::from([
+                "https://api.example.com".to_string(),
+                "https://cdn.example.com".to_string(),
+            ])
+        );
+    }
+
     #[test]
     fn test_host_allowed_exact_match() {
         let mut allowed_hosts = HashSet::new();
@@ -291,6 +796,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dns_name_allowed() {
+        let state = WassetteWasiState::new(create_mock_wasi_state(), HashSet::new())
+            .unwrap()
+            .with_allowed_dns_names(HashSet::from(["API.example.com".to_string()]));
+
+        assert!(state.is_dns_name_allowed("api.example.com"));
+        assert!(state.is_dns_name_allowed("API.EXAMPLE.COM."));
+        assert!(!state.is_dns_name_allowed("other.example.com"));
+        assert!(!state.is_dns_name_allowed("exfil.attacker.com"));
+
+        // IP literals are not resolved, so they do not need an allowlist entry
+        assert!(state.is_dns_name_allowed("127.0.0.1"));
+        assert!(state.is_dns_name_allowed("::1"));
+    }
+
+    #[test]
+    fn test_canonical_host() {
+        assert_eq!(
+            canonical_host("API.Example.COM.").as_deref(),
+            Some("api.example.com")
+        );
+        assert_eq!(
+            canonical_host("Bücher.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(
+            canonical_host("XN--BCHER-KVA.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(canonical_host("[0:0::1]").as_deref(), Some("[::1]"));
+        assert_eq!(canonical_host("127.0.0.1").as_deref(), Some("127.0.0.1"));
+        assert_eq!(canonical_host(""), None);
+        assert_eq!(canonical_host("exa mple.com"), None);
+    }
+
+    #[test]
+    fn test_host_matching_is_canonical() {
+        let allowed_hosts = HashSet::from([
+            "https://API.Example.COM.:443".to_string(),
+            "bücher.example".to_string(),
+        ]);
+        let state = WassetteWasiState::new(create_mock_wasi_state(), allowed_hosts)
+            .unwrap()
+            .with_allowed_dns_names(HashSet::from(["Bücher.Example.".to_string()]));
+
+        for uri in [
+            "https://api.example.com/",
+            "https://API.EXAMPLE.COM./",
+            "https://api.example.com:443/",
+            "http://xn--bcher-kva.example/",
+            "http://XN--BCHER-KVA.EXAMPLE./",
+        ] {
+            assert!(state.is_host_allowed(&uri.parse().unwrap()), "{uri}");
+        }
+        for uri in [
+            "http://api.example.com/",
+            "https://api.example.com.evil.com/",
+            "https://xn--api-example-com.evil.com/",
+            "http://bucher.example/",
+        ] {
+            assert!(!state.is_host_allowed(&uri.parse().unwrap()), "{uri}");
+        }
+
+        assert!(state.is_dns_name_allowed("xn--bcher-kva.example"));
+        assert!(state.is_dns_name_allowed("XN--BCHER-KVA.example."));
+        assert!(!state.is_dns_name_allowed("bucher.example"));
+    }
+
+    #[test]
+    fn test_dns_lookups_denied_by_default() {
+        let mut allowed_hosts = HashSet::new();
+        allowed_hosts.insert("api.example.com".to_string());
+
+        let state = WassetteWasiState::new(create_mock_wasi_state(), allowed_hosts).unwrap();
+
+        // The HTTP allowlist does not implicitly open up DNS resolution
+        assert!(!state.is_dns_name_allowed("api.example.com"));
+    }
+
     #[test]
     fn test_host_matching_is_case_insensitive() {
         let mut allowed_hosts = HashSet::new();
@@ -304,4 +889,154 @@ mod tests {
         assert!(state.is_host_allowed(&uri1));
         assert!(state.is_host_allowed(&uri2));
     }
+
+    fn response(body: &'static [u8], content_length: Option<usize>) -> IncomingResponse {
+        use http_body_util::BodyExt;
+
+        let mut resp = hyper::Response::builder();
+        if let Some(length) = content_length {
+            resp = resp.header(hyper::header::CONTENT_LENGTH, length);
+        }
+        IncomingResponse {
+            resp: resp
+                .body(
+                    http_body_util::Full::new(Bytes::from_static(body))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap(),
+            worker: None,
+            between_bytes_timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_request_limits_overrides() {
+        let limits = RequestLimits::default()
+            .with_overrides(&HttpLimits {
+                max_response_size: Some(policy::MemoryLimit::String("1Mi".to_string())),
+                read_timeout: Some(policy::TimeoutLimit::String("5s".to_string())),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(limits.max_response_size, 1024 * 1024);
+        assert_eq!(
+            limits.connect_timeout,
+            RequestLimits::default().connect_timeout
+        );
+        assert_eq!(limits.read_timeout, Duration::from_secs(5));
+
+        let mut config = OutgoingRequestConfig {
+            use_tls: false,
+            connect_timeout: Duration::from_secs(1),
+            first_byte_timeout: Duration::from_secs(600),
+            between_bytes_timeout: Duration::from_secs(600),
+        };
+        limits.clamp(&mut config);
+        assert_eq!(config.connect_timeout, Duration::from_secs(1));
+        assert_eq!(config.first_byte_timeout, Duration::from_secs(5));
+        assert_eq!(config.between_bytes_timeout, Duration::from_secs(5));
+
+        assert!(RequestLimits::default()
+            .with_overrides(&HttpLimits {
+                connect_timeout: Some(policy::TimeoutLimit::Number(0)),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_response_size_limit() {
+        use http_body_util::BodyExt;
+
+        let limits = RequestLimits {
+            max_response_size: 4,
+            ..Default::default()
+        };
+        let limited = |response| {
+            limits
+                .limit_response(HostFutureIncomingResponse::ready(Ok(Ok(response))), None)
+                .unwrap_ready()
+                .unwrap()
+        };
+
+        let body = limited(response(b"tiny", Some(4)))
+            .unwrap()
+            .resp
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "tiny");
+
+        // Announced sizes are rejected before the body is read
+        assert!(matches!(
+            limited(response(b"too large", Some(9))),
+            Err(types::ErrorCode::HttpResponseBodySize(Some(9)))
+        ));
+
+        // Bodies without a length fail once they grow past the limit
+        let body = limited(response(b"too large", None))
+            .unwrap()
+            .resp
+            .into_body()
+            .collect()
+            .await;
+        assert!(matches!(
+            body,
+            Err(types::ErrorCode::HttpResponseBodySize(Some(9)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_recorded() {
+        use http_body_util::BodyExt;
+
+        let log = NetworkLog::new(
+            crate::events::EventBus::default(),
+            crate::logging::LogBus::default(),
+            Default::default(),
+            Vec::new(),
+        );
+        let limits = RequestLimits {
+            max_response_size: 4,
+            ..Default::default()
+        };
+        let recorder = || {
+            let request = hyper::Request::get("https://api.example.com/items")
+                .body(())
+                .unwrap();
+            Some(RequestRecorder::start(log.clone(), "fetch", &request))
+        };
+
+        let response = limits
+            .limit_response(
+                HostFutureIncomingResponse::ready(Ok(Ok(response(b"tiny", None)))),
+                recorder(),
+            )
+            .unwrap_ready()
+            .unwrap()
+            .unwrap();
+        // The request is recorded once the body is gone
+        assert!(log.recent(None, 10).is_empty());
+        response.resp.into_body().collect().await.unwrap();
+
+        let refused = limits
+            .limit_response(
+                HostFutureIncomingResponse::ready(Ok(Err(types::ErrorCode::ConnectionRefused))),
+                recorder(),
+            )
+            .unwrap_ready()
+            .unwrap();
+        assert!(refused.is_err());
+
+        let recent = log.recent(None, 10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].status, None);
+        assert_eq!(recent[0].error.as_deref(), Some("ConnectionRefused"));
+        assert_eq!(recent[1].status, Some(200));
+        assert_eq!(recent[1].bytes, 4);
+        assert_eq!(recent[1].error, None);
+    }
 }
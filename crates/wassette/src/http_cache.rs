@@ -0,0 +1,656 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Shared cache of outgoing HTTP responses
+//!
+//! Agents often fetch the same page many times in a session. With
+//! [`crate::LifecycleManager::with_http_cache`], the responses to `GET` requests of components are
+//! kept in memory and shared by all components, following the rules of a shared cache: responses
+//! marked `no-store` or `private`, responses that set cookies and requests with credentials are
+//! never cached. A fresh response, by `Cache-Control: max-age`/`s-maxage` or `Expires`, is served
+//! without sending the request. A stale one is revalidated with its `ETag` or `Last-Modified`
+//! validator, and served again if the server answers `304 Not Modified`.
+//!
+//! The cache is consulted after the network policy of the component, so a component only gets
+//! cached responses of hosts it is allowed to reach.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use tracing::debug;
+use wasmtime_wasi_http::bindings::http::types;
+use wasmtime_wasi_http::body::HyperIncomingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, IncomingResponse};
+
+/// Largest response body that is cached, in bytes
+const MAX_ENTRY_SIZE: usize = 8 * 1024 * 1024;
+
+/// Bytes of response bodies kept in the cache before the least recently used ones are evicted
+const CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// Request headers that make a request bypass the cache
+const BYPASS_HEADERS: [HeaderName; 6] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::RANGE,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    header::IF_MATCH,
+];
+
+/// A cached response
+#[derive(Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// Values of the request headers named by the `Vary` header of the response
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    /// When the response was received or last revalidated
+    stored: Instant,
+    /// How long the response is fresh after `stored`
+    fresh_for: Duration,
+    /// Value of [`CacheState::clock`] when the entry was last used
+    last_used: u64,
+}
+
+impl CachedResponse {
+    fn is_fresh(&self) -> bool {
+        self.stored.elapsed() < self.fresh_for
+    }
+
+    fn matches(&self, request: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request.get(name) == value.as_ref())
+    }
+
+    /// The cached response as the component receives it, with an `Age` header
+    fn to_response(&self, between_bytes_timeout: Duration) -> IncomingResponse {
+        let mut resp = hyper::Response::new(
+            http_body_util::Full::new(self.body.clone())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+        resp.headers_mut()
+            .insert(header::AGE, self.stored.elapsed().as_secs().into());
+        IncomingResponse {
+            resp,
+            worker: None,
+            between_bytes_timeout,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CachedResponse>,
+    /// Bytes of the bodies in `entries`
+    size: usize,
+    /// Incremented on every use of an entry, to find the least recently used one
+    clock: u64,
+}
+
+impl CacheState {
+    fn insert(&mut self, key: String, mut entry: CachedResponse) {
+        self.remove(&key);
+        while self.size + entry.body.len() > CACHE_CAPACITY {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.size += entry.body.len();
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.body.len();
+        }
+    }
+}
+
+/// In-memory cache of HTTP responses shared by all components
+#[derive(Clone, Default)]
+pub(crate) struct HttpCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+/// What to do with a request, as decided by [`HttpCache::lookup`]
+pub(crate) enum CacheLookup {
+    /// The request is sent without involving the cache
+    Bypass,
+    /// A fresh response is served from the cache
+    Hit(IncomingResponse),
+    /// The request is sent and its response may be stored, see [`PendingResponse::complete`]
+    Miss(PendingResponse),
+}
+
+/// A request that was not served from the cache. If a stale entry exists, the request carries
+/// its validators.
+pub(crate) struct PendingResponse {
+    cache: HttpCache,
+    key: String,
+    request_headers: HeaderMap,
+    stale: Option<CachedResponse>,
+}
+
+impl HttpCache {
+    /// Looks `request` up in the cache. If a stale entry with validators exists, they are added
+    /// to the request.
+    pub(crate) fn lookup<B>(
+        &self,
+        request: &mut hyper::Request<B>,
+        between_bytes_timeout: Duration,
+    ) -> CacheLookup {
+        if !is_cacheable_request(request) {
+            return CacheLookup::Bypass;
+        }
+        let key = request.uri().to_string();
+        let Ok(mut state) = self.state.lock() else {
+            return CacheLookup::Bypass;
+        };
+        state.clock += 1;
+        let clock = state.clock;
+        let stale = match state.entries.get_mut(&key) {
+            Some(entry) if entry.matches(request.headers()) => {
+                entry.last_used = clock;
+                if entry.is_fresh() {
+                    debug!(uri = %key, "HTTP response served from cache");
+                    return CacheLookup::Hit(entry.to_response(between_bytes_timeout));
+                }
+                Some(entry.clone())
+            }
+            _ => None,
+        };
+        drop(state);
+
+        if let Some(entry) = &stale {
+            if let Some(etag) = entry.headers.get(header::ETAG) {
+                request
+                    .headers_mut()
+                    .insert(header::IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = entry.headers.get(header::LAST_MODIFIED) {
+                request
+                    .headers_mut()
+                    .insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+        CacheLookup::Miss(PendingResponse {
+            cache: self.clone(),
+            key,
+            request_headers: request.headers().clone(),
+            stale,
+        })
+    }
+
+    fn insert(&self, key: String, entry: CachedResponse) {
+        if let Ok(mut state) = self.state.lock() {
+            state.insert(key, entry);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.remove(key);
+        }
+    }
+}
+
+impl PendingResponse {
+    /// Serves the stale entry again if `response` is a `304 Not Modified`, and stores `response`
+    /// once its body was read if it is cacheable
+    pub(crate) fn complete(
+        self,
+        response: HostFutureIncomingResponse,
+    ) -> HostFutureIncomingResponse {
+        match response {
+            HostFutureIncomingResponse::Ready(Ok(response)) => {
+                HostFutureIncomingResponse::ready(Ok(self.handle(response)))
+            }
+            HostFutureIncomingResponse::Pending(handle) => {
+                HostFutureIncomingResponse::pending(wasmtime_wasi::runtime::spawn(async move {
+                    handle.await.map(|response| self.handle(response))
+                }))
+            }
+            response => response,
+        }
+    }
+
+    fn handle(
+        self,
+        response: Result<IncomingResponse, types::ErrorCode>,
+    ) -> Result<IncomingResponse, types::ErrorCode> {
+        let response = response?;
+        let status = response.resp.status();
+        let had_stale = self.stale.is_some();
+
+        if let (StatusCode::NOT_MODIFIED, Some(mut entry)) = (status, self.stale) {
+            debug!(uri = %self.key, "Cached HTTP response revalidated");
+            for (name, value) in response.resp.headers() {
+                if name != header::CONTENT_LENGTH {
+                    entry.headers.insert(name, value.clone());
+                }
+            }
+            entry.stored = Instant::now();
+            entry.fresh_for = freshness(&entry.headers).unwrap_or_default();
+            let revalidated = entry.to_response(response.between_bytes_timeout);
+            self.cache.insert(self.key, entry);
+            return Ok(revalidated);
+        }
+
+        let headers = response.resp.headers();
+        let vary = vary(headers, &self.request_headers);
+        let fresh_for = freshness(headers);
+        let cacheable = status == StatusCode::OK
+            && !headers.contains_key(header::SET_COOKIE)
+            && vary.is_some()
+            && (fresh_for.is_some_and(|fresh_for| !fresh_for.is_zero())
+                || headers.contains_key(header::ETAG)
+                || headers.contains_key(header::LAST_MODIFIED));
+        if !cacheable {
+            if had_stale {
+                self.cache.remove(&self.key);
+            }
+            return Ok(response);
+        }
+
+        let entry = CachedResponse {
+            status,
+            headers: headers.clone(),
+            body: Bytes::new(),
+            vary: vary.unwrap_or_default(),
+            stored: Instant::now(),
+            fresh_for: fresh_for.unwrap_or_default(),
+            last_used: 0,
+        };
+        Ok(IncomingResponse {
+            resp: response.resp.map(|body| {
+                HyperIncomingBody::new(CachingBody {
+                    inner: body,
+                    received: Some(BytesMut::new()),
+                    pending: Some((self.cache, self.key, entry)),
+                })
+            }),
+            ..response
+        })
+    }
+}
+
+/// Whether `request` may be served from and stored in a shared cache
+fn is_cacheable_request<B>(request: &hyper::Request<B>) -> bool {
+    let headers = request.headers();
+    request.method() == Method::GET
+        && !BYPASS_HEADERS.iter().any(|name| headers.contains_key(name))
+        && !has_directive(headers, header::CACHE_CONTROL, "no-store")
+        && !has_directive(headers, header::CACHE_CONTROL, "no-cache")
+        && !has_directive(headers, header::PRAGMA, "no-cache")
+}
+
+/// The directives of the `name` headers, lowercased, with their values
+fn directives(
+    headers: &HeaderMap,
+    name: HeaderName,
+) -> impl Iterator<Item = (String, Option<String>)> + '_ {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+}
+
+fn has_directive(headers: &HeaderMap, name: HeaderName, directive: &str) -> bool {
+    directives(headers, name).any(|(name, _)| name == directive)
+}
+
+/// How long a response with `headers` is fresh for a shared cache, or `None` if it must not be
+/// stored. A response that may be stored but has no explicit lifetime is fresh for zero seconds,
+/// so it is always revalidated.
+fn freshness(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = None;
+    let mut shared_max_age = None;
+    let mut no_cache = false;
+    for (name, value) in directives(headers, header::CACHE_CONTROL) {
+        let seconds = || value.as_deref().and_then(|value| value.parse::<u64>().ok());
+        match name.as_str() {
+            "no-store" | "private" => return None,
+            "no-cache" => no_cache = true,
+            "max-age" => max_age = seconds(),
+            "s-maxage" => shared_max_age = seconds(),
+            _ => {}
+        }
+    }
+    if no_cache {
+        return Some(Duration::ZERO);
+    }
+
+    let lifetime = match shared_max_age.or(max_age) {
+        Some(seconds) => Duration::from_secs(seconds),
+        None => {
+            let expires = http_date(headers, header::EXPIRES);
+            let date = http_date(headers, header::DATE).unwrap_or_else(chrono::Utc::now);
+            expires
+                .and_then(|expires| (expires - date).to_std().ok())
+                .unwrap_or_default()
+        }
+    };
+    let age = headers
+        .get(header::AGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default();
+    Some(lifetime.saturating_sub(age))
+}
+
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<chrono::DateTime<chrono::Utc>> {
+    let value = headers.get(name)?.to_str().ok()?;
+    chrono::DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&chrono::Utc))
+}
+
+/// The request headers named by the `Vary` header of a response, or `None` if the response
+/// varies on everything
+fn vary(
+    headers: &HeaderMap,
+    request_headers: &HeaderMap,
+) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut vary = Vec::new();
+    for (name, _) in directives(headers, header::VARY) {
+        if name == "*" {
+            return None;
+        }
+        let Ok(name) = HeaderName::try_from(name) else {
+            return None;
+        };
+        let value = request_headers.get(&name).cloned();
+        vary.push((name, value));
+    }
+    Some(vary)
+}
+
+/// A response body that is stored in the cache once it was read completely
+struct CachingBody {
+    inner: HyperIncomingBody,
+    /// The body so far, or `None` once it is too large or had trailers
+    received: Option<BytesMut>,
+    pending: Option<(HttpCache, String, CachedResponse)>,
+}
+
+impl Body for CachingBody {
+    type Data = Bytes;
+    type Error = types::ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => match (frame.data_ref(), self.received.as_mut()) {
+                (Some(data), Some(received)) if received.len() + data.len() <= MAX_ENTRY_SIZE => {
+                    received.extend_from_slice(data);
+                }
+                _ => self.received = None,
+            },
+            Some(Err(_)) => self.received = None,
+            None => {
+                if let (Some(received), Some((cache, key, mut entry))) =
+                    (self.received.take(), self.pending.take())
+                {
+                    debug!(uri = %key, bytes = received.len(), "HTTP response cached");
+                    entry.body = received.freeze();
+                    cache.insert(key, entry);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> hyper::Request<()> {
+        let mut request = hyper::Request::get("https://docs.example.com/guide?page=2");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap()
+    }
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &'static str) -> IncomingResponse {
+        let mut resp = hyper::Response::builder().status(status);
+        for (name, value) in headers {
+            resp = resp.header(*name, *value);
+        }
+        IncomingResponse {
+            resp: resp
+                .body(
+                    http_body_util::Full::new(Bytes::from_static(body.as_bytes()))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap(),
+            worker: None,
+            between_bytes_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Sends `request` through `cache`, answering it with `upstream` unless it is a hit.
+    /// Returns the body, and whether the request reached the server.
+    async fn fetch(
+        cache: &HttpCache,
+        mut request: hyper::Request<()>,
+        upstream: impl FnOnce(&hyper::Request<()>) -> IncomingResponse,
+    ) -> (Bytes, bool) {
+        let (response, sent) = match cache.lookup(&mut request, Duration::from_secs(5)) {
+            CacheLookup::Hit(response) => (response, false),
+            CacheLookup::Bypass => (upstream(&request), true),
+            CacheLookup::Miss(pending) => {
+                let response = pending
+                    .complete(HostFutureIncomingResponse::ready(Ok(Ok(upstream(
+                        &request,
+                    )))))
+                    .unwrap_ready()
+                    .unwrap()
+                    .unwrap();
+                (response, true)
+            }
+        };
+        let body = response
+            .resp
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        (body, sent)
+    }
+
+    #[test]
+    fn test_freshness() {
+        let headers = |pairs: &[(&str, &str)]| response(200, pairs, "").resp.headers().clone();
+
+        assert_eq!(
+            freshness(&headers(&[("cache-control", "public, max-age=60")])),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            freshness(&headers(&[
+                ("cache-control", "max-age=60, s-maxage=600"),
+                ("age", "100")
+            ])),
+            Some(Duration::from_secs(500))
+        );
+        assert_eq!(
+            freshness(&headers(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 08:59:37 GMT")
+            ])),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            freshness(&headers(&[("cache-control", "no-cache, max-age=60")])),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(freshness(&headers(&[])), Some(Duration::ZERO));
+        assert_eq!(
+            freshness(&headers(&[("cache-control", "private, max-age=60")])),
+            None
+        );
+        assert_eq!(freshness(&headers(&[("cache-control", "no-store")])), None);
+    }
+
+    #[tokio::test]
+    async fn test_fresh_responses_are_served_from_cache() {
+        let cache = HttpCache::default();
+        let upstream = |body| {
+            move |_: &hyper::Request<()>| response(200, &[("cache-control", "max-age=60")], body)
+        };
+
+        assert_eq!(
+            fetch(&cache, request(&[]), upstream("first")).await,
+            (Bytes::from("first"), true)
+        );
+        assert_eq!(
+            fetch(&cache, request(&[]), upstream("second")).await,
+            (Bytes::from("first"), false)
+        );
+
+        // Requests with credentials or that ask for a fresh copy are sent
+        assert_eq!(
+            fetch(
+                &cache,
+                request(&[("authorization", "Bearer secret")]),
+                upstream("private")
+            )
+            .await,
+            (Bytes::from("private"), true)
+        );
+        assert_eq!(
+            fetch(
+                &cache,
+                request(&[("cache-control", "no-cache")]),
+                upstream("third")
+            )
+            .await,
+            (Bytes::from("third"), true)
+        );
+
+        // Responses that must not be stored are not
+        let cache = HttpCache::default();
+        let no_store =
+            |_: &hyper::Request<()>| response(200, &[("cache-control", "no-store")], "secret");
+        fetch(&cache, request(&[]), no_store).await;
+        assert_eq!(
+            fetch(&cache, request(&[]), upstream("fresh")).await,
+            (Bytes::from("fresh"), true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_responses_are_revalidated() {
+        let cache = HttpCache::default();
+        let etag = "\"v1\"";
+        fetch(&cache, request(&[]), |_| {
+            response(200, &[("etag", etag)], "cached page")
+        })
+        .await;
+
+        // The server confirms the cached response is current
+        let (body, sent) = fetch(&cache, request(&[]), |request| {
+            assert_eq!(request.headers()[header::IF_NONE_MATCH], etag);
+            response(304, &[("etag", etag)], "")
+        })
+        .await;
+        assert_eq!((body, sent), (Bytes::from("cached page"), true));
+
+        // A changed page replaces the cached one
+        fetch(&cache, request(&[]), |_| {
+            response(200, &[("etag", "\"v2\"")], "new page")
+        })
+        .await;
+        let (body, _) = fetch(&cache, request(&[]), |request| {
+            assert_eq!(request.headers()[header::IF_NONE_MATCH], "\"v2\"");
+            response(304, &[], "")
+        })
+        .await;
+        assert_eq!(body, "new page");
+    }
+
+    #[tokio::test]
+    async fn test_vary() {
+        let cache = HttpCache::default();
+        let upstream = |body| {
+            move |_: &hyper::Request<()>| {
+                response(
+                    200,
+                    &[("cache-control", "max-age=60"), ("vary", "Accept-Language")],
+                    body,
+                )
+            }
+        };
+
+        fetch(
+            &cache,
+            request(&[("accept-language", "en")]),
+            upstream("hello"),
+        )
+        .await;
+        assert_eq!(
+            fetch(
+                &cache,
+                request(&[("accept-language", "en")]),
+                upstream("hi")
+            )
+            .await,
+            (Bytes::from("hello"), false)
+        );
+        assert_eq!(
+            fetch(
+                &cache,
+                request(&[("accept-language", "fr")]),
+                upstream("bonjour")
+            )
+            .await,
+            (Bytes::from("bonjour"), true)
+        );
+    }
+}
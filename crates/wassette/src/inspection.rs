@@ -0,0 +1,625 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Inspection of components before they load
+//!
+//! A component's imports tell which capabilities it can ask the host for, whatever its policy
+//! grants. With [`crate::LifecycleManager::with_inspector`], every component is inspected after
+//! it compiles and before it replaces anything: each [`ComponentInspector`] reports findings that
+//! either block the component or are logged as warnings. The findings are also published as a
+//! `component.inspected` event.
+//!
+//! An [`InspectionConfig`] sets up the built-in inspectors: rules by capability, and an external
+//! scanner that is run with the path of the component file as its last argument and the
+//! [`InspectedComponent`] as JSON on its standard input.
+//!
+//! ```yaml
+//! capabilities:
+//!   sockets: block
+//!   filesystem: warn
+//! scanner:
+//!   command: [/usr/local/bin/wasm-scan, --json]
+//!   timeout_secs: 60
+//!   on_error: block
+//! ```
+//!
+//! The scanner passes a component by exiting successfully. It can print findings as JSON, such
+//! as `{"findings": [{"action": "warn", "message": "..."}]}`. A scanner that exits with an error,
+//! can't be run or times out adds a finding with the `on_error` action.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+use wasmtime::component::Component;
+use wasmtime::Engine;
+
+use crate::events::Event;
+use crate::LifecycleManager;
+
+/// Time an external scanner gets by default, in seconds
+const DEFAULT_SCANNER_TIMEOUT_SECS: u64 = 60;
+
+/// A capability that a component requests by importing the interfaces of a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// `wasi:filesystem`
+    Filesystem,
+    /// `wasi:sockets`
+    Sockets,
+    /// `wasi:http`
+    Http,
+    /// `wasi:cli/environment`
+    Environment,
+    /// `wasi:config`
+    Config,
+    /// `wasi:keyvalue`
+    Keyvalue,
+    /// `wasi:blobstore`
+    Blobstore,
+    /// `wasi:sql`
+    Sql,
+    /// `wassette:components`, to call other components
+    Components,
+    /// `wassette:ai`, to sample the client's model
+    Sampling,
+}
+
+impl Capability {
+    /// The name of the capability in inspection configs, such as `sockets`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Filesystem => "filesystem",
+            Self::Sockets => "sockets",
+            Self::Http => "http",
+            Self::Environment => "environment",
+            Self::Config => "config",
+            Self::Keyvalue => "keyvalue",
+            Self::Blobstore => "blobstore",
+            Self::Sql => "sql",
+            Self::Components => "components",
+            Self::Sampling => "sampling",
+        }
+    }
+
+    /// The capability an import such as `wasi:sockets/tcp@0.2.0` requests, if any
+    pub fn of_import(name: &str) -> Option<Self> {
+        let (package, rest) = name.split_once('/').unwrap_or((name, ""));
+        let interface = rest.split('@').next().unwrap_or_default();
+        match package.split('@').next().unwrap_or_default() {
+            "wasi:filesystem" => Some(Self::Filesystem),
+            "wasi:sockets" => Some(Self::Sockets),
+            "wasi:http" => Some(Self::Http),
+            "wasi:cli" if interface == "environment" => Some(Self::Environment),
+            "wasi:config" => Some(Self::Config),
+            "wasi:keyvalue" => Some(Self::Keyvalue),
+            "wasi:blobstore" => Some(Self::Blobstore),
+            "wasi:sql" => Some(Self::Sql),
+            "wassette:components" => Some(Self::Components),
+            "wassette:ai" => Some(Self::Sampling),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a component with a finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InspectionAction {
+    /// Log a warning and load the component
+    Warn,
+    /// Refuse to load the component
+    Block,
+}
+
+/// Something an inspector found in a component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InspectionFinding {
+    /// Name of the inspector that found it
+    #[serde(default)]
+    pub inspector: String,
+    /// Whether the finding blocks the component
+    pub action: InspectionAction,
+    /// What was found
+    pub message: String,
+}
+
+/// A compiled component that is about to load
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InspectedComponent {
+    /// ID the component is loaded under
+    pub component_id: String,
+    /// The component file
+    pub path: PathBuf,
+    /// Names of the instances the component imports, such as `wasi:sockets/tcp@0.2.0`
+    pub imports: Vec<String>,
+    /// The capabilities the imports request
+    pub capabilities: BTreeSet<Capability>,
+}
+
+impl InspectedComponent {
+    pub(crate) fn new(
+        component_id: &str,
+        path: &Path,
+        component: &Component,
+        engine: &Engine,
+    ) -> Self {
+        let imports = component_imports(component, engine);
+        Self {
+            component_id: component_id.to_string(),
+            path: path.to_path_buf(),
+            capabilities: imports
+                .iter()
+                .filter_map(|name| Capability::of_import(name))
+                .collect(),
+            imports,
+        }
+    }
+}
+
+/// Names of the instances `component` imports
+pub(crate) fn component_imports(component: &Component, engine: &Engine) -> Vec<String> {
+    component
+        .component_type()
+        .imports(engine)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// The future of a [`ComponentInspector`]
+pub type InspectionFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<InspectionFinding>>> + Send + 'a>>;
+
+/// Inspects components before they load. An error blocks the component like a blocking finding.
+pub trait ComponentInspector: Send + Sync {
+    /// Name of the inspector, which findings are attributed to
+    fn name(&self) -> &str;
+
+    /// The findings of the inspector about `component`
+    fn inspect<'a>(&'a self, component: &'a InspectedComponent) -> InspectionFuture<'a>;
+}
+
+/// Blocks or warns about components that request certain capabilities
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CapabilityRules {
+    rules: BTreeMap<Capability, InspectionAction>,
+}
+
+impl CapabilityRules {
+    /// Creates rules that take `action` for components that request a capability
+    pub fn new(rules: BTreeMap<Capability, InspectionAction>) -> Self {
+        Self { rules }
+    }
+}
+
+impl ComponentInspector for CapabilityRules {
+    fn name(&self) -> &str {
+        "capabilities"
+    }
+
+    fn inspect<'a>(&'a self, component: &'a InspectedComponent) -> InspectionFuture<'a> {
+        let findings = component
+            .capabilities
+            .iter()
+            .filter_map(|capability| {
+                let action = *self.rules.get(capability)?;
+                let imports: Vec<&str> = component
+                    .imports
+                    .iter()
+                    .filter(|name| Capability::of_import(name) == Some(*capability))
+                    .map(String::as_str)
+                    .collect();
+                Some(InspectionFinding {
+                    inspector: self.name().to_string(),
+                    action,
+                    message: format!(
+                        "Component requests the {} capability by importing {}",
+                        capability.name(),
+                        imports.join(", ")
+                    ),
+                })
+            })
+            .collect();
+        Box::pin(async move { Ok(findings) })
+    }
+}
+
+/// An external scanner, as configured in an [`InspectionConfig`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScannerConfig {
+    /// The program and its arguments; the path of the component file is appended
+    pub command: Vec<String>,
+    /// Seconds the scanner gets per component
+    #[serde(default = "default_scanner_timeout")]
+    pub timeout_secs: u64,
+    /// What to do when the scanner fails, can't be run or times out
+    #[serde(default = "default_on_error")]
+    pub on_error: InspectionAction,
+}
+
+fn default_scanner_timeout() -> u64 {
+    DEFAULT_SCANNER_TIMEOUT_SECS
+}
+
+fn default_on_error() -> InspectionAction {
+    InspectionAction::Block
+}
+
+/// The output of an external scanner
+#[derive(Deserialize)]
+struct ScannerOutput {
+    #[serde(default)]
+    findings: Vec<InspectionFinding>,
+}
+
+/// Runs an external program on every component
+#[derive(Debug, Clone)]
+pub struct ExternalScanner {
+    config: ScannerConfig,
+}
+
+impl ExternalScanner {
+    /// Creates a scanner that runs the command of `config`
+    pub fn new(config: ScannerConfig) -> Result<Self> {
+        if config.command.is_empty() {
+            bail!("The command of the scanner is empty");
+        }
+        Ok(Self { config })
+    }
+
+    async fn run(&self, component: &InspectedComponent) -> Result<Vec<InspectionFinding>> {
+        let (program, args) = self
+            .config
+            .command
+            .split_first()
+            .context("The command of the scanner is empty")?;
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .arg(&component.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run scanner {program}"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = serde_json::to_vec(component)?;
+            // A scanner that doesn't read its input may close it early
+            let _ = stdin.write_all(&input).await;
+        }
+        let output = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout_secs),
+            child.wait_with_output(),
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Scanner {program} timed out after {}s",
+                self.config.timeout_secs
+            )
+        })??;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "Scanner {program} failed ({}): {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let output: ScannerOutput = serde_json::from_str(&stdout)
+            .with_context(|| format!("Scanner {program} printed invalid findings"))?;
+        Ok(output.findings)
+    }
+}
+
+impl ComponentInspector for ExternalScanner {
+    fn name(&self) -> &str {
+        "scanner"
+    }
+
+    fn inspect<'a>(&'a self, component: &'a InspectedComponent) -> InspectionFuture<'a> {
+        Box::pin(async move {
+            let findings = match self.run(component).await {
+                Ok(findings) => findings,
+                Err(e) => vec![InspectionFinding {
+                    inspector: String::new(),
+                    action: self.config.on_error,
+                    message: format!("{e:#}"),
+                }],
+            };
+            Ok(findings
+                .into_iter()
+                .map(|finding| InspectionFinding {
+                    inspector: self.name().to_string(),
+                    ..finding
+                })
+                .collect())
+        })
+    }
+}
+
+/// The built-in inspectors, as read from a YAML file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InspectionConfig {
+    /// What to do with components that request a capability
+    #[serde(default)]
+    pub capabilities: BTreeMap<Capability, InspectionAction>,
+    /// External scanner to run on every component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanner: Option<ScannerConfig>,
+}
+
+impl InspectionConfig {
+    /// Reads the configuration from a YAML file
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read inspection config {}", path.display()))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Invalid inspection config {}", path.display()))
+    }
+
+    /// The inspectors the configuration sets up
+    pub fn inspectors(self) -> Result<Vec<Arc<dyn ComponentInspector>>> {
+        let mut inspectors: Vec<Arc<dyn ComponentInspector>> = Vec::new();
+        if !self.capabilities.is_empty() {
+            inspectors.push(Arc::new(CapabilityRules::new(self.capabilities)));
+        }
+        if let Some(scanner) = self.scanner {
+            inspectors.push(Arc::new(ExternalScanner::new(scanner)?));
+        }
+        Ok(inspectors)
+    }
+}
+
+impl LifecycleManager {
+    /// Inspects every component before it loads, see [`crate::inspection`]
+    pub fn with_inspector(mut self, inspector: Arc<dyn ComponentInspector>) -> Self {
+        self.inspectors.push(inspector);
+        self
+    }
+
+    /// Inspects every component with the built-in inspectors set up by `config`
+    pub fn with_inspection(mut self, config: InspectionConfig) -> Result<Self> {
+        self.inspectors.extend(config.inspectors()?);
+        Ok(self)
+    }
+
+    /// Runs the inspectors on a compiled component, failing if one of them blocks it
+    pub(crate) async fn inspect_component(
+        &self,
+        component_id: &str,
+        path: &Path,
+        component: &Component,
+    ) -> Result<()> {
+        if self.inspectors.is_empty() {
+            return Ok(());
+        }
+        let inspected = InspectedComponent::new(component_id, path, component, &self.engine);
+        let mut findings = Vec::new();
+        for inspector in &self.inspectors {
+            match inspector.inspect(&inspected).await {
+                Ok(found) => findings.extend(found),
+                Err(e) => findings.push(InspectionFinding {
+                    inspector: inspector.name().to_string(),
+                    action: InspectionAction::Block,
+                    message: format!("Inspection failed: {e:#}"),
+                }),
+            }
+        }
+
+        let blocking: Vec<&InspectionFinding> = findings
+            .iter()
+            .filter(|finding| finding.action == InspectionAction::Block)
+            .collect();
+        for finding in &findings {
+            warn!(
+                component_id,
+                inspector = finding.inspector,
+                action = ?finding.action,
+                "{}",
+                finding.message
+            );
+        }
+        info!(
+            component_id,
+            capabilities = ?inspected.capabilities,
+            findings = findings.len(),
+            "Inspected component"
+        );
+        let error = (!blocking.is_empty()).then(|| {
+            let reasons: Vec<String> = blocking
+                .iter()
+                .map(|finding| format!("{}: {}", finding.inspector, finding.message))
+                .collect();
+            anyhow::anyhow!(
+                "Component {component_id} was blocked by inspection: {}",
+                reasons.join("; ")
+            )
+        });
+        self.events.emit(Event::ComponentInspected {
+            component_id: component_id.to_string(),
+            capabilities: inspected.capabilities.into_iter().collect(),
+            blocked: error.is_some(),
+            findings,
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    fn inspected(imports: &[&str]) -> InspectedComponent {
+        InspectedComponent {
+            component_id: "tool".to_string(),
+            path: PathBuf::from("/tmp/tool.wasm"),
+            imports: imports.iter().map(|name| name.to_string()).collect(),
+            capabilities: imports
+                .iter()
+                .filter_map(|name| Capability::of_import(name))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_capability_of_import() {
+        assert_eq!(
+            Capability::of_import("wasi:sockets/tcp@0.2.0"),
+            Some(Capability::Sockets)
+        );
+        assert_eq!(
+            Capability::of_import("wasi:filesystem/preopens@0.2.3"),
+            Some(Capability::Filesystem)
+        );
+        assert_eq!(
+            Capability::of_import("wasi:cli/environment@0.2.0"),
+            Some(Capability::Environment)
+        );
+        assert_eq!(Capability::of_import("wasi:cli/stdout@0.2.0"), None);
+        assert_eq!(
+            Capability::of_import("wassette:components/call@0.1.0"),
+            Some(Capability::Components)
+        );
+        assert_eq!(Capability::of_import("wasi:random/random@0.2.0"), None);
+        assert_eq!(Capability::of_import("local:tool/helpers"), None);
+    }
+
+    #[test(tokio::test)]
+    async fn test_capability_rules() -> Result<()> {
+        let config: InspectionConfig =
+            serde_yaml::from_str("capabilities:\n  sockets: block\n  filesystem: warn\n")?;
+        let rules = CapabilityRules::new(config.capabilities);
+        let findings = rules
+            .inspect(&inspected(&[
+                "wasi:sockets/tcp@0.2.0",
+                "wasi:sockets/network@0.2.0",
+                "wasi:http/outgoing-handler@0.2.0",
+            ]))
+            .await?;
+        assert_eq!(
+            findings,
+            [InspectionFinding {
+                inspector: "capabilities".to_string(),
+                action: InspectionAction::Block,
+                message: "Component requests the sockets capability by importing \
+                          wasi:sockets/tcp@0.2.0, wasi:sockets/network@0.2.0"
+                    .to_string(),
+            }]
+        );
+        assert!(rules
+            .inspect(&inspected(&["wasi:cli/stdout@0.2.0"]))
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_inspect_before_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tool.wasm");
+        let wat = r#"(component (import "wasi:sockets/tcp@0.2.0" (instance)))"#;
+        tokio::fs::write(&path, wat::parse_str(wat)?).await?;
+        let uri = format!("file://{}", path.display());
+
+        let config: InspectionConfig = serde_yaml::from_str("capabilities:\n  sockets: block\n")?;
+        let manager = LifecycleManager::new(dir.path().join("plugins"))
+            .await?
+            .with_inspection(config)?;
+        let mut events = manager.events.subscribe();
+        let err = manager.load_component(&uri).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Component tool was blocked by inspection: capabilities: "),
+            "{err}"
+        );
+        assert!(manager.list_components().await.is_empty());
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        let Event::ComponentInspected {
+            capabilities,
+            blocked,
+            ..
+        } = events.recv().await?
+        else {
+            panic!("Expected an inspection event");
+        };
+        assert_eq!(capabilities, [Capability::Sockets]);
+        assert!(blocked);
+
+        let config: InspectionConfig = serde_yaml::from_str("capabilities:\n  sockets: warn\n")?;
+        let manager = LifecycleManager::new(dir.path().join("plugins"))
+            .await?
+            .with_inspection(config)?;
+        manager.load_component(&uri).await?;
+        assert_eq!(manager.list_components().await, ["tool"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test(tokio::test)]
+    async fn test_external_scanner() -> Result<()> {
+        let scanner = |script: &str, on_error| {
+            ExternalScanner::new(ScannerConfig {
+                command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+                timeout_secs: 1,
+                on_error,
+            })
+            .unwrap()
+        };
+        let component = inspected(&["wasi:filesystem/types@0.2.0"]);
+
+        // The component comes on stdin, and its path as the last argument
+        let findings = scanner(
+            r#"grep -q wasi:filesystem && echo "{\"findings\": [{\"action\": \"warn\", \"message\": \"$0\"}]}""#,
+            InspectionAction::Block,
+        )
+        .inspect(&component)
+        .await?;
+        assert_eq!(
+            findings,
+            [InspectionFinding {
+                inspector: "scanner".to_string(),
+                action: InspectionAction::Warn,
+                message: "/tmp/tool.wasm".to_string(),
+            }]
+        );
+        assert!(scanner("cat > /dev/null", InspectionAction::Block)
+            .inspect(&component)
+            .await?
+            .is_empty());
+
+        let findings = scanner("echo infected >&2; exit 3", InspectionAction::Warn)
+            .inspect(&component)
+            .await?;
+        assert_eq!(findings[0].action, InspectionAction::Warn);
+        assert!(findings[0].message.contains("infected"), "{findings:?}");
+
+        let findings = scanner("sleep 10", InspectionAction::Block)
+            .inspect(&component)
+            .await?;
+        assert_eq!(findings[0].action, InspectionAction::Block);
+        assert!(findings[0].message.contains("timed out"), "{findings:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Secrets of components in the keychain of the operating system
+//!
+//! A [`KeychainSecretProvider`] keeps the secrets of a component in one keychain item whose
+//! service is the provider's and whose account is the component ID, holding the secrets as YAML.
+//! On macOS, the item is a generic password in the login keychain. On Windows, it's a generic
+//! credential in the Credential Manager named `<service>/<component-id>`. Elsewhere, it's an item
+//! of the Secret Service (GNOME Keyring, KWallet) stored and looked up with `secret-tool` from
+//! libsecret, which must be installed. Access to the items is up to the keychain, which may ask
+//! the user to unlock it.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::sync::Mutex;
+
+use crate::secrets::{SecretFuture, SecretProvider};
+
+/// Name of the secret provider that keeps secrets in the keychain of the operating system
+pub const KEYCHAIN_SECRET_PROVIDER: &str = "keychain";
+
+/// Keeps the secrets of each component in an item of the keychain of the operating system
+#[derive(Debug, Clone)]
+pub struct KeychainSecretProvider {
+    service: String,
+    /// Held while an item is read and written back, so concurrent changes aren't lost
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl KeychainSecretProvider {
+    /// Creates a provider that keeps its items under `service`
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            write_lock: Arc::default(),
+        }
+    }
+
+    async fn load(&self, component_id: &str) -> Result<BTreeMap<String, String>> {
+        let (service, account) = (self.service.clone(), component_id.to_string());
+        let contents = tokio::task::spawn_blocking(move || platform::read(&service, &account))
+            .await?
+            .with_context(|| {
+                format!("Failed to read the secrets of {component_id} from the keychain")
+            })?;
+        match contents {
+            Some(contents) if !contents.trim().is_empty() => serde_yaml::from_str(&contents)
+                .with_context(|| format!("Failed to parse the secrets of {component_id}")),
+            _ => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Writes the secrets of `component_id`, or deletes its item if it has none left
+    async fn store(&self, component_id: &str, secrets: &BTreeMap<String, String>) -> Result<()> {
+        let (service, account) = (self.service.clone(), component_id.to_string());
+        let contents = (!secrets.is_empty())
+            .then(|| serde_yaml::to_string(secrets))
+            .transpose()?;
+        tokio::task::spawn_blocking(move || match contents {
+            Some(contents) => platform::write(&service, &account, &contents),
+            None => platform::delete(&service, &account),
+        })
+        .await?
+        .with_context(|| format!("Failed to write the secrets of {component_id} to the keychain"))
+    }
+}
+
+impl SecretProvider for KeychainSecretProvider {
+    fn get<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, BTreeMap<String, String>> {
+        Box::pin(self.load(component_id))
+    }
+
+    fn set<'a>(
+        &'a self,
+        component_id: &'a str,
+        key: &'a str,
+        value: &'a str,
+    ) -> SecretFuture<'a, ()> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            let mut secrets = self.load(component_id).await?;
+            secrets.insert(key.to_string(), value.to_string());
+            self.store(component_id, &secrets).await
+        })
+    }
+
+    fn delete<'a>(&'a self, component_id: &'a str, key: &'a str) -> SecretFuture<'a, bool> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            let mut secrets = self.load(component_id).await?;
+            if secrets.remove(key).is_none() {
+                return Ok(false);
+            }
+            self.store(component_id, &secrets).await?;
+            Ok(true)
+        })
+    }
+
+    fn delete_all<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, ()> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            self.store(component_id, &BTreeMap::new()).await
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use anyhow::Result;
+    use security_framework::passwords;
+
+    /// `errSecItemNotFound`
+    const ITEM_NOT_FOUND: i32 = -25300;
+
+    pub(super) fn read(service: &str, account: &str) -> Result<Option<String>> {
+        match passwords::get_generic_password(service, account) {
+            Ok(password) => Ok(Some(String::from_utf8(password)?)),
+            Err(e) if e.code() == ITEM_NOT_FOUND => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(super) fn write(service: &str, account: &str, contents: &str) -> Result<()> {
+        Ok(passwords::set_generic_password(
+            service,
+            account,
+            contents.as_bytes(),
+        )?)
+    }
+
+    pub(super) fn delete(service: &str, account: &str) -> Result<()> {
+        match passwords::delete_generic_password(service, account) {
+            Err(e) if e.code() != ITEM_NOT_FOUND => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use anyhow::Result;
+    use windows_sys::Win32::Foundation::ERROR_NOT_FOUND;
+    use windows_sys::Win32::Security::Credentials::{
+        CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_TYPE_GENERIC,
+    };
+
+    fn target_name(service: &str, account: &str) -> Vec<u16> {
+        format!("{service}/{account}")
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect()
+    }
+
+    fn not_found(error: &std::io::Error) -> bool {
+        error.raw_os_error() == Some(ERROR_NOT_FOUND as i32)
+    }
+
+    pub(super) fn read(service: &str, account: &str) -> Result<Option<String>> {
+        let target_name = target_name(service, account);
+        let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+        // SAFETY: target_name is NUL-terminated and credential is only used if the call succeeds
+        if unsafe { CredReadW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+            let error = std::io::Error::last_os_error();
+            return if not_found(&error) {
+                Ok(None)
+            } else {
+                Err(error.into())
+            };
+        }
+        // SAFETY: CredReadW succeeded, so credential points to a credential whose blob has
+        // CredentialBlobSize bytes. It's freed once the blob is copied.
+        let contents = unsafe {
+            let blob = std::slice::from_raw_parts(
+                (*credential).CredentialBlob,
+                (*credential).CredentialBlobSize as usize,
+            )
+            .to_vec();
+            CredFree(credential as *const _);
+            blob
+        };
+        Ok(Some(String::from_utf8(contents)?))
+    }
+
+    pub(super) fn write(service: &str, account: &str, contents: &str) -> Result<()> {
+        let mut target_name = target_name(service, account);
+        let mut user_name = account
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let mut blob = contents.as_bytes().to_vec();
+        let credential = CREDENTIALW {
+            Type: CRED_TYPE_GENERIC,
+            TargetName: target_name.as_mut_ptr(),
+            UserName: user_name.as_mut_ptr(),
+            CredentialBlobSize: u32::try_from(blob.len())?,
+            CredentialBlob: blob.as_mut_ptr(),
+            Persist: CRED_PERSIST_LOCAL_MACHINE,
+            ..Default::default()
+        };
+        // SAFETY: all pointers in credential point to buffers that outlive the call
+        if unsafe { CredWriteW(&credential, 0) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    pub(super) fn delete(service: &str, account: &str) -> Result<()> {
+        let target_name = target_name(service, account);
+        // SAFETY: target_name is NUL-terminated
+        if unsafe { CredDeleteW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            let error = std::io::Error::last_os_error();
+            if !not_found(&error) {
+                return Err(error.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use anyhow::{bail, Context, Result};
+
+    /// Runs `secret-tool`, writing `input` to its standard input
+    fn secret_tool(args: &[&str], input: &str) -> Result<std::process::Output> {
+        let mut child = Command::new("secret-tool")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run secret-tool, is libsecret installed?")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        Ok(child.wait_with_output()?)
+    }
+
+    pub(super) fn read(service: &str, account: &str) -> Result<Option<String>> {
+        let output = secret_tool(&["lookup", "service", service, "account", account], "")?;
+        match output.status.success() {
+            true => Ok(Some(String::from_utf8(output.stdout)?)),
+            // secret-tool fails without a message if there is no such item
+            false if output.stderr.is_empty() => Ok(None),
+            false => bail!("{}", String::from_utf8_lossy(&output.stderr).trim()),
+        }
+    }
+
+    pub(super) fn write(service: &str, account: &str, contents: &str) -> Result<()> {
+        let label = format!("Secrets of {account} ({service})");
+        let args = [
+            "store", "--label", &label, "service", service, "account", account,
+        ];
+        let output = secret_tool(&args, contents)?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+
+    pub(super) fn delete(service: &str, account: &str) -> Result<()> {
+        let output = secret_tool(&["clear", "service", service, "account", account], "")?;
+        // Like lookup, clear fails without a message if there is no such item
+        if !output.status.success() && !output.stderr.is_empty() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,747 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Verification of keyless cosign signatures
+//!
+//! A keyless signature is made with an ephemeral key that Fulcio certified for the OIDC identity
+//! of the signer, such as a GitHub Actions workflow. The signature layer carries the signing
+//! certificate, the chain of its issuer and the bundle of the signature's entry in the Rekor
+//! transparency log. The certificate is only valid for a few minutes, so it is checked at the
+//! time Rekor recorded the signature. What makes a signature trusted is the identity in its
+//! certificate, which [`CertificateIdentity`] constrains. The Fulcio roots and the Rekor key can
+//! be given as PEM files, or come from the `trusted_root.json` of a Sigstore instance, see
+//! [`crate::trusted_root`].
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use p256::ecdsa::signature::Verifier as _;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey as _;
+use regex::Regex;
+use rustls::pki_types::pem::PemObject as _;
+use rustls::pki_types::{CertificateDer, UnixTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use webpki::{EndEntityCert, KeyUsage};
+use x509_parser::der_parser::asn1_rs::{FromDer as _, Utf8String};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::X509Certificate;
+
+use crate::bundle::sha256_hex;
+use crate::signature::SignatureVerifier;
+use crate::signing::{BUNDLE_ANNOTATION, CERTIFICATE_ANNOTATION, CHAIN_ANNOTATION};
+use crate::trusted_root::{FulcioCertificate, RekorKey, TrustedRoot, Validity};
+
+/// DER contents of the code signing extended key usage OID, 1.3.6.1.5.5.7.3.3
+const CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+/// Fulcio extension with the OIDC issuer as a DER UTF8String
+const ISSUER_OID: &str = "1.3.6.1.4.1.57264.1.8";
+/// Deprecated Fulcio extension with the OIDC issuer as raw bytes
+const LEGACY_ISSUER_OID: &str = "1.3.6.1.4.1.57264.1.1";
+/// Fulcio extension with the git ref the workflow ran for as a DER UTF8String
+const SOURCE_REPOSITORY_REF_OID: &str = "1.3.6.1.4.1.57264.1.14";
+/// Deprecated Fulcio extension with the git ref of the GitHub workflow as raw bytes
+const LEGACY_WORKFLOW_REF_OID: &str = "1.3.6.1.4.1.57264.1.6";
+
+/// Constraints on the identity in the certificate of a keyless signature. Every constraint that
+/// is set must hold.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CertificateIdentity {
+    /// OIDC issuer the signer authenticated with, such as
+    /// `https://token.actions.githubusercontent.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Regular expression the whole subject of the certificate must match: the email of a
+    /// person, or the URI of a workflow such as
+    /// `https://github.com/microsoft/wassette/.github/workflows/release.yml@refs/heads/main`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Git ref the GitHub workflow ran for, such as `refs/heads/main`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow_ref: Option<String>,
+}
+
+impl CertificateIdentity {
+    /// Checks that at least one constraint is set and that the subject is a valid regular
+    /// expression
+    pub fn validate(&self) -> Result<()> {
+        if self.issuer.is_none() && self.subject.is_none() && self.workflow_ref.is_none() {
+            bail!("A certificate identity needs an issuer, subject or workflow_ref");
+        }
+        self.subject_regex()?;
+        Ok(())
+    }
+
+    fn subject_regex(&self) -> Result<Option<Regex>> {
+        self.subject
+            .as_deref()
+            .map(|subject| {
+                Regex::new(&format!("^(?:{subject})$"))
+                    .with_context(|| format!("Invalid subject pattern {subject}"))
+            })
+            .transpose()
+    }
+}
+
+/// How the keyless signatures of a component are verified. The trust roots are either
+/// `fulcio_root` or `trusted_root`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeylessVerification {
+    /// URI of the PEM certificates of the Fulcio root the signing certificates must chain to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fulcio_root: Option<String>,
+    /// URI of the `trusted_root.json` of the Sigstore instance, with its Fulcio certificates and
+    /// Rekor keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_root: Option<String>,
+    /// URI of the PEM public key of the Rekor transparency log, which must have signed the entry
+    /// timestamp of every signature. Required with `fulcio_root`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rekor_public_key: Option<String>,
+    /// Identity the signing certificates must have
+    #[serde(flatten)]
+    pub identity: CertificateIdentity,
+}
+
+impl KeylessVerification {
+    /// Checks that there is exactly one kind of trust root, that a Rekor key comes with it and
+    /// that the identity is valid
+    pub fn validate(&self) -> Result<()> {
+        match (&self.fulcio_root, &self.trusted_root) {
+            (Some(_), Some(_)) => {
+                bail!("Keyless verification can't have both fulcio_root and trusted_root")
+            }
+            (None, None) => bail!("Keyless verification needs a fulcio_root or trusted_root"),
+            (Some(_), None) if self.rekor_public_key.is_none() => {
+                bail!("Keyless verification with a fulcio_root needs a rekor_public_key")
+            }
+            _ => self.identity.validate(),
+        }
+    }
+
+    /// Reads the trust roots and creates a verifier of the signatures this describes
+    pub async fn verifier(&self) -> Result<SignatureVerifier> {
+        self.validate()?;
+        let verifier = match (&self.fulcio_root, &self.trusted_root) {
+            (_, Some(uri)) => SignatureVerifier::keyless_with_trusted_root(
+                &TrustedRoot::from_file(uri).await?,
+                self.identity.clone(),
+            )?,
+            (Some(uri), None) => SignatureVerifier::keyless(
+                &read_uri(uri, "Fulcio root").await?,
+                self.identity.clone(),
+            )?,
+            (None, None) => unreachable!("validated"),
+        };
+        match &self.rekor_public_key {
+            Some(uri) => verifier.with_rekor_key(&read_uri(uri, "Rekor public key").await?),
+            None => Ok(verifier),
+        }
+    }
+}
+
+async fn read_uri(uri: &str, what: &str) -> Result<String> {
+    let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+    tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {what} {}", path.display()))
+}
+
+/// The trust a [`SignatureVerifier`] puts in keyless signatures
+#[derive(Debug, Clone)]
+pub(crate) struct KeylessTrust {
+    roots: Vec<FulcioCertificate>,
+    identity: CertificateIdentity,
+    subject: Option<Regex>,
+    rekor_keys: Vec<RekorKey>,
+}
+
+/// The cosign bundle of a Rekor entry
+#[derive(Deserialize)]
+struct RekorBundle {
+    #[serde(rename = "SignedEntryTimestamp")]
+    signed_entry_timestamp: String,
+    #[serde(rename = "Payload")]
+    payload: RekorPayload,
+}
+
+/// The part of a Rekor entry its signed entry timestamp signs. The fields are in the order of
+/// the canonical JSON Rekor signs.
+#[derive(Serialize, Deserialize)]
+struct RekorPayload {
+    body: String,
+    #[serde(rename = "integratedTime")]
+    integrated_time: u64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    #[serde(rename = "logIndex")]
+    log_index: u64,
+}
+
+impl KeylessTrust {
+    pub(crate) fn new(roots_pem: &str, identity: CertificateIdentity) -> Result<Self> {
+        identity.validate()?;
+        let roots = CertificateDer::pem_slice_iter(roots_pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Invalid Fulcio root: {e}"))?;
+        if roots.is_empty() {
+            bail!("Fulcio root has no certificates");
+        }
+        for root in &roots {
+            webpki::anchor_from_trusted_cert(root)
+                .map_err(|e| anyhow::anyhow!("Invalid Fulcio root certificate: {e}"))?;
+        }
+        Ok(Self {
+            roots: roots
+                .into_iter()
+                .map(|certificate| FulcioCertificate {
+                    certificate,
+                    validity: Validity::default(),
+                })
+                .collect(),
+            subject: identity.subject_regex()?,
+            identity,
+            rekor_keys: Vec::new(),
+        })
+    }
+
+    pub(crate) fn from_trusted_root(
+        root: &TrustedRoot,
+        identity: CertificateIdentity,
+    ) -> Result<Self> {
+        identity.validate()?;
+        Ok(Self {
+            roots: root.certificates.clone(),
+            subject: identity.subject_regex()?,
+            identity,
+            rekor_keys: root.rekor_keys.clone(),
+        })
+    }
+
+    pub(crate) fn with_rekor_key(mut self, pem: &str) -> Result<Self> {
+        let key = VerifyingKey::from_public_key_pem(pem.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid Rekor public key: {e}"))?;
+        self.rekor_keys.push(RekorKey {
+            log_id: None,
+            key,
+            validity: Validity::default(),
+        });
+        Ok(self)
+    }
+
+    /// The roots, identity and Rekor key that are trusted, as bytes that change whenever one of
+    /// them does
+    pub(crate) fn trust_material(&self) -> Result<Vec<u8>> {
+        let mut material = serde_json::to_vec(&self.identity)?;
+        for root in &self.roots {
+            material.extend_from_slice(format!("\nroot {:?}:", root.validity).as_bytes());
+            material.extend_from_slice(&root.certificate);
+        }
+        for rekor in &self.rekor_keys {
+            material.extend_from_slice(
+                format!("\nrekor {:?} {:?}:", rekor.log_id, rekor.validity).as_bytes(),
+            );
+            material.extend_from_slice(rekor.key.to_encoded_point(true).as_bytes());
+        }
+        Ok(material)
+    }
+
+    /// Checks that `signature`, the base64 signature of `payload`, was made with a certificate
+    /// that Fulcio issued for the expected identity, and that Rekor recorded it while the
+    /// certificate was valid
+    pub(crate) fn verify(
+        &self,
+        payload: &[u8],
+        signature: &str,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let leaf = annotations
+            .get(CERTIFICATE_ANNOTATION)
+            .context("Signature has no certificate; it was not signed keylessly")?;
+        let leaf = CertificateDer::from_pem_slice(leaf.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Invalid signing certificate: {e}"))?;
+        let intermediates = match annotations.get(CHAIN_ANNOTATION) {
+            Some(chain) => CertificateDer::pem_slice_iter(chain.as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow::anyhow!("Invalid certificate chain: {e}"))?,
+            None => Vec::new(),
+        };
+        let bundle: RekorBundle = serde_json::from_str(
+            annotations
+                .get(BUNDLE_ANNOTATION)
+                .context("Signature was not recorded in the Rekor transparency log")?,
+        )
+        .context("Invalid Rekor bundle")?;
+        self.verify_log_entry(&bundle, payload, signature, &leaf)?;
+
+        let signed_at =
+            UnixTime::since_unix_epoch(Duration::from_secs(bundle.payload.integrated_time));
+        // Only the certificate authorities that were valid when the signature was recorded
+        let anchors = self
+            .roots
+            .iter()
+            .filter(|root| root.validity.contains(bundle.payload.integrated_time))
+            .map(|root| webpki::anchor_from_trusted_cert(&root.certificate))
+            .collect::<Result<Vec<_>, _>>()?;
+        EndEntityCert::try_from(&leaf)
+            .map_err(|e| anyhow::anyhow!("Invalid signing certificate: {e}"))?
+            .verify_for_usage(
+                webpki::ALL_VERIFICATION_ALGS,
+                &anchors,
+                &intermediates,
+                signed_at,
+                KeyUsage::required(CODE_SIGNING),
+                None,
+                None,
+            )
+            .map_err(|e| {
+                anyhow::anyhow!("Signing certificate isn't trusted when it was used: {e}")
+            })?;
+
+        let (_, certificate) = X509Certificate::from_der(&leaf)
+            .map_err(|e| anyhow::anyhow!("Invalid signing certificate: {e}"))?;
+        let key = VerifyingKey::from_public_key_der(certificate.public_key().raw)
+            .map_err(|e| anyhow::anyhow!("Unsupported signing certificate key: {e}"))?;
+        key.verify(payload, &decode_signature(signature)?)
+            .map_err(|_| anyhow::anyhow!("Signature doesn't match the signing certificate"))?;
+        self.check_identity(&certificate)
+    }
+
+    /// Checks that the Rekor entry is for this signature and that its signed entry timestamp is
+    /// signed by a trusted Rekor key. Without one, the time the entry claims to be recorded at
+    /// can't be trusted, so verification fails.
+    fn verify_log_entry(
+        &self,
+        bundle: &RekorBundle,
+        payload: &[u8],
+        signature: &str,
+        leaf: &CertificateDer<'_>,
+    ) -> Result<()> {
+        let base64 = base64::engine::general_purpose::STANDARD;
+        if self.rekor_keys.is_empty() {
+            bail!(
+                "No Rekor key is trusted, so the time the signature was recorded can't be verified"
+            );
+        }
+        let timestamp = decode_signature(&bundle.signed_entry_timestamp)?;
+        let signed = serde_json::to_vec(&bundle.payload)?;
+        let entry = &bundle.payload;
+        let signed_by_log = self.rekor_keys.iter().any(|rekor| {
+            rekor
+                .log_id
+                .as_ref()
+                .is_none_or(|log_id| log_id.eq_ignore_ascii_case(&entry.log_id))
+                && rekor.validity.contains(entry.integrated_time)
+                && rekor.key.verify(&signed, &timestamp).is_ok()
+        });
+        if !signed_by_log {
+            bail!(
+                "Rekor bundle isn't signed by the Rekor key of log {}",
+                entry.log_id
+            );
+        }
+
+        let body: Value = serde_json::from_slice(
+            &base64
+                .decode(&bundle.payload.body)
+                .context("Rekor entry is not valid base64")?,
+        )
+        .context("Invalid Rekor entry")?;
+        let spec = &body["spec"];
+        if spec["data"]["hash"]["value"].as_str() != Some(sha256_hex(payload).as_str()) {
+            bail!("Rekor entry is for another payload");
+        }
+        if spec["signature"]["content"].as_str().map(str::trim) != Some(signature.trim()) {
+            bail!("Rekor entry is for another signature");
+        }
+        let logged = spec["signature"]["publicKey"]["content"]
+            .as_str()
+            .and_then(|content| base64.decode(content).ok())
+            .and_then(|pem| CertificateDer::from_pem_slice(&pem).ok());
+        if logged.as_ref() != Some(leaf) {
+            bail!("Rekor entry is for another certificate");
+        }
+        Ok(())
+    }
+
+    fn check_identity(&self, certificate: &X509Certificate<'_>) -> Result<()> {
+        if let Some(expected) = &self.identity.issuer {
+            let issuer = extension_value(certificate, ISSUER_OID, LEGACY_ISSUER_OID)
+                .context("Signing certificate has no issuer")?;
+            if &issuer != expected {
+                bail!("Signing certificate was issued for {issuer}, not {expected}");
+            }
+        }
+        if let Some(pattern) = &self.subject {
+            let subjects: Vec<&str> = certificate
+                .subject_alternative_name()
+                .ok()
+                .flatten()
+                .map(|san| {
+                    san.value
+                        .general_names
+                        .iter()
+                        .filter_map(|name| match name {
+                            GeneralName::RFC822Name(name) | GeneralName::URI(name) => Some(*name),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            if !subjects.iter().any(|subject| pattern.is_match(subject)) {
+                bail!(
+                    "Signing certificate subject {} doesn't match {}",
+                    subjects.join(", "),
+                    self.identity.subject.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        if let Some(expected) = &self.identity.workflow_ref {
+            let workflow_ref = extension_value(
+                certificate,
+                SOURCE_REPOSITORY_REF_OID,
+                LEGACY_WORKFLOW_REF_OID,
+            )
+            .context("Signing certificate has no workflow ref")?;
+            if &workflow_ref != expected {
+                bail!("Signing certificate is for {workflow_ref}, not {expected}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of a Fulcio extension, from its current OID, which holds a DER UTF8String, or else
+/// its deprecated one, which holds the raw string
+fn extension_value(
+    certificate: &X509Certificate<'_>,
+    oid: &str,
+    legacy_oid: &str,
+) -> Option<String> {
+    let find = |oid: &str| {
+        certificate
+            .extensions()
+            .iter()
+            .find(|extension| extension.oid.to_id_string() == oid)
+    };
+    if let Some(extension) = find(oid) {
+        let (_, value) = Utf8String::from_der(extension.value).ok()?;
+        return Some(value.string());
+    }
+    find(legacy_oid).and_then(|extension| String::from_utf8(extension.value.to_vec()).ok())
+}
+
+/// Decodes a base64 DER or fixed-size ECDSA signature
+pub(crate) fn decode_signature(signature: &str) -> Result<Signature> {
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature.trim())
+        .context("Signature is not valid base64")?;
+    Signature::from_der(&signature)
+        .or_else(|_| Signature::from_slice(&signature))
+        .map_err(|e| anyhow::anyhow!("Invalid ECDSA signature: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::signature::Signer as _;
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::{DecodePrivateKey as _, EncodePublicKey as _, LineEnding};
+    use rcgen::{
+        date_time_ymd, BasicConstraints, CertificateParams, CustomExtension,
+        ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, SanType, PKCS_ECDSA_P256_SHA256,
+        PKCS_ECDSA_P384_SHA384,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::signature::SIGNATURE_ANNOTATION;
+    use crate::signing::simple_signing_payload;
+
+    const DIGEST: &str = "sha256:6a0c1a0b7c3b1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e";
+    const ISSUER: &str = "https://token.actions.githubusercontent.com";
+    const SUBJECT: &str =
+        "https://github.com/microsoft/wassette/.github/workflows/release.yml@refs/heads/main";
+    /// 2024-01-01T12:00:00Z, while the signing certificate is valid
+    const SIGNED_AT: u64 = 1_704_110_400;
+
+    fn base64(data: impl AsRef<[u8]>) -> String {
+        base64::engine::general_purpose::STANDARD.encode(data)
+    }
+
+    /// A DER UTF8String, as Fulcio writes its extensions
+    fn utf8_string(value: &str) -> Vec<u8> {
+        [&[0x0c, value.len() as u8], value.as_bytes()].concat()
+    }
+
+    struct Signed {
+        root: String,
+        rekor_key: SigningKey,
+        payload: Vec<u8>,
+        annotations: BTreeMap<String, String>,
+    }
+
+    impl Signed {
+        fn rekor_public_key(&self) -> String {
+            self.rekor_key
+                .verifying_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap()
+        }
+
+        /// Replaces the Rekor bundle with one recorded at `integrated_time`
+        fn record(&mut self, integrated_time: u64) {
+            let certificate = &self.annotations[CERTIFICATE_ANNOTATION];
+            let body = json!({
+                "apiVersion": "0.0.1",
+                "kind": "hashedrekord",
+                "spec": {
+                    "data": {"hash": {"algorithm": "sha256", "value": sha256_hex(&self.payload)}},
+                    "signature": {
+                        "content": self.annotations[SIGNATURE_ANNOTATION],
+                        "publicKey": {"content": base64(certificate)},
+                    },
+                },
+            });
+            let payload = RekorPayload {
+                body: base64(body.to_string()),
+                integrated_time,
+                log_id: "c0d23d6ad406973f".to_string(),
+                log_index: 42,
+            };
+            let timestamp: Signature = self.rekor_key.sign(&serde_json::to_vec(&payload).unwrap());
+            let bundle = json!({
+                "SignedEntryTimestamp": base64(timestamp.to_der()),
+                "Payload": payload,
+            });
+            self.annotations
+                .insert(BUNDLE_ANNOTATION.to_string(), bundle.to_string());
+        }
+    }
+
+    /// Signs a payload keylessly with a certificate for [`SUBJECT`] issued by a new root
+    fn sign_keylessly() -> Signed {
+        let root_key = KeyPair::generate_for(&PKCS_ECDSA_P384_SHA384).unwrap();
+        let mut root_params = CertificateParams::new(Vec::new()).unwrap();
+        root_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let root = root_params.self_signed(&root_key).unwrap();
+        let issuer = Issuer::new(root_params, root_key);
+
+        let leaf_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.subject_alt_names = vec![SanType::URI(SUBJECT.try_into().unwrap())];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::CodeSigning];
+        params.custom_extensions = vec![
+            CustomExtension::from_oid_content(
+                &[1, 3, 6, 1, 4, 1, 57264, 1, 8],
+                utf8_string(ISSUER),
+            ),
+            CustomExtension::from_oid_content(
+                &[1, 3, 6, 1, 4, 1, 57264, 1, 14],
+                utf8_string("refs/heads/main"),
+            ),
+        ];
+        params.not_before = date_time_ymd(2024, 1, 1);
+        params.not_after = date_time_ymd(2024, 1, 2);
+        let leaf = params.signed_by(&leaf_key, &issuer).unwrap();
+
+        let key = SigningKey::from_pkcs8_der(&leaf_key.serialize_der()).unwrap();
+        let payload = simple_signing_payload("ghcr.io/microsoft/fetch-rs", DIGEST);
+        let signature: Signature = key.sign(&payload);
+        let mut signed = Signed {
+            root: root.pem(),
+            rekor_key: SigningKey::from_slice(&[3u8; 32]).unwrap(),
+            payload,
+            annotations: BTreeMap::from([
+                (SIGNATURE_ANNOTATION.to_string(), base64(signature.to_der())),
+                (CERTIFICATE_ANNOTATION.to_string(), leaf.pem()),
+                (CHAIN_ANNOTATION.to_string(), root.pem()),
+            ]),
+        };
+        signed.record(SIGNED_AT);
+        signed
+    }
+
+    fn identity() -> CertificateIdentity {
+        CertificateIdentity {
+            issuer: Some(ISSUER.to_string()),
+            subject: Some(r"https://github\.com/microsoft/.+".to_string()),
+            workflow_ref: Some("refs/heads/main".to_string()),
+        }
+    }
+
+    fn verify(signed: &Signed, identity: CertificateIdentity) -> Result<()> {
+        SignatureVerifier::keyless(&signed.root, identity)?
+            .with_rekor_key(&signed.rekor_public_key())?
+            .verify_layer(&signed.payload, &signed.annotations, DIGEST)
+    }
+
+    #[test]
+    fn test_verify_keyless() {
+        let mut signed = sign_keylessly();
+        verify(&signed, identity()).unwrap();
+
+        let error = |identity| verify(&signed, identity).unwrap_err().to_string();
+        assert!(error(CertificateIdentity {
+            subject: Some(r"https://github\.com/contoso/.+".to_string()),
+            ..identity()
+        })
+        .contains("doesn't match"));
+        // The subject must match as a whole
+        assert!(error(CertificateIdentity {
+            subject: Some("https://github.com/microsoft".to_string()),
+            ..identity()
+        })
+        .contains("doesn't match"));
+        assert!(error(CertificateIdentity {
+            issuer: Some("https://accounts.google.com".to_string()),
+            ..identity()
+        })
+        .contains("was issued for"));
+        assert!(error(CertificateIdentity {
+            workflow_ref: Some("refs/heads/dev".to_string()),
+            ..identity()
+        })
+        .contains("not refs/heads/dev"));
+
+        // Signed by a certificate of another root
+        let other = sign_keylessly();
+        let err = SignatureVerifier::keyless(&other.root, identity())
+            .unwrap()
+            .with_rekor_key(&signed.rekor_public_key())
+            .unwrap()
+            .verify_layer(&signed.payload, &signed.annotations, DIGEST)
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't trusted"), "{err}");
+
+        // Without a Rekor key, the time the entry claims can't be trusted
+        let err = SignatureVerifier::keyless(&signed.root, identity())
+            .unwrap()
+            .verify_layer(&signed.payload, &signed.annotations, DIGEST)
+            .unwrap_err();
+        assert!(err.to_string().contains("No Rekor key"), "{err}");
+
+        // Recorded after the certificate expired
+        signed.record(SIGNED_AT + 7 * 24 * 3600);
+        let err = verify(&signed, identity()).unwrap_err();
+        assert!(err.to_string().contains("isn't trusted"), "{err}");
+
+        // A Rekor bundle that wasn't signed by Rekor
+        let mut bundle: Value =
+            serde_json::from_str(&signed.annotations[BUNDLE_ANNOTATION]).unwrap();
+        bundle["Payload"]["integratedTime"] = json!(SIGNED_AT);
+        signed
+            .annotations
+            .insert(BUNDLE_ANNOTATION.to_string(), bundle.to_string());
+        let err = verify(&signed, identity()).unwrap_err();
+        assert!(err.to_string().contains("Rekor key"), "{err}");
+
+        // Signed with a key rather than a certificate
+        let err = verify(
+            &Signed {
+                annotations: BTreeMap::from([(
+                    SIGNATURE_ANNOTATION.to_string(),
+                    signed.annotations[SIGNATURE_ANNOTATION].clone(),
+                )]),
+                ..sign_keylessly()
+            },
+            identity(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("not signed keylessly"), "{err}");
+    }
+
+    /// A `trusted_root.json` with the root of `signed`, valid until `ca_end`, and its Rekor log
+    fn trusted_root(signed: &Signed, ca_end: &str, log_id: &[u8]) -> String {
+        let root = CertificateDer::from_pem_slice(signed.root.as_bytes()).unwrap();
+        let rekor_key = signed
+            .rekor_key
+            .verifying_key()
+            .to_public_key_der()
+            .unwrap();
+        json!({
+            "mediaType": "application/vnd.dev.sigstore.trustedroot+json;version=0.1",
+            "tlogs": [{
+                "baseUrl": "https://rekor.internal",
+                "hashAlgorithm": "SHA2_256",
+                "publicKey": {
+                    "rawBytes": base64(rekor_key.as_bytes()),
+                    "keyDetails": "PKIX_ECDSA_P256_SHA_256",
+                    "validFor": {"start": "2023-01-01T00:00:00Z"},
+                },
+                "logId": {"keyId": base64(log_id)},
+            }],
+            "certificateAuthorities": [{
+                "subject": {"organization": "internal", "commonName": "fulcio"},
+                "uri": "https://fulcio.internal",
+                "certChain": {"certificates": [{"rawBytes": base64(&root)}]},
+                "validFor": {"start": "2023-01-01T00:00:00Z", "end": ca_end},
+            }],
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_verify_keyless_with_trusted_root() {
+        let signed = sign_keylessly();
+        let log_id = [0xc0, 0xd2, 0x3d, 0x6a, 0xd4, 0x06, 0x97, 0x3f];
+        let verify = |root: &str| {
+            SignatureVerifier::keyless_with_trusted_root(&TrustedRoot::parse(root)?, identity())?
+                .verify_layer(&signed.payload, &signed.annotations, DIGEST)
+        };
+        verify(&trusted_root(&signed, "2030-01-01T00:00:00Z", &log_id)).unwrap();
+
+        // The certificate authority was retired before the signature was recorded
+        let err = verify(&trusted_root(&signed, "2023-12-31T00:00:00Z", &log_id)).unwrap_err();
+        assert!(err.to_string().contains("isn't trusted"), "{err}");
+
+        // Recorded by a log the trusted root doesn't have
+        let err = verify(&trusted_root(&signed, "2030-01-01T00:00:00Z", &[1, 2, 3])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Rekor key of log c0d23d6ad406973f"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_certificate_identity_validate() {
+        identity().validate().unwrap();
+        let keyless = KeylessVerification {
+            fulcio_root: Some("file:///fulcio.pem".to_string()),
+            trusted_root: None,
+            rekor_public_key: Some("file:///rekor.pub".to_string()),
+            identity: identity(),
+        };
+        keyless.validate().unwrap();
+        assert!(KeylessVerification {
+            rekor_public_key: None,
+            ..keyless.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(KeylessVerification {
+            trusted_root: Some("file:///trusted_root.json".to_string()),
+            ..keyless.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(KeylessVerification {
+            fulcio_root: None,
+            ..keyless
+        }
+        .validate()
+        .is_err());
+        assert!(CertificateIdentity::default().validate().is_err());
+        assert!(CertificateIdentity {
+            subject: Some("(unclosed".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,650 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wasi:keyvalue`
+//!
+//! Buckets are stored in an embedded [redb](https://docs.rs/redb) database in the plugin
+//! directory. Every component gets its own namespace, so two components opening a bucket called
+//! `cache` never see each other's data, and a component can only open the buckets listed under
+//! `permissions.keyvalue.allow` in its policy.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use redb::{Database, ReadableTable, TableDefinition, TableError, TableHandle};
+use tracing::debug;
+use wasmtime::component::{Resource, ResourceTable, ResourceTableError};
+
+use self::generated::wasi::keyvalue;
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/keyvalue",
+        world: "wasi:keyvalue/imports",
+        trappable_imports: true,
+        with: {
+            "wasi:keyvalue/store/bucket": crate::keyvalue::Bucket,
+        },
+        trappable_error_type: {
+            "wasi:keyvalue/store/error" => crate::keyvalue::Error,
+        },
+    });
+}
+
+/// File name of the key-value database in the plugin directory
+pub(crate) const KEYVALUE_DB_FILE: &str = "keyvalue.redb";
+
+/// Number of keys returned by a single `list-keys` call
+const LIST_KEYS_PAGE_SIZE: usize = 1000;
+
+pub enum Error {
+    NoSuchStore,
+    AccessDenied,
+    Other(String),
+}
+
+impl From<ResourceTableError> for Error {
+    fn from(err: ResourceTableError) -> Self {
+        Self::Other(err.to_string())
+    }
+}
+
+fn other(err: impl Display) -> Error {
+    Error::Other(err.to_string())
+}
+
+/// An open bucket. It holds the name of the database table backing the bucket.
+pub struct Bucket {
+    table: String,
+}
+
+impl Bucket {
+    fn definition(&self) -> TableDefinition<'_, &'static str, &'static [u8]> {
+        TableDefinition::new(&self.table)
+    }
+}
+
+/// The buckets of a component with their entries, by bucket name and key
+pub(crate) type Buckets = BTreeMap<String, BTreeMap<String, Vec<u8>>>;
+
+/// Name of the table that stores `bucket` for `component_id`
+fn table_name(component_id: &str, bucket: &str) -> String {
+    format!("{component_id}/{bucket}")
+}
+
+/// The key-value database shared by all components. It is opened on first use so that commands
+/// which never run a component don't contend for the database lock.
+#[derive(Clone)]
+pub(crate) struct KeyValueStore {
+    path: PathBuf,
+    db: Arc<Mutex<Option<Arc<Database>>>>,
+}
+
+impl KeyValueStore {
+    pub(crate) fn new(plugin_dir: &Path) -> Self {
+        Self {
+            path: plugin_dir.join(KEYVALUE_DB_FILE),
+            db: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn database(&self) -> Result<Arc<Database>, Error> {
+        let mut db = self.db.lock().map_err(other)?;
+        if let Some(db) = db.as_ref() {
+            return Ok(db.clone());
+        }
+        let opened = Arc::new(Database::create(&self.path).map_err(other)?);
+        *db = Some(opened.clone());
+        Ok(opened)
+    }
+
+    fn open(&self) -> anyhow::Result<Arc<Database>> {
+        self.database().map_err(|e| match e {
+            Error::Other(e) => anyhow::anyhow!(e),
+            _ => anyhow::anyhow!("Failed to open key-value store"),
+        })
+    }
+
+    /// Path of the database file
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The IDs of the components that have buckets
+    pub(crate) fn component_ids(&self) -> anyhow::Result<BTreeSet<String>> {
+        if !self.path.exists() {
+            return Ok(BTreeSet::new());
+        }
+        let db = self.open()?;
+        let txn = db.begin_read()?;
+        let ids = txn
+            .list_tables()?
+            .filter_map(|table| {
+                let (component_id, _) = table.name().split_once('/')?;
+                Some(component_id.to_string())
+            })
+            .collect();
+        Ok(ids)
+    }
+
+    /// Deletes every bucket of a component. Returns the number of deleted buckets.
+    pub(crate) fn delete_component(&self, component_id: &str) -> anyhow::Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let db = self.open()?;
+
+        let prefix = table_name(component_id, "");
+        let txn = db.begin_write()?;
+        let tables: Vec<String> = txn
+            .list_tables()?
+            .map(|table| table.name().to_string())
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        for table in &tables {
+            txn.delete_table(TableDefinition::<&str, &[u8]>::new(table))?;
+        }
+        txn.commit()?;
+
+        debug!(
+            component_id,
+            buckets = tables.len(),
+            "Deleted key-value buckets"
+        );
+        Ok(tables.len())
+    }
+
+    /// Every bucket of a component with its entries
+    pub(crate) fn export_component(&self, component_id: &str) -> anyhow::Result<Buckets> {
+        if !self.path.exists() {
+            return Ok(Buckets::new());
+        }
+        let db = self.open()?;
+
+        let prefix = table_name(component_id, "");
+        let txn = db.begin_read()?;
+        let mut buckets = Buckets::new();
+        for table in txn.list_tables()? {
+            let Some(bucket) = table.name().strip_prefix(&prefix) else {
+                continue;
+            };
+            let mut entries = BTreeMap::new();
+            for entry in txn
+                .open_table(TableDefinition::<&str, &[u8]>::new(table.name()))?
+                .iter()?
+            {
+                let (key, value) = entry?;
+                entries.insert(key.value().to_string(), value.value().to_vec());
+            }
+            buckets.insert(bucket.to_string(), entries);
+        }
+        Ok(buckets)
+    }
+
+    /// Replaces every bucket of a component with `buckets`
+    pub(crate) fn import_component(
+        &self,
+        component_id: &str,
+        buckets: &Buckets,
+    ) -> anyhow::Result<()> {
+        let db = self.open()?;
+
+        let prefix = table_name(component_id, "");
+        let txn = db.begin_write()?;
+        let stale: Vec<String> = txn
+            .list_tables()?
+            .map(|table| table.name().to_string())
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        for table in &stale {
+            txn.delete_table(TableDefinition::<&str, &[u8]>::new(table))?;
+        }
+        for (bucket, entries) in buckets {
+            let name = table_name(component_id, bucket);
+            let mut table = txn.open_table(TableDefinition::<&str, &[u8]>::new(&name))?;
+            for (key, value) in entries {
+                table.insert(key.as_str(), value.as_slice())?;
+            }
+        }
+        txn.commit()?;
+
+        debug!(
+            component_id,
+            buckets = buckets.len(),
+            "Imported key-value buckets"
+        );
+        Ok(())
+    }
+}
+
+/// The `wasi:keyvalue` state of a single component instance
+#[derive(Default)]
+pub(crate) struct KeyValueCtx {
+    store: Option<KeyValueStore>,
+    component_id: String,
+    buckets: HashSet<String>,
+}
+
+impl KeyValueCtx {
+    /// Creates a context that may open the given buckets once it is bound to a store
+    pub(crate) fn new(buckets: HashSet<String>) -> Self {
+        Self {
+            buckets,
+            ..Default::default()
+        }
+    }
+
+    /// Binds the context to the store and the namespace of `component_id`
+    pub(crate) fn bind(&mut self, store: KeyValueStore, component_id: &str) {
+        self.store = Some(store);
+        self.component_id = component_id.to_string();
+    }
+
+    fn store(&self) -> Result<&KeyValueStore, Error> {
+        self.store.as_ref().ok_or(Error::NoSuchStore)
+    }
+}
+
+/// A view of a component's `wasi:keyvalue` state used by the host bindings
+pub(crate) struct KeyValueHost<'a> {
+    ctx: &'a KeyValueCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> KeyValueHost<'a> {
+    pub(crate) fn new(ctx: &'a KeyValueCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    /// Runs `f` on the bucket's table in a read transaction. `f` gets `None` if nothing was
+    /// ever written to the bucket.
+    fn read<R>(
+        &mut self,
+        bucket: &Resource<Bucket>,
+        f: impl FnOnce(Option<&redb::ReadOnlyTable<&'static str, &'static [u8]>>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let bucket = self.table.get(bucket)?;
+        let txn = self.ctx.store()?.database()?.begin_read().map_err(other)?;
+        match txn.open_table(bucket.definition()) {
+            Ok(table) => f(Some(&table)),
+            Err(TableError::TableDoesNotExist(_)) => f(None),
+            Err(e) => Err(other(e)),
+        }
+    }
+
+    /// Runs `f` on the bucket's table in a write transaction and commits the transaction
+    fn write<R>(
+        &mut self,
+        bucket: &Resource<Bucket>,
+        f: impl FnOnce(&mut redb::Table<&'static str, &'static [u8]>) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let bucket = self.table.get(bucket)?;
+        let txn = self.ctx.store()?.database()?.begin_write().map_err(other)?;
+        let result = {
+            let mut table = txn.open_table(bucket.definition()).map_err(other)?;
+            f(&mut table)?
+        };
+        txn.commit().map_err(other)?;
+        Ok(result)
+    }
+}
+
+impl keyvalue::store::Host for KeyValueHost<'_> {
+    fn open(&mut self, identifier: String) -> Result<Resource<Bucket>, Error> {
+        if !self.ctx.buckets.contains(&identifier) {
+            debug!(
+                component_id = %self.ctx.component_id,
+                bucket = %identifier,
+                "Key-value bucket denied by policy"
+            );
+            return Err(Error::AccessDenied);
+        }
+        self.ctx.store()?;
+        Ok(self.table.push(Bucket {
+            table: table_name(&self.ctx.component_id, &identifier),
+        })?)
+    }
+
+    fn convert_error(&mut self, err: Error) -> wasmtime::Result<keyvalue::store::Error> {
+        match err {
+            Error::NoSuchStore => Ok(keyvalue::store::Error::NoSuchStore),
+            Error::AccessDenied => Ok(keyvalue::store::Error::AccessDenied),
+            Error::Other(e) => Ok(keyvalue::store::Error::Other(e)),
+        }
+    }
+}
+
+impl keyvalue::store::HostBucket for KeyValueHost<'_> {
+    fn get(&mut self, bucket: Resource<Bucket>, key: String) -> Result<Option<Vec<u8>>, Error> {
+        self.read(&bucket, |table| {
+            let Some(table) = table else {
+                return Ok(None);
+            };
+            Ok(table
+                .get(key.as_str())
+                .map_err(other)?
+                .map(|value| value.value().to_vec()))
+        })
+    }
+
+    fn set(&mut self, bucket: Resource<Bucket>, key: String, value: Vec<u8>) -> Result<(), Error> {
+        self.write(&bucket, |table| {
+            table
+                .insert(key.as_str(), value.as_slice())
+                .map_err(other)?;
+            Ok(())
+        })
+    }
+
+    fn delete(&mut self, bucket: Resource<Bucket>, key: String) -> Result<(), Error> {
+        self.write(&bucket, |table| {
+            table.remove(key.as_str()).map_err(other)?;
+            Ok(())
+        })
+    }
+
+    fn exists(&mut self, bucket: Resource<Bucket>, key: String) -> Result<bool, Error> {
+        self.read(&bucket, |table| match table {
+            Some(table) => Ok(table.get(key.as_str()).map_err(other)?.is_some()),
+            None => Ok(false),
+        })
+    }
+
+    fn list_keys(
+        &mut self,
+        bucket: Resource<Bucket>,
+        cursor: Option<u64>,
+    ) -> Result<keyvalue::store::KeyResponse, Error> {
+        let offset = cursor.unwrap_or(0) as usize;
+        self.read(&bucket, |table| {
+            let mut keys = Vec::new();
+            if let Some(table) = table {
+                for entry in table
+                    .iter()
+                    .map_err(other)?
+                    .skip(offset)
+                    .take(LIST_KEYS_PAGE_SIZE + 1)
+                {
+                    let (key, _) = entry.map_err(other)?;
+                    keys.push(key.value().to_string());
+                }
+            }
+            // The extra key only tells us whether there is another page
+            let cursor = (keys.len() > LIST_KEYS_PAGE_SIZE).then(|| {
+                keys.truncate(LIST_KEYS_PAGE_SIZE);
+                (offset + LIST_KEYS_PAGE_SIZE) as u64
+            });
+            Ok(keyvalue::store::KeyResponse { keys, cursor })
+        })
+    }
+
+    fn drop(&mut self, bucket: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.table.delete(bucket)?;
+        Ok(())
+    }
+}
+
+impl keyvalue::atomics::Host for KeyValueHost<'_> {
+    fn increment(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        delta: u64,
+    ) -> Result<u64, Error> {
+        self.write(&bucket, |table| {
+            // Counters are stored as decimal strings, like the in-memory wasmtime implementation
+            let current = match table.get(key.as_str()).map_err(other)? {
+                Some(value) => std::str::from_utf8(value.value())
+                    .map_err(other)?
+                    .parse::<u64>()
+                    .map_err(other)?,
+                None => 0,
+            };
+            let new_value = current
+                .checked_add(delta)
+                .ok_or_else(|| Error::Other(format!("Counter overflow for key {key}")))?;
+            table
+                .insert(key.as_str(), new_value.to_string().as_bytes())
+                .map_err(other)?;
+            Ok(new_value)
+        })
+    }
+}
+
+impl keyvalue::batch::Host for KeyValueHost<'_> {
+    fn get_many(
+        &mut self,
+        bucket: Resource<Bucket>,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<(String, Vec<u8>)>>, Error> {
+        self.read(&bucket, |table| {
+            let Some(table) = table else {
+                return Ok(keys.iter().map(|_| None).collect());
+            };
+            keys.into_iter()
+                .map(|key| {
+                    let value = table.get(key.as_str()).map_err(other)?;
+                    Ok(value.map(|value| (key.clone(), value.value().to_vec())))
+                })
+                .collect()
+        })
+    }
+
+    fn set_many(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key_values: Vec<(String, Vec<u8>)>,
+    ) -> Result<(), Error> {
+        self.write(&bucket, |table| {
+            for (key, value) in &key_values {
+                table
+                    .insert(key.as_str(), value.as_slice())
+                    .map_err(other)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_many(&mut self, bucket: Resource<Bucket>, keys: Vec<String>) -> Result<(), Error> {
+        self.write(&bucket, |table| {
+            for key in &keys {
+                table.remove(key.as_str()).map_err(other)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Adds the `wasi:keyvalue` interfaces to the linker
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> KeyValueHost<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    keyvalue::store::add_to_linker_get_host(linker, f)?;
+    keyvalue::atomics::add_to_linker_get_host(linker, f)?;
+    keyvalue::batch::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use keyvalue::atomics::Host as _;
+    use keyvalue::batch::Host as _;
+    use keyvalue::store::{Host as _, HostBucket as _};
+
+    use super::*;
+
+    fn ctx(store: &KeyValueStore, component_id: &str, buckets: &[&str]) -> KeyValueCtx {
+        let mut ctx = KeyValueCtx::new(buckets.iter().map(|b| b.to_string()).collect());
+        ctx.bind(store.clone(), component_id);
+        ctx
+    }
+
+    fn open(host: &mut KeyValueHost<'_>, bucket: &str) -> Resource<Bucket> {
+        match host.open(bucket.to_string()) {
+            Ok(bucket) => bucket,
+            Err(_) => panic!("bucket {bucket} should open"),
+        }
+    }
+
+    fn borrow(bucket: &Resource<Bucket>) -> Resource<Bucket> {
+        Resource::new_borrow(bucket.rep())
+    }
+
+    #[test]
+    fn test_bucket_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyValueStore::new(dir.path());
+        let ctx = ctx(&store, "cache-component", &["cache"]);
+        let mut table = ResourceTable::new();
+        let mut host = KeyValueHost::new(&ctx, &mut table);
+        let bucket = open(&mut host, "cache");
+
+        assert!(matches!(host.get(borrow(&bucket), "a".into()), Ok(None)));
+        assert!(host.set(borrow(&bucket), "a".into(), b"1".to_vec()).is_ok());
+        assert!(matches!(host.get(borrow(&bucket), "a".into()), Ok(Some(v)) if v == b"1"));
+        assert!(matches!(host.exists(borrow(&bucket), "a".into()), Ok(true)));
+
+        assert!(matches!(
+            host.increment(borrow(&bucket), "n".into(), 2),
+            Ok(2)
+        ));
+        assert!(matches!(
+            host.increment(borrow(&bucket), "n".into(), 3),
+            Ok(5)
+        ));
+
+        assert!(host
+            .set_many(
+                borrow(&bucket),
+                vec![("b".into(), b"2".to_vec()), ("c".into(), b"3".to_vec())]
+            )
+            .is_ok());
+        let Ok(values) = host.get_many(borrow(&bucket), vec!["b".into(), "missing".into()]) else {
+            panic!("get_many should succeed");
+        };
+        assert_eq!(values, vec![Some(("b".to_string(), b"2".to_vec())), None]);
+
+        let Ok(response) = host.list_keys(borrow(&bucket), None) else {
+            panic!("list_keys should succeed");
+        };
+        assert_eq!(response.keys, vec!["a", "b", "c", "n"]);
+        assert_eq!(response.cursor, None);
+
+        assert!(host.delete(borrow(&bucket), "a".into()).is_ok());
+        assert!(host
+            .delete_many(borrow(&bucket), vec!["b".into(), "c".into()])
+            .is_ok());
+        assert!(matches!(
+            host.exists(borrow(&bucket), "a".into()),
+            Ok(false)
+        ));
+    }
+
+    #[test]
+    fn test_buckets_are_gated_and_namespaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyValueStore::new(dir.path());
+
+        let first_ctx = ctx(&store, "first", &["cache"]);
+        let mut first_table = ResourceTable::new();
+        let mut first = KeyValueHost::new(&first_ctx, &mut first_table);
+        assert!(matches!(
+            first.open("secrets".to_string()),
+            Err(Error::AccessDenied)
+        ));
+        let bucket = open(&mut first, "cache");
+        assert!(first
+            .set(borrow(&bucket), "k".into(), b"v".to_vec())
+            .is_ok());
+
+        let second_ctx = ctx(&store, "second", &["cache"]);
+        let mut second_table = ResourceTable::new();
+        let mut second = KeyValueHost::new(&second_ctx, &mut second_table);
+        let bucket = open(&mut second, "cache");
+        assert!(matches!(second.get(borrow(&bucket), "k".into()), Ok(None)));
+
+        // A context that was never bound to a store has no buckets at all
+        let unbound = KeyValueCtx::new(HashSet::from(["cache".to_string()]));
+        let mut unbound_table = ResourceTable::new();
+        let mut unbound = KeyValueHost::new(&unbound, &mut unbound_table);
+        assert!(matches!(
+            unbound.open("cache".to_string()),
+            Err(Error::NoSuchStore)
+        ));
+    }
+
+    #[test]
+    fn test_delete_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyValueStore::new(dir.path());
+        assert_eq!(store.delete_component("first").unwrap(), 0);
+
+        let ctx = ctx(&store, "first", &["cache", "state"]);
+        let mut table = ResourceTable::new();
+        let mut host = KeyValueHost::new(&ctx, &mut table);
+        for name in ["cache", "state"] {
+            let bucket = open(&mut host, name);
+            assert!(host.set(borrow(&bucket), "k".into(), b"v".to_vec()).is_ok());
+        }
+        let other_ctx = self::ctx(&store, "first-other", &["cache"]);
+        let mut other_table = ResourceTable::new();
+        let mut other = KeyValueHost::new(&other_ctx, &mut other_table);
+        let bucket = open(&mut other, "cache");
+        assert!(other
+            .set(borrow(&bucket), "k".into(), b"v".to_vec())
+            .is_ok());
+
+        assert_eq!(
+            store.component_ids().unwrap(),
+            ["first", "first-other"].map(String::from).into()
+        );
+
+        assert_eq!(store.delete_component("first").unwrap(), 2);
+        assert_eq!(
+            store.component_ids().unwrap(),
+            ["first-other".to_string()].into()
+        );
+        let bucket = open(&mut other, "cache");
+        assert!(matches!(
+            other.exists(borrow(&bucket), "k".into()),
+            Ok(true)
+        ));
+    }
+
+    #[test]
+    fn test_export_import_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyValueStore::new(dir.path());
+        assert!(store.export_component("first").unwrap().is_empty());
+
+        let ctx = ctx(&store, "first", &["cache", "state"]);
+        let mut table = ResourceTable::new();
+        let mut host = KeyValueHost::new(&ctx, &mut table);
+        let bucket = open(&mut host, "cache");
+        assert!(host.set(borrow(&bucket), "k".into(), b"v".to_vec()).is_ok());
+        let bucket = open(&mut host, "state");
+        assert!(host
+            .set(borrow(&bucket), "count".into(), b"1".to_vec())
+            .is_ok());
+
+        let buckets = store.export_component("first").unwrap();
+        assert_eq!(
+            buckets,
+            Buckets::from([
+                ("cache".into(), [("k".into(), b"v".to_vec())].into()),
+                ("state".into(), [("count".into(), b"1".to_vec())].into()),
+            ])
+        );
+
+        let target = KeyValueStore::new(&dir.path().join("target"));
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        target
+            .import_component("first", &[("stale".into(), BTreeMap::new())].into())
+            .unwrap();
+        target.import_component("first", &buckets).unwrap();
+        assert_eq!(target.export_component("first").unwrap(), buckets);
+    }
+}
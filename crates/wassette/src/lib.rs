@@ -13,34 +13,155 @@ use std::time::Instant;
 
 use anyhow::{anyhow, bail, Context, Result};
 use component2json::{
-    component_exports_to_json_schema, component_exports_to_tools, create_placeholder_results,
-    json_to_vals, vals_to_json, FunctionIdentifier, ToolMetadata,
+    component_exports_to_tools, create_placeholder_results, json_to_vals, vals_to_json,
+    FunctionIdentifier, ToolMetadata,
 };
 use policy::PolicyParser;
-use serde_json::Value;
+use serde_json::{json, Value};
 use tokio::fs::DirEntry;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 use tracing::{debug, info, instrument, warn};
 use wasmtime::component::{Component, InstancePre, Linker};
 use wasmtime::{Engine, Store};
-use wasmtime_wasi_config::WasiConfig;
-
+use wasmtime_wasi_config::{WasiConfig, WasiConfigVariables};
+
+mod artifacts;
+mod blobstore;
+mod bundle;
+mod calls;
+mod channels;
+mod component_index;
+mod config;
+mod delegation;
+mod demo;
+mod detached;
+mod determinism;
+mod dotenv;
+mod event_log;
+mod events;
+mod extension;
+mod history;
 mod http;
+mod http_cache;
+mod inspection;
+mod keychain;
+mod keyless;
+mod keyvalue;
 mod loader;
+mod logging;
+mod management;
+mod manifest;
+mod naming;
+mod nats;
+mod network_log;
+mod otlp;
+mod output;
+mod policy_check;
+mod policy_edit;
 mod policy_internal;
+mod profiling;
+mod proxy;
+mod recall;
+mod sampling;
+mod secrets;
+mod sessions;
+mod signature;
+mod signing;
+mod sockets;
+mod sql;
+mod tls;
+mod trash;
+mod trusted_root;
+mod verification;
+mod verification_cache;
 mod wasistate;
 
+pub use artifacts::{ArtifactKind, OrphanedArtifact};
+pub use bundle::{BundleManifest, BundledComponent, SecretsEncryption, BUNDLE_FORMAT_VERSION};
+pub use channels::{
+    AvailableUpdate, Channel, LockedComponent, Lockfile, ResolvedVersion, UpdateReport, LOCKFILE,
+};
+pub use component_index::{
+    ComponentIndex, ComponentSearch, IndexedComponent, KEYWORDS_ANNOTATION, REF_ANNOTATION,
+};
+pub use delegation::{DelegatedDir, DelegationClaims, DelegationScope, DELEGATION_TOKEN_TTL};
+pub use demo::{demo_fixture_urls, DemoWorkspace};
+pub use detached::DetachedSignature;
+pub use dotenv::parse_dotenv;
+pub use event_log::{EventQuery, LoggedEvent, DEFAULT_EVENT_RETENTION, EVENT_LOG_FILE};
+use events::EventBus;
+pub use events::{
+    CloudEvent, Event, EventSink, LoadStage, CLOUDEVENTS_SPEC_VERSION, EVENT_SCHEMA_VERSION,
+    EVENT_SOURCE,
+};
+pub use extension::{ExtensionGrant, ExtensionLinker, HostExtension};
+use history::HistoryEntry;
+pub use history::{HistoryHit, HistoryKind, HistoryQuery};
+use http::RequestLimits;
 pub use http::WassetteWasiState;
-use loader::{ComponentResource, PolicyResource};
+pub use inspection::{
+    Capability, CapabilityRules, ComponentInspector, ExternalScanner, InspectedComponent,
+    InspectionAction, InspectionConfig, InspectionFinding, InspectionFuture, ScannerConfig,
+};
+pub use keychain::{KeychainSecretProvider, KEYCHAIN_SECRET_PROVIDER};
+pub use keyless::{CertificateIdentity, KeylessVerification};
+use loader::{DownloadedResource, PolicyResource};
+pub use logging::{ComponentLog, LogLevel, COMPONENT_LOG_TARGET};
+pub use management::{
+    ComponentMetrics, ComponentSummary, ManagementApi, ManagementMetrics, ToolMetrics,
+};
+pub use manifest::{
+    DesiredComponent, DesiredStateManifest, Drift, ReconcileFailure, ReconcileReport, MANIFEST_FILE,
+};
+pub use naming::{ToolAlias, ToolCollision, ToolNaming, TOOL_PREFIX_SEPARATOR};
+pub use nats::{NatsClient, NatsMessage, DEFAULT_NATS_PORT};
+pub use network_log::{NetworkRequest, NetworkSampling, NETWORK_LOG_TARGET};
+pub use otlp::{OtlpMetricsConfig, DEFAULT_OTLP_INTERVAL};
+use output::OutputCapture;
+pub use output::{CapturedOutput, DEFAULT_OUTPUT_CAPTURE_LIMIT};
+pub use policy::{AccessType, HttpLimits, MemoryLimit, StoragePermission, TimeoutLimit};
+pub use policy_check::PolicyWarning;
+pub use policy_edit::{diff_policies, PolicyChange};
 use policy_internal::PolicyRegistry;
 pub use policy_internal::{PermissionGrantRequest, PermissionRule, PolicyInfo};
+pub use profiling::{
+    CallProfile, ProfilingConfig, DEFAULT_PROFILE_DIR, DEFAULT_PROFILE_INTERVAL,
+    MAX_PROFILES_PER_COMPONENT,
+};
+pub use proxy::ProxyConfig;
+pub use recall::{Embedder, Memory, RecallQuery};
+pub use sampling::{Sampler, SamplingMessage, SamplingRequest, SamplingResponse, SamplingRole};
+pub use secrets::{
+    builtin_secret_provider, FileSecretProvider, SecretFuture, SecretProvider,
+    DEFAULT_SECRET_WATCH_INTERVAL, FILE_SECRET_PROVIDER,
+};
+pub use signature::SignatureVerifier;
+pub use signing::{
+    ComponentSigner, KeylessConfig, SignedArtifact, DEFAULT_FULCIO_URL, DEFAULT_REKOR_URL,
+};
+pub use sockets::SocketGrant;
+pub use tls::{HostTlsConfig, TlsConfig};
+pub use trash::{TrashedComponent, TRASH_RETENTION};
+pub use trusted_root::TrustedRoot;
+pub use verification::{RegistryRule, VerificationPolicy, VerificationRules};
 use wasistate::WasiState;
 pub use wasistate::{
-    create_wasi_state_template_from_policy, CustomResourceLimiter, WasiStateTemplate,
+    create_wasi_state_template_from_policy, CustomResourceLimiter, TemplateContext,
+    WasiStateTemplate, DATA_DIR,
 };
 
 const DOWNLOADS_DIR: &str = "downloads";
 
+/// The doc comments of a loaded component's WIT world
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComponentDocs {
+    /// The doc comment of the world, which usually describes the component
+    pub description: Option<String>,
+    /// The doc comments of the exported functions, by tool name
+    pub tools: HashMap<String, String>,
+}
+
 #[derive(Debug, Clone)]
 struct ToolInfo {
     component_id: String,
@@ -48,10 +169,22 @@ struct ToolInfo {
     schema: Value,
 }
 
+/// Where a component call comes from
+#[derive(Clone, Copy, Default)]
+struct CallOrigin<'a> {
+    /// The components whose calls led to this one, the outermost first
+    callers: &'a [String],
+    /// The permissions delegated to the call, if another component made it
+    scope: Option<&'a DelegationScope>,
+    /// Where the components of the call send their sampling requests
+    sampler: Option<&'a Arc<dyn Sampler>>,
+}
+
 #[derive(Debug, Default)]
 struct ComponentRegistry {
     tool_map: HashMap<String, Vec<ToolInfo>>,
     component_map: HashMap<String, Vec<String>>,
+    naming: ToolNaming,
 }
 
 /// The returned status when loading a component
@@ -63,40 +196,122 @@ pub enum LoadResult {
     New,
 }
 
+/// Error of a request for a component that isn't loaded, or for a tool that no loaded component
+/// has. Callers can tell it from other failures with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentNotFound {
+    /// No loaded component has this ID
+    Component(String),
+    /// No loaded component has a tool by this name
+    Tool(String),
+}
+
+impl std::fmt::Display for ComponentNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComponentNotFound::Component(component_id) => {
+                write!(f, "Component not found: {component_id}")
+            }
+            ComponentNotFound::Tool(_) => write!(f, "Tool not found"),
+        }
+    }
+}
+
+impl std::error::Error for ComponentNotFound {}
+
 impl ComponentRegistry {
     fn new() -> Self {
         Self::default()
     }
 
+    /// Registers the tools of `component_id` under the names its [`ToolNaming`] gives them,
+    /// replacing the ones it had. Fails without changing anything if a name collides and
+    /// collisions are rejected.
     fn register_tools(&mut self, component_id: &str, tools: Vec<ToolMetadata>) -> Result<()> {
-        let mut tool_names = Vec::new();
-
+        let mut named = Vec::new();
         for tool_metadata in tools {
-            let tool_info = ToolInfo {
-                component_id: component_id.to_string(),
-                identifier: tool_metadata.identifier,
-                schema: tool_metadata.schema,
-            };
+            let mut name = self
+                .naming
+                .name(component_id, &tool_metadata.normalized_name);
+            if let Some(owner) = self.other_owner(&name, component_id) {
+                match self.naming.collision {
+                    ToolCollision::Reject => bail!(
+                        "Tool {name} of component {component_id} has the same name as a tool of component {owner}"
+                    ),
+                    ToolCollision::KeepFirst => {
+                        warn!(tool = %name, component_id, owner, "Left out a tool whose name is taken");
+                        continue;
+                    }
+                    ToolCollision::Prefix => {
+                        let prefixed = self
+                            .naming
+                            .prefixed(component_id, &tool_metadata.normalized_name);
+                        if let Some(owner) = self.other_owner(&prefixed, component_id) {
+                            bail!("Tool {prefixed} of component {component_id} has the same name as a tool of component {owner}");
+                        }
+                        warn!(tool = %name, component_id, owner, renamed = %prefixed, "Prefixed a tool whose name is taken");
+                        name = prefixed;
+                    }
+                }
+            }
+            let mut schema = tool_metadata.schema;
+            schema["name"] = Value::String(name.clone());
+            named.push((
+                name,
+                ToolInfo {
+                    component_id: component_id.to_string(),
+                    identifier: tool_metadata.identifier,
+                    schema,
+                },
+            ));
+        }
 
+        self.unregister_component(component_id);
+        let mut tool_names = Vec::new();
+        for (name, tool_info) in named {
             self.tool_map
-                .entry(tool_metadata.normalized_name.clone())
+                .entry(name.clone())
                 .or_default()
                 .push(tool_info);
-            tool_names.push(tool_metadata.normalized_name);
+            tool_names.push(name);
         }
-
         self.component_map
             .insert(component_id.to_string(), tool_names);
         Ok(())
     }
 
-    fn get_function_identifier(&self, tool_name: &str) -> Option<&FunctionIdentifier> {
+    /// The component other than `component_id` that has a tool called `tool_name`, if any
+    fn other_owner(&self, tool_name: &str, component_id: &str) -> Option<&str> {
         self.tool_map
-            .get(tool_name)
-            .and_then(|tool_infos| tool_infos.first())
+            .get(tool_name)?
+            .iter()
+            .find(|info| info.component_id != component_id)
+            .map(|info| info.component_id.as_str())
+    }
+
+    fn get_function_identifier(
+        &self,
+        component_id: &str,
+        tool_name: &str,
+    ) -> Option<&FunctionIdentifier> {
+        self.tool_map
+            .get(tool_name)?
+            .iter()
+            .find(|tool_info| tool_info.component_id == component_id)
             .map(|tool_info| &tool_info.identifier)
     }
 
+    /// The names of the exported functions behind the tools of `component_id`
+    fn component_function_names(&self, component_id: &str) -> Vec<String> {
+        self.component_map
+            .get(component_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|tool_name| self.get_function_identifier(component_id, tool_name))
+            .map(|identifier| identifier.function_name.clone())
+            .collect()
+    }
+
     fn unregister_component(&mut self, component_id: &str) {
         if let Some(tools) = self.component_map.remove(component_id) {
             for tool_name in tools {
@@ -110,14 +325,33 @@ impl ComponentRegistry {
         }
     }
 
+    fn component_tool_schemas<'a>(
+        &'a self,
+        component_id: &'a str,
+    ) -> impl Iterator<Item = &'a Value> + 'a {
+        self.component_map
+            .get(component_id)
+            .into_iter()
+            .flatten()
+            .filter_map(move |tool_name| {
+                self.tool_map
+                    .get(tool_name)?
+                    .iter()
+                    .find(|info| info.component_id == component_id)
+            })
+            .map(|info| &info.schema)
+    }
+
     fn get_tool_info(&self, tool_name: &str) -> Option<&Vec<ToolInfo>> {
         self.tool_map.get(tool_name)
     }
 
-    fn list_tools(&self) -> Vec<Value> {
+    fn list_tools(&self, visible: impl Fn(&str) -> bool) -> Vec<Value> {
         self.tool_map
             .values()
-            .flat_map(|tools| tools.iter().map(|t| t.schema.clone()))
+            .flatten()
+            .filter(|tool| visible(&tool.component_id))
+            .map(|tool| tool.schema.clone())
             .collect()
     }
 }
@@ -134,6 +368,30 @@ pub struct LifecycleManager {
     http_client: reqwest::Client,
     plugin_dir: PathBuf,
     environment_vars: HashMap<String, String>,
+    events: EventBus,
+    event_log: Option<Arc<event_log::EventLog>>,
+    keyvalue: keyvalue::KeyValueStore,
+    secrets: secrets::SecretManager,
+    component_indexes: Arc<component_index::ComponentIndexes>,
+    logs: logging::LogBus,
+    output_capture_limit: Option<usize>,
+    history: Option<Arc<history::HistoryIndex>>,
+    recall: Option<Arc<recall::RecallStore>>,
+    http_proxy: Option<Arc<ProxyConfig>>,
+    http_tls: Option<Arc<TlsConfig>>,
+    http_limits: RequestLimits,
+    http_cache: Option<http_cache::HttpCache>,
+    network_log: network_log::NetworkLog,
+    metrics: management::MetricsRecorder,
+    delegation: delegation::DelegationAuthority,
+    session_id: String,
+    session_owners: Arc<RwLock<sessions::SessionOwners>>,
+    client_session: Option<Arc<sessions::ClientSession>>,
+    strict_policy: bool,
+    demo_fixtures: bool,
+    verification_policy: Option<Arc<VerificationPolicy>>,
+    inspectors: Vec<Arc<dyn ComponentInspector>>,
+    profiling: Option<Arc<profiling::Profiling>>,
 }
 
 /// A representation of a loaded component instance. It contains both the base component info and a
@@ -180,6 +438,34 @@ impl LifecycleManager {
         environment_vars: HashMap<String, String>,
         oci_client: oci_client::Client,
         http_client: reqwest::Client,
+    ) -> Result<Self> {
+        Self::new_with_engine(plugin_dir, environment_vars, oci_client, http_client, &[]).await
+    }
+
+    /// Creates a lifecycle manager whose components can also import the host functions of
+    /// `extensions`. Each extension is only usable by components whose policy grants it.
+    #[instrument(skip_all, fields(plugin_dir = %plugin_dir.as_ref().display()))]
+    pub async fn new_with_extensions(
+        plugin_dir: impl AsRef<Path>,
+        environment_vars: HashMap<String, String>,
+        extensions: Vec<Arc<dyn HostExtension>>,
+    ) -> Result<Self> {
+        Self::new_with_engine(
+            plugin_dir,
+            environment_vars,
+            oci_client::Client::default(),
+            reqwest::Client::default(),
+            &extensions,
+        )
+        .await
+    }
+
+    async fn new_with_engine(
+        plugin_dir: impl AsRef<Path>,
+        environment_vars: HashMap<String, String>,
+        oci_client: oci_client::Client,
+        http_client: reqwest::Client,
+        extensions: &[Arc<dyn HostExtension>],
     ) -> Result<Self> {
         let components_dir = plugin_dir.as_ref();
 
@@ -190,6 +476,8 @@ impl LifecycleManager {
         let mut config = wasmtime::Config::new();
         config.wasm_component_model(true);
         config.async_support(true);
+        // Lets guest profiling sample calls; the epoch only advances once it is enabled
+        config.epoch_interruption(true);
         let engine = Arc::new(wasmtime::Engine::new(&config)?);
 
         // Create the lifecycle manager
@@ -199,6 +487,7 @@ impl LifecycleManager {
             environment_vars,
             oci_client,
             http_client,
+            extensions,
         )
         .await
     }
@@ -211,15 +500,18 @@ impl LifecycleManager {
         environment_vars: HashMap<String, String>,
         oci_client: oci_client::Client,
         http_client: reqwest::Client,
+        extensions: &[Arc<dyn HostExtension>],
     ) -> Result<Self> {
         info!("Creating new LifecycleManager");
 
         let mut registry = ComponentRegistry::new();
         let mut components = HashMap::new();
         let mut policy_registry = PolicyRegistry::default();
+        let session_id = uuid::Uuid::new_v4().to_string();
 
         let mut linker = Linker::new(engine.as_ref());
         wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
+        http::add_ip_name_lookup_to_linker(&mut linker)?;
 
         // Use the standard HTTP linker - filtering happens at WasiHttpView level
         wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
@@ -229,10 +521,38 @@ impl LifecycleManager {
             |h: &mut WassetteWasiState<WasiState>| WasiConfig::from(&h.inner.wasi_config_vars),
         )?;
 
+        keyvalue::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            keyvalue::KeyValueHost::new(&h.inner.keyvalue, &mut h.inner.table)
+        })?;
+
+        blobstore::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            blobstore::BlobstoreHost::new(&h.inner.blobstore, &mut h.inner.table)
+        })?;
+
+        sql::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            sql::SqlHost::new(&h.inner.sql, &mut h.inner.table)
+        })?;
+
+        calls::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            calls::CallHost::new(&h.inner.calls)
+        })?;
+
+        logging::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            &mut h.inner.logging
+        })?;
+
+        sampling::add_to_linker(&mut linker, |h: &mut WassetteWasiState<WasiState>| {
+            sampling::SamplingHost::new(&h.inner.sampling)
+        })?;
+
+        extension::add_to_linker(&mut linker, extensions)?;
+
         let linker = Arc::new(linker);
 
-        let loaded_components =
+        let mut loaded_components =
             load_components_parallel(plugin_dir.as_ref(), &engine, &linker).await?;
+        // Which of two components with the same tool name gets it must not depend on load order
+        loaded_components.sort_by(|(_, a), (_, b)| a.cmp(b));
 
         for (component_instance, name) in loaded_components.into_iter() {
             let tool_metadata =
@@ -252,6 +572,7 @@ impl LifecycleManager {
                                 &policy,
                                 plugin_dir.as_ref(),
                                 &environment_vars,
+                                &TemplateContext::new(plugin_dir.as_ref(), &name, &session_id),
                             ) {
                                 Ok(wasi_template) => {
                                     policy_registry
@@ -282,9 +603,20 @@ impl LifecycleManager {
         tokio::fs::create_dir_all(plugin_dir.as_ref().join(DOWNLOADS_DIR))
             .await
             .context("Failed to create downloads directory")?;
-
-        info!("LifecycleManager initialized successfully");
-        Ok(Self {
+        tokio::fs::create_dir_all(plugin_dir.as_ref().join(trash::TRASH_DIR))
+            .await
+            .context("Failed to create trash directory")?;
+
+        let events = EventBus::default();
+        let logs = logging::LogBus::default();
+        let metrics = management::MetricsRecorder::default();
+        let network_log = network_log::NetworkLog::new(
+            events.clone(),
+            logs.clone(),
+            metrics.clone(),
+            environment_vars.values().cloned().collect(),
+        );
+        let manager = Self {
             engine,
             linker,
             components: Arc::new(RwLock::new(components)),
@@ -294,21 +626,147 @@ impl LifecycleManager {
             http_client,
             plugin_dir: plugin_dir.as_ref().to_path_buf(),
             environment_vars,
-        })
+            network_log,
+            metrics,
+            delegation: delegation::DelegationAuthority::default(),
+            session_id,
+            session_owners: Default::default(),
+            client_session: None,
+            events,
+            event_log: None,
+            keyvalue: keyvalue::KeyValueStore::new(plugin_dir.as_ref()),
+            secrets: secrets::SecretManager::new(plugin_dir.as_ref()),
+            component_indexes: Arc::default(),
+            logs,
+            output_capture_limit: None,
+            history: None,
+            recall: None,
+            http_proxy: None,
+            http_tls: None,
+            http_limits: RequestLimits::default(),
+            http_cache: None,
+            strict_policy: false,
+            demo_fixtures: false,
+            verification_policy: None,
+            inspectors: Vec::new(),
+            profiling: None,
+        };
+
+        match manager.purge_expired_trash().await {
+            Ok(purged) if !purged.is_empty() => {
+                info!(components = ?purged, "Purged expired components from trash")
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Failed to purge expired components from trash"),
+        }
+        match manager.discard_stale_session_components().await {
+            Ok(discarded) if !discarded.is_empty() => {
+                info!(components = ?discarded, "Moved components of ended sessions to the trash")
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Failed to discard components of ended sessions"),
+        }
+
+        info!("LifecycleManager initialized successfully");
+        Ok(manager)
     }
 
     /// Loads a new component from the given URI. This URI can be a file path, an OCI reference, or a URL.
+    /// The tag of an OCI reference can be a semver range such as `^1.2`, which loads the highest
+    /// matching version and pins it in the [`LOCKFILE`].
     ///
     /// If a component with the given id already exists, it will be updated with the new component.
     /// Returns the new ID and whether or not this component was replaced.
+    ///
+    /// The progress of the load is published as [`Event::ComponentLoading`] events, and a failed
+    /// load as an [`Event::ComponentLoadFailed`] event.
     #[instrument(skip(self))]
     pub async fn load_component(&self, uri: &str) -> Result<(String, LoadResult)> {
         debug!(uri, "Loading component");
+        let result = self.load_component_from_source(uri).await;
+        if let Err(e) = &result {
+            self.events.emit(Event::ComponentLoadFailed {
+                source: uri.to_string(),
+                error: format!("{e:#}"),
+            });
+        }
+        result
+    }
 
-        let downloaded_resource =
-            loader::load_resource::<ComponentResource>(uri, &self.oci_client, &self.http_client)
-                .await?;
+    async fn load_component_from_source(&self, uri: &str) -> Result<(String, LoadResult)> {
+        let progress = |stage| {
+            self.events.emit(Event::ComponentLoading {
+                source: uri.to_string(),
+                stage,
+            })
+        };
+        progress(LoadStage::Downloading);
+        if let Some((repository, range)) = channels::split_constraint(uri) {
+            return self.load_constrained_component(repository, range).await;
+        }
 
+        let downloaded_resource = self.download_component(uri).await?;
+        progress(LoadStage::Compiling);
+        self.load_downloaded_component(downloaded_resource).await
+    }
+
+    /// Loads a component from the bytes of a WebAssembly component, such as an upload, under
+    /// `component_id`. Like a component loaded from a file, it is copied to the plugin directory.
+    /// The policy in `policy_yaml`, if given, replaces the policy of the component before the
+    /// component can be called. Bytes have no source to check against a verification policy, so
+    /// they are refused while one is set.
+    #[instrument(skip(self, wasm, policy_yaml), fields(bytes = wasm.len()))]
+    pub async fn load_component_from_bytes(
+        &self,
+        component_id: &str,
+        wasm: &[u8],
+        policy_yaml: Option<&str>,
+    ) -> Result<(String, LoadResult)> {
+        if self.verification_policy.is_some() {
+            bail!("Components can't be loaded from bytes while a verification policy is set, since they have no source to verify");
+        }
+        if let Some(yaml) = policy_yaml {
+            self.parse_policy(yaml).context("Invalid policy")?;
+        }
+        self.load_component_bytes(component_id, wasm, policy_yaml)
+            .await
+    }
+
+    /// Loads a component from bytes whose origin was already checked, under `component_id`
+    pub(crate) async fn load_component_bytes(
+        &self,
+        component_id: &str,
+        wasm: &[u8],
+        policy_yaml: Option<&str>,
+    ) -> Result<(String, LoadResult)> {
+        bundle::validate_component_id(component_id)?;
+        let (downloaded_resource, mut file) =
+            DownloadedResource::new_temp_file(component_id, "wasm").await?;
+        file.write_all(wasm)
+            .await
+            .context("Failed to write component file")?;
+        file.flush().await?;
+        self.load_downloaded_component_with_policy(downloaded_resource, policy_yaml)
+            .await
+    }
+
+    /// Compiles an already downloaded component and registers it under the ID derived from its
+    /// file name
+    async fn load_downloaded_component(
+        &self,
+        downloaded_resource: DownloadedResource,
+    ) -> Result<(String, LoadResult)> {
+        self.load_downloaded_component_with_policy(downloaded_resource, None)
+            .await
+    }
+
+    /// Like [`LifecycleManager::load_downloaded_component`], but first replaces the policy of
+    /// the component with the one in `policy_yaml`, if given
+    async fn load_downloaded_component_with_policy(
+        &self,
+        downloaded_resource: DownloadedResource,
+        policy_yaml: Option<&str>,
+    ) -> Result<(String, LoadResult)> {
         let wasm_bytes = tokio::fs::read(downloaded_resource.as_ref())
             .await
             .context("Failed to read component file")?;
@@ -317,13 +775,15 @@ impl LifecycleManager {
         // Pre-instantiate the component
         let instance_pre = self.linker.instantiate_pre(&component)?;
         let id = downloaded_resource.id()?;
+        self.ensure_loadable(&id).await?;
+        self.inspect_component(&id, downloaded_resource.as_ref(), &component)
+            .await?;
         let tool_metadata = component_exports_to_tools(&component, &self.engine, true);
 
-        {
-            let mut registry_write = self.registry.write().await;
-            registry_write.unregister_component(&id);
-            registry_write.register_tools(&id, tool_metadata)?;
-        }
+        self.registry
+            .write()
+            .await
+            .register_tools(&id, tool_metadata)?;
 
         if let Err(e) = downloaded_resource.copy_to(&self.plugin_dir).await {
             let mut registry_write = self.registry.write().await;
@@ -334,6 +794,13 @@ impl LifecycleManager {
                 e
             );
         }
+        // Applied before the component is registered, so it is never callable without it
+        if let Some(yaml) = policy_yaml {
+            if let Err(e) = self.install_policy_yaml(&id, yaml).await {
+                self.registry.write().await.unregister_component(&id);
+                return Err(e);
+            }
+        }
 
         let res = self
             .components
@@ -348,8 +815,15 @@ impl LifecycleManager {
             )
             .map(|_| LoadResult::Replaced)
             .unwrap_or(LoadResult::New);
+        self.claim_component(&id).await?;
 
         info!("Successfully loaded component");
+        self.record_history(self.component_docs(&id).await).await;
+        self.events.emit(Event::ComponentLoaded {
+            component_id: id.clone(),
+            replaced: res == LoadResult::Replaced,
+        });
+        self.warn_about_policy(&id).await;
         Ok((id, res))
     }
 
@@ -388,31 +862,44 @@ impl LifecycleManager {
     }
 
     /// Unloads the component with the specified id. This removes the component from the runtime
-    /// and removes all associated files from disk, making it the reverse operation of load_component.
-    /// This function fails if any files cannot be removed (except when they don't exist).
+    /// and moves its files (component, policy and policy metadata) from the plugin directory into
+    /// the trash, from where it can be restored with [`LifecycleManager::restore_component`]
+    /// until [`TRASH_RETENTION`] expires.
     #[instrument(skip(self))]
     pub async fn unload_component(&self, id: &str) -> Result<()> {
-        debug!("Unloading component and removing files from disk");
-
-        // Remove files first, then clean up memory on success
-        let component_file = self.component_path(id);
-        self.remove_file_if_exists(&component_file, "component file", id)
-            .await?;
+        self.ensure_modifiable(id).await?;
+        self.remove_component(id).await
+    }
 
-        let policy_path = self.get_component_policy_path(id);
-        self.remove_file_if_exists(&policy_path, "policy file", id)
-            .await?;
+    /// Unloads a component of any session into the trash
+    pub(crate) async fn remove_component(&self, id: &str) -> Result<()> {
+        debug!("Unloading component and moving its files to the trash");
 
-        let metadata_path = self.get_component_metadata_path(id);
-        self.remove_file_if_exists(&metadata_path, "policy metadata file", id)
-            .await?;
+        // Move files first, then clean up memory on success
+        if !self.move_component_to_trash(id).await? {
+            debug!(component_id = %id, "No component files on disk to move to the trash");
+        }
 
-        // Only cleanup memory after all files are successfully removed
+        // Only cleanup memory after all files are successfully moved
         self.components.write().await.remove(id);
         self.registry.write().await.unregister_component(id);
         self.cleanup_policy_registry(id).await;
+        self.policy_registry
+            .write()
+            .await
+            .config_overrides
+            .remove(id);
+        self.forget_history_docs(id).await;
+        self.release_component(id).await?;
+
+        if let Err(e) = self.purge_expired_trash().await {
+            warn!(error = %e, "Failed to purge expired components from trash");
+        }
 
         info!(component_id = %id, "Component unloaded successfully");
+        self.events.emit(Event::ComponentUnloaded {
+            component_id: id.to_string(),
+        });
         Ok(())
     }
 
@@ -421,9 +908,16 @@ impl LifecycleManager {
     #[instrument(skip(self))]
     pub async fn get_component_id_for_tool(&self, tool_name: &str) -> Result<String> {
         let registry = self.registry.read().await;
+        let owners = self.session_owners.read().await;
         let tool_infos = registry
             .get_tool_info(tool_name)
-            .context("Tool not found")?;
+            .into_iter()
+            .flatten()
+            .filter(|info| self.sees(owners.get(&info.component_id)))
+            .collect::<Vec<_>>();
+        if tool_infos.is_empty() {
+            return Err(ComponentNotFound::Tool(tool_name.to_string()).into());
+        }
 
         if tool_infos.len() > 1 {
             bail!(
@@ -443,72 +937,471 @@ impl LifecycleManager {
     /// Lists all available tools across all components
     #[instrument(skip(self))]
     pub async fn list_tools(&self) -> Vec<Value> {
-        self.registry.read().await.list_tools()
+        let owners = self.session_owners.read().await;
+        self.registry
+            .read()
+            .await
+            .list_tools(|component_id| self.sees(owners.get(component_id)))
     }
 
     /// Returns the requested component. Returns `None` if the component is not found.
     #[instrument(skip(self))]
     pub async fn get_component(&self, component_id: &str) -> Option<ComponentInstance> {
+        if !self.is_visible(component_id).await {
+            return None;
+        }
         self.components.read().await.get(component_id).cloned()
     }
 
     /// Lists all loaded components by their IDs
     #[instrument(skip(self))]
     pub async fn list_components(&self) -> Vec<String> {
-        self.components.read().await.keys().cloned().collect()
+        let owners = self.session_owners.read().await;
+        self.components
+            .read()
+            .await
+            .keys()
+            .filter(|component_id| self.sees(owners.get(*component_id)))
+            .cloned()
+            .collect()
     }
 
     /// Gets the schema for a specific component
     #[instrument(skip(self))]
     pub async fn get_component_schema(&self, component_id: &str) -> Option<Value> {
-        let component_instance = self.get_component(component_id).await?;
-        Some(component_exports_to_json_schema(
-            &component_instance.component,
-            self.engine.as_ref(),
-            true,
-        ))
+        self.get_component(component_id).await?;
+        let registry = self.registry.read().await;
+        let tools = registry
+            .component_tool_schemas(component_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        Some(json!({ "tools": tools }))
+    }
+
+    /// Returns the WIT world of a loaded component, in the format of `wasm-tools component wit`
+    #[instrument(skip(self))]
+    pub async fn get_component_wit(&self, component_id: &str) -> Result<String> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        let path = self.component_path(component_id);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read component {}", path.display()))?;
+        component_wit(&bytes)
+    }
+
+    /// Returns the doc comments of a loaded component's WIT world and exported functions.
+    /// Components built without their WIT docs have none.
+    #[instrument(skip(self))]
+    pub async fn get_component_docs(&self, component_id: &str) -> Result<ComponentDocs> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        let path = self.component_path(component_id);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read component {}", path.display()))?;
+        let (description, mut function_docs) = component_docs(&bytes)?;
+        let registry = self.registry.read().await;
+        let tools = registry
+            .component_map
+            .get(component_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|tool_name| {
+                let info = registry
+                    .get_tool_info(tool_name)?
+                    .iter()
+                    .find(|info| info.component_id == component_id)?;
+                let key = (
+                    info.identifier
+                        .interface_name
+                        .as_deref()
+                        .map(|name| short_interface_name(name).to_string()),
+                    info.identifier.function_name.clone(),
+                );
+                Some((tool_name.clone(), function_docs.remove(&key)?))
+            })
+            .collect();
+        Ok(ComponentDocs { description, tools })
     }
 
     fn component_path(&self, component_id: &str) -> PathBuf {
         self.plugin_dir.join(format!("{component_id}.wasm"))
     }
 
+    /// Builds the state of a call to `component_id`. A call made by another component only gets
+    /// the permissions in the `scope` its delegation token grants, see
+    /// [`WasiStateTemplate::restrict`].
     async fn get_wasi_state_for_component(
         &self,
         component_id: &str,
+        function: Option<&str>,
+        scope: Option<&DelegationScope>,
+        capture: Option<&OutputCapture>,
     ) -> Result<(WassetteWasiState<WasiState>, Option<CustomResourceLimiter>)> {
         let policy_registry = self.policy_registry.read().await;
 
-        let policy_template = policy_registry
+        let mut policy_template = policy_registry
             .component_policies
             .get(component_id)
             .cloned()
             .unwrap_or_else(Self::create_default_policy_template);
+        if let Some(scope) = scope {
+            policy_template = Arc::new(policy_template.restrict(scope));
+        }
 
-        let wasi_state = policy_template.build()?;
+        let mut wasi_state = policy_template.build_with_capture(capture)?;
+        if let Some(config_vars) = self
+            .call_config_vars(
+                component_id,
+                function,
+                scope,
+                &policy_template,
+                policy_registry.config_overrides.get(component_id),
+            )
+            .await?
+        {
+            wasi_state.wasi_config_vars = WasiConfigVariables::from_iter(config_vars);
+        }
+        wasi_state
+            .keyvalue
+            .bind(self.keyvalue.clone(), component_id);
+        wasi_state.blobstore.bind(&self.plugin_dir, component_id)?;
+        wasi_state.sql.bind(component_id);
+        wasi_state.logging.bind(self.logs.clone(), component_id);
+        wasi_state.extensions.bind(component_id);
         let allowed_hosts = policy_template.allowed_hosts.clone();
+        let allowed_dns_names = policy_template.allowed_dns_names.clone();
         let resource_limiter = wasi_state.resource_limiter.clone();
+        let http_limits = match &policy_template.http_limits {
+            Some(limits) => self.http_limits.with_overrides(limits)?,
+            None => self.http_limits,
+        };
 
-        let wassette_wasi_state = WassetteWasiState::new(wasi_state, allowed_hosts)?;
+        let wassette_wasi_state = WassetteWasiState::new(wasi_state, allowed_hosts)?
+            .with_allowed_dns_names(allowed_dns_names)
+            .with_proxy(self.http_proxy.clone())
+            .with_tls(self.http_tls.clone())
+            .with_limits(http_limits)
+            .with_cache(self.http_cache.clone())
+            .with_demo_fixtures(self.demo_fixtures)
+            .with_network_log(self.network_log.clone(), component_id);
         Ok((wassette_wasi_state, resource_limiter))
     }
 
+    /// The `wasi:config` variables of a call, unless they are just the configuration values of
+    /// its policy template: those values, the variables that take their values from secrets, the
+    /// secrets themselves and the runtime overrides. A delegated call only gets the secrets that
+    /// the environment of its scope names, as the variables of its template are already
+    /// restricted to those.
+    async fn call_config_vars(
+        &self,
+        component_id: &str,
+        function: Option<&str>,
+        scope: Option<&DelegationScope>,
+        policy_template: &WasiStateTemplate,
+        overrides: Option<&HashMap<String, String>>,
+    ) -> Result<Option<HashMap<String, String>>> {
+        let secrets = self.secrets.load_for(component_id, function).await?;
+        let mut visible = secrets.clone();
+        if let Some(scope) = scope {
+            visible.retain(|key, _| scope.environment.contains(key));
+        }
+        if visible.is_empty() && policy_template.secret_vars.is_empty() && overrides.is_none() {
+            return Ok(None);
+        }
+        let mut config_vars = policy_template.config_vars.clone();
+        for (key, secret) in &policy_template.secret_vars {
+            match secrets.get(secret) {
+                Some(value) => {
+                    config_vars.insert(key.clone(), value.clone());
+                }
+                None => warn!(component_id, key, secret, "Secret of variable is not set"),
+            }
+        }
+        config_vars.extend(visible);
+        config_vars.extend(overrides.cloned().unwrap_or_default());
+        Ok(Some(config_vars))
+    }
+
+    /// Captures the stdout and stderr of every component call, keeping at most `limit` bytes
+    /// per stream, instead of inheriting them from the server process
+    pub fn with_output_capture(mut self, limit: usize) -> Self {
+        self.output_capture_limit = Some(limit);
+        self
+    }
+
+    /// Sends the `wasi:http` requests of components through the proxies in `proxy`
+    pub fn with_http_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.http_proxy = (!proxy.is_empty()).then(|| Arc::new(proxy));
+        self
+    }
+
+    /// Negotiates TLS for the `wasi:http` requests of components with the per-host settings in
+    /// `tls`
+    pub fn with_http_tls(mut self, tls: TlsConfig) -> Self {
+        self.http_tls = (!tls.is_empty()).then(|| Arc::new(tls));
+        self
+    }
+
+    /// Replaces the default size and time limits of the `wasi:http` requests of components with
+    /// the ones set in `limits`. Policies can override them per component under
+    /// `permissions.resources.http`.
+    pub fn with_http_limits(mut self, limits: &HttpLimits) -> Result<Self> {
+        self.http_limits = RequestLimits::default()
+            .with_overrides(limits)
+            .context("Invalid HTTP limits")?;
+        Ok(self)
+    }
+
+    /// Caches the responses to `wasi:http` `GET` requests of components in memory, shared by
+    /// all components and following `Cache-Control`, `Expires` and `ETag`/`Last-Modified`
+    /// revalidation
+    pub fn with_http_cache(mut self) -> Self {
+        self.http_cache = Some(http_cache::HttpCache::default());
+        self
+    }
+
+    /// Only loads `oci://` components that meet the signature requirements of `policy` for their
+    /// registry and repository, from the manifest and the CLI as well as from clients
+    pub fn with_verification_policy(mut self, policy: VerificationPolicy) -> Self {
+        self.verification_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Rejects policies with unknown fields or legacy constructs such as a numeric
+    /// `resources.memory`, see [`PolicyParser::parse_str_strict`]. The policies of the
+    /// components that are already loaded are checked too, and the first one that fails is
+    /// returned as an error.
+    pub async fn with_strict_policy(mut self) -> Result<Self> {
+        self.strict_policy = true;
+        for component_id in self.list_components().await {
+            let policy_path = self.get_component_policy_path(&component_id);
+            if tokio::fs::try_exists(&policy_path).await.unwrap_or(false) {
+                self.parse_policy_file(&policy_path).with_context(|| {
+                    format!(
+                        "Policy of component {component_id} is not valid in strict mode: {}",
+                        policy_path.display()
+                    )
+                })?;
+            }
+        }
+        Ok(self)
+    }
+
     /// Executes a function call on a WebAssembly component
-    #[instrument(skip(self))]
     pub async fn execute_component_call(
         &self,
         component_id: &str,
         function_name: &str,
         parameters: &str,
+    ) -> Result<String> {
+        self.execute_component_call_with_output(component_id, function_name, parameters)
+            .await
+            .0
+    }
+
+    /// Executes a function call on a WebAssembly component and returns what it wrote to stdout
+    /// and stderr along with the result. The output is `None` unless output capture is enabled
+    /// with [`LifecycleManager::with_output_capture`]. Captured output is also published to
+    /// [`LifecycleManager::subscribe_component_logs`] subscribers, with `stdout` or `stderr` as
+    /// the context.
+    #[instrument(skip(self))]
+    pub async fn execute_component_call_with_output(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        parameters: &str,
+    ) -> (Result<String>, Option<CapturedOutput>) {
+        self.execute_call(
+            CallOrigin::default(),
+            component_id,
+            function_name,
+            parameters,
+        )
+        .await
+    }
+
+    /// Same as [`LifecycleManager::execute_component_call_with_output`], but the component, and
+    /// the components it calls, can request completions from `sampler` through
+    /// `wassette:ai/sampling` as far as their policies allow
+    #[instrument(skip(self, sampler))]
+    pub async fn execute_component_call_with_sampler(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        parameters: &str,
+        sampler: Arc<dyn Sampler>,
+    ) -> (Result<String>, Option<CapturedOutput>) {
+        let origin = CallOrigin {
+            sampler: Some(&sampler),
+            ..Default::default()
+        };
+        self.execute_call(origin, component_id, function_name, parameters)
+            .await
+    }
+
+    /// Issues a delegation token for a call of the last component of `callers` to `tool` of
+    /// `component_id`, with at most the permissions in `scope`
+    pub(crate) fn issue_delegation(
+        &self,
+        callers: &[String],
+        component_id: &str,
+        tool: &str,
+        scope: DelegationScope,
+    ) -> Result<String> {
+        let (token, claims) = self.delegation.issue(callers, component_id, tool, scope)?;
+        debug!(
+            token_id = %claims.id,
+            chain = %format!("{} -> {component_id}", callers.join(" -> ")),
+            tool,
+            "Issued delegation token"
+        );
+        self.events.emit(Event::DelegationIssued(claims));
+        Ok(token)
+    }
+
+    /// Executes a call of a component to a tool of another component, with the delegation
+    /// token the host issued for it and the sampler of the outermost call
+    pub(crate) async fn execute_nested_call(
+        &self,
+        token: &str,
+        sampler: Option<&Arc<dyn Sampler>>,
+        component_id: &str,
+        function_name: &str,
+        parameters: &str,
+    ) -> Result<String> {
+        let claims = self
+            .delegation
+            .validate(token, component_id, function_name)?;
+        info!(
+            token_id = %claims.id,
+            chain = %format!("{} -> {component_id}", claims.callers.join(" -> ")),
+            function_name,
+            "Component called by another component"
+        );
+        let origin = CallOrigin {
+            callers: &claims.callers,
+            scope: Some(&claims.scope),
+            sampler,
+        };
+        self.execute_call(origin, component_id, function_name, parameters)
+            .await
+            .0
+    }
+
+    async fn execute_call(
+        &self,
+        origin: CallOrigin<'_>,
+        component_id: &str,
+        function_name: &str,
+        parameters: &str,
+    ) -> (Result<String>, Option<CapturedOutput>) {
+        let capture = self.output_capture_limit.map(OutputCapture::new);
+        let start = Instant::now();
+        let result = self
+            .call_component_function(
+                origin,
+                component_id,
+                function_name,
+                parameters,
+                capture.as_ref(),
+            )
+            .await;
+        let duration_ms = start.elapsed().as_millis() as u64;
+        self.metrics
+            .record(component_id, function_name, duration_ms, result.is_ok());
+        info!(
+            component_id,
+            tool_name = function_name,
+            duration_ms,
+            success = result.is_ok(),
+            "Tool call finished"
+        );
+        self.events.emit(Event::ToolInvoked {
+            component_id: component_id.to_string(),
+            function_name: function_name.to_string(),
+            duration_ms,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            callers: origin.callers.to_vec(),
+        });
+        if let Ok(text) = &result {
+            self.record_history(vec![HistoryEntry::result(
+                component_id,
+                function_name,
+                parameters,
+                text,
+            )])
+            .await;
+            self.remember(component_id, function_name, parameters, text)
+                .await;
+        }
+
+        let output = capture.map(|capture| capture.finish());
+        if let Some(output) = &output {
+            for (context, level, text) in [
+                ("stdout", LogLevel::Info, &output.stdout),
+                ("stderr", LogLevel::Warn, &output.stderr),
+            ] {
+                if !text.is_empty() {
+                    self.logs.publish(ComponentLog {
+                        component_id: component_id.to_string(),
+                        level,
+                        context: context.to_string(),
+                        message: text.clone(),
+                    });
+                }
+            }
+        }
+        (result, output)
+    }
+
+    async fn call_component_function(
+        &self,
+        origin: CallOrigin<'_>,
+        component_id: &str,
+        function_name: &str,
+        parameters: &str,
+        capture: Option<&OutputCapture>,
     ) -> Result<String> {
         let component = self
             .get_component(component_id)
             .await
-            .ok_or_else(|| anyhow!("Component not found: {}", component_id))?;
+            .ok_or_else(|| ComponentNotFound::Component(component_id.to_string()))?;
+
+        let function_id = self
+            .registry
+            .read()
+            .await
+            .get_function_identifier(component_id, function_name)
+            .ok_or_else(|| anyhow!("Unknown tool name: {}", function_name))?
+            .clone();
 
-        let (state, resource_limiter) = self.get_wasi_state_for_component(component_id).await?;
+        let (mut state, resource_limiter) = self
+            .get_wasi_state_for_component(
+                component_id,
+                Some(&function_id.function_name),
+                origin.scope,
+                capture,
+            )
+            .await?;
+        let sampler = origin.sampler.cloned();
+        state
+            .inner
+            .calls
+            .bind(self.clone(), origin.callers, component_id, sampler.clone());
+        state.inner.sampling.bind(component_id, sampler);
 
         let mut store = Store::new(self.engine.as_ref(), state);
+        let profiler = self.start_profiler(
+            &mut store,
+            &component.component,
+            component_id,
+            function_name,
+        );
 
         // Apply memory limits if configured in the policy by setting up a limiter closure
         // that extracts the resource limiter from the WasiState
@@ -523,16 +1416,13 @@ impl LifecycleManager {
             });
         }
 
-        let instance = component.instance_pre.instantiate_async(&mut store).await?;
-
-        // Use the new function identifier lookup instead of dot-splitting
-        let function_id = self
-            .registry
-            .read()
-            .await
-            .get_function_identifier(function_name)
-            .ok_or_else(|| anyhow!("Unknown tool name: {}", function_name))?
-            .clone();
+        let instance = match component.instance_pre.instantiate_async(&mut store).await {
+            Ok(instance) => instance,
+            Err(e) => {
+                self.record_memory_limit_hit(&store, component_id, function_name);
+                return Err(e);
+            }
+        };
 
         let (interface_name, func_name) = (
             function_id.interface_name.as_deref().unwrap_or(""),
@@ -577,8 +1467,19 @@ impl LifecycleManager {
 
         let mut results = create_placeholder_results(&func.results(&store));
 
-        func.call_async(&mut store, &argument_vals, &mut results)
-            .await?;
+        let call = func
+            .call_async(&mut store, &argument_vals, &mut results)
+            .await;
+        self.record_memory_limit_hit(&store, component_id, function_name);
+        if let Some(profiler) = profiler {
+            match profiler.finish().await {
+                Ok(path) => {
+                    debug!(component_id, function_name, path = %path.display(), "Wrote profile")
+                }
+                Err(e) => warn!(component_id, function_name, error = %e, "Failed to write profile"),
+            }
+        }
+        call?;
 
         let result_json = vals_to_json(&results);
 
@@ -589,6 +1490,21 @@ impl LifecycleManager {
         }
     }
 
+    /// Counts a call whose store hit the memory limit of its policy in the call metrics
+    fn record_memory_limit_hit(
+        &self,
+        store: &Store<WassetteWasiState<WasiState>>,
+        component_id: &str,
+        function_name: &str,
+    ) {
+        let limiter = store.data().inner.resource_limiter.as_ref();
+        if limiter.is_some_and(|limiter| limiter.memory_limit_hit()) {
+            warn!(component_id, function_name, "Call hit the memory limit");
+            self.metrics
+                .record_memory_limit_hit(component_id, function_name);
+        }
+    }
+
     // Granular permission system methods
 }
 // Load components in parallel for improved startup performance
@@ -639,8 +1555,9 @@ impl LifecycleManager {
             uri, "Revoking storage permission by URI from component"
         );
         if !self.components.read().await.contains_key(component_id) {
-            return Err(anyhow!("Component not found: {}", component_id));
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
         }
+        self.ensure_modifiable(component_id).await?;
 
         if uri.is_empty() {
             return Err(anyhow!("Storage URI cannot be empty"));
@@ -652,6 +1569,11 @@ impl LifecycleManager {
         self.update_policy_registry(component_id, &policy).await?;
 
         info!(component_id, uri, "Storage permission revoked successfully");
+        self.events.emit(Event::PermissionRevoked {
+            component_id: component_id.to_string(),
+            permission_type: "storage".to_string(),
+            details: serde_json::json!({ "uri": uri }),
+        });
         Ok(())
     }
 
@@ -713,6 +1635,99 @@ async fn load_component_from_entry(
     )))
 }
 
+/// Prints the WIT world of a binary or text format component, with the packages it uses
+/// Doc comments of exported functions, by interface name and function name. Interfaces are
+/// named without their package and version, e.g. `handler` for `wasi:http/handler@0.2.0`.
+type FunctionDocs = HashMap<(Option<String>, String), String>;
+
+/// Name of the custom section the WIT tooling stores doc comments in, as JSON after a version byte
+const PACKAGE_DOCS_SECTION: &str = "package-docs";
+
+/// Reads the doc comments of a component from its `package-docs` custom section
+fn component_docs(bytes: &[u8]) -> Result<(Option<String>, FunctionDocs)> {
+    let bytes = wat::parse_bytes(bytes).context("Invalid component")?;
+    let mut section = None;
+    for payload in wasmparser::Parser::new(0).parse_all(&bytes) {
+        if let wasmparser::Payload::CustomSection(reader) = payload.context("Invalid component")? {
+            if reader.name() == PACKAGE_DOCS_SECTION {
+                section = Some(reader.data().to_vec());
+                break;
+            }
+        }
+    }
+    let Some(metadata) =
+        section.and_then(|data| serde_json::from_slice::<Value>(data.get(1..)?).ok())
+    else {
+        return Ok((None, FunctionDocs::new()));
+    };
+
+    fn doc_of(item: &Value) -> Option<String> {
+        // Functions without stability annotations are documented by a plain string
+        item.as_str()
+            .or_else(|| item.get("docs")?.as_str())
+            .map(str::to_string)
+    }
+    fn objects<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = (&'a String, &'a Value)> {
+        value
+            .get(key)
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+    }
+
+    let mut docs = FunctionDocs::new();
+    let mut description = None;
+    let mut interfaces = objects(&metadata, "interfaces").collect::<Vec<_>>();
+    for (_, world) in objects(&metadata, "worlds") {
+        description = description.or_else(|| doc_of(world));
+        for key in ["funcs", "func_exports"] {
+            for (name, function) in objects(world, key) {
+                if let Some(doc) = doc_of(function) {
+                    docs.insert((None, name.clone()), doc);
+                }
+            }
+        }
+        interfaces.extend(objects(world, "interfaces"));
+        interfaces.extend(objects(world, "interface_exports"));
+    }
+    for (interface, metadata) in interfaces {
+        for (name, function) in objects(metadata, "funcs") {
+            if let Some(doc) = doc_of(function) {
+                docs.insert((Some(interface.clone()), name.clone()), doc);
+            }
+        }
+    }
+    Ok((description, docs))
+}
+
+/// The name of an interface without its package and version, as doc comments are keyed by it
+fn short_interface_name(name: &str) -> &str {
+    let name = name.rsplit_once('/').map_or(name, |(_, name)| name);
+    name.split_once('@').map_or(name, |(name, _)| name)
+}
+
+fn component_wit(bytes: &[u8]) -> Result<String> {
+    let bytes = wat::parse_bytes(bytes).context("Invalid component")?;
+    let (resolve, world) = match wit_component::decode(&bytes).context("Invalid component")? {
+        wit_component::DecodedWasm::Component(resolve, world) => (resolve, world),
+        wit_component::DecodedWasm::WitPackage(..) => {
+            bail!("Expected a component, found a WIT package")
+        }
+    };
+    let package = resolve.worlds[world]
+        .package
+        .context("The world of the component has no package")?;
+    let nested = resolve
+        .packages
+        .iter()
+        .map(|(id, _)| id)
+        .filter(|id| *id != package)
+        .collect::<Vec<_>>();
+    let mut printer = wit_component::WitPrinter::default();
+    printer.print(&resolve, package, &nested)?;
+    Ok(printer.output.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Deref;
@@ -752,6 +1767,10 @@ mod tests {
         }
     }
 
+    /// A component that exports a `run` function without parameters or results, as WAT, which
+    /// loads like a compiled component
+    pub(crate) const COMPONENT: &str = include_str!("../testdata/run.wat");
+
     pub(crate) async fn create_test_manager() -> Result<TestLifecycleManager> {
         let tempdir = tempfile::tempdir()?;
         let manager = LifecycleManager::new(&tempdir).await?;
@@ -848,6 +1867,47 @@ mod tests {
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn test_get_component_wit() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func $run (result u32) (canon lift (core func $i "run")))
+              (export "run" (func $run)))"#,
+        )?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        let wit = manager.get_component_wit("answer").await?;
+        assert!(wit.contains("world"), "{wit}");
+        assert!(wit.contains("export run: func() -> u32;"), "{wit}");
+        assert!(manager.get_component_wit("missing").await.is_err());
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_get_component_docs() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func $run (result u32) (canon lift (core func $i "run")))
+              (export "run" (func $run))
+              (@custom "package-docs" "\00{\"worlds\":{\"root\":{\"docs\":\"Answers questions.\",\"funcs\":{\"run\":\"Returns the answer.\"}}}}"))"#,
+        )?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        let docs = manager.get_component_docs("answer").await?;
+        assert_eq!(docs.description.as_deref(), Some("Answers questions."));
+        assert_eq!(docs.tools["run"], "Returns the answer.");
+        assert!(manager.get_component_docs("missing").await.is_err());
+        Ok(())
+    }
+
     #[test(tokio::test)]
     async fn test_duplicate_component_id() -> Result<()> {
         let manager = create_test_manager().await?;
@@ -926,12 +1986,56 @@ permissions:
 
         // Test getting WASI state for component with attached policy
         let _wasi_state = manager
-            .get_wasi_state_for_component(TEST_COMPONENT_ID)
+            .get_wasi_state_for_component(TEST_COMPONENT_ID, None, None, None)
             .await?;
 
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn test_strict_policy() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+
+        let legacy_path = manager.plugin_dir.join("legacy-policy.yaml");
+        tokio::fs::write(
+            &legacy_path,
+            "version: \"1.0\"\npermissions:\n  resources:\n    memory: 512\n",
+        )
+        .await?;
+        manager
+            .attach_policy(
+                TEST_COMPONENT_ID,
+                &format!("file://{}", legacy_path.display()),
+            )
+            .await?;
+
+        // Switching to strict mode checks the policies that are already attached
+        let error = manager.clone().with_strict_policy().await.err().unwrap();
+        assert!(
+            format!("{error:#}").contains("permissions.resources.memory"),
+            "{error:#}"
+        );
+
+        manager.detach_policy(TEST_COMPONENT_ID).await?;
+        let strict = manager.clone().with_strict_policy().await?;
+        let unknown_path = manager.plugin_dir.join("unknown-policy.yaml");
+        tokio::fs::write(
+            &unknown_path,
+            "version: \"1.0\"\npermissions:\n  netwrok:\n    allow:\n      - host: \"example.com\"\n",
+        )
+        .await?;
+        let result = strict
+            .attach_policy(
+                TEST_COMPONENT_ID,
+                &format!("file://{}", unknown_path.display()),
+            )
+            .await;
+        assert!(format!("{:#}", result.unwrap_err()).contains("permissions.netwrok"));
+
+        Ok(())
+    }
+
     #[test(tokio::test)]
     async fn test_policy_restoration_on_startup() -> Result<()> {
         let tempdir = tempfile::tempdir()?;
@@ -1033,6 +2137,33 @@ permissions:
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn test_instance_limit() -> Result<()> {
+        let workspace = DemoWorkspace::create().await?;
+        let policy = |instances: u32| {
+            format!("version: \"1.0\"\npermissions:\n  resources:\n    limits:\n      instances: {instances}\n")
+        };
+        let policy_path = workspace.plugin_dir().join("html-to-markdown.policy.yaml");
+        let call = |manager: LifecycleManager| async move {
+            manager
+                .execute_component_call("html-to-markdown", "convert", r#"{"html": "<p>Hi</p>"}"#)
+                .await
+        };
+
+        tokio::fs::write(&policy_path, policy(1)).await?;
+        let manager = LifecycleManager::new(workspace.plugin_dir()).await?;
+        let err = call(manager).await.unwrap_err();
+        assert!(
+            format!("{err:?}").contains("instance count too high"),
+            "{err:?}"
+        );
+
+        tokio::fs::write(&policy_path, policy(100)).await?;
+        let manager = LifecycleManager::new(workspace.plugin_dir()).await?;
+        assert!(call(manager).await?.contains("Hi"));
+        Ok(())
+    }
+
     #[test(tokio::test)]
     async fn test_wasi_state_template_allowed_hosts() -> Result<()> {
         // Test that WasiStateTemplate correctly stores allowed hosts from policy
@@ -1049,7 +2180,12 @@ permissions:
 
         let temp_dir = tempfile::tempdir()?;
         let env_vars = HashMap::new(); // Empty environment for test
-        let template = create_wasi_state_template_from_policy(&policy, temp_dir.path(), &env_vars)?;
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            temp_dir.path(),
+            &env_vars,
+            &TemplateContext::default(),
+        )?;
 
         assert_eq!(template.allowed_hosts.len(), 2);
         assert!(template.allowed_hosts.contains("api.example.com"));
@@ -1170,7 +2306,7 @@ permissions:
         assert!(policy_path.exists());
 
         // Reset all permissions
-        manager.reset_permission(TEST_COMPONENT_ID).await?;
+        manager.reset_permissions(TEST_COMPONENT_ID).await?;
 
         // Verify policy file was removed
         assert!(!policy_path.exists());
@@ -1182,6 +2318,65 @@ permissions:
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn test_reset_permissions_restores_attached_policy() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+
+        let policy_path = manager.plugin_dir.join("attached-policy.yaml");
+        tokio::fs::write(
+            &policy_path,
+            "version: \"1.0\"\npermissions:\n  network:\n    allow:\n      - host: \"example.com\"\n",
+        )
+        .await?;
+        manager
+            .attach_policy(
+                TEST_COMPONENT_ID,
+                &format!("file://{}", policy_path.display()),
+            )
+            .await?;
+        let attached =
+            PolicyParser::parse_file(manager.get_component_policy_path(TEST_COMPONENT_ID))?;
+
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+        manager
+            .revoke_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "example.com"}),
+            )
+            .await?;
+
+        manager.reset_permissions(TEST_COMPONENT_ID).await?;
+
+        let reset = PolicyParser::parse_file(manager.get_component_policy_path(TEST_COMPONENT_ID))?;
+        assert_eq!(reset, attached);
+        assert!(manager.get_policy_info(TEST_COMPONENT_ID).await.is_some());
+        assert!(!manager
+            .get_component_base_policy_path(TEST_COMPONENT_ID)
+            .exists());
+        let templates = manager.policy_registry.read().await;
+        let template = templates
+            .component_policies
+            .get(TEST_COMPONENT_ID)
+            .expect("attached policy should stay active");
+        assert!(template.allowed_hosts.contains("example.com"));
+        assert!(!template.allowed_hosts.contains("api.example.com"));
+        drop(templates);
+
+        // Resetting again without runtime changes keeps the attached policy
+        manager.reset_permissions(TEST_COMPONENT_ID).await?;
+        assert!(manager.get_policy_info(TEST_COMPONENT_ID).await.is_some());
+
+        Ok(())
+    }
+
     #[test(tokio::test)]
     async fn test_revoke_permission_component_not_found() -> Result<()> {
         let manager = create_test_manager().await?;
@@ -1206,7 +2401,7 @@ permissions:
         let manager = create_test_manager().await?;
 
         // Try to reset permissions for non-existent component
-        let result = manager.reset_permission("non-existent").await;
+        let result = manager.reset_permissions("non-existent").await;
 
         assert!(result.is_err());
         assert!(result
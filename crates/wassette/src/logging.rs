@@ -0,0 +1,202 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wasi:logging`
+//!
+//! Log calls from a component are written to the host's `tracing` subscriber under the
+//! `wassette::component` target and published as a [`ComponentLog`] to every subscriber of
+//! [`crate::LifecycleManager::subscribe_component_logs`]. The MCP server uses this to forward
+//! them to clients as `notifications/message`.
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use self::generated::wasi::logging::logging;
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/logging",
+        world: "wasi:logging/imports",
+        trappable_imports: true,
+    });
+}
+
+/// Target of the `tracing` events emitted for component log calls
+pub const COMPONENT_LOG_TARGET: &str = "wassette::component";
+
+const LOG_CHANNEL_CAPACITY: usize = 1024;
+
+/// Severity of a component log message, as defined by `wasi:logging`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Values of variables and control flow
+    Trace,
+    /// Messages of interest when debugging
+    Debug,
+    /// Messages of interest when monitoring
+    Info,
+    /// Hazardous situations
+    Warn,
+    /// Serious errors
+    Error,
+    /// Fatal errors
+    Critical,
+}
+
+impl From<logging::Level> for LogLevel {
+    fn from(level: logging::Level) -> Self {
+        match level {
+            logging::Level::Trace => LogLevel::Trace,
+            logging::Level::Debug => LogLevel::Debug,
+            logging::Level::Info => LogLevel::Info,
+            logging::Level::Warn => LogLevel::Warn,
+            logging::Level::Error => LogLevel::Error,
+            logging::Level::Critical => LogLevel::Critical,
+        }
+    }
+}
+
+/// A message a component logged through `wasi:logging`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentLog {
+    /// The component that logged the message
+    pub component_id: String,
+    /// Severity of the message
+    pub level: LogLevel,
+    /// Free-form context the component attached, e.g. a module name
+    pub context: String,
+    /// The message text
+    pub message: String,
+}
+
+/// The sending side of the component log channel
+#[derive(Debug, Clone)]
+pub(crate) struct LogBus {
+    sender: broadcast::Sender<ComponentLog>,
+}
+
+impl Default for LogBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(LOG_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl LogBus {
+    pub(crate) fn publish(&self, log: ComponentLog) {
+        // An error only means that there are no subscribers right now
+        let _ = self.sender.send(log);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ComponentLog> {
+        self.sender.subscribe()
+    }
+}
+
+/// Per-instance logging state. Until [`LoggingCtx::bind`] is called, messages only go to
+/// `tracing`.
+#[derive(Default)]
+pub(crate) struct LoggingCtx {
+    component_id: String,
+    bus: Option<LogBus>,
+}
+
+impl LoggingCtx {
+    /// Attributes the messages of this instance to `component_id` and publishes them on `bus`
+    pub(crate) fn bind(&mut self, bus: LogBus, component_id: &str) {
+        self.component_id = component_id.to_string();
+        self.bus = Some(bus);
+    }
+}
+
+impl logging::Host for LoggingCtx {
+    fn log(
+        &mut self,
+        level: logging::Level,
+        context: String,
+        message: String,
+    ) -> wasmtime::Result<()> {
+        let component_id = self.component_id.as_str();
+        let level = LogLevel::from(level);
+        match level {
+            LogLevel::Trace => {
+                tracing::trace!(target: COMPONENT_LOG_TARGET, component_id, context, "{message}")
+            }
+            LogLevel::Debug => {
+                tracing::debug!(target: COMPONENT_LOG_TARGET, component_id, context, "{message}")
+            }
+            LogLevel::Info => {
+                tracing::info!(target: COMPONENT_LOG_TARGET, component_id, context, "{message}")
+            }
+            LogLevel::Warn => {
+                tracing::warn!(target: COMPONENT_LOG_TARGET, component_id, context, "{message}")
+            }
+            LogLevel::Error | LogLevel::Critical => {
+                tracing::error!(target: COMPONENT_LOG_TARGET, component_id, context, "{message}")
+            }
+        }
+
+        if let Some(bus) = &self.bus {
+            bus.publish(ComponentLog {
+                component_id: self.component_id.clone(),
+                level,
+                context,
+                message,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> &mut LoggingCtx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    logging::add_to_linker_get_host(linker, f)
+}
+
+impl crate::LifecycleManager {
+    /// Subscribes to the messages components log through `wasi:logging`. A receiver that falls
+    /// too far behind skips the oldest messages.
+    pub fn subscribe_component_logs(&self) -> broadcast::Receiver<ComponentLog> {
+        self.logs.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logging::Host as _;
+
+    use super::*;
+
+    #[test]
+    fn test_log_is_published() {
+        let bus = LogBus::default();
+        let mut receiver = bus.subscribe();
+
+        // Messages from an unbound context are not published
+        let mut ctx = LoggingCtx::default();
+        ctx.log(logging::Level::Info, String::new(), "dropped".to_string())
+            .unwrap();
+
+        ctx.bind(bus, "fetch-rs");
+        ctx.log(
+            logging::Level::Critical,
+            "http".to_string(),
+            "request failed".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            ComponentLog {
+                component_id: "fetch-rs".to_string(),
+                level: LogLevel::Critical,
+                context: "http".to_string(),
+                message: "request failed".to_string(),
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,716 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Stable API for management frontends
+//!
+//! [`ManagementApi`] is everything a frontend needs to drive a wassette runtime: the loaded
+//! components and their tools, their permissions, the event and log streams, call metrics and
+//! tool invocation. [`crate::LifecycleManager`] implements it, so a TUI or desktop app can embed
+//! the runtime and be written against the trait alone. The trait is covered by the semver
+//! guarantees of this crate: methods and the fields of the types they return are only removed
+//! or changed in a major release. New methods always come with a default implementation, and
+//! new fields may be added to [`ComponentSummary`], [`ToolMetrics`] and [`ManagementMetrics`]
+//! in minor releases, so construct them with `..Default::default()` outside this crate.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::{
+    ComponentLog, ComponentNotFound, Event, EventQuery, LoadResult, LoggedEvent, NetworkRequest,
+    PolicyChange, PolicyInfo, PolicyWarning, TrashedComponent,
+};
+
+/// A loaded component as shown by a frontend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComponentSummary {
+    /// ID of the component
+    pub id: String,
+    /// Names of the tools the component exports
+    pub tools: Vec<String>,
+    /// Where the attached policy was loaded from, if the component has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_uri: Option<String>,
+    /// Capabilities the component imports but its policy grants nothing for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy_warnings: Vec<PolicyWarning>,
+}
+
+/// Call statistics of one tool since the runtime started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolMetrics {
+    /// ID of the component that exports the tool
+    pub component_id: String,
+    /// Name of the tool function
+    pub function_name: String,
+    /// Number of calls
+    pub calls: u64,
+    /// Number of calls that returned an error
+    pub failures: u64,
+    /// Total time spent in calls, in milliseconds
+    pub total_duration_ms: u64,
+    /// Duration of the slowest call, in milliseconds
+    pub max_duration_ms: u64,
+    /// Median duration of the recent calls, in milliseconds
+    #[serde(default)]
+    pub p50_duration_ms: u64,
+    /// 90th percentile duration of the recent calls, in milliseconds
+    #[serde(default)]
+    pub p90_duration_ms: u64,
+    /// 99th percentile duration of the recent calls, in milliseconds
+    #[serde(default)]
+    pub p99_duration_ms: u64,
+    /// Number of calls in which the memory limit of the policy denied memory to grow
+    #[serde(default)]
+    pub memory_limit_hits: u64,
+}
+
+/// Call statistics of all tools of one component since the runtime started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentMetrics {
+    /// ID of the component
+    pub component_id: String,
+    /// Number of calls
+    pub calls: u64,
+    /// Number of calls that returned an error
+    pub failures: u64,
+    /// Total time spent in calls, in milliseconds
+    pub total_duration_ms: u64,
+    /// Duration of the slowest call, in milliseconds
+    pub max_duration_ms: u64,
+    /// Median duration of the recent calls, in milliseconds
+    pub p50_duration_ms: u64,
+    /// 90th percentile duration of the recent calls, in milliseconds
+    pub p90_duration_ms: u64,
+    /// 99th percentile duration of the recent calls, in milliseconds
+    pub p99_duration_ms: u64,
+    /// Number of calls in which the memory limit of the policy denied memory to grow
+    pub memory_limit_hits: u64,
+    /// Number of outgoing HTTP requests
+    #[serde(default)]
+    pub http_requests: u64,
+    /// Number of outgoing HTTP requests that were answered from the HTTP cache
+    #[serde(default)]
+    pub http_cache_hits: u64,
+}
+
+/// Metrics of the whole runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManagementMetrics {
+    /// Number of loaded components
+    pub loaded_components: usize,
+    /// Number of components in the trash
+    pub trashed_components: usize,
+    /// Call statistics of every tool that was called at least once, sorted by component and
+    /// function name
+    pub tools: Vec<ToolMetrics>,
+    /// Call statistics of every component with a tool that was called at least once, sorted by
+    /// component
+    #[serde(default)]
+    pub components: Vec<ComponentMetrics>,
+}
+
+/// The data access and actions of a management frontend
+///
+/// A frontend that is generic over this trait also works against a remote runtime, given an
+/// implementation that forwards the calls.
+pub trait ManagementApi: Send + Sync {
+    /// Lists the loaded components, sorted by ID
+    fn components(&self) -> impl Future<Output = Result<Vec<ComponentSummary>>> + Send;
+
+    /// Returns the tool schema of a loaded component
+    fn component_schema(&self, component_id: &str) -> impl Future<Output = Option<Value>> + Send;
+
+    /// Loads a component from a file path, OCI reference or URL. Returns its ID and whether
+    /// it replaced an already loaded component.
+    fn load_component(&self, uri: &str) -> impl Future<Output = Result<(String, bool)>> + Send;
+
+    /// Loads a component from the bytes of a WebAssembly component under `component_id`, and
+    /// replaces its policy with `policy_yaml` if given. The policy is validated before the
+    /// component is loaded. Returns like [`ManagementApi::load_component`].
+    fn upload_component(
+        &self,
+        component_id: &str,
+        wasm: &[u8],
+        policy_yaml: Option<&str>,
+    ) -> impl Future<Output = Result<(String, bool)>> + Send {
+        let _ = (wasm, policy_yaml);
+        async move { bail!("Uploading component {component_id} is not supported") }
+    }
+
+    /// Unloads a component and moves it to the trash
+    fn unload_component(&self, component_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Lists the components in the trash
+    fn trashed_components(&self) -> impl Future<Output = Result<Vec<TrashedComponent>>> + Send;
+
+    /// Loads a component from the trash again
+    fn restore_component(&self, component_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the policy attached to a component
+    fn policy(&self, component_id: &str) -> impl Future<Output = Option<PolicyInfo>> + Send;
+
+    /// Returns the effective policy of a component, including the permissions granted at
+    /// runtime
+    fn permissions(
+        &self,
+        component_id: &str,
+    ) -> impl Future<Output = Result<policy::PolicyDocument>> + Send;
+
+    /// Grants a permission to a component. `permission_type` and `details` take the same
+    /// values as the `grant-*-permission` tools.
+    fn grant_permission(
+        &self,
+        component_id: &str,
+        permission_type: &str,
+        details: &Value,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Revokes a permission from a component
+    fn revoke_permission(
+        &self,
+        component_id: &str,
+        permission_type: &str,
+        details: &Value,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Reverts a component to the policy it had before any runtime change
+    fn reset_permissions(&self, component_id: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the YAML of the policy attached to a component, or `None` if it has none
+    fn policy_yaml(
+        &self,
+        component_id: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send {
+        async move { bail!("Reading the policy of {component_id} is not supported") }
+    }
+
+    /// Replaces the policy of a component with a validated policy in YAML and returns what
+    /// changed
+    fn set_policy_yaml(
+        &self,
+        component_id: &str,
+        yaml: &str,
+    ) -> impl Future<Output = Result<Vec<PolicyChange>>> + Send {
+        let _ = yaml;
+        async move { bail!("Replacing the policy of {component_id} is not supported") }
+    }
+
+    /// Returns the names of the secrets of a component, sorted. Their values are never returned.
+    fn secret_keys(&self, component_id: &str) -> impl Future<Output = Result<Vec<String>>> + Send {
+        async move { bail!("Listing the secrets of {component_id} is not supported") }
+    }
+
+    /// Sets the secret `key` of a component
+    fn set_secret(
+        &self,
+        component_id: &str,
+        key: &str,
+        value: &str,
+    ) -> impl Future<Output = Result<()>> + Send {
+        let _ = (key, value);
+        async move { bail!("Setting the secrets of {component_id} is not supported") }
+    }
+
+    /// Deletes the secret `key` of a component. Returns whether it had one.
+    fn delete_secret(
+        &self,
+        component_id: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<bool>> + Send {
+        let _ = key;
+        async move { bail!("Deleting the secrets of {component_id} is not supported") }
+    }
+
+    /// Subscribes to lifecycle and invocation events
+    fn subscribe_events(&self) -> broadcast::Receiver<Event>;
+
+    /// Subscribes to the logs of components
+    fn subscribe_component_logs(&self) -> broadcast::Receiver<ComponentLog>;
+
+    /// Returns the most recent outgoing HTTP requests of components, newest first
+    fn network_activity(&self, component_id: Option<&str>, limit: usize) -> Vec<NetworkRequest>;
+
+    /// Returns the events of the persistent event log that match `query`, newest first
+    fn events(&self, query: &EventQuery) -> impl Future<Output = Result<Vec<LoggedEvent>>> + Send {
+        let _ = query;
+        async move { bail!("Querying the event log is not supported") }
+    }
+
+    /// Returns the metrics of the runtime
+    fn metrics(&self) -> impl Future<Output = Result<ManagementMetrics>> + Send;
+
+    /// Lists the tool schemas of every loaded component
+    fn tools(&self) -> impl Future<Output = Vec<Value>> + Send;
+
+    /// Calls a tool with JSON arguments and returns its JSON result
+    fn call_tool(
+        &self,
+        tool_name: &str,
+        arguments: &Value,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    /// Calls the tool `function_name` of a given component with JSON arguments and returns its
+    /// JSON result. Unlike [`ManagementApi::call_tool`], the tool name doesn't have to be unique
+    /// across the loaded components.
+    fn call_component_tool(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        arguments: &Value,
+    ) -> impl Future<Output = Result<String>> + Send {
+        let _ = arguments;
+        async move { bail!("Calling {function_name} of {component_id} is not supported") }
+    }
+}
+
+/// How many of the most recent call durations of a tool the latency percentiles are computed
+/// from
+const LATENCY_SAMPLES: usize = 1000;
+
+/// The counters of one tool and its most recent call durations
+#[derive(Debug, Default)]
+struct ToolStats {
+    metrics: ToolMetrics,
+    durations_ms: VecDeque<u64>,
+}
+
+/// Per-tool call counters, updated on every invocation
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricsRecorder {
+    tools: Arc<Mutex<BTreeMap<(String, String), ToolStats>>>,
+    /// Outgoing HTTP requests and cache hits by component
+    http: Arc<Mutex<BTreeMap<String, (u64, u64)>>>,
+}
+
+/// The duration below which `percentile` percent of `sorted_durations` fall
+fn percentile(sorted_durations: &[u64], percentile: usize) -> u64 {
+    if sorted_durations.is_empty() {
+        return 0;
+    }
+    let rank = (sorted_durations.len() * percentile).div_ceil(100);
+    sorted_durations[rank.saturating_sub(1)]
+}
+
+/// The p50, p90 and p99 of `durations`
+fn percentiles<'a>(durations: impl IntoIterator<Item = &'a u64>) -> (u64, u64, u64) {
+    let mut durations = durations.into_iter().copied().collect::<Vec<_>>();
+    durations.sort_unstable();
+    (
+        percentile(&durations, 50),
+        percentile(&durations, 90),
+        percentile(&durations, 99),
+    )
+}
+
+impl MetricsRecorder {
+    fn with_tool<R>(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        update: impl FnOnce(&mut ToolStats) -> R,
+    ) -> R {
+        let mut tools = self.tools.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = tools
+            .entry((component_id.to_string(), function_name.to_string()))
+            .or_insert_with(|| ToolStats {
+                metrics: ToolMetrics {
+                    component_id: component_id.to_string(),
+                    function_name: function_name.to_string(),
+                    ..Default::default()
+                },
+                durations_ms: VecDeque::new(),
+            });
+        update(stats)
+    }
+
+    pub(crate) fn record(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        duration_ms: u64,
+        success: bool,
+    ) {
+        self.with_tool(component_id, function_name, |stats| {
+            let metrics = &mut stats.metrics;
+            metrics.calls += 1;
+            metrics.failures += u64::from(!success);
+            metrics.total_duration_ms += duration_ms;
+            metrics.max_duration_ms = metrics.max_duration_ms.max(duration_ms);
+            if stats.durations_ms.len() == LATENCY_SAMPLES {
+                stats.durations_ms.pop_front();
+            }
+            stats.durations_ms.push_back(duration_ms);
+        });
+    }
+
+    pub(crate) fn record_memory_limit_hit(&self, component_id: &str, function_name: &str) {
+        self.with_tool(component_id, function_name, |stats| {
+            stats.metrics.memory_limit_hits += 1
+        });
+    }
+
+    pub(crate) fn record_http_request(&self, component_id: &str, cached: bool) {
+        let mut http = self.http.lock().unwrap_or_else(|e| e.into_inner());
+        let (requests, cache_hits) = http.entry(component_id.to_string()).or_default();
+        *requests += 1;
+        *cache_hits += u64::from(cached);
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<ToolMetrics> {
+        let tools = self.tools.lock().unwrap_or_else(|e| e.into_inner());
+        tools
+            .values()
+            .map(|stats| {
+                let (p50, p90, p99) = percentiles(&stats.durations_ms);
+                ToolMetrics {
+                    p50_duration_ms: p50,
+                    p90_duration_ms: p90,
+                    p99_duration_ms: p99,
+                    ..stats.metrics.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// The metrics of every component with a tool that was called or an HTTP request, with the
+    /// percentiles of the recent calls of all its tools
+    pub(crate) fn component_snapshot(&self) -> Vec<ComponentMetrics> {
+        let tools = self.tools.lock().unwrap_or_else(|e| e.into_inner());
+        let mut components = BTreeMap::<&str, (ComponentMetrics, Vec<u64>)>::new();
+        for stats in tools.values() {
+            let tool = &stats.metrics;
+            let (metrics, durations) =
+                components
+                    .entry(tool.component_id.as_str())
+                    .or_insert_with(|| {
+                        let metrics = ComponentMetrics {
+                            component_id: tool.component_id.clone(),
+                            ..Default::default()
+                        };
+                        (metrics, Vec::new())
+                    });
+            metrics.calls += tool.calls;
+            metrics.failures += tool.failures;
+            metrics.total_duration_ms += tool.total_duration_ms;
+            metrics.max_duration_ms = metrics.max_duration_ms.max(tool.max_duration_ms);
+            metrics.memory_limit_hits += tool.memory_limit_hits;
+            durations.extend(&stats.durations_ms);
+        }
+        let http = self.http.lock().unwrap_or_else(|e| e.into_inner());
+        for (component_id, (requests, cache_hits)) in http.iter() {
+            let (metrics, _) = components.entry(component_id.as_str()).or_insert_with(|| {
+                let metrics = ComponentMetrics {
+                    component_id: component_id.clone(),
+                    ..Default::default()
+                };
+                (metrics, Vec::new())
+            });
+            metrics.http_requests = *requests;
+            metrics.http_cache_hits = *cache_hits;
+        }
+        components
+            .into_values()
+            .map(|(metrics, durations)| {
+                let (p50, p90, p99) = percentiles(&durations);
+                ComponentMetrics {
+                    p50_duration_ms: p50,
+                    p90_duration_ms: p90,
+                    p99_duration_ms: p99,
+                    ..metrics
+                }
+            })
+            .collect()
+    }
+}
+
+impl ManagementApi for crate::LifecycleManager {
+    async fn components(&self) -> Result<Vec<ComponentSummary>> {
+        let mut ids = self.list_components().await;
+        ids.sort();
+        let mut components = Vec::with_capacity(ids.len());
+        for id in ids {
+            let mut tools = self
+                .registry
+                .read()
+                .await
+                .component_map
+                .get(&id)
+                .cloned()
+                .unwrap_or_default();
+            tools.sort();
+            let policy_uri = self.get_policy_info(&id).await.map(|info| info.source_uri);
+            let policy_warnings = self.policy_warnings(&id).await?;
+            components.push(ComponentSummary {
+                id,
+                tools,
+                policy_uri,
+                policy_warnings,
+            });
+        }
+        Ok(components)
+    }
+
+    async fn component_schema(&self, component_id: &str) -> Option<Value> {
+        self.get_component_schema(component_id).await
+    }
+
+    async fn load_component(&self, uri: &str) -> Result<(String, bool)> {
+        let (id, result) = crate::LifecycleManager::load_component(self, uri).await?;
+        Ok((id, result == LoadResult::Replaced))
+    }
+
+    async fn upload_component(
+        &self,
+        component_id: &str,
+        wasm: &[u8],
+        policy_yaml: Option<&str>,
+    ) -> Result<(String, bool)> {
+        let (id, result) = self
+            .load_component_from_bytes(component_id, wasm, policy_yaml)
+            .await?;
+        Ok((id, result == LoadResult::Replaced))
+    }
+
+    async fn unload_component(&self, component_id: &str) -> Result<()> {
+        crate::LifecycleManager::unload_component(self, component_id).await
+    }
+
+    async fn trashed_components(&self) -> Result<Vec<TrashedComponent>> {
+        self.list_trashed_components().await
+    }
+
+    async fn restore_component(&self, component_id: &str) -> Result<()> {
+        crate::LifecycleManager::restore_component(self, component_id).await
+    }
+
+    async fn policy(&self, component_id: &str) -> Option<PolicyInfo> {
+        self.get_policy_info(component_id).await
+    }
+
+    async fn permissions(&self, component_id: &str) -> Result<policy::PolicyDocument> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        self.load_or_create_component_policy(component_id).await
+    }
+
+    async fn grant_permission(
+        &self,
+        component_id: &str,
+        permission_type: &str,
+        details: &Value,
+    ) -> Result<()> {
+        crate::LifecycleManager::grant_permission(self, component_id, permission_type, details)
+            .await
+    }
+
+    async fn revoke_permission(
+        &self,
+        component_id: &str,
+        permission_type: &str,
+        details: &Value,
+    ) -> Result<()> {
+        crate::LifecycleManager::revoke_permission(self, component_id, permission_type, details)
+            .await
+    }
+
+    async fn reset_permissions(&self, component_id: &str) -> Result<()> {
+        crate::LifecycleManager::reset_permissions(self, component_id).await
+    }
+
+    async fn policy_yaml(&self, component_id: &str) -> Result<Option<String>> {
+        self.get_policy_yaml(component_id).await
+    }
+
+    async fn set_policy_yaml(&self, component_id: &str, yaml: &str) -> Result<Vec<PolicyChange>> {
+        crate::LifecycleManager::set_policy_yaml(self, component_id, yaml).await
+    }
+
+    async fn secret_keys(&self, component_id: &str) -> Result<Vec<String>> {
+        self.list_secret_keys(component_id).await
+    }
+
+    async fn set_secret(&self, component_id: &str, key: &str, value: &str) -> Result<()> {
+        crate::LifecycleManager::set_secret(self, component_id, key, value).await
+    }
+
+    async fn delete_secret(&self, component_id: &str, key: &str) -> Result<bool> {
+        crate::LifecycleManager::delete_secret(self, component_id, key).await
+    }
+
+    fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        crate::LifecycleManager::subscribe_events(self)
+    }
+
+    fn subscribe_component_logs(&self) -> broadcast::Receiver<ComponentLog> {
+        crate::LifecycleManager::subscribe_component_logs(self)
+    }
+
+    fn network_activity(&self, component_id: Option<&str>, limit: usize) -> Vec<NetworkRequest> {
+        crate::LifecycleManager::network_activity(self, component_id, limit)
+    }
+
+    async fn events(&self, query: &EventQuery) -> Result<Vec<LoggedEvent>> {
+        self.query_events(query).await
+    }
+
+    async fn metrics(&self) -> Result<ManagementMetrics> {
+        Ok(ManagementMetrics {
+            loaded_components: self.components.read().await.len(),
+            trashed_components: self.list_trashed_components().await?.len(),
+            tools: self.metrics.snapshot(),
+            components: self.metrics.component_snapshot(),
+        })
+    }
+
+    async fn tools(&self) -> Vec<Value> {
+        self.list_tools().await
+    }
+
+    async fn call_tool(&self, tool_name: &str, arguments: &Value) -> Result<String> {
+        let component_id = self.get_component_id_for_tool(tool_name).await?;
+        self.execute_component_call(&component_id, tool_name, &serde_json::to_string(arguments)?)
+            .await
+    }
+
+    async fn call_component_tool(
+        &self,
+        component_id: &str,
+        function_name: &str,
+        arguments: &Value,
+    ) -> Result<String> {
+        self.execute_component_call(
+            component_id,
+            function_name,
+            &serde_json::to_string(arguments)?,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = r#"(component
+      (core module $m (func (export "run") (result i32) i32.const 42))
+      (core instance $i (instantiate $m))
+      (func (export "run") (result u32) (canon lift (core func $i "run"))))"#;
+
+    #[test]
+    fn test_metrics_recorder() {
+        let recorder = MetricsRecorder::default();
+        recorder.record("b", "run", 5, true);
+        recorder.record("a", "run", 10, false);
+        recorder.record("b", "run", 3, true);
+
+        let tools = recorder.snapshot();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(
+            tools[1],
+            ToolMetrics {
+                component_id: "b".to_string(),
+                function_name: "run".to_string(),
+                calls: 2,
+                failures: 0,
+                total_duration_ms: 8,
+                max_duration_ms: 5,
+                p50_duration_ms: 3,
+                p90_duration_ms: 5,
+                p99_duration_ms: 5,
+                memory_limit_hits: 0,
+            }
+        );
+        assert_eq!(
+            (tools[0].component_id.as_str(), tools[0].failures),
+            ("a", 1)
+        );
+
+        recorder.record("b", "fetch", 100, true);
+        recorder.record_memory_limit_hit("b", "fetch");
+        recorder.record_http_request("b", false);
+        recorder.record_http_request("b", true);
+        recorder.record_http_request("c", false);
+        let components = recorder.component_snapshot();
+        assert_eq!(components.len(), 3);
+        assert_eq!(
+            components[1],
+            ComponentMetrics {
+                component_id: "b".to_string(),
+                calls: 3,
+                failures: 0,
+                total_duration_ms: 108,
+                max_duration_ms: 100,
+                p50_duration_ms: 5,
+                p90_duration_ms: 100,
+                p99_duration_ms: 100,
+                memory_limit_hits: 1,
+                http_requests: 2,
+                http_cache_hits: 1,
+            }
+        );
+        assert_eq!((components[2].calls, components[2].http_requests), (0, 1));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let durations = (1..=100).collect::<Vec<u64>>();
+        assert_eq!(percentile(&durations, 50), 50);
+        assert_eq!(percentile(&durations, 99), 99);
+        assert_eq!(percentile(&[7], 90), 7);
+        assert_eq!(percentile(&[], 50), 0);
+    }
+
+    async fn call_through_api(api: &impl ManagementApi) -> Result<ManagementMetrics> {
+        assert_eq!(api.call_tool("run", &serde_json::json!({})).await?, "42");
+        assert!(api
+            .call_tool("missing", &serde_json::json!({}))
+            .await
+            .is_err());
+        api.metrics().await
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_manager_management_api() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("answer.wasm"), ANSWER)?;
+        let manager = crate::LifecycleManager::new(&tempdir).await?;
+
+        assert_eq!(
+            manager.components().await?,
+            vec![ComponentSummary {
+                id: "answer".to_string(),
+                tools: vec!["run".to_string()],
+                policy_uri: None,
+                policy_warnings: Vec::new(),
+            }]
+        );
+        let metrics = call_through_api(&manager).await?;
+        assert_eq!(metrics.loaded_components, 1);
+        assert_eq!(metrics.tools.len(), 1);
+        assert_eq!(metrics.tools[0].calls, 1);
+
+        assert!(ManagementApi::permissions(&manager, "answer")
+            .await?
+            .permissions
+            .network
+            .is_none());
+        assert!(ManagementApi::permissions(&manager, "missing")
+            .await
+            .is_err());
+
+        ManagementApi::unload_component(&manager, "answer").await?;
+        let metrics = ManagementApi::metrics(&manager).await?;
+        assert_eq!(
+            (metrics.loaded_components, metrics.trashed_components),
+            (0, 1)
+        );
+        Ok(())
+    }
+}
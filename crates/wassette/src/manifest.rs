@@ -0,0 +1,899 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Declarative desired-state management
+//!
+//! A `wassette.yaml` manifest lists the components that should be loaded, where to get them from,
+//! the digest they are expected to have and the policy that should be attached to them.
+//! [`crate::LifecycleManager::reconcile`] compares the running state against the manifest, reports
+//! any drift and loads, updates or unloads components until the two match.
+//!
+//! ```yaml
+//! version: "1.0"
+//! prune: true
+//! components:
+//!   - id: fetch_rs
+//!     source: oci://ghcr.io/microsoft/fetch-rs:latest
+//!     digest: sha256:6a0c1a...
+//!     policy: file://./policies/fetch.yaml
+//! ```
+//!
+//! Instead of a fixed source, a component can follow a release channel of its OCI repository;
+//! see [`crate::channels`]. A component with a `public_key` is only loaded when its cosign
+//! signature verifies, and one with `keyless` when it was signed keylessly by the given identity.
+//! Components from `file://` and `https://` sources are checked against the detached
+//! `signature` of their wasm file instead.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use policy::{PolicyDocument, PolicyParser};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::bundle::sha256_hex;
+use crate::channels::{split_constraint, Channel, LockedComponent};
+use crate::keyless::KeylessVerification;
+use crate::loader::{self, PolicyResource};
+use crate::signature::SignatureVerifier;
+
+/// Conventional file name of a desired-state manifest
+pub const MANIFEST_FILE: &str = "wassette.yaml";
+
+const MANIFEST_VERSION: &str = "1.0";
+
+/// The components that should be loaded, as declared in a `wassette.yaml` manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredStateManifest {
+    /// Manifest format version
+    pub version: String,
+    /// Unload loaded components that are not listed in the manifest
+    #[serde(default)]
+    pub prune: bool,
+    /// The desired components
+    #[serde(default)]
+    pub components: Vec<DesiredComponent>,
+}
+
+/// A component entry of a desired-state manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredComponent {
+    /// ID the component is loaded under
+    pub id: String,
+    /// Where to load the component from (file://, oci:// or https://)
+    pub source: String,
+    /// Expected SHA-256 digest of the component's wasm file, as `sha256:<hex>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// URI of the policy to attach to the component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    /// Release channel of an `oci://` source to follow: a tag such as `stable` or a semver range
+    /// such as `^1.2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// URI of the cosign public key the component must be signed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Identity that must have signed the component keylessly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyless: Option<KeylessVerification>,
+    /// URI of the detached signature or bundle of the wasm file of a `file://` or `https://`
+    /// source, as written by `cosign sign-blob`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl DesiredComponent {
+    /// The verifier of the signatures of the component, if it must be signed
+    pub(crate) async fn verifier(&self) -> Result<Option<SignatureVerifier>> {
+        match (&self.public_key, &self.keyless) {
+            (Some(public_key), _) => Ok(Some(SignatureVerifier::from_file(public_key).await?)),
+            (None, Some(keyless)) => Ok(Some(keyless.verifier().await?)),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl DesiredStateManifest {
+    /// Parses and validates a manifest from YAML. A source whose tag is a semver range, such as
+    /// `oci://ghcr.io/microsoft/fetch-rs:^1.2`, becomes the untagged source with that range as
+    /// its channel.
+    pub fn parse_str(content: &str) -> Result<Self> {
+        let mut manifest: Self = serde_yaml::from_str(content).context("Invalid manifest")?;
+        for component in &mut manifest.components {
+            if component.channel.is_some() {
+                continue;
+            }
+            if let Some((repository, range)) = split_constraint(&component.source) {
+                let source = format!("oci://{repository}");
+                component.channel = Some(range.to_string());
+                component.source = source;
+            }
+        }
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Reads a manifest from disk. Relative `file://` sources and policies are resolved against
+    /// the directory of the manifest, so a manifest can live next to the files it references.
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        let mut manifest = Self::parse_str(&content)?;
+
+        let base_dir = path
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        for component in &mut manifest.components {
+            component.source = resolve_relative_file_uri(&component.source, &base_dir);
+            if let Some(policy) = &mut component.policy {
+                *policy = resolve_relative_file_uri(policy, &base_dir);
+            }
+            if let Some(public_key) = &mut component.public_key {
+                *public_key = resolve_relative_file_uri(public_key, &base_dir);
+            }
+            if let Some(signature) = &mut component.signature {
+                *signature = resolve_relative_file_uri(signature, &base_dir);
+            }
+            if let Some(keyless) = &mut component.keyless {
+                for root in [&mut keyless.fulcio_root, &mut keyless.trusted_root]
+                    .into_iter()
+                    .flatten()
+                {
+                    *root = resolve_relative_file_uri(root, &base_dir);
+                }
+                if let Some(rekor_public_key) = &mut keyless.rekor_public_key {
+                    *rekor_public_key = resolve_relative_file_uri(rekor_public_key, &base_dir);
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.version != MANIFEST_VERSION {
+            bail!(
+                "Unsupported manifest version: {} (expected {})",
+                self.version,
+                MANIFEST_VERSION
+            );
+        }
+
+        let mut ids = HashSet::new();
+        for component in &self.components {
+            if component.id.is_empty() {
+                bail!("Manifest component IDs cannot be empty");
+            }
+            if component.source.is_empty() {
+                bail!("Manifest component {} has no source", component.id);
+            }
+            if !ids.insert(component.id.as_str()) {
+                bail!("Component {} is listed more than once", component.id);
+            }
+            if let Some(digest) = &component.digest {
+                let valid = digest.strip_prefix("sha256:").is_some_and(|hex| {
+                    hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+                });
+                if !valid {
+                    bail!(
+                        "Invalid digest for component {}: expected sha256:<64 hex characters>",
+                        component.id
+                    );
+                }
+            }
+            let is_oci = component.source.starts_with("oci://");
+            if let Some(channel) = &component.channel {
+                if !is_oci {
+                    bail!(
+                        "Component {} follows a channel but its source is not an oci:// reference",
+                        component.id
+                    );
+                }
+                if component.digest.is_some() {
+                    bail!(
+                        "Component {} can't pin a digest and follow a channel",
+                        component.id
+                    );
+                }
+                if split_constraint(&component.source).is_some() {
+                    bail!(
+                        "Component {} has both a channel and a version range in its source",
+                        component.id
+                    );
+                }
+                channel
+                    .parse::<Channel>()
+                    .with_context(|| format!("Invalid channel for component {}", component.id))?;
+            }
+            let verified = component.public_key.is_some() || component.keyless.is_some();
+            if verified && !is_oci && component.signature.is_none() {
+                bail!(
+                    "Component {} must be signed but its source is neither an oci:// reference nor has a detached signature",
+                    component.id
+                );
+            }
+            if component.signature.is_some() && (is_oci || !verified) {
+                bail!(
+                    "Component {} has a detached signature, which needs a public key or keyless verification and a file:// or https:// source",
+                    component.id
+                );
+            }
+            if let Some(keyless) = &component.keyless {
+                if component.public_key.is_some() {
+                    bail!(
+                        "Component {} can't have both a public key and keyless verification",
+                        component.id
+                    );
+                }
+                keyless.validate().with_context(|| {
+                    format!(
+                        "Invalid keyless verification for component {}",
+                        component.id
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn resolve_relative_file_uri(uri: &str, base_dir: &Path) -> String {
+    match uri.strip_prefix("file://") {
+        Some(path) if Path::new(path).is_relative() => {
+            format!("file://{}", base_dir.join(path).display())
+        }
+        _ => uri.to_string(),
+    }
+}
+
+/// A difference between the running state and the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Drift {
+    /// The component is in the manifest but not loaded
+    Missing {
+        /// ID of the component
+        component_id: String,
+    },
+    /// The loaded component is not the one pinned by the manifest
+    DigestMismatch {
+        /// ID of the component
+        component_id: String,
+        /// Digest from the manifest
+        expected: String,
+        /// Digest of the loaded component
+        actual: String,
+    },
+    /// The component's attached policy differs from the policy in the manifest
+    PolicyMismatch {
+        /// ID of the component
+        component_id: String,
+    },
+    /// The component is loaded but not listed in the manifest
+    Unmanaged {
+        /// ID of the component
+        component_id: String,
+    },
+    /// The component follows a channel but no version of it is pinned in the lockfile
+    Unpinned {
+        /// ID of the component
+        component_id: String,
+    },
+    /// An update of the component's channel was approved but is not loaded yet
+    UpdateApproved {
+        /// ID of the component
+        component_id: String,
+        /// Reference of the approved version
+        reference: String,
+    },
+}
+
+impl Drift {
+    /// ID of the drifted component
+    pub fn component_id(&self) -> &str {
+        match self {
+            Drift::Missing { component_id }
+            | Drift::DigestMismatch { component_id, .. }
+            | Drift::PolicyMismatch { component_id }
+            | Drift::Unmanaged { component_id }
+            | Drift::Unpinned { component_id }
+            | Drift::UpdateApproved { component_id, .. } => component_id,
+        }
+    }
+}
+
+/// A component that could not be brought in line with the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileFailure {
+    /// ID of the component
+    pub component_id: String,
+    /// Why reconciling the component failed
+    pub error: String,
+}
+
+/// The outcome of a reconcile run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    /// Drift found before any changes were made
+    pub drift: Vec<Drift>,
+    /// Whether changes were applied, or the run only reported drift
+    pub applied: bool,
+    /// Components that were loaded because they were missing
+    pub loaded: Vec<String>,
+    /// Components whose wasm or policy was replaced
+    pub updated: Vec<String>,
+    /// Unmanaged components that were unloaded because the manifest prunes
+    pub unloaded: Vec<String>,
+    /// Components that could not be reconciled
+    pub failed: Vec<ReconcileFailure>,
+}
+
+impl ReconcileReport {
+    /// Whether the running state matched the manifest before reconciling
+    pub fn in_sync(&self) -> bool {
+        self.drift.is_empty()
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Compares the running state against `manifest` without changing anything
+    #[instrument(skip_all)]
+    pub async fn detect_drift(&self, manifest: &DesiredStateManifest) -> Result<Vec<Drift>> {
+        let mut drift = Vec::new();
+        let lockfile = self.read_lockfile().await?;
+
+        for desired in &manifest.components {
+            let component_id = desired.id.clone();
+            if self.get_component(&component_id).await.is_none() {
+                drift.push(Drift::Missing { component_id });
+                continue;
+            }
+
+            // A component that follows a channel is expected to run its pinned version
+            let expected = match (&desired.channel, lockfile.locked(desired)) {
+                (None, _) => desired.digest.clone(),
+                (Some(_), None) => {
+                    drift.push(Drift::Unpinned { component_id });
+                    continue;
+                }
+                (Some(_), Some(locked)) => match &locked.wasm_digest {
+                    Some(wasm_digest) => Some(wasm_digest.clone()),
+                    None => {
+                        drift.push(Drift::UpdateApproved {
+                            component_id,
+                            reference: locked.pinned.reference.clone(),
+                        });
+                        continue;
+                    }
+                },
+            };
+            if let Some(expected) = &expected {
+                let wasm = tokio::fs::read(self.component_path(&component_id)).await?;
+                let actual = format!("sha256:{}", sha256_hex(&wasm));
+                if !actual.eq_ignore_ascii_case(expected) {
+                    drift.push(Drift::DigestMismatch {
+                        component_id,
+                        expected: expected.clone(),
+                        actual,
+                    });
+                    continue;
+                }
+            }
+
+            if let Some(policy_uri) = &desired.policy {
+                let desired_policy = self.fetch_policy(policy_uri).await?;
+                let policy_path = self.get_component_policy_path(&component_id);
+                let attached_policy = if tokio::fs::try_exists(&policy_path).await.unwrap_or(false)
+                {
+                    PolicyParser::parse_file(&policy_path).ok()
+                } else {
+                    None
+                };
+                if attached_policy.as_ref() != Some(&desired_policy) {
+                    drift.push(Drift::PolicyMismatch { component_id });
+                }
+            }
+        }
+
+        let desired_ids: HashSet<&str> =
+            manifest.components.iter().map(|c| c.id.as_str()).collect();
+        let mut loaded = self.list_components().await;
+        loaded.sort();
+        for component_id in loaded {
+            if !desired_ids.contains(component_id.as_str()) {
+                drift.push(Drift::Unmanaged { component_id });
+            }
+        }
+
+        Ok(drift)
+    }
+
+    /// Brings the running state in line with `manifest`: missing components are loaded,
+    /// components with the wrong digest or policy are updated and, when the manifest prunes,
+    /// unmanaged components are unloaded. A component that fails to reconcile is recorded in
+    /// the report and does not stop the others. With `dry_run` only the drift is reported.
+    #[instrument(skip_all, fields(dry_run))]
+    pub async fn reconcile(
+        &self,
+        manifest: &DesiredStateManifest,
+        dry_run: bool,
+    ) -> Result<ReconcileReport> {
+        let mut report = ReconcileReport {
+            drift: self.detect_drift(manifest).await?,
+            applied: !dry_run,
+            ..Default::default()
+        };
+        if dry_run {
+            return Ok(report);
+        }
+
+        for drift in report.drift.clone() {
+            let component_id = drift.component_id().to_string();
+            let desired = manifest.components.iter().find(|c| c.id == component_id);
+            let result = match (&drift, desired) {
+                (Drift::Missing { .. }, Some(desired)) => self
+                    .load_desired_component(desired)
+                    .await
+                    .map(|_| report.loaded.push(component_id.clone())),
+                (
+                    Drift::DigestMismatch { .. }
+                    | Drift::Unpinned { .. }
+                    | Drift::UpdateApproved { .. },
+                    Some(desired),
+                ) => self
+                    .load_desired_component(desired)
+                    .await
+                    .map(|_| report.updated.push(component_id.clone())),
+                (Drift::PolicyMismatch { .. }, Some(desired)) => {
+                    let policy_uri = desired.policy.as_deref().unwrap_or_default();
+                    self.attach_policy(&component_id, policy_uri)
+                        .await
+                        .map(|_| report.updated.push(component_id.clone()))
+                }
+                (Drift::Unmanaged { .. }, _) if manifest.prune => self
+                    .unload_component(&component_id)
+                    .await
+                    .map(|_| report.unloaded.push(component_id.clone())),
+                _ => Ok(()),
+            };
+
+            if let Err(e) = result {
+                warn!(component_id, error = %e, "Failed to reconcile component");
+                report.failed.push(ReconcileFailure {
+                    component_id,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+
+        info!(
+            loaded = report.loaded.len(),
+            updated = report.updated.len(),
+            unloaded = report.unloaded.len(),
+            failed = report.failed.len(),
+            "Reconciled components with manifest"
+        );
+        Ok(report)
+    }
+
+    /// Loads a manifest component from its source, checking its ID, digest and signature before
+    /// it replaces anything, then attaches its policy. A component that follows a channel is
+    /// loaded from its pinned version, which is resolved and pinned first if there is none yet.
+    async fn load_desired_component(&self, desired: &DesiredComponent) -> Result<()> {
+        let mut lockfile = self.read_lockfile().await?;
+        let mut locked = None;
+        let source = if desired.channel.is_some() {
+            let entry = match lockfile.locked(desired) {
+                Some(entry) => entry.clone(),
+                None => LockedComponent {
+                    channel: desired.channel.clone().unwrap_or_default(),
+                    pinned: self.resolve_channel(desired).await?,
+                    wasm_digest: None,
+                    staged: None,
+                },
+            };
+            let source = entry.pinned.source()?;
+            locked = Some(entry);
+            source
+        } else if desired.signature.is_some() {
+            // Checked against the detached signature once downloaded
+            desired.source.clone()
+        } else if let Some(verifier) = desired.verifier().await? {
+            self.verified_source(&desired.source, &verifier).await?
+        } else {
+            desired.source.clone()
+        };
+
+        let downloaded = self.download_component(&source).await?;
+
+        let id = downloaded.id()?;
+        if id != desired.id {
+            bail!(
+                "Source {} provides component {} but the manifest expects {}",
+                desired.source,
+                id,
+                desired.id
+            );
+        }
+        let wasm = tokio::fs::read(downloaded.as_ref()).await?;
+        if let (Some(signature), Some(verifier)) = (&desired.signature, desired.verifier().await?) {
+            self.verify_component_file(&verifier, &wasm, signature)
+                .await
+                .with_context(|| format!("Signature of component {} doesn't verify", desired.id))?;
+            info!(component_id = %desired.id, "Component signature verified");
+        }
+        let actual = format!("sha256:{}", sha256_hex(&wasm));
+        if let Some(expected) = &desired.digest {
+            if !actual.eq_ignore_ascii_case(expected) {
+                bail!(
+                    "Digest mismatch for {}: expected {}, got {}",
+                    desired.source,
+                    expected,
+                    actual
+                );
+            }
+        }
+
+        self.load_downloaded_component(downloaded).await?;
+        if let Some(mut locked) = locked {
+            locked.wasm_digest = Some(actual);
+            lockfile.components.insert(desired.id.clone(), locked);
+            self.write_lockfile(&lockfile).await?;
+        }
+        if let Some(policy_uri) = &desired.policy {
+            self.attach_policy(&desired.id, policy_uri).await?;
+        }
+        Ok(())
+    }
+
+    /// Resolves an `oci://` source to its digest and checks the signature of that digest, so
+    /// the artifact that is loaded is the one that was verified
+    async fn verified_source(&self, source: &str, verifier: &SignatureVerifier) -> Result<String> {
+        let reference: oci_client::Reference = source
+            .strip_prefix("oci://")
+            .context("Only oci:// sources can be verified")?
+            .parse()
+            .with_context(|| format!("Invalid OCI reference {source}"))?;
+        let digest = match reference.digest() {
+            Some(digest) => digest.to_string(),
+            None => self
+                .oci_client
+                .fetch_manifest_digest(&reference, &oci_client::secrets::RegistryAuth::Anonymous)
+                .await
+                .with_context(|| format!("Failed to resolve {source}"))?,
+        };
+        self.verify_artifact(verifier, &reference, &digest).await?;
+        Ok(format!(
+            "oci://{}",
+            reference.clone_with_digest(digest).whole()
+        ))
+    }
+
+    async fn fetch_policy(&self, policy_uri: &str) -> Result<PolicyDocument> {
+        let downloaded = loader::load_resource::<PolicyResource>(
+            policy_uri,
+            &self.oci_client,
+            &self.http_client,
+        )
+        .await?;
+        self.parse_policy_file(downloaded.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::tests::{build_example_component, create_test_manager, TEST_COMPONENT_ID};
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = DesiredStateManifest::parse_str(
+            r#"
+version: "1.0"
+prune: true
+components:
+  - id: fetch_rs
+    source: oci://ghcr.io/microsoft/fetch-rs:latest
+    digest: sha256:0000000000000000000000000000000000000000000000000000000000000000
+    policy: file:///etc/wassette/fetch.yaml
+  - id: time
+    source: file:///components/time.wasm
+  - id: eval_py
+    source: oci://ghcr.io/microsoft/eval-py:~2.0
+"#,
+        )
+        .unwrap();
+
+        assert!(manifest.prune);
+        assert_eq!(manifest.components.len(), 3);
+        assert_eq!(manifest.components[1].digest, None);
+        assert_eq!(manifest.components[1].policy, None);
+        assert_eq!(manifest.components[0].channel, None);
+        assert_eq!(
+            manifest.components[2].source,
+            "oci://ghcr.io/microsoft/eval-py"
+        );
+        assert_eq!(manifest.components[2].channel.as_deref(), Some("~2.0"));
+    }
+
+    #[test]
+    fn test_parse_manifest_rejects_invalid() {
+        let with_components = |components: &str| {
+            DesiredStateManifest::parse_str(&format!("version: \"1.0\"\ncomponents:\n{components}"))
+        };
+
+        assert!(DesiredStateManifest::parse_str("version: \"2.0\"").is_err());
+        assert!(with_components(
+            "  - id: a\n    source: file:///a.wasm\n  - id: a\n    source: file:///b.wasm"
+        )
+        .is_err());
+        assert!(
+            with_components("  - id: a\n    source: file:///a.wasm\n    digest: md5:abc").is_err()
+        );
+        assert!(with_components("  - id: \"\"\n    source: file:///a.wasm").is_err());
+
+        let oci = "  - id: a\n    source: oci://ghcr.io/a\n";
+        assert!(
+            with_components("  - id: a\n    source: oci://ghcr.io/a:^1\n    channel: stable")
+                .is_err()
+        );
+        assert!(with_components(&format!("{oci}    channel: ^1.2")).is_ok());
+        assert!(with_components(&format!("{oci}    channel: stable")).is_ok());
+        assert!(with_components(&format!("{oci}    channel: \"-stable\"")).is_err());
+        assert!(with_components(&format!(
+            "{oci}    channel: stable\n    digest: sha256:{}",
+            "0".repeat(64)
+        ))
+        .is_err());
+        assert!(
+            with_components("  - id: a\n    source: file:///a.wasm\n    channel: stable").is_err()
+        );
+        assert!(with_components(
+            "  - id: a\n    source: file:///a.wasm\n    public_key: file:///cosign.pub"
+        )
+        .is_err());
+
+        let keyless = "    keyless:\n      fulcio_root: file:///fulcio.pem\n      rekor_public_key: file:///rekor.pub\n";
+        let issuer = "      issuer: https://token.actions.githubusercontent.com\n";
+        assert!(with_components(&format!("{oci}{keyless}{issuer}")).is_ok());
+        assert!(with_components(&format!("{oci}{keyless}")).is_err());
+        assert!(with_components(&format!("{oci}{keyless}      subject: \"(\"\n")).is_err());
+        assert!(with_components(&format!(
+            "  - id: a\n    source: file:///a.wasm\n{keyless}{issuer}"
+        ))
+        .is_err());
+        let signature = "    signature: file:///a.wasm.sigstore.json\n";
+        assert!(with_components(&format!(
+            "  - id: a\n    source: file:///a.wasm\n{keyless}{issuer}{signature}"
+        ))
+        .is_ok());
+        assert!(with_components(&format!(
+            "  - id: a\n    source: https://example.com/a.wasm\n    public_key: file:///cosign.pub\n{signature}"
+        ))
+        .is_ok());
+        assert!(with_components(&format!(
+            "  - id: a\n    source: file:///a.wasm\n{signature}"
+        ))
+        .is_err());
+        assert!(with_components(&format!(
+            "{oci}    public_key: file:///cosign.pub\n{signature}"
+        ))
+        .is_err());
+        assert!(with_components(&format!(
+            "{oci}    public_key: file:///cosign.pub\n{keyless}{issuer}"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_resolve_relative_file_uri() {
+        let base = Path::new("/repo/tools");
+        assert_eq!(
+            resolve_relative_file_uri("file://./fetch.wasm", base),
+            "file:///repo/tools/./fetch.wasm"
+        );
+        assert_eq!(
+            resolve_relative_file_uri("file:///abs/fetch.wasm", base),
+            "file:///abs/fetch.wasm"
+        );
+        assert_eq!(
+            resolve_relative_file_uri("oci://ghcr.io/fetch:latest", base),
+            "oci://ghcr.io/fetch:latest"
+        );
+    }
+
+    #[test(tokio::test)]
+    async fn test_reconcile() -> Result<()> {
+        let manager = create_test_manager().await?;
+        let component_path = build_example_component().await?;
+        let digest = format!(
+            "sha256:{}",
+            sha256_hex(&tokio::fs::read(&component_path).await?)
+        );
+
+        let policy_dir = tempfile::tempdir()?;
+        let policy_path = policy_dir.path().join("fetch.yaml");
+        tokio::fs::write(
+            &policy_path,
+            "version: \"1.0\"\npermissions:\n  network:\n    allow:\n      - host: api.example.com\n",
+        )
+        .await?;
+
+        let manifest = DesiredStateManifest {
+            version: MANIFEST_VERSION.to_string(),
+            prune: true,
+            components: vec![DesiredComponent {
+                id: TEST_COMPONENT_ID.to_string(),
+                source: format!("file://{}", component_path.display()),
+                digest: Some(digest),
+                policy: Some(format!("file://{}", policy_path.display())),
+                channel: None,
+                public_key: None,
+                keyless: None,
+                signature: None,
+            }],
+        };
+
+        let report = manager.reconcile(&manifest, true).await?;
+        assert!(!report.applied);
+        assert_eq!(
+            report.drift,
+            vec![Drift::Missing {
+                component_id: TEST_COMPONENT_ID.to_string()
+            }]
+        );
+        assert!(manager.list_components().await.is_empty());
+
+        let report = manager.reconcile(&manifest, false).await?;
+        assert_eq!(report.loaded, vec![TEST_COMPONENT_ID.to_string()]);
+        assert!(report.failed.is_empty());
+        assert!(manager.get_policy_info(TEST_COMPONENT_ID).await.is_some());
+        assert!(manager.detect_drift(&manifest).await?.is_empty());
+
+        // Granting a permission at runtime is drift from the declared policy
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "other.example.com"}),
+            )
+            .await?;
+        let report = manager.reconcile(&manifest, false).await?;
+        assert_eq!(
+            report.drift,
+            vec![Drift::PolicyMismatch {
+                component_id: TEST_COMPONENT_ID.to_string()
+            }]
+        );
+        assert_eq!(report.updated, vec![TEST_COMPONENT_ID.to_string()]);
+        assert!(manager.detect_drift(&manifest).await?.is_empty());
+
+        // An empty pruning manifest unloads everything
+        let empty = DesiredStateManifest {
+            components: Vec::new(),
+            ..manifest
+        };
+        let report = manager.reconcile(&empty, false).await?;
+        assert_eq!(report.unloaded, vec![TEST_COMPONENT_ID.to_string()]);
+        assert!(manager.list_components().await.is_empty());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_reconcile_rejects_wrong_digest() -> Result<()> {
+        let manager = create_test_manager().await?;
+        let component_path = build_example_component().await?;
+
+        let manifest = DesiredStateManifest {
+            version: MANIFEST_VERSION.to_string(),
+            prune: false,
+            components: vec![DesiredComponent {
+                id: TEST_COMPONENT_ID.to_string(),
+                source: format!("file://{}", component_path.display()),
+                digest: Some(format!("sha256:{}", "0".repeat(64))),
+                policy: None,
+                channel: None,
+                public_key: None,
+                keyless: None,
+                signature: None,
+            }],
+        };
+
+        let report = manager.reconcile(&manifest, false).await?;
+        assert!(report.loaded.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].error.contains("Digest mismatch"));
+        assert!(manager.list_components().await.is_empty());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_detect_channel_drift() -> Result<()> {
+        let manager = create_test_manager().await?;
+        let component_path = build_example_component().await?;
+        let digest = format!(
+            "sha256:{}",
+            sha256_hex(&tokio::fs::read(&component_path).await?)
+        );
+        manager
+            .load_component(&format!("file://{}", component_path.display()))
+            .await?;
+
+        let manifest = DesiredStateManifest {
+            version: MANIFEST_VERSION.to_string(),
+            prune: false,
+            components: vec![DesiredComponent {
+                id: TEST_COMPONENT_ID.to_string(),
+                source: "oci://ghcr.io/microsoft/fetch-rs".to_string(),
+                digest: None,
+                policy: None,
+                channel: Some("^1".to_string()),
+                public_key: None,
+                keyless: None,
+                signature: None,
+            }],
+        };
+        let component_id = TEST_COMPONENT_ID.to_string();
+        assert_eq!(
+            manager.detect_drift(&manifest).await?,
+            vec![Drift::Unpinned {
+                component_id: component_id.clone()
+            }]
+        );
+
+        let pinned = crate::channels::ResolvedVersion {
+            reference: "ghcr.io/microsoft/fetch-rs:1.1.0".to_string(),
+            digest: format!("sha256:{}", "a".repeat(64)),
+            verified: false,
+            resolved_at: "2025-01-01T00:00:00.000Z".to_string(),
+        };
+        let mut lockfile = crate::channels::Lockfile::default();
+        let mut locked = LockedComponent {
+            channel: "^1".to_string(),
+            pinned,
+            wasm_digest: None,
+            staged: None,
+        };
+        lockfile
+            .components
+            .insert(component_id.clone(), locked.clone());
+        manager.write_lockfile(&lockfile).await?;
+        assert_eq!(
+            manager.detect_drift(&manifest).await?,
+            vec![Drift::UpdateApproved {
+                component_id: component_id.clone(),
+                reference: "ghcr.io/microsoft/fetch-rs:1.1.0".to_string(),
+            }]
+        );
+
+        locked.wasm_digest = Some(digest);
+        lockfile.components.insert(component_id.clone(), locked);
+        manager.write_lockfile(&lockfile).await?;
+        assert!(manager.detect_drift(&manifest).await?.is_empty());
+
+        // A pin of another channel doesn't count
+        let manifest = DesiredStateManifest {
+            components: vec![DesiredComponent {
+                channel: Some("stable".to_string()),
+                ..manifest.components[0].clone()
+            }],
+            ..manifest
+        };
+        assert_eq!(
+            manager.detect_drift(&manifest).await?,
+            vec![Drift::Unpinned { component_id }]
+        );
+
+        Ok(())
+    }
+}
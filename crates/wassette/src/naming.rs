@@ -0,0 +1,251 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Names of component tools
+//!
+//! A tool is named after the exported function it calls, so two components that export the same
+//! function, such as `fetch`, would end up with tools of the same name. [`ToolNaming`] can prefix
+//! every tool with the ID of its component, or an alias of it, as `<prefix>__<tool>`, and its
+//! [`ToolCollision`] decides what happens when a component that is loaded still has a tool
+//! named like one of another component.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use component2json::component_exports_to_tools;
+
+use crate::{ComponentRegistry, LifecycleManager};
+
+/// Separates the prefix of a tool from the name of its function
+pub const TOOL_PREFIX_SEPARATOR: &str = "__";
+
+/// What happens when a component has a tool named like a tool of a component loaded before it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolCollision {
+    /// The tool of the new component is prefixed with its component's ID or alias
+    #[default]
+    Prefix,
+    /// The new component isn't loaded
+    Reject,
+    /// The tool of the new component is left out, and the other component keeps the name
+    KeepFirst,
+}
+
+impl FromStr for ToolCollision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "prefix" => Ok(Self::Prefix),
+            "reject" => Ok(Self::Reject),
+            "first" => Ok(Self::KeepFirst),
+            _ => bail!("Tool collision strategy must be prefix, reject or first: {s}"),
+        }
+    }
+}
+
+/// The prefix of the tools of a component instead of its ID, parsed from `<component>=<alias>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolAlias {
+    /// ID of the component
+    pub component: String,
+    /// Prefix of the component's tools
+    pub alias: String,
+}
+
+impl FromStr for ToolAlias {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (component, alias) = s
+            .split_once('=')
+            .with_context(|| format!("Tool alias must be <component>=<alias>: {s}"))?;
+        if component.is_empty() {
+            bail!("Tool alias has no component: {s}");
+        }
+        if alias.is_empty()
+            || !alias
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("Tool aliases may only contain letters, digits, '_' and '-': {s}");
+        }
+        Ok(Self {
+            component: component.to_string(),
+            alias: alias.to_string(),
+        })
+    }
+}
+
+/// How the tools of components are named
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolNaming {
+    /// Whether every tool is prefixed with its component's ID or alias
+    pub prefix: bool,
+    /// Prefixes used instead of component IDs, by component ID
+    pub aliases: HashMap<String, String>,
+    /// What happens to a tool named like a tool of another component
+    pub collision: ToolCollision,
+}
+
+impl ToolNaming {
+    /// Naming with the given aliases. Fails if a component has more than one.
+    pub fn new(prefix: bool, aliases: Vec<ToolAlias>, collision: ToolCollision) -> Result<Self> {
+        let mut by_component = HashMap::new();
+        for ToolAlias { component, alias } in aliases {
+            if by_component.insert(component.clone(), alias).is_some() {
+                bail!("Component {component} has more than one tool alias");
+            }
+        }
+        Ok(Self {
+            prefix,
+            aliases: by_component,
+            collision,
+        })
+    }
+
+    /// The name of the tool `tool` of `component_id` with the component's prefix
+    pub(crate) fn prefixed(&self, component_id: &str, tool: &str) -> String {
+        let prefix = match self.aliases.get(component_id) {
+            Some(alias) => alias.clone(),
+            // Component IDs come from file names, which may contain characters tool names can't
+            None => component_id
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect(),
+        };
+        format!("{prefix}{TOOL_PREFIX_SEPARATOR}{tool}")
+    }
+
+    /// The name of the tool `tool` of `component_id` unless it collides
+    pub(crate) fn name(&self, component_id: &str, tool: &str) -> String {
+        if self.prefix {
+            self.prefixed(component_id, tool)
+        } else {
+            tool.to_string()
+        }
+    }
+}
+
+impl LifecycleManager {
+    /// Names the tools of components with `naming`, including the ones already loaded. Fails if
+    /// two loaded components have a tool of the same name and `naming` rejects collisions.
+    pub async fn with_tool_naming(self, naming: ToolNaming) -> Result<Self> {
+        let components = self.components.read().await;
+        let mut component_ids = components.keys().collect::<Vec<_>>();
+        component_ids.sort();
+        let mut registry = ComponentRegistry {
+            naming,
+            ..Default::default()
+        };
+        for component_id in component_ids {
+            registry.register_tools(
+                component_id,
+                component_exports_to_tools(&components[component_id].component, &self.engine, true),
+            )?;
+        }
+        *self.registry.write().await = registry;
+        drop(components);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::COMPONENT;
+
+    #[test]
+    fn test_tool_naming() {
+        let naming = ToolNaming::new(
+            true,
+            vec!["fetch-rs=web".parse().unwrap()],
+            "reject".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(naming.collision, ToolCollision::Reject);
+        assert_eq!(naming.name("fetch-rs", "fetch"), "web__fetch");
+        assert_eq!(naming.name("my.tools", "fetch"), "my_tools__fetch");
+        assert_eq!(ToolNaming::default().name("fetch-rs", "fetch"), "fetch");
+
+        assert!("fetch-rs".parse::<ToolAlias>().is_err());
+        assert!("fetch-rs=we b".parse::<ToolAlias>().is_err());
+        assert!("last".parse::<ToolCollision>().is_err());
+        assert!(ToolNaming::new(
+            false,
+            vec!["a=x".parse().unwrap(), "a=y".parse().unwrap()],
+            ToolCollision::Prefix
+        )
+        .is_err());
+    }
+
+    async fn tool_names(manager: &LifecycleManager) -> Vec<String> {
+        let mut names = manager
+            .list_tools()
+            .await
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_tool_collisions() -> Result<()> {
+        let plugin_dir = tempfile::tempdir()?;
+        for id in ["alpha", "beta"] {
+            tokio::fs::write(plugin_dir.path().join(format!("{id}.wasm")), COMPONENT).await?;
+        }
+
+        // The component that sorts last gets the prefix
+        let manager = LifecycleManager::new(&plugin_dir).await?;
+        assert_eq!(tool_names(&manager).await, vec!["beta__run", "run"]);
+        assert_eq!(manager.get_component_id_for_tool("run").await?, "alpha");
+        assert_eq!(
+            manager.get_component_id_for_tool("beta__run").await?,
+            "beta"
+        );
+        assert_eq!(
+            manager
+                .execute_component_call("beta", "beta__run", "{}")
+                .await?,
+            "null"
+        );
+        let schema = manager.get_component_schema("beta").await.unwrap();
+        assert_eq!(schema["tools"][0]["name"], "beta__run");
+
+        let manager = manager
+            .with_tool_naming(ToolNaming {
+                collision: ToolCollision::KeepFirst,
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(tool_names(&manager).await, vec!["run"]);
+
+        let manager = manager
+            .with_tool_naming(ToolNaming::new(
+                true,
+                vec!["alpha=a".parse()?],
+                ToolCollision::Reject,
+            )?)
+            .await?;
+        assert_eq!(tool_names(&manager).await, vec!["a__run", "beta__run"]);
+
+        let manager = LifecycleManager::new(&plugin_dir).await?;
+        assert!(manager
+            .with_tool_naming(ToolNaming {
+                collision: ToolCollision::Reject,
+                ..Default::default()
+            })
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! A small client for the NATS core protocol
+//!
+//! Wassette only needs plain publish and subscribe, so rather than pulling in a full NATS client
+//! this speaks the [text protocol](https://docs.nats.io/reference/reference-protocols/nats-protocol)
+//! directly. It is used by the CloudEvents sink and by the message-bus tool frontend.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, warn};
+
+/// Default port of a NATS server
+pub const DEFAULT_NATS_PORT: u16 = 4222;
+
+const NATS_TIMEOUT: Duration = Duration::from_secs(10);
+const SUBSCRIPTION_BUFFER: usize = 256;
+
+type Subscriptions = Arc<std::sync::Mutex<HashMap<u64, mpsc::Sender<NatsMessage>>>>;
+
+/// A message received on a subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct NatsMessage {
+    /// The subject the message was published to
+    pub subject: String,
+    /// The subject the publisher expects a reply on, if any
+    pub reply_to: Option<String>,
+    /// The message body
+    pub payload: Vec<u8>,
+}
+
+/// A connection to a NATS server. The connection is not re-established when it drops; check
+/// [`NatsClient::is_closed`] and connect again.
+pub struct NatsClient {
+    address: String,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    subscriptions: Subscriptions,
+    next_sid: AtomicU64,
+    closed: Arc<AtomicBool>,
+}
+
+impl NatsClient {
+    /// Connects to the NATS server at `address` (`host:port`)
+    pub async fn connect(address: &str) -> Result<Self> {
+        let stream = tokio::time::timeout(NATS_TIMEOUT, TcpStream::connect(address))
+            .await
+            .with_context(|| format!("Timed out connecting to NATS server {address}"))?
+            .with_context(|| format!("Failed to connect to NATS server {address}"))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let mut info = String::new();
+        tokio::time::timeout(NATS_TIMEOUT, reader.read_line(&mut info))
+            .await
+            .context("Timed out waiting for NATS server INFO")??;
+        if !info.starts_with("INFO") {
+            bail!("Unexpected greeting from NATS server: {}", info.trim());
+        }
+        writer
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"wassette\"}\r\n")
+            .await?;
+
+        let client = Self {
+            address: address.to_string(),
+            writer: Arc::new(Mutex::new(writer)),
+            subscriptions: Subscriptions::default(),
+            next_sid: AtomicU64::new(1),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        tokio::spawn(read_loop(
+            reader,
+            client.writer.clone(),
+            client.subscriptions.clone(),
+            client.closed.clone(),
+        ));
+        debug!(address, "Connected to NATS server");
+        Ok(client)
+    }
+
+    /// Whether the server closed the connection
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Publishes `payload` to `subject`
+    pub async fn publish(&self, subject: &str, payload: &[u8]) -> Result<()> {
+        let mut frame = format!("PUB {subject} {}\r\n", payload.len()).into_bytes();
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+        self.write(&frame).await
+    }
+
+    /// Subscribes to `subject`. Subscribers that share a `queue` group split the messages
+    /// between them instead of each receiving every message. The returned channel closes when
+    /// the connection drops.
+    pub async fn subscribe(
+        &self,
+        subject: &str,
+        queue: Option<&str>,
+    ) -> Result<mpsc::Receiver<NatsMessage>> {
+        let sid = self.next_sid.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        self.subscriptions
+            .lock()
+            .expect("subscriptions lock poisoned")
+            .insert(sid, sender);
+
+        let frame = match queue {
+            Some(queue) => format!("SUB {subject} {queue} {sid}\r\n"),
+            None => format!("SUB {subject} {sid}\r\n"),
+        };
+        self.write(frame.as_bytes()).await?;
+        Ok(receiver)
+    }
+
+    async fn write(&self, frame: &[u8]) -> Result<()> {
+        if self.is_closed() {
+            bail!("Connection to NATS server {} is closed", self.address);
+        }
+        let mut writer = self.writer.lock().await;
+        match tokio::time::timeout(NATS_TIMEOUT, writer.write_all(frame)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => {
+                self.closed.store(true, Ordering::Release);
+                Err(e.into())
+            }
+            Err(_) => {
+                self.closed.store(true, Ordering::Release);
+                bail!("Timed out writing to NATS server {}", self.address)
+            }
+        }
+    }
+}
+
+/// Handles everything the server sends: messages for our subscriptions, keep-alive pings
+/// (the server drops clients that don't answer them) and errors
+async fn read_loop(
+    mut reader: BufReader<OwnedReadHalf>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    subscriptions: Subscriptions,
+    closed: Arc<AtomicBool>,
+) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        if line.starts_with("MSG ") {
+            let Some((message, sid, len)) = parse_msg_line(&line) else {
+                warn!(line = line.trim(), "Malformed MSG from NATS server");
+                break;
+            };
+            let mut payload = vec![0; len + 2];
+            if reader.read_exact(&mut payload).await.is_err() {
+                break;
+            }
+            payload.truncate(len);
+            let sender = subscriptions
+                .lock()
+                .expect("subscriptions lock poisoned")
+                .get(&sid)
+                .cloned();
+            if let Some(sender) = sender {
+                let _ = sender.send(NatsMessage { payload, ..message }).await;
+            }
+        } else if line.starts_with("PING") {
+            let _ = writer.lock().await.write_all(b"PONG\r\n").await;
+        } else if line.starts_with("-ERR") {
+            warn!(error = line.trim(), "NATS server reported an error");
+        }
+    }
+
+    closed.store(true, Ordering::Release);
+    // Dropping the senders ends every subscription
+    subscriptions
+        .lock()
+        .expect("subscriptions lock poisoned")
+        .clear();
+    debug!("NATS connection closed");
+}
+
+/// Parses `MSG <subject> <sid> [reply-to] <#bytes>`
+fn parse_msg_line(line: &str) -> Option<(NatsMessage, u64, usize)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (subject, sid, reply_to, len) = match parts.as_slice() {
+        ["MSG", subject, sid, len] => (subject, sid, None, len),
+        ["MSG", subject, sid, reply_to, len] => (subject, sid, Some(reply_to.to_string()), len),
+        _ => return None,
+    };
+    Some((
+        NatsMessage {
+            subject: subject.to_string(),
+            reply_to,
+            payload: Vec::new(),
+        },
+        sid.parse().ok()?,
+        len.parse().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_msg_line() {
+        let (message, sid, len) = parse_msg_line("MSG tools.call 7 _INBOX.abc 12\r\n").unwrap();
+        assert_eq!(message.subject, "tools.call");
+        assert_eq!(message.reply_to.as_deref(), Some("_INBOX.abc"));
+        assert_eq!((sid, len), (7, 12));
+
+        let (message, _, _) = parse_msg_line("MSG tools.call 7 0\r\n").unwrap();
+        assert_eq!(message.reply_to, None);
+
+        assert!(parse_msg_line("MSG tools.call\r\n").is_none());
+        assert!(parse_msg_line("MSG tools.call x 3\r\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_subscribe() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"INFO {}\r\n").await.unwrap();
+            let mut reader = BufReader::new(reader);
+            let mut lines = Vec::new();
+            for _ in 0..4 {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                lines.push(line);
+            }
+            writer
+                .write_all(b"PING\r\nMSG tools.call 1 reply.here 5\r\nhello\r\n")
+                .await
+                .unwrap();
+            let mut pong = String::new();
+            reader.read_line(&mut pong).await.unwrap();
+            (lines, pong)
+        });
+
+        let client = NatsClient::connect(&address).await?;
+        client.publish("wassette.events", b"{}").await?;
+        let mut messages = client.subscribe("tools.call", Some("workers")).await?;
+
+        let message = messages.recv().await.expect("message should arrive");
+        assert_eq!(
+            message,
+            NatsMessage {
+                subject: "tools.call".to_string(),
+                reply_to: Some("reply.here".to_string()),
+                payload: b"hello".to_vec(),
+            }
+        );
+
+        let (lines, pong) = server.await?;
+        assert!(lines[0].starts_with("CONNECT "));
+        assert_eq!(lines[1], "PUB wassette.events 2\r\n");
+        assert_eq!(lines[2], "{}\r\n");
+        assert_eq!(lines[3], "SUB tools.call workers 1\r\n");
+        assert_eq!(pong, "PONG\r\n");
+
+        // The subscription ends once the server goes away
+        assert!(messages.recv().await.is_none());
+        assert!(client.is_closed());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,550 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Audit trail of outgoing HTTP requests
+//!
+//! Every `wasi:http` request of a component, including the ones its network policy blocks, is
+//! recorded as a [`NetworkRequest`] once it is finished. The most recent requests are kept in
+//! memory for [`crate::LifecycleManager::network_activity`]. Each one is also logged under the
+//! [`NETWORK_LOG_TARGET`] target, published as a `network.request` [`crate::Event`], so event
+//! sinks receive the complete trail, and written to the logs of the component.
+//!
+//! Paths are recorded as templates: identifiers become `{id}`, and segments that look like
+//! tokens or contain the value of an environment variable passed to components become
+//! `{redacted}`. [`crate::LifecycleManager::with_network_sampling`] records only a share of the
+//! successful requests of busy components; blocked and failed requests are always recorded.
+
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::events::{Event, EventBus};
+use crate::logging::{ComponentLog, LogBus, LogLevel};
+use crate::management::MetricsRecorder;
+
+/// Target of the `tracing` events emitted for outgoing HTTP requests
+pub const NETWORK_LOG_TARGET: &str = "wassette::network";
+
+/// Number of requests kept in memory
+const NETWORK_LOG_CAPACITY: usize = 1000;
+
+/// Context of the component log messages of HTTP requests
+const NETWORK_LOG_CONTEXT: &str = "http";
+
+/// Error of the requests blocked by the network policy of a component
+pub(crate) const NETWORK_POLICY_DENIAL: &str = "denied by network policy";
+
+/// Shortest value of an environment variable that is redacted from paths
+const MIN_SECRET_LEN: usize = 8;
+
+/// Shortest path segment of letters and digits that is treated as a token
+const MIN_TOKEN_LEN: usize = 20;
+
+/// An outgoing HTTP request of a component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkRequest {
+    /// The component that sent the request
+    pub component_id: String,
+    /// The request method, e.g. `GET`
+    pub method: String,
+    /// Host the request was sent to
+    pub host: String,
+    /// Path of the request as a template, without the query string and with identifiers replaced
+    /// by `{id}` and tokens by `{redacted}`, e.g. `/v1/users/{id}/repos`
+    pub path: String,
+    /// Status code of the response, if one was received
+    pub status: Option<u16>,
+    /// Bytes of the response body the component received
+    pub bytes: u64,
+    /// Time from sending the request until the response body was read or dropped, in
+    /// milliseconds
+    pub duration_ms: u64,
+    /// Whether the response was served from the HTTP cache without sending the request
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// Why the request was blocked or failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the request was sent, in RFC 3339 format
+    pub time: String,
+}
+
+impl NetworkRequest {
+    /// Whether the network policy of the component blocked the request
+    pub fn is_denied(&self) -> bool {
+        self.error.as_deref() == Some(NETWORK_POLICY_DENIAL)
+    }
+}
+
+/// Share of the successful HTTP requests of a component that are recorded, parsed from
+/// `<component>=<rate>`. The rate is a fraction such as `0.1` or a percentage such as `10%`, and
+/// `*` as the component applies it to every component without a rate of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkSampling {
+    /// The component, or `*` for every component
+    pub component: String,
+    /// Share of the requests that are recorded, from 0 to 1
+    pub rate: f64,
+}
+
+impl FromStr for NetworkSampling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (component, rate) = s
+            .rsplit_once('=')
+            .with_context(|| format!("Network log sampling must be <component>=<rate>: {s}"))?;
+        if component.is_empty() {
+            bail!("Network log sampling has no component: {s}");
+        }
+        let rate = match rate.strip_suffix('%') {
+            Some(percent) => percent.parse::<f64>().map(|percent| percent / 100.0),
+            None => rate.parse::<f64>(),
+        }
+        .ok()
+        .filter(|rate| (0.0..=1.0).contains(rate))
+        .with_context(|| format!("Network log sampling rate must be between 0 and 1: {s}"))?;
+        Ok(Self {
+            component: component.to_string(),
+            rate,
+        })
+    }
+}
+
+/// Sampling rates and the number of successful requests seen of each component
+#[derive(Default)]
+struct Sampler {
+    rates: HashMap<String, f64>,
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Sampler {
+    /// Whether to record the next successful request of `component_id`. Requests are kept at
+    /// even intervals, starting with the first one.
+    fn sample(&self, component_id: &str) -> bool {
+        let Some(rate) = self
+            .rates
+            .get(component_id)
+            .or_else(|| self.rates.get("*"))
+            .copied()
+        else {
+            return true;
+        };
+        let Ok(mut seen) = self.seen.lock() else {
+            return true;
+        };
+        let seen = seen.entry(component_id.to_string()).or_default();
+        *seen += 1;
+        (*seen as f64 * rate).ceil() > ((*seen - 1) as f64 * rate).ceil()
+    }
+}
+
+/// The most recent outgoing HTTP requests of all components
+#[derive(Clone)]
+pub(crate) struct NetworkLog {
+    requests: Arc<Mutex<VecDeque<NetworkRequest>>>,
+    events: EventBus,
+    logs: LogBus,
+    /// Counts the requests and cache hits of every component, sampled or not
+    metrics: MetricsRecorder,
+    sampler: Arc<Sampler>,
+    /// Values that are redacted from paths
+    secrets: Arc<Vec<String>>,
+}
+
+impl NetworkLog {
+    /// Creates a log that counts requests in `metrics` and redacts `secrets` from the paths of
+    /// requests
+    pub(crate) fn new(
+        events: EventBus,
+        logs: LogBus,
+        metrics: MetricsRecorder,
+        mut secrets: Vec<String>,
+    ) -> Self {
+        secrets.retain(|secret| secret.len() >= MIN_SECRET_LEN);
+        Self {
+            requests: Arc::new(Mutex::new(VecDeque::with_capacity(NETWORK_LOG_CAPACITY))),
+            events,
+            logs,
+            metrics,
+            sampler: Arc::default(),
+            secrets: Arc::new(secrets),
+        }
+    }
+
+    fn record(&self, request: NetworkRequest) {
+        self.metrics
+            .record_http_request(&request.component_id, request.cached);
+        if request.error.is_none() && !self.sampler.sample(&request.component_id) {
+            return;
+        }
+        tracing::info!(
+            target: NETWORK_LOG_TARGET,
+            component_id = %request.component_id,
+            method = %request.method,
+            host = %request.host,
+            path = %request.path,
+            status = request.status,
+            bytes = request.bytes,
+            duration_ms = request.duration_ms,
+            cached = request.cached,
+            error = request.error.as_deref(),
+            "HTTP request"
+        );
+        if let Ok(mut requests) = self.requests.lock() {
+            if requests.len() == NETWORK_LOG_CAPACITY {
+                requests.pop_front();
+            }
+            requests.push_back(request.clone());
+        }
+        self.logs.publish(ComponentLog {
+            component_id: request.component_id.clone(),
+            level: if request.error.is_some() {
+                LogLevel::Warn
+            } else {
+                LogLevel::Info
+            },
+            context: NETWORK_LOG_CONTEXT.to_string(),
+            message: log_message(&request),
+        });
+        self.events.emit(Event::NetworkRequest(request));
+    }
+
+    /// The most recent requests, newest first, optionally only those of one component
+    pub(crate) fn recent(&self, component_id: Option<&str>, limit: usize) -> Vec<NetworkRequest> {
+        let Ok(requests) = self.requests.lock() else {
+            return Vec::new();
+        };
+        requests
+            .iter()
+            .rev()
+            .filter(|request| component_id.is_none_or(|id| request.component_id == id))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// The component log message of `request`, e.g.
+/// `GET api.example.com/v1/items/{id} 200 512 bytes 80 ms`
+fn log_message(request: &NetworkRequest) -> String {
+    let outcome = match (&request.error, request.status) {
+        (Some(error), _) => error.clone(),
+        (None, Some(status)) => status.to_string(),
+        (None, None) => "no response".to_string(),
+    };
+    format!(
+        "{} {}{} {outcome} {} bytes {} ms{}",
+        request.method,
+        request.host,
+        request.path,
+        request.bytes,
+        request.duration_ms,
+        if request.cached { " (cached)" } else { "" }
+    )
+}
+
+/// `path` with segments that are identifiers replaced by `{id}`, and segments that look like
+/// tokens or contain one of `secrets` replaced by `{redacted}`
+fn path_template(path: &str, secrets: &[String]) -> String {
+    path.split('/')
+        .map(|segment| {
+            if secrets
+                .iter()
+                .any(|secret| segment.contains(secret.as_str()))
+            {
+                "{redacted}"
+            } else if is_identifier(segment) {
+                "{id}"
+            } else if is_token(segment) {
+                "{redacted}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Numbers, UUIDs and long hexadecimal strings such as hashes
+fn is_identifier(segment: &str) -> bool {
+    let hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    let uuid = segment.len() == 36 && segment.split('-').map(str::len).eq([8, 4, 4, 4, 12]);
+    (!segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()))
+        || (uuid && segment.split('-').all(hex))
+        || (segment.len() >= 16 && hex(segment))
+}
+
+/// Long segments that mix letters and digits, such as API keys
+fn is_token(segment: &str) -> bool {
+    segment.len() >= MIN_TOKEN_LEN
+        && segment.chars().any(|c| c.is_ascii_digit())
+        && segment.chars().any(|c| c.is_ascii_alphabetic())
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '%'))
+}
+
+/// A request that is in flight. It is recorded by [`RequestRecorder::finish`].
+pub(crate) struct RequestRecorder {
+    log: NetworkLog,
+    request: NetworkRequest,
+    started: Instant,
+}
+
+impl RequestRecorder {
+    pub(crate) fn start<B>(
+        log: NetworkLog,
+        component_id: &str,
+        request: &hyper::Request<B>,
+    ) -> Self {
+        let uri = request.uri();
+        let path = path_template(uri.path(), &log.secrets);
+        Self {
+            log,
+            request: NetworkRequest {
+                component_id: component_id.to_string(),
+                method: request.method().to_string(),
+                host: uri.host().unwrap_or_default().to_string(),
+                path,
+                status: None,
+                bytes: 0,
+                duration_ms: 0,
+                cached: false,
+                error: None,
+                time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            },
+            started: Instant::now(),
+        }
+    }
+
+    /// Marks the request as served from the HTTP cache
+    pub(crate) fn cached(&mut self) {
+        self.request.cached = true;
+    }
+
+    pub(crate) fn finish(mut self, status: Option<u16>, bytes: u64, error: Option<String>) {
+        self.request.status = status;
+        self.request.bytes = bytes;
+        self.request.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.request.error = error;
+        self.log.record(self.request);
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Records only a share of the successful HTTP requests of the components in `rules`.
+    /// Blocked and failed requests are always recorded.
+    pub fn with_network_sampling(mut self, rules: Vec<NetworkSampling>) -> Self {
+        self.network_log.sampler = Arc::new(Sampler {
+            rates: rules
+                .into_iter()
+                .map(|rule| (rule.component, rule.rate))
+                .collect(),
+            seen: Mutex::default(),
+        });
+        self
+    }
+
+    /// Returns the most recent outgoing HTTP requests of components, newest first. Only the last
+    /// 1000 requests are kept.
+    pub fn network_activity(
+        &self,
+        component_id: Option<&str>,
+        limit: usize,
+    ) -> Vec<NetworkRequest> {
+        self.network_log.recent(component_id, limit)
+    }
+
+    /// Returns the requests of `component_id` that its network policy blocked since `since`,
+    /// newest first, such as the ones of a call that started then
+    pub fn denied_network_requests(
+        &self,
+        component_id: &str,
+        since: std::time::SystemTime,
+    ) -> Vec<NetworkRequest> {
+        // Request times have a fixed format, so they compare as strings
+        let since = chrono::DateTime::<chrono::Utc>::from(since)
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        self.network_log
+            .recent(Some(component_id), usize::MAX)
+            .into_iter()
+            .filter(|request| request.is_denied() && request.time >= since)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(log: &NetworkLog, component_id: &str, uri: &str) -> RequestRecorder {
+        let request = hyper::Request::get(uri).body(()).unwrap();
+        RequestRecorder::start(log.clone(), component_id, &request)
+    }
+
+    #[tokio::test]
+    async fn test_network_log() {
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        let logs = LogBus::default();
+        let mut component_logs = logs.subscribe();
+        let log = NetworkLog::new(events, logs, MetricsRecorder::default(), Vec::new());
+
+        request(
+            &log,
+            "fetch",
+            "https://api.example.com/v1/items?token=secret",
+        )
+        .finish(Some(200), 512, None);
+        request(&log, "other", "http://blocked.example.com/").finish(
+            None,
+            0,
+            Some("denied by network policy".to_string()),
+        );
+
+        let recent = log.recent(None, 10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].component_id, "other");
+        assert_eq!(recent[0].error.as_deref(), Some("denied by network policy"));
+        assert!(recent[0].is_denied());
+        assert!(!recent[1].is_denied());
+        assert_eq!(recent[1].method, "GET");
+        assert_eq!(recent[1].host, "api.example.com");
+        assert_eq!(recent[1].path, "/v1/items");
+        assert_eq!(recent[1].status, Some(200));
+        assert_eq!(recent[1].bytes, 512);
+
+        assert_eq!(log.recent(Some("fetch"), 10).len(), 1);
+        assert_eq!(log.recent(None, 1).len(), 1);
+
+        let Ok(Event::NetworkRequest(event)) = receiver.recv().await else {
+            panic!("Expected a network request event");
+        };
+        assert_eq!(event, recent[1]);
+        assert_eq!(
+            Event::NetworkRequest(event).to_cloud_event().event_type,
+            "dev.wassette.network.request.v1"
+        );
+
+        let component_log = component_logs.recv().await.unwrap();
+        assert_eq!(component_log.component_id, "fetch");
+        assert_eq!(component_log.level, LogLevel::Info);
+        assert_eq!(component_log.context, "http");
+        assert!(component_log
+            .message
+            .starts_with("GET api.example.com/v1/items 200 512 bytes "));
+        assert_eq!(component_logs.recv().await.unwrap().level, LogLevel::Warn);
+    }
+
+    #[tokio::test]
+    async fn test_denied_network_requests() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let manager = crate::LifecycleManager::new(&tempdir).await?;
+        let deny = |uri| {
+            request(&manager.network_log, "fetch", uri).finish(
+                None,
+                0,
+                Some(NETWORK_POLICY_DENIAL.to_string()),
+            )
+        };
+
+        deny("http://earlier.example.com/");
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let since = std::time::SystemTime::now();
+        deny("http://blocked.example.com/");
+        request(&manager.network_log, "fetch", "http://allowed.example.com/").finish(
+            Some(200),
+            0,
+            None,
+        );
+
+        let denied = manager.denied_network_requests("fetch", since);
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].host, "blocked.example.com");
+        assert!(manager.denied_network_requests("other", since).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_template() {
+        let secrets = ["s3cr3t-value".to_string()];
+        for (path, template) in [
+            ("/v1/items", "/v1/items"),
+            ("/", "/"),
+            ("/users/42/repos", "/users/{id}/repos"),
+            ("/jobs/4b0c5f6e-2a77-4c43-9d4b-7f0f0de0a1ee", "/jobs/{id}"),
+            (
+                "/commits/9fceb02d0ae598e95dc970b74767f19372d61af8",
+                "/commits/{id}",
+            ),
+            (
+                "/bot123456:AAHdqTcvCH1vGWJxfSeofSAs0K5PALDsaw/sendMessage",
+                "/{redacted}/sendMessage",
+            ),
+            ("/keys/s3cr3t-value/info", "/keys/{redacted}/info"),
+            (
+                "/docs/getting-started-with-wassette",
+                "/docs/getting-started-with-wassette",
+            ),
+        ] {
+            assert_eq!(path_template(path, &secrets), template, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_network_sampling() {
+        assert_eq!(
+            "fetch=0.25".parse::<NetworkSampling>().unwrap(),
+            NetworkSampling {
+                component: "fetch".to_string(),
+                rate: 0.25
+            }
+        );
+        assert_eq!("*=10%".parse::<NetworkSampling>().unwrap().rate, 0.1);
+        for invalid in ["fetch", "=0.5", "fetch=2", "fetch=-1", "fetch=often"] {
+            assert!(invalid.parse::<NetworkSampling>().is_err(), "{invalid}");
+        }
+
+        let mut log = NetworkLog::new(
+            EventBus::default(),
+            LogBus::default(),
+            MetricsRecorder::default(),
+            Vec::new(),
+        );
+        log.sampler = Arc::new(Sampler {
+            rates: HashMap::from([("*".to_string(), 0.25), ("quiet".to_string(), 0.0)]),
+            seen: Mutex::default(),
+        });
+        for _ in 0..8 {
+            request(&log, "fetch", "https://api.example.com/").finish(Some(200), 0, None);
+            request(&log, "quiet", "https://api.example.com/").finish(Some(200), 0, None);
+        }
+        // Blocked and failed requests are always recorded
+        request(&log, "quiet", "https://api.example.com/").finish(
+            None,
+            0,
+            Some("ConnectionRefused".to_string()),
+        );
+        assert_eq!(log.recent(Some("fetch"), usize::MAX).len(), 2);
+        assert_eq!(log.recent(Some("quiet"), usize::MAX).len(), 1);
+    }
+
+    #[test]
+    fn test_network_log_capacity() {
+        let log = NetworkLog::new(
+            EventBus::default(),
+            LogBus::default(),
+            MetricsRecorder::default(),
+            Vec::new(),
+        );
+        for _ in 0..NETWORK_LOG_CAPACITY + 10 {
+            request(&log, "fetch", "https://api.example.com/").finish(Some(204), 0, None);
+        }
+        assert_eq!(log.recent(None, usize::MAX).len(), NETWORK_LOG_CAPACITY);
+    }
+}
@@ -0,0 +1,499 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Export of metrics over OTLP
+//!
+//! [`crate::LifecycleManager::spawn_metrics_exporter`] pushes the metrics of `/api/metrics` of
+//! the management API to an [OTLP/HTTP](https://opentelemetry.io/docs/specs/otlp/) endpoint in
+//! its JSON encoding, so that backends such as Grafana Cloud or Datadog ingest them without
+//! scraping. Every export holds all metrics since the runtime started, with cumulative
+//! temporality:
+//!
+//! - `wassette.components.loaded`, `wassette.components.trashed`: Gauges of the number of
+//!   components
+//! - `wassette.tool.calls`, `wassette.tool.failures`, `wassette.tool.memory_limit_hits`: Sums by
+//!   `component_id` and `tool`
+//! - `wassette.tool.error_rate`: Gauge of failed calls / calls by `component_id` and `tool`
+//! - `wassette.tool.duration`: Summary in milliseconds by `component_id` and `tool`, with the
+//!   count and sum of all calls and the 0.5, 0.9 and 0.99 quantiles of the recent calls
+//! - `wassette.http.requests`, `wassette.http.cache_hits`: Sums of outgoing HTTP requests by
+//!   `component_id`
+//! - `wassette.http.cache_hit_ratio`: Gauge of cache hits / requests by `component_id`
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::management::{ManagementApi, ManagementMetrics};
+use crate::LifecycleManager;
+
+/// Time between two exports by default
+pub const DEFAULT_OTLP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Path of the metrics endpoint of an OTLP/HTTP receiver
+const METRICS_PATH: &str = "/v1/metrics";
+
+/// Time an export may take
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how often metrics are exported
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtlpMetricsConfig {
+    /// URL the metrics are POSTed to
+    pub endpoint: String,
+    /// Headers sent with every export, such as the API key of the backend
+    pub headers: Vec<(String, String)>,
+    /// Time between two exports
+    pub interval: Duration,
+}
+
+impl OtlpMetricsConfig {
+    /// Exports to an http(s):// URL every [`DEFAULT_OTLP_INTERVAL`]. A URL without a path is
+    /// the base URL of a receiver, which takes metrics at `/v1/metrics`.
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let mut url = url::Url::parse(endpoint)
+            .with_context(|| format!("Invalid OTLP endpoint: {endpoint}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("OTLP endpoint must be an http(s):// URL: {endpoint}");
+        }
+        if url.path() == "/" {
+            url.set_path(METRICS_PATH);
+        }
+        Ok(Self {
+            endpoint: url.to_string(),
+            headers: Vec::new(),
+            interval: DEFAULT_OTLP_INTERVAL,
+        })
+    }
+
+    /// Adds a header given as `name=value`
+    pub fn with_header(mut self, header: &str) -> Result<Self> {
+        let (name, value) = header
+            .split_once('=')
+            .with_context(|| format!("OTLP header must be <name>=<value>: {header}"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("OTLP header has no name: {header}");
+        }
+        self.headers
+            .push((name.to_string(), value.trim().to_string()));
+        Ok(self)
+    }
+
+    /// Sets the time between two exports
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attributes(pairs: &[(&str, &str)]) -> Value {
+    pairs
+        .iter()
+        .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+        .collect()
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    match whole {
+        0 => 0.0,
+        _ => part as f64 / whole as f64,
+    }
+}
+
+/// Builds OTLP metrics from data points of the same kind
+struct MetricsBuilder {
+    start: String,
+    time: String,
+    metrics: Vec<Value>,
+}
+
+impl MetricsBuilder {
+    fn point(&self, attributes: Value, value: Value) -> Value {
+        let mut point = json!({
+            "attributes": attributes,
+            "startTimeUnixNano": self.start,
+            "timeUnixNano": self.time,
+        });
+        if let (Some(point), Some(value)) = (point.as_object_mut(), value.as_object()) {
+            point.extend(value.clone());
+        }
+        point
+    }
+
+    fn gauge(&mut self, name: &str, unit: &str, points: Vec<(Value, Value)>) {
+        let points: Vec<Value> = points
+            .into_iter()
+            .map(|(attributes, value)| self.point(attributes, value))
+            .collect();
+        self.metrics.push(json!({
+            "name": name,
+            "unit": unit,
+            "gauge": {"dataPoints": points},
+        }));
+    }
+
+    fn sum(&mut self, name: &str, unit: &str, points: Vec<(Value, u64)>) {
+        let points: Vec<Value> = points
+            .into_iter()
+            .map(|(attributes, value)| self.point(attributes, json!({"asInt": value.to_string()})))
+            .collect();
+        self.metrics.push(json!({
+            "name": name,
+            "unit": unit,
+            "sum": {
+                "dataPoints": points,
+                // AGGREGATION_TEMPORALITY_CUMULATIVE
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+            },
+        }));
+    }
+}
+
+/// The OTLP `ExportMetricsServiceRequest` of `metrics`, collected between `start` and `time`
+pub(crate) fn export_request(
+    metrics: &ManagementMetrics,
+    start: SystemTime,
+    time: SystemTime,
+) -> Value {
+    let mut builder = MetricsBuilder {
+        start: unix_nanos(start),
+        time: unix_nanos(time),
+        metrics: Vec::new(),
+    };
+    builder.gauge(
+        "wassette.components.loaded",
+        "{component}",
+        vec![(
+            json!([]),
+            json!({"asInt": metrics.loaded_components.to_string()}),
+        )],
+    );
+    builder.gauge(
+        "wassette.components.trashed",
+        "{component}",
+        vec![(
+            json!([]),
+            json!({"asInt": metrics.trashed_components.to_string()}),
+        )],
+    );
+
+    let tool_attributes = |component_id: &str, tool: &str| {
+        attributes(&[("component_id", component_id), ("tool", tool)])
+    };
+    let tools = &metrics.tools;
+    let tool_sum = |value: fn(&crate::ToolMetrics) -> u64| -> Vec<(Value, u64)> {
+        tools
+            .iter()
+            .map(|tool| {
+                (
+                    tool_attributes(&tool.component_id, &tool.function_name),
+                    value(tool),
+                )
+            })
+            .collect()
+    };
+    builder.sum("wassette.tool.calls", "{call}", tool_sum(|tool| tool.calls));
+    builder.sum(
+        "wassette.tool.failures",
+        "{call}",
+        tool_sum(|tool| tool.failures),
+    );
+    builder.sum(
+        "wassette.tool.memory_limit_hits",
+        "{call}",
+        tool_sum(|tool| tool.memory_limit_hits),
+    );
+    builder.gauge(
+        "wassette.tool.error_rate",
+        "1",
+        tools
+            .iter()
+            .map(|tool| {
+                (
+                    tool_attributes(&tool.component_id, &tool.function_name),
+                    json!({"asDouble": ratio(tool.failures, tool.calls)}),
+                )
+            })
+            .collect(),
+    );
+    let durations: Vec<Value> = tools
+        .iter()
+        .map(|tool| {
+            builder.point(
+                tool_attributes(&tool.component_id, &tool.function_name),
+                json!({
+                    "count": tool.calls.to_string(),
+                    "sum": tool.total_duration_ms as f64,
+                    "quantileValues": [
+                        {"quantile": 0.5, "value": tool.p50_duration_ms as f64},
+                        {"quantile": 0.9, "value": tool.p90_duration_ms as f64},
+                        {"quantile": 0.99, "value": tool.p99_duration_ms as f64},
+                    ],
+                }),
+            )
+        })
+        .collect();
+    builder.metrics.push(json!({
+        "name": "wassette.tool.duration",
+        "unit": "ms",
+        "summary": {"dataPoints": durations},
+    }));
+
+    let http: Vec<_> = metrics
+        .components
+        .iter()
+        .filter(|component| component.http_requests > 0)
+        .collect();
+    let component_attributes = |component_id: &str| attributes(&[("component_id", component_id)]);
+    builder.sum(
+        "wassette.http.requests",
+        "{request}",
+        http.iter()
+            .map(|c| (component_attributes(&c.component_id), c.http_requests))
+            .collect(),
+    );
+    builder.sum(
+        "wassette.http.cache_hits",
+        "{request}",
+        http.iter()
+            .map(|c| (component_attributes(&c.component_id), c.http_cache_hits))
+            .collect(),
+    );
+    builder.gauge(
+        "wassette.http.cache_hit_ratio",
+        "1",
+        http.iter()
+            .map(|c| {
+                (
+                    component_attributes(&c.component_id),
+                    json!({"asDouble": ratio(c.http_cache_hits, c.http_requests)}),
+                )
+            })
+            .collect(),
+    );
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {"attributes": attributes(&[("service.name", "wassette")])},
+            "scopeMetrics": [{
+                "scope": {"name": "wassette", "version": env!("CARGO_PKG_VERSION")},
+                "metrics": builder.metrics,
+            }],
+        }],
+    })
+}
+
+impl LifecycleManager {
+    /// Exports the metrics of the management API to an OTLP/HTTP endpoint at every interval of
+    /// `config`, for as long as the process runs. Failed exports are logged; the next one
+    /// carries the metrics again, as they are cumulative.
+    pub fn spawn_metrics_exporter(&self, config: OtlpMetricsConfig) -> JoinHandle<()> {
+        let manager = self.clone();
+        let start = SystemTime::now();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.interval);
+            // The first tick completes immediately, when there is nothing to export yet
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match manager.export_metrics(&config, start).await {
+                    Ok(()) => debug!(endpoint = %config.endpoint, "Exported metrics"),
+                    Err(e) => {
+                        warn!(endpoint = %config.endpoint, error = %e, "Failed to export metrics")
+                    }
+                }
+            }
+        })
+    }
+
+    async fn export_metrics(&self, config: &OtlpMetricsConfig, start: SystemTime) -> Result<()> {
+        let metrics = ManagementApi::metrics(self).await?;
+        let body = export_request(&metrics, start, SystemTime::now());
+        let mut request = self
+            .http_client
+            .post(&config.endpoint)
+            .timeout(EXPORT_TIMEOUT)
+            .json(&body);
+        for (name, value) in &config.headers {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::management::ComponentMetrics;
+    use crate::ToolMetrics;
+
+    #[test]
+    fn test_otlp_metrics_config() {
+        let config = OtlpMetricsConfig::new("https://otlp.example.com")
+            .unwrap()
+            .with_header("Authorization=Basic dXNlcjprZXk=")
+            .unwrap();
+        assert_eq!(config.endpoint, "https://otlp.example.com/v1/metrics");
+        assert_eq!(
+            config.headers,
+            [(
+                "Authorization".to_string(),
+                "Basic dXNlcjprZXk=".to_string()
+            )]
+        );
+        assert_eq!(config.interval, DEFAULT_OTLP_INTERVAL);
+        assert_eq!(
+            OtlpMetricsConfig::new("http://localhost:4318/otlp/v1/metrics")
+                .unwrap()
+                .endpoint,
+            "http://localhost:4318/otlp/v1/metrics"
+        );
+        assert!(OtlpMetricsConfig::new("grpc://localhost:4317").is_err());
+        assert!(config.clone().with_header("Authorization").is_err());
+        assert!(config.with_header("=value").is_err());
+    }
+
+    #[test]
+    fn test_export_request() {
+        let metrics = ManagementMetrics {
+            loaded_components: 2,
+            trashed_components: 0,
+            tools: vec![ToolMetrics {
+                component_id: "fetch".to_string(),
+                function_name: "fetch".to_string(),
+                calls: 4,
+                failures: 1,
+                total_duration_ms: 400,
+                max_duration_ms: 250,
+                p50_duration_ms: 50,
+                p90_duration_ms: 250,
+                p99_duration_ms: 250,
+                memory_limit_hits: 0,
+            }],
+            components: vec![ComponentMetrics {
+                component_id: "fetch".to_string(),
+                calls: 4,
+                http_requests: 8,
+                http_cache_hits: 6,
+                ..Default::default()
+            }],
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let request = export_request(&metrics, start, start + Duration::from_secs(60));
+
+        let scope = &request["resourceMetrics"][0]["scopeMetrics"][0];
+        let metric = |name: &str| {
+            scope["metrics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|metric| metric["name"] == name)
+                .unwrap_or_else(|| panic!("{name} is missing"))
+                .clone()
+        };
+        let calls = metric("wassette.tool.calls");
+        assert_eq!(calls["sum"]["aggregationTemporality"], 2);
+        assert_eq!(
+            calls["sum"]["dataPoints"][0],
+            json!({
+                "attributes": [
+                    {"key": "component_id", "value": {"stringValue": "fetch"}},
+                    {"key": "tool", "value": {"stringValue": "fetch"}},
+                ],
+                "startTimeUnixNano": "1700000000000000000",
+                "timeUnixNano": "1700000060000000000",
+                "asInt": "4",
+            })
+        );
+        assert_eq!(
+            metric("wassette.tool.error_rate")["gauge"]["dataPoints"][0]["asDouble"],
+            0.25
+        );
+        let duration = &metric("wassette.tool.duration")["summary"]["dataPoints"][0];
+        assert_eq!(duration["count"], "4");
+        assert_eq!(duration["sum"], 400.0);
+        assert_eq!(duration["quantileValues"][1]["value"], 250.0);
+        assert_eq!(
+            metric("wassette.http.cache_hit_ratio")["gauge"]["dataPoints"][0]["asDouble"],
+            0.75
+        );
+        assert_eq!(
+            metric("wassette.components.loaded")["gauge"]["dataPoints"][0]["asInt"],
+            "2"
+        );
+    }
+
+    /// Accepts one HTTP request and answers it with 200, returning the request
+    async fn receive_request(listener: TcpListener) -> Result<String> {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                bail!("Connection closed before the request was complete");
+            }
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or_default();
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await?;
+        Ok(String::from_utf8(request)?)
+    }
+
+    #[tokio::test]
+    async fn test_spawn_metrics_exporter() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let receiver = tokio::spawn(receive_request(listener));
+
+        let dir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&dir).await?;
+        let config = OtlpMetricsConfig::new(&format!("http://{address}"))?
+            .with_header("x-api-key=secret")?
+            .with_interval(Duration::from_millis(50));
+        let exporter = manager.spawn_metrics_exporter(config);
+
+        let request = tokio::time::timeout(Duration::from_secs(10), receiver).await???;
+        exporter.abort();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /v1/metrics HTTP/1.1"), "{head}");
+        assert!(head.contains("x-api-key: secret"), "{head}");
+        let body: Value = serde_json::from_str(body)?;
+        assert_eq!(
+            body["resourceMetrics"][0]["resource"]["attributes"][0]["value"]["stringValue"],
+            "wassette"
+        );
+        Ok(())
+    }
+}
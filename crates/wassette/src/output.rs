@@ -0,0 +1,160 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Per-call capture of component stdout and stderr
+//!
+//! By default a component's stdout and stderr are inherited from the server process. With
+//! [`crate::LifecycleManager::with_output_capture`], every call instead writes them into a
+//! buffer, which is returned alongside the call result. Each stream keeps at most the configured
+//! number of bytes; anything beyond that is dropped without failing the call.
+
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use serde::Serialize;
+use wasmtime_wasi::p2::{OutputStream, Pollable, StdoutStream, StreamError};
+
+/// Default number of bytes kept per stream and call
+pub const DEFAULT_OUTPUT_CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// How many bytes a guest may write at once. Writes are always accepted, so this only bounds the
+/// size of a single write.
+const WRITE_BUDGET: usize = 64 * 1024;
+
+/// What a component wrote to stdout and stderr during one call
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CapturedOutput {
+    /// Captured stdout, decoded as UTF-8 with invalid sequences replaced
+    pub stdout: String,
+    /// Captured stderr, decoded as UTF-8 with invalid sequences replaced
+    pub stderr: String,
+    /// Whether either stream exceeded the capture limit and was cut off
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    /// Whether the component wrote nothing to either stream
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct CaptureBuffer {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+/// An output stream that keeps the first `limit` bytes written to it
+#[derive(Clone)]
+pub(crate) struct CapturePipe {
+    limit: usize,
+    buffer: Arc<Mutex<CaptureBuffer>>,
+}
+
+impl CapturePipe {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            buffer: Arc::default(),
+        }
+    }
+
+    fn contents(&self) -> (String, bool) {
+        let buffer = self.buffer.lock().expect("capture buffer lock poisoned");
+        (
+            String::from_utf8_lossy(&buffer.data).into_owned(),
+            buffer.truncated,
+        )
+    }
+}
+
+impl StdoutStream for CapturePipe {
+    fn stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl OutputStream for CapturePipe {
+    fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+        let mut buffer = self.buffer.lock().expect("capture buffer lock poisoned");
+        let remaining = self.limit.saturating_sub(buffer.data.len());
+        if bytes.len() > remaining {
+            buffer.truncated = true;
+        }
+        let kept = bytes.len().min(remaining);
+        buffer.data.extend_from_slice(&bytes[..kept]);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> Result<usize, StreamError> {
+        Ok(WRITE_BUDGET)
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for CapturePipe {
+    async fn ready(&mut self) {}
+}
+
+/// The stdout and stderr buffers of one call
+pub(crate) struct OutputCapture {
+    pub(crate) stdout: CapturePipe,
+    pub(crate) stderr: CapturePipe,
+}
+
+impl OutputCapture {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            stdout: CapturePipe::new(limit),
+            stderr: CapturePipe::new(limit),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> CapturedOutput {
+        let (stdout, stdout_truncated) = self.stdout.contents();
+        let (stderr, stderr_truncated) = self.stderr.contents();
+        CapturedOutput {
+            stdout,
+            stderr,
+            truncated: stdout_truncated || stderr_truncated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_is_capped() {
+        let capture = OutputCapture::new(8);
+        let mut stdout = capture.stdout.stream();
+        let mut stderr = capture.stderr.stream();
+
+        stdout.write(Bytes::from_static(b"hello ")).unwrap();
+        // Writes past the limit are cut off but still succeed
+        stdout.write(Bytes::from_static(b"world")).unwrap();
+        stdout.write(Bytes::from_static(b"!")).unwrap();
+        assert_eq!(stdout.check_write().unwrap(), WRITE_BUDGET);
+        stderr.write(Bytes::from_static(b"oops")).unwrap();
+
+        assert_eq!(
+            capture.finish(),
+            CapturedOutput {
+                stdout: "hello wo".to_string(),
+                stderr: "oops".to_string(),
+                truncated: true,
+            }
+        );
+        assert!(OutputCapture::new(8).finish().is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Warnings about imports that the policy of a component grants nothing for
+//!
+//! A component that imports `wasi:sockets` but whose policy grants no network can load and list
+//! its tools, and only fails once a call is denied. When a component loads or gets a policy
+//! attached, its imports are compared with its policy, and every capability it imports without a
+//! grant is logged as a warning and published as a `policy.mismatch` event. Management frontends
+//! show the current warnings with every [`crate::ComponentSummary`].
+//!
+//! `wasi:cli/environment` and `wasi:config` aren't checked, since nearly every component imports
+//! the former and the latter also serves the secrets of a component, which aren't in its policy.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::events::Event;
+use crate::inspection::{component_imports, Capability};
+use crate::wasistate::WasiStateTemplate;
+use crate::{ComponentNotFound, LifecycleManager};
+
+/// A capability that a component imports but its policy grants nothing for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyWarning {
+    /// The capability
+    pub capability: Capability,
+    /// The first import that requests it, such as `wasi:sockets/tcp@0.2.0`
+    pub import: String,
+    /// What is missing, such as `imports wasi:sockets but policy grants no network`
+    pub message: String,
+}
+
+/// What the policy grants nothing of when a component imports a capability without a grant, or
+/// `None` if the policy grants some of it or the capability isn't checked
+fn missing_grant(capability: Capability, template: &WasiStateTemplate) -> Option<&'static str> {
+    let granted = match capability {
+        Capability::Filesystem => !template.preopened_dirs.is_empty(),
+        Capability::Sockets => {
+            !template.tcp_grants.is_empty()
+                || !template.udp_grants.is_empty()
+                || !template.allowed_dns_names.is_empty()
+        }
+        Capability::Http => !template.allowed_hosts.is_empty(),
+        Capability::Keyvalue => !template.keyvalue_buckets.is_empty(),
+        Capability::Blobstore => !template.blobstore_containers.is_empty(),
+        Capability::Sql => !template.databases.is_empty(),
+        Capability::Components => !template.call_grants.is_empty(),
+        Capability::Sampling => template.sampling.is_some(),
+        Capability::Environment | Capability::Config => return None,
+    };
+    if granted {
+        return None;
+    }
+    Some(match capability {
+        Capability::Filesystem => "storage",
+        Capability::Sockets | Capability::Http => "network",
+        Capability::Keyvalue => "keyvalue buckets",
+        Capability::Blobstore => "blobstore containers",
+        Capability::Sql => "databases",
+        Capability::Components => "calls to other components",
+        Capability::Sampling => "sampling",
+        Capability::Environment | Capability::Config => return None,
+    })
+}
+
+/// The warnings about the capabilities that `imports` request and `template` grants nothing for,
+/// one per capability
+pub(crate) fn policy_warnings(
+    imports: &[String],
+    template: &WasiStateTemplate,
+) -> Vec<PolicyWarning> {
+    let mut first_imports = BTreeMap::new();
+    for import in imports {
+        if let Some(capability) = Capability::of_import(import) {
+            first_imports.entry(capability).or_insert(import);
+        }
+    }
+    first_imports
+        .into_iter()
+        .filter_map(|(capability, import)| {
+            let missing = missing_grant(capability, template)?;
+            let package = import.split('/').next().unwrap_or(import);
+            let package = package.split('@').next().unwrap_or(package);
+            Some(PolicyWarning {
+                capability,
+                import: import.clone(),
+                message: format!("imports {package} but policy grants no {missing}"),
+            })
+        })
+        .collect()
+}
+
+impl LifecycleManager {
+    /// The capabilities a loaded component imports but its current policy, including the
+    /// permissions granted at runtime, grants nothing for
+    pub async fn policy_warnings(&self, component_id: &str) -> Result<Vec<PolicyWarning>> {
+        let component = self
+            .get_component(component_id)
+            .await
+            .ok_or_else(|| ComponentNotFound::Component(component_id.to_string()))?;
+        let imports = component_imports(&component.component, &self.engine);
+        let template = self
+            .policy_registry
+            .read()
+            .await
+            .component_policies
+            .get(component_id)
+            .cloned()
+            .unwrap_or_else(Self::create_default_policy_template);
+        Ok(policy_warnings(&imports, &template))
+    }
+
+    /// Logs the [`PolicyWarning`]s of a component and publishes them as a `policy.mismatch`
+    /// event. Failing to is only logged, as the warnings don't keep the component from loading.
+    pub(crate) async fn warn_about_policy(&self, component_id: &str) {
+        let warnings = match self.policy_warnings(component_id).await {
+            Ok(warnings) => warnings,
+            Err(e) => {
+                warn!(component_id, error = %e, "Failed to compare imports with policy");
+                return;
+            }
+        };
+        if warnings.is_empty() {
+            return;
+        }
+        for warning in &warnings {
+            warn!(
+                component_id,
+                capability = warning.capability.name(),
+                import = warning.import,
+                "{}",
+                warning.message
+            );
+        }
+        self.events.emit(Event::PolicyMismatch {
+            component_id: component_id.to_string(),
+            warnings,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use policy::PolicyParser;
+    use test_log::test;
+
+    use super::*;
+    use crate::wasistate::{create_wasi_state_template_from_policy, TemplateContext};
+
+    fn template(yaml: &str) -> WasiStateTemplate {
+        let policy = PolicyParser::parse_str(yaml).unwrap();
+        let plugin_dir = Path::new("/plugins");
+        create_wasi_state_template_from_policy(
+            &policy,
+            plugin_dir,
+            &HashMap::new(),
+            &TemplateContext::new(plugin_dir, "weather", "session-1"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_policy_warnings() {
+        let imports = [
+            "wasi:cli/environment@0.2.0",
+            "wasi:sockets/network@0.2.0",
+            "wasi:sockets/tcp@0.2.0",
+            "wasi:http/outgoing-handler@0.2.0",
+            "wasi:keyvalue/store@0.2.0-draft",
+            "wasi:config/store@0.2.0-draft",
+        ]
+        .map(str::to_string);
+
+        let warnings = policy_warnings(&imports, &WasiStateTemplate::default());
+        assert_eq!(
+            warnings,
+            vec![
+                PolicyWarning {
+                    capability: Capability::Sockets,
+                    import: "wasi:sockets/network@0.2.0".to_string(),
+                    message: "imports wasi:sockets but policy grants no network".to_string(),
+                },
+                PolicyWarning {
+                    capability: Capability::Http,
+                    import: "wasi:http/outgoing-handler@0.2.0".to_string(),
+                    message: "imports wasi:http but policy grants no network".to_string(),
+                },
+                PolicyWarning {
+                    capability: Capability::Keyvalue,
+                    import: "wasi:keyvalue/store@0.2.0-draft".to_string(),
+                    message: "imports wasi:keyvalue but policy grants no keyvalue buckets"
+                        .to_string(),
+                },
+            ]
+        );
+
+        let warnings = policy_warnings(
+            &imports,
+            &template(
+                r#"
+version: "1.0"
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+  keyvalue:
+    allow:
+      - bucket: "cache"
+"#,
+            ),
+        );
+        assert_eq!(warnings, Vec::new());
+    }
+
+    #[test(tokio::test)]
+    async fn test_warn_about_policy() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tool.wasm");
+        let wat = r#"(component (import "wasi:sockets/tcp@0.2.0" (instance)))"#;
+        tokio::fs::write(&path, wat::parse_str(wat)?).await?;
+        let manager = LifecycleManager::new(dir.path().join("plugins")).await?;
+        let mut events = manager.events.subscribe();
+
+        manager
+            .load_component(&format!("file://{}", path.display()))
+            .await?;
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        assert!(matches!(
+            events.recv().await?,
+            Event::ComponentLoaded { .. }
+        ));
+        let Event::PolicyMismatch {
+            component_id,
+            warnings,
+        } = events.recv().await?
+        else {
+            panic!("Expected a policy mismatch event");
+        };
+        assert_eq!(component_id, "tool");
+        assert_eq!(
+            warnings[0].message,
+            "imports wasi:sockets but policy grants no network"
+        );
+        assert_eq!(manager.policy_warnings("tool").await?, warnings);
+
+        let policy_path = dir.path().join("policy.yaml");
+        tokio::fs::write(
+            &policy_path,
+            "version: \"1.0\"\npermissions:\n  network:\n    allow:\n      - tcp: \"tcp://db.internal:5432\"\n",
+        )
+        .await?;
+        manager
+            .attach_policy("tool", &format!("file://{}", policy_path.display()))
+            .await?;
+        assert!(manager.policy_warnings("tool").await?.is_empty());
+        assert!(manager.policy_warnings("missing").await.is_err());
+        Ok(())
+    }
+}
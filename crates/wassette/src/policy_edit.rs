@@ -0,0 +1,293 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Editing the policy of a component as a whole
+//!
+//! Where the `grant-*` and `revoke-*` tools change one permission at a time, a policy editor
+//! reads the full YAML of a component's policy and writes it back. The new policy is validated
+//! like an attached one, replaces the policy file and becomes the baseline that
+//! [`crate::LifecycleManager::reset_permissions`] reverts to. What changed is reported as a list
+//! of [`PolicyChange`]s, so an editor can show it or ask for confirmation.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use policy::PolicyDocument;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, instrument};
+
+use crate::{ComponentNotFound, Event};
+
+/// One difference between two versions of a policy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyChange {
+    /// Dotted path of the changed field, e.g. `permissions.network.allow`
+    pub path: String,
+    /// The value that was removed: the old value of a changed field, or an item of a list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed: Option<Value>,
+    /// The value that was added: the new value of a changed field, or an item of a list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<Value>,
+}
+
+/// The differences between two policies. Lists are compared as sets, so every item that was
+/// added or removed is a change of its own and reordering changes nothing.
+pub fn diff_policies(before: &PolicyDocument, after: &PolicyDocument) -> Vec<PolicyChange> {
+    let mut changes = Vec::new();
+    // Policy documents only hold maps, lists and scalars, which always serialize
+    let before = serde_json::to_value(before).unwrap_or_default();
+    let after = serde_json::to_value(after).unwrap_or_default();
+    diff_values("", &before, &after, &mut changes);
+    changes
+}
+
+fn diff_values(path: &str, before: &Value, after: &Value, changes: &mut Vec<PolicyChange>) {
+    let change = |removed: Option<&Value>, added: Option<&Value>| PolicyChange {
+        path: path.to_string(),
+        removed: removed.cloned(),
+        added: added.cloned(),
+    };
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let mut keys = before.keys().chain(after.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = match path {
+                    "" => key.clone(),
+                    _ => format!("{path}.{key}"),
+                };
+                let null = Value::Null;
+                diff_values(
+                    &path,
+                    before.get(key).unwrap_or(&null),
+                    after.get(key).unwrap_or(&null),
+                    changes,
+                );
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            changes.extend(
+                before
+                    .iter()
+                    .filter(|item| !after.contains(item))
+                    .map(|item| change(Some(item), None)),
+            );
+            changes.extend(
+                after
+                    .iter()
+                    .filter(|item| !before.contains(item))
+                    .map(|item| change(None, Some(item))),
+            );
+        }
+        (Value::Null, Value::Null) => {}
+        // A section that only exists on one side is compared with an empty one, so its items
+        // are listed one by one
+        (Value::Null, Value::Object(_)) => {
+            diff_values(path, &Value::Object(Default::default()), after, changes)
+        }
+        (Value::Object(_), Value::Null) => {
+            diff_values(path, before, &Value::Object(Default::default()), changes)
+        }
+        (Value::Null, Value::Array(_)) => {
+            diff_values(path, &Value::Array(Vec::new()), after, changes)
+        }
+        (Value::Array(_), Value::Null) => {
+            diff_values(path, before, &Value::Array(Vec::new()), changes)
+        }
+        _ if before == after => {}
+        _ => changes.push(change(
+            (!before.is_null()).then_some(before),
+            (!after.is_null()).then_some(after),
+        )),
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Returns the YAML of the policy attached to a loaded component, or `None` if it has no
+    /// policy
+    #[instrument(skip(self))]
+    pub async fn get_policy_yaml(&self, component_id: &str) -> Result<Option<String>> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        let policy_path = self.get_component_policy_path(component_id);
+        match tokio::fs::read_to_string(&policy_path).await {
+            Ok(yaml) => Ok(Some(yaml)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", policy_path.display())),
+        }
+    }
+
+    /// Replaces the policy of a loaded component with the policy in `yaml`, which is validated
+    /// first. Returns what changed compared to the policy the component had, or to an empty
+    /// policy if it had none.
+    #[instrument(skip(self, yaml))]
+    pub async fn set_policy_yaml(
+        &self,
+        component_id: &str,
+        yaml: &str,
+    ) -> Result<Vec<PolicyChange>> {
+        if !self.components.read().await.contains_key(component_id) {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        self.ensure_modifiable(component_id).await?;
+        self.install_policy_yaml(component_id, yaml).await
+    }
+
+    /// Writes the policy in `yaml` as the policy of `component_id` and applies it, whether the
+    /// component is loaded yet or not
+    pub(crate) async fn install_policy_yaml(
+        &self,
+        component_id: &str,
+        yaml: &str,
+    ) -> Result<Vec<PolicyChange>> {
+        let policy = self.parse_policy(yaml).context("Invalid policy")?;
+        let wasi_template = crate::create_wasi_state_template_from_policy(
+            &policy,
+            &self.plugin_dir,
+            &self.environment_vars,
+            &self.template_context(component_id),
+        )?;
+        let changes = diff_policies(
+            &self.load_or_create_component_policy(component_id).await?,
+            &policy,
+        );
+
+        let policy_path = self.get_component_policy_path(component_id);
+        let temp_path = policy_path.with_extension("yaml.tmp");
+        tokio::fs::write(&temp_path, yaml)
+            .await
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        tokio::fs::rename(&temp_path, &policy_path)
+            .await
+            .with_context(|| format!("Failed to write {}", policy_path.display()))?;
+        // The edited policy has no other source and is the new baseline for reset_permissions
+        for (path, file_type) in [
+            (
+                self.get_component_metadata_path(component_id),
+                "policy metadata file",
+            ),
+            (
+                self.get_component_base_policy_path(component_id),
+                "base policy file",
+            ),
+        ] {
+            self.remove_file_if_exists(&path, file_type, component_id)
+                .await?;
+        }
+
+        self.policy_registry
+            .write()
+            .await
+            .component_policies
+            .insert(component_id.to_string(), Arc::new(wasi_template));
+
+        info!(component_id, changes = changes.len(), "Policy replaced");
+        self.events.emit(Event::PolicyAttached {
+            component_id: component_id.to_string(),
+            policy_uri: format!("file://{}", policy_path.display()),
+        });
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::COMPONENT;
+    use crate::LifecycleManager;
+
+    const POLICY: &str = r#"
+version: "1.0"
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+"#;
+
+    #[test]
+    fn test_diff_policies() {
+        let before = policy::PolicyParser::parse_str(POLICY).unwrap();
+        let after = policy::PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Weather lookups"
+permissions:
+  network:
+    allow:
+      - host: "weather.example.com"
+  environment:
+    allow:
+      - key: "API_TOKEN"
+"#,
+        )
+        .unwrap();
+
+        assert!(diff_policies(&before, &before).is_empty());
+        assert_eq!(
+            diff_policies(&before, &after),
+            vec![
+                PolicyChange {
+                    path: "description".to_string(),
+                    removed: None,
+                    added: Some(json!("Weather lookups")),
+                },
+                PolicyChange {
+                    path: "permissions.environment.allow".to_string(),
+                    removed: None,
+                    added: Some(json!({"key": "API_TOKEN"})),
+                },
+                PolicyChange {
+                    path: "permissions.network.allow".to_string(),
+                    removed: Some(json!({"host": "api.example.com"})),
+                    added: None,
+                },
+                PolicyChange {
+                    path: "permissions.network.allow".to_string(),
+                    removed: None,
+                    added: Some(json!({"host": "weather.example.com"})),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_policy_yaml() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        assert_eq!(manager.get_policy_yaml("fetch").await?, None);
+        let changes = manager.set_policy_yaml("fetch", POLICY).await?;
+        assert!(changes.contains(&PolicyChange {
+            path: "permissions.network.allow".to_string(),
+            removed: None,
+            added: Some(json!({"host": "api.example.com"})),
+        }));
+        assert_eq!(
+            manager.get_policy_yaml("fetch").await?.as_deref(),
+            Some(POLICY)
+        );
+        assert!(manager.get_policy_info("fetch").await.is_some());
+
+        assert!(manager
+            .set_policy_yaml("fetch", "permissions: [")
+            .await
+            .is_err());
+        assert_eq!(
+            manager.get_policy_yaml("fetch").await?.as_deref(),
+            Some(POLICY)
+        );
+        let err = manager
+            .set_policy_yaml("missing", POLICY)
+            .await
+            .unwrap_err();
+        assert!(err.is::<ComponentNotFound>());
+        Ok(())
+    }
+}
@@ -4,18 +4,18 @@
 //! Policy management structures and types
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use policy::{
-    AccessType, EnvironmentPermission, NetworkHostPermission, NetworkPermission, PolicyDocument,
-    PolicyParser, StoragePermission,
+    AccessType, EnvironmentPermission, NetworkHostPermission, NetworkPermission,
+    NetworkTcpPermission, NetworkUdpPermission, PolicyDocument, PolicyParser, StoragePermission,
 };
 use serde::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use tracing::{debug, info, instrument};
 
-use crate::WasiStateTemplate;
+use crate::{ComponentNotFound, Event, WasiStateTemplate};
 
 /// Granular permission rule types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +50,8 @@ pub struct PermissionGrantRequest {
 pub(crate) struct PolicyRegistry {
     /// Maps component IDs to their associated policy templates
     pub(crate) component_policies: HashMap<String, Arc<WasiStateTemplate>>,
+    /// Maps component IDs to the `wasi:config` variables set at runtime
+    pub(crate) config_overrides: HashMap<String, HashMap<String, String>>,
 }
 
 /// Information about a policy attached to a component
@@ -77,8 +79,9 @@ impl crate::LifecycleManager {
         info!(component_id, policy_uri, "Attaching policy to component");
 
         if !self.components.read().await.contains_key(component_id) {
-            return Err(anyhow!("Component not found: {}", component_id));
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
         }
+        self.ensure_modifiable(component_id).await?;
 
         let downloaded_policy = crate::loader::load_resource::<crate::PolicyResource>(
             policy_uri,
@@ -87,10 +90,14 @@ impl crate::LifecycleManager {
         )
         .await?;
 
-        let policy = PolicyParser::parse_file(downloaded_policy.as_ref())?;
+        let policy = self.parse_policy_file(downloaded_policy.as_ref())?;
 
         let policy_path = self.get_component_policy_path(component_id);
         tokio::fs::copy(downloaded_policy.as_ref(), &policy_path).await?;
+        // An attached policy is the new baseline for reset_permissions
+        let base_policy_path = self.get_component_base_policy_path(component_id);
+        self.remove_file_if_exists(&base_policy_path, "base policy file", component_id)
+            .await?;
 
         // Store metadata about the policy source
         let metadata = serde_json::json!({
@@ -104,6 +111,7 @@ impl crate::LifecycleManager {
             &policy,
             &self.plugin_dir,
             &self.environment_vars,
+            &self.template_context(component_id),
         )?;
         self.policy_registry
             .write()
@@ -112,6 +120,11 @@ impl crate::LifecycleManager {
             .insert(component_id.to_string(), Arc::new(wasi_template));
 
         info!(component_id, policy_uri, "Policy attached successfully");
+        self.events.emit(Event::PolicyAttached {
+            component_id: component_id.to_string(),
+            policy_uri: policy_uri.to_string(),
+        });
+        self.warn_about_policy(component_id).await;
         Ok(())
     }
 
@@ -119,6 +132,7 @@ impl crate::LifecycleManager {
     /// component and remove the policy file from the plugin directory.
     pub async fn detach_policy(&self, component_id: &str) -> Result<()> {
         info!(component_id, "Detaching policy from component");
+        self.ensure_modifiable(component_id).await?;
 
         // Remove files first, then clean up memory on success
         let policy_path = self.get_component_policy_path(component_id);
@@ -129,10 +143,17 @@ impl crate::LifecycleManager {
         self.remove_file_if_exists(&metadata_path, "policy metadata file", component_id)
             .await?;
 
+        let base_policy_path = self.get_component_base_policy_path(component_id);
+        self.remove_file_if_exists(&base_policy_path, "base policy file", component_id)
+            .await?;
+
         // Only cleanup memory after all files are successfully removed
         self.cleanup_policy_registry(component_id).await;
 
         info!(component_id, "Policy detached successfully");
+        self.events.emit(Event::PolicyDetached {
+            component_id: component_id.to_string(),
+        });
         Ok(())
     }
 
@@ -177,15 +198,58 @@ impl crate::LifecycleManager {
         })
     }
 
+    /// The storage grants of the policy attached to a component, including the ones granted at
+    /// runtime. A component without a policy has none.
+    pub async fn get_storage_grants(&self, component_id: &str) -> Result<Vec<StoragePermission>> {
+        let policy_path = self.get_component_policy_path(component_id);
+        if !tokio::fs::try_exists(&policy_path).await.unwrap_or(false) {
+            return Ok(Vec::new());
+        }
+        let policy = self.parse_policy_file(&policy_path)?;
+        Ok(policy
+            .permissions
+            .storage
+            .and_then(|storage| storage.allow)
+            .unwrap_or_default())
+    }
+
     pub(crate) fn get_component_policy_path(&self, component_id: &str) -> PathBuf {
         self.plugin_dir.join(format!("{component_id}.policy.yaml"))
     }
 
+    /// The values the config variables of a component's policy template are expanded with
+    pub(crate) fn template_context(&self, component_id: &str) -> crate::TemplateContext {
+        crate::TemplateContext::new(&self.plugin_dir, component_id, &self.session_id)
+    }
+
     pub(crate) fn get_component_metadata_path(&self, component_id: &str) -> PathBuf {
         self.plugin_dir
             .join(format!("{component_id}.policy.meta.json"))
     }
 
+    /// The policy a component had before its first runtime grant or revocation. An empty file
+    /// means the component had no policy on disk.
+    pub(crate) fn get_component_base_policy_path(&self, component_id: &str) -> PathBuf {
+        self.plugin_dir
+            .join(format!("{component_id}.policy.base.yaml"))
+    }
+
+    /// Parses a policy that is attached to a component, in strict mode if the lifecycle manager
+    /// runs with [`crate::LifecycleManager::with_strict_policy`]
+    pub(crate) fn parse_policy(&self, content: &str) -> Result<PolicyDocument> {
+        if self.strict_policy {
+            PolicyParser::parse_str_strict(content)
+        } else {
+            PolicyParser::parse_str(content)
+        }
+    }
+
+    /// Reads and parses a policy file like [`crate::LifecycleManager::parse_policy`]
+    pub(crate) fn parse_policy_file(&self, path: impl AsRef<Path>) -> Result<PolicyDocument> {
+        let content = std::fs::read_to_string(path)?;
+        self.parse_policy(&content)
+    }
+
     pub(crate) fn create_default_policy_template() -> Arc<WasiStateTemplate> {
         Arc::new(WasiStateTemplate::default())
     }
@@ -212,8 +276,9 @@ impl crate::LifecycleManager {
             permission_type, "Granting permission to component"
         );
         if !self.components.read().await.contains_key(component_id) {
-            return Err(anyhow!("Component not found: {}", component_id));
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
         }
+        self.ensure_modifiable(component_id).await?;
 
         let permission_rule = self.parse_permission_rule(permission_type, details)?;
         self.validate_permission_rule(&permission_rule)?;
@@ -226,6 +291,11 @@ impl crate::LifecycleManager {
             component_id,
             permission_type, "Permission granted successfully"
         );
+        self.events.emit(Event::PermissionGranted {
+            component_id: component_id.to_string(),
+            permission_type: permission_type.to_string(),
+            details: details.clone(),
+        });
         Ok(())
     }
 
@@ -236,6 +306,26 @@ impl crate::LifecycleManager {
         details: &serde_json::Value,
     ) -> Result<PermissionRule> {
         let permission_rule = match permission_type {
+            "network" if details.get("udp").is_some() => {
+                let udp = details
+                    .get("udp")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("'udp' field must be a string"))?;
+                PermissionRule::Network(NetworkPermission::Udp(NetworkUdpPermission {
+                    udp: udp.to_string(),
+                    port: parse_port(details)?,
+                }))
+            }
+            "network" if details.get("tcp").is_some() => {
+                let tcp = details
+                    .get("tcp")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("'tcp' field must be a string"))?;
+                PermissionRule::Network(NetworkPermission::Tcp(NetworkTcpPermission {
+                    tcp: tcp.to_string(),
+                    port: parse_port(details)?,
+                }))
+            }
             "network" => {
                 let host = details
                     .get("host")
@@ -292,6 +382,7 @@ impl crate::LifecycleManager {
                     .ok_or_else(|| anyhow!("Missing 'key' field for environment permission"))?;
                 PermissionRule::Environment(EnvironmentPermission {
                     key: key.to_string(),
+                    from: None,
                 })
             }
             "resource" => {
@@ -512,6 +603,20 @@ impl crate::LifecycleManager {
         policy: &PolicyDocument,
     ) -> Result<()> {
         let policy_path = self.get_component_policy_path(component_id);
+        let base_policy_path = self.get_component_base_policy_path(component_id);
+        // Keep the policy from before the first runtime change so it can be restored by
+        // reset_permissions
+        if !tokio::fs::try_exists(&base_policy_path)
+            .await
+            .unwrap_or(false)
+        {
+            if tokio::fs::try_exists(&policy_path).await.unwrap_or(false) {
+                tokio::fs::copy(&policy_path, &base_policy_path).await?;
+            } else {
+                tokio::fs::write(&base_policy_path, "").await?;
+            }
+        }
+
         let policy_yaml = serde_yaml::to_string(policy)?;
         tokio::fs::write(&policy_path, policy_yaml).await?;
         Ok(())
@@ -527,6 +632,7 @@ impl crate::LifecycleManager {
             policy,
             &self.plugin_dir,
             &self.environment_vars,
+            &self.template_context(component_id),
         )?;
         self.policy_registry
             .write()
@@ -539,23 +645,30 @@ impl crate::LifecycleManager {
     /// Validate permission rule
     fn validate_permission_rule(&self, rule: &PermissionRule) -> Result<()> {
         match rule {
-            PermissionRule::Network(NetworkPermission::Host(NetworkHostPermission { host })) => {
-                if host.is_empty() {
-                    return Err(anyhow!("Network host cannot be empty"));
-                }
+            PermissionRule::Network(NetworkPermission::Host(NetworkHostPermission { host }))
+                if host.is_empty() =>
+            {
+                return Err(anyhow!("Network host cannot be empty"));
             }
-            PermissionRule::Storage(storage) => {
-                // TODO: the validation should verify if the uri is actually valid or not
-                if storage.uri.is_empty() {
-                    return Err(anyhow!("Storage URI cannot be empty"));
-                }
-                // Note: access can be empty for revocation operations, but not for grant operations
-                // The validation for non-empty access is now done during parsing
+            PermissionRule::Network(NetworkPermission::Udp(NetworkUdpPermission {
+                port, ..
+            })) if *port == Some(0) => {
+                return Err(anyhow!("UDP port cannot be 0"));
             }
-            PermissionRule::Environment(env) => {
-                if env.key.is_empty() {
-                    return Err(anyhow!("Environment variable key cannot be empty"));
-                }
+            PermissionRule::Network(NetworkPermission::Udp(NetworkUdpPermission { udp, port })) => {
+                crate::SocketGrant::parse(udp, *port)?;
+            }
+            PermissionRule::Network(NetworkPermission::Tcp(NetworkTcpPermission { tcp, port })) => {
+                crate::SocketGrant::parse_endpoint(tcp, *port)?;
+            }
+            // TODO: the validation should verify if the uri is actually valid or not
+            // Note: access can be empty for revocation operations, but not for grant operations
+            // The validation for non-empty access is now done during parsing
+            PermissionRule::Storage(storage) if storage.uri.is_empty() => {
+                return Err(anyhow!("Storage URI cannot be empty"));
+            }
+            PermissionRule::Environment(env) if env.key.is_empty() => {
+                return Err(anyhow!("Environment variable key cannot be empty"));
             }
             _ => {}
         }
@@ -575,8 +688,9 @@ impl crate::LifecycleManager {
             permission_type, "Revoking permission from component"
         );
         if !self.components.read().await.contains_key(component_id) {
-            return Err(anyhow!("Component not found: {}", component_id));
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
         }
+        self.ensure_modifiable(component_id).await?;
 
         let permission_rule = self.parse_permission_rule(permission_type, details)?;
         self.validate_permission_rule(&permission_rule)?;
@@ -589,30 +703,64 @@ impl crate::LifecycleManager {
             component_id,
             permission_type, "Permission revoked successfully"
         );
+        self.events.emit(Event::PermissionRevoked {
+            component_id: component_id.to_string(),
+            permission_type: permission_type.to_string(),
+            details: details.clone(),
+        });
         Ok(())
     }
 
-    /// Reset all permissions for a component
+    /// Drops every permission granted or revoked at runtime and reverts the component to the
+    /// policy it had on disk before the first runtime change. Components that had no policy
+    /// go back to the zero-permission default.
     #[instrument(skip(self))]
-    pub async fn reset_permission(&self, component_id: &str) -> Result<()> {
+    pub async fn reset_permissions(&self, component_id: &str) -> Result<()> {
         info!(component_id, "Resetting all permissions for component");
         if !self.components.read().await.contains_key(component_id) {
-            return Err(anyhow!("Component not found: {}", component_id));
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
         }
+        self.ensure_modifiable(component_id).await?;
 
-        // Remove policy files
         let policy_path = self.get_component_policy_path(component_id);
-        self.remove_file_if_exists(&policy_path, "policy file", component_id)
-            .await?;
+        let base_policy_path = self.get_component_base_policy_path(component_id);
+        let base_policy = match tokio::fs::read_to_string(&base_policy_path).await {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
 
-        let metadata_path = self.get_component_metadata_path(component_id);
-        self.remove_file_if_exists(&metadata_path, "policy metadata file", component_id)
-            .await?;
+        match base_policy {
+            Some(content) if !content.trim().is_empty() => {
+                let policy = self.parse_policy(&content)?;
+                tokio::fs::rename(&base_policy_path, &policy_path).await?;
+                self.update_policy_registry(component_id, &policy).await?;
+                info!(component_id, "Permissions reset to the on-disk policy");
+            }
+            Some(_) => {
+                // The component had no policy before runtime grants created one
+                self.remove_file_if_exists(&policy_path, "policy file", component_id)
+                    .await?;
+                let metadata_path = self.get_component_metadata_path(component_id);
+                self.remove_file_if_exists(&metadata_path, "policy metadata file", component_id)
+                    .await?;
+                self.remove_file_if_exists(&base_policy_path, "base policy file", component_id)
+                    .await?;
+                self.cleanup_policy_registry(component_id).await;
+                info!(
+                    component_id,
+                    "Permissions reset to the zero-permission default"
+                );
+            }
+            None => {
+                debug!(component_id, "No runtime permission changes to reset");
+            }
+        }
 
-        // Remove from policy registry
-        self.cleanup_policy_registry(component_id).await;
+        self.events.emit(Event::PermissionsReset {
+            component_id: component_id.to_string(),
+        });
 
-        info!(component_id, "All permissions reset successfully");
         Ok(())
     }
 
@@ -724,6 +872,18 @@ impl crate::LifecycleManager {
     }
 }
 
+/// Reads the optional remote port of a socket grant from permission details
+fn parse_port(details: &serde_json::Value) -> Result<Option<u16>> {
+    details
+        .get("port")
+        .map(|v| {
+            v.as_u64()
+                .and_then(|port| u16::try_from(port).ok())
+                .ok_or_else(|| anyhow!("'port' field must be a valid port number"))
+        })
+        .transpose()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1074,6 +1234,85 @@ permissions: {}
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_grant_permission_network_udp() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+
+        let details = serde_json::json!({"udp": "pool.ntp.org", "port": 123});
+        manager
+            .grant_permission(TEST_COMPONENT_ID, "network", &details)
+            .await?;
+
+        let policy =
+            PolicyParser::parse_file(manager.get_component_policy_path(TEST_COMPONENT_ID))?;
+        let network_allow = policy.permissions.network.unwrap().allow.unwrap();
+        assert_eq!(
+            network_allow,
+            vec![NetworkPermission::Udp(NetworkUdpPermission {
+                udp: "pool.ntp.org".to_string(),
+                port: Some(123),
+            })]
+        );
+
+        let templates = manager.policy_registry.read().await;
+        let template = templates.component_policies.get(TEST_COMPONENT_ID).unwrap();
+        assert!(template.network_perms.allow_udp);
+        assert!(template.allowed_dns_names.contains("pool.ntp.org"));
+        drop(templates);
+
+        for details in [
+            serde_json::json!({"udp": "pool.ntp.org", "port": 0}),
+            serde_json::json!({"udp": "pool.ntp.org", "port": 70000}),
+            serde_json::json!({"udp": "10.0.0.0/99"}),
+        ] {
+            assert!(manager
+                .grant_permission(TEST_COMPONENT_ID, "network", &details)
+                .await
+                .is_err());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_grant_permission_network_tcp() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+
+        let details = serde_json::json!({"tcp": "tcp://db.internal:5432"});
+        manager
+            .grant_permission(TEST_COMPONENT_ID, "network", &details)
+            .await?;
+
+        let templates = manager.policy_registry.read().await;
+        let template = templates.component_policies.get(TEST_COMPONENT_ID).unwrap();
+        assert!(template.network_perms.allow_tcp);
+        assert!(!template.network_perms.allow_udp);
+        assert!(template.allowed_dns_names.contains("db.internal"));
+        drop(templates);
+
+        manager
+            .revoke_permission(TEST_COMPONENT_ID, "network", &details)
+            .await?;
+        let templates = manager.policy_registry.read().await;
+        let template = templates.component_policies.get(TEST_COMPONENT_ID).unwrap();
+        assert!(!template.network_perms.allow_tcp);
+        drop(templates);
+
+        for details in [
+            serde_json::json!({"tcp": "db.internal:5432", "port": 5433}),
+            serde_json::json!({"tcp": "db.internal:postgres"}),
+        ] {
+            assert!(manager
+                .grant_permission(TEST_COMPONENT_ID, "network", &details)
+                .await
+                .is_err());
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_grant_permission_multiple_permissions() -> Result<()> {
         let manager = create_test_manager().await?;
@@ -1115,7 +1354,7 @@ permissions: {}
 
         // Verify policy registry was updated by attempting to get WASI state
         let _wasi_state = manager
-            .get_wasi_state_for_component(TEST_COMPONENT_ID)
+            .get_wasi_state_for_component(TEST_COMPONENT_ID, None, None, None)
             .await?;
 
         // If we get here without error, the policy registry was updated successfully
@@ -1195,6 +1434,7 @@ permissions:
         });
         let env_perm = PermissionRule::Environment(EnvironmentPermission {
             key: "API_KEY".to_string(),
+            from: None,
         });
         let custom_perm = PermissionRule::Custom(
             "custom-type".to_string(),
@@ -0,0 +1,356 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Guest profiles of the tool calls of selected components
+//!
+//! Calls to a profiled component run with Wasmtime's guest profiler, which samples the stack of
+//! the component at every tick of the engine epoch. When the call returns, the profile is written
+//! to `<dir>/<component-id>/<started-at-ms>-<tool>.json` in the Firefox processed profile format,
+//! which <https://profiler.firefox.com> shows as a flame graph. Only the newest
+//! [`MAX_PROFILES_PER_COMPONENT`] profiles of each component are kept.
+//!
+//! The engine is always compiled with epoch interruption, but the epoch only advances once
+//! profiling is enabled. Calls of components that aren't profiled then yield to the runtime at
+//! every tick instead of being sampled.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use wasmtime::component::Component;
+use wasmtime::{GuestProfiler, Store, UpdateDeadline};
+
+use crate::http::WassetteWasiState;
+use crate::wasistate::WasiState;
+use crate::LifecycleManager;
+
+/// How often the stack of a profiled component is sampled by default
+pub const DEFAULT_PROFILE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Directory under the plugin directory that profiles are written to by default
+pub const DEFAULT_PROFILE_DIR: &str = "diagnostics/profiles";
+
+/// How many profiles of each component are kept before the oldest is deleted
+pub const MAX_PROFILES_PER_COMPONENT: usize = 32;
+
+/// Which components to profile, where to write their profiles and how often to sample them
+#[derive(Debug, Clone)]
+pub struct ProfilingConfig {
+    components: BTreeSet<String>,
+    dir: Option<PathBuf>,
+    interval: Duration,
+}
+
+impl ProfilingConfig {
+    /// Profiles every call to the given components, writing the profiles to
+    /// [`DEFAULT_PROFILE_DIR`] under the plugin directory
+    pub fn new(components: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            components: components.into_iter().map(Into::into).collect(),
+            dir: None,
+            interval: DEFAULT_PROFILE_INTERVAL,
+        }
+    }
+
+    /// Writes the profiles to `dir` instead
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Samples the stack every `interval` instead of every [`DEFAULT_PROFILE_INTERVAL`]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+/// The profiling settings of a lifecycle manager, with the directory resolved
+#[derive(Debug)]
+pub(crate) struct Profiling {
+    components: BTreeSet<String>,
+    dir: PathBuf,
+    interval: Duration,
+}
+
+/// A profile written for a call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallProfile {
+    /// File name of the profile, which identifies it among the profiles of the component
+    pub name: String,
+    /// The tool that was called
+    pub tool: String,
+    /// When the call started, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// Size of the profile in bytes
+    pub size: u64,
+}
+
+impl CallProfile {
+    fn parse(name: &str, size: u64) -> Option<Self> {
+        let (started_at_ms, tool) = name.strip_suffix(".json")?.split_once('-')?;
+        Some(Self {
+            name: name.to_string(),
+            tool: tool.to_string(),
+            started_at_ms: started_at_ms.parse().ok()?,
+            size,
+        })
+    }
+}
+
+/// The guest profiler of a running call
+pub(crate) struct CallProfiler {
+    profiler: Arc<Mutex<Option<GuestProfiler>>>,
+    dir: PathBuf,
+    name: String,
+}
+
+impl CallProfiler {
+    /// Stops sampling, writes the profile and deletes the oldest profiles of the component
+    pub(crate) async fn finish(self) -> Result<PathBuf> {
+        let profiler = self
+            .profiler
+            .lock()
+            .expect("profiler lock poisoned")
+            .take()
+            .context("Profile was already written")?;
+        let mut profile = Vec::new();
+        profiler.finish(&mut profile)?;
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(&self.name);
+        tokio::fs::write(&path, profile)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        for stale in list_profiles(&self.dir)
+            .await?
+            .into_iter()
+            .skip(MAX_PROFILES_PER_COMPONENT)
+        {
+            if let Err(e) = tokio::fs::remove_file(self.dir.join(&stale.name)).await {
+                warn!(profile = stale.name, error = %e, "Failed to delete old profile");
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// The profiles in `dir`, newest first
+async fn list_profiles(dir: &Path) -> Result<Vec<CallProfile>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut profiles = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let size = entry.metadata().await?.len();
+        if let Some(profile) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| CallProfile::parse(name, size))
+        {
+            profiles.push(profile);
+        }
+    }
+    profiles.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(profiles)
+}
+
+/// A tool name that is safe to use in a file name
+fn file_safe(tool: &str) -> String {
+    tool.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl LifecycleManager {
+    /// Profiles every call to the components in `config`. The epoch of the engine advances every
+    /// sampling interval from now on, so this should only be called once.
+    pub fn with_profiling(mut self, config: ProfilingConfig) -> Self {
+        let engine = Arc::downgrade(&self.engine);
+        let interval = config.interval;
+        std::thread::Builder::new()
+            .name("wassette-profiler".to_string())
+            .spawn(move || {
+                while let Some(engine) = engine.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(interval);
+                }
+            })
+            .expect("failed to spawn the profiler thread");
+        self.profiling = Some(Arc::new(Profiling {
+            dir: config
+                .dir
+                .unwrap_or_else(|| self.plugin_dir.join(DEFAULT_PROFILE_DIR)),
+            components: config.components,
+            interval,
+        }));
+        self
+    }
+
+    /// Whether the calls to a component are profiled
+    pub fn is_profiling(&self, component_id: &str) -> bool {
+        self.profiling
+            .as_ref()
+            .is_some_and(|profiling| profiling.components.contains(component_id))
+    }
+
+    /// The profiles written for the calls to a component, newest first
+    pub async fn profiles(&self, component_id: &str) -> Result<Vec<CallProfile>> {
+        match &self.profiling {
+            Some(profiling) => list_profiles(&profiling.dir.join(component_id)).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The profile of a component with the given [`CallProfile::name`], as JSON in the Firefox
+    /// processed profile format
+    pub async fn read_profile(&self, component_id: &str, name: &str) -> Result<String> {
+        let Some(profiling) = &self.profiling else {
+            bail!("Profiling is not enabled");
+        };
+        if CallProfile::parse(name, 0).is_none() || name.contains(['/', '\\']) {
+            bail!("Invalid profile name: {name}");
+        }
+        let path = profiling.dir.join(component_id).join(name);
+        tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Failed to read profile {name} of {component_id}"))
+    }
+
+    /// Sets up the epoch deadline of the store of a call. Calls to a profiled component sample
+    /// their stack at every tick and get a [`CallProfiler`]; other calls only yield.
+    pub(crate) fn start_profiler(
+        &self,
+        store: &mut Store<WassetteWasiState<WasiState>>,
+        component: &Component,
+        component_id: &str,
+        function_name: &str,
+    ) -> Option<CallProfiler> {
+        store.set_epoch_deadline(1);
+        let Some(profiling) = self
+            .profiling
+            .as_ref()
+            .filter(|profiling| profiling.components.contains(component_id))
+        else {
+            store.epoch_deadline_async_yield_and_update(1);
+            return None;
+        };
+
+        debug!(component_id, function_name, "Profiling call");
+        let started_at_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let profiler = Arc::new(Mutex::new(Some(GuestProfiler::new_component(
+            component_id,
+            profiling.interval,
+            component.clone(),
+            [],
+        ))));
+        let sampler = profiler.clone();
+        let mut last_sample = Instant::now();
+        store.epoch_deadline_callback(move |context| {
+            let now = Instant::now();
+            if let Some(profiler) = sampler.lock().expect("profiler lock poisoned").as_mut() {
+                profiler.sample(&context, now - last_sample);
+            }
+            last_sample = now;
+            Ok(UpdateDeadline::Yield(1))
+        });
+        Some(CallProfiler {
+            profiler,
+            dir: profiling.dir.join(component_id),
+            name: format!("{started_at_ms:013}-{}.json", file_safe(function_name)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    const SPIN: &str = r#"(component
+      (core module $m
+        (func (export "spin") (result i32) (local $i i32)
+          (loop $continue
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br_if $continue (i32.lt_u (local.get $i) (i32.const 20000000))))
+          (local.get $i)))
+      (core instance $i (instantiate $m))
+      (func $spin (result u32) (canon lift (core func $i "spin")))
+      (export "spin" (func $spin)))"#;
+
+    #[test]
+    fn test_call_profile_parse() {
+        assert_eq!(
+            CallProfile::parse("1749000000123-get-weather.json", 42),
+            Some(CallProfile {
+                name: "1749000000123-get-weather.json".to_string(),
+                tool: "get-weather".to_string(),
+                started_at_ms: 1749000000123,
+                size: 42,
+            })
+        );
+        assert_eq!(CallProfile::parse("notes.txt", 0), None);
+        assert_eq!(CallProfile::parse("latest-spin.json", 0), None);
+        assert_eq!(file_safe("wasi:cli/run.run"), "wasi_cli_run.run");
+    }
+
+    #[test(tokio::test)]
+    async fn test_profiling() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        tokio::fs::write(dir.path().join("spin.wasm"), SPIN).await?;
+        tokio::fs::write(
+            dir.path().join("other.wasm"),
+            SPIN.replace("\"spin\"", "\"churn\""),
+        )
+        .await?;
+        let profile_dir = dir.path().join("profiles");
+        let manager = LifecycleManager::new(dir.path())
+            .await?
+            .with_profiling(ProfilingConfig::new(["spin"]).with_dir(&profile_dir));
+        assert!(manager.is_profiling("spin"));
+        assert!(!manager.is_profiling("other"));
+
+        assert_eq!(
+            manager.execute_component_call("spin", "spin", "{}").await?,
+            "20000000"
+        );
+        assert_eq!(
+            manager
+                .execute_component_call("other", "churn", "{}")
+                .await?,
+            "20000000"
+        );
+
+        let profiles = manager.profiles("spin").await?;
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].tool, "spin");
+        assert!(profile_dir.join("spin").join(&profiles[0].name).exists());
+        assert!(manager.profiles("other").await?.is_empty());
+
+        let profile: serde_json::Value =
+            serde_json::from_str(&manager.read_profile("spin", &profiles[0].name).await?)?;
+        assert!(profile["meta"].is_object());
+        assert!(manager.read_profile("spin", "../spin.wasm").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,599 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Semantic recall of earlier tool results
+//!
+//! When enabled with [`crate::LifecycleManager::with_semantic_recall`], every successful tool
+//! result is embedded into a vector and kept in a small store under `<plugin_dir>/recall.redb`,
+//! along with its provenance: the component and tool that produced it, the call arguments and a
+//! digest of the policy the component ran under. [`crate::LifecycleManager::semantic_recall`]
+//! returns the stored results closest to a query.
+//!
+//! The built-in [`Embedder::Hashing`] embedder needs no model. It hashes words and character
+//! trigrams, so it finds results that share words or word stems with the query, not synonyms.
+//! [`Embedder::Tool`] embeds through a component tool instead, e.g. one that runs a local model
+//! through wasi-nn. That component runs under its own policy like any other.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::bundle::sha256_hex;
+
+/// File name of the recall database in the plugin directory
+pub(crate) const RECALL_DB_FILE: &str = "recall.redb";
+
+const MEMORIES: TableDefinition<u64, &[u8]> = TableDefinition::new("memories");
+
+/// How many results are kept before the oldest is dropped
+const MAX_MEMORIES: usize = 4096;
+
+/// Stored result text beyond this many bytes is dropped
+const MAX_STORED_TEXT: usize = 64 * 1024;
+
+/// Only this many bytes of a result are embedded
+const MAX_EMBEDDED_TEXT: usize = 8 * 1024;
+
+/// Dimensions of the vectors of the hashing embedder
+const HASHING_DIMENSIONS: usize = 256;
+
+/// Length of the excerpt returned instead of the full text, in characters
+const EXCERPT_MAX_CHARS: usize = 200;
+
+/// How tool results and queries are turned into vectors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Embedder {
+    /// Hashes words and character trigrams into a fixed-size vector, with no model
+    #[default]
+    Hashing,
+    /// Calls a component tool with `{"text": ...}`, which must return an array of numbers
+    Tool(String),
+}
+
+impl fmt::Display for Embedder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Embedder::Hashing => f.write_str("hashing"),
+            Embedder::Tool(tool) => write!(f, "tool:{tool}"),
+        }
+    }
+}
+
+impl FromStr for Embedder {
+    type Err = anyhow::Error;
+
+    /// Parses `hashing` or `tool:<tool name>`
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "hashing" => Ok(Embedder::Hashing),
+            Some(("tool", tool)) if !tool.is_empty() => Ok(Embedder::Tool(tool.to_string())),
+            _ => bail!("Embedder must be hashing or tool:<tool name>: {s}"),
+        }
+    }
+}
+
+/// A search for stored results similar to a text
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecallQuery {
+    /// Text to find similar results for
+    pub text: String,
+    /// Most results to return
+    pub limit: usize,
+    /// Only return results of this component
+    pub component_id: Option<String>,
+    /// Whether to return the full stored text of every result along with the excerpt
+    pub include_text: bool,
+}
+
+impl RecallQuery {
+    /// Number of results returned unless a limit is given
+    pub const DEFAULT_LIMIT: usize = 5;
+
+    /// Creates a query for the [`RecallQuery::DEFAULT_LIMIT`] most similar results
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            limit: Self::DEFAULT_LIMIT,
+            component_id: None,
+            include_text: false,
+        }
+    }
+}
+
+/// A stored tool result that is similar to a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Memory {
+    /// The component that produced the result
+    pub component_id: String,
+    /// The tool that produced the result
+    pub tool: String,
+    /// The arguments of the call
+    pub arguments: String,
+    /// The start of the result
+    pub excerpt: String,
+    /// The full stored result, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// When the result was stored, in RFC 3339 format
+    pub timestamp: String,
+    /// Digest of the policy the component ran under, as `sha256:<hex>`, or `None` if it had
+    /// no policy
+    pub policy_digest: Option<String>,
+    /// The embedder that produced the vector of the result
+    pub embedder: String,
+    /// Cosine similarity to the query, from -1 to 1
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMemory {
+    embedder: String,
+    vector: Vec<f32>,
+    component_id: String,
+    tool: String,
+    arguments: String,
+    text: String,
+    timestamp: i64,
+    policy_digest: Option<String>,
+}
+
+/// Cuts `text` to at most `max` bytes at a character boundary
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// 64-bit FNV-1a, which is stable across Rust versions unlike `DefaultHasher`, so stored
+/// vectors stay comparable to new ones
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Scales `vector` to unit length, so a dot product is the cosine similarity
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+/// The vector of the hashing embedder. Every word and every character trigram of a word adds
+/// a signed weight to one dimension.
+fn hashing_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; HASHING_DIMENSIONS];
+    let mut add = |feature: &str, weight: f32| {
+        let hash = fnv1a(feature.as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % HASHING_DIMENSIONS as u64) as usize] += sign * weight;
+    };
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let word = word.to_lowercase();
+        add(&word, 1.0);
+        let padded: Vec<char> = format!("^{word}$").chars().collect();
+        for trigram in padded.windows(3) {
+            add(&trigram.iter().collect::<String>(), 0.5);
+        }
+    }
+    normalize(vector)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// The vector store. Memories are kept in memory for searching and written through to redb.
+pub(crate) struct RecallStore {
+    db: Database,
+    embedder: Embedder,
+    capacity: usize,
+    memories: Mutex<BTreeMap<u64, StoredMemory>>,
+}
+
+impl RecallStore {
+    pub(crate) fn open(path: &Path, embedder: Embedder) -> Result<Arc<Self>> {
+        Self::open_with_capacity(path, embedder, MAX_MEMORIES)
+    }
+
+    fn open_with_capacity(path: &Path, embedder: Embedder, capacity: usize) -> Result<Arc<Self>> {
+        let db = Database::create(path).context("Failed to open recall store")?;
+        let mut memories = BTreeMap::new();
+        {
+            let txn = db.begin_write()?;
+            // Creates the table on first use
+            txn.open_table(MEMORIES)?;
+            txn.commit()?;
+            let txn = db.begin_read()?;
+            let table = txn.open_table(MEMORIES)?;
+            for entry in table.iter()? {
+                let (id, bytes) = entry?;
+                match serde_json::from_slice::<StoredMemory>(bytes.value()) {
+                    Ok(memory) => {
+                        memories.insert(id.value(), memory);
+                    }
+                    Err(e) => warn!(id = id.value(), error = %e, "Skipping unreadable memory"),
+                }
+            }
+        }
+        info!(memories = memories.len(), %embedder, "Opened recall store");
+        Ok(Arc::new(Self {
+            db,
+            embedder,
+            capacity,
+            memories: Mutex::new(memories),
+        }))
+    }
+
+    fn insert(&self, memory: StoredMemory) -> Result<()> {
+        let mut memories = self.memories.lock().expect("recall store lock poisoned");
+        let id = memories.last_key_value().map_or(1, |(id, _)| id + 1);
+        let evicted: Vec<u64> = memories
+            .keys()
+            .take((memories.len() + 1).saturating_sub(self.capacity))
+            .copied()
+            .collect();
+
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(MEMORIES)?;
+            table.insert(id, serde_json::to_vec(&memory)?.as_slice())?;
+            for id in &evicted {
+                table.remove(id)?;
+            }
+        }
+        txn.commit()?;
+
+        for id in evicted {
+            memories.remove(&id);
+        }
+        memories.insert(id, memory);
+        Ok(())
+    }
+
+    /// Deletes every memory of a component. Returns the number of deleted memories.
+    fn delete_component(&self, component_id: &str) -> Result<usize> {
+        let mut memories = self.memories.lock().expect("recall store lock poisoned");
+        let ids: Vec<u64> = memories
+            .iter()
+            .filter(|(_, memory)| memory.component_id == component_id)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(MEMORIES)?;
+            for id in &ids {
+                table.remove(id)?;
+            }
+        }
+        txn.commit()?;
+
+        for id in &ids {
+            memories.remove(id);
+        }
+        Ok(ids.len())
+    }
+
+    /// The IDs of the components that have stored memories
+    fn component_ids(&self) -> BTreeSet<String> {
+        let memories = self.memories.lock().expect("recall store lock poisoned");
+        memories
+            .values()
+            .map(|memory| memory.component_id.clone())
+            .collect()
+    }
+
+    /// Returns the memories most similar to `vector`, most similar first. Memories embedded by
+    /// another embedder can't be compared and are skipped.
+    fn search(&self, vector: &[f32], query: &RecallQuery) -> Vec<Memory> {
+        let embedder = self.embedder.to_string();
+        let memories = self.memories.lock().expect("recall store lock poisoned");
+        let mut scored: Vec<(f32, &StoredMemory)> = memories
+            .values()
+            .filter(|memory| memory.embedder == embedder && memory.vector.len() == vector.len())
+            .filter(|memory| {
+                query
+                    .component_id
+                    .as_ref()
+                    .is_none_or(|id| *id == memory.component_id)
+            })
+            .map(|memory| (dot(vector, &memory.vector), memory))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        scored
+            .into_iter()
+            .take(query.limit)
+            .map(|(similarity, memory)| Memory {
+                component_id: memory.component_id.clone(),
+                tool: memory.tool.clone(),
+                arguments: memory.arguments.clone(),
+                excerpt: memory.text.chars().take(EXCERPT_MAX_CHARS).collect(),
+                text: query.include_text.then(|| memory.text.clone()),
+                timestamp: chrono::DateTime::from_timestamp(memory.timestamp, 0)
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default(),
+                policy_digest: memory.policy_digest.clone(),
+                embedder: memory.embedder.clone(),
+                similarity,
+            })
+            .collect()
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Embeds every successful tool result with `embedder` and stores it for
+    /// [`crate::LifecycleManager::semantic_recall`]. The store lives in the plugin directory
+    /// and can only be open in one process at a time.
+    pub async fn with_semantic_recall(mut self, embedder: Embedder) -> Result<Self> {
+        let path = self.plugin_dir.join(RECALL_DB_FILE);
+        let store =
+            tokio::task::spawn_blocking(move || RecallStore::open(&path, embedder)).await??;
+        self.recall = Some(store);
+        Ok(self)
+    }
+
+    /// Returns the stored tool results most similar to the query. Fails unless recall was
+    /// enabled with [`crate::LifecycleManager::with_semantic_recall`].
+    pub async fn semantic_recall(&self, query: RecallQuery) -> Result<Vec<Memory>> {
+        let Some(store) = self.recall.clone() else {
+            bail!("Semantic recall is not enabled, start the server with --semantic-recall");
+        };
+        if query.text.trim().is_empty() {
+            bail!("Recall query cannot be empty");
+        }
+        let vector = self.embed(&store.embedder, &query.text).await?;
+        Ok(store.search(&vector, &query))
+    }
+
+    async fn embed(&self, embedder: &Embedder, text: &str) -> Result<Vec<f32>> {
+        let text = truncate(text, MAX_EMBEDDED_TEXT);
+        let tool = match embedder {
+            Embedder::Hashing => return Ok(hashing_embedding(text)),
+            Embedder::Tool(tool) => tool,
+        };
+        let component_id = self
+            .get_component_id_for_tool(tool)
+            .await
+            .with_context(|| format!("Embedding tool '{tool}' is not loaded"))?;
+        let parameters = serde_json::json!({ "text": text }).to_string();
+        // Call the tool directly so that embedding calls aren't recorded or embedded themselves
+        let result = self
+            .call_component_function(
+                crate::CallOrigin::default(),
+                &component_id,
+                tool,
+                &parameters,
+                None,
+            )
+            .await
+            .with_context(|| format!("Embedding tool '{tool}' failed"))?;
+        let vector: Vec<f32> = serde_json::from_str(&result).with_context(|| {
+            format!("Embedding tool '{tool}' did not return an array of numbers")
+        })?;
+        if vector.is_empty() {
+            bail!("Embedding tool '{tool}' returned an empty vector");
+        }
+        Ok(normalize(vector))
+    }
+
+    /// Embeds and stores a successful result, if recall is enabled. Failures are only logged.
+    pub(crate) async fn remember(
+        &self,
+        component_id: &str,
+        tool: &str,
+        arguments: &str,
+        text: &str,
+    ) {
+        let Some(store) = self.recall.clone() else {
+            return;
+        };
+        if matches!(&store.embedder, Embedder::Tool(embedding_tool) if embedding_tool == tool) {
+            return;
+        }
+        let vector = match self.embed(&store.embedder, text).await {
+            Ok(vector) => vector,
+            Err(e) => {
+                warn!(component_id, tool, error = %e, "Failed to embed tool result");
+                return;
+            }
+        };
+        let policy_digest = tokio::fs::read(self.get_component_policy_path(component_id))
+            .await
+            .ok()
+            .map(|policy| format!("sha256:{}", sha256_hex(&policy)));
+        let memory = StoredMemory {
+            embedder: store.embedder.to_string(),
+            vector,
+            component_id: component_id.to_string(),
+            tool: tool.to_string(),
+            arguments: arguments.to_string(),
+            text: truncate(text, MAX_STORED_TEXT).to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            policy_digest,
+        };
+        let result = tokio::task::spawn_blocking(move || store.insert(memory)).await;
+        if let Err(e) = result.map_err(anyhow::Error::from).and_then(|r| r) {
+            warn!(component_id, tool, error = %e, "Failed to store tool result for recall");
+        }
+    }
+
+    /// The IDs of the components with stored results, if recall is enabled
+    pub(crate) fn recall_component_ids(&self) -> Option<BTreeSet<String>> {
+        Some(self.recall.as_ref()?.component_ids())
+    }
+
+    /// Deletes the stored results of a purged component, if recall is enabled
+    pub(crate) async fn forget_memories(&self, component_id: &str) {
+        let Some(store) = self.recall.clone() else {
+            return;
+        };
+        let id = component_id.to_string();
+        match tokio::task::spawn_blocking(move || store.delete_component(&id)).await {
+            Ok(Ok(deleted)) => debug!(component_id, deleted, "Deleted stored tool results"),
+            Ok(Err(e)) => warn!(component_id, error = %e, "Failed to delete stored tool results"),
+            Err(e) => warn!(component_id, error = %e, "Failed to delete stored tool results"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(component_id: &str, text: &str) -> StoredMemory {
+        StoredMemory {
+            embedder: Embedder::Hashing.to_string(),
+            vector: hashing_embedding(text),
+            component_id: component_id.to_string(),
+            tool: "fetch".to_string(),
+            arguments: "{}".to_string(),
+            text: text.to_string(),
+            timestamp: 0,
+            policy_digest: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_embedder() {
+        assert_eq!("hashing".parse::<Embedder>().unwrap(), Embedder::Hashing);
+        assert_eq!(
+            "tool:embed".parse::<Embedder>().unwrap(),
+            Embedder::Tool("embed".to_string())
+        );
+        assert!("tool:".parse::<Embedder>().is_err());
+        assert!("openai".parse::<Embedder>().is_err());
+    }
+
+    #[test]
+    fn test_hashing_embedding() {
+        let query = hashing_embedding("weather forecast for Berlin");
+        let close = hashing_embedding("The Berlin weather today: sunny");
+        let far = hashing_embedding("Rust compiler error E0382");
+        assert!(dot(&query, &close) > dot(&query, &far));
+        // Trigrams make related word forms similar
+        assert!(
+            dot(
+                &hashing_embedding("fetched"),
+                &hashing_embedding("fetching")
+            ) > 0.3
+        );
+        assert!((dot(&query, &query) - 1.0).abs() < 1e-5);
+        assert_eq!(hashing_embedding(""), vec![0.0; HASHING_DIMENSIONS]);
+    }
+
+    #[test]
+    fn test_recall_store() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join(RECALL_DB_FILE);
+        let store = RecallStore::open(&path, Embedder::Hashing)?;
+        store.insert(memory("weather", "Berlin: sunny, 21 degrees"))?;
+        store.insert(memory("docs", "The borrow checker rejects moved values"))?;
+
+        let query = RecallQuery::new("sunny in berlin");
+        let hits = store.search(&hashing_embedding(&query.text), &query);
+        assert_eq!(hits[0].component_id, "weather");
+        assert_eq!(hits[0].excerpt, "Berlin: sunny, 21 degrees");
+        assert_eq!(hits[0].text, None);
+        assert!(hits[0].similarity > hits[1].similarity);
+
+        let query = RecallQuery {
+            component_id: Some("docs".to_string()),
+            ..RecallQuery::new("sunny in berlin")
+        };
+        assert_eq!(
+            store.search(&hashing_embedding(&query.text), &query).len(),
+            1
+        );
+
+        // Memories survive a restart, but not with another embedder
+        drop(store);
+        let store = RecallStore::open(&path, Embedder::Hashing)?;
+        let query = RecallQuery::new("berlin");
+        assert_eq!(store.search(&hashing_embedding("berlin"), &query).len(), 2);
+        assert_eq!(store.delete_component("weather")?, 1);
+        assert_eq!(store.search(&hashing_embedding("berlin"), &query).len(), 1);
+        drop(store);
+        let store = RecallStore::open(&path, Embedder::Tool("embed".to_string()))?;
+        assert!(store
+            .search(&hashing_embedding("berlin"), &query)
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_manager_remembers_results() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "run") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func (export "run") (result u32) (canon lift (core func $i "run"))))"#,
+        )?;
+        let manager = crate::LifecycleManager::new(&tempdir)
+            .await?
+            .with_semantic_recall(Embedder::Hashing)
+            .await?;
+
+        manager
+            .execute_component_call("answer", "run", "{}")
+            .await?;
+        let memories = manager.semantic_recall(RecallQuery::new("42")).await?;
+        assert_eq!(memories.len(), 1);
+        assert_eq!(memories[0].component_id, "answer");
+        assert_eq!(memories[0].tool, "run");
+        assert_eq!(memories[0].policy_digest, None);
+        assert!(memories[0].similarity > 0.99);
+
+        // Purging a component deletes what it stored
+        manager.unload_component("answer").await?;
+        manager.purge_component("answer").await?;
+        assert!(manager
+            .semantic_recall(RecallQuery::new("42"))
+            .await?
+            .is_empty());
+
+        let manager = crate::LifecycleManager::new(tempfile::tempdir()?.path()).await?;
+        assert!(manager
+            .semantic_recall(RecallQuery::new("42"))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recall_store_drops_oldest() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join(RECALL_DB_FILE);
+        let store = RecallStore::open_with_capacity(&path, Embedder::Hashing, 3)?;
+        for i in 0..5 {
+            store.insert(memory(&format!("c{i}"), "same text"))?;
+        }
+        drop(store);
+
+        let store = RecallStore::open(&path, Embedder::Hashing)?;
+        let memories = store.memories.lock().unwrap();
+        let kept: Vec<&str> = memories
+            .values()
+            .map(|memory| memory.component_id.as_str())
+            .collect();
+        assert_eq!(kept, vec!["c2", "c3", "c4"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wassette:ai/sampling`
+//!
+//! A component can ask the model of the connected MCP client for a completion, for example to
+//! summarize a page it fetched, without an API key of its own. The host hands the request to the
+//! [`Sampler`] of the call, which the MCP server implements with `sampling/createMessage`. Only
+//! components whose policy has `permissions.sampling` may sample, and `max_tokens` there caps
+//! every completion. Calls without a sampler, such as the ones from the CLI, fail to sample.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use policy::{PolicyDocument, SamplingPermissions};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use self::generated::wassette::ai::sampling;
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/ai",
+        world: "wassette:ai/imports",
+        async: true,
+    });
+}
+
+/// Who wrote a message of a sampling request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplingRole {
+    /// The user
+    User,
+    /// The model
+    Assistant,
+}
+
+/// A message of the conversation a component asks to complete
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingMessage {
+    /// Who wrote the message
+    pub role: SamplingRole,
+    /// The text of the message
+    pub content: String,
+}
+
+/// A completion request of a component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingRequest {
+    /// The component that asks
+    pub component_id: String,
+    /// The conversation, the oldest message first
+    pub messages: Vec<SamplingMessage>,
+    /// Instructions for the model
+    pub system_prompt: Option<String>,
+    /// Most tokens the completion may have, already capped by the policy
+    pub max_tokens: u32,
+    /// Sampling temperature
+    pub temperature: Option<f32>,
+}
+
+/// A completion for a component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingResponse {
+    /// The text the model wrote
+    pub content: String,
+    /// Name of the model that wrote it
+    pub model: String,
+    /// Why the model stopped
+    pub stop_reason: Option<String>,
+}
+
+/// Completes the sampling requests of the components of a call, usually by asking the MCP
+/// client that made the call
+pub trait Sampler: Send + Sync {
+    /// Asks a model to complete `request`
+    fn create_message(
+        &self,
+        request: SamplingRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<SamplingResponse>> + Send + '_>>;
+}
+
+/// Extract the sampling permission of the policy, if it grants one
+pub(crate) fn extract_sampling_grant(policy: &PolicyDocument) -> Option<SamplingPermissions> {
+    policy.permissions.sampling.clone()
+}
+
+/// The `wassette:ai` state of a single component instance
+#[derive(Default)]
+pub(crate) struct SamplingCtx {
+    grant: Option<SamplingPermissions>,
+    component_id: String,
+    sampler: Option<Arc<dyn Sampler>>,
+}
+
+impl SamplingCtx {
+    /// Creates a context that may sample as far as `grant` allows once it has a sampler
+    pub(crate) fn new(grant: Option<SamplingPermissions>) -> Self {
+        Self {
+            grant,
+            ..Default::default()
+        }
+    }
+
+    /// Attributes the requests of this instance to `component_id` and sends them to `sampler`
+    pub(crate) fn bind(&mut self, component_id: &str, sampler: Option<Arc<dyn Sampler>>) {
+        self.component_id = component_id.to_string();
+        self.sampler = sampler;
+    }
+
+    /// Checks that the component may sample and turns its request into one for the sampler
+    fn check(&self, request: sampling::Request) -> Result<(&dyn Sampler, SamplingRequest), String> {
+        let Some(grant) = &self.grant else {
+            debug!(
+                component_id = %self.component_id,
+                "Sampling denied by policy"
+            );
+            return Err(format!(
+                "Component {} is not allowed to request completions",
+                self.component_id
+            ));
+        };
+        let sampler = self
+            .sampler
+            .as_deref()
+            .ok_or_else(|| "No MCP client is connected to complete the request".to_string())?;
+        if request.messages.is_empty() {
+            return Err("A completion request needs at least one message".to_string());
+        }
+        let max_tokens = match grant.max_tokens {
+            Some(limit) => request.max_tokens.min(limit),
+            None => request.max_tokens,
+        };
+        let request = SamplingRequest {
+            component_id: self.component_id.clone(),
+            messages: request
+                .messages
+                .into_iter()
+                .map(|message| SamplingMessage {
+                    role: match message.role {
+                        sampling::Role::User => SamplingRole::User,
+                        sampling::Role::Assistant => SamplingRole::Assistant,
+                    },
+                    content: message.content,
+                })
+                .collect(),
+            system_prompt: request.system_prompt,
+            max_tokens,
+            temperature: request.temperature,
+        };
+        Ok((sampler, request))
+    }
+}
+
+/// A view of a component's `wassette:ai` state used by the host bindings
+pub(crate) struct SamplingHost<'a> {
+    ctx: &'a SamplingCtx,
+}
+
+impl<'a> SamplingHost<'a> {
+    pub(crate) fn new(ctx: &'a SamplingCtx) -> Self {
+        Self { ctx }
+    }
+}
+
+impl sampling::Host for SamplingHost<'_> {
+    async fn create_message(
+        &mut self,
+        request: sampling::Request,
+    ) -> Result<sampling::Response, String> {
+        let (sampler, request) = self.ctx.check(request)?;
+        info!(
+            component_id = %request.component_id,
+            messages = request.messages.len(),
+            max_tokens = request.max_tokens,
+            "Component requested a completion"
+        );
+        let response = sampler
+            .create_message(request)
+            .await
+            .map_err(|e| format!("{e:#}"))?;
+        Ok(sampling::Response {
+            content: response.content,
+            model: response.model,
+            stop_reason: response.stop_reason,
+        })
+    }
+}
+
+/// Adds the `wassette:ai` interfaces to the linker
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> SamplingHost<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    sampling::add_to_linker_get_host(linker, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::sampling::Host as _;
+    use super::*;
+
+    /// Answers every request with the text of its last message and remembers the requests
+    #[derive(Default)]
+    struct EchoSampler {
+        requests: Mutex<Vec<SamplingRequest>>,
+    }
+
+    impl Sampler for EchoSampler {
+        fn create_message(
+            &self,
+            request: SamplingRequest,
+        ) -> Pin<Box<dyn Future<Output = Result<SamplingResponse>> + Send + '_>> {
+            Box::pin(async move {
+                let content = request.messages.last().unwrap().content.clone();
+                self.requests.lock().unwrap().push(request);
+                Ok(SamplingResponse {
+                    content,
+                    model: "echo".to_string(),
+                    stop_reason: None,
+                })
+            })
+        }
+    }
+
+    fn request(max_tokens: u32) -> sampling::Request {
+        sampling::Request {
+            messages: vec![sampling::Message {
+                role: sampling::Role::User,
+                content: "Summarize this".to_string(),
+            }],
+            system_prompt: None,
+            max_tokens,
+            temperature: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_message() {
+        let sampler = Arc::new(EchoSampler::default());
+
+        let mut denied = SamplingCtx::new(None);
+        denied.bind("fetch", Some(sampler.clone()));
+        let err = SamplingHost::new(&denied)
+            .create_message(request(100))
+            .await
+            .unwrap_err();
+        assert!(err.contains("not allowed"), "{err}");
+
+        let mut unbound = SamplingCtx::new(Some(SamplingPermissions::default()));
+        unbound.bind("fetch", None);
+        let err = SamplingHost::new(&unbound)
+            .create_message(request(100))
+            .await
+            .unwrap_err();
+        assert!(err.contains("No MCP client"), "{err}");
+
+        let mut ctx = SamplingCtx::new(Some(SamplingPermissions {
+            max_tokens: Some(50),
+        }));
+        ctx.bind("fetch", Some(sampler.clone()));
+        let response = SamplingHost::new(&ctx)
+            .create_message(request(100))
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Summarize this");
+        let requests = sampler.requests.lock().unwrap();
+        assert_eq!(requests[0].component_id, "fetch");
+        assert_eq!(requests[0].max_tokens, 50);
+    }
+}
@@ -0,0 +1,996 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Secrets of components
+//!
+//! Secrets are kept by a [`SecretProvider`]. By default, that's a [`FileSecretProvider`], which
+//! keeps the secrets of a component in `<plugin-dir>/secrets/<component-id>.yaml`, a map of names
+//! to values that only the user running the server may read. Embedders can keep them elsewhere
+//! with [`crate::LifecycleManager::with_secret_provider`]. The component sees its secrets as
+//! `wasi:config` variables, over the ones derived from its policy and environment and under the
+//! ones set with [`crate::LifecycleManager::set_component_config`]. The provider is asked for
+//! every call, so changes to the secrets, including edits of the files by hand, apply to the
+//! next call. With [`crate::LifecycleManager::with_secret_watch`], the secrets are cached instead
+//! and the files are watched, so edits by hand are reported like any other change. Secrets are
+//! kept when the component is unloaded and deleted when it is purged.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use tokio::io::AsyncWriteExt as _;
+use tokio::sync::Mutex;
+use tracing::{info, instrument, warn};
+
+use crate::dotenv::parse_dotenv;
+use crate::events::EventBus;
+use crate::keychain::{KeychainSecretProvider, KEYCHAIN_SECRET_PROVIDER};
+use crate::{ComponentNotFound, Event};
+
+/// Directory of the secret files in the plugin directory
+pub(crate) const SECRETS_DIR: &str = "secrets";
+
+/// Directory of the files with the functions the secrets of each component are limited to
+pub(crate) const SECRET_SCOPES_DIR: &str = "secret-scopes";
+
+/// How often [`crate::LifecycleManager::with_secret_watch`] checks the secret files by default
+pub const DEFAULT_SECRET_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Name of the secret provider that keeps secrets in files in the plugin directory
+pub const FILE_SECRET_PROVIDER: &str = "file";
+
+/// The future of a [`SecretProvider`] operation
+pub type SecretFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Keeps the secrets of components, by component ID and name
+pub trait SecretProvider: Send + Sync {
+    /// The secrets of `component_id`, by name. Components without secrets have none.
+    fn get<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, BTreeMap<String, String>>;
+
+    /// Sets the secret `key` of `component_id`
+    fn set<'a>(
+        &'a self,
+        component_id: &'a str,
+        key: &'a str,
+        value: &'a str,
+    ) -> SecretFuture<'a, ()>;
+
+    /// Deletes the secret `key` of `component_id`. Returns whether it had one.
+    fn delete<'a>(&'a self, component_id: &'a str, key: &'a str) -> SecretFuture<'a, bool>;
+
+    /// The names of the secrets of `component_id`, sorted
+    fn list<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, Vec<String>> {
+        Box::pin(async move { Ok(self.get(component_id).await?.into_keys().collect()) })
+    }
+
+    /// Deletes all secrets of `component_id`
+    fn delete_all<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, ()> {
+        Box::pin(async move {
+            for key in self.list(component_id).await? {
+                self.delete(component_id, &key).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Returns the built-in secret provider called `name` for the plugin directory `plugin_dir`
+pub fn builtin_secret_provider(name: &str, plugin_dir: &Path) -> Result<Arc<dyn SecretProvider>> {
+    match name {
+        FILE_SECRET_PROVIDER => Ok(Arc::new(FileSecretProvider::new(
+            &plugin_dir.join(SECRETS_DIR),
+        ))),
+        KEYCHAIN_SECRET_PROVIDER => Ok(Arc::new(KeychainSecretProvider::new(format!(
+            "wassette:{}",
+            plugin_dir.display()
+        )))),
+        name => bail!(
+            "Unknown secret provider '{name}', expected {FILE_SECRET_PROVIDER} or \
+             {KEYCHAIN_SECRET_PROVIDER}"
+        ),
+    }
+}
+
+/// Keeps the secrets of each component in a YAML file of its own in a directory
+#[derive(Debug, Clone)]
+pub struct FileSecretProvider {
+    dir: PathBuf,
+    /// Held while a file is read and written back, so concurrent changes aren't lost
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl FileSecretProvider {
+    /// Creates a provider that keeps its files in `dir`, which is created when the first secret
+    /// is set
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            write_lock: Arc::default(),
+        }
+    }
+
+    fn path(&self, component_id: &str) -> PathBuf {
+        self.dir.join(format!("{component_id}.yaml"))
+    }
+
+    async fn load(&self, component_id: &str) -> Result<BTreeMap<String, String>> {
+        let path = self.path(component_id);
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        if contents.trim().is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse secrets in {}", path.display()))
+    }
+
+    /// Writes the secrets of `component_id`, or deletes its file if it has none left. The file
+    /// is replaced in one step and is only readable by the server's user.
+    async fn store(&self, component_id: &str, secrets: &BTreeMap<String, String>) -> Result<()> {
+        let path = self.path(component_id);
+        if secrets.is_empty() {
+            return match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+                }
+                _ => Ok(()),
+            };
+        }
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let temp_path = path.with_extension("yaml.tmp");
+        // Left behind if the server stopped while writing
+        match tokio::fs::remove_file(&temp_path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to delete {}", temp_path.display()));
+            }
+            _ => {}
+        }
+        // Created with its final permissions, so the secrets are never readable by others
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(&temp_path)
+            .await
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        file.write_all(serde_yaml::to_string(secrets)?.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn get<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, BTreeMap<String, String>> {
+        Box::pin(self.load(component_id))
+    }
+
+    fn set<'a>(
+        &'a self,
+        component_id: &'a str,
+        key: &'a str,
+        value: &'a str,
+    ) -> SecretFuture<'a, ()> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            let mut secrets = self.load(component_id).await?;
+            secrets.insert(key.to_string(), value.to_string());
+            self.store(component_id, &secrets).await
+        })
+    }
+
+    fn delete<'a>(&'a self, component_id: &'a str, key: &'a str) -> SecretFuture<'a, bool> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            let mut secrets = self.load(component_id).await?;
+            if secrets.remove(key).is_none() {
+                return Ok(false);
+            }
+            self.store(component_id, &secrets).await?;
+            Ok(true)
+        })
+    }
+
+    fn delete_all<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, ()> {
+        Box::pin(async move {
+            let _guard = self.write_lock.lock().await;
+            self.store(component_id, &BTreeMap::new()).await
+        })
+    }
+}
+
+/// The exported functions each secret of a component is limited to, by the name of the secret.
+/// Secrets without an entry are given to every function.
+type Scopes = BTreeMap<String, BTreeSet<String>>;
+
+/// The scopes of the secrets of each component, kept in `<plugin-dir>/secret-scopes/<id>.yaml`
+/// whatever the secret provider, since they are not secret themselves. They are loaded once and
+/// only changed through the server.
+#[derive(Debug, Clone)]
+struct SecretScopes {
+    dir: PathBuf,
+    loaded: Arc<Mutex<HashMap<String, Scopes>>>,
+}
+
+impl SecretScopes {
+    fn path(&self, component_id: &str) -> PathBuf {
+        self.dir.join(format!("{component_id}.yaml"))
+    }
+
+    async fn load(&self, component_id: &str) -> Result<Scopes> {
+        let path = self.path(component_id);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) if contents.trim().is_empty() => Ok(Scopes::new()),
+            Ok(contents) => serde_yaml::from_str(&contents)
+                .with_context(|| format!("Failed to parse secret scopes in {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Scopes::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    async fn get(&self, component_id: &str) -> Result<Scopes> {
+        let mut loaded = self.loaded.lock().await;
+        if let Some(scopes) = loaded.get(component_id) {
+            return Ok(scopes.clone());
+        }
+        let scopes = self.load(component_id).await?;
+        loaded.insert(component_id.to_string(), scopes.clone());
+        Ok(scopes)
+    }
+
+    /// Changes the scopes of the secrets of `component_id` with `change` and writes them back
+    async fn update(&self, component_id: &str, change: impl FnOnce(&mut Scopes)) -> Result<()> {
+        let mut loaded = self.loaded.lock().await;
+        let mut scopes = match loaded.get(component_id) {
+            Some(scopes) => scopes.clone(),
+            None => self.load(component_id).await?,
+        };
+        change(&mut scopes);
+        let path = self.path(component_id);
+        if scopes.is_empty() {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+                }
+                _ => {}
+            }
+        } else {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+            tokio::fs::write(&path, serde_yaml::to_string(&scopes)?)
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        loaded.insert(component_id.to_string(), scopes);
+        Ok(())
+    }
+}
+
+/// The secrets of each component as last loaded
+type SecretCache = Mutex<HashMap<String, BTreeMap<String, String>>>;
+type CacheGuard<'a> = tokio::sync::MutexGuard<'a, HashMap<String, BTreeMap<String, String>>>;
+
+/// The secrets of the components of a lifecycle manager, kept by its [`SecretProvider`]
+#[derive(Clone)]
+pub(crate) struct SecretManager {
+    provider: Arc<dyn SecretProvider>,
+    /// Only kept while the secrets directory is watched, which keeps it up to date. Otherwise,
+    /// the provider is asked for every call.
+    cache: Option<Arc<SecretCache>>,
+    scopes: SecretScopes,
+}
+
+impl SecretManager {
+    pub(crate) fn new(plugin_dir: &Path) -> Self {
+        Self {
+            provider: Arc::new(FileSecretProvider::new(&plugin_dir.join(SECRETS_DIR))),
+            cache: None,
+            scopes: SecretScopes {
+                dir: plugin_dir.join(SECRET_SCOPES_DIR),
+                loaded: Arc::default(),
+            },
+        }
+    }
+
+    /// The secrets of `component_id` that a call of its exported function `function` gets, by
+    /// name. Anything but a call of a function only gets the secrets that aren't limited to some
+    /// functions.
+    pub(crate) async fn load_for(
+        &self,
+        component_id: &str,
+        function: Option<&str>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut secrets = self.load(component_id).await?;
+        let scopes = self.scopes.get(component_id).await?;
+        secrets.retain(|key, _| {
+            scopes.get(key).is_none_or(|functions| {
+                function.is_some_and(|function| functions.contains(function))
+            })
+        });
+        Ok(secrets)
+    }
+
+    /// The secrets of `component_id`, by name
+    pub(crate) async fn load(&self, component_id: &str) -> Result<BTreeMap<String, String>> {
+        let Some(cache) = &self.cache else {
+            return self.provider.get(component_id).await;
+        };
+        let mut cache = cache.lock().await;
+        if let Some(secrets) = cache.get(component_id) {
+            return Ok(secrets.clone());
+        }
+        let secrets = self.provider.get(component_id).await?;
+        cache.insert(component_id.to_string(), secrets.clone());
+        Ok(secrets)
+    }
+
+    async fn set(&self, component_id: &str, key: &str, value: &str) -> Result<()> {
+        let cache = self.lock_cache().await;
+        let result = self.provider.set(component_id, key, value).await;
+        self.refresh(cache, component_id).await;
+        result
+    }
+
+    async fn delete(&self, component_id: &str, key: &str) -> Result<bool> {
+        let cache = self.lock_cache().await;
+        let result = self.provider.delete(component_id, key).await;
+        self.refresh(cache, component_id).await;
+        result
+    }
+
+    async fn list(&self, component_id: &str) -> Result<Vec<String>> {
+        self.provider.list(component_id).await
+    }
+
+    /// Sets the secrets of `component_id` to the variables of the dotenv file at `path`,
+    /// keeping its other secrets. Returns the names of the variables, sorted.
+    pub(crate) async fn import_env_file(
+        &self,
+        component_id: &str,
+        path: &Path,
+    ) -> Result<Vec<String>> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        // The last value of a variable wins, as when the file is sourced
+        let vars = parse_dotenv(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        for (key, value) in &vars {
+            self.set(component_id, key, value).await?;
+        }
+        Ok(vars.into_keys().collect())
+    }
+
+    /// Deletes all secrets of `component_id`
+    pub(crate) async fn delete_component(&self, component_id: &str) -> Result<()> {
+        let cache = self.lock_cache().await;
+        let result = self.provider.delete_all(component_id).await;
+        self.refresh(cache, component_id).await;
+        result?;
+        self.scopes.update(component_id, Scopes::clear).await
+    }
+
+    /// Holds the cache while the server changes secrets, so the watcher doesn't report the
+    /// change as one made outside the server
+    async fn lock_cache(&self) -> Option<CacheGuard<'_>> {
+        Some(self.cache.as_ref()?.lock().await)
+    }
+
+    /// Caches the secrets of `component_id` after the server changed them
+    async fn refresh(&self, cache: Option<CacheGuard<'_>>, component_id: &str) {
+        let Some(mut cache) = cache else {
+            return;
+        };
+        match self.provider.get(component_id).await {
+            Ok(secrets) => cache.insert(component_id.to_string(), secrets),
+            Err(_) => cache.remove(component_id),
+        };
+    }
+
+    /// Reloads the secrets of `component_id` and returns how they changed since they were last
+    /// loaded, as `(key, removed)`
+    async fn reload(&self, cache: &SecretCache, component_id: &str) -> Result<Vec<(String, bool)>> {
+        let mut cache = cache.lock().await;
+        let secrets = self.provider.get(component_id).await?;
+        let previous = cache
+            .insert(component_id.to_string(), secrets.clone())
+            .unwrap_or_default();
+        let mut changes = previous
+            .iter()
+            .filter(|(key, value)| secrets.get(*key) != Some(*value))
+            .map(|(key, _)| (key.clone(), !secrets.contains_key(key)))
+            .collect::<Vec<_>>();
+        changes.extend(
+            secrets
+                .keys()
+                .filter(|key| !previous.contains_key(*key))
+                .map(|key| (key.clone(), false)),
+        );
+        Ok(changes)
+    }
+}
+
+/// The modification time and size of each secret file in `dir`, by component ID
+async fn scan_secret_files(dir: &Path) -> HashMap<String, (Option<SystemTime>, u64)> {
+    let mut files = HashMap::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return files;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let (Some(component_id), Some("yaml")) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            continue;
+        };
+        if let Ok(metadata) = entry.metadata().await {
+            files.insert(
+                component_id.to_string(),
+                (metadata.modified().ok(), metadata.len()),
+            );
+        }
+    }
+    files
+}
+
+/// Polls the secret files in `dir` every `interval` and reports the secrets that changed with
+/// [`Event::SecretChanged`], until the lifecycle manager that owns `cache` is dropped
+async fn watch_secrets(
+    secrets: SecretManager,
+    cache: Weak<SecretCache>,
+    dir: PathBuf,
+    events: EventBus,
+    interval: Duration,
+) {
+    let mut files = scan_secret_files(&dir).await;
+    if let Some(cache) = cache.upgrade() {
+        for component_id in files.keys() {
+            match secrets.provider.get(component_id).await {
+                Ok(loaded) => {
+                    cache.lock().await.insert(component_id.clone(), loaded);
+                }
+                Err(e) => warn!(component_id, error = %e, "Failed to load secrets"),
+            }
+        }
+    }
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(cache) = cache.upgrade() else {
+            break;
+        };
+        let current = scan_secret_files(&dir).await;
+        let changed = current
+            .iter()
+            .filter(|(component_id, file)| files.get(*component_id) != Some(*file))
+            .map(|(component_id, _)| component_id)
+            .chain(files.keys().filter(|id| !current.contains_key(*id)))
+            .cloned()
+            .collect::<Vec<_>>();
+        for component_id in changed {
+            match secrets.reload(&cache, &component_id).await {
+                Ok(changes) => {
+                    for (key, removed) in changes {
+                        info!(component_id, key, removed, "Secret file changed");
+                        events.emit(Event::SecretChanged {
+                            component_id: component_id.clone(),
+                            key,
+                            removed,
+                        });
+                    }
+                }
+                Err(e) => warn!(component_id, error = %e, "Failed to reload secrets"),
+            }
+        }
+        files = current;
+    }
+}
+
+impl crate::LifecycleManager {
+    /// Keeps the secrets of components with `provider` instead of in files in the plugin
+    /// directory. Secrets already kept elsewhere are not moved.
+    pub fn with_secret_provider(mut self, provider: Arc<dyn SecretProvider>) -> Self {
+        self.secrets.provider = provider;
+        self.secrets.cache = None;
+        self
+    }
+
+    /// Keeps the secrets of components with the built-in secret provider called `name`, see
+    /// [`builtin_secret_provider`]
+    pub fn with_builtin_secret_provider(self, name: &str) -> Result<Self> {
+        let provider = builtin_secret_provider(name, &self.plugin_dir)?;
+        Ok(self.with_secret_provider(provider))
+    }
+
+    /// Watches the secrets directory of the plugin directory, checking it every `interval`.
+    /// Secrets are then cached instead of loaded for every call, and changes to the files made
+    /// outside the server are picked up and reported with [`Event::SecretChanged`] like the ones
+    /// made through the server. Only useful with the file provider, so set any other provider
+    /// before.
+    pub fn with_secret_watch(mut self, interval: Duration) -> Self {
+        let cache = Arc::new(SecretCache::default());
+        self.secrets.cache = Some(cache.clone());
+        tokio::spawn(watch_secrets(
+            self.secrets.clone(),
+            Arc::downgrade(&cache),
+            self.plugin_dir.join(SECRETS_DIR),
+            self.events.clone(),
+            interval,
+        ));
+        self
+    }
+
+    /// Sets the secret `key` of a loaded component. The next call of the component sees it as a
+    /// `wasi:config` variable.
+    #[instrument(skip(self, value))]
+    pub async fn set_secret(&self, component_id: &str, key: &str, value: &str) -> Result<()> {
+        self.ensure_secrets_modifiable(component_id).await?;
+        if key.is_empty() {
+            bail!("Secret key cannot be empty");
+        }
+        self.secrets.set(component_id, key, value).await?;
+        info!(component_id, key, "Secret set");
+        self.events.emit(Event::SecretChanged {
+            component_id: component_id.to_string(),
+            key: key.to_string(),
+            removed: false,
+        });
+        Ok(())
+    }
+
+    /// Deletes the secret `key` of a loaded component. Returns whether it had one.
+    #[instrument(skip(self))]
+    pub async fn delete_secret(&self, component_id: &str, key: &str) -> Result<bool> {
+        self.ensure_secrets_modifiable(component_id).await?;
+        let deleted = self.secrets.delete(component_id, key).await?;
+        self.secrets
+            .scopes
+            .update(component_id, |scopes| {
+                scopes.remove(key);
+            })
+            .await?;
+        if deleted {
+            info!(component_id, key, "Secret deleted");
+            self.events.emit(Event::SecretChanged {
+                component_id: component_id.to_string(),
+                key: key.to_string(),
+                removed: true,
+            });
+        }
+        Ok(deleted)
+    }
+
+    /// Imports the variables of the dotenv file at `path` as secrets of a loaded component,
+    /// replacing secrets of the same names. Returns the names of the imported secrets, sorted.
+    #[instrument(skip(self))]
+    pub async fn import_secrets(&self, component_id: &str, path: &Path) -> Result<Vec<String>> {
+        self.ensure_secrets_modifiable(component_id).await?;
+        let keys = self.secrets.import_env_file(component_id, path).await?;
+        info!(component_id, count = keys.len(), "Secrets imported");
+        for key in &keys {
+            self.events.emit(Event::SecretChanged {
+                component_id: component_id.to_string(),
+                key: key.clone(),
+                removed: false,
+            });
+        }
+        Ok(keys)
+    }
+
+    /// Limits the secret `key` of a loaded component to calls of the exported functions in
+    /// `functions`, or gives it to every function again if `functions` is `None`. Other calls run
+    /// as if the component didn't have the secret, including variables of its policy that take
+    /// their values from it.
+    #[instrument(skip(self))]
+    pub async fn set_secret_scope(
+        &self,
+        component_id: &str,
+        key: &str,
+        functions: Option<Vec<String>>,
+    ) -> Result<()> {
+        self.ensure_secrets_modifiable(component_id).await?;
+        if !self
+            .secrets
+            .list(component_id)
+            .await?
+            .iter()
+            .any(|k| k == key)
+        {
+            bail!("Component {component_id} has no secret {key}");
+        }
+        if let Some(functions) = &functions {
+            let exported = self
+                .registry
+                .read()
+                .await
+                .component_function_names(component_id);
+            if let Some(unknown) = functions.iter().find(|f| !exported.contains(*f)) {
+                bail!("Component {component_id} exports no function {unknown}");
+            }
+        }
+        self.secrets
+            .scopes
+            .update(component_id, |scopes| match functions {
+                Some(functions) => {
+                    scopes.insert(key.to_string(), functions.into_iter().collect());
+                }
+                None => {
+                    scopes.remove(key);
+                }
+            })
+            .await?;
+        info!(component_id, key, "Secret scope set");
+        Ok(())
+    }
+
+    /// Returns the exported functions that the secrets of a loaded component are limited to, by
+    /// the name of the secret. Secrets that every function gets are left out.
+    pub async fn secret_scopes(&self, component_id: &str) -> Result<BTreeMap<String, Vec<String>>> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        Ok(self
+            .secrets
+            .scopes
+            .get(component_id)
+            .await?
+            .into_iter()
+            .map(|(key, functions)| (key, functions.into_iter().collect()))
+            .collect())
+    }
+
+    /// Returns the names of the secrets of a loaded component, sorted. Their values are never
+    /// returned.
+    #[instrument(skip(self))]
+    pub async fn list_secret_keys(&self, component_id: &str) -> Result<Vec<String>> {
+        if self.get_component(component_id).await.is_none() {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        self.secrets.list(component_id).await
+    }
+
+    async fn ensure_secrets_modifiable(&self, component_id: &str) -> Result<()> {
+        if !self.components.read().await.contains_key(component_id) {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        self.ensure_modifiable(component_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::COMPONENT;
+    use crate::{DelegationScope, LifecycleManager};
+
+    /// Exports `run`, which does nothing
+    #[tokio::test]
+    async fn test_secrets() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+
+        assert!(manager.list_secret_keys("fetch").await?.is_empty());
+        let mut events = manager.subscribe_events();
+        manager.set_secret("fetch", "API_TOKEN", "s3cr3t").await?;
+        assert_eq!(
+            events.try_recv()?,
+            Event::SecretChanged {
+                component_id: "fetch".to_string(),
+                key: "API_TOKEN".to_string(),
+                removed: false,
+            }
+        );
+        manager.set_secret("fetch", "ACCOUNT", "acme").await?;
+        assert_eq!(
+            manager.list_secret_keys("fetch").await?,
+            ["ACCOUNT", "API_TOKEN"]
+        );
+
+        let path = tempdir.path().join(SECRETS_DIR).join("fetch.yaml");
+        let contents = std::fs::read_to_string(&path)?;
+        assert!(contents.contains("API_TOKEN: s3cr3t"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+
+            // A temp file left behind by a crash is replaced, not written with its permissions
+            let temp_path = path.with_extension("yaml.tmp");
+            std::fs::write(&temp_path, "stale")?;
+            std::fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o644))?;
+            manager.set_secret("fetch", "ACCOUNT", "acme").await?;
+            assert!(!temp_path.exists());
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+
+        assert!(manager.delete_secret("fetch", "API_TOKEN").await?);
+        assert!(!manager.delete_secret("fetch", "API_TOKEN").await?);
+        assert!(manager.delete_secret("fetch", "ACCOUNT").await?);
+        assert!(!path.exists());
+
+        assert!(manager.set_secret("fetch", "", "x").await.is_err());
+        let err = manager.set_secret("missing", "KEY", "x").await.unwrap_err();
+        assert!(err.is::<ComponentNotFound>());
+        assert!(manager.list_secret_keys("missing").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delegated_call_secrets() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        manager.set_secret("fetch", "API_TOKEN", "s3cr3t").await?;
+        manager.set_secret("fetch", "ACCOUNT", "acme").await?;
+        let template = LifecycleManager::create_default_policy_template();
+        let config_vars = |scope: Option<DelegationScope>| {
+            let manager = manager.clone();
+            let template = template.clone();
+            async move {
+                let scope = match scope {
+                    Some(scope) => {
+                        let token = manager.issue_delegation(
+                            &["caller".to_string()],
+                            "fetch",
+                            "run",
+                            scope,
+                        )?;
+                        Some(manager.delegation.validate(&token, "fetch", "run")?.scope)
+                    }
+                    None => None,
+                };
+                anyhow::Ok(
+                    manager
+                        .call_config_vars("fetch", Some("run"), scope.as_ref(), &template, None)
+                        .await?
+                        .unwrap_or_default(),
+                )
+            }
+        };
+
+        assert_eq!(config_vars(None).await?.len(), 2);
+        // A token whose scope names no variables gets no secrets
+        assert!(config_vars(Some(DelegationScope::default()))
+            .await?
+            .is_empty());
+        let scope = DelegationScope {
+            environment: ["ACCOUNT".to_string()].into(),
+            ..DelegationScope::default()
+        };
+        assert_eq!(
+            config_vars(Some(scope)).await?,
+            [("ACCOUNT".to_string(), "acme".to_string())].into()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secret_watch() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir)
+            .await?
+            .with_secret_watch(Duration::from_millis(20));
+        let mut events = manager.subscribe_events();
+
+        // Changes made by the server are reported once
+        manager.set_secret("fetch", "API_TOKEN", "s3cr3t").await?;
+        assert!(matches!(events.recv().await?, Event::SecretChanged { .. }));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(events.try_recv().is_err());
+
+        // Changes to the file are picked up without a call
+        std::fs::write(
+            tempdir.path().join(SECRETS_DIR).join("fetch.yaml"),
+            "ACCOUNT: acme\n",
+        )?;
+        let mut changes = Vec::new();
+        while changes.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await??;
+            if let Event::SecretChanged { key, removed, .. } = event {
+                changes.push((key, removed));
+            }
+        }
+        changes.sort();
+        assert_eq!(
+            changes,
+            [
+                ("ACCOUNT".to_string(), false),
+                ("API_TOKEN".to_string(), true)
+            ]
+        );
+        assert_eq!(manager.list_secret_keys("fetch").await?, ["ACCOUNT"]);
+        assert_eq!(
+            manager
+                .secrets
+                .load("fetch")
+                .await?
+                .get("ACCOUNT")
+                .map(String::as_str),
+            Some("acme")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_secrets() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        manager.set_secret("fetch", "ACCOUNT", "acme").await?;
+        manager.set_secret("fetch", "API_TOKEN", "old").await?;
+
+        let env_file = tempdir.path().join(".env");
+        std::fs::write(
+            &env_file,
+            "API_TOKEN=s3cr3t\nexport CERT=\"line one\nline two\"\n",
+        )?;
+        let mut events = manager.subscribe_events();
+        assert_eq!(
+            manager.import_secrets("fetch", &env_file).await?,
+            ["API_TOKEN", "CERT"]
+        );
+        assert!(matches!(events.try_recv()?, Event::SecretChanged { .. }));
+        let secrets = manager.secrets.load("fetch").await?;
+        assert_eq!(secrets["ACCOUNT"], "acme");
+        assert_eq!(secrets["API_TOKEN"], "s3cr3t");
+        assert_eq!(secrets["CERT"], "line one\nline two");
+
+        std::fs::write(&env_file, "API_TOKEN='unterminated\n")?;
+        assert!(manager.import_secrets("fetch", &env_file).await.is_err());
+        let err = manager
+            .import_secrets("missing", &env_file)
+            .await
+            .unwrap_err();
+        assert!(err.is::<ComponentNotFound>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secret_scopes() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let manager = LifecycleManager::new(&tempdir).await?;
+        manager.set_secret("fetch", "READ_TOKEN", "r").await?;
+        manager.set_secret("fetch", "WRITE_TOKEN", "w").await?;
+
+        assert!(manager
+            .set_secret_scope("fetch", "WRITE_TOKEN", Some(vec!["publish".to_string()]))
+            .await
+            .is_err());
+        assert!(manager
+            .set_secret_scope("fetch", "MISSING", Some(vec!["run".to_string()]))
+            .await
+            .is_err());
+        manager
+            .set_secret_scope("fetch", "WRITE_TOKEN", Some(vec!["run".to_string()]))
+            .await?;
+        assert_eq!(
+            manager.secret_scopes("fetch").await?,
+            BTreeMap::from([("WRITE_TOKEN".to_string(), vec!["run".to_string()])])
+        );
+
+        let keys = |secrets: BTreeMap<String, String>| secrets.into_keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys(manager.secrets.load_for("fetch", Some("run")).await?),
+            ["READ_TOKEN", "WRITE_TOKEN"]
+        );
+        assert_eq!(
+            keys(manager.secrets.load_for("fetch", Some("check")).await?),
+            ["READ_TOKEN"]
+        );
+        assert_eq!(
+            keys(manager.secrets.load_for("fetch", None).await?),
+            ["READ_TOKEN"]
+        );
+
+        // Scopes are kept across restarts and dropped with their secret
+        let manager = LifecycleManager::new(&tempdir).await?;
+        assert_eq!(manager.secret_scopes("fetch").await?.len(), 1);
+        manager.delete_secret("fetch", "WRITE_TOKEN").await?;
+        assert!(manager.secret_scopes("fetch").await?.is_empty());
+        assert!(!tempdir
+            .path()
+            .join(SECRET_SCOPES_DIR)
+            .join("fetch.yaml")
+            .exists());
+        Ok(())
+    }
+
+    /// Keeps secrets in memory
+    #[derive(Default)]
+    struct MemorySecretProvider(std::sync::Mutex<BTreeMap<(String, String), String>>);
+
+    impl SecretProvider for MemorySecretProvider {
+        fn get<'a>(&'a self, component_id: &'a str) -> SecretFuture<'a, BTreeMap<String, String>> {
+            let secrets = self.0.lock().unwrap();
+            let secrets = secrets
+                .iter()
+                .filter(|((id, _), _)| id == component_id)
+                .map(|((_, key), value)| (key.clone(), value.clone()))
+                .collect();
+            Box::pin(async move { Ok(secrets) })
+        }
+
+        fn set<'a>(
+            &'a self,
+            component_id: &'a str,
+            key: &'a str,
+            value: &'a str,
+        ) -> SecretFuture<'a, ()> {
+            self.0.lock().unwrap().insert(
+                (component_id.to_string(), key.to_string()),
+                value.to_string(),
+            );
+            Box::pin(async { Ok(()) })
+        }
+
+        fn delete<'a>(&'a self, component_id: &'a str, key: &'a str) -> SecretFuture<'a, bool> {
+            let removed = self
+                .0
+                .lock()
+                .unwrap()
+                .remove(&(component_id.to_string(), key.to_string()))
+                .is_some();
+            Box::pin(async move { Ok(removed) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_provider() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let provider = Arc::new(MemorySecretProvider::default());
+        let manager = LifecycleManager::new(&tempdir)
+            .await?
+            .with_secret_provider(provider.clone());
+
+        manager.set_secret("fetch", "API_TOKEN", "s3cr3t").await?;
+        manager.set_secret("fetch", "ACCOUNT", "acme").await?;
+        assert_eq!(
+            manager.list_secret_keys("fetch").await?,
+            ["ACCOUNT", "API_TOKEN"]
+        );
+        assert_eq!(
+            provider
+                .get("fetch")
+                .await?
+                .get("API_TOKEN")
+                .map(String::as_str),
+            Some("s3cr3t")
+        );
+        assert!(!tempdir.path().join(SECRETS_DIR).exists());
+
+        manager.secrets.delete_component("fetch").await?;
+        assert!(provider.get("fetch").await?.is_empty());
+
+        assert!(builtin_secret_provider(FILE_SECRET_PROVIDER, tempdir.path()).is_ok());
+        assert!(builtin_secret_provider(KEYCHAIN_SECRET_PROVIDER, tempdir.path()).is_ok());
+        assert!(builtin_secret_provider("vault", tempdir.path()).is_err());
+        Ok(())
+    }
+}
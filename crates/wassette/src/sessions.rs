@@ -0,0 +1,279 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Per-session views of the loaded components
+//!
+//! A server with several clients can give each of them its own view of the lifecycle manager
+//! with [`LifecycleManager::for_session`]. Components loaded through a session view belong to
+//! that session: only its view lists them, resolves their tools and calls them, and only it can
+//! unload them or change their permissions and configuration. The components loaded by the base
+//! manager, from the plugin directory at startup or by the CLI, stay shared by every session,
+//! which can use them but not change them. When the last clone of a session view is dropped, the
+//! components of the session are unloaded.
+//!
+//! Session components live in the plugin directory like any other, with a `<id>.session` marker
+//! next to them. A server that stops without ending its sessions moves the components with a
+//! marker to the trash on the next start, so they never become shared.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, instrument, warn};
+
+use crate::{ComponentNotFound, LifecycleManager};
+
+/// File extension of the markers of session components in the plugin directory
+const SESSION_MARKER_EXTENSION: &str = "session";
+
+/// Components owned by a session, by ID, with the ID of their session
+pub(crate) type SessionOwners = HashMap<String, String>;
+
+/// The session of a session view. Ends the session when the last view is dropped.
+pub(crate) struct ClientSession {
+    id: String,
+    base: LifecycleManager,
+}
+
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(session = %self.id, "No runtime to unload the components of the session");
+            return;
+        };
+        let base = self.base.clone();
+        let id = std::mem::take(&mut self.id);
+        runtime.spawn(async move {
+            if let Err(e) = base.end_session(&id).await {
+                warn!(session = %id, error = %e, "Failed to unload the components of the session");
+            }
+        });
+    }
+}
+
+impl LifecycleManager {
+    /// A view of the manager for the client session `session_id`, see the [module
+    /// documentation](self). Clones of the view belong to the same session.
+    pub fn for_session(&self, session_id: impl Into<String>) -> Self {
+        let base = Self {
+            client_session: None,
+            ..self.clone()
+        };
+        Self {
+            client_session: Some(Arc::new(ClientSession {
+                id: session_id.into(),
+                base: base.clone(),
+            })),
+            ..base
+        }
+    }
+
+    /// The client session of this view, or `None` for the base manager
+    pub fn session(&self) -> Option<&str> {
+        self.client_session
+            .as_ref()
+            .map(|session| session.id.as_str())
+    }
+
+    /// Whether this view sees the component owned by `owner`, or a shared one if `None`
+    pub(crate) fn sees(&self, owner: Option<&String>) -> bool {
+        owner.is_none_or(|owner| Some(owner.as_str()) == self.session())
+    }
+
+    /// Whether this view sees the component `component_id`
+    pub(crate) async fn is_visible(&self, component_id: &str) -> bool {
+        self.sees(self.session_owners.read().await.get(component_id))
+    }
+
+    /// Fails unless this view may unload the loaded component `component_id` or change its
+    /// permissions or configuration
+    pub(crate) async fn ensure_modifiable(&self, component_id: &str) -> Result<()> {
+        let owners = self.session_owners.read().await;
+        let owner = owners.get(component_id);
+        if !self.sees(owner) {
+            return Err(ComponentNotFound::Component(component_id.to_string()).into());
+        }
+        if owner.is_none() && self.session().is_some() {
+            bail!("Component {component_id} is shared by all sessions and can't be changed from one of them");
+        }
+        Ok(())
+    }
+
+    /// Fails if loading `component_id` through this view would replace a component it may not
+    /// change
+    pub(crate) async fn ensure_loadable(&self, component_id: &str) -> Result<()> {
+        if !self.components.read().await.contains_key(component_id) {
+            return Ok(());
+        }
+        match self.session_owners.read().await.get(component_id) {
+            Some(owner) if Some(owner.as_str()) != self.session() => {
+                bail!("Component {component_id} is already loaded by another session")
+            }
+            None if self.session().is_some() => {
+                bail!("Component {component_id} is shared by all sessions and can't be replaced from one of them")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes a component that was just loaded through this view belong to its session
+    pub(crate) async fn claim_component(&self, component_id: &str) -> Result<()> {
+        let Some(session) = self.session() else {
+            return Ok(());
+        };
+        tokio::fs::write(self.session_marker_path(component_id), session)
+            .await
+            .context("Failed to write session marker")?;
+        self.session_owners
+            .write()
+            .await
+            .insert(component_id.to_string(), session.to_string());
+        Ok(())
+    }
+
+    /// Forgets the session of a component that was unloaded
+    pub(crate) async fn release_component(&self, component_id: &str) -> Result<()> {
+        if self
+            .session_owners
+            .write()
+            .await
+            .remove(component_id)
+            .is_some()
+        {
+            self.remove_file_if_exists(
+                &self.session_marker_path(component_id),
+                "session marker",
+                component_id,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Unloads the components of the session `session_id`. Returns their IDs.
+    #[instrument(skip(self))]
+    pub async fn end_session(&self, session_id: &str) -> Result<Vec<String>> {
+        let owned = self
+            .session_owners
+            .read()
+            .await
+            .iter()
+            .filter(|(_, owner)| *owner == session_id)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for component_id in &owned {
+            self.remove_component(component_id).await?;
+        }
+        if !owned.is_empty() {
+            info!(components = ?owned, "Unloaded the components of the session");
+        }
+        Ok(owned)
+    }
+
+    /// Moves the components of sessions that a previous run didn't end to the trash. Returns
+    /// their IDs.
+    pub(crate) async fn discard_stale_session_components(&self) -> Result<Vec<String>> {
+        let mut discarded = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.plugin_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SESSION_MARKER_EXTENSION) {
+                continue;
+            }
+            let Some(component_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let component_id = component_id.to_string();
+            self.remove_component(&component_id).await?;
+            self.remove_file_if_exists(&path, "session marker", &component_id)
+                .await?;
+            discarded.push(component_id);
+        }
+        Ok(discarded)
+    }
+
+    fn session_marker_path(&self, component_id: &str) -> std::path::PathBuf {
+        self.plugin_dir
+            .join(format!("{component_id}.{SESSION_MARKER_EXTENSION}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::COMPONENT;
+
+    async fn write_component(dir: &std::path::Path, id: &str) -> Result<String> {
+        let path = dir.join(format!("{id}.wasm"));
+        tokio::fs::write(&path, COMPONENT).await?;
+        Ok(format!("file://{}", path.display()))
+    }
+
+    #[tokio::test]
+    async fn test_session_views() -> Result<()> {
+        let plugin_dir = tempfile::tempdir()?;
+        write_component(plugin_dir.path(), "shared").await?;
+        let sources = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&plugin_dir).await?;
+
+        let alice = manager.for_session("alice");
+        let bob = manager.for_session("bob");
+        alice
+            .load_component(&write_component(sources.path(), "experiment").await?)
+            .await?;
+
+        assert_eq!(alice.session(), Some("alice"));
+        let mut seen = alice.list_components().await;
+        seen.sort();
+        assert_eq!(seen, vec!["experiment", "shared"]);
+        assert_eq!(bob.list_components().await, vec!["shared"]);
+        assert_eq!(manager.list_components().await, vec!["shared"]);
+        assert!(bob.get_component("experiment").await.is_none());
+        assert_eq!(alice.list_tools().await.len(), 2);
+        assert_eq!(bob.list_tools().await.len(), 1);
+        assert!(plugin_dir.path().join("experiment.session").exists());
+
+        // Components of other sessions can't be replaced, and shared ones can't be changed from
+        // a session
+        let err = bob
+            .load_component(&write_component(sources.path(), "experiment").await?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("another session"), "{err}");
+        assert!(bob.unload_component("experiment").await.is_err());
+        let err = alice.unload_component("shared").await.unwrap_err();
+        assert!(err.to_string().contains("shared by all sessions"), "{err}");
+
+        // The session's components are unloaded when its last view is gone
+        let view = alice.clone();
+        drop(alice);
+        assert_eq!(view.list_components().await.len(), 2);
+        drop(view);
+        for _ in 0..100 {
+            if manager.session_owners.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(manager.session_owners.read().await.is_empty());
+        assert!(!plugin_dir.path().join("experiment.session").exists());
+        assert_eq!(bob.list_components().await, vec!["shared"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_discard_stale_session_components() -> Result<()> {
+        let plugin_dir = tempfile::tempdir()?;
+        write_component(plugin_dir.path(), "shared").await?;
+        write_component(plugin_dir.path(), "leftover").await?;
+        tokio::fs::write(plugin_dir.path().join("leftover.session"), "old").await?;
+
+        let manager = LifecycleManager::new(&plugin_dir).await?;
+        assert_eq!(manager.list_components().await, vec!["shared"]);
+        assert!(!plugin_dir.path().join("leftover.session").exists());
+        let trashed = manager.list_trashed_components().await?;
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].component_id, "leftover");
+        Ok(())
+    }
+}
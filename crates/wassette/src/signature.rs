@@ -0,0 +1,324 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Verification of cosign signatures of OCI components
+//!
+//! `cosign sign --key` stores the signatures of an artifact as an OCI image tagged
+//! `sha256-<hex>.sig` in the repository of the artifact. Each layer of that image is a simple
+//! signing payload that names the digest of the signed manifest, with the base64 ECDSA P-256
+//! signature of the payload in its `dev.cosignproject.cosign/signature` annotation. Keyless
+//! signatures also carry the Fulcio certificate of the signer, see [`crate::keyless`]. Component
+//! files from other sources can be checked against detached signatures, see [`crate::detached`].
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use oci_client::secrets::RegistryAuth;
+use oci_client::Reference;
+use p256::ecdsa::signature::Verifier as _;
+use p256::ecdsa::VerifyingKey;
+use p256::pkcs8::DecodePublicKey as _;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::bundle::sha256_hex;
+use crate::detached::DetachedSignature;
+use crate::keyless::{decode_signature, CertificateIdentity, KeylessTrust};
+use crate::trusted_root::TrustedRoot;
+
+/// Media type of the layers of a cosign signature image
+pub const SIMPLE_SIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// Annotation of a signature layer that holds the base64 signature of the payload
+pub const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// `critical.type` of a simple signing payload
+pub(crate) const SIMPLE_SIGNING_TYPE: &str = "cosign container image signature";
+
+#[derive(Deserialize)]
+struct SimpleSigning {
+    critical: Critical,
+}
+
+#[derive(Deserialize)]
+struct Critical {
+    image: SignedImage,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct SignedImage {
+    #[serde(rename = "docker-manifest-digest")]
+    digest: String,
+}
+
+/// Checks that OCI artifacts are signed with a cosign public key, or keylessly by a certain
+/// identity
+#[derive(Debug, Clone)]
+pub struct SignatureVerifier {
+    trust: Trust,
+}
+
+#[derive(Debug, Clone)]
+enum Trust {
+    Key(VerifyingKey),
+    Keyless(Box<KeylessTrust>),
+}
+
+impl SignatureVerifier {
+    /// Creates a verifier from a PEM encoded ECDSA P-256 public key, as written by
+    /// `cosign generate-key-pair`
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let key = VerifyingKey::from_public_key_pem(pem.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid cosign public key: {e}"))?;
+        Ok(Self {
+            trust: Trust::Key(key),
+        })
+    }
+
+    /// Creates a verifier of keyless signatures whose certificates chain to one of the PEM
+    /// Fulcio root certificates in `fulcio_roots` and have the given identity. It rejects every
+    /// signature until a Rekor key is added with [`Self::with_rekor_key`].
+    pub fn keyless(fulcio_roots: &str, identity: CertificateIdentity) -> Result<Self> {
+        Ok(Self {
+            trust: Trust::Keyless(Box::new(KeylessTrust::new(fulcio_roots, identity)?)),
+        })
+    }
+
+    /// Creates a verifier of keyless signatures whose certificates chain to a certificate
+    /// authority of `root` and have the given identity, and that were recorded by one of its
+    /// Rekor logs
+    pub fn keyless_with_trusted_root(
+        root: &TrustedRoot,
+        identity: CertificateIdentity,
+    ) -> Result<Self> {
+        Ok(Self {
+            trust: Trust::Keyless(Box::new(KeylessTrust::from_trusted_root(root, identity)?)),
+        })
+    }
+
+    /// Trusts the signed entry timestamps of keyless signatures made with the PEM public key of
+    /// the Rekor transparency log
+    pub fn with_rekor_key(self, pem: &str) -> Result<Self> {
+        match self.trust {
+            Trust::Keyless(trust) => Ok(Self {
+                trust: Trust::Keyless(Box::new(trust.with_rekor_key(pem)?)),
+            }),
+            Trust::Key(_) => bail!("A Rekor key only applies to keyless verification"),
+        }
+    }
+
+    /// Reads the public key from a `file://` URI or a path
+    pub async fn from_file(uri: &str) -> Result<Self> {
+        let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+        let pem = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read public key {}", path.display()))?;
+        Self::from_pem(&pem)
+    }
+
+    /// Hex SHA-256 of the keys, roots and identity the verifier trusts, which identifies what a
+    /// signature was checked against
+    pub(crate) fn fingerprint(&self) -> Result<String> {
+        let material = match &self.trust {
+            Trust::Key(key) => [b"key:", key.to_encoded_point(true).as_bytes()].concat(),
+            Trust::Keyless(trust) => [b"keyless:".as_slice(), &trust.trust_material()?].concat(),
+        };
+        Ok(sha256_hex(&material))
+    }
+
+    /// Tag of the signature image of the manifest with the given digest
+    pub fn signature_tag(digest: &str) -> Result<String> {
+        match digest.split_once(':') {
+            Some((algorithm, hex)) if !hex.is_empty() => Ok(format!("{algorithm}-{hex}.sig")),
+            _ => bail!("Invalid manifest digest: {digest}"),
+        }
+    }
+
+    /// Checks that `signature` is a valid base64 signature of `payload` and that the payload
+    /// signs the manifest with the given digest
+    pub fn verify_payload(&self, payload: &[u8], signature: &str, digest: &str) -> Result<()> {
+        let annotations =
+            BTreeMap::from([(SIGNATURE_ANNOTATION.to_string(), signature.to_string())]);
+        self.verify_layer(payload, &annotations, digest)
+    }
+
+    /// Checks the payload of a signature layer with the given annotations, which hold the
+    /// signature and, for keyless signatures, the certificate and Rekor bundle
+    pub fn verify_layer(
+        &self,
+        payload: &[u8],
+        annotations: &BTreeMap<String, String>,
+        digest: &str,
+    ) -> Result<()> {
+        self.verify_signature(payload, annotations)?;
+        let payload: SimpleSigning =
+            serde_json::from_slice(payload).context("Invalid simple signing payload")?;
+        if payload.critical.kind != SIMPLE_SIGNING_TYPE {
+            bail!("Unexpected signature type: {}", payload.critical.kind);
+        }
+        if payload.critical.image.digest != digest {
+            bail!(
+                "Signature is for {}, not {}",
+                payload.critical.image.digest,
+                digest
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that `signature` is a valid detached signature of `blob`, such as the file of a
+    /// component
+    pub fn verify_blob(&self, blob: &[u8], signature: &DetachedSignature) -> Result<()> {
+        self.verify_signature(blob, &signature.annotations())
+    }
+
+    fn verify_signature(&self, data: &[u8], annotations: &BTreeMap<String, String>) -> Result<()> {
+        let signature = annotations
+            .get(SIGNATURE_ANNOTATION)
+            .context("Signature layer has no signature")?;
+        match &self.trust {
+            Trust::Key(key) => key
+                .verify(data, &decode_signature(signature)?)
+                .map_err(|_| anyhow::anyhow!("Signature doesn't match the public key")),
+            Trust::Keyless(trust) => trust.verify(data, signature, annotations),
+        }
+    }
+
+    /// Checks that the manifest of `reference` with the given digest has a valid signature in
+    /// the registry
+    pub async fn verify(
+        &self,
+        oci_client: &oci_client::Client,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        let signature_ref = Reference::with_tag(
+            reference.registry().to_string(),
+            reference.repository().to_string(),
+            Self::signature_tag(digest)?,
+        );
+        let (manifest, _) = oci_client
+            .pull_image_manifest(&signature_ref, &RegistryAuth::Anonymous)
+            .await
+            .with_context(|| {
+                format!("No signature found for {}@{digest}", reference.repository())
+            })?;
+
+        let mut last_error = None;
+        for layer in &manifest.layers {
+            let Some(annotations) = layer
+                .annotations
+                .as_ref()
+                .filter(|annotations| annotations.contains_key(SIGNATURE_ANNOTATION))
+            else {
+                continue;
+            };
+            if layer.media_type != SIMPLE_SIGNING_MEDIA_TYPE {
+                continue;
+            }
+            let mut payload = Vec::new();
+            oci_client
+                .pull_blob(&signature_ref, layer, &mut payload)
+                .await
+                .context("Failed to download signature payload")?;
+            match self.verify_layer(&payload, annotations, digest) {
+                Ok(()) => {
+                    debug!(
+                        repository = reference.repository(),
+                        digest, "Signature verified"
+                    );
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e.context(format!(
+                "No valid signature for {}@{digest}",
+                reference.repository()
+            ))),
+            None => bail!(
+                "No cosign signature found for {}@{digest}",
+                reference.repository()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine as _;
+    use p256::ecdsa::signature::Signer as _;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::{EncodePublicKey as _, LineEnding};
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:6a0c1a0b7c3b1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7u8; 32]).unwrap()
+    }
+
+    fn public_key_pem() -> String {
+        signing_key()
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap()
+    }
+
+    fn simple_signing(digest: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "critical": {
+                "identity": {"docker-reference": "ghcr.io/microsoft/fetch-rs"},
+                "image": {"docker-manifest-digest": digest},
+                "type": SIMPLE_SIGNING_TYPE,
+            },
+            "optional": null,
+        }))
+        .unwrap()
+    }
+
+    fn sign(payload: &[u8]) -> String {
+        let signature: Signature = signing_key().sign(payload);
+        base64::engine::general_purpose::STANDARD.encode(signature.to_der())
+    }
+
+    #[test]
+    fn test_verify_payload() {
+        let verifier = SignatureVerifier::from_pem(&public_key_pem()).unwrap();
+        let payload = simple_signing(DIGEST);
+        verifier
+            .verify_payload(&payload, &sign(&payload), DIGEST)
+            .unwrap();
+
+        // Signed for another artifact
+        let other = simple_signing(&format!("sha256:{}", "0".repeat(64)));
+        let err = verifier
+            .verify_payload(&other, &sign(&other), DIGEST)
+            .unwrap_err();
+        assert!(err.to_string().contains("not sha256:6a0c"), "{err}");
+
+        // Signed with another key
+        let other_key = SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let signature: Signature = other_key.sign(&payload);
+        let signature = base64::engine::general_purpose::STANDARD.encode(signature.to_der());
+        assert!(verifier
+            .verify_payload(&payload, &signature, DIGEST)
+            .is_err());
+
+        assert!(SignatureVerifier::from_pem("not a key").is_err());
+    }
+
+    #[test]
+    fn test_signature_tag() {
+        assert_eq!(
+            SignatureVerifier::signature_tag("sha256:abc").unwrap(),
+            "sha256-abc.sig"
+        );
+        assert!(SignatureVerifier::signature_tag("abc").is_err());
+    }
+}
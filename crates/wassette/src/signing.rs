@@ -0,0 +1,693 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Cosign signing of OCI components, for component authors
+//!
+//! [`ComponentSigner::sign`] writes the signature of a pushed component the way `cosign sign`
+//! does: a simple signing payload that names the digest of the component's manifest, stored as a
+//! layer of the `sha256-<hex>.sig` image in the component's repository with its signature in an
+//! annotation. That is the layout [`crate::SignatureVerifier`] checks when a component is loaded.
+//!
+//! A signer holds either a key from `cosign generate-key-pair`, or, in GitHub Actions, an
+//! ephemeral key certified by Fulcio for the identity of the workflow (keyless signing). Keyless
+//! signatures are always recorded in the Rekor transparency log, and key-based ones can be.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::XSalsa20Poly1305;
+use oci_client::manifest::{
+    OciDescriptor, OciImageManifest, OciManifest, IMAGE_CONFIG_MEDIA_TYPE, OCI_IMAGE_MEDIA_TYPE,
+};
+use oci_client::secrets::RegistryAuth;
+use oci_client::Reference;
+use p256::ecdsa::signature::Signer as _;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::pkcs8::{DecodePrivateKey as _, EncodePublicKey as _, LineEnding};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info, instrument};
+
+use crate::bundle::sha256_hex;
+use crate::signature::{
+    SignatureVerifier, SIGNATURE_ANNOTATION, SIMPLE_SIGNING_MEDIA_TYPE, SIMPLE_SIGNING_TYPE,
+};
+
+/// Annotation of a keyless signature layer that holds the PEM signing certificate
+pub const CERTIFICATE_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+
+/// Annotation of a keyless signature layer that holds the PEM certificate chain of the issuer
+pub const CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+
+/// Annotation of a signature layer that holds the Rekor bundle of its transparency log entry
+pub const BUNDLE_ANNOTATION: &str = "dev.sigstore.cosign/bundle";
+
+/// The public Sigstore certificate authority
+pub const DEFAULT_FULCIO_URL: &str = "https://fulcio.sigstore.dev";
+
+/// The public Sigstore transparency log
+pub const DEFAULT_REKOR_URL: &str = "https://rekor.sigstore.dev";
+
+/// Scheme of a base64 standard encoding, as cosign writes it
+const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+/// Labels of the PEM blocks of the encrypted private keys written by cosign
+const ENCRYPTED_KEY_LABELS: [&str; 2] = [
+    "ENCRYPTED SIGSTORE PRIVATE KEY",
+    "ENCRYPTED COSIGN PRIVATE KEY",
+];
+
+/// An encrypted private key written by `cosign generate-key-pair`
+#[derive(Deserialize)]
+struct EncryptedKey {
+    kdf: Kdf,
+    cipher: Cipher,
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct Kdf {
+    name: String,
+    params: ScryptParams,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+struct ScryptParams {
+    #[serde(rename = "N")]
+    n: u64,
+    r: u32,
+    p: u32,
+}
+
+#[derive(Deserialize)]
+struct Cipher {
+    name: String,
+    nonce: String,
+}
+
+/// Where keyless signing gets its certificate and records its signatures
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeylessConfig {
+    /// URL of the Fulcio certificate authority
+    pub fulcio_url: String,
+    /// URL of the Rekor transparency log
+    pub rekor_url: String,
+}
+
+impl Default for KeylessConfig {
+    fn default() -> Self {
+        Self {
+            fulcio_url: DEFAULT_FULCIO_URL.to_string(),
+            rekor_url: DEFAULT_REKOR_URL.to_string(),
+        }
+    }
+}
+
+/// A signing certificate issued by Fulcio
+#[derive(Debug, Clone)]
+struct Certificate {
+    /// The PEM signing certificate
+    leaf: String,
+    /// The PEM certificates of the issuer
+    chain: String,
+}
+
+/// The signature written by [`ComponentSigner::sign`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedArtifact {
+    /// Reference of the signed component
+    pub reference: String,
+    /// Digest of the signed manifest
+    pub digest: String,
+    /// Reference of the signature image
+    pub signature: String,
+    /// Whether the signature was made with an ephemeral key certified by Fulcio
+    pub keyless: bool,
+    /// Index of the signature in the Rekor transparency log, if it was recorded there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<u64>,
+}
+
+/// Signs OCI components with a cosign key or keylessly
+#[derive(Debug, Clone)]
+pub struct ComponentSigner {
+    key: SigningKey,
+    certificate: Option<Certificate>,
+    rekor_url: Option<String>,
+    http_client: reqwest::Client,
+}
+
+impl ComponentSigner {
+    /// Creates a signer from a PEM private key: an encrypted key written by
+    /// `cosign generate-key-pair`, decrypted with `password`, or an unencrypted PKCS#8 or SEC1
+    /// ECDSA P-256 key
+    pub fn from_pem(pem: &str, password: &[u8]) -> Result<Self> {
+        let (label, der) = decode_pem(pem)?;
+        let key = if ENCRYPTED_KEY_LABELS.contains(&label.as_str()) {
+            let encrypted: EncryptedKey =
+                serde_json::from_slice(&der).context("Invalid encrypted cosign key")?;
+            let der = decrypt_key(&encrypted, password)?;
+            SigningKey::from_pkcs8_der(&der)
+                .map_err(|e| anyhow::anyhow!("Invalid cosign private key: {e}"))?
+        } else if label == "PRIVATE KEY" {
+            SigningKey::from_pkcs8_der(&der)
+                .map_err(|e| anyhow::anyhow!("Invalid PKCS#8 private key: {e}"))?
+        } else if label == "EC PRIVATE KEY" {
+            p256::SecretKey::from_sec1_der(&der)
+                .map_err(|e| anyhow::anyhow!("Invalid EC private key: {e}"))?
+                .into()
+        } else {
+            bail!("Unsupported private key type: {label}");
+        };
+        Ok(Self::with_key(key))
+    }
+
+    /// Reads the private key from a `file://` URI or a path
+    pub async fn from_file(uri: &str, password: &[u8]) -> Result<Self> {
+        let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+        let pem = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read private key {}", path.display()))?;
+        Self::from_pem(&pem, password)
+    }
+
+    /// Creates a keyless signer in GitHub Actions: the workflow's OIDC token is exchanged with
+    /// Fulcio for a short-lived certificate of an ephemeral key. The workflow needs the
+    /// `id-token: write` permission.
+    #[instrument(skip_all)]
+    pub async fn keyless(config: &KeylessConfig) -> Result<Self> {
+        let http_client = reqwest::Client::new();
+        let token = github_id_token(&http_client).await?;
+        let subject = token_subject(&token)?;
+        let key = SigningKey::random(&mut rand_core::OsRng);
+        let certificate =
+            request_certificate(&http_client, &config.fulcio_url, &key, &token, &subject).await?;
+        info!(subject, "Obtained keyless signing certificate");
+        Ok(Self {
+            key,
+            certificate: Some(certificate),
+            rekor_url: Some(config.rekor_url.clone()),
+            http_client,
+        })
+    }
+
+    fn with_key(key: SigningKey) -> Self {
+        Self {
+            key,
+            certificate: None,
+            rekor_url: None,
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    /// Records key-based signatures in the Rekor transparency log at `rekor_url`
+    pub fn with_transparency_log(mut self, rekor_url: impl Into<String>) -> Self {
+        self.rekor_url = Some(rekor_url.into());
+        self
+    }
+
+    /// The PEM public key of the signer, to verify its signatures with
+    pub fn public_key_pem(&self) -> Result<String> {
+        self.key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("Failed to encode public key: {e}"))
+    }
+
+    /// A verifier of the signatures of this signer
+    pub fn verifier(&self) -> Result<SignatureVerifier> {
+        SignatureVerifier::from_pem(&self.public_key_pem()?)
+    }
+
+    /// The base64 DER signature of `payload`
+    pub fn sign_payload(&self, payload: &[u8]) -> String {
+        let signature: Signature = self.key.sign(payload);
+        BASE64.encode(signature.to_der())
+    }
+
+    /// Signs the manifest of `reference` and pushes the signature next to it. When the
+    /// reference has no digest, the manifest its tag points at is signed. Signatures already in
+    /// the signature image are kept.
+    #[instrument(skip(self, oci_client, auth), fields(reference = %reference))]
+    pub async fn sign(
+        &self,
+        oci_client: &oci_client::Client,
+        reference: &Reference,
+        auth: &RegistryAuth,
+    ) -> Result<SignedArtifact> {
+        oci_client
+            .store_auth_if_needed(reference.resolve_registry(), auth)
+            .await;
+        let digest = match reference.digest() {
+            Some(digest) => digest.to_string(),
+            None => oci_client
+                .fetch_manifest_digest(reference, auth)
+                .await
+                .with_context(|| format!("Failed to resolve {reference}"))?,
+        };
+        let repository = format!("{}/{}", reference.registry(), reference.repository());
+        let payload = simple_signing_payload(&repository, &digest);
+        let signature = self.sign_payload(&payload);
+
+        let mut annotations = BTreeMap::from([(SIGNATURE_ANNOTATION.to_string(), signature)]);
+        if let Some(certificate) = &self.certificate {
+            annotations.insert(CERTIFICATE_ANNOTATION.to_string(), certificate.leaf.clone());
+            annotations.insert(CHAIN_ANNOTATION.to_string(), certificate.chain.clone());
+        }
+        let mut log_index = None;
+        if let Some(rekor_url) = &self.rekor_url {
+            let bundle = self
+                .upload_to_rekor(rekor_url, &payload, &annotations[SIGNATURE_ANNOTATION])
+                .await?;
+            log_index = bundle["Payload"]["logIndex"].as_u64();
+            annotations.insert(BUNDLE_ANNOTATION.to_string(), bundle.to_string());
+        }
+
+        let signature_ref = Reference::with_tag(
+            reference.registry().to_string(),
+            reference.repository().to_string(),
+            SignatureVerifier::signature_tag(&digest)?,
+        );
+        let layer = OciDescriptor {
+            media_type: SIMPLE_SIGNING_MEDIA_TYPE.to_string(),
+            digest: sha256_digest(&payload),
+            size: payload.len() as i64,
+            urls: None,
+            annotations: Some(annotations),
+        };
+        let mut layers = match oci_client.pull_image_manifest(&signature_ref, auth).await {
+            Ok((existing, _)) => existing.layers,
+            Err(e) => {
+                debug!(error = %e, "No existing signature image");
+                Vec::new()
+            }
+        };
+        // ECDSA signatures are deterministic, so signing the same manifest with the same key
+        // again gives a layer that is already there
+        if !layers.iter().any(|existing| {
+            existing.digest == layer.digest && existing.annotations == layer.annotations
+        }) {
+            layers.push(layer);
+        }
+
+        oci_client
+            .push_blob(&signature_ref, &payload, &sha256_digest(&payload))
+            .await
+            .context("Failed to push signature payload")?;
+        let config = signature_config(&layers);
+        let config_digest = sha256_digest(&config);
+        oci_client
+            .push_blob(&signature_ref, &config, &config_digest)
+            .await
+            .context("Failed to push signature config")?;
+        let manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: Some(OCI_IMAGE_MEDIA_TYPE.to_string()),
+            config: OciDescriptor {
+                media_type: IMAGE_CONFIG_MEDIA_TYPE.to_string(),
+                digest: config_digest,
+                size: config.len() as i64,
+                urls: None,
+                annotations: None,
+            },
+            layers,
+            subject: None,
+            artifact_type: None,
+            annotations: None,
+        };
+        oci_client
+            .push_manifest(&signature_ref, &OciManifest::Image(manifest))
+            .await
+            .context("Failed to push signature image")?;
+
+        info!(digest, signature = %signature_ref, "Signed component");
+        Ok(SignedArtifact {
+            reference: reference.whole(),
+            digest,
+            signature: signature_ref.whole(),
+            keyless: self.certificate.is_some(),
+            log_index,
+        })
+    }
+
+    /// Records the signature of `payload` in Rekor and returns the bundle cosign attaches to the
+    /// signature layer
+    async fn upload_to_rekor(
+        &self,
+        rekor_url: &str,
+        payload: &[u8],
+        signature: &str,
+    ) -> Result<Value> {
+        let public_key = match &self.certificate {
+            Some(certificate) => certificate.leaf.clone(),
+            None => self.public_key_pem()?,
+        };
+        let entry = json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": {"hash": {"algorithm": "sha256", "value": sha256_hex(payload)}},
+                "signature": {
+                    "content": signature,
+                    "publicKey": {"content": BASE64.encode(public_key)},
+                },
+            },
+        });
+        let response = self
+            .http_client
+            .post(format!(
+                "{}/api/v1/log/entries",
+                rekor_url.trim_end_matches('/')
+            ))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(entry.to_string())
+            .send()
+            .await
+            .context("Failed to reach the Rekor transparency log")?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            bail!(
+                "Rekor rejected the signature ({status}): {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+        rekor_bundle(&serde_json::from_slice(&body).context("Invalid Rekor response")?)
+    }
+}
+
+/// Splits a PEM document into the label and the decoded contents of its first block
+fn decode_pem(pem: &str) -> Result<(String, Vec<u8>)> {
+    let mut lines = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| !line.starts_with("-----BEGIN "));
+    let label = lines
+        .next()
+        .and_then(|line| line.strip_prefix("-----BEGIN "))
+        .and_then(|line| line.strip_suffix("-----"))
+        .context("Not a PEM private key")?
+        .to_string();
+    let body: String = lines
+        .take_while(|line| !line.starts_with("-----END "))
+        .collect();
+    let der = BASE64
+        .decode(body)
+        .context("Private key is not valid base64")?;
+    Ok((label, der))
+}
+
+/// Decrypts the PKCS#8 key of an encrypted cosign key
+fn decrypt_key(encrypted: &EncryptedKey, password: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.kdf.name != "scrypt" || encrypted.cipher.name != "nacl/secretbox" {
+        bail!(
+            "Unsupported cosign key encryption: {} with {}",
+            encrypted.kdf.name,
+            encrypted.cipher.name
+        );
+    }
+    let params = &encrypted.kdf.params;
+    if !params.n.is_power_of_two() {
+        bail!("Invalid scrypt cost in cosign key: {}", params.n);
+    }
+    let params = scrypt::Params::new(params.n.trailing_zeros() as u8, params.r, params.p, 32)
+        .map_err(|e| anyhow::anyhow!("Invalid scrypt parameters in cosign key: {e}"))?;
+    let salt = BASE64.decode(&encrypted.kdf.salt)?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password, &salt, &params, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {e}"))?;
+
+    let nonce = BASE64.decode(&encrypted.cipher.nonce)?;
+    if nonce.len() != 24 {
+        bail!("Invalid nonce in cosign key");
+    }
+    let ciphertext = BASE64.decode(&encrypted.ciphertext)?;
+    XSalsa20Poly1305::new(&key.into())
+        .decrypt(nonce.as_slice().into(), ciphertext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Wrong password for cosign key"))
+}
+
+/// The simple signing payload cosign signs for the manifest with `digest` in `repository`
+pub(crate) fn simple_signing_payload(repository: &str, digest: &str) -> Vec<u8> {
+    json!({
+        "critical": {
+            "identity": {"docker-reference": repository},
+            "image": {"docker-manifest-digest": digest},
+            "type": SIMPLE_SIGNING_TYPE,
+        },
+        "optional": null,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// The image config of a signature image with the given layers, as cosign writes it
+fn signature_config(layers: &[OciDescriptor]) -> Vec<u8> {
+    let diff_ids: Vec<&str> = layers.iter().map(|layer| layer.digest.as_str()).collect();
+    json!({
+        "architecture": "",
+        "created": "0001-01-01T00:00:00Z",
+        "history": [{"created": "0001-01-01T00:00:00Z"}],
+        "os": "",
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+        "config": {},
+    })
+    .to_string()
+    .into_bytes()
+}
+
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{}", sha256_hex(data))
+}
+
+/// Fetches the OIDC token of the running GitHub Actions job for the `sigstore` audience
+async fn github_id_token(http_client: &reqwest::Client) -> Result<String> {
+    let (Ok(url), Ok(token)) = (
+        std::env::var("ACTIONS_ID_TOKEN_REQUEST_URL"),
+        std::env::var("ACTIONS_ID_TOKEN_REQUEST_TOKEN"),
+    ) else {
+        bail!(
+            "Keyless signing needs the OIDC token of a GitHub Actions job; \
+             grant the workflow the id-token: write permission"
+        );
+    };
+    let response = http_client
+        .get(format!("{url}&audience=sigstore"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .context("Failed to request GitHub OIDC token")?
+        .error_for_status()
+        .context("GitHub refused to issue an OIDC token")?;
+    let body: Value = serde_json::from_slice(&response.bytes().await?)?;
+    body["value"]
+        .as_str()
+        .map(str::to_string)
+        .context("GitHub OIDC response has no token")
+}
+
+/// The `sub` claim of a JWT, which Fulcio expects the proof of possession to sign
+fn token_subject(token: &str) -> Result<String> {
+    let claims = token.split('.').nth(1).context("OIDC token is not a JWT")?;
+    let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(claims.trim_end_matches('='))
+        .context("OIDC token claims are not valid base64")?;
+    let claims: Value = serde_json::from_slice(&claims).context("Invalid OIDC token claims")?;
+    claims["sub"]
+        .as_str()
+        .map(str::to_string)
+        .context("OIDC token has no subject")
+}
+
+/// Asks Fulcio to certify `key` for the identity of `token`
+async fn request_certificate(
+    http_client: &reqwest::Client,
+    fulcio_url: &str,
+    key: &SigningKey,
+    token: &str,
+    subject: &str,
+) -> Result<Certificate> {
+    let public_key = key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Failed to encode public key: {e}"))?;
+    let proof: Signature = key.sign(subject.as_bytes());
+    let request = json!({
+        "credentials": {"oidcIdentityToken": token},
+        "publicKeyRequest": {
+            "publicKey": {"algorithm": "ECDSA", "content": public_key},
+            "proofOfPossession": BASE64.encode(proof.to_der()),
+        },
+    });
+    let response = http_client
+        .post(format!(
+            "{}/api/v2/signingCert",
+            fulcio_url.trim_end_matches('/')
+        ))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(request.to_string())
+        .send()
+        .await
+        .context("Failed to reach Fulcio")?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    if !status.is_success() {
+        bail!(
+            "Fulcio refused to issue a certificate ({status}): {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+    fulcio_certificate(&serde_json::from_slice(&body).context("Invalid Fulcio response")?)
+}
+
+/// The certificate chain of a Fulcio `signingCert` response
+fn fulcio_certificate(response: &Value) -> Result<Certificate> {
+    let certificates = [
+        "signedCertificateEmbeddedSct",
+        "signedCertificateDetachedSct",
+    ]
+    .iter()
+    .find_map(|kind| response[kind]["chain"]["certificates"].as_array())
+    .context("Fulcio response has no certificate chain")?;
+    let mut certificates = certificates.iter().filter_map(Value::as_str);
+    let leaf = certificates
+        .next()
+        .context("Fulcio returned an empty certificate chain")?;
+    let chain: Vec<&str> = certificates.collect();
+    Ok(Certificate {
+        leaf: leaf.to_string(),
+        chain: chain.join(""),
+    })
+}
+
+/// The cosign bundle of the single entry of a Rekor `log/entries` response
+fn rekor_bundle(response: &Value) -> Result<Value> {
+    let entry = response
+        .as_object()
+        .and_then(|entries| entries.values().next())
+        .context("Rekor response has no log entry")?;
+    Ok(json!({
+        "SignedEntryTimestamp": entry["verification"]["signedEntryTimestamp"],
+        "Payload": {
+            "body": entry["body"],
+            "integratedTime": entry["integratedTime"],
+            "logIndex": entry["logIndex"],
+            "logID": entry["logID"],
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::pkcs8::EncodePrivateKey as _;
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:6a0c1a0b7c3b1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e";
+
+    fn pem(label: &str, der: &[u8]) -> String {
+        format!(
+            "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+            BASE64.encode(der)
+        )
+    }
+
+    /// Encrypts a key the way `cosign generate-key-pair` does, with a cheap scrypt cost
+    fn encrypted_cosign_key(key: &SigningKey, password: &[u8]) -> String {
+        let der = key.to_pkcs8_der().unwrap();
+        let salt = [3u8; 32];
+        let nonce = [5u8; 24];
+        let mut secret = [0u8; 32];
+        scrypt::scrypt(
+            password,
+            &salt,
+            &scrypt::Params::new(4, 8, 1, 32).unwrap(),
+            &mut secret,
+        )
+        .unwrap();
+        let ciphertext = XSalsa20Poly1305::new(&secret.into())
+            .encrypt(&nonce.into(), der.as_bytes())
+            .unwrap();
+        let encrypted = json!({
+            "kdf": {
+                "name": "scrypt",
+                "params": {"N": 16, "r": 8, "p": 1},
+                "salt": BASE64.encode(salt),
+            },
+            "cipher": {"name": "nacl/secretbox", "nonce": BASE64.encode(nonce)},
+            "ciphertext": BASE64.encode(ciphertext),
+        });
+        pem(
+            "ENCRYPTED SIGSTORE PRIVATE KEY",
+            encrypted.to_string().as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_signatures_verify() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let signer = ComponentSigner::with_key(key.clone());
+        let payload = simple_signing_payload("ghcr.io/microsoft/fetch-rs", DIGEST);
+        let signature = signer.sign_payload(&payload);
+        signer
+            .verifier()
+            .unwrap()
+            .verify_payload(&payload, &signature, DIGEST)
+            .unwrap();
+
+        let encrypted = encrypted_cosign_key(&key, b"hunter2");
+        let signer = ComponentSigner::from_pem(&encrypted, b"hunter2").unwrap();
+        assert_eq!(signer.sign_payload(&payload), signature);
+        let err = ComponentSigner::from_pem(&encrypted, b"wrong").unwrap_err();
+        assert!(err.to_string().contains("Wrong password"), "{err}");
+
+        let pkcs8 = key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let signer = ComponentSigner::from_pem(&pkcs8, b"").unwrap();
+        assert_eq!(signer.sign_payload(&payload), signature);
+
+        assert!(ComponentSigner::from_pem(&pem("RSA PRIVATE KEY", &[0u8; 8]), b"").is_err());
+    }
+
+    #[test]
+    fn test_token_subject() {
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"sub":"repo:microsoft/wassette:ref:refs/heads/main"}"#);
+        let token = format!("eyJhbGciOiJSUzI1NiJ9.{claims}.c2ln");
+        assert_eq!(
+            token_subject(&token).unwrap(),
+            "repo:microsoft/wassette:ref:refs/heads/main"
+        );
+        assert!(token_subject("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn test_sigstore_responses() {
+        let certificate = fulcio_certificate(&json!({
+            "signedCertificateEmbeddedSct": {
+                "chain": {"certificates": ["LEAF\n", "INTERMEDIATE\n", "ROOT\n"]}
+            }
+        }))
+        .unwrap();
+        assert_eq!(certificate.leaf, "LEAF\n");
+        assert_eq!(certificate.chain, "INTERMEDIATE\nROOT\n");
+        assert!(fulcio_certificate(&json!({})).is_err());
+
+        let bundle = rekor_bundle(&json!({
+            "24296fb24b8ad77a": {
+                "body": "eyJraW5kIjoiaGFzaGVkcmVrb3JkIn0=",
+                "integratedTime": 1700000000,
+                "logID": "c0d23d6ad406973f",
+                "logIndex": 42,
+                "verification": {"signedEntryTimestamp": "MEUCIQ=="}
+            }
+        }))
+        .unwrap();
+        assert_eq!(bundle["SignedEntryTimestamp"], "MEUCIQ==");
+        assert_eq!(bundle["Payload"]["logIndex"], 42);
+        assert_eq!(bundle["Payload"]["integratedTime"], 1700000000);
+    }
+}
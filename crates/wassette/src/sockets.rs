@@ -0,0 +1,470 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Address checks for wasi:sockets
+//!
+//! wasi-sockets asks the host about every address a socket binds to, connects to or sends a
+//! datagram to. This module turns the UDP and TCP grants of a component's policy into that check
+//! so a component can talk to e.g. `pool.ntp.org:123` over UDP or `db.internal:5432` over TCP
+//! without opening sockets to the whole network.
+
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use policy::{NetworkPermission, NetworkTcpPermission, PolicyDocument};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use wasmtime_wasi::SocketAddrUse;
+
+/// The remote endpoint of a socket grant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum SocketTarget {
+    Ip(IpAddr),
+    Cidr { network: IpAddr, prefix_len: u8 },
+    Host(String),
+}
+
+/// A single "this component may talk to this endpoint" grant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SocketGrant {
+    target: SocketTarget,
+    port: Option<u16>,
+}
+
+impl SocketGrant {
+    /// Parses a grant target, which is a hostname, an IP address or a CIDR range
+    pub fn parse(target: &str, port: Option<u16>) -> Result<Self> {
+        let target = target.trim();
+        if target.is_empty() {
+            bail!("Socket grant target cannot be empty");
+        }
+
+        let target = if let Some((network, prefix_len)) = target.split_once('/') {
+            let network: IpAddr = network
+                .parse()
+                .with_context(|| format!("Invalid CIDR network address: {target}"))?;
+            let prefix_len: u8 = prefix_len
+                .parse()
+                .with_context(|| format!("Invalid CIDR prefix length: {target}"))?;
+            let max_len = if network.is_ipv4() { 32 } else { 128 };
+            if prefix_len > max_len {
+                bail!("CIDR prefix length is too long: {target}");
+            }
+            SocketTarget::Cidr {
+                network,
+                prefix_len,
+            }
+        } else if let Ok(ip) = target.trim_matches(['[', ']']).parse::<IpAddr>() {
+            SocketTarget::Ip(ip)
+        } else {
+            SocketTarget::Host(
+                crate::http::canonical_host(target)
+                    .with_context(|| format!("Invalid host in socket grant: {target}"))?,
+            )
+        };
+
+        Ok(Self { target, port })
+    }
+
+    /// Parses a grant that may be written as an endpoint with a port, e.g. `tcp://db.internal:5432`
+    /// or `[fd00::1]:5432`. A port in the endpoint has to agree with `port` if both are given.
+    pub fn parse_endpoint(endpoint: &str, port: Option<u16>) -> Result<Self> {
+        let endpoint = endpoint.trim();
+        let target = endpoint.strip_prefix("tcp://").unwrap_or(endpoint);
+        let (target, endpoint_port) = match target.rsplit_once(':') {
+            // Bare IPv6 addresses contain colons but no port
+            Some((host, endpoint_port)) if !host.contains(':') || host.ends_with(']') => {
+                let endpoint_port: u16 = endpoint_port
+                    .parse()
+                    .with_context(|| format!("Invalid port in socket grant: {endpoint}"))?;
+                (host, Some(endpoint_port))
+            }
+            _ => (target, None),
+        };
+
+        let port = match (port, endpoint_port) {
+            (Some(port), Some(endpoint_port)) if port != endpoint_port => {
+                bail!("Socket grant {endpoint} conflicts with port {port}");
+            }
+            (port, endpoint_port) => port.or(endpoint_port),
+        };
+        if port == Some(0) {
+            bail!("Socket grant port cannot be 0: {endpoint}");
+        }
+        Self::parse(target, port)
+    }
+
+    /// The hostname of the grant, if it is not an IP address or CIDR range
+    pub fn host_name(&self) -> Option<&str> {
+        match &self.target {
+            SocketTarget::Host(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn allows_port(&self, port: u16) -> bool {
+        self.port.is_none_or(|granted| granted == port)
+    }
+
+    /// Whether the grant covers `addr` without needing a name lookup
+    fn matches_ip(&self, addr: SocketAddr) -> bool {
+        if !self.allows_port(addr.port()) {
+            return false;
+        }
+        match &self.target {
+            SocketTarget::Ip(ip) => canonical_ip(*ip) == canonical_ip(addr.ip()),
+            SocketTarget::Cidr {
+                network,
+                prefix_len,
+            } => cidr_contains(*network, *prefix_len, addr.ip()),
+            SocketTarget::Host(_) => false,
+        }
+    }
+}
+
+/// Maps IPv4-mapped IPv6 addresses back to IPv4 so both forms match the same grant
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+pub(crate) fn cidr_contains(network: IpAddr, prefix_len: u8, ip: IpAddr) -> bool {
+    match (canonical_ip(network), canonical_ip(ip)) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Extract the UDP grants from the policy document. Invalid grants are skipped with a warning.
+pub(crate) fn extract_udp_grants(policy: &PolicyDocument) -> Vec<SocketGrant> {
+    let Some(allow_list) = policy
+        .permissions
+        .network
+        .as_ref()
+        .and_then(|network| network.allow.as_ref())
+    else {
+        return Vec::new();
+    };
+
+    allow_list
+        .iter()
+        .filter_map(|perm| match perm {
+            NetworkPermission::Udp(udp) => match SocketGrant::parse(&udp.udp, udp.port) {
+                Ok(grant) => Some(grant),
+                Err(e) => {
+                    warn!(target = %udp.udp, error = %e, "Ignoring invalid UDP grant");
+                    None
+                }
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Extract the TCP grants from the policy document. Invalid grants are skipped with a warning.
+pub(crate) fn extract_tcp_grants(policy: &PolicyDocument) -> Vec<SocketGrant> {
+    let Some(allow_list) = policy
+        .permissions
+        .network
+        .as_ref()
+        .and_then(|network| network.allow.as_ref())
+    else {
+        return Vec::new();
+    };
+
+    allow_list
+        .iter()
+        .filter_map(|perm| match perm {
+            NetworkPermission::Tcp(NetworkTcpPermission { tcp, port }) => {
+                match SocketGrant::parse_endpoint(tcp, *port) {
+                    Ok(grant) => Some(grant),
+                    Err(e) => {
+                        warn!(target = %tcp, error = %e, "Ignoring invalid TCP grant");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The socket addresses a component is allowed to use
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SocketAllowlist {
+    udp: Vec<SocketGrant>,
+    tcp: Vec<SocketGrant>,
+}
+
+impl SocketAllowlist {
+    pub(crate) fn new(udp: Vec<SocketGrant>, tcp: Vec<SocketGrant>) -> Self {
+        Self { udp, tcp }
+    }
+
+    /// Decides whether a socket may use `addr`. Hostname grants are resolved on demand, so a
+    /// grant keeps working when the name moves to a different address.
+    async fn allows(self: Arc<Self>, addr: SocketAddr, reason: SocketAddrUse) -> bool {
+        let allowed = match reason {
+            // Binding only picks the local side of the socket. Restricting it to an ephemeral
+            // port keeps components from listening on well-known ports.
+            SocketAddrUse::UdpBind => !self.udp.is_empty() && addr.port() == 0,
+            SocketAddrUse::TcpBind => !self.tcp.is_empty() && addr.port() == 0,
+            SocketAddrUse::UdpConnect | SocketAddrUse::UdpOutgoingDatagram => {
+                Self::grants_allow(&self.udp, addr).await
+            }
+            // Outgoing HTTP goes through wasi:http, so raw TCP needs its own grants
+            SocketAddrUse::TcpConnect => Self::grants_allow(&self.tcp, addr).await,
+        };
+
+        if !allowed {
+            warn!(%addr, ?reason, "Socket address denied by policy");
+        }
+        allowed
+    }
+
+    async fn grants_allow(grants: &[SocketGrant], addr: SocketAddr) -> bool {
+        grants.iter().any(|grant| grant.matches_ip(addr))
+            || Self::resolves_to_granted_host(grants, addr).await
+    }
+
+    async fn resolves_to_granted_host(grants: &[SocketGrant], addr: SocketAddr) -> bool {
+        let target = canonical_ip(addr.ip());
+        for grant in grants {
+            let Some(host) = grant.host_name() else {
+                continue;
+            };
+            if !grant.allows_port(addr.port()) {
+                continue;
+            }
+            match tokio::net::lookup_host((host, addr.port())).await {
+                Ok(mut resolved) => {
+                    if resolved.any(|resolved| canonical_ip(resolved.ip()) == target) {
+                        return true;
+                    }
+                }
+                Err(e) => warn!(host, error = %e, "Failed to resolve socket grant"),
+            }
+        }
+        false
+    }
+
+    /// Turns the allowlist into a check for `WasiCtxBuilder::socket_addr_check`
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn into_check(
+        self,
+    ) -> impl Fn(SocketAddr, SocketAddrUse) -> Pin<Box<dyn Future<Output = bool> + Send + Sync>>
+           + Send
+           + Sync
+           + 'static {
+        let allowlist = Arc::new(self);
+        move |addr, reason| {
+            // Name lookups run on their own task because the check's future must be Sync
+            let task = tokio::spawn(allowlist.clone().allows(addr, reason));
+            Box::pin(async move { task.await.unwrap_or(false) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use policy::PolicyParser;
+
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_socket_grant_parse() {
+        assert_eq!(
+            SocketGrant::parse("Pool.NTP.org.", Some(123)).unwrap(),
+            SocketGrant {
+                target: SocketTarget::Host("pool.ntp.org".to_string()),
+                port: Some(123),
+            }
+        );
+        assert_eq!(
+            SocketGrant::parse("[::1]", None).unwrap().target,
+            SocketTarget::Ip("::1".parse().unwrap())
+        );
+        assert_eq!(
+            SocketGrant::parse("Bücher.example", None)
+                .unwrap()
+                .host_name(),
+            Some("xn--bcher-kva.example")
+        );
+        assert!(SocketGrant::parse("", None).is_err());
+        assert!(SocketGrant::parse("db internal", None).is_err());
+        assert!(SocketGrant::parse("10.0.0.0/33", None).is_err());
+        assert!(SocketGrant::parse("not-an-ip/8", None).is_err());
+    }
+
+    #[test]
+    fn test_socket_grant_matches_ip() {
+        let dns = SocketGrant::parse("1.1.1.1", Some(53)).unwrap();
+        assert!(dns.matches_ip(addr("1.1.1.1:53")));
+        assert!(dns.matches_ip(addr("[::ffff:1.1.1.1]:53")));
+        assert!(!dns.matches_ip(addr("1.1.1.1:54")));
+        assert!(!dns.matches_ip(addr("1.0.0.1:53")));
+
+        let private = SocketGrant::parse("10.0.0.0/8", None).unwrap();
+        assert!(private.matches_ip(addr("10.1.2.3:9999")));
+        assert!(!private.matches_ip(addr("11.0.0.1:9999")));
+
+        let v6 = SocketGrant::parse("fd00::/8", Some(5353)).unwrap();
+        assert!(v6.matches_ip(addr("[fd12::1]:5353")));
+        assert!(!v6.matches_ip(addr("[fe80::1]:5353")));
+
+        let host = SocketGrant::parse("pool.ntp.org", Some(123)).unwrap();
+        assert!(!host.matches_ip(addr("1.1.1.1:123")));
+    }
+
+    #[tokio::test]
+    async fn test_socket_allowlist() {
+        let allowlist = Arc::new(SocketAllowlist::new(
+            vec![
+                SocketGrant::parse("1.1.1.1", Some(53)).unwrap(),
+                SocketGrant::parse("localhost", Some(123)).unwrap(),
+            ],
+            Vec::new(),
+        ));
+        let allows = |addr: &str, reason| allowlist.clone().allows(self::addr(addr), reason);
+
+        assert!(allows("1.1.1.1:53", SocketAddrUse::UdpConnect).await);
+        assert!(allows("1.1.1.1:53", SocketAddrUse::UdpOutgoingDatagram).await);
+        assert!(!allows("8.8.8.8:53", SocketAddrUse::UdpOutgoingDatagram).await);
+        assert!(allows("127.0.0.1:123", SocketAddrUse::UdpConnect).await);
+        assert!(!allows("127.0.0.1:124", SocketAddrUse::UdpConnect).await);
+
+        assert!(allows("0.0.0.0:0", SocketAddrUse::UdpBind).await);
+        assert!(!allows("0.0.0.0:123", SocketAddrUse::UdpBind).await);
+        assert!(!allows("1.1.1.1:53", SocketAddrUse::TcpConnect).await);
+
+        let empty = Arc::new(SocketAllowlist::default());
+        assert!(
+            !empty
+                .allows(addr("0.0.0.0:0"), SocketAddrUse::UdpBind)
+                .await
+        );
+    }
+
+    #[test]
+    fn test_socket_grant_parse_endpoint() {
+        assert_eq!(
+            SocketGrant::parse_endpoint("tcp://DB.internal:5432", None).unwrap(),
+            SocketGrant {
+                target: SocketTarget::Host("db.internal".to_string()),
+                port: Some(5432),
+            }
+        );
+        assert_eq!(
+            SocketGrant::parse_endpoint("db.internal", Some(5432)).unwrap(),
+            SocketGrant::parse_endpoint("db.internal:5432", Some(5432)).unwrap()
+        );
+        assert!(SocketGrant::parse_endpoint("[fd00::1]:5432", None)
+            .unwrap()
+            .matches_ip(addr("[fd00::1]:5432")));
+        assert_eq!(
+            SocketGrant::parse_endpoint("fd00::/8", None).unwrap().port,
+            None
+        );
+
+        assert!(SocketGrant::parse_endpoint("db.internal:5432", Some(5433)).is_err());
+        assert!(SocketGrant::parse_endpoint("db.internal:postgres", None).is_err());
+        assert!(SocketGrant::parse_endpoint("tcp://db.internal:0", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socket_allowlist_tcp() {
+        let allowlist = Arc::new(SocketAllowlist::new(
+            Vec::new(),
+            vec![
+                SocketGrant::parse_endpoint("tcp://localhost:5432", None).unwrap(),
+                SocketGrant::parse("10.0.0.0/8", Some(25)).unwrap(),
+            ],
+        ));
+        let allows = |addr: &str, reason| allowlist.clone().allows(self::addr(addr), reason);
+
+        assert!(allows("127.0.0.1:5432", SocketAddrUse::TcpConnect).await);
+        assert!(!allows("127.0.0.1:5433", SocketAddrUse::TcpConnect).await);
+        assert!(allows("10.1.2.3:25", SocketAddrUse::TcpConnect).await);
+        assert!(!allows("11.1.2.3:25", SocketAddrUse::TcpConnect).await);
+        assert!(allows("0.0.0.0:0", SocketAddrUse::TcpBind).await);
+        assert!(!allows("0.0.0.0:5432", SocketAddrUse::TcpBind).await);
+        // TCP grants don't open UDP
+        assert!(!allows("127.0.0.1:5432", SocketAddrUse::UdpConnect).await);
+        assert!(!allows("0.0.0.0:0", SocketAddrUse::UdpBind).await);
+    }
+
+    #[test]
+    fn test_extract_udp_grants() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "UDP grants"
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+      - udp: "pool.ntp.org"
+        port: 123
+      - udp: "8.8.8.8"
+        port: 53
+"#,
+        )
+        .unwrap();
+
+        let grants = extract_udp_grants(&policy);
+
+        assert_eq!(grants.len(), 2);
+        assert_eq!(grants[0].host_name(), Some("pool.ntp.org"));
+        assert!(grants[1].matches_ip(addr("8.8.8.8:53")));
+    }
+
+    #[test]
+    fn test_extract_tcp_grants() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "TCP grants"
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+      - udp: "pool.ntp.org"
+        port: 123
+      - tcp: "tcp://db.internal:5432"
+      - tcp: "10.0.0.5"
+        port: 25
+"#,
+        )
+        .unwrap();
+
+        let grants = extract_tcp_grants(&policy);
+
+        assert_eq!(grants.len(), 2);
+        assert_eq!(grants[0].host_name(), Some("db.internal"));
+        assert!(grants[1].matches_ip(addr("10.0.0.5:25")));
+        assert!(!grants[1].matches_ip(addr("10.0.0.5:587")));
+    }
+}
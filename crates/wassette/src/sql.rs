@@ -0,0 +1,521 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Host implementation of `wasi:sql`
+//!
+//! Databases are SQLite files opened by the host, so components can run SQL without shipping a
+//! database engine of their own. A component can only open the databases listed under
+//! `permissions.database.allow` in its policy, by the name they are listed with. A database is
+//! either a file inside one of the component's storage grants, which is read-only unless the
+//! grant allows writing, or a connection string set up by the operator.
+//!
+//! `ATTACH` is disabled, so a component can't reach files outside the databases it was granted.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use rusqlite::limits::Limit;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::OpenFlags;
+use tracing::debug;
+use wasmtime::component::{Resource, ResourceTable};
+
+use self::generated::wasi::sql::{readwrite, types};
+
+mod generated {
+    wasmtime::component::bindgen!({
+        path: "wit/sql",
+        world: "wasi:sql/imports",
+        trappable_imports: true,
+        additional_derives: [PartialEq],
+        with: {
+            "wasi:sql/types/statement": crate::sql::Statement,
+            "wasi:sql/types/error": crate::sql::Error,
+            "wasi:sql/types/connection": crate::sql::Connection,
+        },
+    });
+}
+
+/// How long a statement waits for a lock held by another connection before it fails
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest number of rows a single query can return
+const MAX_QUERY_ROWS: usize = 100_000;
+
+/// A database a component may open
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SqlDatabase {
+    /// A SQLite file
+    File { path: PathBuf, read_only: bool },
+    /// An empty in-memory SQLite database for every connection
+    Memory,
+}
+
+impl SqlDatabase {
+    /// Parses a SQLite connection string: `sqlite::memory:` or `sqlite:<path>`, where the path
+    /// may start with `//` and be followed by `?mode=ro` to open the file read-only
+    pub(crate) fn from_dsn(dsn: &str) -> anyhow::Result<Self> {
+        let rest = dsn
+            .strip_prefix("sqlite:")
+            .ok_or_else(|| anyhow!("Unsupported database connection string {dsn}"))?;
+        if rest == ":memory:" {
+            return Ok(Self::Memory);
+        }
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let path = path.strip_prefix("//").unwrap_or(path);
+        if path.is_empty() {
+            bail!("Database connection string {dsn} has no path");
+        }
+        let mut read_only = false;
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param {
+                "mode=ro" => read_only = true,
+                "mode=rw" => read_only = false,
+                _ => bail!("Unsupported parameter {param} in database connection string {dsn}"),
+            }
+        }
+        Ok(Self::File {
+            path: PathBuf::from(path),
+            read_only,
+        })
+    }
+
+    fn open(&self) -> rusqlite::Result<rusqlite::Connection> {
+        let conn = match self {
+            Self::File { path, read_only } => {
+                let flags = if *read_only {
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                } else {
+                    OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+                };
+                rusqlite::Connection::open_with_flags(
+                    path,
+                    flags | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?
+            }
+            Self::Memory => rusqlite::Connection::open_in_memory()?,
+        };
+        // No attached databases also rules out VACUUM INTO, which attaches the file it writes
+        conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 0);
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
+}
+
+/// A statement with its parameters. It is prepared on the connection it runs on.
+pub struct Statement {
+    query: String,
+    params: Vec<Value>,
+}
+
+pub struct Error {
+    trace: String,
+}
+
+/// An open database
+pub struct Connection {
+    conn: rusqlite::Connection,
+}
+
+/// The `wasi:sql` state of a single component instance
+#[derive(Default)]
+pub(crate) struct SqlCtx {
+    databases: HashMap<String, SqlDatabase>,
+    component_id: String,
+}
+
+impl SqlCtx {
+    /// Creates a context that may open the given databases
+    pub(crate) fn new(databases: HashMap<String, SqlDatabase>) -> Self {
+        Self {
+            databases,
+            ..Default::default()
+        }
+    }
+
+    /// Binds the context to `component_id`
+    pub(crate) fn bind(&mut self, component_id: &str) {
+        self.component_id = component_id.to_string();
+    }
+}
+
+/// A view of a component's `wasi:sql` state used by the host bindings
+pub(crate) struct SqlHost<'a> {
+    ctx: &'a SqlCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> SqlHost<'a> {
+    pub(crate) fn new(ctx: &'a SqlCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    /// Turns the outcome of an operation into the result the guest sees
+    fn result<T>(
+        &mut self,
+        result: anyhow::Result<T>,
+    ) -> wasmtime::Result<Result<T, Resource<Error>>> {
+        match result {
+            Ok(value) => Ok(Ok(value)),
+            Err(err) => Ok(Err(self.table.push(Error {
+                trace: format!("{err:#}"),
+            })?)),
+        }
+    }
+
+    fn run<T>(
+        &self,
+        connection: &Resource<Connection>,
+        statement: &Resource<Statement>,
+        f: impl FnOnce(rusqlite::Statement<'_>, &[Value]) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let conn = &self.table.get(connection)?.conn;
+        let statement = self.table.get(statement)?;
+        let prepared = conn.prepare(&statement.query)?;
+        f(prepared, &statement.params)
+    }
+}
+
+fn to_value(value: types::DataType) -> anyhow::Result<Value> {
+    use types::DataType;
+    Ok(match value {
+        DataType::Int32(v) => Value::Integer(v.into()),
+        DataType::Int64(v) => Value::Integer(v),
+        DataType::Uint32(v) => Value::Integer(v.into()),
+        DataType::Uint64(v) => Value::Integer(
+            i64::try_from(v).map_err(|_| anyhow!("{v} is too large for a SQLite integer"))?,
+        ),
+        DataType::Float(v) | DataType::Double(v) => Value::Real(v),
+        DataType::Str(v) | DataType::Date(v) | DataType::Time(v) | DataType::Timestamp(v) => {
+            Value::Text(v)
+        }
+        DataType::Boolean(v) => Value::Integer(v.into()),
+        DataType::Binary(v) => Value::Blob(v),
+        DataType::Null => Value::Null,
+    })
+}
+
+fn to_data_type(value: ValueRef<'_>) -> anyhow::Result<types::DataType> {
+    use types::DataType;
+    Ok(match value {
+        ValueRef::Null => DataType::Null,
+        ValueRef::Integer(v) => DataType::Int64(v),
+        ValueRef::Real(v) => DataType::Double(v),
+        ValueRef::Text(v) => DataType::Str(String::from_utf8(v.to_vec())?),
+        ValueRef::Blob(v) => DataType::Binary(v.to_vec()),
+    })
+}
+
+impl types::Host for SqlHost<'_> {}
+
+impl types::HostStatement for SqlHost<'_> {
+    fn prepare(
+        &mut self,
+        query: String,
+        params: Vec<types::DataType>,
+    ) -> wasmtime::Result<Result<Resource<Statement>, Resource<Error>>> {
+        let params = params.into_iter().map(to_value).collect();
+        let statement = match params {
+            Ok(params) => Ok(self.table.push(Statement { query, params })?),
+            Err(err) => Err(err),
+        };
+        self.result(statement)
+    }
+
+    fn drop(&mut self, statement: Resource<Statement>) -> wasmtime::Result<()> {
+        self.table.delete(statement)?;
+        Ok(())
+    }
+}
+
+impl types::HostError for SqlHost<'_> {
+    fn trace(&mut self, error: Resource<Error>) -> wasmtime::Result<String> {
+        Ok(self.table.get(&error)?.trace.clone())
+    }
+
+    fn drop(&mut self, error: Resource<Error>) -> wasmtime::Result<()> {
+        self.table.delete(error)?;
+        Ok(())
+    }
+}
+
+impl types::HostConnection for SqlHost<'_> {
+    fn open(
+        &mut self,
+        name: String,
+    ) -> wasmtime::Result<Result<Resource<Connection>, Resource<Error>>> {
+        let Some(database) = self.ctx.databases.get(&name) else {
+            debug!(
+                component_id = %self.ctx.component_id,
+                database = %name,
+                "Database denied by policy"
+            );
+            return self.result(Err(anyhow!(
+                "Database {name} is not allowed by the policy of the component"
+            )));
+        };
+        let connection = match database.open() {
+            Ok(conn) => Ok(self.table.push(Connection { conn })?),
+            Err(err) => Err(anyhow!(err).context(format!("Failed to open database {name}"))),
+        };
+        self.result(connection)
+    }
+
+    fn drop(&mut self, connection: Resource<Connection>) -> wasmtime::Result<()> {
+        self.table.delete(connection)?;
+        Ok(())
+    }
+}
+
+impl readwrite::Host for SqlHost<'_> {
+    fn query(
+        &mut self,
+        connection: Resource<Connection>,
+        statement: Resource<Statement>,
+    ) -> wasmtime::Result<Result<Vec<types::Row>, Resource<Error>>> {
+        let rows = self.run(&connection, &statement, |mut prepared, params| {
+            let names: Vec<String> = prepared
+                .column_names()
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let mut rows = prepared.query(rusqlite::params_from_iter(params))?;
+            let mut result = Vec::new();
+            while let Some(row) = rows.next()? {
+                if result.len() == MAX_QUERY_ROWS {
+                    bail!("Query returned more than {MAX_QUERY_ROWS} rows");
+                }
+                let fields = names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| {
+                        Ok(types::Field {
+                            name: name.clone(),
+                            value: to_data_type(row.get_ref(index)?)?,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                result.push(fields);
+            }
+            Ok(result)
+        });
+        self.result(rows)
+    }
+
+    fn exec(
+        &mut self,
+        connection: Resource<Connection>,
+        statement: Resource<Statement>,
+    ) -> wasmtime::Result<Result<u32, Resource<Error>>> {
+        let changed = self.run(&connection, &statement, |mut prepared, params| {
+            let changed = prepared.execute(rusqlite::params_from_iter(params))?;
+            Ok(u32::try_from(changed).unwrap_or(u32::MAX))
+        });
+        self.result(changed)
+    }
+}
+
+/// Adds the `wasi:sql` interfaces to the linker
+pub(crate) fn add_to_linker<T: Send>(
+    linker: &mut wasmtime::component::Linker<T>,
+    f: impl Fn(&mut T) -> SqlHost<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    types::add_to_linker_get_host(linker, f)?;
+    readwrite::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use readwrite::Host as _;
+    use types::{DataType, HostConnection as _, HostError as _, HostStatement as _};
+
+    use super::*;
+
+    fn ctx(databases: &[(&str, SqlDatabase)]) -> SqlCtx {
+        let mut ctx = SqlCtx::new(
+            databases
+                .iter()
+                .map(|(name, database)| (name.to_string(), database.clone()))
+                .collect(),
+        );
+        ctx.bind("analysis");
+        ctx
+    }
+
+    fn borrow(conn: &Resource<Connection>) -> Resource<Connection> {
+        Resource::new_borrow(conn.rep())
+    }
+
+    fn unwrap<T>(host: &mut SqlHost<'_>, result: Result<T, Resource<Error>>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(err) => panic!("{}", host.trace(err).unwrap()),
+        }
+    }
+
+    fn trace<T>(host: &mut SqlHost<'_>, result: Result<T, Resource<Error>>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => host.trace(err).unwrap(),
+        }
+    }
+
+    fn statement(
+        host: &mut SqlHost<'_>,
+        query: &str,
+        params: Vec<DataType>,
+    ) -> Resource<Statement> {
+        let result = host.prepare(query.to_string(), params).unwrap();
+        unwrap(host, result)
+    }
+
+    #[test]
+    fn test_from_dsn() {
+        assert_eq!(
+            SqlDatabase::from_dsn("sqlite::memory:").unwrap(),
+            SqlDatabase::Memory
+        );
+        assert_eq!(
+            SqlDatabase::from_dsn("sqlite:///var/lib/shared.db?mode=ro").unwrap(),
+            SqlDatabase::File {
+                path: PathBuf::from("/var/lib/shared.db"),
+                read_only: true,
+            }
+        );
+        assert_eq!(
+            SqlDatabase::from_dsn("sqlite:data.db").unwrap(),
+            SqlDatabase::File {
+                path: PathBuf::from("data.db"),
+                read_only: false,
+            }
+        );
+        assert!(SqlDatabase::from_dsn("postgres://localhost/db").is_err());
+        assert!(SqlDatabase::from_dsn("sqlite://").is_err());
+        assert!(SqlDatabase::from_dsn("sqlite:data.db?cache=shared").is_err());
+    }
+
+    #[test]
+    fn test_query_and_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sales.db");
+        let ctx = ctx(&[(
+            "sales",
+            SqlDatabase::File {
+                path: path.clone(),
+                read_only: false,
+            },
+        )]);
+        let mut table = ResourceTable::new();
+        let mut host = SqlHost::new(&ctx, &mut table);
+
+        let result = host.open("other".to_string()).unwrap();
+        assert!(trace(&mut host, result).contains("not allowed"));
+
+        let result = host.open("sales".to_string()).unwrap();
+        let conn = unwrap(&mut host, result);
+        let create = statement(
+            &mut host,
+            "CREATE TABLE sales (region TEXT, amount REAL, units INTEGER, note BLOB)",
+            vec![],
+        );
+        let result = host.exec(borrow(&conn), create).unwrap();
+        unwrap(&mut host, result);
+
+        for (region, amount, units) in [("north", 10.5, 3), ("south", 4.0, 1)] {
+            let insert = statement(
+                &mut host,
+                "INSERT INTO sales VALUES (?1, ?2, ?3, ?4)",
+                vec![
+                    DataType::Str(region.to_string()),
+                    DataType::Double(amount),
+                    DataType::Int32(units),
+                    DataType::Null,
+                ],
+            );
+            let result = host.exec(borrow(&conn), insert).unwrap();
+            assert_eq!(unwrap(&mut host, result), 1);
+        }
+
+        let select = statement(
+            &mut host,
+            "SELECT region, amount, units, note FROM sales WHERE units >= ?1 ORDER BY region",
+            vec![DataType::Uint64(1)],
+        );
+        let result = host.query(borrow(&conn), select).unwrap();
+        let rows = unwrap(&mut host, result);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0],
+            vec![
+                types::Field {
+                    name: "region".to_string(),
+                    value: DataType::Str("north".to_string()),
+                },
+                types::Field {
+                    name: "amount".to_string(),
+                    value: DataType::Double(10.5),
+                },
+                types::Field {
+                    name: "units".to_string(),
+                    value: DataType::Int64(3),
+                },
+                types::Field {
+                    name: "note".to_string(),
+                    value: DataType::Null,
+                },
+            ]
+        );
+
+        let result = host.prepare("SELECT ?1".to_string(), vec![DataType::Uint64(u64::MAX)]);
+        assert!(trace(&mut host, result.unwrap()).contains("too large"));
+    }
+
+    #[test]
+    fn test_database_restrictions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reports.db");
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE reports (title TEXT); INSERT INTO reports VALUES ('q3');")
+            .unwrap();
+
+        let ctx = ctx(&[
+            (
+                "reports",
+                SqlDatabase::File {
+                    path: path.clone(),
+                    read_only: true,
+                },
+            ),
+            ("scratch", SqlDatabase::Memory),
+        ]);
+        let mut table = ResourceTable::new();
+        let mut host = SqlHost::new(&ctx, &mut table);
+
+        let result = host.open("reports".to_string()).unwrap();
+        let conn = unwrap(&mut host, result);
+        let select = statement(&mut host, "SELECT count(*) AS n FROM reports", vec![]);
+        let result = host.query(borrow(&conn), select).unwrap();
+        assert_eq!(unwrap(&mut host, result)[0][0].value, DataType::Int64(1));
+
+        let insert = statement(&mut host, "INSERT INTO reports VALUES ('q4')", vec![]);
+        let result = host.exec(borrow(&conn), insert).unwrap();
+        assert!(trace(&mut host, result).contains("readonly"));
+
+        let result = host.open("scratch".to_string()).unwrap();
+        let scratch = unwrap(&mut host, result);
+        let attach = format!("ATTACH DATABASE '{}' AS stolen", path.display());
+        let attach = statement(&mut host, &attach, vec![]);
+        let result = host.exec(borrow(&scratch), attach).unwrap();
+        assert!(trace(&mut host, result).contains("too many attached databases"));
+
+        let vacuum = format!("VACUUM INTO '{}'", dir.path().join("copy.db").display());
+        let vacuum = statement(&mut host, &vacuum, vec![]);
+        let result = host.exec(borrow(&scratch), vacuum).unwrap();
+        trace(&mut host, result);
+        assert!(!dir.path().join("copy.db").exists());
+    }
+}
@@ -0,0 +1,377 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! TLS settings for outgoing `wasi:http` requests
+//!
+//! Components trust the Mozilla root certificates by default. A host can be given CA
+//! certificates to trust on top of those, for services behind a private PKI, and a client
+//! certificate to present, for services that require mutual TLS. Requests to hosts with their
+//! own settings are sent by Wassette instead of the default `wasi:http` client, directly or
+//! through a proxy tunnel.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{IncomingResponse, OutgoingRequestConfig};
+
+use crate::proxy::{origin_form, send_on};
+
+/// TLS settings for the requests to one host
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HostTlsConfig {
+    /// Host the settings apply to, or `*.<domain>` for every subdomain of a domain
+    pub host: String,
+    /// PEM file of CA certificates to trust besides the default roots
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM file of the client certificate chain to present
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    /// PEM file of the private key of the client certificate
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+}
+
+impl HostTlsConfig {
+    fn matches(&self, host: &str) -> bool {
+        let pattern = self.host.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.')),
+            None => host == pattern,
+        }
+    }
+
+    fn connector(&self) -> Result<TlsConnector> {
+        let mut roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+        };
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let certs = read_certs(ca_bundle)?;
+            let (_, ignored) = roots.add_parsable_certificates(certs);
+            if ignored > 0 {
+                bail!(
+                    "{ignored} certificates in CA bundle {} could not be parsed",
+                    ca_bundle.display()
+                );
+            }
+        }
+        let builder = client_config_builder().with_root_certificates(roots);
+        let config = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .with_context(|| format!("Failed to read client key {}", key.display()))?;
+                builder
+                    .with_client_auth_cert(read_certs(cert)?, key)
+                    .with_context(|| format!("Invalid client certificate for {}", self.host))?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => bail!(
+                "TLS settings for {} need both client_cert and client_key",
+                self.host
+            ),
+        };
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// TLS settings for the outgoing `wasi:http` requests of components
+#[derive(Clone)]
+pub struct TlsConfig {
+    hosts: Vec<(HostTlsConfig, TlsConnector)>,
+    default: TlsConnector,
+}
+
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field(
+                "hosts",
+                &self.hosts.iter().map(|(host, _)| host).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            default: default_connector(),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Creates TLS settings from per-host settings, reading every certificate and key up
+    /// front. The first entry that matches a host applies to it.
+    pub fn new(hosts: impl IntoIterator<Item = HostTlsConfig>) -> Result<Self> {
+        let hosts = hosts
+            .into_iter()
+            .map(|host| {
+                let connector = host.connector()?;
+                Ok((host, connector))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            hosts,
+            default: default_connector(),
+        })
+    }
+
+    /// Whether no host has its own settings
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    fn host_connector(&self, host: &str) -> Option<&TlsConnector> {
+        let host = host.trim_matches(['[', ']']).to_ascii_lowercase();
+        self.hosts
+            .iter()
+            .find(|(config, _)| config.matches(&host))
+            .map(|(_, connector)| connector)
+    }
+
+    /// Whether `host` has its own settings
+    pub(crate) fn has_host(&self, host: &str) -> bool {
+        self.host_connector(host).is_some()
+    }
+
+    /// Negotiates TLS with `host` over `stream`
+    pub(crate) async fn connect<S>(&self, host: &str, stream: S) -> Result<TlsStream<S>, ErrorCode>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let connector = self.host_connector(host).unwrap_or(&self.default);
+        let domain = ServerName::try_from(host.trim_matches(['[', ']']))
+            .map_err(|_| ErrorCode::HttpRequestUriInvalid)?
+            .to_owned();
+        connector.connect(domain, stream).await.map_err(|e| {
+            warn!(error = %e, host, "TLS handshake failed");
+            ErrorCode::TlsProtocolError
+        })
+    }
+}
+
+/// Starts a client config with the aws-lc-rs provider. The process-wide default can't be
+/// relied on, because it is ambiguous when other crates in the build enable ring as well.
+fn client_config_builder() -> rustls::ConfigBuilder<rustls::ClientConfig, rustls::WantsVerifier> {
+    rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::aws_lc_rs::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("aws-lc-rs supports the default protocol versions")
+}
+
+fn default_connector() -> TlsConnector {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+    let config = client_config_builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Sends an `https://` request straight to its host with the TLS settings in `tls`
+pub(crate) async fn send_request(
+    mut request: hyper::Request<HyperOutgoingBody>,
+    config: OutgoingRequestConfig,
+    tls: Arc<TlsConfig>,
+) -> Result<IncomingResponse, ErrorCode> {
+    let uri = request.uri().clone();
+    let host = uri.host().ok_or(ErrorCode::HttpRequestUriInvalid)?;
+    let addr = format!("{host}:{}", uri.port_u16().unwrap_or(443));
+    debug!(uri = %uri, "Sending HTTP request with custom TLS settings");
+
+    let stream = timeout(config.connect_timeout, TcpStream::connect(&addr))
+        .await
+        .map_err(|_| ErrorCode::ConnectionTimeout)?
+        .map_err(|e| {
+            warn!(error = %e, addr, "Failed to connect");
+            ErrorCode::ConnectionRefused
+        })?;
+    let stream = timeout(config.connect_timeout, tls.connect(host, stream))
+        .await
+        .map_err(|_| ErrorCode::ConnectionTimeout)??;
+
+    *request.uri_mut() = origin_form(&uri)?;
+    send_on(stream, request, &config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http_body_util::{BodyExt, Empty};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn host(host: &str) -> HostTlsConfig {
+        HostTlsConfig {
+            host: host.to_string(),
+            ca_bundle: None,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+
+    #[test]
+    fn test_host_matching() {
+        let tls = TlsConfig::new([host("api.internal.corp"), host("*.svc.corp")]).unwrap();
+        assert!(tls.has_host("api.internal.corp"));
+        assert!(tls.has_host("API.internal.corp"));
+        assert!(tls.has_host("db.svc.corp"));
+        assert!(tls.has_host("a.b.svc.corp"));
+        assert!(!tls.has_host("svc.corp"));
+        assert!(!tls.has_host("other.internal.corp"));
+        assert!(TlsConfig::default().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "").unwrap();
+
+        let mut missing_key = host("example.com");
+        missing_key.client_cert = Some(empty.clone());
+        assert!(TlsConfig::new([missing_key]).is_err());
+
+        let mut empty_bundle = host("example.com");
+        empty_bundle.ca_bundle = Some(empty);
+        assert!(TlsConfig::new([empty_bundle]).is_err());
+
+        let mut missing_bundle = host("example.com");
+        missing_bundle.ca_bundle = Some(dir.path().join("missing.pem"));
+        assert!(TlsConfig::new([missing_bundle]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_with_private_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: String| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let issuer = Issuer::new(ca_params, ca_key);
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &issuer)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &issuer)
+            .unwrap();
+
+        let mut client_roots = rustls::RootCertStore::empty();
+        client_roots.add(ca_cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(client_roots), provider.clone())
+                .build()
+                .unwrap();
+        let server_config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::try_from(server_key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            let request_line = String::from_utf8(head)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap()
+                .to_string();
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{request_line}",
+                        request_line.len()
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let tls = TlsConfig::new([HostTlsConfig {
+            host: "localhost".to_string(),
+            ca_bundle: Some(write("ca.pem", ca_cert.pem())),
+            client_cert: Some(write("client.pem", client_cert.pem())),
+            client_key: Some(write("client.key", client_key.serialize_pem())),
+        }])
+        .unwrap();
+        let request = hyper::Request::builder()
+            .uri(format!("https://localhost:{port}/status"))
+            .header(hyper::header::HOST, "localhost")
+            .body(Empty::new().map_err(|_| unreachable!()).boxed())
+            .unwrap();
+        let config = OutgoingRequestConfig {
+            use_tls: true,
+            connect_timeout: Duration::from_secs(5),
+            first_byte_timeout: Duration::from_secs(5),
+            between_bytes_timeout: Duration::from_secs(5),
+        };
+        let response = send_request(request, config, Arc::new(tls)).await.unwrap();
+        let body = response
+            .resp
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(String::from_utf8_lossy(&body), "GET /status HTTP/1.1");
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Soft-delete support for unloaded components
+//!
+//! Unloading a component moves its files into `trash/<component_id>` inside the plugin
+//! directory instead of deleting them. Trashed components can be restored until the retention
+//! period expires, after which they are purged.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument, warn};
+
+pub(crate) const TRASH_DIR: &str = "trash";
+
+/// How long an unloaded component is kept in the trash before it is purged
+pub const TRASH_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const TRASH_METADATA_FILE: &str = "trash.json";
+
+/// Information about an unloaded component that can still be restored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashedComponent {
+    /// ID of the unloaded component
+    pub component_id: String,
+    /// When the component was unloaded, in seconds since the Unix epoch
+    pub deleted_at: u64,
+    /// When the component will be purged, in seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+impl TrashedComponent {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl crate::LifecycleManager {
+    pub(crate) fn trash_path(&self, component_id: &str) -> PathBuf {
+        self.plugin_dir.join(TRASH_DIR).join(component_id)
+    }
+
+    /// The files that make up a component in the plugin directory, as (path, file name) pairs
+    pub(crate) fn component_files(&self, component_id: &str) -> Vec<(PathBuf, String)> {
+        [
+            self.component_path(component_id),
+            self.get_component_policy_path(component_id),
+            self.get_component_metadata_path(component_id),
+            self.get_component_base_policy_path(component_id),
+        ]
+        .into_iter()
+        .filter_map(|path| {
+            let file_name = path.file_name()?.to_string_lossy().to_string();
+            Some((path, file_name))
+        })
+        .collect()
+    }
+
+    /// Moves the component's files from the plugin directory into the trash. Returns `false`
+    /// if there was nothing on disk to move.
+    pub(crate) async fn move_component_to_trash(&self, component_id: &str) -> Result<bool> {
+        let mut existing = Vec::new();
+        for (path, file_name) in self.component_files(component_id) {
+            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                existing.push((path, file_name));
+            }
+        }
+        if existing.is_empty() {
+            return Ok(false);
+        }
+
+        let trash_path = self.trash_path(component_id);
+        if tokio::fs::try_exists(&trash_path).await.unwrap_or(false) {
+            // Only the most recent unload of a component is kept
+            tokio::fs::remove_dir_all(&trash_path)
+                .await
+                .with_context(|| format!("Failed to clear {}", trash_path.display()))?;
+        }
+        tokio::fs::create_dir_all(&trash_path)
+            .await
+            .with_context(|| format!("Failed to create {}", trash_path.display()))?;
+
+        for (path, file_name) in existing {
+            tokio::fs::rename(&path, trash_path.join(&file_name))
+                .await
+                .with_context(|| format!("Failed to move {} to the trash", path.display()))?;
+            debug!(component_id, path = %path.display(), "Moved file to trash");
+        }
+
+        let deleted_at = now_secs();
+        let metadata = TrashedComponent {
+            component_id: component_id.to_string(),
+            deleted_at,
+            expires_at: deleted_at + TRASH_RETENTION.as_secs(),
+        };
+        tokio::fs::write(
+            trash_path.join(TRASH_METADATA_FILE),
+            serde_json::to_string_pretty(&metadata)?,
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Lists the unloaded components that are still in the trash
+    #[instrument(skip(self))]
+    pub async fn list_trashed_components(&self) -> Result<Vec<TrashedComponent>> {
+        let trash_dir = self.plugin_dir.join(TRASH_DIR);
+        let mut trashed = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&trash_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(trashed),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata_path = entry.path().join(TRASH_METADATA_FILE);
+            match tokio::fs::read_to_string(&metadata_path).await {
+                Ok(content) => match serde_json::from_str::<TrashedComponent>(&content) {
+                    Ok(metadata) => trashed.push(metadata),
+                    Err(e) => {
+                        warn!(path = %metadata_path.display(), error = %e, "Invalid trash metadata")
+                    }
+                },
+                Err(e) => {
+                    warn!(path = %metadata_path.display(), error = %e, "Missing trash metadata")
+                }
+            }
+        }
+
+        trashed.sort_by(|a, b| a.component_id.cmp(&b.component_id));
+        Ok(trashed)
+    }
+
+    /// Restores a previously unloaded component from the trash, including its policy
+    #[instrument(skip(self))]
+    pub async fn restore_component(&self, component_id: &str) -> Result<()> {
+        if self.components.read().await.contains_key(component_id) {
+            bail!("Component is already loaded: {}", component_id);
+        }
+
+        let trash_path = self.trash_path(component_id);
+        let trashed_wasm = trash_path.join(format!("{component_id}.wasm"));
+        if !tokio::fs::try_exists(&trashed_wasm).await.unwrap_or(false) {
+            bail!("Component not found in trash: {}", component_id);
+        }
+
+        // Put the policy files back first so the restored component picks them up
+        for (path, file_name) in self.component_files(component_id) {
+            let trashed_file = trash_path.join(&file_name);
+            if path != self.component_path(component_id)
+                && tokio::fs::try_exists(&trashed_file).await.unwrap_or(false)
+            {
+                tokio::fs::rename(&trashed_file, &path)
+                    .await
+                    .with_context(|| format!("Failed to restore {}", path.display()))?;
+            }
+        }
+
+        self.load_component(&format!("file://{}", trashed_wasm.display()))
+            .await?;
+
+        let policy_path = self.get_component_policy_path(component_id);
+        if tokio::fs::try_exists(&policy_path).await.unwrap_or(false) {
+            let policy = self.parse_policy_file(&policy_path)?;
+            self.update_policy_registry(component_id, &policy).await?;
+        }
+
+        tokio::fs::remove_dir_all(&trash_path)
+            .await
+            .with_context(|| format!("Failed to clean up {}", trash_path.display()))?;
+
+        info!(component_id, "Component restored from trash");
+        self.events.emit(crate::Event::ComponentRestored {
+            component_id: component_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Permanently deletes an unloaded component from the trash
+    #[instrument(skip(self))]
+    pub async fn purge_component(&self, component_id: &str) -> Result<()> {
+        let trash_path = self.trash_path(component_id);
+        if !tokio::fs::try_exists(&trash_path).await.unwrap_or(false) {
+            bail!("Component not found in trash: {}", component_id);
+        }
+
+        self.delete_component_artifacts(component_id).await?;
+
+        info!(component_id, "Component purged from trash");
+        self.events.emit(crate::Event::ComponentPurged {
+            component_id: component_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Unloads a component and deletes it right away instead of keeping it in the trash
+    #[instrument(skip(self))]
+    pub async fn unload_and_purge_component(&self, component_id: &str) -> Result<()> {
+        self.unload_component(component_id).await?;
+        self.delete_component_artifacts(component_id).await?;
+
+        info!(component_id, "Component purged");
+        self.events.emit(crate::Event::ComponentPurged {
+            component_id: component_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Deletes the trash entry of a component and everything derived from it: its key-value
+    /// buckets, its containers in the default blobstore directory, its secrets, its entries in the history index and its stored results for recall
+    async fn delete_component_artifacts(&self, component_id: &str) -> Result<()> {
+        let trash_path = self.trash_path(component_id);
+        if tokio::fs::try_exists(&trash_path).await.unwrap_or(false) {
+            tokio::fs::remove_dir_all(&trash_path)
+                .await
+                .with_context(|| format!("Failed to purge {}", trash_path.display()))?;
+        }
+        if let Err(e) = self.keyvalue.delete_component(component_id) {
+            warn!(component_id, error = %e, "Failed to delete key-value buckets");
+        }
+        if let Err(e) = self.delete_blobstore(component_id).await {
+            warn!(component_id, error = %e, "Failed to delete blobstore containers");
+        }
+        if let Err(e) = self.secrets.delete_component(component_id).await {
+            warn!(component_id, error = %e, "Failed to delete secrets");
+        }
+        self.forget_history(component_id).await;
+        self.forget_memories(component_id).await;
+        Ok(())
+    }
+
+    /// Purges every trashed component whose retention period has expired. Returns the IDs of
+    /// the purged components.
+    #[instrument(skip(self))]
+    pub async fn purge_expired_trash(&self) -> Result<Vec<String>> {
+        let now = now_secs();
+        let mut purged = Vec::new();
+        for trashed in self.list_trashed_components().await? {
+            if trashed.is_expired(now) {
+                self.purge_component(&trashed.component_id).await?;
+                purged.push(trashed.component_id);
+            }
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+    use crate::tests::{create_test_manager, TEST_COMPONENT_ID};
+
+    #[test(tokio::test)]
+    async fn test_unload_moves_component_to_trash() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+
+        manager.unload_component(TEST_COMPONENT_ID).await?;
+
+        assert!(!manager.component_path(TEST_COMPONENT_ID).exists());
+        assert!(!manager
+            .get_component_policy_path(TEST_COMPONENT_ID)
+            .exists());
+
+        let trash_path = manager.trash_path(TEST_COMPONENT_ID);
+        assert!(trash_path
+            .join(format!("{TEST_COMPONENT_ID}.wasm"))
+            .exists());
+        assert!(trash_path
+            .join(format!("{TEST_COMPONENT_ID}.policy.yaml"))
+            .exists());
+
+        let trashed = manager.list_trashed_components().await?;
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].component_id, TEST_COMPONENT_ID);
+        assert_eq!(
+            trashed[0].expires_at - trashed[0].deleted_at,
+            TRASH_RETENTION.as_secs()
+        );
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_restore_component() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+        manager
+            .grant_permission(
+                TEST_COMPONENT_ID,
+                "network",
+                &serde_json::json!({"host": "api.example.com"}),
+            )
+            .await?;
+        manager.unload_component(TEST_COMPONENT_ID).await?;
+
+        manager.restore_component(TEST_COMPONENT_ID).await?;
+
+        assert_eq!(manager.list_components().await, vec![TEST_COMPONENT_ID]);
+        assert!(manager.get_policy_info(TEST_COMPONENT_ID).await.is_some());
+        let templates = manager.policy_registry.read().await;
+        let template = templates
+            .component_policies
+            .get(TEST_COMPONENT_ID)
+            .expect("policy should be restored");
+        assert!(template.allowed_hosts.contains("api.example.com"));
+        drop(templates);
+
+        assert!(manager.list_trashed_components().await?.is_empty());
+        assert!(!manager.trash_path(TEST_COMPONENT_ID).exists());
+
+        // A component that is loaded again cannot be restored on top of itself
+        let err = manager
+            .restore_component(TEST_COMPONENT_ID)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("already loaded"));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_purge_component() -> Result<()> {
+        let manager = create_test_manager().await?;
+        manager.load_test_component().await?;
+        manager.unload_component(TEST_COMPONENT_ID).await?;
+
+        manager.purge_component(TEST_COMPONENT_ID).await?;
+        assert!(manager.list_trashed_components().await?.is_empty());
+
+        let err = manager
+            .restore_component(TEST_COMPONENT_ID)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not found in trash"));
+
+        let err = manager
+            .purge_component(TEST_COMPONENT_ID)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not found in trash"));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_purge_expired_trash() -> Result<()> {
+        let manager = create_test_manager().await?;
+
+        let expired_path = manager.trash_path("expired");
+        tokio::fs::create_dir_all(&expired_path).await?;
+        tokio::fs::write(
+            expired_path.join(TRASH_METADATA_FILE),
+            serde_json::to_string(&TrashedComponent {
+                component_id: "expired".to_string(),
+                deleted_at: 0,
+                expires_at: 1,
+            })?,
+        )
+        .await?;
+
+        let fresh_path = manager.trash_path("fresh");
+        tokio::fs::create_dir_all(&fresh_path).await?;
+        tokio::fs::write(
+            fresh_path.join(TRASH_METADATA_FILE),
+            serde_json::to_string(&TrashedComponent {
+                component_id: "fresh".to_string(),
+                deleted_at: now_secs(),
+                expires_at: now_secs() + TRASH_RETENTION.as_secs(),
+            })?,
+        )
+        .await?;
+
+        let purged = manager.purge_expired_trash().await?;
+        assert_eq!(purged, vec!["expired".to_string()]);
+        assert!(!expired_path.exists());
+        assert!(fresh_path.exists());
+
+        Ok(())
+    }
+}
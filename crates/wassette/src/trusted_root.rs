@@ -0,0 +1,242 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Sigstore trust roots
+//!
+//! A Sigstore instance publishes what clients need to trust its signatures as a
+//! `trusted_root.json`, which its TUF repository distributes: the certificate chains of its
+//! Fulcio certificate authorities and the public keys of its Rekor transparency logs, each with
+//! the time it was valid for. The public instance's is `trusted_root.json` in
+//! [sigstore/root-signing](https://github.com/sigstore/root-signing/tree/main/targets); a private
+//! instance or an air-gapped deployment supplies its own. Keyless verification with a
+//! [`TrustedRoot`] needs no other file and no connection to the instance.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use chrono::{DateTime, Utc};
+use p256::ecdsa::VerifyingKey;
+use p256::pkcs8::DecodePublicKey as _;
+use rustls::pki_types::CertificateDer;
+use serde::Deserialize;
+
+/// Prefix of the media types of trusted roots
+const TRUSTED_ROOT_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.trustedroot";
+
+/// The time a certificate authority or transparency log was valid for. Either end can be open.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Validity {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Validity {
+    /// Whether the Unix time `time` is within the validity
+    pub(crate) fn contains(&self, time: u64) -> bool {
+        let time = i64::try_from(time).unwrap_or(i64::MAX);
+        self.start.is_none_or(|start| start <= time) && self.end.is_none_or(|end| time <= end)
+    }
+}
+
+/// A public key of a Rekor transparency log
+#[derive(Debug, Clone)]
+pub(crate) struct RekorKey {
+    /// Hex ID of the log, the SHA-256 of its DER public key; `None` for a key that was given
+    /// without one, which is tried for any log
+    pub(crate) log_id: Option<String>,
+    pub(crate) key: VerifyingKey,
+    pub(crate) validity: Validity,
+}
+
+/// A Fulcio certificate that signing certificates can chain to
+#[derive(Debug, Clone)]
+pub(crate) struct FulcioCertificate {
+    pub(crate) certificate: CertificateDer<'static>,
+    pub(crate) validity: Validity,
+}
+
+/// The certificate authorities and transparency logs of a Sigstore instance, as read from its
+/// `trusted_root.json`
+#[derive(Debug, Clone)]
+pub struct TrustedRoot {
+    pub(crate) certificates: Vec<FulcioCertificate>,
+    pub(crate) rekor_keys: Vec<RekorKey>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrustedRootJson {
+    media_type: String,
+    #[serde(default)]
+    tlogs: Vec<TransparencyLog>,
+    #[serde(default)]
+    certificate_authorities: Vec<CertificateAuthority>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransparencyLog {
+    public_key: PublicKey,
+    log_id: LogId,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicKey {
+    raw_bytes: String,
+    #[serde(default)]
+    key_details: Option<String>,
+    #[serde(default)]
+    valid_for: Option<TimeRange>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogId {
+    key_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CertificateAuthority {
+    cert_chain: CertChain,
+    #[serde(default)]
+    valid_for: Option<TimeRange>,
+}
+
+#[derive(Deserialize)]
+struct CertChain {
+    certificates: Vec<RawBytes>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+#[derive(Deserialize)]
+struct TimeRange {
+    #[serde(default)]
+    start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    end: Option<DateTime<Utc>>,
+}
+
+impl From<Option<TimeRange>> for Validity {
+    fn from(range: Option<TimeRange>) -> Self {
+        let range = range.unwrap_or(TimeRange {
+            start: None,
+            end: None,
+        });
+        Self {
+            start: range.start.map(|start| start.timestamp()),
+            end: range.end.map(|end| end.timestamp()),
+        }
+    }
+}
+
+impl TrustedRoot {
+    /// Parses a `trusted_root.json`. Rekor keys other than ECDSA P-256 are skipped.
+    pub fn parse(json: &str) -> Result<Self> {
+        let root: TrustedRootJson = serde_json::from_str(json).context("Invalid trusted root")?;
+        if !root.media_type.starts_with(TRUSTED_ROOT_MEDIA_TYPE) {
+            bail!("Unsupported trusted root type {}", root.media_type);
+        }
+        let base64 = base64::engine::general_purpose::STANDARD;
+
+        let mut certificates = Vec::new();
+        for authority in root.certificate_authorities {
+            let validity = Validity::from(authority.valid_for);
+            for certificate in authority.cert_chain.certificates {
+                let certificate = CertificateDer::from(
+                    base64
+                        .decode(&certificate.raw_bytes)
+                        .context("Certificate of trusted root is not valid base64")?,
+                );
+                webpki::anchor_from_trusted_cert(&certificate)
+                    .map_err(|e| anyhow::anyhow!("Invalid certificate in trusted root: {e}"))?;
+                certificates.push(FulcioCertificate {
+                    certificate,
+                    validity,
+                });
+            }
+        }
+        if certificates.is_empty() {
+            bail!("Trusted root has no certificate authorities");
+        }
+
+        let mut rekor_keys = Vec::new();
+        for log in root.tlogs {
+            if log
+                .public_key
+                .key_details
+                .as_deref()
+                .is_some_and(|details| details != "PKIX_ECDSA_P256_SHA_256")
+            {
+                continue;
+            }
+            let der = base64
+                .decode(&log.public_key.raw_bytes)
+                .context("Rekor key of trusted root is not valid base64")?;
+            let key = VerifyingKey::from_public_key_der(&der)
+                .map_err(|e| anyhow::anyhow!("Invalid Rekor key in trusted root: {e}"))?;
+            let log_id = base64
+                .decode(&log.log_id.key_id)
+                .context("Rekor log ID of trusted root is not valid base64")?;
+            rekor_keys.push(RekorKey {
+                log_id: Some(log_id.iter().map(|b| format!("{b:02x}")).collect()),
+                key,
+                validity: log.public_key.valid_for.into(),
+            });
+        }
+        if rekor_keys.is_empty() {
+            bail!("Trusted root has no ECDSA P-256 Rekor keys");
+        }
+        Ok(Self {
+            certificates,
+            rekor_keys,
+        })
+    }
+
+    /// Reads a `trusted_root.json` from a `file://` URI or a path
+    pub async fn from_file(uri: &str) -> Result<Self> {
+        let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+        let json = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read trusted root {}", path.display()))?;
+        Self::parse(&json).with_context(|| format!("Invalid trusted root {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validity() {
+        let validity = Validity::from(Some(TimeRange {
+            start: "2024-01-01T00:00:00Z".parse().ok(),
+            end: None,
+        }));
+        assert!(!validity.contains(1_704_067_199));
+        assert!(validity.contains(1_704_067_200));
+        assert!(validity.contains(u64::MAX));
+        assert!(Validity::default().contains(0));
+    }
+
+    #[test]
+    fn test_parse_trusted_root_errors() {
+        assert!(TrustedRoot::parse("{}").is_err());
+        let err = TrustedRoot::parse(
+            r#"{"mediaType": "application/vnd.dev.sigstore.trustedroot+json;version=0.1"}"#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("no certificate authorities"),
+            "{err}"
+        );
+        assert!(TrustedRoot::parse(r#"{"mediaType": "application/json"}"#).is_err());
+    }
+}
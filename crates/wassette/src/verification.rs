@@ -0,0 +1,512 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Signature requirements per registry
+//!
+//! A verification policy decides which `oci://` components may be loaded and how they must be
+//! signed, by the registry and repository they come from. Its rules are checked in order and the
+//! first whose pattern matches decides; an `oci://` source that no rule matches is loaded without
+//! checks. Other sources, which have no registry signature, are matched by their whole URI and
+//! are only loaded if a rule allows them. Their URI is matched without `.` segments and repeated
+//! slashes, and one with a `..` segment is rejected, so it can't leave the directory or path a
+//! rule allows. A rule that requires a signature of such a source is
+//! checked against the detached signature next to it, at `<uri>.sigstore.json` or `<uri>.sig`.
+//!
+//! ```yaml
+//! registries:
+//!   - match: ghcr.io/microsoft/*
+//!     keyless:
+//!       fulcio_root: file:///etc/wassette/fulcio_v1.crt.pem
+//!       rekor_public_key: file:///etc/wassette/rekor.pub
+//!       issuer: https://token.actions.githubusercontent.com
+//!       subject: https://github\.com/microsoft/.+
+//!   - match: registry.internal/*
+//!     public_key: file:///etc/wassette/internal.pub
+//!   - match: file:///opt/wassette/components/*
+//!   - match: "*"
+//!     deny: true
+//! ```
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use oci_client::secrets::RegistryAuth;
+use oci_client::Reference;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::keyless::KeylessVerification;
+use crate::loader::{self, ComponentResource, DownloadedResource};
+use crate::signature::SignatureVerifier;
+use crate::LifecycleManager;
+
+/// Suffixes of the detached signatures looked up next to a `file://` or `https://` component
+/// that must be signed, in the order they are tried
+const DETACHED_SIGNATURE_SUFFIXES: [&str; 2] = [".sigstore.json", ".sig"];
+
+/// The rules of a verification policy file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VerificationRules {
+    /// Rules by registry and repository, checked in order
+    #[serde(default)]
+    pub registries: Vec<RegistryRule>,
+}
+
+/// What a component from the registries and repositories matching a pattern needs to be loaded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryRule {
+    /// `<registry>/<repository>` of the components the rule applies to, where `*` matches any
+    /// characters, such as `ghcr.io/microsoft/*`
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// URI of the cosign public key the components must be signed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Identity that must have signed the components keylessly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyless: Option<KeylessVerification>,
+    /// Never load the components
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Requirement {
+    Unsigned,
+    Signed(SignatureVerifier),
+    Denied,
+}
+
+/// Signature requirements of `oci://` components by registry and repository
+#[derive(Debug, Clone)]
+pub struct VerificationPolicy {
+    rules: Vec<(String, Requirement)>,
+}
+
+impl VerificationPolicy {
+    /// Creates a policy from its rules, reading the keys and roots they refer to
+    pub async fn new(rules: VerificationRules) -> Result<Self> {
+        let mut compiled = Vec::with_capacity(rules.registries.len());
+        for rule in rules.registries {
+            let requirement = match (&rule.public_key, &rule.keyless, rule.deny) {
+                (None, None, false) => Requirement::Unsigned,
+                (None, None, true) => Requirement::Denied,
+                (Some(public_key), None, false) => {
+                    Requirement::Signed(SignatureVerifier::from_file(public_key).await?)
+                }
+                (None, Some(keyless), false) => Requirement::Signed(
+                    keyless
+                        .verifier()
+                        .await
+                        .with_context(|| format!("Invalid rule for {}", rule.pattern))?,
+                ),
+                _ => bail!(
+                    "The rule for {} can only have one of public_key, keyless and deny",
+                    rule.pattern
+                ),
+            };
+            compiled.push((rule.pattern, requirement));
+        }
+        Ok(Self { rules: compiled })
+    }
+
+    /// Reads a policy from a YAML file
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read verification policy {}", path.display()))?;
+        let rules = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Invalid verification policy {}", path.display()))?;
+        Self::new(rules).await
+    }
+
+    /// The rule for a repository, as its pattern and requirement
+    fn rule(&self, repository: &str) -> Option<&(String, Requirement)> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| wildcard_match(pattern, repository))
+    }
+}
+
+/// Whether `value` matches `pattern`, where `*` matches any characters
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// The URI of a `file://` or `https://` source without `.` segments and repeated slashes, which
+/// is what its rule is matched against and what it is loaded from. A source with a `..` segment
+/// is rejected, since it could leave the directory that the rule allows.
+fn normalized_source(uri: &str) -> Result<String> {
+    let uri = uri.trim();
+    if let Some(path) = uri.strip_prefix("file://") {
+        let mut normalized = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::ParentDir => bail!("Component sources can't contain '..': {uri}"),
+                Component::CurDir => {}
+                component => normalized.push(component),
+            }
+        }
+        return Ok(format!("file://{}", normalized.display()));
+    }
+    if let Some(rest) = uri.strip_prefix("https://") {
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        if path.split('/').any(|segment| {
+            percent_encoding::percent_decode_str(segment).decode_utf8_lossy() == ".."
+        }) {
+            bail!("Component sources can't contain '..': {uri}");
+        }
+        let url = url::Url::parse(uri).with_context(|| format!("Invalid URL {uri}"))?;
+        return Ok(url.to_string());
+    }
+    Ok(uri.to_string())
+}
+
+impl LifecycleManager {
+    /// Checks a source against the verification policy and returns the source to load: an
+    /// `oci://` source that had to be signed is pinned to the digest whose signature was checked.
+    /// Another source that has to be signed comes with the verifier of its detached signature,
+    /// which is checked once it is downloaded.
+    async fn verified_component_source(
+        &self,
+        uri: &str,
+    ) -> Result<(String, Option<&SignatureVerifier>)> {
+        let Some(policy) = &self.verification_policy else {
+            return Ok((uri.to_string(), None));
+        };
+        let Some(reference) = uri.trim().strip_prefix("oci://") else {
+            // Other sources have no registry to keep a signature in, so only a rule that matches
+            // their URI lets them through
+            let source = normalized_source(uri)?;
+            return match policy.rule(&source) {
+                Some((_, Requirement::Unsigned)) => Ok((source, None)),
+                Some((_, Requirement::Signed(verifier))) => Ok((source, Some(verifier))),
+                Some((pattern, Requirement::Denied)) => {
+                    bail!("The verification policy doesn't allow {uri} (rule {pattern})")
+                }
+                None => bail!("The verification policy has no rule that allows {uri}"),
+            };
+        };
+        let reference: Reference = reference
+            .parse()
+            .with_context(|| format!("Invalid OCI reference {uri}"))?;
+        let repository = format!("{}/{}", reference.registry(), reference.repository());
+        let verifier = match policy.rule(&repository) {
+            None | Some((_, Requirement::Unsigned)) => return Ok((uri.to_string(), None)),
+            Some((pattern, Requirement::Denied)) => bail!(
+                "The verification policy doesn't allow components from {repository} (rule {pattern})"
+            ),
+            Some((_, Requirement::Signed(verifier))) => verifier,
+        };
+        let digest = match reference.digest() {
+            Some(digest) => digest.to_string(),
+            None => self
+                .oci_client
+                .fetch_manifest_digest(&reference, &RegistryAuth::Anonymous)
+                .await
+                .with_context(|| format!("Failed to resolve {uri}"))?,
+        };
+        self.verify_artifact(verifier, &reference, &digest)
+            .await
+            .with_context(|| {
+                format!("The verification policy requires {repository} to be signed")
+            })?;
+        info!(repository, digest, "Component signature verified");
+        Ok((
+            format!("oci://{}", reference.clone_with_digest(digest).whole()),
+            None,
+        ))
+    }
+
+    /// Checks the contents of a component downloaded from `uri` against the first of its
+    /// detached signatures that verifies
+    async fn verify_detached_signature(
+        &self,
+        verifier: &SignatureVerifier,
+        wasm: &[u8],
+        uri: &str,
+    ) -> Result<()> {
+        let mut errors = Vec::new();
+        for suffix in DETACHED_SIGNATURE_SUFFIXES {
+            let signature_uri = format!("{}{suffix}", uri.trim());
+            match self
+                .verify_component_file(verifier, wasm, &signature_uri)
+                .await
+            {
+                Ok(()) => {
+                    info!(uri, signature_uri, "Component signature verified");
+                    return Ok(());
+                }
+                Err(e) => errors.push(format!("{e:#}")),
+            }
+        }
+        bail!(
+            "The verification policy requires {uri} to be signed, but none of its detached signatures verifies: {}",
+            errors.join("; ")
+        )
+    }
+
+    /// Downloads a component after checking its source against the verification policy
+    pub(crate) async fn download_component(&self, uri: &str) -> Result<DownloadedResource> {
+        let (source, verifier) = self.verified_component_source(uri).await?;
+        debug!(uri, source, "Downloading component");
+        let downloaded = loader::load_resource::<ComponentResource>(
+            &source,
+            &self.oci_client,
+            &self.http_client,
+        )
+        .await?;
+        if let Some(verifier) = verifier {
+            let wasm = tokio::fs::read(downloaded.as_ref()).await?;
+            self.verify_detached_signature(verifier, &wasm, &source)
+                .await?;
+        }
+        Ok(downloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_log::test;
+
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "ghcr.io/microsoft/fetch-rs"));
+        assert!(wildcard_match(
+            "ghcr.io/microsoft/*",
+            "ghcr.io/microsoft/fetch-rs"
+        ));
+        assert!(wildcard_match(
+            "ghcr.io/microsoft/*",
+            "ghcr.io/microsoft/tools/fetch"
+        ));
+        assert!(!wildcard_match(
+            "ghcr.io/microsoft/*",
+            "ghcr.io/microsoftx/fetch"
+        ));
+        assert!(wildcard_match(
+            "*.internal/*",
+            "registry.internal/tools/fetch"
+        ));
+        assert!(wildcard_match("ghcr.io/*/fetch-*", "ghcr.io/a/b/fetch-rs"));
+        assert!(wildcard_match("ghcr.io/a/fetch", "ghcr.io/a/fetch"));
+        assert!(!wildcard_match("ghcr.io/a/fetch", "ghcr.io/a/fetch-rs"));
+        assert!(!wildcard_match("ghcr.io/*/fetch", "ghcr.io/fetch"));
+    }
+
+    #[test(tokio::test)]
+    async fn test_verification_policy() -> Result<()> {
+        let rules: VerificationRules = serde_yaml::from_str(
+            r#"
+registries:
+  - match: docker.io/library/*
+  - match: ghcr.io/microsoft/*
+    deny: true
+  - match: "*"
+    public_key: file:///missing/cosign.pub
+"#,
+        )?;
+        // Keys are read when the policy is created
+        let err = VerificationPolicy::new(rules.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("cosign.pub"), "{err}");
+
+        let rules = VerificationRules {
+            registries: rules.registries[..2].to_vec(),
+        };
+        let dir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&dir)
+            .await?
+            .with_verification_policy(VerificationPolicy::new(rules).await?);
+
+        let err = manager
+            .load_component("oci://ghcr.io/microsoft/fetch-rs:latest")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("doesn't allow components from ghcr.io/microsoft/fetch-rs"),
+            "{err}"
+        );
+        assert_eq!(
+            manager
+                .verified_component_source("oci://docker.io/library/tool:1.0")
+                .await?
+                .0,
+            "oci://docker.io/library/tool:1.0"
+        );
+        // Other sources need a rule that allows them
+        let err = manager
+            .verified_component_source("file:///tmp/tool.wasm")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no rule that allows"), "{err}");
+
+        let both = VerificationRules {
+            registries: vec![RegistryRule {
+                pattern: "*".to_string(),
+                public_key: Some("file:///cosign.pub".to_string()),
+                keyless: None,
+                deny: true,
+            }],
+        };
+        assert!(VerificationPolicy::new(both).await.is_err());
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_verification_policy_other_sources() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&dir).await?;
+        let with_policy = |yaml: &str| {
+            let rules = serde_yaml::from_str(yaml).unwrap();
+            let manager = manager.clone();
+            async move {
+                Ok::<_, anyhow::Error>(
+                    manager.with_verification_policy(VerificationPolicy::new(rules).await?),
+                )
+            }
+        };
+
+        let deny_all = with_policy("registries:\n  - match: \"*\"\n    deny: true\n").await?;
+        let err = deny_all
+            .load_component("https://example.com/tool.wasm")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("doesn't allow https://example.com/tool.wasm"),
+            "{err}"
+        );
+
+        let allow_local = with_policy("registries:\n  - match: file:///opt/tools/*\n").await?;
+        assert_eq!(
+            allow_local
+                .verified_component_source("file:///opt/tools/tool.wasm")
+                .await?
+                .0,
+            "file:///opt/tools/tool.wasm"
+        );
+        assert_eq!(
+            allow_local
+                .verified_component_source("file:///opt/tools/./nested//tool.wasm")
+                .await?
+                .0,
+            "file:///opt/tools/nested/tool.wasm"
+        );
+        for escaping in [
+            "file:///opt/tools/../../tmp/evil.wasm",
+            "file:///opt/tools/nested/../../../tmp/evil.wasm",
+        ] {
+            let err = allow_local
+                .verified_component_source(escaping)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("can't contain '..'"), "{err}");
+        }
+
+        let allow_remote =
+            with_policy("registries:\n  - match: https://example.com/tools/*\n").await?;
+        assert_eq!(
+            allow_remote
+                .verified_component_source("https://example.com/tools/./tool.wasm")
+                .await?
+                .0,
+            "https://example.com/tools/tool.wasm"
+        );
+        for escaping in [
+            "https://example.com/tools/../evil.wasm",
+            "https://example.com/tools/%2e%2E/evil.wasm",
+        ] {
+            let err = allow_remote
+                .verified_component_source(escaping)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("can't contain '..'"), "{err}");
+        }
+
+        let err = allow_local
+            .verified_component_source("https://example.com/tool.wasm")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no rule that allows"), "{err}");
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_verification_policy_detached_signature() -> Result<()> {
+        use base64::Engine as _;
+        use p256::ecdsa::signature::Signer as _;
+        use p256::ecdsa::{Signature, SigningKey};
+        use p256::pkcs8::{EncodePublicKey as _, LineEnding};
+
+        let dir = tempfile::tempdir()?;
+        let components = dir.path().join("components");
+        tokio::fs::create_dir(&components).await?;
+        let key = SigningKey::from_slice(&[7u8; 32])?;
+        let key_path = dir.path().join("cosign.pub");
+        tokio::fs::write(
+            &key_path,
+            key.verifying_key().to_public_key_pem(LineEnding::LF)?,
+        )
+        .await?;
+        let rules = serde_yaml::from_str(&format!(
+            "registries:\n  - match: file://{}/*\n    public_key: file://{}\n",
+            components.display(),
+            key_path.display()
+        ))?;
+        let manager = LifecycleManager::new(dir.path().join("plugins"))
+            .await?
+            .with_verification_policy(VerificationPolicy::new(rules).await?);
+
+        let wasm = r#"(component
+          (core module $m (func (export "answer") (result i32) i32.const 42))
+          (core instance $i (instantiate $m))
+          (func $answer (result u32) (canon lift (core func $i "answer")))
+          (export "answer" (func $answer)))"#;
+        let path = components.join("answer.wasm");
+        tokio::fs::write(&path, wasm).await?;
+        let uri = format!("file://{}", path.display());
+
+        // Without a signature next to it
+        let err = manager.load_component(&uri).await.unwrap_err();
+        assert!(err.to_string().contains("requires"), "{err}");
+
+        let signature: Signature = key.sign(wasm.as_bytes());
+        tokio::fs::write(
+            components.join("answer.wasm.sig"),
+            base64::engine::general_purpose::STANDARD.encode(signature.to_der()),
+        )
+        .await?;
+        manager.load_component(&uri).await?;
+        assert_eq!(
+            manager
+                .execute_component_call("answer", "answer", "{}")
+                .await?,
+            "42"
+        );
+
+        // The signature is of other contents
+        tokio::fs::write(&path, wasm.replace("42", "43")).await?;
+        assert!(manager.load_component(&uri).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Cache of successful signature verifications
+//!
+//! Checking a signature means downloading it from the registry or its URI and, for keyless
+//! signatures, checking the certificate chain and Rekor entry that come with it. Once an artifact
+//! verifies, the result is kept in the `verified` directory of the plugin directory, keyed by the
+//! digest of the artifact and the fingerprint of the keys, roots and identity it was checked
+//! against. Loading the same component again, such as at every start of the server, then skips
+//! the check. A change of key, root or identity changes the fingerprint, so the artifact is
+//! checked again. Failed verifications are never cached.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use oci_client::Reference;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::bundle::sha256_hex;
+use crate::detached::DetachedSignature;
+use crate::signature::SignatureVerifier;
+use crate::LifecycleManager;
+
+/// Directory of the plugin directory with the cached verification results
+pub(crate) const VERIFICATION_CACHE_DIR: &str = "verified";
+
+/// A successful verification
+#[derive(Debug, Serialize, Deserialize)]
+struct VerifiedEntry {
+    /// Digest of the OCI manifest or component file that verified
+    digest: String,
+    /// Fingerprint of the verifier it verified with
+    verifier: String,
+    verified_at: DateTime<Utc>,
+}
+
+impl LifecycleManager {
+    fn verification_cache_path(&self, digest: &str, fingerprint: &str) -> PathBuf {
+        let key = sha256_hex(format!("{digest}\n{fingerprint}").as_bytes());
+        self.plugin_dir
+            .join(VERIFICATION_CACHE_DIR)
+            .join(format!("{key}.json"))
+    }
+
+    async fn was_verified(&self, digest: &str, fingerprint: &str) -> bool {
+        let Ok(contents) = tokio::fs::read(self.verification_cache_path(digest, fingerprint)).await
+        else {
+            return false;
+        };
+        serde_json::from_slice::<VerifiedEntry>(&contents)
+            .is_ok_and(|entry| entry.digest == digest && entry.verifier == fingerprint)
+    }
+
+    /// Caches a successful verification. Failing to is only logged, as the artifact did verify.
+    async fn remember_verified(&self, digest: &str, fingerprint: &str) {
+        let path = self.verification_cache_path(digest, fingerprint);
+        let entry = VerifiedEntry {
+            digest: digest.to_string(),
+            verifier: fingerprint.to_string(),
+            verified_at: Utc::now(),
+        };
+        let result = async {
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::write(&path, serde_json::to_vec_pretty(&entry)?).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!(digest, error = %e, "Failed to cache signature verification");
+        }
+    }
+
+    /// Checks the signature of the manifest of `reference` with the given digest in the
+    /// registry, unless it verified with the same trust before
+    pub(crate) async fn verify_artifact(
+        &self,
+        verifier: &SignatureVerifier,
+        reference: &Reference,
+        digest: &str,
+    ) -> Result<()> {
+        let fingerprint = verifier.fingerprint()?;
+        if self.was_verified(digest, &fingerprint).await {
+            debug!(
+                repository = reference.repository(),
+                digest, "Signature verified before"
+            );
+            return Ok(());
+        }
+        verifier.verify(&self.oci_client, reference, digest).await?;
+        self.remember_verified(digest, &fingerprint).await;
+        Ok(())
+    }
+
+    /// Checks the detached signature at `signature_uri` of the contents of a component file,
+    /// unless they verified with the same trust before
+    pub(crate) async fn verify_component_file(
+        &self,
+        verifier: &SignatureVerifier,
+        wasm: &[u8],
+        signature_uri: &str,
+    ) -> Result<()> {
+        let digest = format!("sha256:{}", sha256_hex(wasm));
+        let fingerprint = verifier.fingerprint()?;
+        if self.was_verified(&digest, &fingerprint).await {
+            debug!(digest, "Signature verified before");
+            return Ok(());
+        }
+        let signature = DetachedSignature::from_uri(signature_uri, &self.http_client).await?;
+        verifier.verify_blob(wasm, &signature)?;
+        self.remember_verified(&digest, &fingerprint).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine as _;
+    use p256::ecdsa::signature::Signer as _;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::{EncodePublicKey as _, LineEnding};
+    use test_log::test;
+
+    use super::*;
+
+    fn verifier(key: &SigningKey) -> SignatureVerifier {
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        SignatureVerifier::from_pem(&pem).unwrap()
+    }
+
+    #[test(tokio::test)]
+    async fn test_verification_cache() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manager = LifecycleManager::new(&dir).await?;
+        let key = SigningKey::from_slice(&[7u8; 32])?;
+        let wasm = b"\0asm\x0d\0\x01\0";
+        let signature: Signature = key.sign(wasm);
+        let signature_path = dir.path().join("tool.wasm.sig");
+        tokio::fs::write(
+            &signature_path,
+            base64::engine::general_purpose::STANDARD.encode(signature.to_der()),
+        )
+        .await?;
+        let signature_uri = format!("file://{}", signature_path.display());
+
+        manager
+            .verify_component_file(&verifier(&key), wasm, &signature_uri)
+            .await?;
+        assert_eq!(verifier(&key).fingerprint()?, verifier(&key).fingerprint()?);
+
+        // Verified before, so the signature isn't read again
+        tokio::fs::remove_file(&signature_path).await?;
+        manager
+            .verify_component_file(&verifier(&key), wasm, &signature_uri)
+            .await?;
+
+        // Other contents or another key have to be verified
+        assert!(manager
+            .verify_component_file(&verifier(&key), b"\0asm", &signature_uri)
+            .await
+            .is_err());
+        let other = SigningKey::from_slice(&[9u8; 32])?;
+        assert_ne!(
+            verifier(&key).fingerprint()?,
+            verifier(&other).fingerprint()?
+        );
+        assert!(manager
+            .verify_component_file(&verifier(&other), wasm, &signature_uri)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
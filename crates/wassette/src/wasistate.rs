@@ -1,24 +1,52 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use std::collections::{HashMap, HashSet};
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 
-use policy::{AccessType, PolicyDocument};
+use anyhow::Context;
+use policy::{AccessType, DeterministicRuntime, NetworkPermission, PolicyDocument};
 use wasmtime_wasi::p2::WasiCtxBuilder;
 use wasmtime_wasi_config::WasiConfigVariables;
 use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
 
+use crate::blobstore::BlobstoreCtx;
+use crate::calls::{extract_call_grants, CallCtx, CallGrants};
+use crate::delegation::{DelegatedDir, DelegationScope};
+use crate::determinism;
+use crate::extension::{extract_extension_grants, ExtensionCtx};
+use crate::keyvalue::KeyValueCtx;
+use crate::logging::LoggingCtx;
+use crate::output::OutputCapture;
+use crate::sampling::{extract_sampling_grant, SamplingCtx};
+use crate::sockets::{extract_tcp_grants, extract_udp_grants, SocketAllowlist, SocketGrant};
+use crate::sql::{SqlCtx, SqlDatabase};
+
+/// Guest path of the scratch directory every call gets, unless a storage grant already uses it
+pub const SCRATCH_GUEST_PATH: &str = "/tmp";
+
+/// Subdirectory of the plugin dir that holds the `{data_dir}` of every component
+pub const DATA_DIR: &str = "data";
+
 /// Custom resource limiter that stores the limits
 #[derive(Clone)]
 pub struct CustomResourceLimiter {
     limits: wasmtime::StoreLimits,
+    memory_limit_hit: bool,
 }
 
 impl CustomResourceLimiter {
     /// Create a new CustomResourceLimiter with the given limits
     pub fn new(limits: wasmtime::StoreLimits) -> Self {
-        Self { limits }
+        Self {
+            limits,
+            memory_limit_hit: false,
+        }
+    }
+
+    /// Whether the memory limit denied a memory of the store to grow
+    pub fn memory_limit_hit(&self) -> bool {
+        self.memory_limit_hit
     }
 }
 
@@ -29,7 +57,11 @@ impl wasmtime::ResourceLimiter for CustomResourceLimiter {
         desired: usize,
         _maximum: Option<usize>,
     ) -> anyhow::Result<bool> {
-        self.limits.memory_growing(current, desired, _maximum)
+        let allowed = self.limits.memory_growing(current, desired, _maximum);
+        if !matches!(allowed, Ok(true)) {
+            self.memory_limit_hit = true;
+        }
+        allowed
     }
 
     fn table_growing(
@@ -40,6 +72,18 @@ impl wasmtime::ResourceLimiter for CustomResourceLimiter {
     ) -> anyhow::Result<bool> {
         self.limits.table_growing(current, desired, _maximum)
     }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
 }
 
 pub struct WasiState {
@@ -48,6 +92,22 @@ pub struct WasiState {
     pub http: wasmtime_wasi_http::WasiHttpCtx,
     pub wasi_config_vars: WasiConfigVariables,
     pub resource_limiter: Option<CustomResourceLimiter>,
+    pub(crate) keyvalue: KeyValueCtx,
+    pub(crate) blobstore: BlobstoreCtx,
+    pub(crate) sql: SqlCtx,
+    pub(crate) calls: CallCtx,
+    pub(crate) logging: LoggingCtx,
+    pub(crate) sampling: SamplingCtx,
+    pub(crate) extensions: ExtensionCtx,
+    /// Scratch directory preopened at [`SCRATCH_GUEST_PATH`], deleted when the state is dropped
+    scratch: Option<tempfile::TempDir>,
+}
+
+impl WasiState {
+    /// Host path of the scratch directory of this state, if it has one
+    pub fn scratch_dir(&self) -> Option<&Path> {
+        self.scratch.as_ref().map(|scratch| scratch.path())
+    }
 }
 
 impl wasmtime_wasi::p2::IoView for WasiState {
@@ -71,28 +131,48 @@ impl WasiHttpView for WasiState {
 impl WasiStateTemplate {
     /// Creates a new `WasiState` from the template.
     pub fn build(&self) -> anyhow::Result<WasiState> {
+        self.build_with_capture(None)
+    }
+
+    /// Creates a new `WasiState` whose stdout and stderr go to `capture` instead of the
+    /// server process, if given
+    pub(crate) fn build_with_capture(
+        &self,
+        capture: Option<&OutputCapture>,
+    ) -> anyhow::Result<WasiState> {
         let mut ctx_builder = WasiCtxBuilder::new();
         if self.allow_stdout {
-            ctx_builder.inherit_stdout();
+            match capture {
+                Some(capture) => ctx_builder.stdout(capture.stdout.clone()),
+                None => ctx_builder.inherit_stdout(),
+            };
         }
         if self.allow_stderr {
-            ctx_builder.inherit_stderr();
+            match capture {
+                Some(capture) => ctx_builder.stderr(capture.stderr.clone()),
+                None => ctx_builder.inherit_stderr(),
+            };
         }
         ctx_builder.inherit_args();
         if self.allow_args {
             ctx_builder.inherit_args();
         }
         // Note(mossaka): removed ctx_builder.inherit_network() to implement deny-by-default network policy
-        // For HTTP requests to work, we need to allow TCP and DNS lookups when there are network permissions
-        // But HTTP-level filtering happens in WassetteWasiState::send_request
-        if self.network_perms.allow_tcp || !self.allowed_hosts.is_empty() {
-            ctx_builder.allow_tcp(true);
-            ctx_builder.allow_ip_name_lookup(true);
-        } else {
-            ctx_builder.allow_tcp(false);
-            ctx_builder.allow_ip_name_lookup(false);
-        }
+        // HTTP requests go through wasi:http and are filtered in WassetteWasiState::send_request,
+        // so raw TCP sockets are only available with explicit TCP grants
+        ctx_builder.allow_tcp(self.network_perms.allow_tcp);
+        // Name lookups are only enabled for components with a DNS allowlist, and each lookup is
+        // checked against that allowlist in WassetteWasiState's ip-name-lookup implementation
+        ctx_builder.allow_ip_name_lookup(!self.allowed_dns_names.is_empty());
+        // UDP sockets are only available with explicit UDP grants, and every address UDP and TCP
+        // sockets use is checked against the grants for that protocol
         ctx_builder.allow_udp(self.network_perms.allow_udp);
+        ctx_builder.socket_addr_check(
+            SocketAllowlist::new(self.udp_grants.clone(), self.tcp_grants.clone()).into_check(),
+        );
+        if let Some(deterministic) = &self.deterministic {
+            determinism::apply(&mut ctx_builder, deterministic);
+        }
         for preopened_dir in &self.preopened_dirs {
             ctx_builder.preopened_dir(
                 preopened_dir.host_path.as_path(),
@@ -101,6 +181,25 @@ impl WasiStateTemplate {
                 preopened_dir.file_perms,
             )?;
         }
+        // Every call gets an empty directory of its own at /tmp, which is removed with the state
+        let scratch = if self
+            .preopened_dirs
+            .iter()
+            .any(|dir| Path::new(&dir.guest_path) == Path::new(SCRATCH_GUEST_PATH))
+        {
+            None
+        } else {
+            let scratch = tempfile::Builder::new()
+                .prefix("wassette-scratch-")
+                .tempdir()?;
+            ctx_builder.preopened_dir(
+                scratch.path(),
+                SCRATCH_GUEST_PATH,
+                wasmtime_wasi::DirPerms::all(),
+                wasmtime_wasi::FilePerms::all(),
+            )?;
+            Some(scratch)
+        };
 
         Ok(WasiState {
             ctx: ctx_builder.build(),
@@ -111,8 +210,144 @@ impl WasiStateTemplate {
                 .store_limits
                 .as_ref()
                 .map(|limits| CustomResourceLimiter::new(limits.clone())),
+            keyvalue: KeyValueCtx::new(self.keyvalue_buckets.clone()),
+            blobstore: BlobstoreCtx::new(
+                self.blobstore_containers.clone(),
+                self.blobstore_backend.clone(),
+            ),
+            sql: SqlCtx::new(self.databases.clone()),
+            calls: CallCtx::new(
+                self.call_grants.clone(),
+                // Only components that may call others delegate their permissions
+                if self.call_grants.is_empty() {
+                    DelegationScope::default()
+                } else {
+                    self.delegation_scope()
+                },
+            ),
+            logging: LoggingCtx::default(),
+            sampling: SamplingCtx::new(self.sampling.clone()),
+            extensions: ExtensionCtx::new(&self.extension_grants),
+            scratch,
         })
     }
+
+    /// The permissions of the template, which the components it calls can be given at most
+    pub(crate) fn delegation_scope(&self) -> DelegationScope {
+        DelegationScope {
+            stdout: self.allow_stdout,
+            stderr: self.allow_stderr,
+            args: self.allow_args,
+            tcp: self.network_perms.allow_tcp,
+            udp: self.network_perms.allow_udp,
+            ip_name_lookup: self.network_perms.allow_ip_name_lookup,
+            hosts: self.allowed_hosts.iter().cloned().collect(),
+            dns_names: self.allowed_dns_names.iter().cloned().collect(),
+            environment: self
+                .config_vars
+                .keys()
+                .chain(self.secret_vars.keys())
+                .cloned()
+                .collect(),
+            storage: self
+                .preopened_dirs
+                .iter()
+                .map(|dir| DelegatedDir {
+                    host_path: dir.host_path.clone(),
+                    guest_path: dir.guest_path.clone(),
+                    read: dir.file_perms.contains(wasmtime_wasi::FilePerms::READ),
+                    write: dir.file_perms.contains(wasmtime_wasi::FilePerms::WRITE),
+                })
+                .collect(),
+            udp_grants: self.udp_grants.clone(),
+            tcp_grants: self.tcp_grants.clone(),
+            keyvalue_buckets: self.keyvalue_buckets.iter().cloned().collect(),
+            blobstore_containers: self.blobstore_containers.iter().cloned().collect(),
+            databases: self.databases.keys().cloned().collect(),
+            extensions: self.extension_grants.keys().cloned().collect(),
+            sampling: self.sampling.is_some(),
+        }
+    }
+
+    /// The template a component runs with when it is called with a delegation token for
+    /// `scope`: every permission is limited to what both the template and the scope allow.
+    /// Configuration values, resource limits and the components the callee may call in turn
+    /// are the template's own; its calls are limited again by the scope it ends up with.
+    pub(crate) fn restrict(&self, scope: &DelegationScope) -> Self {
+        let dirs = self
+            .preopened_dirs
+            .iter()
+            .flat_map(|dir| {
+                scope.storage.iter().filter_map(move |delegated| {
+                    let (file_perms, dir_perms) = calculate_permissions(
+                        &[
+                            delegated.read.then_some(AccessType::Read),
+                            delegated.write.then_some(AccessType::Write),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>(),
+                    );
+                    dir.intersect(&PreopenedDir {
+                        host_path: delegated.host_path.clone(),
+                        guest_path: delegated.guest_path.clone(),
+                        dir_perms,
+                        file_perms,
+                    })
+                })
+            })
+            .collect();
+        let hosts = scope.hosts.iter().cloned().collect();
+        Self {
+            allow_stdout: self.allow_stdout && scope.stdout,
+            allow_stderr: self.allow_stderr && scope.stderr,
+            allow_args: self.allow_args && scope.args,
+            network_perms: NetworkPermissions {
+                allow_tcp: self.network_perms.allow_tcp && scope.tcp,
+                allow_udp: self.network_perms.allow_udp && scope.udp,
+                allow_ip_name_lookup: self.network_perms.allow_ip_name_lookup
+                    && scope.ip_name_lookup,
+            },
+            config_vars: retain_keys(&self.config_vars, &scope.environment),
+            secret_vars: retain_keys(&self.secret_vars, &scope.environment),
+            preopened_dirs: dirs,
+            allowed_hosts: crate::http::intersect_allowed_hosts(&self.allowed_hosts, &hosts),
+            allowed_dns_names: retain_in(&self.allowed_dns_names, &scope.dns_names),
+            udp_grants: retain_shared(&self.udp_grants, &scope.udp_grants),
+            tcp_grants: retain_shared(&self.tcp_grants, &scope.tcp_grants),
+            keyvalue_buckets: retain_in(&self.keyvalue_buckets, &scope.keyvalue_buckets),
+            blobstore_containers: retain_in(
+                &self.blobstore_containers,
+                &scope.blobstore_containers,
+            ),
+            databases: retain_keys(&self.databases, &scope.databases),
+            extension_grants: retain_keys(&self.extension_grants, &scope.extensions),
+            sampling: self.sampling.clone().filter(|_| scope.sampling),
+            ..self.clone()
+        }
+    }
+}
+
+fn retain_keys<V: Clone>(map: &HashMap<String, V>, keys: &BTreeSet<String>) -> HashMap<String, V> {
+    map.iter()
+        .filter(|(key, _)| keys.contains(*key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn retain_in(set: &HashSet<String>, other: &BTreeSet<String>) -> HashSet<String> {
+    set.iter()
+        .filter(|item| other.contains(*item))
+        .cloned()
+        .collect()
+}
+
+fn retain_shared<T: Clone + PartialEq>(items: &[T], other: &[T]) -> Vec<T> {
+    items
+        .iter()
+        .filter(|item| other.contains(item))
+        .cloned()
+        .collect()
 }
 
 /// A struct that presents the arguments passed to `wasmtime_wasi::WasiCtxBuilder::preopened_dir`
@@ -124,6 +359,36 @@ pub struct PreopenedDir {
     pub file_perms: wasmtime_wasi::FilePerms,
 }
 
+impl PreopenedDir {
+    /// The part of this directory that `other` grants access to as well, with the permissions
+    /// both allow, at the guest path it has in this directory
+    fn intersect(&self, other: &PreopenedDir) -> Option<PreopenedDir> {
+        let dir_perms = self.dir_perms & other.dir_perms;
+        let file_perms = self.file_perms & other.file_perms;
+        if dir_perms.is_empty() && file_perms.is_empty() {
+            return None;
+        }
+        if self.host_path.starts_with(&other.host_path) {
+            Some(PreopenedDir {
+                dir_perms,
+                file_perms,
+                ..self.clone()
+            })
+        } else {
+            let relative = other.host_path.strip_prefix(&self.host_path).ok()?;
+            Some(PreopenedDir {
+                host_path: other.host_path.clone(),
+                guest_path: Path::new(&self.guest_path)
+                    .join(relative)
+                    .to_string_lossy()
+                    .to_string(),
+                dir_perms,
+                file_perms,
+            })
+        }
+    }
+}
+
 /// A struct that presents the network permissions passed to wasmtime_wasi::WasiContextBuilder
 #[derive(Default, Clone)]
 pub struct NetworkPermissions {
@@ -146,14 +411,41 @@ pub struct WasiStateTemplate {
     pub network_perms: NetworkPermissions,
     /// Configuration variables for wasmtime_wasi_config
     pub config_vars: HashMap<String, String>,
+    /// Configuration variables whose values are secrets of the component, by the names of the
+    /// secrets. They are looked up for every call.
+    pub secret_vars: HashMap<String, String>,
     /// Preopened directories for filesystem access
     pub preopened_dirs: Vec<PreopenedDir>,
     /// Allowed network hosts for HTTP requests
     pub allowed_hosts: HashSet<String>,
+    /// Names the component may resolve through wasi:sockets/ip-name-lookup
+    pub allowed_dns_names: HashSet<String>,
+    /// Hosts, addresses and ports the component may exchange UDP datagrams with
+    pub udp_grants: Vec<SocketGrant>,
+    /// Hosts, addresses and ports the component may open raw TCP connections to
+    pub tcp_grants: Vec<SocketGrant>,
+    /// wasi:keyvalue buckets the component may open
+    pub keyvalue_buckets: HashSet<String>,
+    /// wasi:blobstore containers the component may use
+    pub blobstore_containers: HashSet<String>,
+    /// Where the blobstore containers of the component are stored
+    pub blobstore_backend: policy::BlobstoreBackend,
+    /// wasi:sql databases the component may open, by name
+    pub(crate) databases: HashMap<String, SqlDatabase>,
+    /// Components and tools the component may call through wassette:components
+    pub(crate) call_grants: CallGrants,
+    /// Virtual clocks and seeded random numbers for deterministic execution
+    pub deterministic: Option<DeterministicRuntime>,
+    /// Host extensions the component may use, with their settings
+    pub extension_grants: HashMap<String, serde_json::Value>,
+    /// Whether and how much the component may sample through wassette:ai
+    pub(crate) sampling: Option<policy::SamplingPermissions>,
     /// Memory limit in bytes for the component
     pub memory_limit: Option<u64>,
     /// Store limits for wasmtime (built from memory_limit)
     pub store_limits: Option<wasmtime::StoreLimits>,
+    /// Size and time limits of outgoing HTTP requests that replace the server defaults
+    pub http_limits: Option<policy::HttpLimits>,
 }
 
 impl Default for WasiStateTemplate {
@@ -164,43 +456,110 @@ impl Default for WasiStateTemplate {
             allow_args: true,
             network_perms: NetworkPermissions::default(),
             config_vars: HashMap::new(),
+            secret_vars: HashMap::new(),
             preopened_dirs: Vec::new(),
             allowed_hosts: HashSet::new(),
+            allowed_dns_names: HashSet::new(),
+            udp_grants: Vec::new(),
+            tcp_grants: Vec::new(),
+            keyvalue_buckets: HashSet::new(),
+            blobstore_containers: HashSet::new(),
+            blobstore_backend: policy::BlobstoreBackend::default(),
+            databases: HashMap::new(),
+            call_grants: CallGrants::new(),
+            deterministic: None,
+            extension_grants: HashMap::new(),
+            sampling: None,
             memory_limit: None,
             store_limits: None,
+            http_limits: None,
         }
     }
 }
 
+/// Host values that config variables can refer to as `{component_id}`, `{data_dir}` and
+/// `{session_id}`
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    /// ID of the component the template is built for
+    pub component_id: String,
+    /// Host directory for the data of the component, created when a variable refers to it
+    pub data_dir: PathBuf,
+    /// ID of the running server, which changes on every start
+    pub session_id: String,
+}
+
+impl TemplateContext {
+    /// Creates the context of a component, whose data directory is `<plugin_dir>/data/<component_id>`
+    pub fn new(plugin_dir: &Path, component_id: &str, session_id: &str) -> Self {
+        Self {
+            component_id: component_id.to_string(),
+            data_dir: plugin_dir.join(DATA_DIR).join(component_id),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Replaces the placeholders in a value. Other text in braces is left as it is.
+    pub fn expand(&self, value: &str) -> String {
+        value
+            .replace("{component_id}", &self.component_id)
+            .replace("{data_dir}", &self.data_dir.to_string_lossy())
+            .replace("{session_id}", &self.session_id)
+    }
+}
+
 /// Maps the policy-mcp capabiltiies to the wasi state template
 pub fn create_wasi_state_template_from_policy(
     policy: &PolicyDocument,
     plugin_dir: &Path,
     environment_vars: &HashMap<String, String>,
+    context: &TemplateContext,
 ) -> anyhow::Result<WasiStateTemplate> {
-    let env_vars = extract_env_vars(policy, environment_vars)?;
+    let env_vars = expand_config_vars(extract_env_vars(policy, environment_vars)?, context)?;
     let network_perms = extract_network_perms(policy);
     let preopened_dirs = extract_storage_permissions(policy, plugin_dir)?;
     let allowed_hosts = extract_allowed_hosts(policy);
+    let udp_grants = extract_udp_grants(policy);
+    let tcp_grants = extract_tcp_grants(policy);
+    let mut allowed_dns_names = extract_allowed_dns_names(&allowed_hosts);
+    // Components with a socket grant for a hostname need to be able to resolve it
+    allowed_dns_names.extend(
+        udp_grants
+            .iter()
+            .chain(&tcp_grants)
+            .filter_map(|grant| grant.host_name().map(str::to_string)),
+    );
+    let keyvalue_buckets = extract_keyvalue_buckets(policy);
+    let (blobstore_containers, blobstore_backend) = extract_blobstore_permissions(policy);
+    let databases = extract_databases(policy, &preopened_dirs)?;
+    let call_grants = extract_call_grants(policy);
+    let deterministic = extract_deterministic_runtime(policy);
+    let extension_grants = extract_extension_grants(policy)?;
+    let sampling = extract_sampling_grant(policy);
     let memory_limit = extract_memory_limit(policy)?;
-    let store_limits = memory_limit
-        .map(|limit| -> anyhow::Result<wasmtime::StoreLimits> {
-            let limit_usize = limit.try_into().map_err(|_| {
-                anyhow::anyhow!("Memory limit {} too large for target architecture", limit)
-            })?;
-            Ok(wasmtime::StoreLimitsBuilder::new()
-                .memory_size(limit_usize)
-                .build())
-        })
-        .transpose()?;
+    let http_limits = extract_http_limits(policy);
+    let store_limits = extract_store_limits(policy, memory_limit)?;
 
     Ok(WasiStateTemplate {
         network_perms,
         config_vars: env_vars,
+        secret_vars: extract_secret_vars(policy),
         preopened_dirs,
         allowed_hosts,
+        allowed_dns_names,
+        udp_grants,
+        tcp_grants,
+        keyvalue_buckets,
+        blobstore_containers,
+        blobstore_backend,
+        databases,
+        call_grants,
+        deterministic,
+        extension_grants,
+        sampling,
         memory_limit,
         store_limits,
+        http_limits,
         ..Default::default()
     })
 }
@@ -212,7 +571,8 @@ pub(crate) fn extract_env_vars(
     let mut env_vars = HashMap::new();
     if let Some(env_perms) = &policy.permissions.environment {
         if let Some(env_allow_vec) = &env_perms.allow {
-            for env_allow in env_allow_vec {
+            // Variables with a source of their own never come from the server's environment
+            for env_allow in env_allow_vec.iter().filter(|env| env.from.is_none()) {
                 if let Some(value) = environment_vars.get(&env_allow.key) {
                     env_vars.insert(env_allow.key.clone(), value.clone());
                 }
@@ -222,13 +582,55 @@ pub(crate) fn extract_env_vars(
     Ok(env_vars)
 }
 
+/// Variables of the policy whose values are secrets of the component, by the names of the secrets
+pub(crate) fn extract_secret_vars(policy: &PolicyDocument) -> HashMap<String, String> {
+    policy
+        .permissions
+        .environment
+        .iter()
+        .flat_map(|env| env.allow.iter().flatten())
+        .filter_map(|env| Some((env.key.clone(), env.secret()?.to_string())))
+        .collect()
+}
+
+fn expand_config_vars(
+    config_vars: HashMap<String, String>,
+    context: &TemplateContext,
+) -> anyhow::Result<HashMap<String, String>> {
+    if config_vars
+        .values()
+        .any(|value| value.contains("{data_dir}"))
+    {
+        std::fs::create_dir_all(&context.data_dir).with_context(|| {
+            format!(
+                "Failed to create data directory {}",
+                context.data_dir.display()
+            )
+        })?;
+    }
+    Ok(config_vars
+        .into_iter()
+        .map(|(key, value)| (key, context.expand(&value)))
+        .collect())
+}
+
 pub(crate) fn extract_network_perms(policy: &PolicyDocument) -> NetworkPermissions {
     if let Some(network_perms) = &policy.permissions.network {
         let has_network_perms =
             network_perms.allow.is_some() && !network_perms.allow.as_ref().unwrap().is_empty();
+        let has_udp_grants = network_perms
+            .allow
+            .iter()
+            .flatten()
+            .any(|perm| matches!(perm, NetworkPermission::Udp(_)));
+        let has_tcp_grants = network_perms
+            .allow
+            .iter()
+            .flatten()
+            .any(|perm| matches!(perm, NetworkPermission::Tcp(_)));
         NetworkPermissions {
-            allow_tcp: has_network_perms,
-            allow_udp: has_network_perms,
+            allow_tcp: has_tcp_grants,
+            allow_udp: has_udp_grants,
             allow_ip_name_lookup: has_network_perms,
         }
     } else {
@@ -256,6 +658,110 @@ pub(crate) fn extract_allowed_hosts(policy: &PolicyDocument) -> HashSet<String>
     allowed_hosts
 }
 
+/// Extract the deterministic execution settings of the policy, if it asks for them
+pub(crate) fn extract_deterministic_runtime(
+    policy: &PolicyDocument,
+) -> Option<DeterministicRuntime> {
+    policy
+        .permissions
+        .runtime
+        .as_ref()
+        .and_then(|runtime| runtime.deterministic.clone())
+}
+
+/// Extract the wasi:keyvalue buckets the policy allows the component to open
+pub(crate) fn extract_keyvalue_buckets(policy: &PolicyDocument) -> HashSet<String> {
+    policy
+        .permissions
+        .keyvalue
+        .iter()
+        .flat_map(|keyvalue| keyvalue.allow.iter().flatten())
+        .map(|perm| perm.bucket.clone())
+        .collect()
+}
+
+/// Extract the wasi:blobstore containers the policy allows the component to use and the backend
+/// that stores them
+pub(crate) fn extract_blobstore_permissions(
+    policy: &PolicyDocument,
+) -> (HashSet<String>, policy::BlobstoreBackend) {
+    let blobstore = policy.permissions.blobstore.as_ref();
+    let containers = blobstore
+        .iter()
+        .flat_map(|blobstore| blobstore.allow.iter().flatten())
+        .map(|perm| perm.container.clone())
+        .collect();
+    let backend = blobstore
+        .and_then(|blobstore| blobstore.backend.clone())
+        .unwrap_or_default();
+    (containers, backend)
+}
+
+/// Extract the wasi:sql databases the policy allows the component to open. A database file given
+/// by `path` has to be inside one of the component's storage grants and is read-only unless the
+/// grant allows writing.
+pub(crate) fn extract_databases(
+    policy: &PolicyDocument,
+    preopened_dirs: &[PreopenedDir],
+) -> anyhow::Result<HashMap<String, SqlDatabase>> {
+    let mut databases = HashMap::new();
+    for perm in policy
+        .permissions
+        .database
+        .iter()
+        .flat_map(|database| database.allow.iter().flatten())
+    {
+        let database = match (&perm.path, &perm.dsn) {
+            (Some(path), None) => database_in_storage(path, preopened_dirs)?,
+            (None, Some(dsn)) => SqlDatabase::from_dsn(dsn)?,
+            _ => anyhow::bail!(
+                "Database {} needs either a path or a dsn, but not both",
+                perm.name
+            ),
+        };
+        databases.insert(perm.name.clone(), database);
+    }
+    Ok(databases)
+}
+
+fn database_in_storage(uri: &str, preopened_dirs: &[PreopenedDir]) -> anyhow::Result<SqlDatabase> {
+    let path = Path::new(uri.strip_prefix("fs://").unwrap_or(uri));
+    if path.components().any(|c| c == Component::ParentDir) {
+        anyhow::bail!("Database path {uri} must not contain '..'");
+    }
+    // The most specific grant decides whether the file can be changed
+    let dir = preopened_dirs
+        .iter()
+        .filter(|dir| path.starts_with(&dir.guest_path) && path != Path::new(&dir.guest_path))
+        .max_by_key(|dir| dir.guest_path.len())
+        .ok_or_else(|| {
+            anyhow::anyhow!("Database file {uri} is not inside a storage grant of the component")
+        })?;
+    let relative = path.strip_prefix(&dir.guest_path)?;
+    Ok(SqlDatabase::File {
+        path: dir.host_path.join(relative),
+        read_only: !dir.file_perms.contains(wasmtime_wasi::FilePerms::WRITE),
+    })
+}
+
+/// Derive the DNS allowlist from the granted network hosts. Only the host name of each grant is
+/// kept, so `https://api.example.com:8443` allows resolving `api.example.com`.
+pub(crate) fn extract_allowed_dns_names(allowed_hosts: &HashSet<String>) -> HashSet<String> {
+    allowed_hosts
+        .iter()
+        .filter_map(|host| {
+            let url = if host.contains("://") {
+                url::Url::parse(host)
+            } else {
+                url::Url::parse(&format!("http://{host}"))
+            };
+            url.ok()
+                .and_then(|url| url.host_str().and_then(crate::http::canonical_host))
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
 pub(crate) fn extract_storage_permissions(
     policy: &PolicyDocument,
     plugin_dir: &Path,
@@ -329,6 +835,66 @@ pub(crate) fn extract_memory_limit(policy: &PolicyDocument) -> anyhow::Result<Op
     Ok(None)
 }
 
+/// Build the store limits from the memory limit and the `table_elements`, `instances`, `tables`
+/// and `memories` limits of the policy. Returns `None` if none of them is set.
+pub(crate) fn extract_store_limits(
+    policy: &PolicyDocument,
+    memory_limit: Option<u64>,
+) -> anyhow::Result<Option<wasmtime::StoreLimits>> {
+    let limits = policy
+        .permissions
+        .resources
+        .as_ref()
+        .and_then(|resources| resources.limits.as_ref());
+    let limit = |name: &str, value: Option<u64>| -> anyhow::Result<Option<usize>> {
+        value
+            .map(|value| {
+                value.try_into().map_err(|_| {
+                    anyhow::anyhow!("Limit {name} of {value} too large for target architecture")
+                })
+            })
+            .transpose()
+    };
+    let memory_size = limit("memory", memory_limit)?;
+    let table_elements = limit("table_elements", limits.and_then(|l| l.table_elements))?;
+    let instances = limit("instances", limits.and_then(|l| l.instances))?;
+    let tables = limit("tables", limits.and_then(|l| l.tables))?;
+    let memories = limit("memories", limits.and_then(|l| l.memories))?;
+    if [memory_size, table_elements, instances, tables, memories]
+        .iter()
+        .all(Option::is_none)
+    {
+        return Ok(None);
+    }
+
+    let mut builder = wasmtime::StoreLimitsBuilder::new();
+    if let Some(size) = memory_size {
+        builder = builder.memory_size(size);
+    }
+    if let Some(elements) = table_elements {
+        builder = builder.table_elements(elements);
+    }
+    if let Some(instances) = instances {
+        builder = builder.instances(instances);
+    }
+    if let Some(tables) = tables {
+        builder = builder.tables(tables);
+    }
+    if let Some(memories) = memories {
+        builder = builder.memories(memories);
+    }
+    Ok(Some(builder.build()))
+}
+
+/// Extract the outgoing HTTP limits of the policy document
+pub(crate) fn extract_http_limits(policy: &PolicyDocument) -> Option<policy::HttpLimits> {
+    policy
+        .permissions
+        .resources
+        .as_ref()
+        .and_then(|resources| resources.http.clone())
+}
+
 #[cfg(test)]
 mod tests {
     use policy::{AccessType, PolicyParser};
@@ -510,16 +1076,133 @@ permissions:
         assert!(extracted_vars.is_empty());
     }
 
+    #[test]
+    fn test_restrict() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let callee = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+permissions:
+  network:
+    allow:
+      - host: "api.example.com"
+      - host: "cdn.example.com"
+  environment:
+    allow:
+      - key: "API_KEY"
+      - key: "REGION"
+  storage:
+    allow:
+      - uri: "fs:///data"
+        access: ["read", "write"]
+      - uri: "fs:///cache"
+        access: ["read"]
+  keyvalue:
+    allow:
+      - bucket: "cache"
+  sampling:
+    max_tokens: 200
+"#,
+        )?;
+        let caller = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+permissions:
+  network:
+    allow:
+      - host: "https://api.example.com"
+  environment:
+    allow:
+      - key: "REGION"
+  storage:
+    allow:
+      - uri: "fs:///data/pages"
+        access: ["read"]
+"#,
+        )?;
+        let env_vars = HashMap::from([
+            ("API_KEY".to_string(), "secret".to_string()),
+            ("REGION".to_string(), "eu".to_string()),
+        ]);
+        let context = TemplateContext::default();
+        let callee =
+            create_wasi_state_template_from_policy(&callee, temp_dir.path(), &env_vars, &context)?;
+        let caller =
+            create_wasi_state_template_from_policy(&caller, temp_dir.path(), &env_vars, &context)?;
+
+        let restricted = callee.restrict(&caller.delegation_scope());
+        assert_eq!(
+            restricted.allowed_hosts,
+            HashSet::from(["https://api.example.com".to_string()])
+        );
+        assert_eq!(
+            restricted.config_vars,
+            HashMap::from([("REGION".to_string(), "eu".to_string())])
+        );
+        assert_eq!(restricted.preopened_dirs.len(), 1);
+        let dir = &restricted.preopened_dirs[0];
+        assert_eq!(dir.host_path, PathBuf::from("/data/pages"));
+        assert_eq!(dir.guest_path, "/data/pages");
+        assert_eq!(dir.file_perms, wasmtime_wasi::FilePerms::READ);
+        assert!(restricted.keyvalue_buckets.is_empty());
+        assert!(callee.sampling.is_some());
+        assert_eq!(restricted.sampling, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_var_templates() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let policy = create_test_policy();
+        let env_vars = HashMap::from([(
+            "TEST_VAR".to_string(),
+            "{data_dir}/cache-{session_id}.db?owner={component_id}&opts={}".to_string(),
+        )]);
+        let context = TemplateContext::new(temp_dir.path(), "notes", "session-1");
+
+        let template =
+            create_wasi_state_template_from_policy(&policy, temp_dir.path(), &env_vars, &context)?;
+
+        let data_dir = temp_dir.path().join(DATA_DIR).join("notes");
+        assert_eq!(
+            template.config_vars["TEST_VAR"],
+            format!(
+                "{}/cache-session-1.db?owner=notes&opts={{}}",
+                data_dir.display()
+            )
+        );
+        assert!(data_dir.is_dir());
+        Ok(())
+    }
+
     #[test]
     fn test_extract_network_permissions_with_allow() {
         let policy = create_test_policy();
         let network_perms = extract_network_perms(&policy);
 
-        assert!(network_perms.allow_tcp);
-        assert!(network_perms.allow_udp);
+        // Host grants are for HTTP and don't open raw TCP or UDP sockets
+        assert!(!network_perms.allow_tcp);
+        assert!(!network_perms.allow_udp);
         assert!(network_perms.allow_ip_name_lookup);
     }
 
+    #[test]
+    fn test_extract_network_permissions_with_udp_grant() {
+        let yaml_content = r#"
+version: "1.0"
+description: "Policy with a UDP grant"
+permissions:
+  network:
+    allow:
+      - udp: "pool.ntp.org"
+        port: 123
+"#;
+        let policy = PolicyParser::parse_str(yaml_content).unwrap();
+        let network_perms = extract_network_perms(&policy);
+
+        assert!(network_perms.allow_udp);
+    }
+
     #[test]
     fn test_extract_network_permissions_no_permissions() {
         let policy = create_zero_permission_policy();
@@ -657,13 +1340,287 @@ permissions:
         let policy = create_test_policy();
         let env_vars = HashMap::new(); // Empty environment for test
 
-        let template =
-            create_wasi_state_template_from_policy(&policy, plugin_dir, &env_vars).unwrap();
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            plugin_dir,
+            &env_vars,
+            &TemplateContext::default(),
+        )
+        .unwrap();
 
-        assert!(template.network_perms.allow_tcp);
-        assert!(template.network_perms.allow_udp);
+        assert!(!template.network_perms.allow_tcp);
+        assert!(!template.network_perms.allow_udp);
         assert!(template.network_perms.allow_ip_name_lookup);
+        assert!(template.udp_grants.is_empty());
+        assert!(template.tcp_grants.is_empty());
         assert_eq!(template.preopened_dirs.len(), 3);
+        assert_eq!(
+            template.allowed_dns_names,
+            HashSet::from(["api.example.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_create_wasi_state_template_with_udp_grants() {
+        let temp_dir = TempDir::new().unwrap();
+        let yaml_content = r#"
+version: "1.0"
+description: "Policy with UDP grants"
+permissions:
+  network:
+    allow:
+      - udp: "Pool.NTP.org"
+        port: 123
+      - udp: "8.8.8.8"
+        port: 53
+"#;
+        let policy = PolicyParser::parse_str(yaml_content).unwrap();
+
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            temp_dir.path(),
+            &HashMap::new(),
+            &TemplateContext::default(),
+        )
+        .unwrap();
+
+        assert!(template.network_perms.allow_udp);
+        assert_eq!(template.udp_grants.len(), 2);
+        assert!(template.allowed_hosts.is_empty());
+        assert_eq!(
+            template.allowed_dns_names,
+            HashSet::from(["pool.ntp.org".to_string()])
+        );
+        assert!(template.build().is_ok());
+    }
+
+    #[test]
+    fn test_create_wasi_state_template_with_tcp_grants() {
+        let temp_dir = TempDir::new().unwrap();
+        let yaml_content = r#"
+version: "1.0"
+description: "Policy with TCP grants"
+permissions:
+  network:
+    allow:
+      - tcp: "tcp://DB.internal:5432"
+      - tcp: "10.0.0.0/8"
+        port: 25
+"#;
+        let policy = PolicyParser::parse_str(yaml_content).unwrap();
+
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            temp_dir.path(),
+            &HashMap::new(),
+            &TemplateContext::default(),
+        )
+        .unwrap();
+
+        assert!(template.network_perms.allow_tcp);
+        assert!(!template.network_perms.allow_udp);
+        assert_eq!(template.tcp_grants.len(), 2);
+        assert_eq!(
+            template.allowed_dns_names,
+            HashSet::from(["db.internal".to_string()])
+        );
+        assert!(template.build().is_ok());
+    }
+
+    #[test]
+    fn test_extract_allowed_dns_names() {
+        let allowed_hosts = HashSet::from([
+            "api.example.com".to_string(),
+            "https://Secure.Example.com:8443".to_string(),
+            "localhost:8080".to_string(),
+            "http://".to_string(),
+        ]);
+
+        let dns_names = extract_allowed_dns_names(&allowed_hosts);
+
+        assert_eq!(
+            dns_names,
+            HashSet::from([
+                "api.example.com".to_string(),
+                "secure.example.com".to_string(),
+                "localhost".to_string(),
+            ])
+        );
+        assert!(extract_allowed_dns_names(&HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_extract_keyvalue_buckets() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Key-value buckets"
+permissions:
+  keyvalue:
+    allow:
+      - bucket: "cache"
+      - bucket: "sessions"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            extract_keyvalue_buckets(&policy),
+            HashSet::from(["cache".to_string(), "sessions".to_string()])
+        );
+        assert!(extract_keyvalue_buckets(&create_policy_without_permissions()).is_empty());
+    }
+
+    #[test]
+    fn test_extract_blobstore_permissions() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Blobstore containers"
+permissions:
+  blobstore:
+    backend:
+      type: s3
+      endpoint: "https://s3.example.com"
+      bucket: "artifacts"
+    allow:
+      - container: "reports"
+"#,
+        )
+        .unwrap();
+
+        let (containers, backend) = extract_blobstore_permissions(&policy);
+        assert_eq!(containers, HashSet::from(["reports".to_string()]));
+        assert!(matches!(
+            backend,
+            policy::BlobstoreBackend::S3 { bucket, .. } if bucket == "artifacts"
+        ));
+
+        let (containers, backend) =
+            extract_blobstore_permissions(&create_policy_without_permissions());
+        assert!(containers.is_empty());
+        assert!(matches!(
+            backend,
+            policy::BlobstoreBackend::Filesystem { path: None }
+        ));
+    }
+
+    #[test]
+    fn test_extract_databases() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Databases"
+permissions:
+  storage:
+    allow:
+      - uri: "fs:///data"
+        access: ["read"]
+      - uri: "fs:///data/scratch"
+        access: ["read", "write"]
+  database:
+    allow:
+      - name: "sales"
+        path: "fs:///data/sales.db"
+      - name: "notes"
+        path: "fs:///data/scratch/notes.db"
+      - name: "shared"
+        dsn: "sqlite:///var/lib/wassette/shared.db?mode=ro"
+"#,
+        )
+        .unwrap();
+        let plugin_dir = Path::new("/plugins");
+        let preopened_dirs = extract_storage_permissions(&policy, plugin_dir).unwrap();
+
+        let databases = extract_databases(&policy, &preopened_dirs).unwrap();
+        assert_eq!(
+            databases["sales"],
+            SqlDatabase::File {
+                path: PathBuf::from("/data/sales.db"),
+                read_only: true,
+            }
+        );
+        assert_eq!(
+            databases["notes"],
+            SqlDatabase::File {
+                path: PathBuf::from("/data/scratch/notes.db"),
+                read_only: false,
+            }
+        );
+        assert_eq!(
+            databases["shared"],
+            SqlDatabase::File {
+                path: PathBuf::from("/var/lib/wassette/shared.db"),
+                read_only: true,
+            }
+        );
+
+        for path in ["fs:///etc/app.db", "fs:///data/../etc/app.db", "fs:///data"] {
+            let policy = PolicyParser::parse_str(format!(
+                "version: \"1.0\"\npermissions:\n  database:\n    allow:\n      - name: db\n        path: \"{path}\"\n"
+            ))
+            .unwrap();
+            assert!(
+                extract_databases(&policy, &preopened_dirs).is_err(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_http_limits() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "HTTP limits"
+permissions:
+  resources:
+    http:
+      max_response_size: "10Mi"
+      read_timeout: "2m"
+"#,
+        )
+        .unwrap();
+
+        let limits = extract_http_limits(&policy).unwrap();
+        assert_eq!(
+            limits.max_response_size.unwrap().to_bytes().unwrap(),
+            10 << 20
+        );
+        assert_eq!(limits.connect_timeout, None);
+        assert!(extract_http_limits(&create_policy_without_permissions()).is_none());
+
+        assert!(PolicyParser::parse_str(
+            r#"
+version: "1.0"
+permissions:
+  resources:
+    http:
+      connect_timeout: "soon"
+"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_extract_deterministic_runtime() {
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Deterministic execution"
+permissions:
+  runtime:
+    deterministic:
+      clock: fixed
+      seed: 42
+"#,
+        )
+        .unwrap();
+
+        let deterministic = extract_deterministic_runtime(&policy).unwrap();
+        assert_eq!(deterministic.clock, policy::VirtualClock::Fixed);
+        assert_eq!(deterministic.seed, 42);
+        assert!(extract_deterministic_runtime(&create_policy_without_permissions()).is_none());
     }
 
     #[test]
@@ -673,14 +1630,20 @@ permissions:
         let policy = create_policy_without_permissions();
         let env_vars = HashMap::new(); // Empty environment for test
 
-        let template =
-            create_wasi_state_template_from_policy(&policy, plugin_dir, &env_vars).unwrap();
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            plugin_dir,
+            &env_vars,
+            &TemplateContext::default(),
+        )
+        .unwrap();
 
         assert!(!template.network_perms.allow_tcp);
         assert!(!template.network_perms.allow_udp);
         assert!(!template.network_perms.allow_ip_name_lookup);
         assert!(template.config_vars.is_empty());
         assert!(template.preopened_dirs.is_empty());
+        assert!(template.allowed_dns_names.is_empty());
         assert!(template.allow_stdout);
         assert!(template.allow_stderr);
         assert!(template.allow_args);
@@ -735,13 +1698,75 @@ permissions:
 "#;
         let policy = PolicyParser::parse_str(yaml_content).unwrap();
         let env_vars = HashMap::new(); // Empty environment for test
-        let template =
-            create_wasi_state_template_from_policy(&policy, plugin_dir, &env_vars).unwrap();
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            plugin_dir,
+            &env_vars,
+            &TemplateContext::default(),
+        )
+        .unwrap();
 
         assert_eq!(template.memory_limit, Some(512 * 1024 * 1024));
         assert!(template.store_limits.is_some());
     }
 
+    #[test]
+    fn test_extract_store_limits() {
+        use wasmtime::ResourceLimiter as _;
+
+        let policy = PolicyParser::parse_str(
+            r#"
+version: "1.0"
+description: "Count limits"
+permissions:
+  resources:
+    limits:
+      table_elements: 5000
+      instances: 12
+"#,
+        )
+        .unwrap();
+        let limits = extract_store_limits(&policy, None).unwrap().unwrap();
+        let mut limiter = CustomResourceLimiter::new(limits);
+        assert_eq!(limiter.instances(), 12);
+        assert!(limiter.table_growing(0, 5000, None).unwrap());
+        assert!(!limiter.table_growing(0, 5001, None).unwrap());
+
+        assert!(extract_store_limits(&create_zero_permission_policy(), None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_scratch_directory_per_state() -> anyhow::Result<()> {
+        let template = WasiStateTemplate::default();
+        let first = template.build()?;
+        let second = template.build()?;
+
+        let first_path = first.scratch_dir().unwrap().to_path_buf();
+        let second_path = second.scratch_dir().unwrap().to_path_buf();
+        assert_ne!(first_path, second_path);
+        std::fs::write(first_path.join("scratch.txt"), "temporary")?;
+
+        drop(first);
+        assert!(!first_path.exists());
+        assert!(second_path.exists());
+
+        // A storage grant for /tmp takes the place of the scratch directory
+        let temp_dir = TempDir::new()?;
+        let template = WasiStateTemplate {
+            preopened_dirs: vec![PreopenedDir {
+                host_path: temp_dir.path().to_path_buf(),
+                guest_path: SCRATCH_GUEST_PATH.to_string(),
+                dir_perms: wasmtime_wasi::DirPerms::READ,
+                file_perms: wasmtime_wasi::FilePerms::READ,
+            }],
+            ..Default::default()
+        };
+        assert!(template.build()?.scratch_dir().is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_memory_resource_end_to_end() -> anyhow::Result<()> {
         let temp_dir = TempDir::new().unwrap();
@@ -764,8 +1789,13 @@ permissions:
 
         // Test that WASI state template is created with memory limit
         let env_vars = HashMap::new(); // Empty environment for test
-        let template =
-            create_wasi_state_template_from_policy(&policy, plugin_dir, &env_vars).unwrap();
+        let template = create_wasi_state_template_from_policy(
+            &policy,
+            plugin_dir,
+            &env_vars,
+            &TemplateContext::default(),
+        )
+        .unwrap();
         assert_eq!(template.memory_limit, Some(1024 * 1024 * 1024));
         assert!(template.store_limits.is_some());
 
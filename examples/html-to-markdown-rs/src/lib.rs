@@ -0,0 +1,152 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+#[allow(warnings)]
+mod bindings;
+
+use bindings::Guest;
+use scraper::{ElementRef, Html, Node};
+
+struct Component;
+
+impl Guest for Component {
+    fn convert(html: String) -> Result<String, String> {
+        let document = Html::parse_document(&html);
+        let mut markdown = String::new();
+        write_children(document.root_element(), &mut markdown);
+        Ok(tidy(&markdown))
+    }
+}
+
+fn write_children(element: ElementRef, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => out.push_str(&collapse_whitespace(text)),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    write_element(child, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn inner_markdown(element: ElementRef) -> String {
+    let mut text = String::new();
+    write_children(element, &mut text);
+    text.trim().to_string()
+}
+
+fn write_element(element: ElementRef, out: &mut String) {
+    let name = element.value().name();
+    match name {
+        "script" | "style" | "noscript" | "template" | "head" => {}
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap_or(1);
+            out.push_str(&format!(
+                "\n\n{} {}\n\n",
+                "#".repeat(level),
+                inner_markdown(element)
+            ));
+        }
+        "p" | "div" | "section" | "article" | "header" | "footer" | "main" => {
+            out.push_str("\n\n");
+            write_children(element, out);
+            out.push_str("\n\n");
+        }
+        "br" => out.push('\n'),
+        "hr" => out.push_str("\n\n---\n\n"),
+        "strong" | "b" => out.push_str(&format!("**{}**", inner_markdown(element))),
+        "em" | "i" => out.push_str(&format!("_{}_", inner_markdown(element))),
+        "code" => out.push_str(&format!("`{}`", element.text().collect::<String>())),
+        "pre" => out.push_str(&format!(
+            "\n\n```\n{}\n```\n\n",
+            element.text().collect::<String>().trim_end()
+        )),
+        "a" => {
+            let text = inner_markdown(element);
+            match element.value().attr("href") {
+                Some(href) => out.push_str(&format!("[{text}]({href})")),
+                None => out.push_str(&text),
+            }
+        }
+        "img" => {
+            if let Some(src) = element.value().attr("src") {
+                let alt = element.value().attr("alt").unwrap_or_default();
+                out.push_str(&format!("![{alt}]({src})"));
+            }
+        }
+        "ul" | "ol" => {
+            out.push_str("\n\n");
+            let items = element
+                .children()
+                .filter_map(ElementRef::wrap)
+                .filter(|child| child.value().name() == "li");
+            for (index, item) in items.enumerate() {
+                let marker = if name == "ol" {
+                    format!("{}.", index + 1)
+                } else {
+                    "-".to_string()
+                };
+                out.push_str(&format!("{marker} {}\n", inner_markdown(item)));
+            }
+            out.push('\n');
+        }
+        "blockquote" => {
+            out.push_str("\n\n");
+            for line in inner_markdown(element).lines() {
+                out.push_str(&format!("> {line}\n"));
+            }
+            out.push('\n');
+        }
+        _ => write_children(element, out),
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last_was_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !last_was_space {
+                collapsed.push(' ');
+            }
+            last_was_space = true;
+        } else {
+            collapsed.push(c);
+            last_was_space = false;
+        }
+    }
+    collapsed
+}
+
+/// Trims every line outside code blocks and keeps at most one blank line between blocks
+fn tidy(markdown: &str) -> String {
+    let mut tidied = String::new();
+    let mut blank = true;
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if in_code {
+            in_code = line != "```";
+            tidied.push_str(line);
+            tidied.push('\n');
+            continue;
+        }
+        let line = line.trim();
+        in_code = line == "```";
+        if line.is_empty() {
+            if !blank {
+                tidied.push('\n');
+            }
+            blank = true;
+        } else {
+            tidied.push_str(line);
+            tidied.push('\n');
+            blank = false;
+        }
+    }
+    tidied.trim().to_string()
+}
+
+bindings::export!(Component with_types_in bindings);
@@ -0,0 +1,330 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! API key authentication for the HTTP transports
+//!
+//! Keys are defined in the `api_keys` table array of the configuration file. With at least one
+//! key, every request to the MCP endpoints needs one of them, as `Authorization: Bearer <key>` or
+//! `X-API-Key: <key>`. Each key can list the tools and components it may call and carry rate
+//! limits of its own, which the tool pipeline enforces for the calls that come with the key.
+//! Only hashes of the keys are kept in memory.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use mcp_server::{ApiKeyAccess, ApiKeys, RateLimitRule, RateLimiter};
+use ring::digest::{digest, SHA256};
+use rmcp::model::Extensions;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Path of the streamable HTTP transport, the only one that passes request headers on to tool
+/// calls
+const STREAMABLE_HTTP_PATH: &str = "/mcp";
+
+/// Whether `path` is served by the streamable HTTP transport. A prefix match would also let
+/// through paths such as `/mcpadmin/...` of a management API mounted next to it.
+fn is_streamable_http_path(path: &str) -> bool {
+    path.strip_prefix(STREAMABLE_HTTP_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// An API key as defined in the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ApiKeyConfig {
+    /// Name of the key, used in logs and errors
+    pub name: String,
+    /// The key itself
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Environment variable to read the key from, instead of `key`
+    #[serde(default)]
+    pub key_env: Option<String>,
+    /// Rate limits of the key's calls, as `<tool>=<calls>/<period>`
+    #[serde(default)]
+    pub rate_limit: Vec<String>,
+    /// Tools the key may call. Every tool if neither `tools` nor `components` is set.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    /// Components whose tools the key may call
+    #[serde(default)]
+    pub components: Option<Vec<String>>,
+}
+
+impl ApiKeyConfig {
+    fn secret(&self) -> Result<String> {
+        let secret = match (&self.key, &self.key_env) {
+            (Some(key), None) => key.clone(),
+            (None, Some(var)) => std::env::var(var)
+                .with_context(|| format!("Failed to read API key '{}' from {var}", self.name))?,
+            _ => bail!(
+                "API key '{}' needs exactly one of key and key_env",
+                self.name
+            ),
+        };
+        if secret.trim().is_empty() {
+            bail!("API key '{}' is empty", self.name);
+        }
+        Ok(secret)
+    }
+
+    fn access(&self) -> Result<ApiKeyAccess> {
+        Ok(ApiKeyAccess {
+            name: self.name.clone(),
+            rate_limits: RateLimiter::new(
+                self.rate_limit
+                    .iter()
+                    .map(|rule| rule.parse::<RateLimitRule>())
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("Invalid rate limit of API key '{}'", self.name))?,
+            ),
+            tools: self.tools.clone(),
+            components: self.components.clone(),
+        })
+    }
+}
+
+/// The key a request was authenticated with, added to the extensions of the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyName(pub String);
+
+/// Who holds a key, looked up by the hash of the key
+#[derive(Debug, Clone)]
+struct Holder {
+    name: String,
+    restricted: bool,
+}
+
+/// Authenticates the requests to the HTTP transports by their API keys
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth {
+    holders: Arc<HashMap<Vec<u8>, Holder>>,
+}
+
+impl ApiKeyAuth {
+    /// Creates the authenticator of `keys` and what each of them may call, or `None` if there are
+    /// no keys
+    pub fn new(keys: &[ApiKeyConfig]) -> Result<Option<(Self, ApiKeys)>> {
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let mut holders = HashMap::new();
+        let mut access = Vec::new();
+        for key in keys {
+            if key.name.is_empty() {
+                bail!("API keys need a name");
+            }
+            if access
+                .iter()
+                .any(|access: &ApiKeyAccess| access.name == key.name)
+            {
+                bail!("API key name '{}' is used more than once", key.name);
+            }
+            let key_access = key.access()?;
+            let holder = Holder {
+                name: key.name.clone(),
+                restricted: key_access.is_restricted() || !key.rate_limit.is_empty(),
+            };
+            if holders.insert(hash(&key.secret()?), holder).is_some() {
+                bail!("API key '{}' is the same as another key", key.name);
+            }
+            access.push(key_access);
+        }
+        Ok(Some((
+            Self {
+                holders: Arc::new(holders),
+            },
+            ApiKeys::new(access),
+        )))
+    }
+
+    fn holder(&self, headers: &HeaderMap) -> Option<&Holder> {
+        let key = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| headers.get(X_API_KEY).and_then(|value| value.to_str().ok()))?;
+        self.holders.get(&hash(key.trim()))
+    }
+}
+
+fn hash(key: &str) -> Vec<u8> {
+    digest(&SHA256, key.as_bytes()).as_ref().to_vec()
+}
+
+fn reject(status: StatusCode, description: &str) -> Response {
+    let mut response = (
+        status,
+        Json(json!({ "error": "invalid_api_key", "error_description": description })),
+    )
+        .into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer realm=\"wassette\""),
+        );
+    }
+    response
+}
+
+/// Middleware that lets requests through only with a known API key, and tells the tool pipeline
+/// which key it was
+pub async fn authenticate(
+    State(auth): State<ApiKeyAuth>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(holder) = auth.holder(request.headers()) else {
+        return reject(
+            StatusCode::UNAUTHORIZED,
+            "The request needs a known API key",
+        );
+    };
+    // The SSE transport doesn't pass the request on to tool calls, so the allowlists and limits
+    // of the key could not be enforced there
+    if holder.restricted && !is_streamable_http_path(request.uri().path()) {
+        tracing::info!(api_key = holder.name, path = %request.uri().path(), "Rejected API key");
+        return reject(
+            StatusCode::FORBIDDEN,
+            "API keys with tool allowlists or rate limits can only be used with the streamable HTTP transport",
+        );
+    }
+    tracing::debug!(api_key = holder.name, "Authenticated request");
+    request
+        .extensions_mut()
+        .insert(ApiKeyName(holder.name.clone()));
+    next.run(request).await
+}
+
+/// The name of the API key of the HTTP request that an MCP request came with, from the extensions
+/// of its request context
+pub fn api_key_name(extensions: &Extensions) -> Option<&str> {
+    extensions
+        .get::<Parts>()?
+        .extensions
+        .get::<ApiKeyName>()
+        .map(|name| name.0.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::routing::post;
+    use axum::Router;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    fn keys() -> Vec<ApiKeyConfig> {
+        vec![
+            ApiKeyConfig {
+                name: "triage-bot".to_string(),
+                key: Some("triage-secret".to_string()),
+                rate_limit: vec!["*=10/1m".to_string()],
+                tools: Some(vec!["list-components".to_string()]),
+                ..Default::default()
+            },
+            ApiKeyConfig {
+                name: "admin".to_string(),
+                key: Some("admin-secret".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_api_key_auth() {
+        let (_, access) = ApiKeyAuth::new(&keys()).unwrap().unwrap();
+        let triage = access.get("triage-bot").unwrap();
+        assert_eq!(triage.tools, Some(vec!["list-components".to_string()]));
+        assert!(triage.rate_limits.status("fetch").is_some());
+        assert!(ApiKeyAuth::new(&[]).unwrap().is_none());
+
+        let mut duplicate = keys();
+        duplicate[1].key = Some("triage-secret".to_string());
+        assert!(ApiKeyAuth::new(&duplicate).is_err());
+        let mut both = keys();
+        both[0].key_env = Some("TRIAGE_KEY".to_string());
+        assert!(ApiKeyAuth::new(&both).is_err());
+        let mut invalid = keys();
+        invalid[0].rate_limit = vec!["10/1m".to_string()];
+        assert!(ApiKeyAuth::new(&invalid).is_err());
+    }
+
+    async fn send(router: &Router, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::builder().method("POST").uri(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let (auth, _) = ApiKeyAuth::new(&keys()).unwrap().unwrap();
+        let echo_key = |request: Request| async move {
+            request
+                .extensions()
+                .get::<ApiKeyName>()
+                .map(|name| name.0.clone())
+                .unwrap_or_default()
+        };
+        let router = Router::new()
+            .route("/mcp", post(echo_key))
+            .route("/message", post(echo_key))
+            .route("/mcpadmin/api/components", post(echo_key))
+            .layer(axum::middleware::from_fn_with_state(auth, authenticate));
+
+        let response = send(&router, "/mcp", &[]).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+        let response = send(&router, "/mcp", &[("x-api-key", "wrong")]).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(
+            &router,
+            "/mcp",
+            &[("authorization", "Bearer triage-secret")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "triage-bot");
+        let response = send(&router, "/message", &[("x-api-key", "admin-secret")]).await;
+        assert_eq!(body(response).await, "admin");
+
+        // Restricted keys can't be enforced on the SSE transport
+        let response = send(&router, "/message", &[("x-api-key", "triage-secret")]).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // Nor on paths that merely start with the path of the streamable HTTP transport
+        let response = send(
+            &router,
+            "/mcpadmin/api/components",
+            &[("x-api-key", "triage-secret")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(is_streamable_http_path("/mcp/"));
+        assert!(!is_streamable_http_path("/mcp-other"));
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Message-bus frontend for tool invocation
+//!
+//! Tool calls can arrive on a NATS subject or an MQTT topic instead of an MCP transport. Each
+//! message is a JSON [`BusRequest`]; the result is published back as a [`BusResponse`]. Calls run
+//! through [`mcp_server::execute_tool_call`], the same pipeline that serves MCP `tools/call`.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use mcp_server::{
+    execute_tool_call, LifecycleManager, Priority, RateLimitExceeded, ToolCallOptions,
+};
+use rmcp::model::CallToolRequestParam;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use wassette::{NatsClient, DEFAULT_NATS_PORT};
+
+const DEFAULT_MQTT_PORT: u16 = 1883;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Where tool calls are received from
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusEndpoint {
+    /// `nats://host[:port]/subject[?group=name]`
+    Nats {
+        address: String,
+        subject: String,
+        group: Option<String>,
+    },
+    /// `mqtt://host[:port]/topic[?group=name]`
+    Mqtt {
+        host: String,
+        port: u16,
+        topic: String,
+        group: Option<String>,
+    },
+}
+
+impl FromStr for BusEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url = url::Url::parse(s).with_context(|| format!("Invalid bus URI: {s}"))?;
+        let host = url
+            .host_str()
+            .with_context(|| format!("Bus URI is missing a host: {s}"))?
+            .to_string();
+        let path = url.path().trim_start_matches('/');
+        if path.is_empty() {
+            bail!("Bus URI is missing a subject or topic: {s}");
+        }
+        let group = url
+            .query_pairs()
+            .find(|(key, _)| key == "group")
+            .map(|(_, value)| value.to_string());
+
+        match url.scheme() {
+            "nats" => Ok(BusEndpoint::Nats {
+                address: format!("{host}:{}", url.port().unwrap_or(DEFAULT_NATS_PORT)),
+                subject: path.replace('/', "."),
+                group,
+            }),
+            "mqtt" => Ok(BusEndpoint::Mqtt {
+                host,
+                port: url.port().unwrap_or(DEFAULT_MQTT_PORT),
+                topic: path.to_string(),
+                group,
+            }),
+            scheme => bail!("Unsupported bus scheme '{scheme}': {s}"),
+        }
+    }
+}
+
+/// A tool call received from the bus
+#[derive(Debug, Deserialize)]
+pub struct BusRequest {
+    /// Correlation ID, echoed in the response
+    #[serde(default)]
+    pub id: Option<Value>,
+    /// Name of the tool to call
+    pub name: String,
+    /// Arguments of the tool call
+    #[serde(default)]
+    pub arguments: Option<Map<String, Value>>,
+    /// Where to publish the response instead of the default reply subject or topic
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// Priority class of the call, `interactive` or `background` (the default)
+    #[serde(default)]
+    pub priority: Option<String>,
+}
+
+/// The outcome of a tool call, published back to the bus
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BusResponse {
+    /// Correlation ID of the request
+    pub id: Option<Value>,
+    /// The `CallToolResult` of the call
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Why the request could not be handled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Limit, remaining calls and retry delay, if the call was rejected by a rate limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<Value>,
+}
+
+/// Runs one bus message through the tool pipeline. Returns the `reply_to` of the request, if it
+/// has one, and the serialized response.
+async fn handle_message(
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+    payload: &[u8],
+) -> (Option<String>, Vec<u8>) {
+    let (reply_to, response) = match serde_json::from_slice::<BusRequest>(payload) {
+        Ok(request) => {
+            let req = CallToolRequestParam {
+                name: request.name.into(),
+                arguments: request.arguments,
+            };
+            // Bus calls are usually jobs nobody waits on, so they queue behind MCP clients
+            let result = match request.priority.as_deref().map(str::parse).transpose() {
+                Ok(priority) => {
+                    let options = ToolCallOptions {
+                        priority: priority.unwrap_or(Priority::Background),
+                        ..options.clone()
+                    };
+                    execute_tool_call(req, lifecycle_manager, None, &options).await
+                }
+                Err(e) => Err(e),
+            };
+            let response = match result {
+                Ok(result) => BusResponse {
+                    id: request.id,
+                    result: Some(result),
+                    error: None,
+                    rate_limit: None,
+                },
+                Err(e) => BusResponse {
+                    id: request.id,
+                    result: None,
+                    error: Some(e.to_string()),
+                    rate_limit: e
+                        .downcast_ref::<RateLimitExceeded>()
+                        .map(RateLimitExceeded::data),
+                },
+            };
+            (request.reply_to, response)
+        }
+        Err(e) => (
+            None,
+            BusResponse {
+                id: None,
+                result: None,
+                error: Some(format!("Invalid tool call request: {e}")),
+                rate_limit: None,
+            },
+        ),
+    };
+
+    let body = serde_json::to_vec(&response).unwrap_or_default();
+    (reply_to, body)
+}
+
+/// Receives tool calls from `endpoint` until the process exits, reconnecting when the
+/// connection to the broker drops
+pub async fn serve_bus(
+    endpoint: BusEndpoint,
+    lifecycle_manager: LifecycleManager,
+    options: ToolCallOptions,
+) {
+    loop {
+        let result = match &endpoint {
+            BusEndpoint::Nats {
+                address,
+                subject,
+                group,
+            } => {
+                serve_nats(
+                    address,
+                    subject,
+                    group.as_deref(),
+                    &lifecycle_manager,
+                    &options,
+                )
+                .await
+            }
+            BusEndpoint::Mqtt {
+                host,
+                port,
+                topic,
+                group,
+            } => {
+                serve_mqtt(
+                    host,
+                    *port,
+                    topic,
+                    group.as_deref(),
+                    &lifecycle_manager,
+                    &options,
+                )
+                .await
+            }
+        };
+        if let Err(e) = result {
+            tracing::warn!(endpoint = ?endpoint, error = %e, "Message bus connection failed");
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn serve_nats(
+    address: &str,
+    subject: &str,
+    group: Option<&str>,
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+) -> Result<()> {
+    let client = std::sync::Arc::new(NatsClient::connect(address).await?);
+    let mut messages = client.subscribe(subject, group).await?;
+    tracing::info!(address, subject, "Receiving tool calls from NATS");
+
+    while let Some(message) = messages.recv().await {
+        let client = client.clone();
+        let lifecycle_manager = lifecycle_manager.clone();
+        let options = options.clone();
+        let default_reply = message
+            .reply_to
+            .clone()
+            .unwrap_or_else(|| format!("{subject}.results"));
+        tokio::spawn(async move {
+            let (reply_to, body) =
+                handle_message(&lifecycle_manager, &options, &message.payload).await;
+            let reply_to = reply_to.unwrap_or(default_reply);
+            if let Err(e) = client.publish(&reply_to, &body).await {
+                tracing::warn!(reply_to, error = %e, "Failed to publish tool call result");
+            }
+        });
+    }
+
+    bail!("Connection to NATS server {address} closed")
+}
+
+async fn serve_mqtt(
+    host: &str,
+    port: u16,
+    topic: &str,
+    group: Option<&str>,
+    lifecycle_manager: &LifecycleManager,
+    options: &ToolCallOptions,
+) -> Result<()> {
+    let client_id = format!("wassette-{}", std::process::id());
+    let mut mqtt_options = MqttOptions::new(client_id, host, port);
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(mqtt_options, 64);
+    // Shared subscriptions spread the calls over every instance in the group
+    let subscription = match group {
+        Some(group) => format!("$share/{group}/{topic}"),
+        None => topic.to_string(),
+    };
+    let default_reply = format!("{topic}/results");
+
+    loop {
+        match eventloop.poll().await? {
+            Event::Incoming(Packet::ConnAck(_)) => {
+                client
+                    .subscribe(subscription.clone(), QoS::AtLeastOnce)
+                    .await?;
+                tracing::info!(host, port, topic, "Receiving tool calls from MQTT");
+            }
+            Event::Incoming(Packet::Publish(publish)) => {
+                let client = client.clone();
+                let lifecycle_manager = lifecycle_manager.clone();
+                let options = options.clone();
+                let default_reply = default_reply.clone();
+                tokio::spawn(async move {
+                    let (reply_to, body) =
+                        handle_message(&lifecycle_manager, &options, &publish.payload).await;
+                    let reply_to = reply_to.unwrap_or(default_reply);
+                    if let Err(e) = client
+                        .publish(&reply_to, QoS::AtLeastOnce, false, body)
+                        .await
+                    {
+                        tracing::warn!(reply_to, error = %e, "Failed to publish tool call result");
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bus_endpoint_parse() {
+        assert_eq!(
+            "nats://localhost/wassette/tools?group=workers"
+                .parse::<BusEndpoint>()
+                .unwrap(),
+            BusEndpoint::Nats {
+                address: "localhost:4222".to_string(),
+                subject: "wassette.tools".to_string(),
+                group: Some("workers".to_string()),
+            }
+        );
+        assert_eq!(
+            "mqtt://broker:8883/wassette/tools"
+                .parse::<BusEndpoint>()
+                .unwrap(),
+            BusEndpoint::Mqtt {
+                host: "broker".to_string(),
+                port: 8883,
+                topic: "wassette/tools".to_string(),
+                group: None,
+            }
+        );
+        assert!("nats://localhost".parse::<BusEndpoint>().is_err());
+        assert!("amqp://localhost/tools".parse::<BusEndpoint>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_message() {
+        let plugin_dir = tempfile::tempdir().unwrap();
+        let lifecycle_manager = LifecycleManager::new(plugin_dir.path()).await.unwrap();
+
+        let options = ToolCallOptions::default();
+        let (reply_to, body) = handle_message(
+            &lifecycle_manager,
+            &options,
+            br#"{"id": 7, "name": "list-components", "reply_to": "replies"}"#,
+        )
+        .await;
+        assert_eq!(reply_to.as_deref(), Some("replies"));
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["id"], 7);
+        assert!(response["result"]["content"].is_array());
+
+        let (_, body) = handle_message(
+            &lifecycle_manager,
+            &options,
+            br#"{"name": "list-components", "priority": "urgent"}"#,
+        )
+        .await;
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .contains("Unknown priority class 'urgent'"));
+
+        let (reply_to, body) = handle_message(&lifecycle_manager, &options, b"not json").await;
+        assert_eq!(reply_to, None);
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid tool call request"));
+    }
+}
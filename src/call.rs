@@ -0,0 +1,229 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! `wassette call`: runs a single tool call and reports how it went
+//!
+//! The call goes straight to the component, without the MCP pipeline, so a failure can be told
+//! apart: a tool that returned an error, a request the component's policy denied, a trap, or a
+//! call that took longer than `--timeout`.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+use mcp_server::LifecycleManager;
+use serde_json::{json, Map, Value};
+use wassette::NetworkRequest;
+
+use crate::diagnostics::{print_value, Failure, FailureClass, OutputMode};
+use crate::repl::parse_value;
+
+/// Parses a `--timeout` such as `30s`, `500ms` or `2m`, or a number of seconds
+pub fn parse_timeout(s: &str) -> Result<Duration> {
+    let limit = match s.parse::<u64>() {
+        Ok(seconds) => wassette::TimeoutLimit::Number(seconds),
+        Err(_) => wassette::TimeoutLimit::String(s.to_string()),
+    };
+    limit.to_duration()
+}
+
+/// Parses the arguments of a call: a single JSON object, or `key=value` pairs
+fn parse_arguments(arguments: &[String]) -> Result<Map<String, Value>> {
+    if let [object] = arguments {
+        if object.starts_with('{') {
+            return serde_json::from_str(object).context("Arguments are not a valid JSON object");
+        }
+    }
+    let mut map = Map::new();
+    for argument in arguments {
+        let (key, value) = argument
+            .split_once('=')
+            .with_context(|| format!("Expected KEY=VALUE, got '{argument}'"))?;
+        map.insert(key.to_string(), parse_value(value));
+    }
+    Ok(map)
+}
+
+/// Turns the result of a component call into the value of the tool, or the failure to report.
+/// Once the policy denied a request, a failed call is reported as a policy denial, since that is
+/// most likely what made it fail.
+fn classify(
+    tool: &str,
+    result: Result<String>,
+    denied_requests: &[NetworkRequest],
+) -> Result<Value, Failure> {
+    let denied = !denied_requests.is_empty();
+    let failure = match result {
+        Ok(text) => {
+            let value = serde_json::from_str(&text).unwrap_or(Value::String(text));
+            // A `result<_, E>` tool returns `{"err": ...}` for its errors
+            let err = match value.as_object().and_then(|object| object.get("err")) {
+                Some(err) if value.as_object().is_some_and(|object| object.len() == 1) => err,
+                _ => return Ok(value),
+            };
+            let err = match err {
+                Value::String(s) => s.clone(),
+                err => err.to_string(),
+            };
+            let class = if denied {
+                FailureClass::PolicyDenied
+            } else {
+                FailureClass::ToolError
+            };
+            Failure::new(class, format!("Tool {tool} returned an error: {err}"))
+        }
+        Err(e) => {
+            let class = if denied {
+                FailureClass::PolicyDenied
+            } else if e.downcast_ref::<wasmtime::Trap>().is_some() {
+                FailureClass::Trap
+            } else {
+                FailureClass::Error
+            };
+            Failure::new(class, format!("Tool {tool} failed: {e:#}"))
+        }
+    };
+    if denied {
+        Err(failure.with_details(json!({ "denied_requests": denied_requests })))
+    } else {
+        Err(failure)
+    }
+}
+
+/// Calls `tool` with the given arguments and prints its result
+pub async fn run(
+    lifecycle_manager: LifecycleManager,
+    tool: &str,
+    arguments: &[String],
+    timeout: Option<Duration>,
+    mode: OutputMode,
+) -> Result<()> {
+    let arguments = serde_json::to_string(&parse_arguments(arguments)?)?;
+    let component_id = lifecycle_manager
+        .get_component_id_for_tool(tool)
+        .await
+        .with_context(|| format!("Failed to find component for tool '{tool}'"))?;
+
+    let start = Instant::now();
+    // The call runs in a task of its own so the timeout fires even while a component spins
+    // without yielding
+    let call = tokio::spawn({
+        let lifecycle_manager = lifecycle_manager.clone();
+        let component_id = component_id.clone();
+        let tool = tool.to_string();
+        async move {
+            lifecycle_manager
+                .execute_component_call(&component_id, &tool, &arguments)
+                .await
+        }
+    });
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, call).await.map_err(|_| {
+            Failure::new(
+                FailureClass::Timeout,
+                format!("Tool {tool} didn't finish within {}ms", timeout.as_millis()),
+            )
+        })?,
+        None => call.await,
+    }
+    .context("Tool call panicked")?;
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    let denied_requests: Vec<NetworkRequest> = lifecycle_manager
+        .network_activity(Some(&component_id), usize::MAX)
+        .into_iter()
+        .filter(NetworkRequest::is_denied)
+        .collect();
+    let value = classify(tool, result, &denied_requests)?;
+
+    match mode {
+        OutputMode::Text => match &value {
+            Value::String(text) => println!("{text}"),
+            value => println!("{}", serde_json::to_string_pretty(value)?),
+        },
+        OutputMode::Json => print_value(
+            mode,
+            &json!({
+                "tool": tool,
+                "component_id": component_id,
+                "duration_ms": duration_ms,
+                "value": value,
+                "denied_requests": denied_requests,
+            }),
+        )?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denied_request() -> NetworkRequest {
+        NetworkRequest {
+            component_id: "fetch".to_string(),
+            method: "GET".to_string(),
+            host: "example.com".to_string(),
+            path: "/".to_string(),
+            status: None,
+            bytes: 0,
+            duration_ms: 0,
+            cached: false,
+            error: Some("denied by network policy".to_string()),
+            time: "2025-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let arguments = parse_arguments(&[r#"{"url": "https://example.com"}"#.to_string()]);
+        assert_eq!(arguments.unwrap()["url"], "https://example.com");
+
+        let arguments =
+            parse_arguments(&["url=https://example.com".to_string(), "limit=3".to_string()])
+                .unwrap();
+        assert_eq!(arguments["url"], "https://example.com");
+        assert_eq!(arguments["limit"], 3);
+
+        assert!(parse_arguments(&["https://example.com".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_timeout("30").unwrap(), Duration::from_secs(30));
+        assert!(parse_timeout("soon").is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let value = classify("fetch", Ok(r#"{"ok": "body"}"#.to_string()), &[]).unwrap();
+        assert_eq!(value, json!({"ok": "body"}));
+        let value = classify("time", Ok("12:00".to_string()), &[denied_request()]).unwrap();
+        assert_eq!(value, "12:00");
+
+        let failure = classify("fetch", Ok(r#"{"err": "not found"}"#.to_string()), &[]);
+        let failure = failure.unwrap_err();
+        assert_eq!(failure.class, FailureClass::ToolError);
+        assert_eq!(failure.message, "Tool fetch returned an error: not found");
+
+        let failure = classify(
+            "fetch",
+            Ok(r#"{"err": "request denied"}"#.to_string()),
+            &[denied_request()],
+        )
+        .unwrap_err();
+        assert_eq!(failure.class, FailureClass::PolicyDenied);
+        assert_eq!(
+            failure.details.unwrap()["denied_requests"][0]["host"],
+            "example.com"
+        );
+
+        let trap = anyhow::Error::new(wasmtime::Trap::UnreachableCodeReached)
+            .context("error while executing at wasm backtrace");
+        let failure = classify("fetch", Err(trap), &[]).unwrap_err();
+        assert_eq!(failure.class, FailureClass::Trap);
+
+        let failure = classify("fetch", Err(anyhow::anyhow!("Unknown tool name")), &[]);
+        assert_eq!(failure.unwrap_err().class, FailureClass::Error);
+    }
+}
@@ -3,12 +3,15 @@
 
 //! CLI command definitions for wassette
 
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
+use crate::diagnostics::OutputMode;
 use crate::format::OutputFormat;
+use crate::log_format::LogFormat;
 
 #[derive(Parser, Debug)]
 #[command(name = "wassette-mcp-server", about, long_about = None)]
@@ -21,14 +24,23 @@ pub struct Cli {
     #[arg(long)]
     pub plugin_dir: Option<std::path::PathBuf>,
 
+    /// How to report the outcome: text, or json for a single JSON document on stdout with the
+    /// result or the class of the failure
+    #[arg(long = "output", global = true, value_enum, default_value_t)]
+    pub output_mode: OutputMode,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     /// Begin handling requests over the specified protocol.
     Serve(Serve),
+    /// Serve bundled example components with canned network responses, without registry or
+    /// network access.
+    Demo(Demo),
     /// Manage WebAssembly components.
     Component {
         #[command(subcommand)]
@@ -44,9 +56,167 @@ pub enum Commands {
         #[command(subcommand)]
         command: PermissionCommands,
     },
+    /// Manage the secrets of components.
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommands,
+    },
+    /// Export components with their policies, state and secrets to a single bundle archive.
+    #[command(name = "export-bundle")]
+    ExportBundle {
+        /// Path of the bundle to write (a .tar.gz archive)
+        output: PathBuf,
+        /// Component ID to include. Can be specified multiple times; defaults to every loaded component
+        #[arg(long = "component")]
+        components: Vec<String>,
+        /// Encrypt the secrets of the components with the passphrase in
+        /// $WASSETTE_BUNDLE_PASSPHRASE instead of storing them in plain text
+        #[arg(long)]
+        encrypt_secrets: bool,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+    /// Load, update and unload components to match a desired-state manifest.
+    Reconcile {
+        /// Path of the manifest. Defaults to wassette.yaml in the current directory
+        #[arg(default_value = wassette::MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Only report drift between the manifest and the loaded components
+        #[arg(long)]
+        dry_run: bool,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Check the release channels of manifest components and stage newer versions for approval.
+    #[command(name = "check-updates")]
+    CheckUpdates {
+        /// Path of the manifest. Defaults to wassette.yaml in the current directory
+        #[arg(default_value = wassette::MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Pin the staged update of a component and reconcile the manifest to load it.
+    #[command(name = "approve-update")]
+    ApproveUpdate {
+        /// ID of the component
+        component_id: String,
+        /// Path of the manifest. Defaults to wassette.yaml in the current directory
+        #[arg(long, default_value = wassette::MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Import the components of a bundle archive with their policies, state and secrets.
+    /// Encrypted secrets are decrypted with the passphrase in $WASSETTE_BUNDLE_PASSPHRASE.
+    #[command(name = "import-bundle")]
+    ImportBundle {
+        /// Path of the bundle to import
+        path: PathBuf,
+        /// Replace components that are already loaded
+        #[arg(long)]
+        overwrite: bool,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+    /// Sign a pushed component with cosign so it can be loaded with signature verification.
+    Sign {
+        /// OCI reference of the component, e.g. oci://ghcr.io/me/tool:1.0.0
+        reference: String,
+        /// Cosign private key. Encrypted keys are decrypted with $COSIGN_PASSWORD
+        #[arg(long, required_unless_present = "keyless", conflicts_with = "keyless")]
+        key: Option<PathBuf>,
+        /// Sign with a Fulcio certificate for the OIDC identity of the GitHub Actions job
+        #[arg(long)]
+        keyless: bool,
+        /// Also record key-based signatures in the Rekor transparency log. Keyless signatures
+        /// are always recorded.
+        #[arg(long)]
+        tlog_upload: bool,
+        /// URL of the Fulcio certificate authority for keyless signing
+        #[arg(long, default_value = wassette::DEFAULT_FULCIO_URL)]
+        fulcio_url: String,
+        /// URL of the Rekor transparency log
+        #[arg(long, default_value = wassette::DEFAULT_REKOR_URL)]
+        rekor_url: String,
+        /// User name for the registry. The password is read from $WASSETTE_REGISTRY_PASSWORD
+        #[arg(long)]
+        username: Option<String>,
+    },
+    /// Call a tool once and exit with a status that tells how it went.
+    Call {
+        /// Name of the tool to call
+        tool: String,
+        /// Arguments as a JSON object or KEY=VALUE pairs
+        arguments: Vec<String>,
+        /// Fail the call if it takes longer, e.g. 30s, 500ms or 2m
+        #[arg(long, value_parser = crate::call::parse_timeout)]
+        timeout: Option<std::time::Duration>,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Explore and call the tools of the loaded components at an interactive prompt.
+    Repl {
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Watch and manage the components in a terminal console, for servers without a browser.
+    Tui {
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Search the tool results, tool docs and logs indexed by a server run with --index-history.
+    #[command(name = "search-history")]
+    SearchHistory {
+        /// Full-text query, e.g. 'weather AND berlin'
+        query: String,
+        /// Most hits to return
+        #[arg(long, default_value_t = wassette::HistoryQuery::DEFAULT_LIMIT)]
+        limit: usize,
+        /// Only return entries of this kind: result, doc or log
+        #[arg(long)]
+        kind: Option<String>,
+        /// Only return entries of this component
+        #[arg(long)]
+        component_id: Option<String>,
+        /// Print the full indexed text of every hit, not just a snippet
+        #[arg(long)]
+        include_text: bool,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Output format
+        #[arg(short = 'o', long = "output-format", default_value = "json")]
+        output_format: OutputFormat,
+    },
 }
 
-#[derive(Parser, Debug, Clone, Serialize, Deserialize)]
+/// An MCP transport of the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// JSON-RPC over stdin and stdout, for clients that start the server
+    Stdio,
+    /// The legacy HTTP+SSE transport, for clients that don't support streamable HTTP yet
+    Sse,
+    /// Streamable HTTP
+    StreamableHttp,
+}
+
+#[derive(Parser, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Serve {
     /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
     #[arg(long)]
@@ -68,6 +238,56 @@ pub struct Serve {
     #[serde(skip)]
     pub streamable_http: bool,
 
+    /// Transports to serve MCP on, in place of --stdio, --sse or --streamable-http. All of them
+    /// share the loaded components and permissions. Can be specified multiple times or as a
+    /// comma separated list.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with_all = ["stdio", "sse", "streamable_http"]
+    )]
+    #[serde(skip)]
+    pub transport: Vec<Transport>,
+
+    /// Address the SSE and streamable HTTP transports listen on (default: 127.0.0.1:9001)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<SocketAddr>,
+
+    /// Serve the SSE and streamable HTTP transports over HTTPS with the certificate chain in this
+    /// PEM file. Needs --tls-key.
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve the SSE and streamable HTTP transports over HTTPS with a certificate for this domain
+    /// from an ACME issuer such as Let's Encrypt. Can be specified multiple times.
+    #[arg(long, value_name = "DOMAIN", conflicts_with = "tls_cert")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub acme_domain: Vec<String>,
+
+    /// Contact email address of the ACME account. Can be specified multiple times.
+    #[arg(long, value_name = "EMAIL")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub acme_contact: Vec<String>,
+
+    /// Directory to cache the ACME account and certificates in (default:
+    /// $XDG_DATA_HOME/wassette/acme)
+    #[arg(long, value_name = "DIR")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acme_cache: Option<PathBuf>,
+
+    /// ACME directory to get certificates from (default: Let's Encrypt's production directory)
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acme_directory: Option<String>,
+
     /// Set environment variables (KEY=VALUE format). Can be specified multiple times.
     #[arg(long = "env", value_parser = crate::parse_env_var)]
     #[serde(skip)]
@@ -77,22 +297,364 @@ pub struct Serve {
     #[arg(long = "env-file")]
     #[serde(skip)]
     pub env_file: Option<PathBuf>,
+
+    /// Desired-state manifest (wassette.yaml) to reconcile the loaded components with on startup
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<PathBuf>,
+
+    /// Send lifecycle and invocation events as CloudEvents to an http(s):// URL or a
+    /// nats://host:port/subject URI. Can be specified multiple times.
+    #[arg(long = "event-sink")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub event_sinks: Vec<String>,
+
+    /// Keep every event in <plugin-dir>/events.jsonl, where /api/events of the management API
+    /// can query it
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub event_log: bool,
+
+    /// Days events are kept in the event log (default: 30)
+    #[arg(long, value_name = "DAYS")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_log_retention_days: Option<u64>,
+
+    /// Push the metrics of the management API to an OTLP/HTTP endpoint, e.g.
+    /// http://localhost:4318 or the OTLP URL of Grafana Cloud or Datadog
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Header to send with every OTLP export, e.g. the API key of the backend. Can be specified
+    /// multiple times.
+    #[arg(long = "otlp-header", value_name = "NAME=VALUE")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub otlp_headers: Vec<String>,
+
+    /// Seconds between two OTLP exports (default: 60)
+    #[arg(long, value_name = "SECS")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_interval_secs: Option<u64>,
+
+    /// Profile every call to this component and keep the profiles in --profile-dir, readable as
+    /// MCP resources. Can be specified multiple times.
+    #[arg(long = "profile", value_name = "COMPONENT")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profile_components: Vec<String>,
+
+    /// Directory to write the profiles of calls to (default: <plugin-dir>/diagnostics/profiles)
+    #[arg(long, value_name = "DIR")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_dir: Option<PathBuf>,
+
+    /// Format of the log output: pretty for terminals, or json for one object per line with
+    /// stable field names such as component_id, tool_name and duration_ms
+    #[arg(long, value_enum, default_value_t)]
+    #[serde(skip)]
+    pub log_format: LogFormat,
+
+    /// Also receive tool calls from a message bus: nats://host:port/subject or
+    /// mqtt://host:port/topic, with an optional ?group=<name> to share the work between instances
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+
+    /// Capture the stdout and stderr of each component call and return them with the tool
+    /// result, instead of writing them to the server's terminal
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub capture_output: bool,
+
+    /// Disable zstd/gzip compression of HTTP transport responses
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_compression: bool,
+
+    /// Smallest HTTP response, in bytes, that is compressed (default: 1024). Event streams are
+    /// always compressed.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression_threshold: Option<usize>,
+
+    /// How to estimate the tokens of tool results: chars:<characters per token> or
+    /// words:<tokens per word> (default: chars:4)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_heuristic: Option<String>,
+
+    /// Add the estimated token count to every component tool result
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub annotate_tokens: bool,
+
+    /// Summarize results of a tool above a token budget through client sampling, as
+    /// <tool>=<tokens>; use *=<tokens> for every tool. Can be specified multiple times.
+    #[arg(long, value_name = "TOOL=TOKENS")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub summarize: Vec<String>,
+
+    /// Index tool results, tool docs and component logs under the plugin directory so they can
+    /// be found with the search-history tool
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub index_history: bool,
+
+    /// Let clients manage the secrets of components with the set-secret, list-secret-keys and
+    /// delete-secret tools
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub secret_tools: bool,
+
+    /// Where to keep the secrets of components: `file`, files in the secrets directory of the
+    /// plugin directory, or `keychain`, the keychain of the operating system [default: file]
+    #[arg(long, value_name = "PROVIDER")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_provider: Option<String>,
+
+    /// Component index that the search-components tool searches: an http(s):// or file:// URL
+    /// of a JSON index, or oci://<registry>/<repository>:<tag> of an artifact whose referrers are
+    /// the components. Can be specified multiple times.
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub component_index: Vec<String>,
+
+    /// Embed tool results into a vector store under the plugin directory so they can be found
+    /// with the semantic-recall tool
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub semantic_recall: bool,
+
+    /// How to embed results for semantic recall: hashing, or tool:<tool name> to call a component
+    /// tool that returns an array of numbers (default: hashing)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedder: Option<String>,
+
+    /// Limit calls of a tool as <tool>=<calls>/<period>, e.g. fetch=10/1m; use *=<calls>/<period>
+    /// for every tool. Periods are in s, m, h or d. Can be specified multiple times.
+    #[arg(long, value_name = "TOOL=CALLS/PERIOD")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rate_limit: Vec<String>,
+
+    /// Run at most this many tool calls at once. Calls beyond the limit wait, with interactive
+    /// calls served before background ones.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_calls: Option<usize>,
+
+    /// Priority class of a tool's calls, as <tool>=<interactive|background>; use *=<class> for
+    /// every tool. Can be specified multiple times.
+    #[arg(long, value_name = "TOOL=CLASS")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub priority: Vec<String>,
+
+    /// Share of the call slots of a component relative to others, as <component>=<weight>;
+    /// components default to 1. Can be specified multiple times.
+    #[arg(long, value_name = "COMPONENT=WEIGHT")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub component_weight: Vec<String>,
+
+    /// Reject policies with unknown fields or legacy constructs such as a numeric
+    /// `resources.memory`, instead of ignoring or accepting them
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strict_policy: bool,
+
+    /// YAML file with the signature requirements of oci:// components by registry and
+    /// repository: a public key, a keyless identity, or deny
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_policy: Option<PathBuf>,
+
+    /// YAML file with the inspections of components before they load: what to do with
+    /// components that import certain capabilities, and an external scanner to run
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inspection_config: Option<PathBuf>,
+
+    /// Cache the responses to HTTP GET requests of components in memory, following
+    /// Cache-Control and revalidating stale responses with ETag or Last-Modified
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub http_cache: bool,
+
+    /// Record only a share of a component's successful HTTP requests, as <component>=<rate>
+    /// with a rate such as 0.1 or 10%; use *=<rate> for every component. Blocked and failed
+    /// requests are always recorded. Can be specified multiple times.
+    #[arg(long, value_name = "COMPONENT=RATE")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub network_log_sample: Vec<String>,
+
+    /// Prefix the name of every component tool with its component ID, or its alias from
+    /// --tool-alias, as <prefix>__<tool>
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub prefix_tools: bool,
+
+    /// Prefix of the tools of a component instead of its ID, as <component>=<alias>. Can be
+    /// specified multiple times.
+    #[arg(long, value_name = "COMPONENT=ALIAS")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_alias: Vec<String>,
+
+    /// What happens when a component has a tool named like a tool of another loaded component:
+    /// prefix it with the component's ID or alias, reject the component, or keep the first
+    /// component's tool (default: prefix)
+    #[arg(long, value_name = "prefix|reject|first")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_collision: Option<String>,
+
+    /// File with the template of the instructions sent to clients when they connect. The
+    /// template can use {components}, {component_count} and {tool_count}.
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<PathBuf>,
+
+    /// Give every client session of the HTTP transports its own components on top of the ones
+    /// shared by all sessions. Components a session loads are only visible to it and unloaded
+    /// when it ends.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub isolate_sessions: bool,
+
+    /// Serve the management API for web frontends under /api on the HTTP transports
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub management_api: bool,
+
+    /// Serve the management API on this address instead of the HTTP transports: host:port, or
+    /// unix:<path> for a unix socket. Implies --management-api.
+    #[arg(long, value_name = "ADDR")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub management_listen: Option<String>,
+
+    /// Path prefix of the management API, e.g. /wassette to serve it under /wassette/api behind a
+    /// reverse proxy
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub management_base_path: Option<String>,
+
+    /// Require a bearer token from this OAuth 2.0 issuer for every request to the HTTP
+    /// transports. Tokens are JWTs verified with the issuer's published keys.
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_issuer: Option<String>,
+
+    /// Audience the bearer tokens must be issued for
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_audience: Option<String>,
+
+    /// JWKS with the keys of the OAuth issuer. Discovered from the issuer's metadata by default.
+    #[arg(long, value_name = "URL")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_jwks_url: Option<String>,
+
+    /// Scope a token needs to call a tool, as <tool>=<scope>; use *=<scope> for every tool. The
+    /// built-in tools that change components need tools:write by default. Can be specified
+    /// multiple times.
+    #[arg(long, value_name = "TOOL=SCOPE")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub oauth_scope: Vec<String>,
+}
+
+impl Serve {
+    /// The transports selected by --transport or the legacy transport flags, without duplicates.
+    /// Stdio when none is given.
+    pub fn selected_transports(&self) -> anyhow::Result<Vec<Transport>> {
+        let mut transports = Vec::new();
+        let legacy = [
+            (self.stdio, Transport::Stdio),
+            (self.sse, Transport::Sse),
+            (self.streamable_http, Transport::StreamableHttp),
+        ];
+        let selected = self.transport.iter().copied().chain(
+            legacy
+                .into_iter()
+                .filter_map(|(enabled, transport)| enabled.then_some(transport)),
+        );
+        for transport in selected {
+            if !transports.contains(&transport) {
+                transports.push(transport);
+            }
+        }
+        if transports.is_empty() {
+            transports.push(Transport::Stdio);
+        }
+        if transports == [Transport::Stdio] && self.listen.is_some() {
+            anyhow::bail!("--listen only applies to the sse and streamable-http transports");
+        }
+        if transports == [Transport::Stdio]
+            && (self.tls_cert.is_some() || !self.acme_domain.is_empty())
+        {
+            anyhow::bail!(
+                "--tls-cert and --acme-domain only apply to the sse and streamable-http transports"
+            );
+        }
+        Ok(transports)
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Demo {
+    /// Enable stdio transport
+    #[arg(long)]
+    pub stdio: bool,
+
+    /// Enable SSE transport
+    #[arg(long)]
+    pub sse: bool,
+
+    /// Enable streamable HTTP transport
+    #[arg(long)]
+    pub streamable_http: bool,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum ComponentCommands {
     /// Load a WebAssembly component from a file path or OCI registry.
     Load {
-        /// Path to the component (file:// or oci://)
+        /// Path to the component (file:// or oci://). The tag of an OCI reference can be a semver
+        /// range such as ^1.2
         path: String,
         /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
         #[arg(long)]
         plugin_dir: Option<PathBuf>,
     },
-    /// Unload a WebAssembly component.
+    /// Unload a WebAssembly component. The component is kept in the trash and can be restored.
     Unload {
         /// Component ID to unload
         id: String,
+        /// Delete the component and everything derived from it instead of keeping it in the
+        /// trash
+        #[arg(long)]
+        purge: bool,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Restore a previously unloaded component from the trash.
+    Restore {
+        /// Component ID to restore
+        id: String,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// Permanently delete a previously unloaded component from the trash.
+    Purge {
+        /// Component ID to purge
+        id: String,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+    },
+    /// List the artifacts of components that are neither loaded nor in the trash.
+    Orphans {
+        /// Remove the orphaned artifacts
+        #[arg(long)]
+        remove: bool,
         /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
         #[arg(long)]
         plugin_dir: Option<PathBuf>,
@@ -135,7 +697,7 @@ pub enum PermissionCommands {
         #[command(subcommand)]
         permission: RevokePermissionCommands,
     },
-    /// Reset all permissions for a component.
+    /// Reset a component to the policy it had before any runtime grants.
     Reset {
         /// Component ID to reset permissions for
         component_id: String,
@@ -227,3 +789,66 @@ pub enum RevokePermissionCommands {
         plugin_dir: Option<PathBuf>,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub enum SecretCommands {
+    /// Set a secret of a component, replacing its value if it has one.
+    Set {
+        /// Component ID to set the secret for
+        component_id: String,
+        /// Name of the secret
+        key: String,
+        /// Value of the secret. Read from standard input if not given, which keeps it out of the
+        /// shell history
+        #[arg(long)]
+        value: Option<String>,
+        /// Exported function that gets the secret. Can be specified multiple times; defaults to
+        /// every function
+        #[arg(long = "function")]
+        functions: Vec<String>,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+    /// List the names of the secrets of a component, never their values.
+    List {
+        /// Component ID to list the secrets of
+        component_id: String,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+    /// Delete a secret of a component.
+    Delete {
+        /// Component ID to delete the secret of
+        component_id: String,
+        /// Name of the secret
+        key: String,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+    /// Import the variables of a dotenv file as secrets of a component, replacing secrets of the
+    /// same names.
+    Import {
+        /// Component ID to import the secrets for
+        component_id: String,
+        /// Path to the dotenv file
+        path: PathBuf,
+        /// Directory where plugins are stored. Defaults to $XDG_DATA_HOME/wassette/components
+        #[arg(long)]
+        plugin_dir: Option<PathBuf>,
+        /// Where the secrets are kept, `file` or `keychain`
+        #[arg(long, value_name = "PROVIDER")]
+        secret_provider: Option<String>,
+    },
+}
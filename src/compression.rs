@@ -0,0 +1,274 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Response compression for the HTTP transports
+//!
+//! Responses are compressed with zstd or gzip when the client lists one of them in
+//! `Accept-Encoding`. Responses with a known length below the threshold are sent as they are.
+//! Tool results travel over event streams, whose length is not known up front, so streams are
+//! always compressed; every frame is flushed as soon as it is written, which keeps events from
+//! being held back until the stream closes.
+
+use std::io::{self, Write};
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::stream::{self, Stream, StreamExt};
+
+/// Responses smaller than this many bytes are not compressed unless configured otherwise
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Picks the encoding with the highest quality value in an `Accept-Encoding` header, preferring
+/// zstd on ties
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let encoding = match params.next().unwrap_or_default().trim() {
+            "zstd" => Encoding::Zstd,
+            "gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some((current, current_quality)) => {
+                quality > current_quality
+                    || (quality == current_quality
+                        && encoding == Encoding::Zstd
+                        && current != Encoding::Zstd)
+            }
+        };
+        if better {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> io::Result<Self> {
+        Ok(match encoding {
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Encoding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?),
+        })
+    }
+
+    /// Compresses `data` and flushes, returning everything the encoder produced so far
+    fn compress(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+fn compress_body(body: Body, encoder: Encoder) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    stream::unfold(
+        Some((body.into_data_stream(), encoder)),
+        |state| async move {
+            let (mut data, mut encoder) = state?;
+            loop {
+                match data.next().await {
+                    Some(Ok(chunk)) => match encoder.compress(&chunk) {
+                        Ok(output) if output.is_empty() => continue,
+                        Ok(output) => return Some((Ok(output), Some((data, encoder)))),
+                        Err(e) => return Some((Err(e), None)),
+                    },
+                    Some(Err(e)) => return Some((Err(io::Error::other(e)), None)),
+                    None => return Some((encoder.finish(), None)),
+                }
+            }
+        },
+    )
+}
+
+/// Middleware that compresses responses of at least `threshold` bytes for clients that accept
+/// zstd or gzip
+pub async fn compress(State(threshold): State<usize>, request: Request, next: Next) -> Response {
+    let encoding = request
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate);
+    let response = next.run(request).await;
+
+    let Some(encoding) = encoding else {
+        return response;
+    };
+    if response.headers().contains_key(CONTENT_ENCODING) {
+        return response;
+    }
+    if let Some(size) = response.body().size_hint().exact() {
+        if size == 0 || (size as usize) < threshold {
+            return response;
+        }
+    }
+    let encoder = match Encoder::new(encoding) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to create response encoder");
+            return response;
+        }
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    Response::from_parts(parts, Body::from_stream(compress_body(body, encoder)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip, zstd"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("zstd;q=0.5, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("zstd;q=0, gzip;q=0"), None);
+        assert_eq!(negotiate("identity"), None);
+    }
+
+    async fn get_response(accept_encoding: &str, path: &str) -> Response {
+        let router = Router::new()
+            .route("/small", get(|| async { "ok" }))
+            .route("/large", get(|| async { "wassette ".repeat(1000) }))
+            .layer(axum::middleware::from_fn_with_state(
+                DEFAULT_COMPRESSION_THRESHOLD,
+                compress,
+            ));
+        let request = Request::builder()
+            .uri(path)
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    async fn body_bytes(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_compress_responses() {
+        let expected = "wassette ".repeat(1000).into_bytes();
+
+        let response = get_response("gzip", "/large").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let compressed = body_bytes(response).await;
+        assert!(compressed.len() < expected.len());
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, expected);
+
+        let response = get_response("zstd", "/large").await;
+        assert_eq!(response.headers()[CONTENT_ENCODING], "zstd");
+        let compressed = body_bytes(response).await;
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), expected);
+
+        // Below the threshold, or without a supported encoding, the body is left alone
+        let response = get_response("gzip", "/small").await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body_bytes(response).await, b"ok");
+        let response = get_response("br", "/large").await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_stream_frames_are_flushed() {
+        let frames = stream::iter(vec![
+            Ok::<_, io::Error>(Bytes::from("data: first\n\n")),
+            Ok(Bytes::from("data: second\n\n")),
+        ]);
+        let body = Body::from_stream(frames);
+        let mut compressed = Box::pin(compress_body(body, Encoder::new(Encoding::Gzip).unwrap()));
+
+        // The first frame can be decoded on its own, before the stream ends
+        let first = compressed.next().await.unwrap().unwrap();
+        let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+        decoder.write_all(&first).unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(decoder.get_ref().as_slice(), b"data: first\n\n");
+
+        while let Some(frame) = compressed.next().await {
+            decoder.write_all(&frame.unwrap()).unwrap();
+        }
+        assert_eq!(
+            decoder.finish().unwrap(),
+            b"data: first\n\ndata: second\n\n"
+        );
+    }
+}
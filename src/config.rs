@@ -2,12 +2,20 @@
 // Licensed under the MIT license.
 
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use etcetera::BaseStrategy;
 use figment::providers::{Env, Format, Serialized, Toml};
+use mcp_server::{
+    CallLimitOverrides, CallLimitSettings, CallLimits, DownstreamServer, DownstreamTransport,
+};
 use serde::{Deserialize, Serialize};
+use wassette::{HostTlsConfig, HttpLimits, ToolAlias, ToolCollision, ToolNaming};
+
+use crate::api_key::ApiKeyConfig;
+use crate::tls::ServerTlsConfig;
 
 /// Get the default component directory path based on the OS
 pub fn get_component_dir() -> Result<PathBuf, anyhow::Error> {
@@ -32,9 +40,386 @@ pub struct Config {
     /// Environment variables to be made available to components
     #[serde(default)]
     pub environment_vars: HashMap<String, String>,
+
+    /// Address the SSE and streamable HTTP transports listen on
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+
+    /// PEM file of the certificate chain the HTTP transports serve HTTPS with
+    #[serde(default)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file of the private key of `tls_cert`
+    #[serde(default)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Domains to get a certificate for through ACME, instead of `tls_cert`
+    #[serde(default)]
+    pub acme_domain: Vec<String>,
+
+    /// Contact email addresses of the ACME account
+    #[serde(default)]
+    pub acme_contact: Vec<String>,
+
+    /// Directory the ACME account and certificates are cached in
+    #[serde(default)]
+    pub acme_cache: Option<PathBuf>,
+
+    /// ACME directory to get certificates from instead of Let's Encrypt's
+    #[serde(default)]
+    pub acme_directory: Option<String>,
+
+    /// Desired-state manifest to reconcile the loaded components with on startup
+    #[serde(default)]
+    pub manifest: Option<PathBuf>,
+
+    /// Sinks that receive lifecycle and invocation events as CloudEvents
+    #[serde(default)]
+    pub event_sinks: Vec<String>,
+
+    /// Whether to keep every event in the event log under the plugin directory
+    #[serde(default)]
+    pub event_log: bool,
+
+    /// Days events are kept in the event log
+    #[serde(default)]
+    pub event_log_retention_days: Option<u64>,
+
+    /// OTLP/HTTP endpoint to push metrics to
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Headers sent with every OTLP export, as `name=value`
+    #[serde(default)]
+    pub otlp_headers: Vec<String>,
+
+    /// Seconds between two OTLP exports
+    #[serde(default)]
+    pub otlp_interval_secs: Option<u64>,
+
+    /// Components whose calls are profiled
+    #[serde(default)]
+    pub profile_components: Vec<String>,
+
+    /// Directory the profiles of calls are written to
+    #[serde(default)]
+    pub profile_dir: Option<PathBuf>,
+
+    /// Message bus to receive tool calls from, in addition to the MCP transport
+    #[serde(default)]
+    pub bus: Option<String>,
+
+    /// Whether to capture the stdout and stderr of component calls
+    #[serde(default)]
+    pub capture_output: bool,
+
+    /// Whether to send HTTP transport responses uncompressed
+    #[serde(default)]
+    pub no_compression: bool,
+
+    /// Smallest HTTP response, in bytes, that is compressed
+    #[serde(default)]
+    pub compression_threshold: Option<usize>,
+
+    /// Heuristic for estimating the tokens of tool results
+    #[serde(default)]
+    pub token_heuristic: Option<String>,
+
+    /// Whether to add the estimated token count to component tool results
+    #[serde(default)]
+    pub annotate_tokens: bool,
+
+    /// Per-tool token budgets above which results are summarized, as `<tool>=<tokens>`
+    #[serde(default)]
+    pub summarize: Vec<String>,
+
+    /// Whether to index tool results, tool docs and logs for the search-history tool
+    #[serde(default)]
+    pub index_history: bool,
+
+    /// Whether clients may manage the secrets of components over MCP
+    #[serde(default)]
+    pub secret_tools: bool,
+
+    /// Where to keep the secrets of components, `file` or `keychain`, `file` if not set
+    #[serde(default)]
+    pub secret_provider: Option<String>,
+
+    /// Component indexes that the search-components tool searches
+    #[serde(default)]
+    pub component_index: Vec<String>,
+
+    /// Whether to embed tool results for the semantic-recall tool
+    #[serde(default)]
+    pub semantic_recall: bool,
+
+    /// Embedder for semantic recall, `hashing` or `tool:<tool name>`
+    #[serde(default)]
+    pub embedder: Option<String>,
+
+    /// Per-tool rate limits, as `<tool>=<calls>/<period>`
+    #[serde(default)]
+    pub rate_limit: Vec<String>,
+
+    /// How many tool calls run at once, or any number if unset
+    #[serde(default)]
+    pub max_concurrent_calls: Option<usize>,
+
+    /// Per-tool priority classes, as `<tool>=<interactive|background>`
+    #[serde(default)]
+    pub priority: Vec<String>,
+
+    /// Per-component shares of the call slots, as `<component>=<weight>`
+    #[serde(default)]
+    pub component_weight: Vec<String>,
+
+    /// Whether to reject policies with unknown fields or legacy constructs
+    #[serde(default)]
+    pub strict_policy: bool,
+
+    /// File with the signature requirements of `oci://` components by registry and repository
+    #[serde(default)]
+    pub verification_policy: Option<PathBuf>,
+
+    /// File with the inspections of components before they load
+    #[serde(default)]
+    pub inspection_config: Option<PathBuf>,
+
+    /// Whether to cache the responses to HTTP GET requests of components
+    #[serde(default)]
+    pub http_cache: bool,
+
+    /// Per-component shares of the successful HTTP requests that are recorded, as
+    /// `<component>=<rate>`
+    #[serde(default)]
+    pub network_log_sample: Vec<String>,
+
+    /// Proxy for the `http://` requests of components. Defaults to `HTTP_PROXY`.
+    #[serde(default)]
+    pub http_proxy: Option<String>,
+
+    /// Proxy for the `https://` requests of components. Defaults to `HTTPS_PROXY`.
+    #[serde(default)]
+    pub https_proxy: Option<String>,
+
+    /// Hosts that components connect to without a proxy. Defaults to `NO_PROXY`.
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+
+    /// Per-host CA certificates and client certificates for the `https://` requests of components
+    #[serde(default)]
+    pub http_tls: Vec<HostTlsConfig>,
+
+    /// Size and time limits of the HTTP requests of components
+    #[serde(default)]
+    pub http_limits: HttpLimits,
+
+    /// Whether every component tool is prefixed with its component ID or alias
+    #[serde(default)]
+    pub prefix_tools: bool,
+
+    /// Prefixes of the tools of components instead of their IDs, as `<component>=<alias>`
+    #[serde(default)]
+    pub tool_alias: Vec<String>,
+
+    /// What happens to a tool named like a tool of another component: `prefix`, `reject` or
+    /// `first`
+    #[serde(default)]
+    pub tool_collision: Option<String>,
+
+    /// File with the template of the instructions sent to clients when they connect
+    #[serde(default)]
+    pub instructions: Option<PathBuf>,
+
+    /// Whether client sessions get their own components on top of the shared ones
+    #[serde(default)]
+    pub isolate_sessions: bool,
+
+    /// Whether the HTTP transports serve the management API under `/api`
+    #[serde(default)]
+    pub management_api: bool,
+
+    /// Address of the management API when it doesn't share the address of the HTTP transports,
+    /// `host:port` or `unix:<path>`
+    #[serde(default)]
+    pub management_listen: Option<String>,
+
+    /// Path prefix of the management API
+    #[serde(default)]
+    pub management_base_path: Option<String>,
+
+    /// OAuth 2.0 issuer whose bearer tokens the HTTP transports require
+    #[serde(default)]
+    pub oauth_issuer: Option<String>,
+
+    /// Audience the bearer tokens must be issued for
+    #[serde(default)]
+    pub oauth_audience: Option<String>,
+
+    /// JWKS with the keys of the OAuth issuer, if not discovered
+    #[serde(default)]
+    pub oauth_jwks_url: Option<String>,
+
+    /// Scopes that calls of tools need, as `<tool>=<scope>`
+    #[serde(default)]
+    pub oauth_scope: Vec<String>,
+
+    /// API keys that the HTTP transports require, with what each of them may call
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+
+    /// Downstream MCP servers whose tools are proxied
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
+
+    /// Limits on the arguments and results of tool calls
+    #[serde(default)]
+    pub call_limits: CallLimitsConfig,
+}
+
+/// Limits on the arguments and results of the calls of a component's tools. Unset limits keep
+/// their defaults, or for a component, the limits of every component.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ComponentCallLimits {
+    /// Most bytes the arguments of a call may take as JSON
+    #[serde(default)]
+    pub max_argument_size: Option<usize>,
+    /// Deepest the arguments of a call may nest arrays and objects
+    #[serde(default)]
+    pub max_argument_depth: Option<usize>,
+    /// Whether escape sequences and control characters are stripped from results
+    #[serde(default)]
+    pub sanitize_output: Option<bool>,
+}
+
+impl From<&ComponentCallLimits> for CallLimitOverrides {
+    fn from(limits: &ComponentCallLimits) -> Self {
+        Self {
+            max_argument_size: limits.max_argument_size,
+            max_argument_depth: limits.max_argument_depth,
+            sanitize_output: limits.sanitize_output,
+        }
+    }
+}
+
+/// The `call_limits` table of the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CallLimitsConfig {
+    /// Limits of every component
+    #[serde(flatten)]
+    pub limits: ComponentCallLimits,
+    /// Limits of single components or downstream servers, by component ID or server name
+    #[serde(default)]
+    pub components: HashMap<String, ComponentCallLimits>,
+}
+
+impl CallLimitsConfig {
+    /// The limits of every component, with the overrides of components
+    pub fn to_settings(&self) -> CallLimitSettings {
+        let defaults = CallLimits::default();
+        CallLimitSettings::new(
+            CallLimits {
+                max_argument_size: self
+                    .limits
+                    .max_argument_size
+                    .unwrap_or(defaults.max_argument_size),
+                max_argument_depth: self
+                    .limits
+                    .max_argument_depth
+                    .unwrap_or(defaults.max_argument_depth),
+                sanitize_output: self
+                    .limits
+                    .sanitize_output
+                    .unwrap_or(defaults.sanitize_output),
+            },
+            self.components
+                .iter()
+                .map(|(id, limits)| (id.clone(), limits.into())),
+        )
+    }
+}
+
+/// A downstream MCP server as defined in the configuration file, reached either by running
+/// `command` or at `url`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct McpServerConfig {
+    /// Namespace of the server's tools
+    pub name: String,
+    /// Command that starts a server speaking MCP over stdio
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Arguments of `command`
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment variables of `command`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Streamable HTTP endpoint of the server
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Headers sent to `url`, such as `Authorization`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Tools to import. Every tool of the server if unset.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+}
+
+impl McpServerConfig {
+    /// The server to connect to
+    pub fn to_downstream(&self) -> Result<DownstreamServer, anyhow::Error> {
+        let transport = match (&self.command, &self.url) {
+            (Some(command), None) => DownstreamTransport::Command {
+                command: command.clone(),
+                args: self.args.clone(),
+                env: self.env.clone(),
+            },
+            (None, Some(url)) => DownstreamTransport::StreamableHttp {
+                url: url.clone(),
+                headers: self.headers.clone(),
+            },
+            _ => anyhow::bail!(
+                "MCP server '{}' needs exactly one of command and url",
+                self.name
+            ),
+        };
+        Ok(DownstreamServer {
+            name: self.name.clone(),
+            transport,
+            tools: self.tools.clone(),
+        })
+    }
 }
 
 impl Config {
+    /// How component tools are named
+    pub fn tool_naming(&self) -> Result<ToolNaming, anyhow::Error> {
+        ToolNaming::new(
+            self.prefix_tools,
+            self.tool_alias
+                .iter()
+                .map(|alias| alias.parse::<ToolAlias>())
+                .collect::<Result<Vec<_>, _>>()?,
+            self.tool_collision
+                .as_deref()
+                .map(str::parse::<ToolCollision>)
+                .transpose()?
+                .unwrap_or_default(),
+        )
+    }
+
+    /// How the HTTP transports terminate TLS
+    pub fn server_tls(&self) -> ServerTlsConfig {
+        ServerTlsConfig {
+            cert: self.tls_cert.clone(),
+            key: self.tls_key.clone(),
+            acme_domains: self.acme_domain.clone(),
+            acme_contact: self.acme_contact.clone(),
+            acme_cache: self.acme_cache.clone(),
+            acme_directory: self.acme_directory.clone(),
+        }
+    }
+
     /// Returns a new [`Config`] instance by merging the configuration from the specified
     /// `cli_config` (any struct that is Serialize/Deserialize, but generally a Clap `Parser`) with
     /// the configuration file and environment variables. By default, the configuration file is
@@ -117,8 +502,60 @@ mod tests {
             stdio: true,
             sse: false,
             streamable_http: false,
+            transport: Vec::new(),
+            listen: None,
+            tls_cert: None,
+            tls_key: None,
+            acme_domain: vec![],
+            acme_contact: vec![],
+            acme_cache: None,
+            acme_directory: None,
             env_vars: vec![],
             env_file: None,
+            manifest: None,
+            event_sinks: vec![],
+            event_log: false,
+            event_log_retention_days: None,
+            otlp_endpoint: None,
+            otlp_headers: vec![],
+            otlp_interval_secs: None,
+            profile_components: vec![],
+            profile_dir: None,
+            log_format: Default::default(),
+            bus: None,
+            capture_output: false,
+            no_compression: false,
+            compression_threshold: None,
+            token_heuristic: None,
+            annotate_tokens: false,
+            summarize: vec![],
+            index_history: false,
+            secret_tools: false,
+            component_index: vec![],
+            semantic_recall: false,
+            embedder: None,
+            rate_limit: vec![],
+            max_concurrent_calls: None,
+            priority: vec![],
+            component_weight: vec![],
+            strict_policy: false,
+            http_cache: false,
+            network_log_sample: vec![],
+            prefix_tools: false,
+            tool_alias: vec![],
+            tool_collision: None,
+            instructions: None,
+            isolate_sessions: false,
+            management_api: false,
+            management_listen: None,
+            management_base_path: None,
+            secret_provider: None,
+            verification_policy: None,
+            inspection_config: None,
+            oauth_issuer: None,
+            oauth_audience: None,
+            oauth_jwks_url: None,
+            oauth_scope: vec![],
         }
     }
 
@@ -128,8 +565,60 @@ mod tests {
             stdio: false,
             sse: false,
             streamable_http: false,
+            transport: Vec::new(),
+            listen: None,
+            tls_cert: None,
+            tls_key: None,
+            acme_domain: vec![],
+            acme_contact: vec![],
+            acme_cache: None,
+            acme_directory: None,
             env_vars: vec![],
             env_file: None,
+            manifest: None,
+            event_sinks: vec![],
+            event_log: false,
+            event_log_retention_days: None,
+            otlp_endpoint: None,
+            otlp_headers: vec![],
+            otlp_interval_secs: None,
+            profile_components: vec![],
+            profile_dir: None,
+            log_format: Default::default(),
+            bus: None,
+            capture_output: false,
+            no_compression: false,
+            compression_threshold: None,
+            token_heuristic: None,
+            annotate_tokens: false,
+            summarize: vec![],
+            index_history: false,
+            secret_tools: false,
+            component_index: vec![],
+            semantic_recall: false,
+            embedder: None,
+            rate_limit: vec![],
+            max_concurrent_calls: None,
+            priority: vec![],
+            component_weight: vec![],
+            strict_policy: false,
+            http_cache: false,
+            network_log_sample: vec![],
+            prefix_tools: false,
+            tool_alias: vec![],
+            tool_collision: None,
+            instructions: None,
+            isolate_sessions: false,
+            management_api: false,
+            management_listen: None,
+            management_base_path: None,
+            secret_provider: None,
+            verification_policy: None,
+            inspection_config: None,
+            oauth_issuer: None,
+            oauth_audience: None,
+            oauth_jwks_url: None,
+            oauth_scope: vec![],
         }
     }
 
@@ -236,6 +725,299 @@ plugin_dir = "/config/plugin/dir"
         assert_eq!(config.plugin_dir, PathBuf::from("/config/plugin/dir"));
     }
 
+    #[test]
+    fn test_config_file_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+
+        fs::write(&config_file, "manifest = \"/etc/wassette/wassette.yaml\"\n").unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(
+            config.manifest,
+            Some(PathBuf::from("/etc/wassette/wassette.yaml"))
+        );
+
+        let config = Config::new_from_path(&empty_test_cli_config(), temp_dir.path().join("none"))
+            .expect("Failed to create config");
+        assert_eq!(config.manifest, None);
+    }
+
+    #[test]
+    fn test_config_file_event_sinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+
+        fs::write(
+            &config_file,
+            "event_sinks = [\"https://events.example.com/hook\", \"nats://localhost:4222/wassette\"]\n",
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(
+            config.event_sinks,
+            vec![
+                "https://events.example.com/hook".to_string(),
+                "nats://localhost:4222/wassette".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_file_otlp() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+
+        fs::write(
+            &config_file,
+            "otlp_endpoint = \"https://otlp.example.com\"\notlp_headers = [\"DD-API-KEY=key\"]\notlp_interval_secs = 15\n",
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(
+            config.otlp_endpoint.as_deref(),
+            Some("https://otlp.example.com")
+        );
+        assert_eq!(config.otlp_headers, vec!["DD-API-KEY=key".to_string()]);
+        assert_eq!(config.otlp_interval_secs, Some(15));
+    }
+
+    #[test]
+    fn test_config_file_profiling() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            "profile_components = [\"fetch-rs\"]\nprofile_dir = \"/tmp/profiles\"\n",
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(config.profile_components, vec!["fetch-rs".to_string()]);
+        assert_eq!(config.profile_dir, Some(PathBuf::from("/tmp/profiles")));
+    }
+
+    #[test]
+    fn test_config_file_capture_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(&config_file, "capture_output = true\n").unwrap();
+
+        // The CLI flag defaults to off, which must not override the file
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert!(config.capture_output);
+    }
+
+    #[test]
+    fn test_config_file_http_tls() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+[[http_tls]]
+host = "*.internal.corp"
+ca_bundle = "/etc/wassette/corp-ca.pem"
+
+[[http_tls]]
+host = "payments.example.com"
+client_cert = "/etc/wassette/client.pem"
+client_key = "/etc/wassette/client.key"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(config.http_tls.len(), 2);
+        assert_eq!(config.http_tls[0].host, "*.internal.corp");
+        assert_eq!(
+            config.http_tls[0].ca_bundle,
+            Some(PathBuf::from("/etc/wassette/corp-ca.pem"))
+        );
+        assert_eq!(config.http_tls[0].client_cert, None);
+        assert_eq!(
+            config.http_tls[1].client_key,
+            Some(PathBuf::from("/etc/wassette/client.key"))
+        );
+    }
+
+    #[test]
+    fn test_config_file_http_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+[http_limits]
+max_response_size = "16Mi"
+read_timeout = "30s"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(
+            config.http_limits.max_response_size,
+            Some(wassette::MemoryLimit::String("16Mi".to_string()))
+        );
+        assert_eq!(config.http_limits.connect_timeout, None);
+        assert_eq!(
+            config.http_limits.read_timeout,
+            Some(wassette::TimeoutLimit::String("30s".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_config_file_api_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+[[api_keys]]
+name = "triage-bot"
+key_env = "TRIAGE_BOT_KEY"
+rate_limit = ["*=60/1m"]
+tools = ["list-components"]
+components = ["fetch"]
+
+[[api_keys]]
+name = "admin"
+key = "admin-secret"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(config.api_keys.len(), 2);
+        assert_eq!(
+            config.api_keys[0].key_env.as_deref(),
+            Some("TRIAGE_BOT_KEY")
+        );
+        assert_eq!(config.api_keys[0].rate_limit, vec!["*=60/1m"]);
+        assert_eq!(
+            config.api_keys[0].components,
+            Some(vec!["fetch".to_string()])
+        );
+        assert_eq!(config.api_keys[1].key.as_deref(), Some("admin-secret"));
+        assert_eq!(config.api_keys[1].tools, None);
+    }
+
+    #[test]
+    fn test_config_file_tool_naming() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+prefix_tools = true
+tool_alias = ["fetch-rs=web"]
+tool_collision = "reject"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        let naming = config.tool_naming().unwrap();
+        assert!(naming.prefix);
+        assert_eq!(naming.aliases["fetch-rs"], "web");
+        assert_eq!(naming.collision, ToolCollision::Reject);
+
+        let mut config = config;
+        config.tool_collision = Some("last".to_string());
+        assert!(config.tool_naming().is_err());
+    }
+
+    #[test]
+    fn test_config_file_call_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+[call_limits]
+max_argument_size = 65536
+
+[call_limits.components.fetch-rs]
+max_argument_depth = 4
+sanitize_output = false
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        let settings = config.call_limits.to_settings();
+        let limits = settings.limits(None);
+        assert_eq!(limits.max_argument_size, 65536);
+        assert_eq!(limits.max_argument_depth, 32);
+        assert!(limits.sanitize_output);
+        let fetch = settings.limits(Some("fetch-rs"));
+        assert_eq!(fetch.max_argument_size, 65536);
+        assert_eq!(fetch.max_argument_depth, 4);
+        assert!(!fetch.sanitize_output);
+    }
+
+    #[test]
+    fn test_config_file_mcp_servers() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            r#"
+[[mcp_servers]]
+name = "github"
+command = "github-mcp-server"
+args = ["stdio"]
+env = { GITHUB_TOKEN = "secret" }
+
+[[mcp_servers]]
+name = "search"
+url = "https://search.example.com/mcp"
+headers = { Authorization = "Bearer token" }
+tools = ["query"]
+"#,
+        )
+        .unwrap();
+
+        let config = Config::new_from_path(&empty_test_cli_config(), &config_file)
+            .expect("Failed to create config");
+        assert_eq!(config.mcp_servers.len(), 2);
+        assert_eq!(
+            config.mcp_servers[0].to_downstream().unwrap().transport,
+            DownstreamTransport::Command {
+                command: "github-mcp-server".to_string(),
+                args: vec!["stdio".to_string()],
+                env: HashMap::from([("GITHUB_TOKEN".to_string(), "secret".to_string())]),
+            }
+        );
+        let search = config.mcp_servers[1].to_downstream().unwrap();
+        assert!(matches!(
+            search.transport,
+            DownstreamTransport::StreamableHttp { ref url, .. } if url == "https://search.example.com/mcp"
+        ));
+        assert_eq!(search.tools, Some(vec!["query".to_string()]));
+
+        let both = McpServerConfig {
+            name: "both".to_string(),
+            command: Some("server".to_string()),
+            url: Some("http://localhost/mcp".to_string()),
+            ..Default::default()
+        };
+        assert!(both.to_downstream().is_err());
+    }
+
     #[test]
     fn test_new_method_without_wassette_config_file_env() {
         // This test verifies that new() works when WASETTE_CONFIG_FILE is not set
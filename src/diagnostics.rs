@@ -0,0 +1,192 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Machine-readable outcomes of CLI commands
+//!
+//! With `--output json`, a command prints exactly one JSON document on stdout: `{"status": "ok",
+//! "result": ...}` when it succeeds, or `{"status": "error", "class": ..., "exit_code": ...,
+//! "message": ...}` when it fails. Every [`FailureClass`] has its own exit code in either mode, so
+//! CI pipelines can tell a policy denial from a trap or a timeout.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// How a command reports its outcome
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum OutputMode {
+    /// Results on stdout and errors on stderr, for people
+    #[default]
+    Text,
+    /// A single JSON document on stdout, for scripts and CI
+    Json,
+}
+
+/// Why a command failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// Anything without a class of its own, such as a missing component or invalid arguments
+    Error,
+    /// The tool ran and returned an error
+    ToolError,
+    /// The component was denied access to a resource by its policy and the call failed
+    PolicyDenied,
+    /// The component trapped
+    Trap,
+    /// The call didn't finish in time
+    Timeout,
+}
+
+impl FailureClass {
+    /// The exit code of the process. 2 is left to usage errors, which clap reports itself.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureClass::Error => 1,
+            FailureClass::ToolError => 3,
+            FailureClass::PolicyDenied => 4,
+            FailureClass::Trap => 5,
+            FailureClass::Timeout => 6,
+        }
+    }
+}
+
+/// An error of a command with the class it is reported with. Errors of any other type are
+/// reported as [`FailureClass::Error`].
+#[derive(Debug)]
+pub struct Failure {
+    /// Why the command failed
+    pub class: FailureClass,
+    /// What went wrong
+    pub message: String,
+    /// Structured information about the failure, such as the report of a failed reconcile
+    pub details: Option<Value>,
+}
+
+impl Failure {
+    /// Creates a failure without details
+    pub fn new(class: FailureClass, message: impl Into<String>) -> Self {
+        Self {
+            class,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    /// Adds structured details to the failure
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Prints the result of a successful command
+pub fn print_value(mode: OutputMode, value: &Value) -> anyhow::Result<()> {
+    let document = match mode {
+        OutputMode::Text => serde_json::to_string_pretty(value)?,
+        OutputMode::Json => serde_json::to_string_pretty(&json!({
+            "status": "ok",
+            "result": value,
+        }))?,
+    };
+    println!("{document}");
+    Ok(())
+}
+
+/// The document `--output json` prints for a failed command
+fn error_document(error: &anyhow::Error) -> (FailureClass, Value) {
+    let (class, message, details) = match error.downcast_ref::<Failure>() {
+        Some(failure) => (
+            failure.class,
+            failure.message.clone(),
+            failure.details.clone(),
+        ),
+        None => (FailureClass::Error, format!("{error:#}"), None),
+    };
+    let mut document = json!({
+        "status": "error",
+        "class": class,
+        "exit_code": class.exit_code(),
+        "message": message,
+    });
+    if let Some(details) = details {
+        document["details"] = details;
+    }
+    (class, document)
+}
+
+/// Reports a failed command and returns the exit code of its class
+pub fn report_error(mode: OutputMode, error: &anyhow::Error) -> i32 {
+    let (class, document) = error_document(error);
+    match mode {
+        OutputMode::Text => {
+            if let Some(details) = document.get("details") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(details).unwrap_or_default()
+                );
+            }
+            match error.downcast_ref::<Failure>() {
+                Some(failure) => eprintln!("Error: {failure}"),
+                None => eprintln!("Error: {error:?}"),
+            }
+        }
+        OutputMode::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&document).unwrap_or_default()
+        ),
+    }
+    class.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context as _;
+
+    use super::*;
+
+    #[test]
+    fn test_error_document() {
+        let error = anyhow::Error::new(
+            Failure::new(
+                FailureClass::PolicyDenied,
+                "Tool fetch failed after a request was denied",
+            )
+            .with_details(json!({"denied_requests": []})),
+        );
+        let (class, document) = error_document(&error);
+        assert_eq!(class, FailureClass::PolicyDenied);
+        assert_eq!(
+            document,
+            json!({
+                "status": "error",
+                "class": "policy_denied",
+                "exit_code": 4,
+                "message": "Tool fetch failed after a request was denied",
+                "details": {"denied_requests": []},
+            })
+        );
+
+        let error = Err::<(), _>(anyhow::anyhow!("Component not found: fetch"))
+            .context("Failed to call tool")
+            .unwrap_err();
+        let (class, document) = error_document(&error);
+        assert_eq!(class, FailureClass::Error);
+        assert_eq!(document["exit_code"], 1);
+        assert_eq!(
+            document["message"],
+            "Failed to call tool: Component not found: fetch"
+        );
+        assert!(document.get("details").is_none());
+    }
+}
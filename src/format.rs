@@ -9,9 +9,10 @@ use rmcp::model::CallToolResult;
 use serde_json::{Map, Value};
 
 /// Output format options for CLI commands
-#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
 pub enum OutputFormat {
     /// JSON format
+    #[default]
     Json,
     /// YAML format
     Yaml,
@@ -19,12 +20,6 @@ pub enum OutputFormat {
     Table,
 }
 
-impl Default for OutputFormat {
-    fn default() -> Self {
-        Self::Json
-    }
-}
-
 /// Format a JSON value as YAML string
 pub fn format_as_yaml(value: &Value) -> Result<String> {
     serde_yaml::to_string(value).map_err(|e| anyhow::anyhow!("Failed to format as YAML: {}", e))
@@ -83,6 +78,24 @@ pub fn format_as_table(value: &Value) -> Result<String> {
     Ok(table)
 }
 
+/// The text contents of a tool call result, one per line
+pub fn result_text(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .flatten()
+        .filter_map(|content| content.as_text())
+        .map(|text| text.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The value of a tool call result: its text parsed as JSON, or the text if it isn't JSON
+pub fn result_value(result: &CallToolResult) -> Value {
+    let text = result_text(result);
+    serde_json::from_str(&text).unwrap_or(Value::String(text))
+}
+
 /// Print the result of a tool call with the specified format
 pub fn print_result(result: &CallToolResult, output_format: OutputFormat) -> Result<()> {
     if let Some(contents) = &result.content {
@@ -0,0 +1,129 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Health and readiness endpoints for the HTTP transports
+//!
+//! When the server listens on HTTP, it binds its address before it initializes the engine and
+//! loads the components, and answers `/healthz` and `/readyz` from then on. `/healthz` returns
+//! 200 as long as the process runs. `/readyz` returns 503 until the server has started up and
+//! 200 after that, so Kubernetes and load balancers only route traffic to a server that can
+//! handle it. Until then, the MCP endpoints return 503 with `Retry-After` as well. Neither
+//! endpoint requires authentication.
+
+use std::sync::{Arc, OnceLock};
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+use tower::ServiceExt as _;
+
+/// Path of the liveness endpoint
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Path of the readiness endpoint
+pub const READY_PATH: &str = "/readyz";
+
+/// Seconds after which clients should retry a request made while the server starts up
+const STARTING_RETRY_AFTER_SECS: u64 = 1;
+
+/// The startup state of an HTTP server: the router of its transports, once it has one
+#[derive(Clone, Default)]
+pub struct Startup {
+    app: Arc<OnceLock<Router>>,
+}
+
+impl Startup {
+    /// The router to serve from the start: the health endpoints, and `app` for every other path
+    /// once the server is ready
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(HEALTH_PATH, get(healthz))
+            .route(READY_PATH, get(readyz))
+            .fallback(forward)
+            .with_state(self.clone())
+    }
+
+    /// Marks the server as ready to handle the requests to `app`
+    pub fn ready(&self, app: Router) {
+        if self.app.set(app).is_err() {
+            tracing::warn!("HTTP server was already marked as ready");
+            return;
+        }
+        tracing::info!("HTTP server is ready");
+    }
+
+    /// Whether the server has started up
+    pub fn is_ready(&self) -> bool {
+        self.app.get().is_some()
+    }
+}
+
+async fn healthz() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn readyz(State(startup): State<Startup>) -> Response {
+    if startup.is_ready() {
+        Json(json!({ "status": "ready" })).into_response()
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "starting" })),
+        )
+            .into_response()
+    }
+}
+
+/// Passes a request on to the router of the transports, or rejects it while there is none
+async fn forward(State(startup): State<Startup>, request: Request) -> Response {
+    match startup.app.get() {
+        Some(app) => match app.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        },
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, STARTING_RETRY_AFTER_SECS.to_string())],
+            "Server is starting",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    async fn get_status(router: &Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_startup() {
+        let startup = Startup::default();
+        let router = startup.router();
+
+        assert_eq!(get_status(&router, HEALTH_PATH).await, StatusCode::OK);
+        assert_eq!(
+            get_status(&router, READY_PATH).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let request = Request::builder().uri("/mcp").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        startup.ready(Router::new().route("/mcp", get(|| async { "mcp" })));
+        assert_eq!(get_status(&router, READY_PATH).await, StatusCode::OK);
+        assert_eq!(get_status(&router, "/mcp").await, StatusCode::OK);
+        assert_eq!(get_status(&router, "/other").await, StatusCode::NOT_FOUND);
+        assert_eq!(get_status(&router, HEALTH_PATH).await, StatusCode::OK);
+    }
+}
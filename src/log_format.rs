@@ -0,0 +1,275 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Structured JSON log output for `serve --log-format json`
+//!
+//! Every event is written as one JSON object per line with `timestamp`, `level`, `target` and
+//! `message`, the fields of the spans it happened in and its own fields. The fields that name a
+//! tool are renamed to `tool_name`, so that log pipelines such as Loki or ELK can group by
+//! `component_id`, `tool_name` and `duration_ms` regardless of which module logged them.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::Record;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{
+    FmtContext, FormatEvent, FormatFields, FormattedFields, Layer, MakeWriter,
+};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Format of the log output of the server
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+/// The stable name of a field, which is `tool_name` for every field that names a tool
+fn field_name(name: &str) -> &str {
+    match name {
+        "function_name" | "tool" => "tool_name",
+        name => name,
+    }
+}
+
+/// Records fields into a JSON object, keeping numbers and booleans as such
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.0.insert(field_name(field.name()).to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, Value::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{value:?}")));
+    }
+}
+
+/// Keeps the fields of a span as a JSON object, so that [`JsonFormat`] can merge them into the
+/// events in the span
+pub struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    fn add_fields(&self, current: &mut FormattedFields<Self>, fields: &Record<'_>) -> fmt::Result {
+        let mut map = serde_json::from_str(&current.fields).unwrap_or_default();
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+/// Writes an event as one JSON line. The fields of the event win over those of its spans, and
+/// those of inner spans over those of outer ones.
+pub struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let metadata = event.metadata();
+
+        let mut map = Map::new();
+        map.insert("timestamp".to_string(), Value::from(timestamp));
+        map.insert(
+            "level".to_string(),
+            Value::from(metadata.level().to_string()),
+        );
+        map.insert("target".to_string(), Value::from(metadata.target()));
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<JsonFields>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) {
+                    map.extend(fields);
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut map));
+        writeln!(writer, "{}", Value::Object(map))
+    }
+}
+
+/// A layer that writes every event as a JSON line to `writer`
+pub fn json_layer<S, W>(writer: W) -> Layer<S, JsonFields, JsonFormat, W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'writer> MakeWriter<'writer> + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .fmt_fields(JsonFields)
+        .event_format(JsonFormat)
+        .with_writer(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use mcp_server::{execute_tool_call, ToolCallOptions};
+    use rmcp::model::CallToolRequestParam;
+    use tracing::{info, info_span, warn};
+    use tracing_subscriber::layer::SubscriberExt as _;
+    use wassette::LifecycleManager;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'writer> MakeWriter<'writer> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_json_layer() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::registry().with(json_layer(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("call", component_id = "weather", tool = "get-weather");
+            let _guard = span.enter();
+            info!(duration_ms = 42u64, success = true, "Tool call finished");
+            span.record("component_id", "forecast");
+            warn!(function_name = "get-forecast", "Slow call");
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+
+        assert!(lines[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], module_path!());
+        assert_eq!(lines[0]["message"], "Tool call finished");
+        assert_eq!(lines[0]["component_id"], "weather");
+        assert_eq!(lines[0]["tool_name"], "get-weather");
+        assert_eq!(lines[0]["duration_ms"], 42);
+        assert_eq!(lines[0]["success"], true);
+
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["component_id"], "forecast");
+        assert_eq!(lines[1]["tool_name"], "get-forecast");
+        assert!(lines[1].get("function_name").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_span() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("answer.wasm"),
+            r#"(component
+              (core module $m (func (export "answer") (result i32) i32.const 42))
+              (core instance $i (instantiate $m))
+              (func $answer (result u32) (canon lift (core func $i "answer")))
+              (export "answer" (func $answer)))"#,
+        )?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+
+        let buffer = Buffer::default();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(json_layer(buffer.clone())),
+        );
+        let req = CallToolRequestParam {
+            name: "answer".into(),
+            arguments: Some(Map::new()),
+        };
+        execute_tool_call(req, &lifecycle_manager, None, &ToolCallOptions::default()).await?;
+
+        let lines = buffer.lines();
+        let line = |message: &str| {
+            lines
+                .iter()
+                .find(|line| line["message"] == message)
+                .unwrap_or_else(|| panic!("No {message:?} in {lines:?}"))
+        };
+        // Logged in the span of the tool call
+        let handling = line("Handling tool call");
+        assert_eq!(handling["tool_name"], "answer");
+        assert!(handling.get("method_name").is_none());
+        let finished = line("Tool call finished");
+        assert_eq!(finished["component_id"], "answer");
+        assert_eq!(finished["tool_name"], "answer");
+        assert_eq!(finished["success"], true);
+        assert!(finished["duration_ms"].is_u64());
+        Ok(())
+    }
+}
@@ -7,48 +7,94 @@
 
 use std::collections::HashMap;
 use std::future::Future;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
 use mcp_server::components::{
-    handle_list_components, handle_load_component_cli, handle_unload_component_cli,
+    handle_list_components, handle_load_component_cli, handle_purge_component,
+    handle_restore_component_cli, handle_search_history, handle_unload_component_cli,
+};
+use mcp_server::security::{
+    ACCESS_DENIED_ERROR_CODE, INVALID_CALL_ERROR_CODE, RATE_LIMITED_ERROR_CODE,
 };
 use mcp_server::tools::*;
 use mcp_server::{
-    handle_prompts_list, handle_resources_list, handle_tools_call, handle_tools_list,
-    LifecycleManager,
+    forward_component_logs, forward_list_changes, forward_resource_updates,
+    forward_security_events, handle_completion, handle_prompts_get, handle_prompts_list,
+    handle_resource_templates_list, handle_resources_list, handle_resources_read,
+    handle_tools_call, handle_tools_list, list_roots, loaded_tools, render_instructions,
+    requested_loggers, ComponentWeight, Downstreams, InvalidArguments, InvalidToolCall,
+    LifecycleManager, ListChanges, LoadedTools, LogFilter, RateLimitExceeded, RateLimitRule,
+    RateLimiter, ResourceSubscriptions, RootGrants, Scheduler, SummarizeSettings, SummaryBudget,
+    TokenHeuristic, TokenSettings, ToolAccessDenied, ToolCallOptions, ToolError, ToolPriority,
+    DEFAULT_INSTRUCTIONS_TEMPLATE,
 };
 use rmcp::model::{
-    CallToolRequestParam, CallToolResult, ErrorData, ListPromptsResult, ListResourcesResult,
-    ListToolsResult, PaginatedRequestParam, ServerCapabilities, ServerInfo, ToolsCapability,
+    CallToolRequestParam, CallToolResult, CompleteRequestParam, CompleteResult, ErrorCode,
+    ErrorData, GetPromptRequestParam, GetPromptResult, InitializeRequestParam, InitializeResult,
+    ListPromptsResult, ListResourceTemplatesResult, ListResourcesResult, ListToolsResult,
+    PaginatedRequestParam, PromptsCapability, ReadResourceRequestParam, ReadResourceResult,
+    ResourcesCapability, ServerCapabilities, ServerInfo, SetLevelRequestParam,
+    SubscribeRequestParam, ToolsCapability, UnsubscribeRequestParam,
 };
-use rmcp::service::{serve_server, RequestContext, RoleServer};
+use rmcp::service::{serve_server, NotificationContext, RequestContext, RoleServer};
+use rmcp::transport::sse_server::SseServerConfig;
 use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
 use rmcp::transport::streamable_http_server::StreamableHttpService;
 use rmcp::transport::{stdio as stdio_transport, SseServer};
 use rmcp::ServerHandler;
 use serde_json::{json, Map, Value};
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
+use wassette::{
+    demo_fixture_urls, ComponentIndex, ComponentSigner, DemoWorkspace, DesiredStateManifest,
+    Embedder, EventSink, InspectionConfig, KeylessConfig, NetworkSampling, OtlpMetricsConfig,
+    ProfilingConfig, ProxyConfig, TlsConfig, ToolNaming, VerificationPolicy,
+    DEFAULT_EVENT_RETENTION, DEFAULT_OUTPUT_CAPTURE_LIMIT, DEFAULT_SECRET_WATCH_INTERVAL,
+    FILE_SECRET_PROVIDER,
+};
 
+mod api_key;
+mod bus;
+mod call;
 mod commands;
+mod compression;
 mod config;
+mod diagnostics;
 mod format;
+mod health;
+mod log_format;
+mod management_api;
+mod oauth;
+mod openapi;
+mod rate_limit;
+mod repl;
+mod tls;
+mod tui;
 
 use commands::{
     Cli, Commands, ComponentCommands, GrantPermissionCommands, PermissionCommands, PolicyCommands,
-    RevokePermissionCommands, Serve,
+    RevokePermissionCommands, SecretCommands, Serve, Transport,
 };
-use format::{print_result, OutputFormat};
+use diagnostics::{print_value, Failure, FailureClass, OutputMode};
+use format::{print_result, result_text, result_value, OutputFormat};
+use log_format::LogFormat;
 
 /// Represents the different types of tools available in the MCP server
 #[derive(Debug, Clone, PartialEq)]
 enum ToolName {
     LoadComponent,
     UnloadComponent,
+    RestoreComponent,
+    PurgeComponent,
     ListComponents,
+    SearchHistory,
     GetPolicy,
     GrantStoragePermission,
     GrantNetworkPermission,
@@ -67,7 +113,10 @@ impl TryFrom<&str> for ToolName {
         match value {
             "load-component" => Ok(Self::LoadComponent),
             "unload-component" => Ok(Self::UnloadComponent),
+            "restore-component" => Ok(Self::RestoreComponent),
+            "purge-component" => Ok(Self::PurgeComponent),
             "list-components" => Ok(Self::ListComponents),
+            "search-history" => Ok(Self::SearchHistory),
             "get-policy" => Ok(Self::GetPolicy),
             "grant-storage-permission" => Ok(Self::GrantStoragePermission),
             "grant-network-permission" => Ok(Self::GrantNetworkPermission),
@@ -97,7 +146,10 @@ impl AsRef<str> for ToolName {
         match self {
             Self::LoadComponent => "load-component",
             Self::UnloadComponent => "unload-component",
+            Self::RestoreComponent => "restore-component",
+            Self::PurgeComponent => "purge-component",
             Self::ListComponents => "list-components",
+            Self::SearchHistory => "search-history",
             Self::GetPolicy => "get-policy",
             Self::GrantStoragePermission => "grant-storage-permission",
             Self::GrantNetworkPermission => "grant-network-permission",
@@ -192,14 +244,22 @@ const BIND_ADDRESS: &str = "127.0.0.1:9001";
 #[derive(Clone)]
 pub struct McpServer {
     lifecycle_manager: LifecycleManager,
+    options: ToolCallOptions,
+    subscriptions: ResourceSubscriptions,
+    instructions: Arc<str>,
+    isolate_sessions: bool,
 }
 
+/// Source of the IDs of isolated client sessions
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
 /// Handle CLI tool commands by creating appropriate tool call requests
 async fn handle_tool_cli_command(
     lifecycle_manager: &LifecycleManager,
     tool_name: &str,
     args: Map<String, Value>,
     output_format: OutputFormat,
+    output_mode: OutputMode,
 ) -> Result<()> {
     let tool = ToolName::try_from(tool_name)?;
 
@@ -211,7 +271,10 @@ async fn handle_tool_cli_command(
     let result = match tool {
         ToolName::LoadComponent => handle_load_component_cli(&req, lifecycle_manager).await?,
         ToolName::UnloadComponent => handle_unload_component_cli(&req, lifecycle_manager).await?,
+        ToolName::RestoreComponent => handle_restore_component_cli(&req, lifecycle_manager).await?,
+        ToolName::PurgeComponent => handle_purge_component(&req, lifecycle_manager).await?,
         ToolName::ListComponents => handle_list_components(lifecycle_manager).await?,
+        ToolName::SearchHistory => handle_search_history(&req, lifecycle_manager).await?,
         ToolName::GetPolicy => handle_get_policy(&req, lifecycle_manager).await?,
         ToolName::GrantStoragePermission => {
             handle_grant_storage_permission(&req, lifecycle_manager).await?
@@ -237,15 +300,86 @@ async fn handle_tool_cli_command(
         ToolName::ResetPermission => handle_reset_permission(&req, lifecycle_manager).await?,
     };
 
-    // Print the result using the format module
-    print_result(&result, output_format)?;
-
-    // Exit with error code if the tool result indicates an error
     if result.is_error.unwrap_or(false) {
-        std::process::exit(1);
+        return Err(Failure::new(FailureClass::Error, result_text(&result)).into());
     }
+    match output_mode {
+        OutputMode::Text => print_result(&result, output_format),
+        OutputMode::Json => print_value(output_mode, &result_value(&result)),
+    }
+}
 
-    Ok(())
+/// Compresses the responses of `router` when a threshold is given
+fn with_compression(router: axum::Router, threshold: Option<usize>) -> axum::Router {
+    match threshold {
+        Some(threshold) => router.layer(axum::middleware::from_fn_with_state(
+            threshold,
+            compression::compress,
+        )),
+        None => router,
+    }
+}
+
+/// Adds rate limit headers to the responses of `router` to calls of rate limited tools
+fn with_rate_limit_headers(router: axum::Router, limiter: RateLimiter) -> axum::Router {
+    router.layer(axum::middleware::from_fn_with_state(
+        limiter,
+        rate_limit::rate_limit_headers,
+    ))
+}
+
+/// Requires a valid bearer token for the requests to `router`, and serves the protected resource
+/// metadata that tells clients where to get one
+fn with_oauth(router: axum::Router, server: Option<oauth::ResourceServer>) -> axum::Router {
+    match server {
+        Some(server) => router
+            .layer(axum::middleware::from_fn_with_state(
+                server.clone(),
+                oauth::authenticate,
+            ))
+            .route(
+                oauth::RESOURCE_METADATA_PATH,
+                axum::routing::get(oauth::resource_metadata).with_state(server),
+            ),
+        None => router,
+    }
+}
+
+/// Requires one of the configured API keys for the requests to `router`
+fn with_api_keys(router: axum::Router, auth: Option<api_key::ApiKeyAuth>) -> axum::Router {
+    match auth {
+        Some(auth) => router.layer(axum::middleware::from_fn_with_state(
+            auth,
+            api_key::authenticate,
+        )),
+        None => router,
+    }
+}
+
+/// Create LifecycleManager from plugin directory that keeps secrets with the built-in secret
+/// provider called `secret_provider`, or in files
+async fn create_secret_lifecycle_manager(
+    plugin_dir: &Option<PathBuf>,
+    secret_provider: &Option<String>,
+) -> Result<LifecycleManager> {
+    let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+    match secret_provider {
+        Some(provider) => lifecycle_manager.with_builtin_secret_provider(provider),
+        None => Ok(lifecycle_manager),
+    }
+}
+
+/// Reads the value of a secret from standard input, without the line break that ends it
+fn read_secret_value() -> Result<String> {
+    let mut value = std::io::read_to_string(std::io::stdin())
+        .context("Failed to read the value of the secret from standard input")?;
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
 }
 
 /// Create LifecycleManager from plugin directory
@@ -254,6 +388,64 @@ async fn create_lifecycle_manager(plugin_dir: Option<PathBuf>) -> Result<Lifecyc
         config::Config {
             plugin_dir: dir,
             environment_vars: std::collections::HashMap::new(),
+            listen: None,
+            tls_cert: None,
+            tls_key: None,
+            acme_domain: vec![],
+            acme_contact: vec![],
+            acme_cache: None,
+            acme_directory: None,
+            manifest: None,
+            event_sinks: vec![],
+            event_log: false,
+            event_log_retention_days: None,
+            otlp_endpoint: None,
+            otlp_headers: vec![],
+            otlp_interval_secs: None,
+            profile_components: vec![],
+            profile_dir: None,
+            bus: None,
+            capture_output: false,
+            no_compression: false,
+            compression_threshold: None,
+            token_heuristic: None,
+            annotate_tokens: false,
+            summarize: vec![],
+            index_history: false,
+            secret_tools: false,
+            component_index: vec![],
+            semantic_recall: false,
+            embedder: None,
+            rate_limit: vec![],
+            max_concurrent_calls: None,
+            priority: vec![],
+            component_weight: vec![],
+            strict_policy: false,
+            http_cache: false,
+            network_log_sample: vec![],
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            http_tls: vec![],
+            http_limits: Default::default(),
+            prefix_tools: false,
+            tool_alias: vec![],
+            tool_collision: None,
+            instructions: None,
+            isolate_sessions: false,
+            management_api: false,
+            management_listen: None,
+            management_base_path: None,
+            secret_provider: None,
+            verification_policy: None,
+            inspection_config: None,
+            oauth_issuer: None,
+            oauth_audience: None,
+            oauth_jwks_url: None,
+            oauth_scope: vec![],
+            api_keys: vec![],
+            mcp_servers: vec![],
+            call_limits: Default::default(),
         }
     } else {
         config::Config::from_serve(&crate::Serve {
@@ -261,13 +453,71 @@ async fn create_lifecycle_manager(plugin_dir: Option<PathBuf>) -> Result<Lifecyc
             stdio: false,
             sse: false,
             streamable_http: false,
+            transport: Vec::new(),
+            listen: None,
+            tls_cert: None,
+            tls_key: None,
+            acme_domain: vec![],
+            acme_contact: vec![],
+            acme_cache: None,
+            acme_directory: None,
             env_vars: vec![],
             env_file: None,
+            manifest: None,
+            event_sinks: vec![],
+            event_log: false,
+            event_log_retention_days: None,
+            otlp_endpoint: None,
+            otlp_headers: vec![],
+            otlp_interval_secs: None,
+            profile_components: vec![],
+            profile_dir: None,
+            log_format: Default::default(),
+            bus: None,
+            capture_output: false,
+            no_compression: false,
+            compression_threshold: None,
+            token_heuristic: None,
+            annotate_tokens: false,
+            summarize: vec![],
+            index_history: false,
+            secret_tools: false,
+            component_index: vec![],
+            semantic_recall: false,
+            embedder: None,
+            rate_limit: vec![],
+            max_concurrent_calls: None,
+            priority: vec![],
+            component_weight: vec![],
+            strict_policy: false,
+            http_cache: false,
+            network_log_sample: vec![],
+            prefix_tools: false,
+            tool_alias: vec![],
+            tool_collision: None,
+            instructions: None,
+            isolate_sessions: false,
+            management_api: false,
+            management_listen: None,
+            management_base_path: None,
+            secret_provider: None,
+            verification_policy: None,
+            inspection_config: None,
+            oauth_issuer: None,
+            oauth_audience: None,
+            oauth_jwks_url: None,
+            oauth_scope: vec![],
         })
         .context("Failed to load configuration")?
     };
 
-    LifecycleManager::new_with_env(&config.plugin_dir, config.environment_vars).await
+    let naming = config.tool_naming()?;
+    let lifecycle_manager =
+        LifecycleManager::new_with_env(&config.plugin_dir, config.environment_vars).await?;
+    if naming == ToolNaming::default() {
+        return Ok(lifecycle_manager);
+    }
+    lifecycle_manager.with_tool_naming(naming).await
 }
 
 impl McpServer {
@@ -275,8 +525,51 @@ impl McpServer {
     ///
     /// # Arguments
     /// * `lifecycle_manager` - The lifecycle manager for handling component operations
-    pub fn new(lifecycle_manager: LifecycleManager) -> Self {
-        Self { lifecycle_manager }
+    /// * `options` - Settings applied to every tool call
+    pub fn new(lifecycle_manager: LifecycleManager, options: ToolCallOptions) -> Self {
+        Self {
+            lifecycle_manager,
+            options,
+            subscriptions: ResourceSubscriptions::default(),
+            instructions: DEFAULT_INSTRUCTIONS_TEMPLATE.into(),
+            isolate_sessions: false,
+        }
+    }
+
+    /// Replaces the template of the instructions sent to clients when they connect
+    pub fn with_instructions(mut self, template: impl Into<Arc<str>>) -> Self {
+        self.instructions = template.into();
+        self
+    }
+
+    /// Gives every client session its own view of the components, see
+    /// [`LifecycleManager::for_session`]
+    pub fn with_isolated_sessions(mut self) -> Self {
+        self.isolate_sessions = true;
+        self
+    }
+
+    /// A server for a new client session. It shares everything but the resource subscriptions,
+    /// tool list change notifications, client roots and log level, and with isolated sessions
+    /// the components the session loads.
+    pub fn session(&self) -> Self {
+        let lifecycle_manager = if self.isolate_sessions {
+            let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+            self.lifecycle_manager.for_session(format!("session-{id}"))
+        } else {
+            self.lifecycle_manager.clone()
+        };
+        Self {
+            lifecycle_manager,
+            options: ToolCallOptions {
+                list_changes: ListChanges::default(),
+                roots: RootGrants::default(),
+                log_filter: LogFilter::default(),
+                ..self.options.clone()
+            },
+            subscriptions: ResourceSubscriptions::default(),
+            ..self.clone()
+        }
     }
 }
 
@@ -288,49 +581,149 @@ impl ServerHandler for McpServer {
                 tools: Some(ToolsCapability {
                     list_changed: Some(true),
                 }),
+                logging: Some(Default::default()),
+                // Component IDs and enum values of tool, prompt and resource template arguments
+                completions: Some(Default::default()),
+                // Every loaded component has a usage prompt
+                prompts: Some(PromptsCapability { list_changed: None }),
+                // Component files and the full results of summarized tool calls are served as
+                // resources
+                resources: Some(ResourcesCapability {
+                    subscribe: Some(true),
+                    list_changed: None,
+                }),
                 ..Default::default()
             },
-            instructions: Some(
-                r#"This server runs tools in sandboxed WebAssembly environments with no default access to host resources.
-
-Key points:
-- Tools must be loaded before use: "Load component from oci://registry/tool:version" or "file:///path/to/tool.wasm"
-- When the server starts, it will load all tools present in the plugin directory.
-- You can list loaded tools with 'list-components' tool.
-- Each tool only accesses resources explicitly granted by a policy file (filesystem paths, network domains, etc.)
-- You MUST never modify the policy file directly, use tools to grant permissions instead.
-- Tools needs permission for that resource
-- If access is denied, suggest alternatives within allowed permissions or propose to grant permission"#.to_string(),
-            ),
             ..Default::default()
         }
     }
 
+    fn initialize<'a>(
+        &'a self,
+        request: InitializeRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<InitializeResult, ErrorData>> + Send + 'a>> {
+        if ctx.peer.peer_info().is_none() {
+            ctx.peer.set_peer_info(request);
+        }
+        Box::pin(async move {
+            // The instructions describe the components that are loaded when the client connects
+            Ok(InitializeResult {
+                instructions: Some(
+                    render_instructions(&self.lifecycle_manager, &self.instructions).await,
+                ),
+                ..self.get_info()
+            })
+        })
+    }
+
     fn call_tool<'a>(
         &'a self,
         params: CallToolRequestParam,
         ctx: RequestContext<RoleServer>,
     ) -> Pin<Box<dyn Future<Output = Result<CallToolResult, ErrorData>> + Send + 'a>> {
         let peer_clone = ctx.peer.clone();
+        let api_key = api_key::api_key_name(&ctx.extensions).map(str::to_string);
 
         Box::pin(async move {
-            let result = handle_tools_call(params, &self.lifecycle_manager, peer_clone).await;
+            let result = handle_tools_call(
+                params,
+                &self.lifecycle_manager,
+                peer_clone,
+                &self.options,
+                api_key.as_deref(),
+            )
+            .await;
             match result {
                 Ok(value) => serde_json::from_value(value).map_err(|e| {
                     ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
                 }),
-                Err(err) => Err(ErrorData::parse_error(err.to_string(), None)),
+                Err(err) => {
+                    if let Some(exceeded) = err.downcast_ref::<RateLimitExceeded>() {
+                        Err(ErrorData::new(
+                            ErrorCode(RATE_LIMITED_ERROR_CODE),
+                            exceeded.to_string(),
+                            Some(exceeded.data()),
+                        ))
+                    } else if let Some(denied) = err.downcast_ref::<ToolAccessDenied>() {
+                        Err(ErrorData::new(
+                            ErrorCode(ACCESS_DENIED_ERROR_CODE),
+                            denied.to_string(),
+                            Some(denied.data()),
+                        ))
+                    } else if let Some(invalid) = err.downcast_ref::<InvalidToolCall>() {
+                        Err(ErrorData::new(
+                            ErrorCode(INVALID_CALL_ERROR_CODE),
+                            invalid.to_string(),
+                            Some(invalid.data()),
+                        ))
+                    } else if let Some(invalid) = err.downcast_ref::<InvalidArguments>() {
+                        Err(ErrorData::new(
+                            ErrorCode(INVALID_CALL_ERROR_CODE),
+                            invalid.to_string(),
+                            Some(invalid.data()),
+                        ))
+                    } else {
+                        let data = ToolError::from_error(&err).to_value();
+                        Err(ErrorData::parse_error(err.to_string(), Some(data)))
+                    }
+                }
             }
         })
     }
 
+    fn on_initialized<'a>(
+        &'a self,
+        ctx: NotificationContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        tracing::info!("client initialized");
+        // Component wasi:logging calls are sent to each client as notifications/message
+        tokio::spawn(forward_component_logs(
+            self.lifecycle_manager.clone(),
+            ctx.peer.clone(),
+            self.options.log_filter.clone(),
+        ));
+        tokio::spawn(forward_security_events(
+            self.lifecycle_manager.clone(),
+            ctx.peer.clone(),
+            self.options.log_filter.clone(),
+        ));
+        tokio::spawn(forward_list_changes(
+            self.lifecycle_manager.clone(),
+            ctx.peer.clone(),
+            self.options.list_changes.clone(),
+        ));
+        tokio::spawn(forward_resource_updates(
+            self.lifecycle_manager.clone(),
+            ctx.peer,
+            self.subscriptions.clone(),
+        ));
+        Box::pin(std::future::ready(()))
+    }
+
+    fn on_roots_list_changed<'a>(
+        &'a self,
+        ctx: NotificationContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        let lifecycle_manager = self.lifecycle_manager.clone();
+        let root_grants = self.options.roots.clone();
+        // Listing the roots is a request to the client, which must not wait in its notification
+        tokio::spawn(async move {
+            match list_roots(&ctx.peer).await {
+                Ok(roots) => root_grants.sync(&lifecycle_manager, roots).await,
+                Err(e) => tracing::warn!(error = %e, "Failed to update root access"),
+            }
+        });
+        Box::pin(std::future::ready(()))
+    }
+
     fn list_tools<'a>(
         &'a self,
         _params: Option<PaginatedRequestParam>,
         _ctx: RequestContext<RoleServer>,
     ) -> Pin<Box<dyn Future<Output = Result<ListToolsResult, ErrorData>> + Send + 'a>> {
         Box::pin(async move {
-            let result = handle_tools_list(&self.lifecycle_manager).await;
+            let result = handle_tools_list(&self.lifecycle_manager, &self.options).await;
             match result {
                 Ok(value) => serde_json::from_value(value).map_err(|e| {
                     ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
@@ -346,7 +739,7 @@ Key points:
         _ctx: RequestContext<RoleServer>,
     ) -> Pin<Box<dyn Future<Output = Result<ListPromptsResult, ErrorData>> + Send + 'a>> {
         Box::pin(async move {
-            let result = handle_prompts_list(serde_json::Value::Null).await;
+            let result = handle_prompts_list(&self.lifecycle_manager).await;
             match result {
                 Ok(value) => serde_json::from_value(value).map_err(|e| {
                     ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
@@ -356,13 +749,30 @@ Key points:
         })
     }
 
+    fn get_prompt<'a>(
+        &'a self,
+        params: GetPromptRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<GetPromptResult, ErrorData>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = handle_prompts_get(params, &self.lifecycle_manager).await;
+            match result {
+                Ok(value) => serde_json::from_value(value).map_err(|e| {
+                    ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
+                }),
+                Err(err) => Err(ErrorData::invalid_params(err.to_string(), None)),
+            }
+        })
+    }
+
     fn list_resources<'a>(
         &'a self,
         _params: Option<PaginatedRequestParam>,
         _ctx: RequestContext<RoleServer>,
     ) -> Pin<Box<dyn Future<Output = Result<ListResourcesResult, ErrorData>> + Send + 'a>> {
         Box::pin(async move {
-            let result = handle_resources_list(serde_json::Value::Null).await;
+            let result =
+                handle_resources_list(&self.options.results, &self.lifecycle_manager).await;
             match result {
                 Ok(value) => serde_json::from_value(value).map_err(|e| {
                     ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
@@ -371,6 +781,88 @@ Key points:
             }
         })
     }
+
+    fn list_resource_templates<'a>(
+        &'a self,
+        _params: Option<PaginatedRequestParam>,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<ListResourceTemplatesResult, ErrorData>> + Send + 'a>>
+    {
+        Box::pin(async move {
+            match handle_resource_templates_list() {
+                Ok(value) => serde_json::from_value(value).map_err(|e| {
+                    ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
+                }),
+                Err(err) => Err(ErrorData::parse_error(err.to_string(), None)),
+            }
+        })
+    }
+
+    fn complete<'a>(
+        &'a self,
+        params: CompleteRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<CompleteResult, ErrorData>> + Send + 'a>> {
+        Box::pin(async move {
+            let result = handle_completion(params, &self.lifecycle_manager).await;
+            match result {
+                Ok(value) => serde_json::from_value(value).map_err(|e| {
+                    ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
+                }),
+                Err(err) => Err(ErrorData::internal_error(err.to_string(), None)),
+            }
+        })
+    }
+
+    fn subscribe<'a>(
+        &'a self,
+        params: SubscribeRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), ErrorData>> + Send + 'a>> {
+        let result = self
+            .subscriptions
+            .subscribe(&params.uri)
+            .map_err(|e| ErrorData::resource_not_found(e.to_string(), None));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn set_level<'a>(
+        &'a self,
+        params: SetLevelRequestParam,
+        ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), ErrorData>> + Send + 'a>> {
+        // Clients can narrow the notifications down to some loggers in the request's `_meta`
+        let result = requested_loggers(&ctx.meta)
+            .map(|loggers| self.options.log_filter.set(params.level, loggers))
+            .map_err(|e| ErrorData::invalid_params(e.to_string(), None));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn unsubscribe<'a>(
+        &'a self,
+        params: UnsubscribeRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), ErrorData>> + Send + 'a>> {
+        self.subscriptions.unsubscribe(&params.uri);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn read_resource<'a>(
+        &'a self,
+        params: ReadResourceRequestParam,
+        _ctx: RequestContext<RoleServer>,
+    ) -> Pin<Box<dyn Future<Output = Result<ReadResourceResult, ErrorData>> + Send + 'a>> {
+        Box::pin(async move {
+            let result =
+                handle_resources_read(params, &self.options.results, &self.lifecycle_manager).await;
+            match result {
+                Ok(value) => serde_json::from_value(value).map_err(|e| {
+                    ErrorData::parse_error(format!("Failed to parse result: {e}"), None)
+                }),
+                Err(err) => Err(ErrorData::resource_not_found(err.to_string(), None)),
+            }
+        })
+    }
 }
 
 /// Formats build information similar to agentgateway's version output
@@ -411,10 +903,473 @@ fn format_build_info() -> String {
     )
 }
 
+/// Runs the MCP server. A demo server answers the HTTP requests of components from canned
+/// responses.
+async fn serve(cfg: &Serve, demo: Option<&DemoWorkspace>) -> Result<()> {
+    // Initialize logging based on transport type
+    let transports = cfg.selected_transports()?;
+    let use_stdio_transport = transports.contains(&Transport::Stdio);
+
+    // Configure logging - use stderr for stdio transport to avoid interfering with MCP protocol
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+    .unwrap_or_else(|_| {
+        "info,cranelift_codegen=warn,cranelift_entity=warn,cranelift_bforest=warn,cranelift_frontend=warn"
+            .to_string()
+            .into()
+    });
+
+    let registry = tracing_subscriber::registry().with(env_filter);
+
+    if cfg.log_format == LogFormat::Json {
+        if use_stdio_transport {
+            registry
+                .with(log_format::json_layer(std::io::stderr))
+                .init();
+        } else {
+            registry
+                .with(log_format::json_layer(std::io::stdout))
+                .init();
+        }
+    } else if use_stdio_transport {
+        registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(std::io::stderr)
+                    .with_ansi(false),
+            )
+            .init();
+    } else {
+        registry.with(tracing_subscriber::fmt::layer()).init();
+    }
+
+    let config = config::Config::from_serve(cfg).context("Failed to load configuration")?;
+    let listen = config
+        .listen
+        .unwrap_or_else(|| BIND_ADDRESS.parse().expect("valid default bind address"));
+    let management_listen = config
+        .management_listen
+        .as_deref()
+        .map(str::parse::<management_api::ManagementListen>)
+        .transpose()?;
+    let management_api = config.management_api || management_listen.is_some();
+    let management_base_path = config
+        .management_base_path
+        .as_deref()
+        .map(management_api::parse_base_path)
+        .transpose()?
+        .flatten();
+    if management_api && management_listen.is_none() && transports == [Transport::Stdio] {
+        bail!(
+            "The management API is only served on the sse and streamable-http transports, \
+             or on its own address with --management-listen"
+        );
+    }
+
+    // The HTTP listener is up during startup, so probes can tell a starting server from a dead one
+    let ct = CancellationToken::new();
+    let startup = health::Startup::default();
+    let server_tls = config.server_tls();
+    if transports
+        .iter()
+        .any(|transport| *transport != Transport::Stdio)
+    {
+        spawn_http_server(listen, &server_tls, startup.router(), ct.clone()).await?;
+    }
+
+    let naming = config.tool_naming()?;
+    let mut lifecycle_manager =
+        LifecycleManager::new_with_env(&config.plugin_dir, config.environment_vars).await?;
+    if naming != ToolNaming::default() {
+        lifecycle_manager = lifecycle_manager.with_tool_naming(naming).await?;
+    }
+    match config.secret_provider.as_deref() {
+        None | Some(FILE_SECRET_PROVIDER) => {
+            lifecycle_manager = lifecycle_manager.with_secret_watch(DEFAULT_SECRET_WATCH_INTERVAL);
+        }
+        Some(provider) => {
+            lifecycle_manager = lifecycle_manager.with_builtin_secret_provider(provider)?;
+        }
+    }
+    if config.capture_output {
+        lifecycle_manager = lifecycle_manager.with_output_capture(DEFAULT_OUTPUT_CAPTURE_LIMIT);
+    }
+    if !config.profile_components.is_empty() {
+        let mut profiling = ProfilingConfig::new(config.profile_components.clone());
+        if let Some(dir) = &config.profile_dir {
+            profiling = profiling.with_dir(dir);
+        }
+        lifecycle_manager = lifecycle_manager.with_profiling(profiling);
+    }
+    if let Some(workspace) = demo {
+        lifecycle_manager = lifecycle_manager.with_demo_fixtures();
+        tracing::info!(
+            components = ?DemoWorkspace::component_ids().collect::<Vec<_>>(),
+            files = %workspace.files_dir().display(),
+            "Serving the demo components. Network access is stubbed, only these URLs respond: {}",
+            demo_fixture_urls().collect::<Vec<_>>().join(", ")
+        );
+    }
+    lifecycle_manager = lifecycle_manager.with_http_proxy(ProxyConfig::from_env_or(
+        config.http_proxy.as_deref(),
+        config.https_proxy.as_deref(),
+        config.no_proxy.as_deref(),
+    )?);
+    lifecycle_manager = lifecycle_manager.with_http_tls(TlsConfig::new(config.http_tls)?);
+    lifecycle_manager = lifecycle_manager.with_http_limits(&config.http_limits)?;
+    if config.http_cache {
+        lifecycle_manager = lifecycle_manager.with_http_cache();
+    }
+    if !config.network_log_sample.is_empty() {
+        lifecycle_manager = lifecycle_manager.with_network_sampling(
+            config
+                .network_log_sample
+                .iter()
+                .map(|rule| rule.parse::<NetworkSampling>())
+                .collect::<Result<Vec<_>>>()?,
+        );
+    }
+    if config.strict_policy {
+        lifecycle_manager = lifecycle_manager.with_strict_policy().await?;
+    }
+    if let Some(path) = &config.verification_policy {
+        lifecycle_manager =
+            lifecycle_manager.with_verification_policy(VerificationPolicy::from_file(path).await?);
+    }
+    if let Some(path) = &config.inspection_config {
+        lifecycle_manager =
+            lifecycle_manager.with_inspection(InspectionConfig::from_file(path).await?)?;
+    }
+    if config.index_history {
+        lifecycle_manager = lifecycle_manager.with_history_index().await?;
+    }
+    if !config.component_index.is_empty() {
+        lifecycle_manager = lifecycle_manager.with_component_indexes(
+            config
+                .component_index
+                .iter()
+                .map(|index| index.parse::<ComponentIndex>())
+                .collect::<Result<Vec<_>>>()?,
+        );
+    }
+    if config.semantic_recall {
+        let embedder = config
+            .embedder
+            .as_deref()
+            .map(str::parse::<Embedder>)
+            .transpose()?
+            .unwrap_or_default();
+        lifecycle_manager = lifecycle_manager.with_semantic_recall(embedder).await?;
+    }
+
+    if !config.event_sinks.is_empty() {
+        let sinks = config
+            .event_sinks
+            .iter()
+            .map(|sink| sink.parse::<EventSink>())
+            .collect::<Result<Vec<_>>>()?;
+        lifecycle_manager.spawn_event_forwarder(sinks);
+    }
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        let mut otlp = OtlpMetricsConfig::new(endpoint)?;
+        for header in &config.otlp_headers {
+            otlp = otlp.with_header(header)?;
+        }
+        if let Some(secs) = config.otlp_interval_secs {
+            otlp = otlp.with_interval(std::time::Duration::from_secs(secs.max(1)));
+        }
+        lifecycle_manager.spawn_metrics_exporter(otlp);
+    }
+
+    if config.event_log {
+        let retention = match config.event_log_retention_days {
+            Some(days) => std::time::Duration::from_secs(days * 24 * 60 * 60),
+            None => DEFAULT_EVENT_RETENTION,
+        };
+        lifecycle_manager = lifecycle_manager.with_event_log(retention).await?;
+    }
+
+    if let Some(manifest_path) = &config.manifest {
+        let manifest = DesiredStateManifest::from_file(manifest_path).await?;
+        let report = lifecycle_manager.reconcile(&manifest, false).await?;
+        for failure in &report.failed {
+            tracing::warn!(
+                component_id = %failure.component_id,
+                error = %failure.error,
+                "Component does not match the manifest"
+            );
+        }
+    }
+
+    if config.oauth_issuer.is_some() && !config.api_keys.is_empty() {
+        bail!("OAuth and API keys can't both authenticate the HTTP transports");
+    }
+    let (api_key_auth, api_keys) = api_key::ApiKeyAuth::new(&config.api_keys)?.unzip();
+    let downstreams = Downstreams::connect(
+        &config
+            .mcp_servers
+            .iter()
+            .map(config::McpServerConfig::to_downstream)
+            .collect::<Result<Vec<_>>>()?,
+    )
+    .await?;
+    let options = ToolCallOptions {
+        tokens: TokenSettings {
+            heuristic: config
+                .token_heuristic
+                .as_deref()
+                .map(str::parse::<TokenHeuristic>)
+                .transpose()?
+                .unwrap_or_default(),
+            annotate: config.annotate_tokens,
+        },
+        summarize: SummarizeSettings::new(
+            config
+                .summarize
+                .iter()
+                .map(|budget| budget.parse::<SummaryBudget>())
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        rate_limits: RateLimiter::new(
+            config
+                .rate_limit
+                .iter()
+                .map(|rule| rule.parse::<RateLimitRule>())
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        scheduler: Scheduler::new(
+            config.max_concurrent_calls,
+            config
+                .priority
+                .iter()
+                .map(|priority| priority.parse::<ToolPriority>())
+                .collect::<Result<Vec<_>>>()?,
+            config
+                .component_weight
+                .iter()
+                .map(|weight| weight.parse::<ComponentWeight>())
+                .collect::<Result<Vec<_>>>()?,
+        )?,
+        api_keys: api_keys.unwrap_or_default(),
+        downstreams,
+        call_limits: config.call_limits.to_settings(),
+        secret_tools: config.secret_tools,
+        ..Default::default()
+    };
+
+    if let Some(bus) = &config.bus {
+        let endpoint = bus.parse::<bus::BusEndpoint>()?;
+        tokio::spawn(bus::serve_bus(
+            endpoint,
+            lifecycle_manager.clone(),
+            options.clone(),
+        ));
+    }
+
+    let compression_threshold = (!config.no_compression).then(|| {
+        config
+            .compression_threshold
+            .unwrap_or(compression::DEFAULT_COMPRESSION_THRESHOLD)
+    });
+
+    let rate_limits = options.rate_limits.clone();
+    let resource_server = config
+        .oauth_issuer
+        .clone()
+        .map(|issuer| -> Result<_> {
+            Ok(oauth::ResourceServer::new(oauth::OAuthConfig {
+                issuer,
+                audience: config.oauth_audience.clone(),
+                jwks_url: config.oauth_jwks_url.clone(),
+                scopes: config
+                    .oauth_scope
+                    .iter()
+                    .map(|rule| rule.parse::<oauth::ScopeRule>())
+                    .collect::<Result<Vec<_>>>()?,
+            }))
+        })
+        .transpose()?;
+    let loaded = loaded_tools(&lifecycle_manager).await;
+    let management_router = management_api.then(|| {
+        management_api::with_base_path(
+            management_api::router(lifecycle_manager.clone()),
+            management_base_path.as_deref(),
+        )
+    });
+    let mut server = McpServer::new(lifecycle_manager, options);
+    if let Some(path) = &config.instructions {
+        let template = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read instructions template {}", path.display()))?;
+        server = server.with_instructions(template);
+    }
+    if config.isolate_sessions {
+        server = server.with_isolated_sessions();
+    }
+    print_banner(&config.plugin_dir, loaded);
+
+    // Every transport serves clones of the same server, so they share one lifecycle manager
+    let mut router = axum::Router::new();
+    if transports.contains(&Transport::StreamableHttp) {
+        tracing::info!(
+            "Starting MCP server on {} with streamable HTTP transport",
+            listen
+        );
+        router = router.merge(streamable_http_router(server.clone()));
+    }
+    if transports.contains(&Transport::Sse) {
+        tracing::info!("Starting MCP server on {} with SSE HTTP transport", listen);
+        let (sse_server, sse_router) = SseServer::new(SseServerConfig {
+            bind: listen,
+            sse_path: "/sse".to_string(),
+            post_path: "/message".to_string(),
+            ct: ct.clone(),
+            sse_keep_alive: None,
+        });
+        router = router.merge(sse_router);
+        let server = server.clone();
+        sse_server.with_service(move || server.session());
+    }
+    // The management API is behind the same authentication as the MCP endpoints, wherever it is
+    let secure = |router| {
+        with_compression(
+            with_rate_limit_headers(
+                with_api_keys(
+                    with_oauth(router, resource_server.clone()),
+                    api_key_auth.clone(),
+                ),
+                rate_limits.clone(),
+            ),
+            compression_threshold,
+        )
+    };
+    match (management_router, &management_listen) {
+        (Some(management_router), Some(management_listen)) => {
+            tracing::info!(
+                "Serving the management API on {}{}{}",
+                management_listen,
+                management_base_path.as_deref().unwrap_or_default(),
+                management_api::API_PATH
+            );
+            management_api::spawn_server(
+                management_listen,
+                &server_tls,
+                secure(management_router),
+                ct.clone(),
+            )
+            .await?;
+        }
+        (Some(management_router), None) => {
+            tracing::info!(
+                "Serving the management API on {}{}{}",
+                listen,
+                management_base_path.as_deref().unwrap_or_default(),
+                management_api::API_PATH
+            );
+            router = router.merge(management_router);
+        }
+        (None, _) => {}
+    }
+    if transports
+        .iter()
+        .any(|transport| *transport != Transport::Stdio)
+    {
+        startup.ready(secure(router));
+    }
+    let stdio_service = if use_stdio_transport {
+        tracing::info!("Starting MCP server with stdio transport");
+        Some(serve_server(server, stdio_transport()).await?)
+    } else {
+        None
+    };
+
+    tokio::signal::ctrl_c().await?;
+    ct.cancel();
+    if let Some(running_service) = stdio_service {
+        let _ = running_service.cancel().await;
+    }
+
+    tracing::info!("MCP server shutting down");
+    Ok(())
+}
+
+/// Shows what the server starts with: on stderr when it's a terminal, so it doesn't get in the way
+/// of the stdio transport or log collectors, and in the log otherwise
+fn print_banner(plugin_dir: &std::path::Path, loaded: LoadedTools) {
+    if std::io::stderr().is_terminal() {
+        eprintln!(
+            "Wassette {}\n  Plugin directory: {}\n  Loaded: {} components, {} tools",
+            env!("CARGO_PKG_VERSION"),
+            plugin_dir.display(),
+            loaded.components,
+            loaded.tools
+        );
+    } else {
+        tracing::info!(
+            version = env!("CARGO_PKG_VERSION"),
+            plugin_dir = %plugin_dir.display(),
+            components = loaded.components,
+            tools = loaded.tools,
+            "Wassette started"
+        );
+    }
+}
+
+/// Serves `router` on `listen` until `shutdown` is cancelled, over HTTPS if `tls` is enabled
+async fn spawn_http_server(
+    listen: std::net::SocketAddr,
+    tls: &tls::ServerTlsConfig,
+    router: axum::Router,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let tcp_listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+    let shutdown = async move { shutdown.cancelled().await };
+    let server = if tls.is_enabled() {
+        tracing::info!("Serving HTTPS on {listen}");
+        let listener = tls.listen(tcp_listener)?;
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown)
+                .await
+        })
+    } else {
+        tokio::spawn(async move {
+            axum::serve(tcp_listener, router)
+                .with_graceful_shutdown(shutdown)
+                .await
+        })
+    };
+    tokio::spawn(async move {
+        if let Ok(Err(e)) = server.await {
+            tracing::error!(error = %e, "HTTP server shut down with error");
+        }
+    });
+    Ok(())
+}
+
+/// The streamable HTTP transport of `server`, at `/mcp`
+fn streamable_http_router(server: McpServer) -> axum::Router {
+    let service = StreamableHttpService::new(
+        move || Ok(server.session()),
+        LocalSessionManager::default().into(),
+        Default::default(),
+    );
+    axum::Router::new().nest_service("/mcp", service)
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     let cli = Cli::parse();
+    let output_mode = cli.output_mode;
+    if let Err(e) = run(&cli).await {
+        // Exit right away: a timed out call may still occupy a runtime thread
+        std::process::exit(diagnostics::report_error(output_mode, &e));
+    }
+}
 
+async fn run(cli: &Cli) -> Result<()> {
     // Handle version flag
     if cli.version {
         println!("{}", format_build_info());
@@ -423,92 +1378,17 @@ async fn main() -> Result<()> {
 
     match &cli.command {
         Some(command) => match command {
-            Commands::Serve(cfg) => {
-                // Initialize logging based on transport type
-                let (use_stdio_transport, use_streamable_http) = match (
-                    cfg.stdio,
-                    cfg.sse,
-                    cfg.streamable_http,
-                ) {
-                    (false, false, false) => (true, false), // Default case: use stdio transport
-                    (true, false, false) => (true, false),  // Stdio transport only
-                    (false, true, false) => (false, false), // SSE transport only
-                    (false, false, true) => (false, true),  // Streamable HTTP transport only
-                    _ => {
-                        return Err(anyhow::anyhow!(
-                        "Running multiple transports simultaneously is not supported. Please choose one of: --stdio, --sse, or --streamable-http."
-                    ));
-                    }
+            Commands::Serve(cfg) => serve(cfg, None).await?,
+            Commands::Demo(demo) => {
+                let workspace = DemoWorkspace::create().await?;
+                let cfg = Serve {
+                    plugin_dir: Some(workspace.plugin_dir()),
+                    stdio: demo.stdio,
+                    sse: demo.sse,
+                    streamable_http: demo.streamable_http,
+                    ..Default::default()
                 };
-
-                // Configure logging - use stderr for stdio transport to avoid interfering with MCP protocol
-                let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| {
-                    "info,cranelift_codegen=warn,cranelift_entity=warn,cranelift_bforest=warn,cranelift_frontend=warn"
-                        .to_string()
-                        .into()
-                });
-
-                let registry = tracing_subscriber::registry().with(env_filter);
-
-                if use_stdio_transport {
-                    registry
-                        .with(
-                            tracing_subscriber::fmt::layer()
-                                .with_writer(std::io::stderr)
-                                .with_ansi(false),
-                        )
-                        .init();
-                } else {
-                    registry.with(tracing_subscriber::fmt::layer()).init();
-                }
-
-                let config =
-                    config::Config::from_serve(cfg).context("Failed to load configuration")?;
-
-                let lifecycle_manager =
-                    LifecycleManager::new_with_env(&config.plugin_dir, config.environment_vars)
-                        .await?;
-
-                let server = McpServer::new(lifecycle_manager);
-
-                if use_stdio_transport {
-                    tracing::info!("Starting MCP server with stdio transport");
-                    let transport = stdio_transport();
-                    let running_service = serve_server(server, transport).await?;
-
-                    tokio::signal::ctrl_c().await?;
-                    let _ = running_service.cancel().await;
-                } else if use_streamable_http {
-                    tracing::info!(
-                        "Starting MCP server on {} with streamable HTTP transport",
-                        BIND_ADDRESS
-                    );
-                    let service = StreamableHttpService::new(
-                        move || Ok(server.clone()),
-                        LocalSessionManager::default().into(),
-                        Default::default(),
-                    );
-
-                    let router = axum::Router::new().nest_service("/mcp", service);
-                    let tcp_listener = tokio::net::TcpListener::bind(BIND_ADDRESS).await?;
-                    let _ = axum::serve(tcp_listener, router)
-                        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
-                        .await;
-                } else {
-                    tracing::info!(
-                        "Starting MCP server on {} with SSE HTTP transport",
-                        BIND_ADDRESS
-                    );
-                    let ct = SseServer::serve(BIND_ADDRESS.parse().unwrap())
-                        .await?
-                        .with_service(move || server.clone());
-
-                    tokio::signal::ctrl_c().await?;
-                    ct.cancel();
-                }
-
-                tracing::info!("MCP server shutting down");
+                serve(&cfg, Some(&workspace)).await?;
             }
             Commands::Component { command } => match command {
                 ComponentCommands::Load { path, plugin_dir } => {
@@ -520,21 +1400,69 @@ async fn main() -> Result<()> {
                         "load-component",
                         args,
                         OutputFormat::Json,
+                        cli.output_mode,
                     )
                     .await?;
                 }
-                ComponentCommands::Unload { id, plugin_dir } => {
+                ComponentCommands::Unload {
+                    id,
+                    purge,
+                    plugin_dir,
+                } => {
                     let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
                     let mut args = Map::new();
                     args.insert("id".to_string(), json!(id));
+                    args.insert("purge".to_string(), json!(purge));
                     handle_tool_cli_command(
                         &lifecycle_manager,
                         "unload-component",
                         args,
                         OutputFormat::Json,
+                        cli.output_mode,
+                    )
+                    .await?;
+                }
+                ComponentCommands::Restore { id, plugin_dir } => {
+                    let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                    let mut args = Map::new();
+                    args.insert("id".to_string(), json!(id));
+                    handle_tool_cli_command(
+                        &lifecycle_manager,
+                        "restore-component",
+                        args,
+                        OutputFormat::Json,
+                        cli.output_mode,
                     )
                     .await?;
                 }
+                ComponentCommands::Purge { id, plugin_dir } => {
+                    let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                    let mut args = Map::new();
+                    args.insert("id".to_string(), json!(id));
+                    handle_tool_cli_command(
+                        &lifecycle_manager,
+                        "purge-component",
+                        args,
+                        OutputFormat::Json,
+                        cli.output_mode,
+                    )
+                    .await?;
+                }
+                ComponentCommands::Orphans { remove, plugin_dir } => {
+                    let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                    let orphans = if *remove {
+                        lifecycle_manager.remove_orphaned_artifacts().await?
+                    } else {
+                        lifecycle_manager.find_orphaned_artifacts().await?
+                    };
+                    print_value(
+                        cli.output_mode,
+                        &json!({
+                            "orphans": orphans,
+                            "removed": remove,
+                        }),
+                    )?;
+                }
                 ComponentCommands::List {
                     plugin_dir,
                     output_format,
@@ -546,6 +1474,7 @@ async fn main() -> Result<()> {
                         "list-components",
                         args,
                         *output_format,
+                        cli.output_mode,
                     )
                     .await?;
                 }
@@ -559,8 +1488,14 @@ async fn main() -> Result<()> {
                     let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
                     let mut args = Map::new();
                     args.insert("component_id".to_string(), json!(component_id));
-                    handle_tool_cli_command(&lifecycle_manager, "get-policy", args, *output_format)
-                        .await?;
+                    handle_tool_cli_command(
+                        &lifecycle_manager,
+                        "get-policy",
+                        args,
+                        *output_format,
+                        cli.output_mode,
+                    )
+                    .await?;
                 }
             },
             Commands::Permission { command } => match command {
@@ -587,6 +1522,7 @@ async fn main() -> Result<()> {
                             "grant-storage-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -610,6 +1546,7 @@ async fn main() -> Result<()> {
                             "grant-network-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -633,6 +1570,7 @@ async fn main() -> Result<()> {
                             "grant-environment-variable-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -660,6 +1598,7 @@ async fn main() -> Result<()> {
                             "grant-memory-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -685,6 +1624,7 @@ async fn main() -> Result<()> {
                             "revoke-storage-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -708,6 +1648,7 @@ async fn main() -> Result<()> {
                             "revoke-network-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -731,6 +1672,7 @@ async fn main() -> Result<()> {
                             "revoke-environment-variable-permission",
                             args,
                             OutputFormat::Json,
+                            cli.output_mode,
                         )
                         .await?;
                     }
@@ -747,15 +1689,315 @@ async fn main() -> Result<()> {
                         "reset-permission",
                         args,
                         OutputFormat::Json,
+                        cli.output_mode,
                     )
                     .await?;
                 }
             },
+            Commands::Secret { command } => match command {
+                SecretCommands::Set {
+                    component_id,
+                    key,
+                    value,
+                    functions,
+                    plugin_dir,
+                    secret_provider,
+                } => {
+                    let value = match value {
+                        Some(value) => value.clone(),
+                        None => read_secret_value()?,
+                    };
+                    let lifecycle_manager =
+                        create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                    lifecycle_manager
+                        .set_secret(component_id, key, &value)
+                        .await?;
+                    if !functions.is_empty() {
+                        lifecycle_manager
+                            .set_secret_scope(component_id, key, Some(functions.clone()))
+                            .await?;
+                    }
+                    print_value(
+                        cli.output_mode,
+                        &json!({
+                            "component_id": component_id,
+                            "key": key,
+                            "status": "secret set",
+                        }),
+                    )?;
+                }
+                SecretCommands::List {
+                    component_id,
+                    plugin_dir,
+                    secret_provider,
+                } => {
+                    let lifecycle_manager =
+                        create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                    print_value(
+                        cli.output_mode,
+                        &json!({
+                            "component_id": component_id,
+                            "keys": lifecycle_manager.list_secret_keys(component_id).await?,
+                            "scopes": lifecycle_manager.secret_scopes(component_id).await?,
+                        }),
+                    )?;
+                }
+                SecretCommands::Delete {
+                    component_id,
+                    key,
+                    plugin_dir,
+                    secret_provider,
+                } => {
+                    let lifecycle_manager =
+                        create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                    if !lifecycle_manager.delete_secret(component_id, key).await? {
+                        bail!("Component {component_id} has no secret {key}");
+                    }
+                    print_value(
+                        cli.output_mode,
+                        &json!({
+                            "component_id": component_id,
+                            "key": key,
+                            "status": "secret deleted",
+                        }),
+                    )?;
+                }
+                SecretCommands::Import {
+                    component_id,
+                    path,
+                    plugin_dir,
+                    secret_provider,
+                } => {
+                    let lifecycle_manager =
+                        create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                    let keys = lifecycle_manager.import_secrets(component_id, path).await?;
+                    print_value(
+                        cli.output_mode,
+                        &json!({
+                            "component_id": component_id,
+                            "imported": keys,
+                        }),
+                    )?;
+                }
+            },
+            Commands::ExportBundle {
+                output,
+                components,
+                encrypt_secrets,
+                plugin_dir,
+                secret_provider,
+            } => {
+                let passphrase = if *encrypt_secrets {
+                    Some(
+                        std::env::var("WASSETTE_BUNDLE_PASSPHRASE")
+                            .ok()
+                            .filter(|passphrase| !passphrase.is_empty())
+                            .context(
+                                "Set WASSETTE_BUNDLE_PASSPHRASE to export with --encrypt-secrets",
+                            )?,
+                    )
+                } else {
+                    None
+                };
+                let lifecycle_manager =
+                    create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                let manifest = lifecycle_manager
+                    .export_bundle(components, output, passphrase.as_deref())
+                    .await
+                    .context("Failed to export bundle")?;
+                print_value(
+                    cli.output_mode,
+                    &json!({
+                        "status": "bundle exported successfully",
+                        "path": output,
+                        "manifest": manifest,
+                    }),
+                )?;
+            }
+            Commands::Reconcile {
+                manifest,
+                dry_run,
+                plugin_dir,
+            } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                let desired = DesiredStateManifest::from_file(manifest).await?;
+                let report = lifecycle_manager.reconcile(&desired, *dry_run).await?;
+                if !report.failed.is_empty() {
+                    return Err(Failure::new(
+                        FailureClass::Error,
+                        format!("{} components failed to reconcile", report.failed.len()),
+                    )
+                    .with_details(serde_json::to_value(&report)?)
+                    .into());
+                }
+                print_value(cli.output_mode, &serde_json::to_value(&report)?)?;
+            }
+            Commands::CheckUpdates {
+                manifest,
+                plugin_dir,
+            } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                let desired = DesiredStateManifest::from_file(manifest).await?;
+                let report = lifecycle_manager.check_updates(&desired).await?;
+                print_value(cli.output_mode, &serde_json::to_value(&report)?)?;
+            }
+            Commands::ApproveUpdate {
+                component_id,
+                manifest,
+                plugin_dir,
+            } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                let desired = DesiredStateManifest::from_file(manifest).await?;
+                let approved = lifecycle_manager.approve_update(component_id).await?;
+                let report = lifecycle_manager.reconcile(&desired, false).await?;
+                if !report.failed.is_empty() {
+                    return Err(Failure::new(
+                        FailureClass::Error,
+                        format!(
+                            "Approved {} but {} components failed to reconcile",
+                            approved.reference,
+                            report.failed.len()
+                        ),
+                    )
+                    .with_details(serde_json::to_value(&report)?)
+                    .into());
+                }
+                print_value(
+                    cli.output_mode,
+                    &json!({
+                        "approved": approved,
+                        "reconcile": report,
+                    }),
+                )?;
+            }
+            Commands::ImportBundle {
+                path,
+                overwrite,
+                plugin_dir,
+                secret_provider,
+            } => {
+                let passphrase = std::env::var("WASSETTE_BUNDLE_PASSPHRASE").ok();
+                let lifecycle_manager =
+                    create_secret_lifecycle_manager(plugin_dir, secret_provider).await?;
+                let manifest = lifecycle_manager
+                    .import_bundle(path, *overwrite, passphrase.as_deref())
+                    .await
+                    .context("Failed to import bundle")?;
+                print_value(
+                    cli.output_mode,
+                    &json!({
+                        "status": "bundle imported successfully",
+                        "path": path,
+                        "manifest": manifest,
+                    }),
+                )?;
+            }
+            Commands::Sign {
+                reference,
+                key,
+                keyless,
+                tlog_upload,
+                fulcio_url,
+                rekor_url,
+                username,
+            } => {
+                let signer = match key {
+                    Some(key) if !*keyless => {
+                        let password = std::env::var("COSIGN_PASSWORD").unwrap_or_default();
+                        let signer =
+                            ComponentSigner::from_file(&key.to_string_lossy(), password.as_bytes())
+                                .await?;
+                        if *tlog_upload {
+                            signer.with_transparency_log(rekor_url.clone())
+                        } else {
+                            signer
+                        }
+                    }
+                    _ => {
+                        ComponentSigner::keyless(&KeylessConfig {
+                            fulcio_url: fulcio_url.clone(),
+                            rekor_url: rekor_url.clone(),
+                        })
+                        .await?
+                    }
+                };
+                let reference: oci_client::Reference = reference
+                    .strip_prefix("oci://")
+                    .unwrap_or(reference)
+                    .parse()
+                    .with_context(|| format!("Invalid OCI reference {reference}"))?;
+                let auth = match username {
+                    Some(username) => oci_client::secrets::RegistryAuth::Basic(
+                        username.clone(),
+                        std::env::var("WASSETTE_REGISTRY_PASSWORD")
+                            .context("Set WASSETTE_REGISTRY_PASSWORD to push with --username")?,
+                    ),
+                    None => oci_client::secrets::RegistryAuth::Anonymous,
+                };
+                let signed = signer
+                    .sign(&oci_client::Client::default(), &reference, &auth)
+                    .await
+                    .context("Failed to sign component")?;
+                print_value(cli.output_mode, &serde_json::to_value(&signed)?)?;
+            }
+            Commands::Call {
+                tool,
+                arguments,
+                timeout,
+                plugin_dir,
+            } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                call::run(
+                    lifecycle_manager,
+                    tool,
+                    arguments,
+                    *timeout,
+                    cli.output_mode,
+                )
+                .await?;
+            }
+            Commands::Repl { plugin_dir } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                repl::run(lifecycle_manager).await?;
+            }
+            Commands::Tui { plugin_dir } => {
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone()).await?;
+                tui::run(lifecycle_manager).await?;
+            }
+            Commands::SearchHistory {
+                query,
+                limit,
+                kind,
+                component_id,
+                include_text,
+                plugin_dir,
+                output_format,
+            } => {
+                // A running server may hold the writer, so only open the index for reading
+                let lifecycle_manager = create_lifecycle_manager(plugin_dir.clone())
+                    .await?
+                    .with_history_reader()?;
+                let mut args = Map::new();
+                args.insert("query".to_string(), json!(query));
+                args.insert("limit".to_string(), json!(limit));
+                if let Some(kind) = kind {
+                    args.insert("kind".to_string(), json!(kind));
+                }
+                if let Some(component_id) = component_id {
+                    args.insert("component_id".to_string(), json!(component_id));
+                }
+                args.insert("include_text".to_string(), json!(include_text));
+                handle_tool_cli_command(
+                    &lifecycle_manager,
+                    "search-history",
+                    args,
+                    *output_format,
+                    cli.output_mode,
+                )
+                .await?;
+            }
         },
-        None => {
-            eprintln!("No command provided. Use --help for usage information.");
-            std::process::exit(1);
-        }
+        None => bail!("No command provided. Use --help for usage information."),
     }
 
     Ok(())
@@ -842,10 +2084,22 @@ mod cli_tests {
             ToolName::try_from("unload-component").unwrap(),
             ToolName::UnloadComponent
         );
+        assert_eq!(
+            ToolName::try_from("restore-component").unwrap(),
+            ToolName::RestoreComponent
+        );
+        assert_eq!(
+            ToolName::try_from("purge-component").unwrap(),
+            ToolName::PurgeComponent
+        );
         assert_eq!(
             ToolName::try_from("list-components").unwrap(),
             ToolName::ListComponents
         );
+        assert_eq!(
+            ToolName::try_from("search-history").unwrap(),
+            ToolName::SearchHistory
+        );
         assert_eq!(
             ToolName::try_from("get-policy").unwrap(),
             ToolName::GetPolicy
@@ -891,7 +2145,10 @@ mod cli_tests {
     fn test_tool_name_as_str() {
         assert_eq!(ToolName::LoadComponent.as_str(), "load-component");
         assert_eq!(ToolName::UnloadComponent.as_str(), "unload-component");
+        assert_eq!(ToolName::RestoreComponent.as_str(), "restore-component");
+        assert_eq!(ToolName::PurgeComponent.as_str(), "purge-component");
         assert_eq!(ToolName::ListComponents.as_str(), "list-components");
+        assert_eq!(ToolName::SearchHistory.as_str(), "search-history");
         assert_eq!(ToolName::GetPolicy.as_str(), "get-policy");
         assert_eq!(
             ToolName::GrantStoragePermission.as_str(),
@@ -929,7 +2186,10 @@ mod cli_tests {
         let test_cases = [
             ToolName::LoadComponent,
             ToolName::UnloadComponent,
+            ToolName::RestoreComponent,
+            ToolName::PurgeComponent,
             ToolName::ListComponents,
+            ToolName::SearchHistory,
             ToolName::GetPolicy,
             ToolName::GrantStoragePermission,
             ToolName::GrantNetworkPermission,
@@ -955,6 +2215,15 @@ mod cli_tests {
         let cli = Cli::try_parse_from(args).unwrap();
         matches!(cli.command, Some(Commands::Component { .. }));
 
+        let args = vec!["wassette", "component", "restore", "test-component"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Component {
+                command: ComponentCommands::Restore { .. }
+            })
+        ));
+
         // Test policy commands
         let args = vec!["wassette", "policy", "get", "test-component"];
         let cli = Cli::try_parse_from(args).unwrap();
@@ -974,10 +2243,364 @@ mod cli_tests {
         let cli = Cli::try_parse_from(args).unwrap();
         matches!(cli.command, Some(Commands::Permission { .. }));
 
+        // Test secret commands
+        let args = vec!["wassette", "secret", "import", "fetch-rs", ".env"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Secret {
+                command:
+                    SecretCommands::Import {
+                        component_id, path, ..
+                    },
+            }) => {
+                assert_eq!(component_id, "fetch-rs");
+                assert_eq!(path, PathBuf::from(".env"));
+            }
+            _ => panic!("Expected secret import command"),
+        }
+
+        let args = vec![
+            "wassette",
+            "secret",
+            "set",
+            "fetch-rs",
+            "API_TOKEN",
+            "--function",
+            "fetch",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Secret {
+                command:
+                    SecretCommands::Set {
+                        key,
+                        value,
+                        functions,
+                        ..
+                    },
+            }) => {
+                assert_eq!(key, "API_TOKEN");
+                assert_eq!(value, None);
+                assert_eq!(functions, ["fetch"]);
+            }
+            _ => panic!("Expected secret set command"),
+        }
+
+        // Test bundle commands
+        let args = vec![
+            "wassette",
+            "export-bundle",
+            "team.tar.gz",
+            "--component",
+            "fetch-rs",
+            "--component",
+            "time-server",
+            "--encrypt-secrets",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::ExportBundle {
+                components,
+                encrypt_secrets,
+                ..
+            }) => {
+                assert_eq!(components, vec!["fetch-rs", "time-server"]);
+                assert!(encrypt_secrets);
+            }
+            _ => panic!("Expected export-bundle command"),
+        }
+
+        let args = vec!["wassette", "import-bundle", "team.tar.gz", "--overwrite"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::ImportBundle {
+                overwrite: true,
+                ..
+            })
+        ));
+
+        let args = vec![
+            "wassette",
+            "search-history",
+            "weather berlin",
+            "--kind",
+            "result",
+            "--limit",
+            "3",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::SearchHistory {
+                query, limit, kind, ..
+            }) => {
+                assert_eq!(query, "weather berlin");
+                assert_eq!(limit, 3);
+                assert_eq!(kind.as_deref(), Some("result"));
+            }
+            _ => panic!("Expected search-history command"),
+        }
+
+        let args = vec!["wassette", "reconcile", "--dry-run"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Reconcile {
+                manifest, dry_run, ..
+            }) => {
+                assert_eq!(manifest, PathBuf::from("wassette.yaml"));
+                assert!(dry_run);
+            }
+            _ => panic!("Expected reconcile command"),
+        }
+
+        let args = vec![
+            "wassette",
+            "sign",
+            "oci://ghcr.io/me/tool:1.0.0",
+            "--keyless",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Sign {
+                key,
+                keyless,
+                rekor_url,
+                ..
+            }) => {
+                assert!(keyless && key.is_none());
+                assert_eq!(rekor_url, wassette::DEFAULT_REKOR_URL);
+            }
+            _ => panic!("Expected sign command"),
+        }
+        assert!(Cli::try_parse_from(["wassette", "sign", "oci://ghcr.io/me/tool:1.0.0"]).is_err());
+        assert!(Cli::try_parse_from([
+            "wassette",
+            "sign",
+            "oci://ghcr.io/me/tool:1.0.0",
+            "--key",
+            "cosign.key",
+            "--keyless"
+        ])
+        .is_err());
+
+        let args = vec![
+            "wassette",
+            "approve-update",
+            "fetch_rs",
+            "--manifest",
+            "tools.yaml",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::ApproveUpdate {
+                component_id,
+                manifest,
+                ..
+            }) => {
+                assert_eq!(component_id, "fetch_rs");
+                assert_eq!(manifest, PathBuf::from("tools.yaml"));
+            }
+            _ => panic!("Expected approve-update command"),
+        }
+
+        let args = vec![
+            "wassette",
+            "serve",
+            "--transport",
+            "sse",
+            "--listen",
+            "0.0.0.0:9001",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert_eq!(serve.selected_transports().unwrap(), vec![Transport::Sse]);
+                assert_eq!(serve.listen, Some("0.0.0.0:9001".parse().unwrap()));
+            }
+            _ => panic!("Expected serve command"),
+        }
+        assert!(
+            Cli::try_parse_from(["wassette", "serve", "--transport", "sse", "--stdio"]).is_err()
+        );
+        let cli = Cli::try_parse_from(["wassette", "serve", "--listen", "0.0.0.0:9001"]).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => assert!(serve.selected_transports().is_err()),
+            _ => panic!("Expected serve command"),
+        }
+        let cli = Cli::try_parse_from([
+            "wassette",
+            "serve",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => assert!(serve.selected_transports().is_err()),
+            _ => panic!("Expected serve command"),
+        }
+        assert!(Cli::try_parse_from(["wassette", "serve", "--tls-cert", "cert.pem"]).is_err());
+        assert!(Cli::try_parse_from([
+            "wassette",
+            "serve",
+            "--streamable-http",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+            "--acme-domain",
+            "mcp.example.com",
+        ])
+        .is_err());
+        let cli = Cli::try_parse_from(["wassette", "serve", "--streamable-http"]).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert_eq!(
+                    serve.selected_transports().unwrap(),
+                    vec![Transport::StreamableHttp]
+                )
+            }
+            _ => panic!("Expected serve command"),
+        }
+        let cli = Cli::try_parse_from([
+            "wassette",
+            "serve",
+            "--transport",
+            "stdio,streamable-http",
+            "--transport",
+            "sse",
+            "--transport",
+            "stdio",
+            "--listen",
+            "127.0.0.1:9002",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert_eq!(
+                    serve.selected_transports().unwrap(),
+                    vec![Transport::Stdio, Transport::StreamableHttp, Transport::Sse]
+                )
+            }
+            _ => panic!("Expected serve command"),
+        }
+        let cli = Cli::try_parse_from(["wassette", "serve", "--stdio", "--sse"]).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert_eq!(
+                    serve.selected_transports().unwrap(),
+                    vec![Transport::Stdio, Transport::Sse]
+                )
+            }
+            _ => panic!("Expected serve command"),
+        }
+
+        // Test demo command
+        let args = vec!["wassette", "demo", "--streamable-http"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Demo(demo)) => assert!(demo.streamable_http && !demo.sse),
+            _ => panic!("Expected demo command"),
+        }
+
+        let args = vec!["wassette", "repl", "--plugin-dir", "/tmp/components"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Repl { plugin_dir }) => {
+                assert_eq!(plugin_dir, Some(PathBuf::from("/tmp/components")))
+            }
+            _ => panic!("Expected repl command"),
+        }
+
+        let cli = Cli::try_parse_from(["wassette", "tui"]).unwrap();
+        match cli.command {
+            Some(Commands::Tui { plugin_dir }) => assert_eq!(plugin_dir, None),
+            _ => panic!("Expected tui command"),
+        }
+        // The console doesn't serve MCP
+        assert!(Cli::try_parse_from(["wassette", "tui", "--listen", "127.0.0.1:9001"]).is_err());
+
+        let args = vec![
+            "wassette",
+            "call",
+            "fetch",
+            "url=https://example.com",
+            "--timeout",
+            "30s",
+            "--output",
+            "json",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.output_mode, diagnostics::OutputMode::Json);
+        match cli.command {
+            Some(Commands::Call {
+                tool,
+                arguments,
+                timeout,
+                ..
+            }) => {
+                assert_eq!(tool, "fetch");
+                assert_eq!(arguments, vec!["url=https://example.com"]);
+                assert_eq!(timeout, Some(std::time::Duration::from_secs(30)));
+            }
+            _ => panic!("Expected call command"),
+        }
+
         // Test serve command still works
         let args = vec!["wassette", "serve", "--sse"];
         let cli = Cli::try_parse_from(args).unwrap();
         matches!(cli.command, Some(Commands::Serve(_)));
+
+        let args = vec![
+            "wassette",
+            "serve",
+            "--sse",
+            "--event-sink",
+            "https://events.example.com/hook",
+            "--event-sink",
+            "nats://localhost:4222/wassette",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => assert_eq!(serve.event_sinks.len(), 2),
+            _ => panic!("Expected serve command"),
+        }
+
+        let args = vec![
+            "wassette",
+            "serve",
+            "--stdio",
+            "--bus",
+            "nats://localhost:4222/wassette.tools",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert_eq!(
+                    serve.bus.as_deref(),
+                    Some("nats://localhost:4222/wassette.tools")
+                )
+            }
+            _ => panic!("Expected serve command"),
+        }
+
+        let args = vec![
+            "wassette",
+            "serve",
+            "--stdio",
+            "--semantic-recall",
+            "--embedder",
+            "tool:embed",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            Some(Commands::Serve(serve)) => {
+                assert!(serve.semantic_recall);
+                assert_eq!(serve.embedder.as_deref(), Some("tool:embed"));
+            }
+            _ => panic!("Expected serve command"),
+        }
     }
 
     #[test]
@@ -0,0 +1,1069 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! HTTP management API for web frontends
+//!
+//! With `--management-api`, the HTTP transports also serve a JSON API under `/api` that exposes
+//! [`ManagementApi`] to frontends that can't embed the runtime, behind the same authentication as
+//! the MCP endpoints:
+//!
+//! - `GET /api/openapi.json`: the OpenAPI document of the API, see [`crate::openapi`]
+//! - `GET /api/events`: the events of the event log, newest first, filtered by the query
+//!   parameters `since`, `until`, `component_id`, `type`, `success` and `limit`
+//! - `GET /api/metrics`: call counts, error rates, latency percentiles and memory limit hits
+//!   of every component and tool that was called
+//! - `GET /api/components`: the loaded components and their tools
+//! - `POST /api/components/load`: loads a component from the file path, OCI reference or URL in
+//!   the `path` of the JSON body, publishing its progress as `component.loading` events, and
+//!   returns its ID and tools
+//! - `POST /api/components/upload`: loads a component from a `multipart/form-data` upload with
+//!   the `.wasm` file as `component`, and optionally `component_id` and a policy YAML as `policy`
+//! - `GET /api/components/{id}/permissions`: the effective policy of a component, including the
+//!   permissions granted at runtime
+//! - `GET /api/components/{id}/policy`: the YAML of the attached policy
+//! - `PUT /api/components/{id}/policy`: replaces the policy with the YAML in the body, and returns
+//!   what changed
+//! - `GET /api/components/{id}/secrets`: the names of the secrets of a component
+//! - `PUT /api/components/{id}/secrets/{key}`: sets a secret to the value in the body
+//! - `DELETE /api/components/{id}/secrets/{key}`: deletes a secret
+//! - `POST /api/components/{id}/tools/{tool}/invoke`: calls a tool with the JSON arguments in the
+//!   body, after validating them against the tool's input schema, and returns the result with
+//!   the duration of the call
+//!
+//! Under OAuth, every request other than `GET` needs a token with the `tools:write` scope.
+//!
+//! Secret values are write-only: no endpoint returns them, and changes are published as
+//! `secret.changed` events with the name of the secret only.
+//!
+//! With `--management-base-path`, every path is prefixed, e.g. `/wassette/api/components`, for a
+//! reverse proxy that serves the API next to other applications without stripping its prefix.
+//!
+//! With `--management-listen`, the API is served on an address of its own instead, a TCP address
+//! or a unix socket, so it can be reached by a local frontend without sharing the port of the
+//! MCP endpoints, or be kept off the network entirely.
+//!
+//! Errors come as `{"error": "..."}` with status 404 for unknown components and tools, 400 for
+//! rejected input and 500 otherwise. Arguments that don't match the input schema of a tool also
+//! list each violation under `errors`.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{anyhow, Context};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{DefaultBodyLimit, Multipart, OriginalUri, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use mcp_server::InvalidArguments;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+use wassette::{ComponentNotFound, EventQuery, ManagementApi};
+
+use crate::openapi::OPENAPI_PATH;
+use crate::tls::ServerTlsConfig;
+
+/// Path prefix of the management API
+pub const API_PATH: &str = "/api";
+
+/// Largest upload accepted by `POST /api/components/upload`
+pub(crate) const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Checks and normalizes the path prefix of `--management-base-path`: it starts with a slash
+/// and doesn't end with one, and `/` is no prefix at all
+pub fn parse_base_path(base_path: &str) -> anyhow::Result<Option<String>> {
+    if !base_path.starts_with('/') {
+        anyhow::bail!("The management API base path must start with /: {base_path}");
+    }
+    if base_path
+        .split('/')
+        .any(|segment| matches!(segment, "." | "..") || segment.contains(['{', '}', '*']))
+    {
+        anyhow::bail!("Invalid management API base path: {base_path}");
+    }
+    let base_path = base_path.trim_end_matches('/');
+    Ok((!base_path.is_empty()).then(|| base_path.to_string()))
+}
+
+/// Serves `router`, the management API, under `base_path` instead of the root
+pub fn with_base_path(router: Router, base_path: Option<&str>) -> Router {
+    match base_path {
+        Some(base_path) => Router::new().nest(base_path, router),
+        None => router,
+    }
+}
+
+/// Prefix of the unix socket addresses of `--management-listen`
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Where the management API listens when it doesn't share the address of the HTTP transports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManagementListen {
+    /// A TCP address, e.g. `127.0.0.1:9002`
+    Tcp(SocketAddr),
+    /// A unix socket, written as `unix:<path>`
+    Unix(PathBuf),
+}
+
+impl FromStr for ManagementListen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.strip_prefix(UNIX_SOCKET_PREFIX) {
+            Some("") => Err(anyhow!("Unix socket address is missing a path: {s}")),
+            Some(path) => Ok(ManagementListen::Unix(PathBuf::from(path))),
+            None => s.parse().map(ManagementListen::Tcp).with_context(|| {
+                format!("Invalid management API address {s}, expected host:port or unix:<path>")
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ManagementListen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManagementListen::Tcp(addr) => write!(f, "{addr}"),
+            ManagementListen::Unix(path) => write!(f, "{UNIX_SOCKET_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+/// Serves `router` on `listen` until `shutdown` is cancelled. A TCP address is served over HTTPS
+/// when `tls` is enabled, like the HTTP transports. A unix socket is only accessible to the user
+/// running the server, and replaces a socket left behind at its path.
+pub async fn spawn_server(
+    listen: &ManagementListen,
+    tls: &ServerTlsConfig,
+    router: Router,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let server = match listen {
+        ManagementListen::Tcp(addr) => {
+            return crate::spawn_http_server(*addr, tls, router, shutdown).await;
+        }
+        #[cfg(unix)]
+        ManagementListen::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale socket {}", path.display()))?,
+                Ok(_) => anyhow::bail!("{} exists and is not a socket", path.display()),
+                Err(_) => {}
+            }
+            let listener = bind_private_socket(path)
+                .with_context(|| format!("Failed to listen on {}", path.display()))?;
+            tokio::spawn(async move {
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(async move { shutdown.cancelled().await })
+                    .await
+            })
+        }
+        #[cfg(not(unix))]
+        ManagementListen::Unix(_) => {
+            anyhow::bail!("Unix sockets are not supported on this platform")
+        }
+    };
+    tokio::spawn(async move {
+        if let Ok(Err(e)) = server.await {
+            tracing::error!(error = %e, "Management API server shut down with error");
+        }
+    });
+    Ok(())
+}
+
+/// Binds a unix socket at `path` that only the current user can connect to. The socket is bound
+/// inside a directory only the user can enter and moved to `path` once its permissions are
+/// restricted, so other users can't connect in between.
+#[cfg(unix)]
+fn bind_private_socket(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Socket path {} has no file name", path.display()))?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    let private_dir = parent.join(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("Failed to create {}", private_dir.display()))?;
+    let bound = (|| {
+        let private_path = private_dir.join(file_name);
+        let listener = tokio::net::UnixListener::bind(&private_path)?;
+        std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&private_path, path)?;
+        anyhow::Ok(listener)
+    })();
+    let _ = std::fs::remove_dir_all(&private_dir);
+    bound
+}
+
+/// An error of a management API request, with the status it is returned with
+struct ApiError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl ApiError {
+    /// An error caused by the input of the request
+    fn bad_request(error: anyhow::Error) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            ..error.into()
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self.error.is::<ComponentNotFound>() {
+            true => StatusCode::NOT_FOUND,
+            false => self.status,
+        };
+        if status.is_server_error() {
+            tracing::warn!(error = %self.error, "Management API request failed");
+        }
+        let mut body = json!({ "error": format!("{:#}", self.error) });
+        if let Some(invalid) = self.error.downcast_ref::<InvalidArguments>() {
+            body["errors"] = invalid.data()["errors"].clone();
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+/// The routes of the management API, served by `api`
+pub fn router<A>(api: A) -> Router
+where
+    A: ManagementApi + Clone + 'static,
+{
+    Router::new()
+        .route(&format!("{API_PATH}{OPENAPI_PATH}"), get(get_openapi))
+        .route(&format!("{API_PATH}/events"), get(get_events::<A>))
+        .route(&format!("{API_PATH}/metrics"), get(get_metrics::<A>))
+        .route(&format!("{API_PATH}/components"), get(list_components::<A>))
+        .route(
+            &format!("{API_PATH}/components/load"),
+            post(load_component::<A>),
+        )
+        .route(
+            &format!("{API_PATH}/components/upload"),
+            post(upload_component::<A>).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            &format!("{API_PATH}/components/{{id}}/permissions"),
+            get(get_permissions::<A>),
+        )
+        .route(
+            &format!("{API_PATH}/components/{{id}}/policy"),
+            get(get_policy::<A>).put(put_policy::<A>),
+        )
+        .route(
+            &format!("{API_PATH}/components/{{id}}/secrets"),
+            get(list_secrets::<A>),
+        )
+        .route(
+            &format!("{API_PATH}/components/{{id}}/secrets/{{key}}"),
+            put(set_secret::<A>).delete(delete_secret::<A>),
+        )
+        .route(
+            &format!("{API_PATH}/components/{{id}}/tools/{{tool}}/invoke"),
+            post(invoke_tool::<A>),
+        )
+        .layer(axum::middleware::from_fn(crate::oauth::require_write_scope))
+        .with_state(api)
+}
+
+/// Adds the share of failed calls to the serialized metrics of each component or tool
+fn with_error_rates<T: serde::Serialize>(metrics: &[T]) -> Result<Vec<Value>, ApiError> {
+    metrics
+        .iter()
+        .map(|metrics| {
+            let mut value = serde_json::to_value(metrics).map_err(anyhow::Error::from)?;
+            let calls = value["calls"].as_u64().unwrap_or_default();
+            let failures = value["failures"].as_u64().unwrap_or_default();
+            value["error_rate"] = json!(match calls {
+                0 => 0.0,
+                _ => failures as f64 / calls as f64,
+            });
+            Ok(value)
+        })
+        .collect()
+}
+
+async fn get_openapi(OriginalUri(uri): OriginalUri) -> Json<Value> {
+    // Under a base path, the document lists it as the server URL
+    let suffix = format!("{API_PATH}{OPENAPI_PATH}");
+    Json(crate::openapi::document(
+        uri.path().strip_suffix(&suffix).unwrap_or_default(),
+    ))
+}
+
+async fn get_events<A: ManagementApi>(
+    State(api): State<A>,
+    query: Result<Query<EventQuery>, QueryRejection>,
+) -> ApiResult {
+    let Query(query) = query.map_err(|e| ApiError::bad_request(anyhow!(e.body_text())))?;
+    Ok(Json(json!({ "events": api.events(&query).await? })))
+}
+
+async fn get_metrics<A: ManagementApi>(State(api): State<A>) -> ApiResult {
+    let metrics = api.metrics().await?;
+    Ok(Json(json!({
+        "loaded_components": metrics.loaded_components,
+        "trashed_components": metrics.trashed_components,
+        "components": with_error_rates(&metrics.components)?,
+        "tools": with_error_rates(&metrics.tools)?,
+    })))
+}
+
+async fn list_components<A: ManagementApi>(State(api): State<A>) -> ApiResult {
+    Ok(Json(json!({ "components": api.components().await? })))
+}
+
+/// The tools of a loaded component
+async fn component_tools<A: ManagementApi>(
+    api: &A,
+    component_id: &str,
+) -> Result<Vec<String>, ApiError> {
+    Ok(api
+        .components()
+        .await?
+        .into_iter()
+        .find(|component| component.id == component_id)
+        .map(|component| component.tools)
+        .unwrap_or_default())
+}
+
+async fn load_component<A: ManagementApi>(State(api): State<A>, body: String) -> ApiResult {
+    let body: Value = serde_json::from_str(&body)
+        .map_err(|e| ApiError::bad_request(anyhow!("Invalid JSON body: {e}")))?;
+    let Some(path) = body["path"].as_str() else {
+        return Err(ApiError::bad_request(anyhow!(
+            "The body needs the file path, OCI reference or URL of the component as path"
+        )));
+    };
+    let (component_id, replaced) = api
+        .load_component(path)
+        .await
+        .map_err(ApiError::bad_request)?;
+    tracing::info!(
+        component_id,
+        path,
+        "Component loaded through the management API"
+    );
+    Ok(Json(json!({
+        "component_id": component_id,
+        "replaced": replaced,
+        "tools": component_tools(&api, &component_id).await?,
+    })))
+}
+
+async fn upload_component<A: ManagementApi>(
+    State(api): State<A>,
+    mut multipart: Multipart,
+) -> ApiResult {
+    let bad_request = |e: axum::extract::multipart::MultipartError| {
+        ApiError::bad_request(anyhow!("Invalid upload: {}", e.body_text()))
+    };
+    let (mut component, mut component_id, mut policy) = (None, None, None);
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        match field.name() {
+            Some("component") => {
+                let file_name = field.file_name().map(str::to_string);
+                component = Some((file_name, field.bytes().await.map_err(bad_request)?));
+            }
+            Some("component_id") => component_id = Some(field.text().await.map_err(bad_request)?),
+            Some("policy") => policy = Some(field.text().await.map_err(bad_request)?),
+            _ => {}
+        }
+    }
+    let Some((file_name, wasm)) = component else {
+        return Err(ApiError::bad_request(anyhow!(
+            "The upload needs the .wasm file of the component as the component field"
+        )));
+    };
+    // Without an explicit ID, the component is named after its file like one loaded from disk
+    let component_id = component_id
+        .or_else(|| {
+            let file_name = std::path::Path::new(file_name.as_deref()?);
+            Some(file_name.file_stem()?.to_str()?.to_string())
+        })
+        .ok_or_else(|| ApiError::bad_request(anyhow!("The upload needs a component_id")))?;
+
+    let (component_id, replaced) = api
+        .upload_component(&component_id, &wasm, policy.as_deref())
+        .await
+        .map_err(ApiError::bad_request)?;
+    tracing::info!(
+        component_id,
+        bytes = wasm.len(),
+        "Component uploaded through the management API"
+    );
+    Ok(Json(json!({
+        "component_id": component_id,
+        "replaced": replaced,
+        "tools": component_tools(&api, &component_id).await?,
+    })))
+}
+
+async fn get_permissions<A: ManagementApi>(
+    State(api): State<A>,
+    Path(component_id): Path<String>,
+) -> ApiResult {
+    let policy = api.permissions(&component_id).await?;
+    Ok(Json(json!({
+        "component_id": component_id,
+        "policy": serde_json::to_value(policy).map_err(anyhow::Error::from)?,
+    })))
+}
+
+async fn get_policy<A: ManagementApi>(
+    State(api): State<A>,
+    Path(component_id): Path<String>,
+) -> ApiResult {
+    let yaml = api.policy_yaml(&component_id).await?;
+    let source_uri = api.policy(&component_id).await.map(|info| info.source_uri);
+    Ok(Json(json!({
+        "component_id": component_id,
+        "source_uri": source_uri,
+        "yaml": yaml,
+    })))
+}
+
+async fn put_policy<A: ManagementApi>(
+    State(api): State<A>,
+    Path(component_id): Path<String>,
+    yaml: String,
+) -> ApiResult {
+    let changes = api
+        .set_policy_yaml(&component_id, &yaml)
+        .await
+        .map_err(ApiError::bad_request)?;
+    tracing::info!(
+        component_id,
+        changes = changes.len(),
+        "Policy replaced through the management API"
+    );
+    Ok(Json(json!({
+        "component_id": component_id,
+        "changes": changes,
+    })))
+}
+
+async fn list_secrets<A: ManagementApi>(
+    State(api): State<A>,
+    Path(component_id): Path<String>,
+) -> ApiResult {
+    let keys = api.secret_keys(&component_id).await?;
+    Ok(Json(json!({
+        "component_id": component_id,
+        "keys": keys,
+    })))
+}
+
+async fn set_secret<A: ManagementApi>(
+    State(api): State<A>,
+    Path((component_id, key)): Path<(String, String)>,
+    value: String,
+) -> ApiResult {
+    api.set_secret(&component_id, &key, &value)
+        .await
+        .map_err(ApiError::bad_request)?;
+    tracing::info!(component_id, key, "Secret set through the management API");
+    Ok(Json(json!({
+        "component_id": component_id,
+        "key": key,
+    })))
+}
+
+async fn delete_secret<A: ManagementApi>(
+    State(api): State<A>,
+    Path((component_id, key)): Path<(String, String)>,
+) -> ApiResult {
+    let deleted = api.delete_secret(&component_id, &key).await?;
+    if deleted {
+        tracing::info!(
+            component_id,
+            key,
+            "Secret deleted through the management API"
+        );
+    }
+    Ok(Json(json!({
+        "component_id": component_id,
+        "key": key,
+        "deleted": deleted,
+    })))
+}
+
+async fn invoke_tool<A: ManagementApi>(
+    State(api): State<A>,
+    Path((component_id, tool)): Path<(String, String)>,
+    body: String,
+) -> ApiResult {
+    let schema = api
+        .component_schema(&component_id)
+        .await
+        .ok_or_else(|| anyhow::Error::from(ComponentNotFound::Component(component_id.clone())))?;
+    let input_schema = schema["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|schema| schema["name"] == tool.as_str())
+        .map(|schema| schema["inputSchema"].clone())
+        .ok_or_else(|| anyhow::Error::from(ComponentNotFound::Tool(tool.clone())))?;
+
+    // An empty body calls the tool without arguments
+    let arguments = match body.trim() {
+        "" => json!({}),
+        body => serde_json::from_str(body)
+            .map_err(|e| ApiError::bad_request(anyhow!("Invalid JSON arguments: {e}")))?,
+    };
+    let Value::Object(arguments) = arguments else {
+        return Err(ApiError::bad_request(anyhow!(
+            "The arguments must be a JSON object"
+        )));
+    };
+    mcp_server::security::validate_against_schema(&tool, Some(&arguments), &input_schema)
+        .map_err(|e| ApiError::bad_request(e.into()))?;
+
+    let started = Instant::now();
+    let result = api
+        .call_component_tool(&component_id, &tool, &Value::Object(arguments))
+        .await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    tracing::info!(
+        component_id,
+        tool,
+        duration_ms,
+        success = result.is_ok(),
+        "Tool invoked through the management API"
+    );
+    // A failing tool is a result of the call like any other, reported with its duration
+    let (result, is_error) = match result {
+        Ok(output) => (
+            serde_json::from_str(&output).unwrap_or(Value::String(output)),
+            false,
+        ),
+        Err(e) => (Value::String(format!("{e:#}")), true),
+    };
+    Ok(Json(json!({
+        "component_id": component_id,
+        "tool": tool,
+        "result": result,
+        "is_error": is_error,
+        "duration_ms": duration_ms,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+    use tower::ServiceExt as _;
+    use wassette::{LifecycleManager, VerificationPolicy};
+
+    use super::*;
+
+    const POLICY: &str = "version: \"1.0\"\npermissions:\n  network:\n    allow:\n      - host: \"api.example.com\"\n";
+
+    const COMPONENT: &str = include_str!("../crates/wassette/testdata/run.wat");
+
+    const BOUNDARY: &str = "wassette-test-boundary";
+
+    async fn send(router: &Router, method: &str, path: &str, body: &str) -> (StatusCode, Value) {
+        let content_type = match body.starts_with(&format!("--{BOUNDARY}")) {
+            true => format!("multipart/form-data; boundary={BOUNDARY}"),
+            false => "text/plain".to_string(),
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("content-type", content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_policy_endpoints() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let router = router(LifecycleManager::new(&tempdir).await?);
+
+        let (status, body) = send(&router, "GET", "/api/components", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["components"][0]["id"], "fetch");
+
+        let (status, body) = send(&router, "GET", "/api/components/fetch/policy", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["yaml"], Value::Null);
+
+        let (status, body) = send(&router, "PUT", "/api/components/fetch/policy", POLICY).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["changes"].as_array().unwrap().contains(
+            &json!({"path": "permissions.network.allow", "added": {"host": "api.example.com"}})
+        ));
+
+        let (_, body) = send(&router, "GET", "/api/components/fetch/policy", "").await;
+        assert_eq!(body["yaml"], POLICY);
+        let (_, body) = send(&router, "GET", "/api/components/fetch/permissions", "").await;
+        assert_eq!(
+            body["policy"]["permissions"]["network"]["allow"][0]["host"],
+            "api.example.com"
+        );
+
+        let (status, body) = send(
+            &router,
+            "PUT",
+            "/api/components/fetch/policy",
+            "permissions: [",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("Invalid policy"));
+        let (status, _) = send(&router, "GET", "/api/components/missing/policy", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    fn form_data(fields: &[(&str, Option<&str>, &str)]) -> String {
+        let mut body = String::new();
+        for (name, file_name, contents) in fields {
+            let file_name = file_name
+                .map(|file_name| format!("; filename=\"{file_name}\""))
+                .unwrap_or_default();
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"{file_name}\r\n\r\n{contents}\r\n"
+            ));
+        }
+        body.push_str(&format!("--{BOUNDARY}--\r\n"));
+        body
+    }
+
+    #[tokio::test]
+    async fn test_upload_component() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+        let mut events = lifecycle_manager.subscribe_events();
+        let router = router(lifecycle_manager.clone());
+
+        let upload = form_data(&[
+            ("component", Some("answer.wasm"), COMPONENT),
+            ("policy", None, POLICY),
+        ]);
+        let (status, body) = send(&router, "POST", "/api/components/upload", &upload).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body,
+            json!({"component_id": "answer", "replaced": false, "tools": ["run"]})
+        );
+        assert!(tempdir.path().join("answer.wasm").exists());
+        let (_, body) = send(&router, "GET", "/api/components/answer/policy", "").await;
+        assert_eq!(body["yaml"], POLICY);
+        // The policy is in place before the component can be called
+        let mut names = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            names.push(event.name());
+        }
+        assert_eq!(names, ["policy.attached", "component.loaded"]);
+
+        // An invalid policy is rejected before the component is loaded
+        let upload = form_data(&[
+            ("component", Some("answer.wasm"), COMPONENT),
+            ("component_id", None, "other"),
+            ("policy", None, "permissions: ["),
+        ]);
+        let (status, _) = send(&router, "POST", "/api/components/upload", &upload).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!tempdir.path().join("other.wasm").exists());
+
+        let upload = form_data(&[("component", Some("bad.wasm"), "not a component")]);
+        let (status, _) = send(&router, "POST", "/api/components/upload", &upload).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let upload = form_data(&[("policy", None, POLICY)]);
+        let (status, _) = send(&router, "POST", "/api/components/upload", &upload).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Uploads have no source that a verification policy could check
+        let rules = serde_yaml::from_str("registries:\n  - match: \"*\"\n")?;
+        let router = super::router(
+            lifecycle_manager.with_verification_policy(VerificationPolicy::new(rules).await?),
+        );
+        let upload = form_data(&[("component", Some("verified.wasm"), COMPONENT)]);
+        let (status, body) = send(&router, "POST", "/api/components/upload", &upload).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .contains("verification policy"),
+            "{body}"
+        );
+        assert!(!tempdir.path().join("verified.wasm").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_component() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let source = tempdir.path().join("source");
+        std::fs::create_dir(&source)?;
+        std::fs::write(source.join("answer.wasm"), COMPONENT)?;
+        let lifecycle_manager = LifecycleManager::new(tempdir.path().join("plugins")).await?;
+        let mut events = lifecycle_manager.subscribe_events();
+        let router = router(lifecycle_manager);
+
+        let path = format!("file://{}", source.join("answer.wasm").display());
+        let (status, body) = send(
+            &router,
+            "POST",
+            "/api/components/load",
+            &json!({ "path": path }).to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body,
+            json!({"component_id": "answer", "replaced": false, "tools": ["run"]})
+        );
+        let mut names = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            names.push(event.name());
+        }
+        assert_eq!(
+            names,
+            ["component.loading", "component.loading", "component.loaded"]
+        );
+
+        let (status, body) = send(
+            &router,
+            "POST",
+            "/api/components/load",
+            r#"{"path": "file:///missing.wasm"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"].as_str().unwrap().contains("missing.wasm"),
+            "{body}"
+        );
+        assert_eq!(events.recv().await?.name(), "component.loading");
+        assert_eq!(events.recv().await?.name(), "component.load.failed");
+        let (status, _) = send(&router, "POST", "/api/components/load", "{}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+        let router = router(lifecycle_manager.clone());
+
+        let (status, body) = send(&router, "GET", "/api/metrics", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["loaded_components"], 1);
+        assert_eq!(body["components"], json!([]));
+
+        lifecycle_manager.call_tool("run", &json!({})).await?;
+        let (_, body) = send(&router, "GET", "/api/metrics", "").await;
+        let component = &body["components"][0];
+        assert_eq!(component["component_id"], "fetch");
+        assert_eq!(component["calls"], 1);
+        assert_eq!(component["error_rate"], 0.0);
+        assert_eq!(component["memory_limit_hits"], 0);
+        assert_eq!(body["tools"][0]["function_name"], "run");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_events() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir)
+            .await?
+            .with_event_log(wassette::DEFAULT_EVENT_RETENTION)
+            .await?;
+        let router = router(lifecycle_manager.clone());
+
+        lifecycle_manager.call_tool("run", &json!({})).await?;
+        lifecycle_manager.record_proxied_call("github", "search", 12, Some("boom".to_string()));
+        let mut body = Value::Null;
+        for _ in 0..100 {
+            (_, body) = send(&router, "GET", "/api/events", "").await;
+            if body["events"].as_array().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(body["events"][0]["event"]["kind"], "proxied_tool_invoked");
+        assert_eq!(body["events"][1]["event"]["component_id"], "fetch");
+
+        let (status, body) = send(
+            &router,
+            "GET",
+            "/api/events?component_id=fetch&type=tool&success=true",
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["events"].as_array().unwrap().len(), 1);
+        let (_, body) = send(&router, "GET", "/api/events?success=false", "").await;
+        assert_eq!(body["events"][0]["event"]["server"], "github");
+
+        let (status, _) = send(&router, "GET", "/api/events?since=yesterday", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secret_endpoints() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join("fetch.wasm"), COMPONENT)?;
+        let lifecycle_manager = LifecycleManager::new(&tempdir).await?;
+        let router = router(lifecycle_manager.clone());
+        let mut events = lifecycle_manager.subscribe_events();
+
+        let secret = "/api/components/fetch/secrets/API_TOKEN";
+        let (status, body) = send(&router, "PUT", secret, "s3cr3t").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.to_string().contains("s3cr3t"));
+        assert!(!format!("{:?}", events.try_recv()?).contains("s3cr3t"));
+
+        let (status, body) = send(&router, "GET", "/api/components/fetch/secrets", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"component_id": "fetch", "keys": ["API_TOKEN"]})
+        );
+
+        let (_, body) = send(&router, "DELETE", secret, "").await;
+        assert_eq!(body["deleted"], true);
+        let (_, body) = send(&router, "DELETE", secret, "").await;
+        assert_eq!(body["deleted"], false);
+        let (_, body) = send(&router, "GET", "/api/components/fetch/secrets", "").await;
+        assert_eq!(body["keys"], json!([]));
+
+        let (status, _) = send(&router, "PUT", "/api/components/missing/secrets/KEY", "x").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_base_path() -> anyhow::Result<()> {
+        assert_eq!(parse_base_path("/")?, None);
+        assert_eq!(parse_base_path("/wassette/")?.as_deref(), Some("/wassette"));
+        assert_eq!(
+            parse_base_path("/tools/wassette")?.as_deref(),
+            Some("/tools/wassette")
+        );
+        assert!(parse_base_path("wassette").is_err());
+        assert!(parse_base_path("/wassette/../admin").is_err());
+        assert!(parse_base_path("/{tenant}").is_err());
+
+        let tempdir = tempfile::tempdir()?;
+        let router = with_base_path(
+            router(LifecycleManager::new(&tempdir).await?),
+            Some("/wassette"),
+        );
+        let (status, body) = send(&router, "GET", "/wassette/api/components", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["components"], json!([]));
+        let (_, body) = send(&router, "GET", "/wassette/api/openapi.json", "").await;
+        assert_eq!(body["servers"][0]["url"], "/wassette");
+        let request = Request::builder()
+            .uri("/api/components")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_listen() {
+        assert_eq!(
+            "127.0.0.1:9002".parse::<ManagementListen>().unwrap(),
+            ManagementListen::Tcp("127.0.0.1:9002".parse().unwrap())
+        );
+        let unix = "unix:/run/wassette/api.sock"
+            .parse::<ManagementListen>()
+            .unwrap();
+        assert_eq!(
+            unix,
+            ManagementListen::Unix(PathBuf::from("/run/wassette/api.sock"))
+        );
+        assert_eq!(unix.to_string(), "unix:/run/wassette/api.sock");
+        assert!("unix:".parse::<ManagementListen>().is_err());
+        assert!("localhost".parse::<ManagementListen>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix_socket() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let tempdir = tempfile::tempdir()?;
+        let socket = tempdir.path().join("api.sock");
+        let listen = ManagementListen::Unix(socket.clone());
+        let lifecycle_manager = LifecycleManager::new(tempdir.path().join("plugins")).await?;
+        // A socket left behind by an earlier run is replaced
+        let _stale = std::os::unix::net::UnixListener::bind(&socket)?;
+        let shutdown = CancellationToken::new();
+        spawn_server(
+            &listen,
+            &ServerTlsConfig::default(),
+            router(lifecycle_manager),
+            shutdown.clone(),
+        )
+        .await?;
+        let mode = std::fs::metadata(&socket)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Nothing is left of the directory the socket was bound in
+        assert_eq!(std::fs::read_dir(tempdir.path())?.count(), 2);
+
+        let mut stream = tokio::net::UnixStream::connect(&socket).await?;
+        stream
+            .write_all(
+                b"GET /api/components HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with(r#"{"components":[]}"#), "{response}");
+        shutdown.cancel();
+
+        // Files other than sockets aren't replaced
+        let file = tempdir.path().join("api.txt");
+        std::fs::write(&file, "keep")?;
+        let err = spawn_server(
+            &ManagementListen::Unix(file.clone()),
+            &ServerTlsConfig::default(),
+            Router::new(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not a socket"), "{err}");
+        assert_eq!(std::fs::read_to_string(&file)?, "keep");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_tcp_https() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let tls = ServerTlsConfig {
+            cert: Some(tempdir.path().join("cert.pem")),
+            key: Some(tempdir.path().join("key.pem")),
+            ..Default::default()
+        };
+        std::fs::write(tls.cert.as_ref().unwrap(), certified.cert.pem())?;
+        std::fs::write(
+            tls.key.as_ref().unwrap(),
+            certified.signing_key.serialize_pem(),
+        )?;
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let lifecycle_manager = LifecycleManager::new(tempdir.path().join("plugins")).await?;
+        let shutdown = CancellationToken::new();
+        spawn_server(
+            &ManagementListen::Tcp(addr),
+            &tls,
+            router(lifecycle_manager),
+            shutdown.clone(),
+        )
+        .await?;
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        let response = client
+            .get(format!("https://localhost:{}/api/components", addr.port()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await?, r#"{"components":[]}"#);
+        shutdown.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invoke_tool() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(
+            tempdir.path().join("double.wasm"),
+            r#"(component
+              (core module $m
+                (func (export "double") (param i32) (result i32)
+                  local.get 0
+                  i32.const 2
+                  i32.mul))
+              (core instance $i (instantiate $m))
+              (func $double (param "n" u32) (result u32) (canon lift (core func $i "double")))
+              (export "double" (func $double)))"#,
+        )?;
+        let router = router(LifecycleManager::new(&tempdir).await?);
+        let invoke = "/api/components/double/tools/double/invoke";
+
+        let (status, body) = send(&router, "POST", invoke, r#"{"n": 21}"#).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["result"], 42);
+        assert_eq!(body["is_error"], false);
+        assert!(body["duration_ms"].is_u64());
+
+        let (status, body) = send(&router, "POST", invoke, r#"{"n": "many"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][0]["path"], "/n");
+        let (status, _) = send(&router, "POST", invoke, "[21]").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/api/components/double/tools/missing/invoke",
+            "{}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(
+            &router,
+            "POST",
+            "/api/components/missing/tools/double/invoke",
+            "{}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
@@ -0,0 +1,911 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! OAuth 2.0 bearer token authentication for the HTTP transports
+//!
+//! With `--oauth-issuer`, the server acts as an OAuth resource server: every request to the MCP
+//! endpoints needs an `Authorization: Bearer` JWT signed by one of the issuer's keys, which are
+//! fetched from its JWKS and cached. A `tools/call` of a tool that a scope rule covers also needs
+//! that scope in the token; the built-in tools that change which components run, what they may
+//! do or which secrets they get need `tools:write` unless a rule says otherwise. Requests without
+//! a valid token get a `401` that points to the protected resource metadata at
+//! `/.well-known/oauth-protected-resource`, so clients can find the authorization server.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, HOST, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use http_body_util::LengthLimitError;
+use mcp_server::{batch_entries, BATCH_TOOL};
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use rmcp::model::CallToolRequestParam;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+
+/// Path of the OAuth 2.0 protected resource metadata (RFC 9728)
+pub const RESOURCE_METADATA_PATH: &str = "/.well-known/oauth-protected-resource";
+
+/// The scope the built-in tools in [`WRITE_TOOLS`] need by default
+pub const WRITE_SCOPE: &str = "tools:write";
+
+/// Built-in tools that load or remove components or change their permissions, configuration or
+/// secrets
+const WRITE_TOOLS: [&str; 16] = [
+    "load-component",
+    "unload-component",
+    "restore-component",
+    "purge-component",
+    "grant-storage-permission",
+    "grant-network-permission",
+    "grant-environment-variable-permission",
+    "revoke-storage-permission",
+    "revoke-network-permission",
+    "revoke-environment-variable-permission",
+    "reset-permission",
+    "grant-root-access",
+    "revoke-root-access",
+    "set-config",
+    "set-secret",
+    "delete-secret",
+];
+
+/// How far clocks of the issuer and the server may be apart
+const CLOCK_LEEWAY: u64 = 60;
+
+/// How long fetched keys are used before they are fetched again
+const KEYS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// How often keys are fetched again at most for tokens signed with an unknown key
+const KEYS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Largest request body [`authenticate`] reads to find the tools it calls, which is the largest
+/// upload of the management API
+const MAX_BODY_SIZE: usize = crate::management_api::MAX_UPLOAD_SIZE;
+
+/// A scope that calls of a tool need, parsed from `<tool>=<scope>`. `*` stands for every tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeRule {
+    tool: String,
+    scope: String,
+}
+
+impl FromStr for ScopeRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tool, scope) = s
+            .split_once('=')
+            .with_context(|| format!("Scope rule must be <tool>=<scope>: {s}"))?;
+        if tool.is_empty() || scope.is_empty() || scope.contains(char::is_whitespace) {
+            bail!("Scope rule must be <tool>=<scope>: {s}");
+        }
+        Ok(Self {
+            tool: tool.to_string(),
+            scope: scope.to_string(),
+        })
+    }
+}
+
+/// Where tokens come from and what they must allow
+#[derive(Debug, Clone, Default)]
+pub struct OAuthConfig {
+    /// The `iss` every token must have
+    pub issuer: String,
+    /// The `aud` every token must include, if any
+    pub audience: Option<String>,
+    /// Where the issuer publishes its keys. Discovered from the issuer's metadata if unset.
+    pub jwks_url: Option<String>,
+    /// Scopes that calls of tools need
+    pub scopes: Vec<ScopeRule>,
+}
+
+impl OAuthConfig {
+    /// The scope calls of `tool` need: that of the first rule for the tool, else that of a `*`
+    /// rule, else `tools:write` for the built-in tools that change components
+    pub fn required_scope(&self, tool: &str) -> Option<&str> {
+        self.scopes
+            .iter()
+            .find(|rule| rule.tool == tool)
+            .or_else(|| self.scopes.iter().find(|rule| rule.tool == "*"))
+            .map(|rule| rule.scope.as_str())
+            .or_else(|| WRITE_TOOLS.contains(&tool).then_some(WRITE_SCOPE))
+    }
+}
+
+/// A public key of the issuer from its JWKS
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Default)]
+struct KeyCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+/// What a valid token says about its bearer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenClaims {
+    /// The `sub` of the token
+    pub subject: Option<String>,
+    /// The scopes the token grants
+    pub scopes: BTreeSet<String>,
+}
+
+/// Checks bearer tokens against an issuer's keys
+#[derive(Debug, Clone)]
+pub struct ResourceServer {
+    config: Arc<OAuthConfig>,
+    http_client: reqwest::Client,
+    keys: Arc<RwLock<KeyCache>>,
+}
+
+impl ResourceServer {
+    pub fn new(config: OAuthConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            http_client: reqwest::Client::new(),
+            keys: Arc::default(),
+        }
+    }
+
+    /// Verifies the signature, issuer, audience and lifetime of `token`
+    pub async fn verify(&self, token: &str) -> Result<TokenClaims> {
+        let Some((signed, signature)) = token.rsplit_once('.') else {
+            bail!("Token is not a JWT");
+        };
+        let Some((header, payload)) = signed.split_once('.') else {
+            bail!("Token is not a JWT");
+        };
+        let header: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(header)
+                .context("Invalid token header")?,
+        )
+        .context("Invalid token header")?;
+        let alg = header["alg"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Token has no algorithm"))?;
+        let kid = header["kid"].as_str();
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .context("Invalid token signature")?;
+
+        let key = self.key(kid, alg).await?;
+        verify_signature(alg, &key, signed.as_bytes(), &signature)?;
+
+        let claims: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(payload)
+                .context("Invalid token claims")?,
+        )
+        .context("Invalid token claims")?;
+        validate_claims(&claims, &self.config, unix_now())
+    }
+
+    /// The key that signed a token, fetching the keys again if it isn't known
+    async fn key(&self, kid: Option<&str>, alg: &str) -> Result<Jwk> {
+        let find = |keys: &[Jwk]| {
+            keys.iter()
+                .filter(|key| key.usage.as_deref().is_none_or(|usage| usage == "sig"))
+                .filter(|key| key.alg.as_deref().is_none_or(|key_alg| key_alg == alg))
+                .find(|key| kid.is_none() || key.kid.as_deref() == kid)
+                .cloned()
+        };
+        {
+            let cache = self.keys.read().await;
+            let fresh = cache
+                .fetched_at
+                .is_some_and(|fetched_at| fetched_at.elapsed() < KEYS_MAX_AGE);
+            if let Some(key) = find(&cache.keys).filter(|_| fresh) {
+                return Ok(key);
+            }
+        }
+        let mut cache = self.keys.write().await;
+        // Unknown keys must not make every request fetch the keys again
+        let refreshable = cache
+            .fetched_at
+            .is_none_or(|fetched_at| fetched_at.elapsed() >= KEYS_MIN_REFRESH);
+        if refreshable {
+            cache.keys = self.fetch_keys().await?;
+            cache.fetched_at = Some(Instant::now());
+        }
+        find(&cache.keys).ok_or_else(|| anyhow!("Token is signed with an unknown key"))
+    }
+
+    async fn fetch_keys(&self) -> Result<Vec<Jwk>> {
+        let jwks_url = match &self.config.jwks_url {
+            Some(jwks_url) => jwks_url.clone(),
+            None => self.discover_jwks_url().await?,
+        };
+        let keys: JwkSet = self
+            .http_client
+            .get(&jwks_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch the keys of the issuer from {jwks_url}"))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid JWKS at {jwks_url}"))?;
+        tracing::debug!(
+            jwks_url,
+            keys = keys.keys.len(),
+            "Fetched OAuth issuer keys"
+        );
+        Ok(keys.keys)
+    }
+
+    /// The `jwks_uri` of the issuer's OpenID or OAuth authorization server metadata
+    async fn discover_jwks_url(&self) -> Result<String> {
+        let issuer = self.config.issuer.trim_end_matches('/');
+        for path in [
+            "/.well-known/openid-configuration",
+            "/.well-known/oauth-authorization-server",
+        ] {
+            let url = format!("{issuer}{path}");
+            let Ok(response) = self
+                .http_client
+                .get(&url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            else {
+                continue;
+            };
+            if let Some(jwks_url) = response
+                .json::<Value>()
+                .await
+                .ok()
+                .and_then(|metadata| metadata["jwks_uri"].as_str().map(String::from))
+            {
+                return Ok(jwks_url);
+            }
+        }
+        bail!("Failed to discover the JWKS of {issuer}; set --oauth-jwks-url")
+    }
+
+    /// The protected resource metadata of the server at `base_url`
+    pub fn resource_metadata(&self, base_url: &str) -> Value {
+        let mut scopes = self
+            .config
+            .scopes
+            .iter()
+            .map(|rule| rule.scope.clone())
+            .collect::<BTreeSet<_>>();
+        scopes.insert(WRITE_SCOPE.to_string());
+        json!({
+            "resource": base_url,
+            "authorization_servers": [self.config.issuer],
+            "scopes_supported": scopes,
+            "bearer_methods_supported": ["header"],
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn verify_signature(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<()> {
+    let decode = |value: &Option<String>, name: &str| {
+        value
+            .as_deref()
+            .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+            .ok_or_else(|| anyhow!("Key has no valid '{name}'"))
+    };
+    let verified = match (alg, key.kty.as_str()) {
+        ("RS256" | "RS384" | "RS512" | "PS256", "RSA") => {
+            let parameters = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                "RS512" => &signature::RSA_PKCS1_2048_8192_SHA512,
+                _ => &signature::RSA_PSS_2048_8192_SHA256,
+            };
+            RsaPublicKeyComponents {
+                n: decode(&key.n, "n")?,
+                e: decode(&key.e, "e")?,
+            }
+            .verify(parameters, message, signature)
+        }
+        ("ES256" | "ES384", "EC") => {
+            let (parameters, curve): (&signature::EcdsaVerificationAlgorithm, _) = match alg {
+                "ES256" => (&signature::ECDSA_P256_SHA256_FIXED, "P-256"),
+                _ => (&signature::ECDSA_P384_SHA384_FIXED, "P-384"),
+            };
+            if key.crv.as_deref() != Some(curve) {
+                bail!("Key is not on curve {curve}");
+            }
+            let mut point = vec![0x04];
+            point.extend(decode(&key.x, "x")?);
+            point.extend(decode(&key.y, "y")?);
+            UnparsedPublicKey::new(parameters, point).verify(message, signature)
+        }
+        _ => bail!("Unsupported token algorithm {alg}"),
+    };
+    verified.map_err(|_| anyhow!("Invalid token signature"))
+}
+
+fn validate_claims(claims: &Value, config: &OAuthConfig, now: u64) -> Result<TokenClaims> {
+    let issuer = claims["iss"].as_str().unwrap_or_default();
+    if issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+        bail!("Token is from another issuer");
+    }
+    if let Some(audience) = &config.audience {
+        let matches = match &claims["aud"] {
+            Value::String(aud) => aud == audience,
+            Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !matches {
+            bail!("Token is for another audience");
+        }
+    }
+    let expires = claims["exp"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("Token has no expiry"))?;
+    if expires + CLOCK_LEEWAY <= now {
+        bail!("Token has expired");
+    }
+    if claims["nbf"]
+        .as_u64()
+        .is_some_and(|not_before| not_before > now + CLOCK_LEEWAY)
+    {
+        bail!("Token is not valid yet");
+    }
+
+    let scopes = match (&claims["scope"], &claims["scp"]) {
+        (Value::String(scope), _) | (_, Value::String(scope)) => {
+            scope.split_whitespace().map(String::from).collect()
+        }
+        (_, Value::Array(scopes)) => scopes
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        _ => BTreeSet::new(),
+    };
+    Ok(TokenClaims {
+        subject: claims["sub"].as_str().map(String::from),
+        scopes,
+    })
+}
+
+/// The tools of the JSON-RPC `tools/call` requests in a message or batch, including the tools
+/// that the calls of `call-tools-batch` run
+fn called_tools(body: &[u8]) -> Vec<String> {
+    let messages = match serde_json::from_slice(body) {
+        Ok(Value::Array(messages)) => messages,
+        Ok(message) => vec![message],
+        Err(_) => return Vec::new(),
+    };
+    let mut tools = Vec::new();
+    for message in messages
+        .into_iter()
+        .filter(|message| message["method"] == "tools/call")
+    {
+        let Ok(params) = serde_json::from_value::<CallToolRequestParam>(message["params"].clone())
+        else {
+            continue;
+        };
+        if params.name == BATCH_TOOL {
+            // A batch that can't be read fails before any of its calls run
+            if let Ok(entries) = batch_entries(&params) {
+                tools.extend(entries.into_iter().map(|entry| entry.name.to_string()));
+            }
+        }
+        tools.push(params.name.to_string());
+    }
+    tools
+}
+
+/// The URL clients reach the server at
+fn base_url(headers: &HeaderMap) -> String {
+    let host = headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .unwrap_or("http");
+    format!("{scheme}://{host}")
+}
+
+/// A `401` or `403` with the `WWW-Authenticate` challenge of RFC 6750
+fn challenge(
+    headers: &HeaderMap,
+    status: StatusCode,
+    error: Option<&str>,
+    description: &str,
+    scope: Option<&str>,
+) -> Response {
+    let mut value = format!(
+        "Bearer resource_metadata=\"{}{RESOURCE_METADATA_PATH}\"",
+        base_url(headers)
+    );
+    if let Some(error) = error {
+        value.push_str(&format!(", error=\"{error}\""));
+    }
+    if let Some(scope) = scope {
+        value.push_str(&format!(", scope=\"{scope}\""));
+    }
+    let mut response = (
+        status,
+        Json(json!({ "error": error, "error_description": description })),
+    )
+        .into_response();
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().insert(WWW_AUTHENTICATE, value);
+    }
+    response
+}
+
+/// Middleware that lets requests through only with a valid bearer token with the scopes of the
+/// tools they call
+pub async fn authenticate(
+    State(server): State<ResourceServer>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let Some(token) = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return challenge(
+            &parts.headers,
+            StatusCode::UNAUTHORIZED,
+            None,
+            "The request needs a bearer token",
+            None,
+        );
+    };
+    let claims = match server.verify(token.trim()).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            tracing::info!(error = %e, "Rejected bearer token");
+            return challenge(
+                &parts.headers,
+                StatusCode::UNAUTHORIZED,
+                Some("invalid_token"),
+                &e.to_string(),
+                None,
+            );
+        }
+    };
+
+    let body = match axum::body::to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(e) => {
+            let e = e.into_inner();
+            if e.is::<LengthLimitError>() {
+                tracing::info!(subject = ?claims.subject, "Request body is too large");
+                return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+            }
+            tracing::warn!(error = %e, "Failed to read request body");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    for tool in called_tools(&body) {
+        if let Some(scope) = server.config.required_scope(&tool) {
+            if !claims.scopes.contains(scope) {
+                tracing::info!(subject = ?claims.subject, tool, scope, "Token lacks scope");
+                return challenge(
+                    &parts.headers,
+                    StatusCode::FORBIDDEN,
+                    Some("insufficient_scope"),
+                    &format!("The call of {tool} needs the scope {scope}"),
+                    Some(scope),
+                );
+            }
+        }
+    }
+    tracing::debug!(subject = ?claims.subject, "Authenticated request");
+    let mut request = Request::from_parts(parts, Body::from(body));
+    request.extensions_mut().insert(claims);
+    next.run(request).await
+}
+
+/// Middleware of the management API that lets requests other than `GET` and `HEAD` through
+/// only if their token has `tools:write`, since they change components, policies or secrets or
+/// call tools. Requests that [`authenticate`] didn't check, because OAuth is off, pass.
+pub async fn require_write_scope(request: Request, next: Next) -> Response {
+    let read_only = matches!(*request.method(), Method::GET | Method::HEAD);
+    if let Some(claims) = request.extensions().get::<TokenClaims>() {
+        if !read_only && !claims.scopes.contains(WRITE_SCOPE) {
+            tracing::info!(
+                subject = ?claims.subject,
+                method = %request.method(),
+                path = request.uri().path(),
+                "Token lacks scope for the management API"
+            );
+            return challenge(
+                request.headers(),
+                StatusCode::FORBIDDEN,
+                Some("insufficient_scope"),
+                &format!(
+                    "{} {} needs the scope {WRITE_SCOPE}",
+                    request.method(),
+                    request.uri().path()
+                ),
+                Some(WRITE_SCOPE),
+            );
+        }
+    }
+    next.run(request).await
+}
+
+/// Serves the protected resource metadata
+pub async fn resource_metadata(
+    State(server): State<ResourceServer>,
+    headers: HeaderMap,
+) -> Json<Value> {
+    Json(server.resource_metadata(&base_url(&headers)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::{get, post};
+    use axum::Router;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const ISSUER: &str = "https://auth.example.com";
+
+    struct Signer {
+        key_pair: EcdsaKeyPair,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            Self { key_pair }
+        }
+
+        fn jwk(&self) -> Jwk {
+            let point = self.key_pair.public_key().as_ref();
+            Jwk {
+                kty: "EC".to_string(),
+                kid: Some("key-1".to_string()),
+                alg: Some("ES256".to_string()),
+                usage: Some("sig".to_string()),
+                n: None,
+                e: None,
+                crv: Some("P-256".to_string()),
+                x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+                y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+            }
+        }
+
+        fn token(&self, claims: Value) -> String {
+            let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256","kid":"key-1"}"#);
+            let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+            let message = format!("{header}.{claims}");
+            let signature = self
+                .key_pair
+                .sign(&SystemRandom::new(), message.as_bytes())
+                .unwrap();
+            format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        }
+    }
+
+    async fn server(signer: &Signer, scopes: &[&str]) -> ResourceServer {
+        let server = ResourceServer::new(OAuthConfig {
+            issuer: ISSUER.to_string(),
+            audience: Some("wassette".to_string()),
+            jwks_url: None,
+            scopes: scopes.iter().map(|rule| rule.parse().unwrap()).collect(),
+        });
+        *server.keys.write().await = KeyCache {
+            keys: vec![signer.jwk()],
+            fetched_at: Some(Instant::now()),
+        };
+        server
+    }
+
+    fn claims(scope: &str) -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": ["wassette"],
+            "sub": "bot",
+            "exp": unix_now() + 300,
+            "scope": scope,
+        })
+    }
+
+    #[test]
+    fn test_required_scope() {
+        let config = OAuthConfig {
+            scopes: vec![
+                "fetch=tools:fetch".parse().unwrap(),
+                "*=tools:call".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(config.required_scope("fetch"), Some("tools:fetch"));
+        assert_eq!(config.required_scope("eval"), Some("tools:call"));
+        assert_eq!(
+            OAuthConfig::default().required_scope("load-component"),
+            Some(WRITE_SCOPE)
+        );
+        for tool in ["set-secret", "delete-secret"] {
+            assert_eq!(
+                OAuthConfig::default().required_scope(tool),
+                Some(WRITE_SCOPE)
+            );
+        }
+        assert_eq!(
+            OAuthConfig::default().required_scope("list-components"),
+            None
+        );
+        assert_eq!(
+            OAuthConfig::default().required_scope("list-secret-keys"),
+            None
+        );
+        assert!("fetch".parse::<ScopeRule>().is_err());
+        assert!("=tools:call".parse::<ScopeRule>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let signer = Signer::new();
+        let server = server(&signer, &[]).await;
+
+        let claims = server
+            .verify(&signer.token(claims("tools:read tools:write")))
+            .await
+            .unwrap();
+        assert_eq!(claims.subject.as_deref(), Some("bot"));
+        assert!(claims.scopes.contains(WRITE_SCOPE));
+
+        let mut expired = self::claims("");
+        expired["exp"] = json!(unix_now() - 2 * CLOCK_LEEWAY);
+        let mut other_issuer = self::claims("");
+        other_issuer["iss"] = json!("https://evil.example.com");
+        let mut other_audience = self::claims("");
+        other_audience["aud"] = json!("other");
+        for (claims, error) in [
+            (expired, "expired"),
+            (other_issuer, "another issuer"),
+            (other_audience, "another audience"),
+        ] {
+            let err = server.verify(&signer.token(claims)).await.unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+
+        // A token signed by another key
+        let err = server
+            .verify(&Signer::new().token(self::claims("")))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("signature"), "{err}");
+        assert!(server.verify("not-a-token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let signer = Signer::new();
+        let server = server(&signer, &["fetch=tools:fetch"]).await;
+        let router = Router::new()
+            .route("/mcp", post(|body: String| async move { body }))
+            .layer(axum::middleware::from_fn_with_state(
+                server.clone(),
+                authenticate,
+            ))
+            .route(RESOURCE_METADATA_PATH, get(resource_metadata))
+            .with_state(server);
+        let call = |token: Option<String>, tool: &str| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/mcp")
+                .header(HOST, "wassette.example.com");
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let body = json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": tool}});
+            router
+                .clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        };
+
+        let response = call(None, "fetch").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[WWW_AUTHENTICATE],
+            "Bearer resource_metadata=\"http://wassette.example.com/.well-known/oauth-protected-resource\""
+        );
+
+        let reader = signer.token(claims("tools:read"));
+        let response = call(Some(reader.clone()), "load-component").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers()[WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap()
+            .contains("error=\"insufficient_scope\", scope=\"tools:write\""));
+        let response = call(Some(reader.clone()), "fetch").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = call(Some(reader.clone()), "list-components").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The calls of a batch need the scopes of their tools
+        let batch = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {
+            "name": BATCH_TOOL,
+            "arguments": {"calls": [
+                {"tool": "list-components"},
+                {"tool": "load-component", "arguments": {"path": "oci://ghcr.io/evil/tool:latest"}},
+            ]},
+        }});
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/mcp")
+                    .header(HOST, "wassette.example.com")
+                    .header(AUTHORIZATION, format!("Bearer {reader}"))
+                    .body(Body::from(batch.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers()[WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap()
+            .contains("scope=\"tools:write\""));
+
+        let writer = signer.token(claims("tools:write tools:fetch"));
+        for tool in ["load-component", "fetch"] {
+            let response = call(Some(writer.clone()), tool).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/mcp")
+                    .header(HOST, "wassette.example.com")
+                    .header(AUTHORIZATION, format!("Bearer {writer}"))
+                    .body(Body::from(vec![b' '; MAX_BODY_SIZE + 1]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let metadata = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(RESOURCE_METADATA_PATH)
+                    .header(HOST, "wassette.example.com")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(metadata.status(), StatusCode::OK);
+        let metadata: Value = serde_json::from_slice(
+            &axum::body::to_bytes(metadata.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata["authorization_servers"], json!([ISSUER]));
+        assert_eq!(
+            metadata["scopes_supported"],
+            json!(["tools:fetch", "tools:write"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_write_scope() {
+        let signer = Signer::new();
+        let server = server(&signer, &[]).await;
+        let management = Router::new()
+            .route(
+                "/api/components/{id}/policy",
+                get(|| async { "policy" }).put(|| async { "replaced" }),
+            )
+            .layer(axum::middleware::from_fn(require_write_scope));
+        let router = management
+            .clone()
+            .layer(axum::middleware::from_fn_with_state(server, authenticate));
+        let send = |router: &Router, method: &str, token: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri("/api/components/fetch/policy")
+                .header(HOST, "wassette.example.com");
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            router
+                .clone()
+                .oneshot(request.body(Body::from("version: \"1.0\"")).unwrap())
+        };
+
+        let reader = signer.token(claims("tools:read"));
+        let response = send(&router, "GET", Some(&reader)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&router, "PUT", Some(&reader)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers()[WWW_AUTHENTICATE]
+            .to_str()
+            .unwrap()
+            .contains("scope=\"tools:write\""));
+
+        let writer = signer.token(claims("tools:write"));
+        let response = send(&router, "PUT", Some(&writer)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without OAuth there are no claims to check
+        let response = send(&management, "PUT", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_called_tools() {
+        let batch = json!([
+            {"method": "tools/call", "params": {"name": "fetch"}},
+            {"method": "tools/list"},
+            {"method": "tools/call", "params": {"name": "load-component"}},
+        ]);
+        assert_eq!(
+            called_tools(batch.to_string().as_bytes()),
+            vec!["fetch", "load-component"]
+        );
+        assert!(called_tools(b"not json").is_empty());
+
+        let batch = json!({"method": "tools/call", "params": {
+            "name": BATCH_TOOL,
+            "arguments": {"calls": [{"tool": "fetch"}, {"tool": "grant-network-permission"}]},
+        }});
+        assert_eq!(
+            called_tools(batch.to_string().as_bytes()),
+            vec!["fetch", "grant-network-permission", BATCH_TOOL]
+        );
+    }
+}
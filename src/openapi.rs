@@ -0,0 +1,551 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! OpenAPI document of the management API
+//!
+//! The management API describes itself at `GET /api/openapi.json` as an OpenAPI 3.1 document, so
+//! clients can be generated for it. The document is written by hand next to the routes in
+//! [`crate::management_api`]; a test checks that every path it lists is served.
+
+use serde_json::{json, Value};
+
+use crate::management_api::API_PATH;
+
+/// Version of the OpenAPI specification the document follows
+const OPENAPI_VERSION: &str = "3.1.0";
+
+/// Path of the document, under [`API_PATH`]
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "description": description, "schema": schema })
+}
+
+/// The responses of an operation: `ok` and the errors every endpoint can return
+fn responses(ok: Value) -> Value {
+    let error = |description: &str| json_response(description, schema_ref("Error"));
+    json!({
+        "200": ok,
+        "400": error("The request was rejected"),
+        "401": { "description": "Authentication is missing or invalid" },
+        "404": error("The component or tool is not loaded"),
+        "500": error("The request failed"),
+    })
+}
+
+fn operation(operation_id: &str, summary: &str, parameters: Vec<Value>, ok: Value) -> Value {
+    json!({
+        "operationId": operation_id,
+        "summary": summary,
+        "parameters": parameters,
+        "responses": responses(ok),
+    })
+}
+
+fn with_body(mut operation: Value, content_type: &str, schema: Value) -> Value {
+    operation["requestBody"] = json!({
+        "required": true,
+        "content": { content_type: { "schema": schema } },
+    });
+    operation
+}
+
+fn object(properties: Value, required: &[&str]) -> Value {
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+/// `schema` with a description
+fn described(mut schema: Value, description: &str) -> Value {
+    schema["description"] = json!(description);
+    schema
+}
+
+/// The schema of the call statistics of a component or tool, identified by `id_properties`
+fn call_statistics(id_properties: Value, required: &[&str]) -> Value {
+    let mut properties = id_properties;
+    for (name, description) in [
+        ("calls", "Number of calls"),
+        ("failures", "Number of calls that returned an error"),
+        ("total_duration_ms", "Total time spent in calls"),
+        ("max_duration_ms", "Duration of the slowest call"),
+        ("p50_duration_ms", "Median duration of the recent calls"),
+        (
+            "p90_duration_ms",
+            "90th percentile duration of the recent calls",
+        ),
+        (
+            "p99_duration_ms",
+            "99th percentile duration of the recent calls",
+        ),
+        (
+            "memory_limit_hits",
+            "Calls in which the memory limit denied memory",
+        ),
+    ] {
+        properties[name] = described(integer(), description);
+    }
+    properties["error_rate"] = described(json!({ "type": "number" }), "failures / calls");
+    object(properties, required)
+}
+
+fn paths() -> serde_json::Map<String, Value> {
+    let component_id = || path_parameter("id", "ID of the component");
+    let secret_key = || path_parameter("key", "Name of the secret");
+    let date_time = || json!({ "type": "string", "format": "date-time" });
+    let nullable_string = || json!({ "type": ["string", "null"] });
+
+    let list_events = operation(
+        "listEvents",
+        "Events of the event log, newest first",
+        vec![
+            query_parameter("since", "Only events at or after this time", date_time()),
+            query_parameter("until", "Only events before this time", date_time()),
+            query_parameter(
+                "component_id",
+                "Only events about this component or downstream server",
+                string(),
+            ),
+            query_parameter(
+                "type",
+                "Only events of this type or a type under it, e.g. tool.invoked or permission",
+                string(),
+            ),
+            query_parameter(
+                "success",
+                "Only tool calls and network requests that succeeded or failed",
+                json!({ "type": "boolean" }),
+            ),
+            query_parameter(
+                "limit",
+                "Most events to return",
+                json!({ "type": "integer", "minimum": 0, "maximum": 1000, "default": 100 }),
+            ),
+        ],
+        json_response(
+            "The matching events",
+            object(
+                json!({ "events": array(schema_ref("LoggedEvent")) }),
+                &["events"],
+            ),
+        ),
+    );
+    let loaded_component = json_response(
+        "The loaded component",
+        object(
+            json!({
+                "component_id": string(),
+                "replaced": described(
+                    json!({ "type": "boolean" }),
+                    "Whether a loaded component with the same ID was replaced",
+                ),
+                "tools": array(string()),
+            }),
+            &["component_id", "replaced", "tools"],
+        ),
+    );
+    let load_component = with_body(
+        operation(
+            "loadComponent",
+            "Loads a component from a file path, OCI reference or URL",
+            vec![],
+            loaded_component.clone(),
+        ),
+        "application/json",
+        object(
+            json!({
+                "path": described(
+                    string(),
+                    "File path, OCI reference or URL of the component, e.g. oci://ghcr.io/microsoft/fetch-rs:latest",
+                ),
+            }),
+            &["path"],
+        ),
+    );
+    let upload_component = with_body(
+        operation(
+            "uploadComponent",
+            "Loads an uploaded component",
+            vec![],
+            loaded_component,
+        ),
+        "multipart/form-data",
+        object(
+            json!({
+                "component": described(
+                    json!({ "type": "string", "format": "binary" }),
+                    "The .wasm file of the component",
+                ),
+                "component_id": described(
+                    string(),
+                    "ID to load the component under, the file name without .wasm by default",
+                ),
+                "policy": described(string(), "Policy YAML to attach to the component"),
+            }),
+            &["component"],
+        ),
+    );
+    let get_policy = operation(
+        "getPolicy",
+        "The YAML of the policy attached to a component",
+        vec![component_id()],
+        json_response(
+            "The attached policy",
+            object(
+                json!({
+                    "component_id": string(),
+                    "source_uri": nullable_string(),
+                    "yaml": described(nullable_string(), "null if the component has no policy"),
+                }),
+                &["component_id", "source_uri", "yaml"],
+            ),
+        ),
+    );
+    let set_policy = with_body(
+        operation(
+            "setPolicy",
+            "Replaces the policy of a component",
+            vec![component_id()],
+            json_response(
+                "What changed",
+                object(
+                    json!({
+                        "component_id": string(),
+                        "changes": array(schema_ref("PolicyChange")),
+                    }),
+                    &["component_id", "changes"],
+                ),
+            ),
+        ),
+        "text/yaml",
+        string(),
+    );
+    let set_secret = with_body(
+        operation(
+            "setSecret",
+            "Sets a secret of a component to the request body",
+            vec![component_id(), secret_key()],
+            json_response(
+                "The secret was set",
+                object(
+                    json!({ "component_id": string(), "key": string() }),
+                    &["component_id", "key"],
+                ),
+            ),
+        ),
+        "text/plain",
+        string(),
+    );
+    let delete_secret = operation(
+        "deleteSecret",
+        "Deletes a secret of a component",
+        vec![component_id(), secret_key()],
+        json_response(
+            "Whether the secret existed",
+            object(
+                json!({
+                    "component_id": string(),
+                    "key": string(),
+                    "deleted": { "type": "boolean" },
+                }),
+                &["component_id", "key", "deleted"],
+            ),
+        ),
+    );
+    let invoke_tool = with_body(
+        operation(
+            "invokeTool",
+            "Calls a tool of a component with arguments that match its input schema",
+            vec![component_id(), path_parameter("tool", "Name of the tool")],
+            json_response(
+                "The result of the call",
+                object(
+                    json!({
+                        "component_id": string(),
+                        "tool": string(),
+                        "result": described(
+                            json!({}),
+                            "The JSON result, or the error of a failed call",
+                        ),
+                        "is_error": { "type": "boolean" },
+                        "duration_ms": integer(),
+                    }),
+                    &["component_id", "tool", "result", "is_error", "duration_ms"],
+                ),
+            ),
+        ),
+        "application/json",
+        described(json!({ "type": "object" }), "The arguments of the tool"),
+    );
+
+    [
+        (
+            OPENAPI_PATH,
+            json!({ "get": operation(
+                "getOpenApi",
+                "This document",
+                vec![],
+                json_response("The OpenAPI document", json!({ "type": "object" })),
+            )}),
+        ),
+        ("/events", json!({ "get": list_events })),
+        (
+            "/metrics",
+            json!({ "get": operation(
+                "getMetrics",
+                "Call statistics of every component and tool that was called",
+                vec![],
+                json_response("The metrics", schema_ref("Metrics")),
+            )}),
+        ),
+        (
+            "/components",
+            json!({ "get": operation(
+                "listComponents",
+                "The loaded components and their tools",
+                vec![],
+                json_response(
+                    "The loaded components",
+                    object(
+                        json!({ "components": array(schema_ref("ComponentSummary")) }),
+                        &["components"],
+                    ),
+                ),
+            )}),
+        ),
+        ("/components/load", json!({ "post": load_component })),
+        ("/components/upload", json!({ "post": upload_component })),
+        (
+            "/components/{id}/permissions",
+            json!({ "get": operation(
+                "getPermissions",
+                "The effective policy of a component, including the permissions granted at runtime",
+                vec![component_id()],
+                json_response(
+                    "The effective policy",
+                    object(
+                        json!({ "component_id": string(), "policy": { "type": "object" } }),
+                        &["component_id", "policy"],
+                    ),
+                ),
+            )}),
+        ),
+        (
+            "/components/{id}/policy",
+            json!({ "get": get_policy, "put": set_policy }),
+        ),
+        (
+            "/components/{id}/secrets",
+            json!({ "get": operation(
+                "listSecrets",
+                "The names of the secrets of a component",
+                vec![component_id()],
+                json_response(
+                    "The secret names, sorted",
+                    object(
+                        json!({ "component_id": string(), "keys": array(string()) }),
+                        &["component_id", "keys"],
+                    ),
+                ),
+            )}),
+        ),
+        (
+            "/components/{id}/secrets/{key}",
+            json!({ "put": set_secret, "delete": delete_secret }),
+        ),
+        (
+            "/components/{id}/tools/{tool}/invoke",
+            json!({ "post": invoke_tool }),
+        ),
+    ]
+    .into_iter()
+    .map(|(path, operations)| (format!("{API_PATH}{path}"), operations))
+    .collect()
+}
+
+fn schemas() -> Value {
+    let violation = object(
+        json!({ "path": string(), "message": string() }),
+        &["path", "message"],
+    );
+    json!({
+        "Error": object(
+            json!({
+                "error": string(),
+                "errors": described(
+                    array(violation),
+                    "Each argument that doesn't match the input schema of a tool",
+                ),
+            }),
+            &["error"],
+        ),
+        "ComponentSummary": object(
+            json!({
+                "id": string(),
+                "tools": array(string()),
+                "policy_uri": string(),
+                "policy_warnings": described(
+                    array(object(
+                        json!({
+                            "capability": string(),
+                            "import": string(),
+                            "message": string(),
+                        }),
+                        &["capability", "import", "message"],
+                    )),
+                    "Capabilities the component imports but its policy grants nothing for",
+                ),
+            }),
+            &["id", "tools"],
+        ),
+        "PolicyChange": object(
+            json!({
+                "path": described(string(), "Dotted path of the changed field"),
+                "removed": described(json!({}), "The removed value or list item"),
+                "added": described(json!({}), "The added value or list item"),
+            }),
+            &["path"],
+        ),
+        "LoggedEvent": object(
+            json!({
+                "time": { "type": "string", "format": "date-time" },
+                "event": object(json!({ "kind": string() }), &["kind"]),
+            }),
+            &["time", "event"],
+        ),
+        "Metrics": object(
+            json!({
+                "loaded_components": integer(),
+                "trashed_components": integer(),
+                "components": array(call_statistics(
+                    json!({
+                        "component_id": string(),
+                        "http_requests": described(integer(), "Outgoing HTTP requests"),
+                        "http_cache_hits": described(
+                            integer(),
+                            "Outgoing HTTP requests answered from the HTTP cache",
+                        ),
+                    }),
+                    &["component_id", "calls", "failures"],
+                )),
+                "tools": array(call_statistics(
+                    json!({ "component_id": string(), "function_name": string() }),
+                    &["component_id", "function_name", "calls", "failures"],
+                )),
+            }),
+            &["loaded_components", "trashed_components", "components", "tools"],
+        ),
+    })
+}
+
+/// The OpenAPI document of the management API when it is served under `base_path`
+pub fn document(base_path: &str) -> Value {
+    let server_url = match base_path {
+        "" => "/",
+        base_path => base_path,
+    };
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Wassette management API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Manage the components of a Wassette server",
+        },
+        "servers": [{ "url": server_url }],
+        "security": [{}, { "bearer": [] }],
+        "paths": paths(),
+        "components": {
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "An API key or OAuth access token, when the server requires one",
+                },
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use tower::ServiceExt as _;
+    use wassette::LifecycleManager;
+
+    use super::*;
+    use crate::management_api::router;
+
+    #[tokio::test]
+    async fn test_document_matches_routes() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let router = router(LifecycleManager::new(&tempdir).await?);
+        let document = document("");
+        assert_eq!(document["servers"][0]["url"], "/");
+
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                let uri = path
+                    .replace("{id}", "missing")
+                    .replace("{key}", "KEY")
+                    .replace("{tool}", "run");
+                let request = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = router.clone().oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+                // Routes that don't exist return 404 or 405 without a body
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+                assert!(
+                    status != StatusCode::NOT_FOUND || !body.is_empty(),
+                    "{method} {path}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_path() {
+        let document = document("/wassette");
+        assert_eq!(document["servers"][0]["url"], "/wassette");
+        assert!(document["paths"]["/api/components/{id}/policy"]["put"].is_object());
+    }
+}
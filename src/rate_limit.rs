@@ -0,0 +1,128 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Rate limit headers for the HTTP transports
+//!
+//! Responses to `tools/call` requests for a rate limited tool carry `X-RateLimit-Limit`,
+//! `X-RateLimit-Remaining` and `X-RateLimit-Reset` (seconds until the window resets). When the
+//! window is used up, `Retry-After` is set as well. The limit itself is enforced by the tool
+//! pipeline, which rejects the call with a JSON-RPC error carrying the same data.
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use mcp_server::security::{retry_after_secs, RateLimitStatus};
+use mcp_server::RateLimiter;
+use serde_json::Value;
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Returns the tool name of a JSON-RPC `tools/call` request
+fn called_tool(body: &[u8]) -> Option<String> {
+    let message: Value = serde_json::from_slice(body).ok()?;
+    if message.get("method")?.as_str()? != "tools/call" {
+        return None;
+    }
+    Some(message.get("params")?.get("name")?.as_str()?.to_string())
+}
+
+/// The headers for a call of a tool in the state `status`, counting the call itself
+fn insert_headers(headers: &mut HeaderMap, status: RateLimitStatus) {
+    let reset = retry_after_secs(status.reset_after);
+    headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(status.limit));
+    headers.insert(
+        X_RATELIMIT_REMAINING,
+        HeaderValue::from(status.remaining.saturating_sub(1)),
+    );
+    headers.insert(X_RATELIMIT_RESET, HeaderValue::from(reset));
+    if status.remaining == 0 {
+        headers.insert(RETRY_AFTER, HeaderValue::from(reset));
+    }
+}
+
+/// Middleware that adds rate limit headers to responses to `tools/call` requests
+pub async fn rate_limit_headers(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read request body");
+            return next.run(Request::from_parts(parts, Body::empty())).await;
+        }
+    };
+    let status = called_tool(&body).and_then(|tool| limiter.status(&tool));
+
+    let mut response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if let Some(status) = status {
+        insert_headers(response.headers_mut(), status);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::post;
+    use axum::Router;
+    use serde_json::json;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    async fn call(router: &Router, body: Value) -> Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/mcp")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers() {
+        let limiter = RateLimiter::new(["fetch=2/1m".parse().unwrap()]);
+        let router = Router::new()
+            .route("/mcp", post(|body: String| async move { body }))
+            .layer(axum::middleware::from_fn_with_state(
+                limiter.clone(),
+                rate_limit_headers,
+            ));
+        let fetch =
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": "fetch"}});
+
+        let response = call(&router, fetch.clone()).await;
+        assert_eq!(response.headers()[X_RATELIMIT_LIMIT], "2");
+        assert_eq!(response.headers()[X_RATELIMIT_REMAINING], "1");
+        assert_eq!(response.headers()[X_RATELIMIT_RESET], "60");
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+        // The body reaches the service untouched
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), fetch);
+
+        limiter.check("fetch").unwrap();
+        limiter.check("fetch").unwrap();
+        let response = call(&router, fetch).await;
+        assert_eq!(response.headers()[X_RATELIMIT_REMAINING], "0");
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        // Other requests and tools without a limit get no headers
+        let response = call(&router, json!({"method": "tools/list"})).await;
+        assert!(!response.headers().contains_key(X_RATELIMIT_LIMIT));
+        let response = call(
+            &router,
+            json!({"method": "tools/call", "params": {"name": "read-file"}}),
+        )
+        .await;
+        assert!(!response.headers().contains_key(X_RATELIMIT_LIMIT));
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Interactive prompt for exploring and calling tools
+//!
+//! `wassette repl` embeds a [`LifecycleManager`] for the plugin directory and reads commands from
+//! the terminal, with tab-completion of tool and argument names and a history that is kept
+//! between sessions. Calls run through [`mcp_server::execute_tool_call`], the same pipeline that
+//! serves MCP `tools/call`.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context as _, Result};
+use etcetera::BaseStrategy;
+use mcp_server::{execute_tool_call, handle_tools_list, LifecycleManager, ToolCallOptions};
+use rmcp::model::{CallToolRequestParam, CallToolResult, ListToolsResult, RawContent};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Helper, Highlighter, Hinter, Validator};
+use serde_json::{Map, Value};
+
+const PROMPT: &str = "wassette> ";
+
+/// File in the wassette data directory that keeps the history between sessions
+const HISTORY_FILE: &str = "repl_history";
+
+const HELP: &str = "\
+Commands:
+  tools [FILTER]              List the tools, optionally only those containing FILTER
+  describe TOOL               Show the description and arguments of a tool
+  TOOL [KEY=VALUE]...         Call a tool. Values are parsed as JSON if they can be,
+                              so quote strings that look like numbers: id='42'
+  TOOL {JSON}                 Call a tool with a JSON object of arguments
+  help                        Show this help
+  exit                        Leave the REPL (or press Ctrl-D)
+
+Press Tab to complete tool and argument names.";
+
+const BUILTIN_COMMANDS: [&str; 5] = ["describe", "exit", "help", "quit", "tools"];
+
+/// A line entered at the prompt
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Exit,
+    Tools(Option<String>),
+    Describe(String),
+    Call {
+        tool: String,
+        arguments: Map<String, Value>,
+    },
+}
+
+/// Parses a line. Returns `None` for a blank line.
+fn parse_line(line: &str) -> Result<Option<Command>> {
+    let line = line.trim();
+    let (first, rest) = line
+        .split_once(char::is_whitespace)
+        .map(|(first, rest)| (first, rest.trim()))
+        .unwrap_or((line, ""));
+    let command = match first {
+        "" => return Ok(None),
+        "help" | "?" => Command::Help,
+        "exit" | "quit" => Command::Exit,
+        "tools" => Command::Tools((!rest.is_empty()).then(|| rest.to_string())),
+        "describe" => {
+            if rest.is_empty() {
+                bail!("Usage: describe TOOL");
+            }
+            Command::Describe(rest.to_string())
+        }
+        tool => Command::Call {
+            tool: tool.to_string(),
+            arguments: parse_arguments(rest)?,
+        },
+    };
+    Ok(Some(command))
+}
+
+/// Parses the arguments of a call: a JSON object, or `key=value` pairs
+fn parse_arguments(input: &str) -> Result<Map<String, Value>> {
+    if input.starts_with('{') {
+        return serde_json::from_str(input).context("Arguments are not a valid JSON object");
+    }
+    let mut arguments = Map::new();
+    for word in split_words(input)? {
+        let (key, value) = word
+            .split_once('=')
+            .with_context(|| format!("Expected KEY=VALUE, got '{word}'"))?;
+        arguments.insert(key.to_string(), parse_value(value));
+    }
+    Ok(arguments)
+}
+
+/// Splits at whitespace outside of quotes and brackets, so `tags=["a", "b"]` stays one word.
+/// Quotes are kept for [`parse_value`].
+fn split_words(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut escaped = false;
+    for c in input.chars() {
+        if let Some(q) = quote {
+            word.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && q == '"' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                word.push(c);
+            }
+            '[' | '{' => {
+                depth += 1;
+                word.push(c);
+            }
+            ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                word.push(c);
+            }
+            c if c.is_whitespace() && depth == 0 => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("Unterminated quote in '{input}'");
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// `"..."` is a JSON string and `'...'` a literal string. Anything else is parsed as JSON, and
+/// kept as a string if it isn't valid JSON, so `url=https://example.com` needs no quotes.
+pub(crate) fn parse_value(value: &str) -> Value {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return Value::String(value[1..value.len() - 1].to_string());
+    }
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// What the prompt knows about a tool
+#[derive(Debug, Clone)]
+struct ToolInfo {
+    name: String,
+    description: String,
+    /// Argument names with their JSON schema
+    arguments: Vec<(String, Value)>,
+    required: Vec<String>,
+}
+
+impl ToolInfo {
+    fn from_list(tools: ListToolsResult) -> Vec<Self> {
+        tools
+            .tools
+            .into_iter()
+            .map(|tool| {
+                let arguments = tool
+                    .input_schema
+                    .get("properties")
+                    .and_then(Value::as_object)
+                    .map(|properties| {
+                        properties
+                            .iter()
+                            .map(|(name, schema)| (name.clone(), schema.clone()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let required = tool
+                    .input_schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .map(|required| {
+                        required
+                            .iter()
+                            .filter_map(|name| name.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Self {
+                    name: tool.name.to_string(),
+                    description: tool.description.unwrap_or_default().to_string(),
+                    arguments,
+                    required,
+                }
+            })
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!("{}\n", self.name);
+        if !self.description.is_empty() {
+            text.push_str(&format!("  {}\n", self.description));
+        }
+        if self.arguments.is_empty() {
+            text.push_str("  No arguments\n");
+        }
+        for (name, schema) in &self.arguments {
+            let kind = schema.get("type").map(Value::to_string).unwrap_or_default();
+            let required = if self.required.contains(name) {
+                " (required)"
+            } else {
+                ""
+            };
+            text.push_str(&format!("  {name}: {}{required}", kind.trim_matches('"')));
+            if let Some(description) = schema.get("description").and_then(Value::as_str) {
+                text.push_str(&format!(" - {description}"));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Completes tool names, and argument names after a tool name
+#[derive(Default, Helper, Highlighter, Hinter, Validator)]
+struct ReplHelper {
+    tools: Vec<ToolInfo>,
+}
+
+impl ReplHelper {
+    /// Completions for the word that ends at the end of `line`, and where that word starts
+    fn completions(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line
+            .rfind(char::is_whitespace)
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        let word = &line[start..];
+        let mut previous = line[..start].split_whitespace();
+        let candidates: Vec<String> = match previous.next() {
+            None => BUILTIN_COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .chain(self.tools.iter().map(|tool| tool.name.clone()))
+                .collect(),
+            Some("describe") => self.tools.iter().map(|tool| tool.name.clone()).collect(),
+            Some(name) => {
+                let given: Vec<&str> = previous
+                    .filter_map(|word| word.split_once('=').map(|(key, _)| key))
+                    .collect();
+                self.tools
+                    .iter()
+                    .find(|tool| tool.name == name)
+                    .map(|tool| {
+                        tool.arguments
+                            .iter()
+                            .filter(|(argument, _)| !given.contains(&argument.as_str()))
+                            .map(|(argument, _)| format!("{argument}="))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+        };
+        let mut candidates: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = self.completions(&line[..pos]);
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+/// Renders a tool result for the terminal. JSON is pretty-printed.
+fn render_result(result: &CallToolResult) -> String {
+    let mut parts = Vec::new();
+    if let Some(structured) = &result.structured_content {
+        parts.push(serde_json::to_string_pretty(structured).unwrap_or_default());
+    } else {
+        for content in result.content.iter().flatten() {
+            match &content.raw {
+                RawContent::Text(text) => {
+                    let pretty = serde_json::from_str::<Value>(&text.text)
+                        .ok()
+                        .filter(|value| value.is_object() || value.is_array())
+                        .and_then(|value| serde_json::to_string_pretty(&value).ok());
+                    parts.push(pretty.unwrap_or_else(|| text.text.clone()));
+                }
+                other => parts.push(serde_json::to_string_pretty(other).unwrap_or_default()),
+            }
+        }
+    }
+    parts.join("\n")
+}
+
+fn history_path() -> Result<PathBuf> {
+    let strategy = etcetera::choose_base_strategy().context("Unable to get home directory")?;
+    Ok(strategy.data_dir().join("wassette").join(HISTORY_FILE))
+}
+
+async fn list_tools(lifecycle_manager: &LifecycleManager) -> Result<Vec<ToolInfo>> {
+    let tools: ListToolsResult =
+        serde_json::from_value(handle_tools_list(lifecycle_manager, &Default::default()).await?)?;
+    Ok(ToolInfo::from_list(tools))
+}
+
+/// Runs the prompt until the user exits
+pub async fn run(lifecycle_manager: LifecycleManager) -> Result<()> {
+    let options = ToolCallOptions::default();
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper::default()));
+    let history = history_path()?;
+    // There is no history before the first session
+    let _ = editor.load_history(&history);
+
+    println!("Wassette REPL. Type 'help' for commands, Tab to complete, Ctrl-D to exit.");
+    loop {
+        // Tools change when components are loaded or unloaded, also from the prompt
+        let tools = list_tools(&lifecycle_manager).await?;
+        if let Some(helper) = editor.helper_mut() {
+            helper.tools = tools.clone();
+        }
+
+        let line = match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        let command = match parse_line(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{e:#}");
+                continue;
+            }
+        };
+        match command {
+            Command::Help => println!("{HELP}"),
+            Command::Exit => break,
+            Command::Tools(filter) => {
+                for tool in &tools {
+                    if filter
+                        .as_ref()
+                        .is_some_and(|filter| !tool.name.contains(filter))
+                    {
+                        continue;
+                    }
+                    let summary = tool.description.lines().next().unwrap_or_default();
+                    println!("{:<32} {summary}", tool.name);
+                }
+            }
+            Command::Describe(name) => match tools.iter().find(|tool| tool.name == name) {
+                Some(tool) => print!("{}", tool.describe()),
+                None => eprintln!("Unknown tool: {name}"),
+            },
+            Command::Call { tool, arguments } => {
+                if !tools.iter().any(|info| info.name == tool) {
+                    eprintln!("Unknown tool: {tool}. Type 'tools' to list them.");
+                    continue;
+                }
+                let req = CallToolRequestParam {
+                    name: tool.into(),
+                    arguments: Some(arguments),
+                };
+                match execute_tool_call(req, &lifecycle_manager, None, &options).await {
+                    Ok(result) => match serde_json::from_value::<CallToolResult>(result) {
+                        Ok(result) if result.is_error.unwrap_or(false) => {
+                            eprintln!("Error: {}", render_result(&result))
+                        }
+                        Ok(result) => println!("{}", render_result(&result)),
+                        Err(e) => eprintln!("Unexpected tool result: {e}"),
+                    },
+                    Err(e) => eprintln!("Error: {e:#}"),
+                }
+            }
+        }
+    }
+
+    if let Some(parent) = history.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn helper() -> ReplHelper {
+        let tools = serde_json::from_value(json!({
+            "tools": [
+                {
+                    "name": "fetch",
+                    "description": "Fetch a URL",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "url": {"type": "string", "description": "URL to fetch"},
+                            "headers": {"type": "object"}
+                        },
+                        "required": ["url"]
+                    }
+                },
+                {"name": "list-components", "inputSchema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+        ReplHelper {
+            tools: ToolInfo::from_list(tools),
+        }
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("   ").unwrap(), None);
+        assert_eq!(parse_line("quit").unwrap(), Some(Command::Exit));
+        assert_eq!(
+            parse_line("tools fetch").unwrap(),
+            Some(Command::Tools(Some("fetch".to_string())))
+        );
+        assert!(parse_line("describe").is_err());
+
+        let Some(Command::Call { tool, arguments }) = parse_line(
+            r#"fetch url=https://example.com/a?b=c n=3 id='42' title="a \"b\"" tags=["x", "y"]"#,
+        )
+        .unwrap() else {
+            panic!("expected a call");
+        };
+        assert_eq!(tool, "fetch");
+        assert_eq!(
+            Value::Object(arguments),
+            json!({
+                "url": "https://example.com/a?b=c",
+                "n": 3,
+                "id": "42",
+                "title": "a \"b\"",
+                "tags": ["x", "y"]
+            })
+        );
+
+        let Some(Command::Call { arguments, .. }) =
+            parse_line(r#"fetch {"url": "https://example.com"}"#).unwrap()
+        else {
+            panic!("expected a call");
+        };
+        assert_eq!(arguments["url"], "https://example.com");
+
+        assert!(parse_line("fetch url").is_err());
+        assert!(parse_line("fetch url='open").is_err());
+    }
+
+    #[test]
+    fn test_completions() {
+        let helper = helper();
+        assert_eq!(helper.completions("fe"), (0, vec!["fetch".to_string()]));
+        assert_eq!(
+            helper.completions("describe l"),
+            (9, vec!["list-components".to_string()])
+        );
+        assert_eq!(
+            helper.completions("fetch "),
+            (6, vec!["headers=".to_string(), "url=".to_string()])
+        );
+        assert_eq!(
+            helper.completions("fetch url=https://example.com "),
+            (30, vec!["headers=".to_string()])
+        );
+        assert_eq!(helper.completions("missing "), (8, vec![]));
+
+        let description = helper.tools[0].describe();
+        assert!(description.contains("url: string (required) - URL to fetch"));
+        assert!(description.contains("headers: object\n"));
+    }
+
+    #[test]
+    fn test_render_result() {
+        let result =
+            CallToolResult::success(vec![rmcp::model::Content::text(r#"{"status":"ok"}"#)]);
+        assert_eq!(render_result(&result), "{\n  \"status\": \"ok\"\n}");
+
+        let result = CallToolResult::success(vec![rmcp::model::Content::text("plain text")]);
+        assert_eq!(render_result(&result), "plain text");
+
+        let result = CallToolResult::structured(json!({"count": 2}));
+        assert_eq!(render_result(&result), "{\n  \"count\": 2\n}");
+    }
+}
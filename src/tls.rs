@@ -0,0 +1,283 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! TLS termination for the HTTP transports
+//!
+//! With a certificate and key in PEM files, or with domains to get a certificate for through
+//! ACME, the HTTP transports serve HTTPS themselves instead of relying on a reverse proxy. ACME
+//! certificates come from Let's Encrypt unless another directory is given, and are validated
+//! with the TLS-ALPN-01 challenge on the listening address, so the domains must reach it on port
+//! 443. They are cached in a directory, which keeps the server under the issuer's rate limits
+//! across restarts, and renewed before they expire.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use rustls_acme::caches::DirCache;
+use rustls_acme::AcmeConfig;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::TcpListenerStream;
+
+/// Longest a client may take for the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting for the HTTP server to take them
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Protocols offered to clients, in order of preference
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// How the HTTP transports terminate TLS
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerTlsConfig {
+    /// PEM file of the certificate chain to serve
+    pub cert: Option<PathBuf>,
+    /// PEM file of the private key of the certificate
+    pub key: Option<PathBuf>,
+    /// Domains to get a certificate for through ACME
+    pub acme_domains: Vec<String>,
+    /// Contact email addresses of the ACME account
+    pub acme_contact: Vec<String>,
+    /// Directory the ACME account and certificates are cached in
+    pub acme_cache: Option<PathBuf>,
+    /// ACME directory to use instead of Let's Encrypt's production directory
+    pub acme_directory: Option<String>,
+}
+
+impl ServerTlsConfig {
+    /// Whether the HTTP transports serve HTTPS
+    pub fn is_enabled(&self) -> bool {
+        self.cert.is_some() || self.key.is_some() || !self.acme_domains.is_empty()
+    }
+
+    /// Starts terminating TLS on the connections of `tcp_listener`. Fails if the settings are
+    /// inconsistent or the certificate can't be read.
+    pub fn listen(&self, tcp_listener: TcpListener) -> Result<TlsListener> {
+        let local_addr = tcp_listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+        match (&self.cert, &self.key, self.acme_domains.is_empty()) {
+            (Some(cert), Some(key), true) => {
+                let acceptor = TlsAcceptor::from(Arc::new(server_config(
+                    read_certs(cert)?,
+                    PrivateKeyDer::from_pem_file(key)
+                        .with_context(|| format!("Failed to read TLS key {}", key.display()))?,
+                )?));
+                tokio::spawn(accept_with_certificate(tcp_listener, acceptor, sender));
+            }
+            (None, None, false) => {
+                let cache = match &self.acme_cache {
+                    Some(cache) => cache.clone(),
+                    None => default_acme_cache()?,
+                };
+                let mut acme = AcmeConfig::new(&self.acme_domains)
+                    .contact(
+                        self.acme_contact
+                            .iter()
+                            .map(|email| format!("mailto:{email}")),
+                    )
+                    .cache(DirCache::new(cache));
+                acme = match &self.acme_directory {
+                    Some(directory) => acme.directory(directory),
+                    None => acme.directory_lets_encrypt(true),
+                };
+                tokio::spawn(accept_with_acme(tcp_listener, acme, sender));
+            }
+            (_, _, true) => bail!("TLS needs both a certificate and a key"),
+            _ => bail!("TLS takes either a certificate and key or ACME domains, not both"),
+        }
+        Ok(TlsListener {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+/// The default directory of the ACME cache: `$XDG_DATA_HOME/wassette/acme`
+fn default_acme_cache() -> Result<PathBuf> {
+    use etcetera::BaseStrategy;
+
+    let dir_strategy = etcetera::choose_base_strategy().context("Unable to get home directory")?;
+    Ok(dir_strategy.data_dir().join("wassette").join("acme"))
+}
+
+fn read_certs(path: &std::path::Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::aws_lc_rs::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .context("Invalid TLS certificate or key")?;
+    config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+    Ok(config)
+}
+
+/// A connection of the HTTP server after the TLS handshake
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The connections of a TCP listener that completed the TLS handshake. Handshakes run in the
+/// background, so a slow client doesn't hold up the others.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(Box<dyn Connection>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = Box<dyn Connection>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The TCP listener failed for good, which has been logged; accept nothing more
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+async fn accept_with_certificate(
+    tcp_listener: TcpListener,
+    acceptor: TlsAcceptor,
+    sender: mpsc::Sender<(Box<dyn Connection>, SocketAddr)>,
+) {
+    loop {
+        let (stream, peer) = match tcp_listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to accept TCP connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if sender.is_closed() {
+            return;
+        }
+        let acceptor = acceptor.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = sender.send((Box::new(stream), peer)).await;
+                }
+                Ok(Err(e)) => tracing::debug!(%peer, error = %e, "TLS handshake failed"),
+                Err(_) => tracing::debug!(%peer, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+async fn accept_with_acme(
+    tcp_listener: TcpListener,
+    acme: AcmeConfig<std::io::Error>,
+    sender: mpsc::Sender<(Box<dyn Connection>, SocketAddr)>,
+) {
+    let alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+    // The stream also orders and renews the certificate, so it is polled even without clients
+    let mut incoming = acme
+        .state()
+        .tokio_incoming(TcpListenerStream::new(tcp_listener), alpn_protocols);
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let peer = stream.get_ref().get_ref().0.get_ref().peer_addr();
+                let Ok(peer) = peer else { continue };
+                if sender.send((Box::new(stream), peer)).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to accept TCP connection"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use axum::routing::get;
+    use axum::Router;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn pem_file(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn test_inconsistent_settings() {
+        let tcp_listener = || async { TcpListener::bind("127.0.0.1:0").await.unwrap() };
+        let cert_only = ServerTlsConfig {
+            cert: Some("cert.pem".into()),
+            ..Default::default()
+        };
+        assert!(cert_only.listen(tcp_listener().await).is_err());
+        let both = ServerTlsConfig {
+            cert: Some("cert.pem".into()),
+            key: Some("key.pem".into()),
+            acme_domains: vec!["mcp.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(both.listen(tcp_listener().await).is_err());
+        assert!(!ServerTlsConfig::default().is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_serve_https() -> Result<()> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let cert = pem_file(&certified.cert.pem());
+        let key = pem_file(&certified.signing_key.serialize_pem());
+        let config = ServerTlsConfig {
+            cert: Some(cert.path().to_path_buf()),
+            key: Some(key.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = tcp_listener.local_addr()?.port();
+        let listener = config.listen(tcp_listener)?;
+        let router = Router::new().route("/healthz", get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        let response = client
+            .get(format!("https://localhost:{port}/healthz"))
+            .send()
+            .await?;
+        assert_eq!(response.text().await?, "ok");
+        Ok(())
+    }
+}
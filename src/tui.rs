@@ -0,0 +1,715 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Terminal management console
+//!
+//! `wassette tui` embeds a [`LifecycleManager`] for the plugin directory and shows its components,
+//! the latest tool invocations, the progress of loads, the lifecycle and permission changes and
+//! the network requests that policies denied, updated live from the event stream. Components can
+//! be loaded and unloaded and permissions granted or revoked from the keyboard. Everything goes
+//! through [`ManagementApi`], so the console only needs what a third-party frontend would.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use ratatui::crossterm::event::{
+    self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use wassette::{
+    ComponentSummary, Event, LifecycleManager, LoadStage, ManagementApi, ManagementMetrics,
+};
+
+/// How many invocations and denials are kept on screen
+const HISTORY_CAPACITY: usize = 200;
+
+/// How often components and metrics are refreshed when no event arrives
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+const KEY_HELP: &str = "↑/↓ select  l load  u unload  g grant  r revoke  q quit";
+
+const PERMISSION_HELP: &str = "network HOST | storage URI [read,write] | environment KEY";
+
+/// A tool call shown in the invocations pane
+#[derive(Debug, Clone, PartialEq)]
+struct Invocation {
+    component_id: String,
+    function_name: String,
+    duration_ms: u64,
+    error: Option<String>,
+}
+
+/// What the text typed at the prompt is for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PromptKind {
+    Load,
+    Grant,
+    Revoke,
+}
+
+impl PromptKind {
+    fn label(self) -> &'static str {
+        match self {
+            PromptKind::Load => "Load component (path, OCI reference or URL)",
+            PromptKind::Grant => "Grant permission",
+            PromptKind::Revoke => "Revoke permission",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+/// An operation requested from the keyboard
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    None,
+    Quit,
+    Load(String),
+    Unload(String),
+    Grant {
+        component_id: String,
+        permission_type: String,
+        details: Value,
+    },
+    Revoke {
+        component_id: String,
+        permission_type: String,
+        details: Value,
+    },
+}
+
+#[derive(Debug, Default)]
+struct App {
+    components: Vec<ComponentSummary>,
+    metrics: ManagementMetrics,
+    selected: usize,
+    invocations: VecDeque<Invocation>,
+    /// Loads in progress and lifecycle and permission events, newest first
+    activity: VecDeque<String>,
+    denials: VecDeque<String>,
+    prompt: Option<Prompt>,
+    status: String,
+}
+
+/// Parses a permission typed at the prompt into the permission type and details that
+/// [`ManagementApi::grant_permission`] takes
+fn parse_permission(input: &str) -> Result<(String, Value)> {
+    let words = input.split_whitespace().collect::<Vec<_>>();
+    let (permission_type, details) = match words.as_slice() {
+        ["network", host] => ("network", json!({ "host": host })),
+        ["storage", uri] => ("storage", json!({ "uri": uri, "access": ["read"] })),
+        ["storage", uri, access] => (
+            "storage",
+            json!({ "uri": uri, "access": access.split(',').collect::<Vec<_>>() }),
+        ),
+        ["environment", key] => ("environment", json!({ "key": key })),
+        _ => bail!("Expected {PERMISSION_HELP}"),
+    };
+    Ok((permission_type.to_string(), details))
+}
+
+fn push_bounded<T>(items: &mut VecDeque<T>, item: T) {
+    if items.len() == HISTORY_CAPACITY {
+        items.pop_back();
+    }
+    items.push_front(item);
+}
+
+impl App {
+    fn selected_component(&self) -> Option<&ComponentSummary> {
+        self.components.get(self.selected)
+    }
+
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::ToolInvoked {
+                component_id,
+                function_name,
+                duration_ms,
+                error,
+                ..
+            } => push_bounded(
+                &mut self.invocations,
+                Invocation {
+                    component_id,
+                    function_name,
+                    duration_ms,
+                    error,
+                },
+            ),
+            Event::NetworkRequest(request) if request.is_denied() => push_bounded(
+                &mut self.denials,
+                format!(
+                    "{} {} {}{}",
+                    request.component_id, request.method, request.host, request.path
+                ),
+            ),
+            Event::PolicyMismatch {
+                component_id,
+                warnings,
+            } => {
+                for warning in warnings {
+                    push_bounded(
+                        &mut self.activity,
+                        format!("{component_id}: {}", warning.message),
+                    );
+                }
+            }
+            Event::ComponentLoading { source, stage } => {
+                let stage = match stage {
+                    LoadStage::Downloading => "downloading",
+                    LoadStage::Compiling => "compiling",
+                };
+                push_bounded(&mut self.activity, format!("{stage} {source}"));
+            }
+            Event::ComponentLoadFailed { source, error } => push_bounded(
+                &mut self.activity,
+                format!("failed to load {source}: {error}"),
+            ),
+            Event::NetworkRequest(_)
+            | Event::ProxiedToolInvoked { .. }
+            | Event::DelegationIssued(_) => {}
+            event => push_bounded(
+                &mut self.activity,
+                format!("{} {}", event.name(), event.component_id()),
+            ),
+        }
+    }
+
+    /// Shows that `action` started, since the console isn't redrawn until it finishes
+    fn on_start(&mut self, action: &Action) {
+        if let Action::Load(uri) = action {
+            self.status = format!("Loading {uri}...");
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if let Some(prompt) = &mut self.prompt {
+            match key.code {
+                KeyCode::Char(c) => prompt.input.push(c),
+                KeyCode::Backspace => {
+                    prompt.input.pop();
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter => {
+                    let prompt = self.prompt.take().expect("prompt is open");
+                    return self.submit(prompt);
+                }
+                _ => {}
+            }
+            return Action::None;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.components.len().saturating_sub(1))
+            }
+            KeyCode::Char('l') => self.open_prompt(PromptKind::Load),
+            KeyCode::Char('u') => {
+                if let Some(component) = self.selected_component() {
+                    return Action::Unload(component.id.clone());
+                }
+            }
+            KeyCode::Char('g') => self.open_prompt(PromptKind::Grant),
+            KeyCode::Char('r') => self.open_prompt(PromptKind::Revoke),
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind) {
+        if kind != PromptKind::Load && self.selected_component().is_none() {
+            self.status = "No component selected".to_string();
+            return;
+        }
+        self.prompt = Some(Prompt {
+            kind,
+            input: String::new(),
+        });
+    }
+
+    fn submit(&mut self, prompt: Prompt) -> Action {
+        let input = prompt.input.trim();
+        if input.is_empty() {
+            return Action::None;
+        }
+        if prompt.kind == PromptKind::Load {
+            return Action::Load(input.to_string());
+        }
+        let Some(component_id) = self.selected_component().map(|c| c.id.clone()) else {
+            return Action::None;
+        };
+        match parse_permission(input) {
+            Ok((permission_type, details)) if prompt.kind == PromptKind::Grant => Action::Grant {
+                component_id,
+                permission_type,
+                details,
+            },
+            Ok((permission_type, details)) => Action::Revoke {
+                component_id,
+                permission_type,
+                details,
+            },
+            Err(e) => {
+                self.status = e.to_string();
+                Action::None
+            }
+        }
+    }
+
+    async fn perform(&mut self, api: &impl ManagementApi, action: Action) {
+        let result = match &action {
+            Action::None | Action::Quit => return,
+            Action::Load(uri) => match api.load_component(uri).await {
+                Ok((id, replaced)) => {
+                    let tools = api
+                        .components()
+                        .await
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(|component| component.id == id)
+                        .map(|component| component.tools.join(", "))
+                        .unwrap_or_default();
+                    let verb = if replaced { "Reloaded" } else { "Loaded" };
+                    Ok(format!("{verb} {id} with tools: {tools}"))
+                }
+                Err(e) => Err(e),
+            },
+            Action::Unload(id) => api
+                .unload_component(id)
+                .await
+                .map(|()| format!("Unloaded {id}, it can be restored from the trash")),
+            Action::Grant {
+                component_id,
+                permission_type,
+                details,
+            } => api
+                .grant_permission(component_id, permission_type, details)
+                .await
+                .map(|()| format!("Granted {permission_type} permission to {component_id}")),
+            Action::Revoke {
+                component_id,
+                permission_type,
+                details,
+            } => api
+                .revoke_permission(component_id, permission_type, details)
+                .await
+                .map(|()| format!("Revoked {permission_type} permission from {component_id}")),
+        };
+        self.status = match result {
+            Ok(status) => status,
+            Err(e) => format!("Error: {e:#}"),
+        };
+    }
+
+    async fn refresh(&mut self, api: &impl ManagementApi) {
+        match api.components().await {
+            Ok(components) => self.components = components,
+            Err(e) => self.status = format!("Error: {e:#}"),
+        }
+        if let Ok(metrics) = api.metrics().await {
+            self.metrics = metrics;
+        }
+        self.selected = self.selected.min(self.components.len().saturating_sub(1));
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [main, status, footer] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Fill(1)]).areas(main);
+        let [components, details] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Fill(1)]).areas(left);
+        let [invocations, activity, denials] = Layout::vertical([
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+        ])
+        .areas(right);
+
+        self.render_components(frame, components);
+        self.render_details(frame, details);
+
+        let items = self.invocations.iter().map(|invocation| {
+            let (mark, color, error) = match &invocation.error {
+                None => ("✓", Color::Green, String::new()),
+                Some(error) => ("✗", Color::Red, format!("  {error}")),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(mark, Style::default().fg(color)),
+                Span::raw(format!(
+                    " {}.{} {}ms{error}",
+                    invocation.component_id, invocation.function_name, invocation.duration_ms
+                )),
+            ]))
+        });
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Recent invocations ")),
+            invocations,
+        );
+        frame.render_widget(
+            List::new(
+                self.activity
+                    .iter()
+                    .map(|activity| ListItem::new(activity.as_str())),
+            )
+            .block(Block::bordered().title(" Activity ")),
+            activity,
+        );
+        frame.render_widget(
+            List::new(
+                self.denials
+                    .iter()
+                    .map(|denial| ListItem::new(denial.as_str())),
+            )
+            .block(Block::bordered().title(" Denied requests ")),
+            denials,
+        );
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+        let footer_text = match &self.prompt {
+            Some(prompt) => {
+                let hint = match prompt.kind {
+                    PromptKind::Load => String::new(),
+                    _ => format!(" [{PERMISSION_HELP}]"),
+                };
+                format!("{}{hint}: {}", prompt.kind.label(), prompt.input)
+            }
+            None => KEY_HELP.to_string(),
+        };
+        frame.render_widget(
+            Paragraph::new(footer_text).style(Style::default().add_modifier(Modifier::REVERSED)),
+            footer,
+        );
+    }
+
+    fn render_components(&self, frame: &mut Frame, area: Rect) {
+        let items = self.components.iter().map(|component| {
+            let policy = match component.policy_uri {
+                Some(_) => " [policy]",
+                None => "",
+            };
+            let warnings = match component.policy_warnings.len() {
+                0 => String::new(),
+                count => format!(" [{count} policy warnings]"),
+            };
+            ListItem::new(format!(
+                "{} ({} tools){policy}{warnings}",
+                component.id,
+                component.tools.len()
+            ))
+        });
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(
+                " Components ({} loaded, {} in trash) ",
+                self.metrics.loaded_components, self.metrics.trashed_components
+            )))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default()
+            .with_selected((!self.components.is_empty()).then_some(self.selected));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn render_details(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        if let Some(component) = self.selected_component() {
+            if let Some(policy) = &component.policy_uri {
+                lines.push(Line::from(format!("Policy: {policy}")));
+            }
+            for warning in &component.policy_warnings {
+                lines.push(Line::from(format!("Warning: {}", warning.message)));
+            }
+            for tool in &component.tools {
+                let metrics = self
+                    .metrics
+                    .tools
+                    .iter()
+                    .find(|m| m.component_id == component.id && &m.function_name == tool);
+                lines.push(Line::from(match metrics {
+                    Some(m) => format!(
+                        "{tool}: {} calls, {} failed, avg {}ms, max {}ms",
+                        m.calls,
+                        m.failures,
+                        m.total_duration_ms / m.calls.max(1),
+                        m.max_duration_ms
+                    ),
+                    None => format!("{tool}: not called yet"),
+                }));
+            }
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Tools ")),
+            area,
+        );
+    }
+}
+
+/// Runs the console until the user quits
+pub async fn run(lifecycle_manager: LifecycleManager) -> Result<()> {
+    let mut events = lifecycle_manager.subscribe_events();
+
+    // crossterm reads block, so keys are read on their own thread
+    let (key_sender, mut keys) = mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        match event::read() {
+            Ok(TermEvent::Key(key)) if key.kind == KeyEventKind::Press => {
+                if key_sender.send(key).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &lifecycle_manager, &mut events, &mut keys).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    api: &impl ManagementApi,
+    events: &mut tokio::sync::broadcast::Receiver<Event>,
+    keys: &mut mpsc::UnboundedReceiver<KeyEvent>,
+) -> Result<()> {
+    let mut app = App::default();
+    app.refresh(api).await;
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        terminal.draw(|frame| app.render(frame))?;
+        tokio::select! {
+            key = keys.recv() => match key.map(|key| app.on_key(key)) {
+                None | Some(Action::Quit) => return Ok(()),
+                Some(Action::None) => {}
+                Some(action) => {
+                    app.on_start(&action);
+                    terminal.draw(|frame| app.render(frame))?;
+                    app.perform(api, action).await;
+                    app.refresh(api).await;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) => app.on_event(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    app.status = format!("Skipped {skipped} events");
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = refresh.tick() => app.refresh(api).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use wassette::{Capability, NetworkRequest, PolicyWarning, ToolMetrics};
+
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            assert_eq!(app.on_key(key(KeyCode::Char(c))), Action::None);
+        }
+    }
+
+    fn app_with_component() -> App {
+        App {
+            components: vec![ComponentSummary {
+                id: "fetch".to_string(),
+                tools: vec!["fetch".to_string()],
+                policy_uri: Some("file:///policy.yaml".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_permission() {
+        assert_eq!(
+            parse_permission("network api.example.com").unwrap(),
+            ("network".to_string(), json!({"host": "api.example.com"}))
+        );
+        assert_eq!(
+            parse_permission("storage fs:///tmp read,write").unwrap(),
+            (
+                "storage".to_string(),
+                json!({"uri": "fs:///tmp", "access": ["read", "write"]})
+            )
+        );
+        assert_eq!(
+            parse_permission("storage fs:///tmp").unwrap().1["access"],
+            json!(["read"])
+        );
+        assert_eq!(
+            parse_permission("environment API_KEY").unwrap().1,
+            json!({"key": "API_KEY"})
+        );
+        assert!(parse_permission("network").is_err());
+        assert!(parse_permission("ipc anything").is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut app = app_with_component();
+        app.on_key(key(KeyCode::Char('g')));
+        type_text(&mut app, "network api.example.com");
+        assert_eq!(
+            app.on_key(key(KeyCode::Enter)),
+            Action::Grant {
+                component_id: "fetch".to_string(),
+                permission_type: "network".to_string(),
+                details: json!({"host": "api.example.com"}),
+            }
+        );
+        assert!(app.prompt.is_none());
+
+        // Typing 'q' at the prompt doesn't quit, Esc closes it
+        app.on_key(key(KeyCode::Char('l')));
+        type_text(&mut app, "q");
+        app.on_key(key(KeyCode::Esc));
+        assert!(app.prompt.is_none());
+
+        app.on_key(key(KeyCode::Char('l')));
+        type_text(&mut app, "oci://ghcr.io/microsoft/time-server-js:latestx");
+        app.on_key(key(KeyCode::Backspace));
+        assert_eq!(
+            app.on_key(key(KeyCode::Enter)),
+            Action::Load("oci://ghcr.io/microsoft/time-server-js:latest".to_string())
+        );
+
+        app.on_key(key(KeyCode::Char('r')));
+        type_text(&mut app, "bogus");
+        assert_eq!(app.on_key(key(KeyCode::Enter)), Action::None);
+        assert!(app.status.starts_with("Expected"), "{}", app.status);
+
+        assert_eq!(
+            app.on_key(key(KeyCode::Char('u'))),
+            Action::Unload("fetch".to_string())
+        );
+        assert_eq!(app.on_key(key(KeyCode::Char('q'))), Action::Quit);
+
+        // Permissions need a selected component
+        let mut app = App::default();
+        app.on_key(key(KeyCode::Char('g')));
+        assert!(app.prompt.is_none());
+        assert_eq!(app.on_key(key(KeyCode::Char('u'))), Action::None);
+    }
+
+    #[test]
+    fn test_events_and_render() {
+        let mut app = app_with_component();
+        for _ in 0..HISTORY_CAPACITY + 1 {
+            app.on_event(Event::ToolInvoked {
+                component_id: "fetch".to_string(),
+                function_name: "fetch".to_string(),
+                duration_ms: 12,
+                success: true,
+                error: None,
+                callers: Vec::new(),
+            });
+        }
+        assert_eq!(app.invocations.len(), HISTORY_CAPACITY);
+        app.on_event(Event::NetworkRequest(NetworkRequest {
+            component_id: "fetch".to_string(),
+            method: "GET".to_string(),
+            host: "evil.example.com".to_string(),
+            path: "/".to_string(),
+            status: None,
+            bytes: 0,
+            duration_ms: 0,
+            cached: false,
+            error: Some("denied by network policy".to_string()),
+            time: "2025-01-01T00:00:00Z".to_string(),
+        }));
+        app.on_event(Event::ComponentUnloaded {
+            component_id: "other".to_string(),
+        });
+        app.on_event(Event::PolicyMismatch {
+            component_id: "fetch".to_string(),
+            warnings: vec![PolicyWarning {
+                capability: Capability::Http,
+                import: "wasi:http/outgoing-handler@0.2.0".to_string(),
+                message: "imports wasi:http but policy grants no network".to_string(),
+            }],
+        });
+        assert_eq!(app.denials.len(), 1);
+        let source = "oci://ghcr.io/example/weather:1.0".to_string();
+        app.on_start(&Action::Load(source.clone()));
+        assert_eq!(app.status, "Loading oci://ghcr.io/example/weather:1.0...");
+        app.on_event(Event::ComponentLoading {
+            source: source.clone(),
+            stage: LoadStage::Downloading,
+        });
+        app.on_event(Event::ComponentLoadFailed {
+            source,
+            error: "manifest unknown".to_string(),
+        });
+        assert_eq!(
+            app.activity,
+            [
+                "failed to load oci://ghcr.io/example/weather:1.0: manifest unknown",
+                "downloading oci://ghcr.io/example/weather:1.0",
+                "fetch: imports wasi:http but policy grants no network",
+                "component.unloaded other"
+            ]
+        );
+        app.metrics.tools.push(ToolMetrics {
+            component_id: "fetch".to_string(),
+            function_name: "fetch".to_string(),
+            calls: 4,
+            failures: 1,
+            total_duration_ms: 40,
+            max_duration_ms: 25,
+            ..Default::default()
+        });
+
+        let mut terminal = Terminal::new(TestBackend::new(160, 30)).unwrap();
+        terminal.draw(|frame| app.render(frame)).unwrap();
+        let screen = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>();
+        assert!(screen.contains("fetch (1 tools) [policy]"), "{screen}");
+        assert!(screen.contains("fetch.fetch 12ms"), "{screen}");
+        assert!(screen.contains("fetch GET evil.example.com/"), "{screen}");
+        assert!(screen.contains("component.unloaded other"), "{screen}");
+        assert!(
+            screen.contains("fetch: 4 calls, 1 failed, avg 10ms, max 25ms"),
+            "{screen}"
+        );
+        assert!(screen.contains("l load"), "{screen}");
+    }
+}
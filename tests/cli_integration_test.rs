@@ -522,6 +522,54 @@ async fn test_cli_output_format_table() -> Result<()> {
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn test_cli_call_diagnostics() -> Result<()> {
+    let ctx = CliTestContext::new().await?;
+    let component_path = build_fetch_component().await?;
+
+    let (stdout, stderr, exit_code) = ctx
+        .run_command(&[
+            "component",
+            "load",
+            &format!("file://{}", component_path.display()),
+            "--output",
+            "json",
+        ])
+        .await?;
+    assert_eq!(exit_code, 0, "Load command failed with stderr: {}", stderr);
+    let output = ctx.parse_json_output(&stdout)?;
+    assert_eq!(output["status"], "ok");
+    assert_eq!(output["result"]["status"], "component loaded successfully");
+
+    // The component has no network permission, so the request is denied before it is sent
+    let (stdout, _, exit_code) = ctx
+        .run_command(&[
+            "call",
+            "fetch",
+            "url=https://example.com/",
+            "--output",
+            "json",
+        ])
+        .await?;
+    assert_eq!(exit_code, 4);
+    let output = ctx.parse_json_output(&stdout)?;
+    assert_eq!(output["status"], "error");
+    assert_eq!(output["class"], "policy_denied");
+    assert_eq!(
+        output["details"]["denied_requests"][0]["host"],
+        "example.com"
+    );
+
+    let (stdout, _, exit_code) = ctx
+        .run_command(&["call", "no-such-tool", "--output", "json"])
+        .await?;
+    assert_eq!(exit_code, 1);
+    let output = ctx.parse_json_output(&stdout)?;
+    assert_eq!(output["class"], "error");
+
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn test_cli_version_command() -> Result<()> {
     let ctx = CliTestContext::new().await?;
@@ -95,7 +95,7 @@ async fn test_component_lifecycle_with_policies() -> Result<()> {
 
     // Reset all permissions
     manager
-        .reset_permission(&component_id)
+        .reset_permissions(&component_id)
         .await
         .context("Failed to reset permissions")?;
 
@@ -256,7 +256,7 @@ async fn test_permission_operations_error_handling() -> Result<()> {
         .contains("Component not found"));
 
     // Try to reset permissions for non-existent component
-    let result = manager.reset_permission("non-existent-component").await;
+    let result = manager.reset_permissions("non-existent-component").await;
 
     assert!(result.is_err());
     assert!(result
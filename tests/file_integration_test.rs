@@ -265,9 +265,23 @@ async fn test_filesystem_component_integration() -> Result<()> {
     .context("Timeout waiting for grant-permission response")?
     .context("Failed to read grant-permission response")?;
 
-    let grant_response: serde_json::Value = serde_json::from_str(&grant_response_line)
+    let mut grant_response: serde_json::Value = serde_json::from_str(&grant_response_line)
         .context("Failed to parse grant-permission response")?;
 
+    // Skip the notifications about the permission change that come before the response
+    while grant_response["method"].is_string() {
+        grant_response_line.clear();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            stdout.read_line(&mut grant_response_line),
+        )
+        .await
+        .context("Timeout waiting for grant-permission response")?
+        .context("Failed to read grant-permission response")?;
+        grant_response = serde_json::from_str(&grant_response_line)
+            .context("Failed to parse grant-permission response")?;
+    }
+
     assert_eq!(grant_response["jsonrpc"], "2.0");
     assert_eq!(grant_response["id"], 5);
     assert!(grant_response["result"].is_object());
@@ -770,29 +770,83 @@ async fn test_tool_list_notification() -> Result<()> {
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn test_resource_subscription() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    std::fs::write(
+        temp_dir.path().join("answer.wasm"),
+        r#"(component
+          (core module $m (func (export "run") (result i32) i32.const 42))
+          (core instance $i (instantiate $m))
+          (func $run (result u32) (canon lift (core func $i "run")))
+          (export "run" (func $run)))"#,
+    )?;
+    let plugin_dir_arg = format!("--plugin-dir={}", temp_dir.path().display());
+    let binary_path = std::env::current_dir()
+        .context("Failed to get current directory")?
+        .join("target/debug/wassette");
+
+    let mut child = tokio::process::Command::new(&binary_path)
+        .args(["serve", &plugin_dir_arg])
+        .env("RUST_LOG", "off")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start wassette with stdio transport")?;
+    let mut stdin = child.stdin.take().context("Failed to get stdin handle")?;
+    let mut stdout = BufReader::new(child.stdout.take().context("Failed to get stdout handle")?);
+
+    let requests = [
+        r#"{"jsonrpc": "2.0", "method": "initialize", "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}}, "id": 1}"#,
+        r#"{"jsonrpc": "2.0", "method": "notifications/initialized", "params": {}}"#,
+        r#"{"jsonrpc": "2.0", "method": "resources/subscribe", "params": {"uri": "wassette://components/answer/policy"}, "id": 2}"#,
+        r#"{"jsonrpc": "2.0", "method": "tools/call", "params": {"name": "grant-network-permission", "arguments": {"component_id": "answer", "details": {"host": "api.example.com"}}}, "id": 3}"#,
+    ];
+    for request in requests {
+        stdin.write_all(format!("{request}\n").as_bytes()).await?;
+    }
+    stdin.flush().await?;
+
+    // Read until the update arrives; responses and other notifications come in between
+    let mut messages = Vec::new();
+    let updated = tokio::time::timeout(Duration::from_secs(15), async {
+        loop {
+            let mut line = String::new();
+            if stdout.read_line(&mut line).await? == 0 {
+                anyhow::bail!("Server closed stdout");
+            }
+            let message: serde_json::Value = serde_json::from_str(&line)?;
+            if message["method"] == "notifications/resources/updated" {
+                return Ok(message);
+            }
+            messages.push(message);
+        }
+    })
+    .await
+    .context("Timeout waiting for notifications/resources/updated")??;
+    assert_eq!(
+        updated["params"]["uri"],
+        "wassette://components/answer/policy"
+    );
+
+    let initialize = messages.iter().find(|message| message["id"] == 1).unwrap();
+    assert_eq!(
+        initialize["result"]["capabilities"]["resources"]["subscribe"],
+        true
+    );
+    let subscribe = messages.iter().find(|message| message["id"] == 2).unwrap();
+    assert!(subscribe.get("error").is_none(), "{subscribe}");
+
+    child.kill().await.ok();
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn test_http_transport() -> Result<()> {
     // Use a random available port to avoid conflicts
     let port = find_open_port().await?;
-
-    // We need to modify the source to support configurable bind address
-    // For now, let's test with the default port but check if it's available
-    let default_port = 9001u16;
-    let test_port = if TcpListener::bind(format!("127.0.0.1:{default_port}"))
-        .await
-        .is_ok()
-    {
-        default_port
-    } else {
-        port
-    };
-
-    // If we're not using the default port, skip this test for now
-    // since the server code uses a hardcoded bind address
-    if test_port != default_port {
-        println!("Skipping HTTP transport test: default port 9001 is not available");
-        return Ok(());
-    }
+    let listen = format!("127.0.0.1:{port}");
 
     // Create a temporary directory for this test to avoid loading existing components
     let temp_dir = tempfile::tempdir()?;
@@ -805,7 +859,14 @@ async fn test_http_transport() -> Result<()> {
 
     // Start the server with HTTP transport
     let mut child = tokio::process::Command::new(&binary_path)
-        .args(["serve", "--sse", &plugin_dir_arg])
+        .args([
+            "serve",
+            "--transport",
+            "sse",
+            "--listen",
+            &listen,
+            &plugin_dir_arg,
+        ])
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -817,7 +878,7 @@ async fn test_http_transport() -> Result<()> {
 
     // Create HTTP client
     let client = reqwest::Client::new();
-    let base_url = format!("http://127.0.0.1:{test_port}");
+    let base_url = format!("http://{listen}");
 
     // Test that the server is responding
     let response = tokio::time::timeout(Duration::from_secs(10), client.get(&base_url).send())
@@ -829,12 +890,106 @@ async fn test_http_transport() -> Result<()> {
     // The important thing is that it's listening and responding
     assert!(response.status().as_u16() >= 200);
 
+    // The SSE endpoint opens an event stream
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.get(format!("{base_url}/sse")).send(),
+    )
+    .await
+    .context("Timeout waiting for SSE endpoint")?
+    .context("Failed to connect to SSE endpoint")?;
+    assert!(response.status().is_success());
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    assert!(
+        content_type.starts_with("text/event-stream"),
+        "{content_type}"
+    );
+
     // Clean up
     child.kill().await.ok();
 
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn test_simultaneous_transports() -> Result<()> {
+    let port = find_open_port().await?;
+    let listen = format!("127.0.0.1:{port}");
+    let temp_dir = tempfile::tempdir()?;
+    let plugin_dir_arg = format!("--plugin-dir={}", temp_dir.path().display());
+    let binary_path = std::env::current_dir()
+        .context("Failed to get current directory")?
+        .join("target/debug/wassette");
+
+    // One server on stdio and both HTTP transports
+    let mut child = tokio::process::Command::new(&binary_path)
+        .args([
+            "serve",
+            "--transport",
+            "stdio,sse,streamable-http",
+            "--listen",
+            &listen,
+            &plugin_dir_arg,
+        ])
+        .env("RUST_LOG", "off")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start wassette with multiple transports")?;
+    let mut stdin = child.stdin.take().context("Failed to get stdin handle")?;
+    let mut stdout = BufReader::new(child.stdout.take().context("Failed to get stdout handle")?);
+
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+
+    let initialize_request = r#"{"jsonrpc": "2.0", "method": "initialize", "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}}, "id": 1}"#;
+    stdin
+        .write_all(format!("{initialize_request}\n").as_bytes())
+        .await?;
+    stdin.flush().await?;
+    let mut response_line = String::new();
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        stdout.read_line(&mut response_line),
+    )
+    .await
+    .context("Timeout waiting for stdio initialize response")??;
+    let response: serde_json::Value = serde_json::from_str(&response_line)?;
+    assert!(response["result"].is_object(), "{response}");
+
+    let client = reqwest::Client::new();
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client
+            .post(format!("http://{listen}/mcp"))
+            .header(
+                reqwest::header::ACCEPT,
+                "application/json, text/event-stream",
+            )
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(initialize_request)
+            .send(),
+    )
+    .await
+    .context("Timeout waiting for streamable HTTP endpoint")??;
+    assert!(response.status().is_success(), "{}", response.status());
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.get(format!("http://{listen}/sse")).send(),
+    )
+    .await
+    .context("Timeout waiting for SSE endpoint")??;
+    assert!(response.status().is_success(), "{}", response.status());
+
+    child.kill().await.ok();
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn test_default_stdio_transport() -> Result<()> {
     // Create a temporary directory for this test to avoid loading existing components